@@ -0,0 +1,49 @@
+// Runtime ABI
+// Runtime entry points that hand-written Z80 code may call, reached through
+// a jump table at a fixed address so they survive layout changes
+
+/// ABI version (major, minor). New entries are only ever appended to the
+/// table (minor bump); moving, removing or changing the calling convention
+/// of an existing entry is a major bump.
+pub const VERSION: (u8, u8) = (1, 0);
+
+/// Offset of the version bytes (major, minor) from the build's origin
+pub const VERSION_ADDR: u16 = 0x007E;
+
+/// Offset of the jump table from the build's origin; entry n is a `JP` at
+/// origin + TABLE_BASE + 3n
+pub const TABLE_BASE: u16 = 0x0080;
+
+/// One runtime entry point and its calling convention
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub name: &'static str,      // Runtime label (also in the symbol table)
+    pub inputs: &'static str,
+    pub outputs: &'static str,
+    pub clobbers: &'static str,  // Registers not preserved, besides flags
+}
+
+impl Entry {
+    /// Z80 address of this entry's slot in the jump table of a build
+    /// linked at `origin`
+    pub fn address(&self, origin: u16) -> u16 {
+        let index = ENTRIES.iter().position(|e| e.name == self.name).unwrap_or(0);
+        origin + TABLE_BASE + 3 * index as u16
+    }
+}
+
+/// The jump table, in slot order
+pub const ENTRIES: &[Entry] = &[
+    Entry { name: "print_char", inputs: "A = character", outputs: "-", clobbers: "-" },
+    Entry { name: "print_str", inputs: "HL = NUL-terminated string", outputs: "HL = the NUL", clobbers: "A" },
+    Entry { name: "print_hex8", inputs: "A = byte", outputs: "-", clobbers: "A" },
+    Entry { name: "refresh_display", inputs: "-", outputs: "-", clobbers: "A, B, DE, HL" },
+    Entry { name: "memset", inputs: "HL = dest, BC = count, A = value", outputs: "HL = dest + count", clobbers: "A, BC, E" },
+    Entry { name: "translate_i", inputs: "I (0x8010)", outputs: "HL = Z80 address of I", clobbers: "A, DE" },
+    Entry { name: "tick_wait", inputs: "-", outputs: "-", clobbers: "A, DE, HL" },
+];
+
+/// Look up an entry by name
+pub fn entry(name: &str) -> Option<&'static Entry> {
+    ENTRIES.iter().find(|e| e.name == name)
+}
@@ -0,0 +1,295 @@
+// Code/data classification
+// Marks every ROM byte as code, sprite/data or unreferenced so the
+// disassembler and the compiler agree on what is part of the program
+
+use crate::chip8::{self, Instruction, RegionMap};
+
+/// What a ROM byte is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteKind {
+    Unused,  // Neither executed nor pointed at by I
+    Code,    // Part of a reachable instruction
+    Data,    // Read through I (sprites, tables, BCD/register storage)
+}
+
+/// A run of bytes of the same kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: u16,  // CHIP-8 address
+    pub len: u16,
+    pub kind: ByteKind,
+}
+
+impl Region {
+    /// First address past the region
+    pub fn end(&self) -> u16 {
+        self.start + self.len
+    }
+}
+
+/// A store through I that lands on discovered code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeWrite {
+    pub at: u16,      // Address of the FX33/FX55
+    pub target: u16,  // First byte written
+    pub len: u16,     // Bytes written
+}
+
+/// An FX65 loading bytes past the ROM that no store in it writes, so it
+/// gets whatever --ram-init left there
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnwrittenRead {
+    pub at: u16,      // Address of the FX65
+    pub source: u16,  // First byte loaded
+    pub len: u16,     // Bytes loaded
+}
+
+/// An FX65 or DXYN that may read bytes a CHIP-8 machine leaves
+/// uninitialized: outside the font and the ROM, and written by no store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UninitRead {
+    pub at: u16,      // Address of the FX65/DXYN
+    pub source: u16,  // First uninitialized byte it may read
+    pub len: u16,     // Through the last
+}
+
+/// Classified ROM
+#[derive(Debug, Clone)]
+pub struct RomMap {
+    pub instructions: Vec<Instruction>,     // Reachable code, by address
+    pub kinds: Vec<ByteKind>,               // One entry per ROM byte
+    pub code_writes: Vec<CodeWrite>,        // Self-modifying stores
+    pub unwritten_reads: Vec<UnwrittenRead>,  // Loads of RAM nothing stores
+    pub uninit_reads: Vec<UninitRead>,        // Reads of RAM nothing fills
+}
+
+impl RomMap {
+    /// Kind of the byte at a CHIP-8 address
+    pub fn kind(&self, addr: u16) -> ByteKind {
+        kind_at(&self.kinds, addr)
+    }
+
+    /// The ROM as consecutive runs of code, data and unused bytes
+    pub fn regions(&self) -> Vec<Region> {
+        let mut regions: Vec<Region> = Vec::new();
+        for (i, &kind) in self.kinds.iter().enumerate() {
+            match regions.last_mut() {
+                Some(region) if region.kind == kind => region.len += 1,
+                _ => regions.push(Region { start: 0x200 + i as u16, len: 1, kind }),
+            }
+        }
+        regions
+    }
+
+    /// Number of bytes of the given kind
+    pub fn count(&self, kind: ByteKind) -> usize {
+        self.kinds.iter().filter(|&&k| k == kind).count()
+    }
+}
+
+/// Classify a ROM. Code comes from the control-flow walk in `chip8::parse`;
+/// data is seeded by ANNN targets and sized by how I is then used (DXYN
+/// height, FX33, FX55/FX65 count). Code wins where the two overlap.
+pub fn classify(rom: &[u8]) -> RomMap {
+    classify_with_regions(rom, &RegionMap::default())
+}
+
+/// Classify a ROM with known code and data regions. Known data is marked
+/// as data whether or not anything is seen reading it.
+pub fn classify_with_regions(rom: &[u8], regions: &RegionMap) -> RomMap {
+    let instructions = chip8::parse_with_regions(rom, regions);
+    let mut kinds = vec![ByteKind::Unused; rom.len()];
+
+    for inst in &instructions {
+        let start = (inst.addr - 0x200) as usize;
+        let end = (start + inst.size() as usize).min(rom.len());
+        kinds[start..end].fill(ByteKind::Code);
+    }
+
+    // Track I through straight-line code, and the range of values I and
+    // V0-VF may hold there
+    let mut code_writes = Vec::new();
+    let mut written: Vec<(u16, u16)> = Vec::new();  // Start and length of each store
+    let mut store = |kinds: &[ByteKind], at: u16, target: u16, len: u16| {
+        let write = CodeWrite { at, target, len };
+        if (target..target + len).any(|addr| kind_at(kinds, addr) == ByteKind::Code) {
+            code_writes.push(write);
+        }
+        written.push((target, len));
+    };
+    let mut spread = Vec::new();  // Start and length of the bytes each store with I in a range may write
+    let mut loads = Vec::new();
+    let mut reads = Vec::new();  // Each FX65/DXYN with the first and last byte it may read
+    let mut stores_anywhere = false;  // A store with I unknown could be behind any load
+    let mut index: Option<u16> = None;
+    let mut ranges = Ranges::default();
+    let mut prev_end = 0;
+    let mut after_skip = false;
+    for inst in &instructions {
+        if inst.addr != prev_end {
+            index = None;  // Not reached by falling through
+            ranges = Ranges::default();
+        }
+        prev_end = inst.addr + inst.size();
+        // Past a skip the instruction may not have run
+        let before = after_skip.then(|| ranges.clone());
+        after_skip = inst.is_skip();
+        ranges.step(inst);
+        if let Some(before) = before {
+            ranges.join(&before);
+        }
+
+        if let Some(operand) = inst.operand {
+            index = Some(operand);
+            mark_data(&mut kinds, operand, 1);
+            continue;
+        }
+        match inst.nibbles() {
+            (0xA, _, _, _) => {
+                index = Some(inst.nnn());
+                mark_data(&mut kinds, inst.nnn(), 1);
+            }
+            (0xD, _, _, n) => {
+                let len = if n == 0 { 32 } else { n as u16 };
+                if let Some(i) = index {
+                    mark_data(&mut kinds, i, len as usize);
+                }
+                if let Some((low, high)) = ranges.i {
+                    reads.push((inst.addr, low, high.saturating_add(len - 1)));
+                }
+            }
+            (0xF, _, 0x3, 0x3) => {
+                if let Some(i) = index {
+                    store(&kinds, inst.addr, i, 3);
+                    mark_data(&mut kinds, i, 3);
+                } else if let Some((low, high)) = ranges.i {
+                    spread.push((low, (high - low).saturating_add(3)));
+                } else {
+                    stores_anywhere = true;
+                }
+            }
+            (0xF, x, 0x5, 0x5) => {
+                if let Some(i) = index {
+                    store(&kinds, inst.addr, i, x as u16 + 1);
+                    mark_data(&mut kinds, i, x as usize + 1);
+                } else if let Some((low, high)) = ranges.i {
+                    spread.push((low, (high - low).saturating_add(x as u16 + 1)));
+                } else {
+                    stores_anywhere = true;
+                }
+            }
+            (0xF, x, 0x6, 0x5) => {
+                if let Some(i) = index {
+                    loads.push(UnwrittenRead { at: inst.addr, source: i, len: x as u16 + 1 });
+                    mark_data(&mut kinds, i, x as usize + 1);
+                }
+                if let Some((low, high)) = ranges.i {
+                    reads.push((inst.addr, low, high.saturating_add(x as u16)));
+                }
+            }
+            // I changes in a way we don't follow
+            (0xF, _, 0x1, 0xE) | (0xF, _, 0x2, 0x9) | (0x2, _, _, _) => index = None,
+            _ => {}
+        }
+    }
+    for range in &regions.data {
+        mark_data(&mut kinds, range.start, range.len());
+    }
+
+    // Loads of any byte that neither the ROM nor a store reaches
+    let rom_end = 0x200 + rom.len() as u16;
+    let stored = |addr: u16| written.iter().chain(&spread).any(|&(start, len)| addr.wrapping_sub(start) < len);
+    let unwritten_reads = match stores_anywhere {
+        true => Vec::new(),
+        false => loads
+            .into_iter()
+            .filter(|load| (load.source..load.source + load.len).any(|addr| !(0x200..rom_end).contains(&addr) && !stored(addr)))
+            .collect(),
+    };
+
+    // Reads of bytes that neither the font, the ROM nor a store fills
+    let filled = |addr: u16| addr < 0x50 || (0x200..rom_end).contains(&addr) || stored(addr);
+    let uninit_reads = match stores_anywhere {
+        true => Vec::new(),
+        false => reads
+            .into_iter()
+            .filter_map(|(at, first, last)| {
+                let source = (first..=last).find(|&addr| !filled(addr))?;
+                let end = (first..=last).rev().find(|&addr| !filled(addr))?;
+                Some(UninitRead { at, source, len: end - source + 1 })
+            })
+            .collect(),
+    };
+
+    RomMap { instructions, kinds, code_writes, unwritten_reads, uninit_reads }
+}
+
+/// Lowest and highest value each of V0-VF and I may hold, where known
+#[derive(Debug, Clone, Default)]
+struct Ranges {
+    v: [Option<(u8, u8)>; 16],
+    i: Option<(u16, u16)>,
+}
+
+impl Ranges {
+    /// Follow one instruction
+    fn step(&mut self, inst: &Instruction) {
+        if let Some(operand) = inst.operand {
+            self.i = Some((operand, operand));
+            return;
+        }
+        let (x, y) = (inst.x() as usize, inst.y() as usize);
+        match inst.nibbles() {
+            (0x6, _, _, _) => self.v[x] = Some((inst.nn(), inst.nn())),
+            (0x7, _, _, _) => {
+                self.v[x] = self.v[x].and_then(|(low, high)| Some((low.checked_add(inst.nn())?, high.checked_add(inst.nn())?)));
+            }
+            (0x8, _, _, 0x0) => self.v[x] = self.v[y],
+            (0x8, _, _, 0x2) => {
+                let high = [self.v[x], self.v[y]].into_iter().flatten().map(|(_, high)| high).min();
+                self.v[x] = high.map(|high| (0, high));
+                self.v[0xF] = None;
+            }
+            (0x8, _, _, _) => {
+                self.v[x] = None;
+                self.v[0xF] = None;
+            }
+            (0xC, _, _, _) => self.v[x] = Some((0, inst.nn())),
+            (0xD, _, _, _) => self.v[0xF] = None,
+            (0xA, _, _, _) => self.i = Some((inst.nnn(), inst.nnn())),
+            (0xF, _, 0x1, 0xE) => {
+                self.i = self.i.zip(self.v[x]).map(|((low, high), (add_low, add_high))| (low.saturating_add(add_low as u16), high.saturating_add(add_high as u16)));
+            }
+            (0xF, _, 0x2, 0x9) => self.i = Some((0, 0x4B)),  // One of the font digits
+            (0xF, _, 0x0, 0x7 | 0xA) => self.v[x] = None,
+            (0xF, _, 0x6, 0x5) => self.v[..=x].fill(None),
+            (0x2, _, _, _) => *self = Ranges::default(),  // The subroutine may change anything
+            _ => {}
+        }
+    }
+
+    /// Widen to also cover the values in another
+    fn join(&mut self, other: &Ranges) {
+        for (v, other) in self.v.iter_mut().zip(other.v) {
+            *v = v.zip(other).map(|((low, high), (other_low, other_high))| (low.min(other_low), high.max(other_high)));
+        }
+        self.i = self.i.zip(other.i).map(|((low, high), (other_low, other_high))| (low.min(other_low), high.max(other_high)));
+    }
+}
+
+fn kind_at(kinds: &[ByteKind], addr: u16) -> ByteKind {
+    addr.checked_sub(0x200)
+        .and_then(|i| kinds.get(i as usize))
+        .copied()
+        .unwrap_or(ByteKind::Unused)
+}
+
+/// Mark bytes read through I as data, leaving code alone
+fn mark_data(kinds: &mut [ByteKind], addr: u16, len: usize) {
+    let Some(start) = addr.checked_sub(0x200) else { return };
+    for kind in kinds.iter_mut().skip(start as usize).take(len) {
+        if *kind == ByteKind::Unused {
+            *kind = ByteKind::Data;
+        }
+    }
+}
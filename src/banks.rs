@@ -0,0 +1,72 @@
+// Banked multi-game EPROMs
+// Games built with the same options share one runtime layout, so each can
+// fill one bank of a large EPROM, picked by switches on its upper address lines
+
+use crate::codegen::CompiledCode;
+
+/// Largest bank: the Z80 sees the bank below RAM at 0x8000
+pub const MAX_BANK_SIZE: usize = 0x8000;
+
+/// Lay the games out one per bank, each padded with 0xFF (erased EPROM).
+/// The runtime layout of every bank must match the first, so that the
+/// entry point, ABI table and runtime routines are at the same addresses
+/// whichever bank is selected.
+pub fn combine(games: &[CompiledCode], bank_size: usize) -> Result<Vec<u8>, String> {
+    if !bank_size.is_power_of_two() || bank_size > MAX_BANK_SIZE {
+        return Err(format!("bank size {:X} is not a power of two up to {:X}", bank_size, MAX_BANK_SIZE));
+    }
+    let first = games.first().ok_or("no games to combine")?;
+    let layout = runtime_layout(first);
+    let mut image = Vec::with_capacity(games.len() * bank_size);
+    for (n, game) in games.iter().enumerate() {
+        let start = game.origin as usize;
+        if start + game.code.len() > bank_size {
+            return Err(format!(
+                "bank {} needs {} bytes, more than the {} byte bank",
+                n, start + game.code.len(), bank_size
+            ));
+        }
+        if game.origin != first.origin || runtime_layout(game) != layout {
+            return Err(format!("bank {} has a different runtime layout from bank 0", n));
+        }
+        let base = image.len();
+        image.resize(base + bank_size, 0xFF);
+        image[base + start..base + start + game.code.len()].copy_from_slice(&game.code);
+    }
+    Ok(image)
+}
+
+/// Manifest of a combined image: where each game sits and the switch
+/// settings of the address lines that select it
+pub fn manifest(names: &[String], games: &[CompiledCode], bank_size: usize) -> String {
+    let low = bank_size.trailing_zeros();
+    let lines = select_lines(games.len());
+    let mut out = format!("# {} banks of {} bytes, selected by", games.len(), bank_size);
+    for line in (0..lines).rev() {
+        out += &format!(" A{}", low + line);
+    }
+    out += "\n# bank  offset    lines  used   game\n";
+    for (n, (name, game)) in names.iter().zip(games).enumerate() {
+        let switches: String = (0..lines).rev().map(|line| if n >> line & 1 != 0 { '1' } else { '0' }).collect();
+        out += &format!(
+            "{:<5} 0x{:06X}  {:<6} {:<6} {}\n",
+            n,
+            n * bank_size,
+            switches,
+            game.origin as usize + game.code.len(),
+            name
+        );
+    }
+    out
+}
+
+/// Address lines needed to select one of `count` banks
+fn select_lines(count: usize) -> u32 {
+    count.next_power_of_two().trailing_zeros()
+}
+
+/// Runtime routines and their addresses: every label before the program
+fn runtime_layout(game: &CompiledCode) -> Vec<(&String, u16)> {
+    let main = game.symbols.get("main").copied().unwrap_or(0);
+    game.symbols.iter().map(|(name, &addr)| (name, addr)).filter(|&(_, addr)| addr <= main).collect()
+}
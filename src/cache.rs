@@ -0,0 +1,118 @@
+// Compilation cache
+// Compiled code kept on disk between runs, keyed by the ROM, the options
+// that shape its code and the compiler build, so that rebuilding a corpus
+// only compiles what changed and repeats just the packaging for the rest
+
+use crate::codegen::{CodeStats, CompiledCode};
+use crate::diagnostics::{Category, Diagnostic};
+use crate::fnv;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+
+/// Cache entry format version, part of every key
+pub const FORMAT_VERSION: u64 = 1;
+
+/// A directory of compiled code, one JSON file per key
+pub struct Cache {
+    dir: PathBuf,
+    build: u64,  // Identifies the compiler, whose runtime goes into every entry
+}
+
+impl Cache {
+    /// Use `dir` as the cache, creating it if needed, for entries made by
+    /// the compiler build `build` (a hash of the executable, say)
+    pub fn open(dir: &str, build: u64) -> Result<Cache, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("creating cache {}: {}", dir, e))?;
+        Ok(Cache { dir: PathBuf::from(dir), build })
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        let mut id = Vec::new();
+        for word in [FORMAT_VERSION, self.build, key] {
+            id.extend_from_slice(&word.to_le_bytes());
+        }
+        self.dir.join(format!("{:016x}.json", fnv::hash(&id)))
+    }
+
+    /// Code cached under `key` (from Compiler::cache_key), if any. Entries
+    /// that can't be read are treated as missing.
+    pub fn get(&self, key: u64) -> Option<CompiledCode> {
+        let text = fs::read_to_string(self.path(key)).ok()?;
+        from_json(&text).ok()
+    }
+
+    /// Keep `code` under `key`
+    pub fn put(&self, key: u64, code: &CompiledCode) -> Result<(), String> {
+        let path = self.path(key);
+        fs::write(&path, to_json(code)).map_err(|e| format!("writing {}: {}", path.display(), e))
+    }
+}
+
+fn stats_fields(stats: &CodeStats) -> [(&'static str, usize); 8] {
+    [
+        ("runtime_bytes", stats.runtime_bytes),
+        ("program_bytes", stats.program_bytes),
+        ("data_bytes", stats.data_bytes),
+        ("instructions", stats.instructions),
+        ("program_tstates", stats.program_tstates as usize),
+        ("rom_code", stats.rom_code),
+        ("rom_data", stats.rom_data),
+        ("rom_unused", stats.rom_unused),
+    ]
+}
+
+/// Cache entry for compiled code. Pass timings aren't kept.
+pub fn to_json(code: &CompiledCode) -> String {
+    let hex: String = code.code.iter().map(|b| format!("{:02x}", b)).collect();
+    let stats: serde_json::Map<String, Value> =
+        stats_fields(&code.stats).into_iter().map(|(name, value)| (name.to_string(), json!(value))).collect();
+    let diagnostics: Vec<Value> = code
+        .diagnostics
+        .iter()
+        .map(|d| json!({ "category": d.category.name(), "message": d.message }))
+        .collect();
+    json!({
+        "origin": code.origin,
+        "code": hex,
+        "symbols": code.symbols,
+        "stats": stats,
+        "diagnostics": diagnostics,
+    })
+    .to_string()
+}
+
+/// Compiled code from a cache entry
+pub fn from_json(text: &str) -> Result<CompiledCode, String> {
+    let value: Value = serde_json::from_str(text).map_err(|e| format!("invalid cache entry: {}", e))?;
+    let number = |v: &Value| v.as_u64().ok_or("cache entry has a bad number");
+    let origin = number(&value["origin"])? as u16;
+    let hex = value["code"].as_str().ok_or("cache entry has no code")?;
+    let code = (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or("cache entry has bad code")?;
+    let mut symbols = std::collections::BTreeMap::new();
+    for (name, addr) in value["symbols"].as_object().ok_or("cache entry has no symbols")? {
+        symbols.insert(name.clone(), number(addr)? as u16);
+    }
+    let field = |name: &str| number(&value["stats"][name]).map(|n| n as usize);
+    let stats = CodeStats {
+        runtime_bytes: field("runtime_bytes")?,
+        program_bytes: field("program_bytes")?,
+        data_bytes: field("data_bytes")?,
+        instructions: field("instructions")?,
+        program_tstates: field("program_tstates")? as u32,
+        rom_code: field("rom_code")?,
+        rom_data: field("rom_data")?,
+        rom_unused: field("rom_unused")?,
+    };
+    let mut diagnostics = Vec::new();
+    for d in value["diagnostics"].as_array().ok_or("cache entry has no diagnostics")? {
+        let category = d["category"].as_str().and_then(Category::from_name).ok_or("cache entry has a bad diagnostic")?;
+        let message = d["message"].as_str().ok_or("cache entry has a bad diagnostic")?.to_string();
+        diagnostics.push(Diagnostic { category, message });
+    }
+    Ok(CompiledCode { code, origin, symbols, stats, diagnostics, passes: Vec::new() })
+}
@@ -1,15 +1,36 @@
 // CHIP-8 ROM parser and disassembler
 
+use crate::analysis::{self, ByteKind};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+
 /// CHIP-8 instruction
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Instruction {
     pub opcode: u16,
-    pub addr: u16,  // Address in CHIP-8 memory (0x200 + offset)
+    pub addr: u16,              // Address in CHIP-8 memory (0x200 + offset)
+    pub operand: Option<u16>,   // Second word of XO-CHIP F000 NNNN
 }
 
 impl Instruction {
     pub fn new(opcode: u16, addr: u16) -> Self {
-        Self { opcode, addr }
+        Self { opcode, addr, operand: None }
+    }
+
+    /// Create a double-wide instruction (XO-CHIP F000 NNNN)
+    pub fn long(opcode: u16, addr: u16, operand: u16) -> Self {
+        Self { opcode, addr, operand: Some(operand) }
+    }
+
+    /// Size in bytes (2, or 4 for the XO-CHIP long form)
+    pub fn size(&self) -> u16 {
+        if self.operand.is_some() { 4 } else { 2 }
+    }
+
+    /// Check for the XO-CHIP F000 NNNN prefix
+    pub fn is_long_prefix(opcode: u16) -> bool {
+        opcode == 0xF000
     }
 
     /// Extract nibbles from opcode
@@ -45,53 +66,277 @@ impl Instruction {
     pub fn nnn(&self) -> u16 {
         self.opcode & 0xFFF
     }
+
+    /// Whether this is a conditional skip of the next instruction
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self.nibbles(),
+            (0x3, _, _, _) | (0x4, _, _, _) | (0x5, _, _, 0x0) | (0x9, _, _, 0x0) | (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1)
+        )
+    }
+}
+
+/// Code and data boundaries known ahead of time, e.g. from a listing of
+/// the original source, for what the control-flow walk can't work out
+#[derive(Debug, Clone, Default)]
+pub struct RegionMap {
+    pub code: Vec<Range<u16>>,  // Decoded word by word, and walked from
+    pub data: Vec<Range<u16>>,  // Never decoded, even if a jump lands there
+}
+
+impl RegionMap {
+    /// Whether a CHIP-8 address lies in a known data region
+    pub fn is_data(&self, addr: u16) -> bool {
+        self.data.iter().any(|range| range.contains(&addr))
+    }
 }
 
 /// Parse ROM into instructions
-/// Stops parsing when an infinite loop (JP to self) is detected
+/// Follows control flow from 0x200 so that only reachable words are
+/// treated as code; data mixed in with the program is left alone. The word
+/// after a CALL only counts once its subroutine is found able to return.
+/// The result is sorted by address.
 pub fn parse(rom: &[u8]) -> Vec<Instruction> {
-    let mut instructions = Vec::new();
-    let mut i = 0;
-
-    while i + 1 < rom.len() {
-        let opcode = ((rom[i] as u16) << 8) | (rom[i + 1] as u16);
-        let addr = 0x200 + i as u16;
-        instructions.push(Instruction::new(opcode, addr));
-
-        // Check for infinite loop (JP to self)
-        // This indicates end of code, rest is data
-        let nibble0 = (opcode >> 12) & 0xF;
-        if nibble0 == 0x1 {  // JP instruction
-            let target = opcode & 0xFFF;
-            if target == addr {
-                // Infinite loop detected (JP to self), stop parsing
-                break;
+    parse_with_regions(rom, &RegionMap::default())
+}
+
+/// Parse ROM into instructions, also walking from the start of each known
+/// code region and stopping at known data
+pub fn parse_with_regions(rom: &[u8], regions: &RegionMap) -> Vec<Instruction> {
+    let mut found: BTreeMap<u16, Instruction> = BTreeMap::new();
+    let mut calls: Vec<(u16, u16)> = Vec::new();  // (Subroutine, return address)
+    let mut pending = vec![0x200u16];
+    for range in &regions.code {
+        let mut addr = range.start;
+        while let Some(inst) = decode_at(rom, addr).filter(|_| addr < range.end) {
+            pending.push(addr);
+            addr += inst.size();
+        }
+    }
+
+    loop {
+        walk(rom, regions, &mut found, &mut calls, pending);
+        let returning = returning_subroutines(&found);
+        pending = calls
+            .iter()
+            .filter(|(sub, ret)| returning.contains(sub) && !found.contains_key(ret))
+            .map(|&(_, ret)| ret)
+            .collect();
+        if pending.is_empty() {
+            break;
+        }
+    }
+
+    found.into_values().collect()
+}
+
+/// Add everything reachable from `pending` to `found`, recording calls
+/// instead of following them back to their return address
+fn walk(
+    rom: &[u8],
+    regions: &RegionMap,
+    found: &mut BTreeMap<u16, Instruction>,
+    calls: &mut Vec<(u16, u16)>,
+    mut pending: Vec<u16>,
+) {
+    while let Some(addr) = pending.pop() {
+        if found.contains_key(&addr) || regions.is_data(addr) {
+            continue;
+        }
+        let inst = match decode_at(rom, addr) {
+            Some(inst) => inst,
+            None => continue,  // Outside the ROM
+        };
+        found.insert(addr, inst);
+
+        let next = addr + inst.size();
+        match inst.nibbles() {
+            // RET and SUPER-CHIP EXIT end the path
+            (0x0, 0x0, 0xE, 0xE) | (0x0, 0x0, 0xF, 0xD) => {}
+            (0x1, _, _, _) => pending.push(inst.nnn()),
+            (0x2, _, _, _) => {
+                pending.push(inst.nnn());
+                calls.push((inst.nnn(), next));
+            }
+            // Skips continue at the next instruction or the one after it
+            (0x3, _, _, _) | (0x4, _, _, _) | (0x5, _, _, 0x0) | (0x9, _, _, 0x0)
+            | (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1) => {
+                pending.push(next);
+                if let Some(skipped) = decode_at(rom, next) {
+                    pending.push(next + skipped.size());
+                }
+            }
+            // BNNN targets are computed; assume a table of jumps at NNN
+            (0xB, _, _, _) => {
+                let mut entry = inst.nnn();
+                pending.push(entry);
+                while let Some(target) = decode_at(rom, entry) {
+                    if target.opcode >> 12 != 0x1 {
+                        break;
+                    }
+                    pending.push(entry);
+                    entry += 2;
+                }
+            }
+            _ => pending.push(next),
+        }
+    }
+}
+
+/// Called subroutines with a path from their entry to a RET. A path
+/// through a nested call only continues if that subroutine returns, and a
+/// BNNN is assumed to reach one.
+fn returning_subroutines(found: &BTreeMap<u16, Instruction>) -> BTreeSet<u16> {
+    let entries: BTreeSet<u16> =
+        found.values().filter(|inst| inst.opcode >> 12 == 0x2).map(|inst| inst.nnn()).collect();
+    let mut returning = BTreeSet::new();
+    loop {
+        let before = returning.len();
+        for &entry in &entries {
+            if !returning.contains(&entry) && can_return(found, &returning, entry) {
+                returning.insert(entry);
+            }
+        }
+        if returning.len() == before {
+            return returning;
+        }
+    }
+}
+
+fn can_return(found: &BTreeMap<u16, Instruction>, returning: &BTreeSet<u16>, entry: u16) -> bool {
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![entry];
+    while let Some(addr) = pending.pop() {
+        let Some(inst) = found.get(&addr) else { continue };
+        if !visited.insert(addr) {
+            continue;
+        }
+        let next = addr + inst.size();
+        match inst.nibbles() {
+            (0x0, 0x0, 0xE, 0xE) | (0xB, _, _, _) => return true,
+            (0x0, 0x0, 0xF, 0xD) => {}
+            (0x1, _, _, _) => pending.push(inst.nnn()),
+            (0x2, _, _, _) => {
+                if returning.contains(&inst.nnn()) {
+                    pending.push(next);
+                }
+            }
+            (0x3, _, _, _) | (0x4, _, _, _) | (0x5, _, _, 0x0) | (0x9, _, _, 0x0)
+            | (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1) => {
+                pending.push(next);
+                if let Some(skipped) = found.get(&next) {
+                    pending.push(next + skipped.size());
+                }
             }
+            _ => pending.push(next),
         }
+    }
+    false
+}
 
-        i += 2;
+/// Decode the instruction at a CHIP-8 address, if it lies in the ROM
+fn decode_at(rom: &[u8], addr: u16) -> Option<Instruction> {
+    let i = addr.checked_sub(0x200)? as usize;
+    if i + 1 >= rom.len() {
+        return None;
     }
+    let opcode = ((rom[i] as u16) << 8) | (rom[i + 1] as u16);
 
-    instructions
+    // XO-CHIP F000 NNNN: the following word is the 16-bit address
+    if Instruction::is_long_prefix(opcode) && i + 3 < rom.len() {
+        let operand = ((rom[i + 2] as u16) << 8) | (rom[i + 3] as u16);
+        Some(Instruction::long(opcode, addr, operand))
+    } else {
+        Some(Instruction::new(opcode, addr))
+    }
+}
+
+/// Whether execution can continue at the following instruction
+pub fn falls_through(inst: &Instruction) -> bool {
+    !matches!(
+        inst.nibbles(),
+        (0x0, 0x0, 0xE, 0xE) | (0x0, 0x0, 0xF, 0xD) | (0x1, _, _, _) | (0xB, _, _, _)
+    )
+}
+
+/// One line of a disassembly listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Code { inst: Instruction, writes_code_at: Option<u16> },
+    Data { addr: u16, byte: u8 },
+    Unused { start: u16, len: u16 },
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Line::Code { inst, writes_code_at } => {
+                match inst.operand {
+                    Some(operand) => write!(f, "{:03X}: {:04X} {:04X}  {}", inst.addr, inst.opcode, operand, disasm_instruction(&inst))?,
+                    None => write!(f, "{:03X}: {:04X}  {}", inst.addr, inst.opcode, disasm_instruction(&inst))?,
+                }
+                match writes_code_at {
+                    Some(target) => write!(f, "    ; writes code at {:03X}", target),
+                    None => Ok(()),
+                }
+            }
+            Line::Data { addr, byte } => {
+                let pixels: String = (0..8).map(|b| if byte & (0x80 >> b) != 0 { '#' } else { '.' }).collect();
+                write!(f, "{:03X}: {:02X}    DB   {:02X}      {}", addr, byte, byte, pixels)
+            }
+            Line::Unused { start, len } => write!(f, "{:03X}: ..    ({} bytes not referenced)", start, len),
+        }
+    }
 }
 
 /// Disassemble and print ROM
+/// Code is listed as instructions, sprite data as bytes with their pixels
 pub fn disassemble(rom: &[u8]) {
-    let instructions = parse(rom);
+    for line in disassemble_with_regions(rom, &RegionMap::default()) {
+        println!("{}", line);
+    }
+}
+
+/// Disassemble a ROM into listing lines, in address order, using known
+/// regions on top of the control-flow walk
+pub fn disassemble_with_regions(rom: &[u8], regions: &RegionMap) -> Vec<Line> {
+    let map = analysis::classify_with_regions(rom, regions);
+    let mut code = map.instructions.iter().peekable();
+    let mut lines = Vec::new();
 
-    for inst in instructions {
-        let mnemonic = disasm_instruction(&inst);
-        println!("{:03X}: {:04X}  {}", inst.addr, inst.opcode, mnemonic);
+    for region in map.regions() {
+        match region.kind {
+            ByteKind::Code => {
+                while let Some(&inst) = code.next_if(|inst| inst.addr < region.end()) {
+                    let writes_code_at = map.code_writes.iter().find(|w| w.at == inst.addr).map(|w| w.target);
+                    lines.push(Line::Code { inst, writes_code_at });
+                }
+            }
+            ByteKind::Data => {
+                for addr in region.start..region.end() {
+                    lines.push(Line::Data { addr, byte: rom[(addr - 0x200) as usize] });
+                }
+            }
+            ByteKind::Unused => lines.push(Line::Unused { start: region.start, len: region.len }),
+        }
     }
+    lines
 }
 
 /// Disassemble a single instruction
 pub fn disasm_instruction(inst: &Instruction) -> String {
     let (n0, n1, n2, n3) = inst.nibbles();
 
+    if let Some(operand) = inst.operand {
+        return format!("LD   I, {:04X}", operand);
+    }
+
     match (n0, n1, n2, n3) {
         (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
         (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
+        (0x0, 0x0, 0xF, 0xD) => "EXIT".to_string(),
+        (0x0, 0x0, 0xF, 0xE) => "LOW".to_string(),
+        (0x0, 0x0, 0xF, 0xF) => "HIGH".to_string(),
         (0x0, _, _, _) => format!("SYS  {:03X}", inst.nnn()),
         (0x1, _, _, _) => format!("JP   {:03X}", inst.nnn()),
         (0x2, _, _, _) => format!("CALL {:03X}", inst.nnn()),
@@ -1,8 +1,23 @@
 // Z80 Code Generator for CHIP-8
 // Compiles CHIP-8 instructions to native Z80 code
 
+use crate::abi;
+use crate::analysis::{self, ByteKind, RomMap};
 use crate::chip8::{self, Instruction};
-use std::collections::HashMap;
+use crate::delta;
+use crate::diagnostics::{Category, Diagnostic, Level, Levels};
+use crate::keymap::KeyMap;
+use crate::memmap::{self, MemoryMap};
+use crate::profile::Profile;
+use crate::quirks::Quirks;
+use crate::sms::{self, PadMap};
+use crate::stepgate;
+use crate::timing::{self, PassStart, PassTime};
+use crate::trap::Trap;
+#[cfg(feature = "config")]
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
 
 /// Memory layout for RetroShield Z80 (32KB ROM)
 /// 0x0000-0x00FF: RST vectors
@@ -12,32 +27,795 @@ use std::collections::HashMap;
 /// 0x8200-0x82FF: Display buffer (64x32 = 256 bytes)
 /// 0x8300-0x83FF: Font data (16 chars x 5 bytes = 80 bytes)
 /// 0x8400-0xFFFF: CHIP-8 RAM (for data, not code)
-
 const CODE_START: u16 = 0x0100;
-// RAM must be at >= 0x8000 (above 32KB ROM area) for emulator compatibility
-const CHIP8_V0: u16 = 0x8000;      // V0-VF registers (16 bytes)
+// RAM must be at >= 0x8000 (above 32KB ROM area) for emulator compatibility.
+// These are the addresses with RAM at 0x8000; Compiler::ram moves them for
+// targets, or memory maps, with RAM elsewhere.
+pub(crate) const CHIP8_V0: u16 = 0x8000;      // V0-VF registers (16 bytes)
 const CHIP8_I: u16 = 0x8010;       // I register (2 bytes)
 const CHIP8_SP: u16 = 0x8012;      // Stack pointer (1 byte)
 const CHIP8_DT: u16 = 0x8013;      // Delay timer (1 byte)
-const CHIP8_ST: u16 = 0x8014;      // Sound timer (1 byte)
-const CHIP8_KEY: u16 = 0x8015;     // Current key pressed (1 byte, 0xFF = none)
+pub const CHIP8_ST: u16 = 0x8014;  // Sound timer (1 byte)
 const CHIP8_RNG: u16 = 0x8016;     // RNG state (2 bytes)
+const TICK_COUNT: u16 = 0x8018;    // Timer tick countdown (2 bytes)
+const DRAW_OLD: u16 = 0x801A;      // Screen address of a sprite being moved (2 bytes)
+pub const FRAME_COUNT: u16 = 0x801C;  // 60Hz frame counter (1 byte)
+#[cfg(feature = "interp")]
+const INTERP_PC: u16 = 0x801E;     // Interpreter program counter (2 bytes)
+const TEXT_POS: u16 = 0x8020;      // Console column and row on the screen targets (2 bytes)
+const DEBUG_STEP: u16 = 0x8022;    // Debug stub stops before the next instruction when set (1 byte)
+const DEBUG_WATCHES: u16 = 0x8023; // Debug stub watches (WATCH_SLOTS x WATCH_SIZE bytes)
+const STEP_GATE: u16 = 0x807B;     // Blocks wait for the host when set (1 byte)
+const STEP_KEY: u16 = 0x807C;      // Character the step gate read for get_key, 0 if none (1 byte)
+const DEBUG_BREAKS: u16 = 0x807D;  // Debug stub breakpoints, 0 when free (BREAK_SLOTS words)
+const GLYPH_ROWS: u16 = 0x8085;    // Display bytes being turned into block or braille characters (4 bytes)
+const KEY_STATE: u16 = 0x8089;     // Frames each console key stays down, 0 when up (16 bytes)
+const RX_TAIL: u16 = 0x8099;       // Low byte of the next character's address in RX_RING (1 byte)
+const RX_HEAD: u16 = 0x809A;       // Low byte of where the ACIA's interrupt puts the next one (1 byte)
+const BELL_WAIT: u16 = 0x809B;     // Frames until the bell may ring again (1 byte)
+const BELL_ON: u16 = 0x809C;       // Sound timer was running last frame (1 byte)
+const DRAW_SHIFT: u16 = 0x809D;    // Bits a sprite being drawn is right of its first display byte (1 byte)
+const DRAW_OLD_SHIFT: u16 = 0x809E; // The same for a sprite being moved (1 byte)
+pub const HIRES_MODE: u16 = 0x809F; // Non-zero while S-CHIP's 128x64 mode is on (1 byte)
+const RX_RING: u16 = 0x80A0;       // Characters received but not yet read (32 bytes, one page)
+const DIRTY_ROWS: u16 = 0x80E0;    // Display rows to redraw on the ANSI targets (32 bytes, one page)
 const CHIP8_STACK: u16 = 0x8100;   // Call stack (32 bytes)
-const DISPLAY_BUF: u16 = 0x8200;   // 64x32 / 8 = 256 bytes
-const FONT_DATA: u16 = 0x8300;     // Sprite font
-const CHIP8_RAM: u16 = 0x8400;     // General RAM
+const INPUT_RAW: u16 = 0x8120;     // What the input test's get_key last returned (1 byte)
+const INPUT_STATES: u16 = 0x8121;  // Keys down by EX9E and by EXA1 as the input test last printed them (4 bytes)
+const INPUT_COUNT: u16 = 0x8125;   // Keys the input test has left to gather (1 byte)
+const INPUT_TABLE: u16 = 0x300;    // CHIP-8 address of the input test's EX9E and EXA1 results
+const HIRES_WIDE: u16 = 0x8126;    // The hires sprite being drawn is 16 pixels wide (1 byte)
+const DRAW_HIT: u16 = 0x8127;      // Pixels the hires sprite being drawn turned off (1 byte)
+const PACE_BUDGET: u16 = 0x8128;   // CHIP-8 instructions left to run this frame with --speed (2 bytes)
+const PACE_FRAME: u16 = 0x812A;    // FRAME_COUNT the budget is for (1 byte)
+const HIRES_ROWS: u16 = 0x8140;    // Hires display rows to redraw (64 bytes)
+pub const DISPLAY_BUF: u16 = 0x8200;  // 64x32 / 8 = 256 bytes
+pub const FONT_DATA: u16 = 0x8300;  // Sprite font
+pub const CHIP8_RAM: u16 = 0x8400;  // General RAM
+
+const STACK_DEPTH: u8 = 16;        // CHIP-8 call stack entries
+const KEY_RELEASE_MS: u16 = 200;   // Time a console key stays down after its last character
+const WATCH_SLOTS: u8 = 4;         // Debug stub watches
+const BREAK_SLOTS: u8 = 4;         // Debug stub breakpoints
+const BELL_GAP: u8 = 15;           // Frames between bells, at the least
+const RX_MASK: u8 = 0x1F;          // RX_RING index bits
+const RX_VECTOR: u16 = 0x0038;     // Where IM 1 sends the ACIA's interrupt
+const CTC_TABLE: u16 = 0x0040;     // IM 2's entry for the CTC's interrupt, 8-aligned for its vector
+const CTC_CONTROL: u8 = 0xA7;      // Interrupt on, timer mode, prescaler 256, time constant follows, reset
+const CTC_PRESCALE: u32 = 256;
+// Each watch: length (0 when free), kind ('V', 'I' or 'M'), Z80 address,
+// register or CHIP-8 address, then the bytes last seen
+const WATCH_SIZE: u8 = 6 + WATCH_BYTES;
+const WATCH_BYTES: u8 = 16;        // Longest memory watch
+const MEM_TOP: u16 = 0x7000;       // First CHIP-8 address beyond mapped RAM
+const MEM_TOP_4K: u16 = 0x1000;    // CHIP-8's own 4KB, so RAM ends at 0x9200 (CP/M, CPC)
+pub const HIRES_SIZE: u16 = 0x400; // 128x64 / 8 bytes of hires display
+const PROFILE_COUNTERS: u16 = 0xF200;  // Block counters of a --profile-gen build (4 bytes each)
+const PROFILE_END: u16 = 0xFE00;       // Keeps the counters clear of the Z80 stack
+const DELTA_SHADOW: u16 = 0xF200;      // Display as last sent by a --delta-display build (256 bytes, in the counters' place)
+
+// Default Z80 clock used to pace the 60Hz timers
+pub const DEFAULT_CLOCK_HZ: u32 = 4_000_000;
+// Timer ticks are weighted in units of this many T-states
+const TICK_UNIT: u32 = 64;
+// T-states of the tick call itself (LD A,n + CALL + common path)
+const TICK_OVERHEAD: u32 = 110;
+// Timer weight of one interpreted instruction (in TICK_UNITs)
+#[cfg(feature = "interp")]
+const INTERP_WEIGHT: u8 = 8;
+// T-states of an inlined timer tick when no frame boundary is crossed
+const INLINE_TICK: u32 = 77;
+// T-states of key_down testing a console key with no character waiting
+const KEY_DOWN_TSTATES: u32 = 250;
+// A block is hot when it runs at least 1/HOT_FRACTION as often as the hottest
+const HOT_FRACTION: u64 = 16;
 
 // ACIA ports
-const ACIA_CTRL: u8 = 0x80;
+const ACIA_CTRL: u8 = 0x80;  // Where the RetroShield decodes it, unless --acia-ports moves it
 const ACIA_DATA: u8 = 0x81;
 
+// RC2014 target: SIO/2 channel A
+const RC2014_CLOCK_HZ: u32 = 7_372_800;  // The standard clock module, which also clocks the SIO
+const SIO_CTRL: u8 = 0x80;               // Write register select and RR0 status
+const SIO_DATA: u8 = 0x81;
+
+/// Serial byte that enters the debug stub's monitor (Ctrl-B)
+pub const DEBUG_ESCAPE: u8 = 0x02;
+
+// ZX Spectrum target
+const SPECTRUM_CLOCK_HZ: u32 = 3_500_000;
+const SPECTRUM_ORIGIN: u16 = 0x6000;  // Above the BASIC loader, below CHIP-8 RAM
+const SPECTRUM_LOWEST: u16 = 0x5E00;  // Leaves room for the loader and BASIC's stack
+const ULA_PORT: u8 = 0xFE;            // Border colour out, keyboard half-rows in
+const SCREEN: u16 = 0x4000;           // Bitmap, 32 bytes x 192 lines
+const ATTRIBUTES: u16 = 0x5800;       // Colour of each 8x8 cell, 32 x 24
+const ROM_FONT: u16 = 0x3C00;         // ROM character set, indexed from code 0
+const DISPLAY_TOP: u8 = 32;           // First screen line of the 4x scaled display
+const TEXT_TOP: u8 = 20;              // First console text row, below the display
+// Keyboard half-row (high port byte) and bit of CHIP-8 keys 0-F, laid
+// out as the COSMAC VIP keypad on 1-4, Q-R, A-F and Z-V
+const SPECTRUM_KEYS: [(u8, u8); 16] = [
+    (0xFE, 2), (0xF7, 0), (0xF7, 1), (0xF7, 2),  // X 1 2 3
+    (0xFB, 0), (0xFB, 1), (0xFB, 2), (0xFD, 0),  // Q W E A
+    (0xFD, 1), (0xFD, 2), (0xFE, 1), (0xFE, 3),  // S D Z C
+    (0xF7, 3), (0xFB, 3), (0xFD, 3), (0xFE, 4),  // 4 R F V
+];
+
+// MSX target
+const MSX_CLOCK_HZ: u32 = 3_579_545;
+const MSX_ORIGIN: u16 = 0x4000;       // Cartridge in page 1; page 2 stays RAM
+const MSX_CARTRIDGE_SIZE: usize = 0x4000;
+const MSX_STACK: u16 = 0xF380;        // Below the BIOS work area and the slot register at 0xFFFF
+const BIOS_FONT: u16 = 0x0004;        // CGTABL: address of the BIOS character set
+const VDP_DATA: u8 = 0x98;
+const VDP_CTRL: u8 = 0x99;
+const PPI_KEYS: u8 = 0xA9;            // Columns of the selected keyboard row, low when pressed
+const PPI_ROW: u8 = 0xAA;             // Keyboard row in the low 4 bits
+const NAME_TABLE: u16 = 0x1800;       // One pattern number per 8x8 cell, 32 x 24
+const COLOR_TABLE: u16 = 0x2000;      // Colours of each group of 8 patterns
+const MSX_DISPLAY_TOP: u16 = 2;       // First cell row of the display, 2x2 pixels per cell
+// VDP registers 0-7 for Graphics I with the tables above, blanked, no
+// interrupts, black backdrop
+const VDP_REGS: [u8; 8] = [0x00, 0x80, 0x06, 0x80, 0x00, 0x36, 0x07, 0x01];
+// Keyboard row and bit of CHIP-8 keys 0-F, on the same keys as the Spectrum
+const MSX_KEYS: [(u8, u8); 16] = [
+    (5, 5), (0, 1), (0, 2), (0, 3),  // X 1 2 3
+    (4, 6), (5, 4), (3, 2), (2, 6),  // Q W E A
+    (5, 0), (3, 1), (5, 7), (3, 0),  // S D Z C
+    (0, 4), (4, 7), (3, 3), (5, 3),  // 4 R F V
+];
+
+// CP/M target
+const CPM_ORIGIN: u16 = 0x0100;       // Start of the TPA, where .COM files load
+const BDOS: u16 = 0x0005;             // BDOS entry, a JP to the top of the TPA
+const CPM_TPA_NEEDED: u8 = 0x93;      // Lowest BDOS page: the RAM and a page of stack
+const DIRECT_IO: u8 = 6;              // BDOS console I/O, without ^S/^C handling
+
+// Amstrad CPC target
+const CPC_CLOCK_HZ: u32 = 3_300_000;  // 4MHz, but the Gate Array stretches each instruction to whole microseconds
+const CPC_ORIGIN: u16 = 0x4000;       // Clear of the lower ROM, which print_char pages in for the font
+const CPC_STACK: u16 = 0xC000;        // Below the screen, over the firmware's RAM (interrupts are off)
+const GATE_ARRAY: u16 = 0x7F00;       // Pen and colour select, screen mode and ROM paging
+const GA_SCREEN: u8 = 0x8D;           // Mode 1 (40 columns, 4 colours), both ROMs paged out
+const GA_FONT: u8 = 0x89;             // The same with the lower ROM paged in
+const CRTC_SELECT: u16 = 0xBC00;
+const CRTC_DATA: u16 = 0xBD00;
+const PSG_PORT: u16 = 0xF400;         // PPI port A: PSG data, and the keyboard line when read
+const PSG_CONTROL: u16 = 0xF600;      // PPI port C: PSG function in bits 6-7, keyboard line in 0-3
+const PPI_CONTROL: u16 = 0xF700;
+const CPC_SCREEN: u16 = 0xC000;       // 80 bytes a line, line n at (n / 8) * 80 + (n % 8) * 0x800
+const CPC_ROM_FONT: u16 = 0x3800;     // Lower ROM character set, indexed from code 0
+const CPC_DISPLAY: u16 = CPC_SCREEN + 3 * 80 + 8;  // Character row 3, centred: 64 bytes of 80
+// Keyboard line and bit of CHIP-8 keys 0-F, on the same keys as the Spectrum
+const CPC_KEYS: [(u8, u8); 16] = [
+    (7, 7), (8, 0), (8, 1), (7, 1),  // X 1 2 3
+    (8, 3), (7, 3), (7, 2), (8, 5),  // Q W E A
+    (7, 4), (7, 5), (8, 7), (7, 6),  // S D Z C
+    (7, 0), (6, 2), (6, 5), (6, 7),  // 4 R F V
+];
+
+// Sega Master System target
+const SMS_CLOCK_HZ: u32 = 3_579_545;
+const SMS_STACK: u16 = 0xDFF0;        // Top of the console's RAM, below the mapper registers
+const SMS_RAM_SELECT: u16 = 0xFFFC;   // Mapper control: bit 3 pages cartridge RAM in at 0x8000
+const SMS_CARTRIDGE_RAM: u8 = 0x08;
+const SMS_VDP_DATA: u8 = 0xBE;
+const SMS_VDP_CTRL: u8 = 0xBF;
+const SMS_PAD: u8 = 0xDC;             // Player 1 buttons in bits 0-5, low when pressed
+const SMS_NAME_TABLE: u16 = 0x3800;   // Tile and attribute byte per 8x8 cell, 32 x 28
+const SMS_SPRITES: u16 = 0x3F00;      // Sprite attribute table
+const SMS_FONT_TILE: u16 = 32;        // Tile of the first font character, a space
+// VDP registers 0-10 for mode 4 with the tables above, blanked, no
+// interrupts, backdrop from palette entry 16
+const SMS_VDP_REGS: [u8; 11] = [0x04, 0x80, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0xFF];
+
+// ColecoVision target
+const COLECO_CLOCK_HZ: u32 = 3_579_545;
+const COLECO_ORIGIN: u16 = 0x8000;     // Cartridge slot, with the BIOS below it
+const COLECO_CARTRIDGE_SIZE: usize = 0x8000;
+const COLECO_RAM: u16 = 0x2000;        // The Super Game Module's RAM, to 0x7FFF
+const COLECO_STACK: u16 = 0x8000;      // Down from the top of the module's RAM
+const SGM_RAM_PORT: u8 = 0x53;         // Bit 0 maps the module's RAM in at 0x2000
+const COLECO_VDP_DATA: u8 = 0xBE;
+const COLECO_VDP_CTRL: u8 = 0xBF;
+const KEYPAD_MODE: u8 = 0x80;          // Any write: controllers read their keypads
+const JOYSTICK_MODE: u8 = 0xC0;        // Any write: controllers read their sticks
+const CONTROLLER_1: u8 = 0xFC;         // Keypad code in bits 0-3, fire in bit 6, low when pressed
+// CHIP-8 key of each keypad code, with * and # as A and B
+const COLECO_KEYPAD: [u8; 16] = [
+    0xFF, 0x8, 0x4, 0x5, 0xFF, 0x7, 0xA, 0x2,
+    0xFF, 0xB, 0x0, 0x9, 0x3, 0x1, 0x6, 0xFF,
+];
+// AY-3-8910 (or YM2149) ports of the RC2014 sound module: register select
+// and data
+const AY_REGISTER: u8 = 0xD8;
+const AY_DATA: u8 = 0xD0;
+const AY_TONE: u16 = 252;              // Tone period: 440Hz from a 1.7734MHz clock
+const AY_MIXER: u8 = 0xBE;             // Tone A alone, I/O port B an output as on the MSX
+const AY_VOLUME: u8 = 15;              // Channel A's level while the sound timer runs
+const COLECO_LEFT_FIRE: u8 = 0xC;
+const COLECO_RIGHT_FIRE: u8 = 0xD;
+
+/// Output port bit driven high while the sound timer runs (active buzzer)
+#[derive(Debug, Clone, Copy)]
+pub struct Beeper {
+    pub port: u8,
+    pub mask: u8,
+}
+
+/// Memory-mapped video the display is copied to instead of drawn on the
+/// terminal: one bit per pixel, leftmost in bit 7, 8 bytes of each row
+/// `stride` bytes apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framebuffer {
+    pub base: u16,
+    pub stride: u16,
+}
+
+impl Framebuffer {
+    /// Addresses the rows cover, laid out as `orientation` says and
+    /// drawn `scale` times as large
+    fn span(self, orientation: Orientation, scale: Scale) -> Range<usize> {
+        let start = self.base as usize;
+        start..start + (orientation.rows() * scale.factor() - 1) * self.stride as usize + orientation.row_bytes() * scale.factor()
+    }
+}
+
+/// Size of each display pixel in framebuffer video memory, for modules
+/// with more pixels than CHIP-8
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+pub enum Scale {
+    #[default]
+    #[cfg_attr(feature = "config", serde(rename = "1"))]
+    Single,     // A pixel each: 64x32
+    #[cfg_attr(feature = "config", serde(rename = "2"))]
+    Double,     // 2x2 pixels each: 128x64
+    #[cfg_attr(feature = "config", serde(rename = "scanlines"))]
+    Scanlines,  // 2x1 pixels each, over a dark line: 128x64
+}
+
+impl Scale {
+    /// Look up a scale by its `--scale` name
+    pub fn from_name(name: &str) -> Option<Scale> {
+        match name {
+            "1" => Some(Scale::Single),
+            "2" => Some(Scale::Double),
+            "scanlines" => Some(Scale::Scanlines),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scale::Single => "1",
+            Scale::Double => "2",
+            Scale::Scanlines => "scanlines",
+        }
+    }
+
+    fn factor(self) -> usize {
+        match self {
+            Scale::Single => 1,
+            Scale::Double | Scale::Scanlines => 2,
+        }
+    }
+}
+
+// Each nibble with its bits doubled, for the framebuffer at Scale::Double
+const DOUBLED_NIBBLES: [u8; 16] = [
+    0x00, 0x03, 0x0C, 0x0F, 0x30, 0x33, 0x3C, 0x3F,
+    0xC0, 0xC3, 0xCC, 0xCF, 0xF0, 0xF3, 0xFC, 0xFF,
+];
+
+/// How a display module wants the pixels in its video memory: turned
+/// clockwise, then mirrored left to right, with the leftmost pixel of each
+/// byte in bit 7 or bit 0
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default, rename_all = "kebab-case"))]
+pub struct Orientation {
+    pub rotate: u16,  // 0, 90, 180 or 270 degrees
+    pub mirror: bool,
+    pub lsb_first: bool,
+}
+
+impl Orientation {
+    /// Apply one `--orientation` argument: `rotate=0|90|180|270`,
+    /// `portrait` (rotate=90), `mirror[=on|off]`, `lsb-first` or `msb-first`
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        let (name, value) = match spec.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (spec, None),
+        };
+        match (name, value) {
+            ("rotate", Some(degrees)) => match degrees.parse() {
+                Ok(rotate @ (0 | 90 | 180 | 270)) => self.rotate = rotate,
+                _ => return Err(format!("invalid rotate '{}' (expected 0, 90, 180 or 270)", degrees)),
+            },
+            ("portrait", None) => self.rotate = 90,
+            ("mirror", None | Some("on")) => self.mirror = true,
+            ("mirror", Some("off")) => self.mirror = false,
+            ("lsb-first", None) => self.lsb_first = true,
+            ("msb-first", None) => self.lsb_first = false,
+            _ => return Err(format!("invalid orientation '{}' (expected rotate=DEGREES, portrait, mirror, lsb-first or msb-first)", spec)),
+        }
+        self.check()
+    }
+
+    pub fn check(self) -> Result<(), String> {
+        match self.rotate {
+            0 | 90 | 180 | 270 => Ok(()),
+            _ => Err("orientation rotate should be 0, 90, 180 or 270 degrees".to_string()),
+        }
+    }
+
+    /// Settings apart from the default, in `--orientation` syntax
+    pub fn names(self) -> Vec<String> {
+        let mut names = Vec::new();
+        if self.rotate != 0 {
+            names.push(format!("rotate={}", self.rotate));
+        }
+        if self.mirror {
+            names.push("mirror".to_string());
+        }
+        if self.lsb_first {
+            names.push("lsb-first".to_string());
+        }
+        names
+    }
+
+    /// Whether the display is on its side, 32 pixels wide and 64 high
+    fn sideways(self) -> bool {
+        self.rotate % 180 == 90
+    }
+
+    fn rows(self) -> usize {
+        if self.sideways() { 64 } else { 32 }
+    }
+
+    fn row_bytes(self) -> usize {
+        if self.sideways() { 4 } else { 8 }
+    }
+}
+
+/// Size and static cycle counts of the generated code
+#[derive(Debug, Clone, Default)]
+pub struct CodeStats {
+    pub runtime_bytes: usize,    // Header, init and runtime routines
+    pub program_bytes: usize,    // Compiled CHIP-8 instructions
+    pub data_bytes: usize,       // Tables and embedded ROM data
+    pub instructions: usize,     // CHIP-8 instructions compiled
+    pub program_tstates: u32,    // Inline T-states of the compiled instructions
+    pub rom_code: usize,         // CHIP-8 ROM bytes classified as code
+    pub rom_data: usize,         // ... as sprite/data
+    pub rom_unused: usize,       // ... as unreferenced
+}
+
+/// What runs code the compiler can't resolve statically: computed jumps
+/// to undiscovered addresses and instructions the ROM overwrites
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum Fallback {
+    #[default]
+    Trap,    // Runtime trap (bad jump) or compile error (self-modifying)
+    Interp,  // Embedded CHIP-8 interpreter
+}
+
+/// What a trace build prints before each compiled instruction runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum Trace {
+    #[default]
+    Off,
+    Pc,      // `T 0200` lines
+    Opcode,  // `T 0200 00E0` lines
+}
+
+/// Characters the ANSI display draws pixels with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum Glyphs {
+    #[default]
+    Ascii,    // `#` or space for each pixel: 64x32 characters
+    Blocks,   // Quadrant block elements, 2x2 pixels each: 32x16
+    Braille,  // Braille patterns, 2x4 pixels each: 32x8
+}
+
+impl Glyphs {
+    /// Look up glyphs by their `--glyphs` name
+    pub fn from_name(name: &str) -> Option<Glyphs> {
+        match name {
+            "ascii" => Some(Glyphs::Ascii),
+            "blocks" => Some(Glyphs::Blocks),
+            "braille" => Some(Glyphs::Braille),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Glyphs::Ascii => "ascii",
+            Glyphs::Blocks => "blocks",
+            Glyphs::Braille => "braille",
+        }
+    }
+
+    /// Display rows drawn by each line of characters
+    fn rows(self) -> u8 {
+        match self {
+            Glyphs::Ascii => 1,
+            Glyphs::Blocks => 2,
+            Glyphs::Braille => 4,
+        }
+    }
+}
+
+/// Sound of the sound timer besides the beeper
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum Audio {
+    Bell,  // BEL to the console's terminal as the sound starts
+    Ay8910 { register: u8, data: u8 },  // Tone A of an AY-3-8910 at these ports
+}
+
+impl Audio {
+    /// Look up audio by its `--audio` name
+    pub fn from_name(name: &str) -> Option<Audio> {
+        match name {
+            "bell" => Some(Audio::Bell),
+            "ay8910" => Some(Audio::Ay8910 { register: AY_REGISTER, data: AY_DATA }),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Audio::Bell => "bell",
+            Audio::Ay8910 { .. } => "ay8910",
+        }
+    }
+}
+
+/// What paces the 60Hz timers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum Timer {
+    #[default]
+    Cycles,          // The estimated T-states of the code, counted at its merge points
+    Ctc { port: u8 },  // Interrupts from channel 0 of a Z80 CTC at this port
+}
+
+impl Timer {
+    /// Look up a timer by its `--timer` name: cycles or ctc:PORT
+    pub fn from_name(name: &str) -> Option<Timer> {
+        if name == "cycles" {
+            return Some(Timer::Cycles);
+        }
+        let port = name.strip_prefix("ctc:")?;
+        let port = match port.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16).ok()?,
+            None => port.parse().ok()?,
+        };
+        Some(Timer::Ctc { port })
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Timer::Cycles => "cycles".to_string(),
+            Timer::Ctc { port } => format!("ctc:0x{:02X}", port),
+        }
+    }
+}
+
+/// What CHIP-8 RAM holds before the game stores anything there. When the
+/// game may read its own bytes, init copies the ROM over it from 0x200, so
+/// this is only what lies past the copy; otherwise it is all of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum RamInit {
+    Zero,
+    Ff,
+    Random(u16),  // Bytes of a 16-bit LFSR started from the seed
+}
+
+impl RamInit {
+    /// Look up a fill by its `--ram-init` name: zero, ff or random(SEED)
+    pub fn from_name(name: &str) -> Option<RamInit> {
+        match name {
+            "zero" => Some(RamInit::Zero),
+            "ff" => Some(RamInit::Ff),
+            _ => {
+                let seed = name.strip_prefix("random(")?.strip_suffix(')')?;
+                let seed = match seed.strip_prefix("0x") {
+                    Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+                    None => seed.parse().ok()?,
+                };
+                Some(RamInit::Random(seed))
+            }
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            RamInit::Zero => "zero".to_string(),
+            RamInit::Ff => "ff".to_string(),
+            RamInit::Random(seed) => format!("random({})", seed),
+        }
+    }
+}
+
+// Last byte of the UTF-8 for each quadrant block, U+2580-U+259F, indexed by
+// its pixels: 1 top left, 2 top right, 4 bottom left, 8 bottom right (an
+// empty one is sent as a space)
+const QUADRANTS: [u8; 16] = [0x00, 0x98, 0x9D, 0x80, 0x96, 0x8C, 0x9E, 0x9B, 0x97, 0x9A, 0x90, 0x9C, 0x84, 0x99, 0x9F, 0x88];
+
+/// Order in which the compiled blocks are placed in ROM
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum Layout {
+    #[default]
+    Source,            // CHIP-8 address order
+    Loops,             // Hottest first, estimated from loop nesting
+    Profile(Profile),  // Hottest first, from measured block counts
+}
+
+/// How far the compiled code may stray from the CHIP-8 program's shape
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum CodegenStyle {
+    #[default]
+    Compact,    // Every optimization, across instructions too
+    Traceable,  // A block per instruction, each behind a marker of its address and opcode
+}
+
+/// Hardware the compiled code runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum Target {
+    #[default]
+    RetroShield,  // ACIA serial console, display drawn on an ANSI terminal
+    Spectrum,     // 48K ZX Spectrum: screen memory display, keyboard matrix
+    Msx,          // MSX cartridge: VDP display, keyboard matrix through the PPI
+    Cpm,          // CP/M .COM program: BDOS console, display on an ANSI terminal
+    Cpc,          // Amstrad CPC disc: mode 1 screen through the Gate Array, keyboard through the PSG
+    Rc2014,       // RC2014 ROM: SIO/2 serial console, display drawn on an ANSI terminal
+    Sms,          // Sega Master System cartridge: VDP tilemap display, control pad keys
+    Coleco,       // ColecoVision cartridge with the Super Game Module: VDP display, keypad
+}
+
+impl Target {
+    /// Look up a target by its `--target` name
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "retroshield" => Some(Target::RetroShield),
+            "spectrum" => Some(Target::Spectrum),
+            "msx" => Some(Target::Msx),
+            "cpm" => Some(Target::Cpm),
+            "cpc" => Some(Target::Cpc),
+            "rc2014" => Some(Target::Rc2014),
+            "sms" => Some(Target::Sms),
+            "coleco" => Some(Target::Coleco),
+            _ => None,
+        }
+    }
+
+    /// Name used by `--target`
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::RetroShield => "retroshield",
+            Target::Spectrum => "spectrum",
+            Target::Msx => "msx",
+            Target::Cpm => "cpm",
+            Target::Cpc => "cpc",
+            Target::Rc2014 => "rc2014",
+            Target::Sms => "sms",
+            Target::Coleco => "coleco",
+        }
+    }
+
+    /// Z80 clock the timers are paced for unless set
+    pub fn clock_hz(self) -> u32 {
+        match self {
+            Target::RetroShield | Target::Cpm => DEFAULT_CLOCK_HZ,
+            Target::Spectrum => SPECTRUM_CLOCK_HZ,
+            Target::Msx => MSX_CLOCK_HZ,
+            Target::Cpc => CPC_CLOCK_HZ,
+            Target::Rc2014 => RC2014_CLOCK_HZ,
+            Target::Sms => SMS_CLOCK_HZ,
+            Target::Coleco => COLECO_CLOCK_HZ,
+        }
+    }
+
+    /// Address the code is linked at unless set: ROM at 0x0000 on the
+    /// RetroShield, RC2014 and Master System, RAM above the BASIC loader on the Spectrum, the
+    /// cartridge slot on the MSX and ColecoVision, the start of the TPA
+    /// under CP/M, RAM above the lower ROM on the CPC
+    pub fn origin(self) -> u16 {
+        match self {
+            Target::RetroShield | Target::Rc2014 | Target::Sms => 0,
+            Target::Spectrum => SPECTRUM_ORIGIN,
+            Target::Msx => MSX_ORIGIN,
+            Target::Cpm => CPM_ORIGIN,
+            Target::Cpc => CPC_ORIGIN,
+            Target::Coleco => COLECO_ORIGIN,
+        }
+    }
+
+    /// Initial Z80 stack pointer (0x0000 pushes into 0xFFFF first), or
+    /// None to start it under the BDOS
+    fn stack_top(self) -> Option<u16> {
+        match self {
+            Target::RetroShield | Target::Spectrum | Target::Rc2014 => Some(0x0000),
+            Target::Msx => Some(MSX_STACK),
+            Target::Cpc => Some(CPC_STACK),
+            Target::Sms => Some(SMS_STACK),
+            Target::Coleco => Some(COLECO_STACK),
+            Target::Cpm => None,
+        }
+    }
+
+    /// RAM the runtime uses, from the CHIP-8 registers to the Z80 stack
+    fn ram(self) -> Range<usize> {
+        match self {
+            Target::Coleco => COLECO_RAM as usize..COLECO_ORIGIN as usize,
+            _ => CHIP8_V0 as usize..0x10000,
+        }
+    }
+
+    /// First CHIP-8 address beyond the RAM the runtime maps
+    fn mem_top(self) -> u16 {
+        match self {
+            Target::Cpm | Target::Cpc | Target::Sms | Target::Coleco => MEM_TOP_4K,
+            _ => MEM_TOP,
+        }
+    }
+}
+
+/// How a DXYN (or the skip between a draw pair) handles the display refresh
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RefreshPlan {
+    Deferred,        // A later draw in the same block refreshes
+    SkipTo(String),  // Skip lands on the pair's shared refresh
+    JoinAt(String),  // Place the shared refresh after this draw
+}
+
+/// Outcome of a register op or skip whose operands are known constants
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Fold {
+    Set(Vec<(u8, u8)>),  // (Register, value) results, in order
+    Skip(bool),          // Whether the skip is taken
+}
+
+/// A display buffer as the terminal refresh walks it
+#[derive(Debug, Clone, Copy)]
+struct Screen {
+    prefix: &'static str,  // Of the refresh routines' labels
+    buffer: u16,
+    row_bytes: u8,
+    height: u8,
+    flags: u16,            // A byte per row, non-zero to resend it
+}
+
+/// Role of a DXYN in a fused erase/redraw sprite move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MovePlan {
+    Erase,   // Only record the old position
+    Redraw,  // Erase the old position and draw the new one via move_sprite
+}
+
+/// Role of an instruction in a key menu folded into one menu_wait call
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MenuPlan {
+    Wait(Vec<(u8, u16)>),  // The FX0A, with the (key, CHIP-8 target) of each entry
+    Ladder,                // A compare or jump of the ladder, not compiled
+}
+
+/// Generated code without ROM padding, plus the resolved symbol table
+#[derive(Debug, Clone)]
+pub struct CompiledCode {
+    pub code: Vec<u8>,                   // Z80 bytes, starting at `origin`
+    pub origin: u16,                     // Z80 address of code[0]
+    pub symbols: BTreeMap<String, u16>,  // Label -> Z80 address
+    pub stats: CodeStats,
+    pub diagnostics: Vec<Diagnostic>,    // Warnings, in the order found
+    pub passes: Vec<PassTime>,           // Compiler passes, in order, when timed
+}
+
+/// Where `compile_single` places a lone instruction: the Z80 address its
+/// translation starts at, and the symbols of the build it joins, for the
+/// runtime routines it calls and the `c8_XXX` blocks it jumps to
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    pub at: u16,
+    pub symbols: BTreeMap<String, u16>,
+}
+
+impl Context {
+    /// A translation placed at `at` in a compiled build
+    pub fn new(code: &CompiledCode, at: u16) -> Context {
+        Context { at, symbols: code.symbols.clone() }
+    }
+}
+
 pub struct Compiler {
     code: Vec<u8>,
     pc: u16,
     labels: HashMap<String, u16>,
     forward_refs: Vec<(u16, String)>,
+    relative_refs: Vec<(u16, String)>,   // JR offset byte -> target label
+    jump_sites: Vec<(u16, String)>,      // Every relaxable jump, in emission order
+    short_jumps: HashMap<usize, String>, // Jump site -> target, for sites emitted as JR
+    entry_regs: HashMap<String, Vec<Option<u16>>>,  // CHIP-8 label -> what A holds on each way in
     chip8_labels: HashMap<u16, String>,  // CHIP-8 addr -> Z80 label
     chip8_rom: Vec<u8>,                  // Original CHIP-8 ROM data
+    name: String,                        // Game name for the embedded build info
+    clock_hz: u32,                       // Z80 clock, for 60Hz timer pacing
+    compact_traps: bool,                 // Print trap codes instead of messages
+    symbol_table: bool,                  // Embed the CHIP-8 address of each compiled instruction
+    display_test: bool,                  // SYS 0NN prints display checksums (the display-test kit)
+    input_test: bool,                    // SYS 0NN reports keys, and get_key prints what it returns (the input-test kit)
+    beeper: Option<Beeper>,              // Sound timer output
+    audio: Option<Audio>,                // Sound timer's other output
+    framebuffer: Option<Framebuffer>,    // Video memory in place of the ANSI display
+    orientation: Orientation,            // Pixel layout of the framebuffer
+    scale: Scale,                        // Pixel size in the framebuffer
+    delta_display: bool,                 // Changed display bytes over serial in place of the ANSI display
+    glyphs: Glyphs,                      // Characters of the ANSI display
+    ram_init: Option<RamInit>,           // Fill of CHIP-8 RAM at startup, left as found if None
+    compress_rom: bool,                  // Embed the ROM run-length encoded, inflated into CHIP-8 RAM at startup
+    rom_data: bool,                      // Embed the ROM, left out when nothing of the last compile reads it
+    key_release_ms: u16,                 // How long a console key stays down after its character
+    rx_buffer: bool,                     // Serial characters go into RX_RING on the ACIA's interrupt
+    acia_ports: (u8, u8),                // The ACIA's control/status and data ports
+    timer: Timer,                        // What paces the 60Hz timers
+    speed: Option<u32>,                  // CHIP-8 instructions a second, as fast as the Z80 goes if None
+    quirks: Quirks,                      // Opcode semantics
+    allow_self_modify: bool,             // Warn instead of failing on stores into code
+    levels: Levels,                      // Diagnostic levels set over the defaults
+    diagnostics: Vec<Diagnostic>,        // Warnings from the last compile
+    denied: Option<Diagnostic>,          // Finding whose error level failed it
+    fallback: Fallback,                  // Handling of code that can't be compiled
+    layout: Layout,                      // Block placement
+    style: CodegenStyle,                 // Optimized, or one block per instruction for hardware traces
+    profile_gen: bool,                   // Count block entries for --profile-use
+    trace: Trace,                        // Print each instruction before it runs
+    debug_stub: bool,                    // Serial debug monitor and single-stepping
+    step_gate: bool,                     // Blocks stop for the host's debug subcommand
+    trim: bool,                          // Leave the free space off the ROM image
+    origin: u16,                         // Z80 address the code is linked to run at
+    memory_map: Option<MemoryMap>,       // Board's own ROM and RAM, over the target's
+    bank: Option<(usize, usize)>,        // Bank number and count in a banked EPROM
+    target: Target,                      // Hardware the runtime drives
+    tstates: u32,                        // Running T-state count of emitted code
+    a_reg: Option<u16>,                  // V register address whose value A holds
+    z_reg: Option<u16>,                  // V register whose zero-ness the Z flag holds
+    v_const: [Option<u8>; 16],           // Known V register values in straight-line code
+    v_dirty: u16,                        // Registers whose constant isn't stored yet
+    refresh_plan: HashMap<u16, RefreshPlan>,  // CHIP-8 addr -> refresh handling
+    move_plan: HashMap<u16, MovePlan>,        // CHIP-8 addr -> sprite move role
+    menu_plan: HashMap<u16, MenuPlan>,        // CHIP-8 addr -> key menu role
+    time_passes: bool,                   // Time each pass of the compile
+    passes: Vec<PassTime>,               // Pass timings of the last compile
+    pad: PadMap,                         // CHIP-8 keys of the Master System pad
+    keymap: KeyMap,                      // CHIP-8 keys of serial characters, and of each player
+    keys_read: u16,                      // Keys tested by SKP and SKNP with a known Vx
+    hires: bool,                         // The ROM switches to S-CHIP's 128x64 mode (00FF) and the display can show it
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compiler {
@@ -47,390 +825,6971 @@ impl Compiler {
             pc: 0,  // Start at 0, not CODE_START
             labels: HashMap::new(),
             forward_refs: Vec::new(),
+            relative_refs: Vec::new(),
+            jump_sites: Vec::new(),
+            short_jumps: HashMap::new(),
+            entry_regs: HashMap::new(),
             chip8_labels: HashMap::new(),
             chip8_rom: Vec::new(),
+            name: String::from("unnamed"),
+            clock_hz: DEFAULT_CLOCK_HZ,
+            compact_traps: false,
+            symbol_table: false,
+            display_test: false,
+            input_test: false,
+            beeper: None,
+            audio: None,
+            framebuffer: None,
+            orientation: Orientation::default(),
+            scale: Scale::default(),
+            delta_display: false,
+            glyphs: Glyphs::Ascii,
+            ram_init: None,
+            compress_rom: false,
+            rom_data: true,
+            key_release_ms: KEY_RELEASE_MS,
+            rx_buffer: false,
+            acia_ports: (ACIA_CTRL, ACIA_DATA),
+            timer: Timer::Cycles,
+            speed: None,
+            quirks: Quirks::default(),
+            allow_self_modify: false,
+            levels: Levels::default(),
+            diagnostics: Vec::new(),
+            denied: None,
+            fallback: Fallback::Trap,
+            layout: Layout::Source,
+            style: CodegenStyle::Compact,
+            profile_gen: false,
+            trace: Trace::Off,
+            debug_stub: false,
+            step_gate: false,
+            trim: false,
+            origin: 0,
+            memory_map: None,
+            bank: None,
+            target: Target::RetroShield,
+            tstates: 0,
+            a_reg: None,
+            z_reg: None,
+            v_const: [None; 16],
+            v_dirty: 0,
+            refresh_plan: HashMap::new(),
+            move_plan: HashMap::new(),
+            menu_plan: HashMap::new(),
+            time_passes: false,
+            passes: Vec::new(),
+            pad: PadMap::default(),
+            keymap: KeyMap::default(),
+            keys_read: 0,
+            hires: false,
         }
     }
 
-    pub fn compile(&mut self, rom: &[u8]) -> Result<Vec<u8>, String> {
-        // Store original ROM for sprite data access
-        self.chip8_rom = rom.to_vec();
+    /// Compile ROMs that store into their own code, with a warning
+    pub fn set_allow_self_modify(&mut self, allow: bool) {
+        self.allow_self_modify = allow;
+    }
 
-        // Parse CHIP-8 instructions
-        let instructions = chip8::parse(rom);
+    /// Set the levels of diagnostic categories, over their defaults
+    pub fn set_levels(&mut self, levels: Levels) {
+        self.levels = levels;
+    }
 
-        // First pass: create labels for all CHIP-8 addresses
-        for inst in &instructions {
-            let label = format!("c8_{:03X}", inst.addr);
-            self.chip8_labels.insert(inst.addr, label);
-        }
+    /// Set the level of one diagnostic category
+    pub fn set_level(&mut self, category: Category, level: Level) {
+        self.levels.set(category, level);
+    }
 
-        // Generate Z80 code
-        self.generate_header();
-        self.generate_init();
-        self.generate_runtime();
+    /// Warnings from the last compile
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
 
-        // Main entry point - jump to first CHIP-8 instruction
-        self.label("main");
-        if !instructions.is_empty() {
-            let first_label = format!("c8_{:03X}", 0x200);
-            self.jp_label(&first_label);
-        } else {
-            self.jp_label("halt");
-        }
+    /// Finding that failed the last compile because its level is error, if
+    /// that is why it failed
+    pub fn denied(&self) -> Option<&Diagnostic> {
+        self.denied.as_ref()
+    }
 
-        // Compile each CHIP-8 instruction
-        for inst in &instructions {
-            let label = format!("c8_{:03X}", inst.addr);
-            self.label(&label);
-            self.compile_instruction(inst)?;
-        }
+    /// Choose what runs code the compiler can't resolve
+    pub fn set_fallback(&mut self, fallback: Fallback) {
+        self.fallback = fallback;
+    }
 
-        // Generate halt
-        self.label("halt");
-        self.emit(0x76);  // HALT
-        self.jp_label("halt");
+    /// Choose how compiled blocks are ordered in ROM
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
 
-        // Embed CHIP-8 ROM data for custom sprite access
-        // This label marks the start of embedded ROM (corresponds to CHIP-8 address 0x200)
-        self.label("chip8_rom_data");
-        for byte in &self.chip8_rom.clone() {
-            self.emit(*byte);
-        }
+    /// Choose between optimized code and code a logic analyzer trace can
+    /// be mapped back to CHIP-8 instructions from
+    pub fn set_codegen_style(&mut self, style: CodegenStyle) {
+        self.style = style;
+    }
 
-        // Resolve forward references
-        self.resolve_refs()?;
+    /// Instrument the program with block counters, dumped with '!'
+    pub fn set_profile_gen(&mut self, profile_gen: bool) {
+        self.profile_gen = profile_gen;
+    }
 
-        // Create 32KB ROM image
-        let mut rom_image = vec![0u8; 32768];
+    /// Print the CHIP-8 address (and opcode) of each compiled instruction
+    /// on the serial console before it runs
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = trace;
+    }
 
-        // Copy code
-        for (i, byte) in self.code.iter().enumerate() {
-            if i < rom_image.len() {
-                rom_image[i] = *byte;
-            }
-        }
+    /// Build in a debug monitor, entered by Ctrl-B on the serial console,
+    /// that shows the CHIP-8 registers, stack and memory and can step
+    /// through the game an instruction at a time
+    pub fn set_debug_stub(&mut self, debug_stub: bool) {
+        self.debug_stub = debug_stub;
+    }
 
-        // Embed font data at FONT_DATA (but in ROM, we mirror at code location)
-        self.embed_font(&mut rom_image);
+    /// Stop at the start of each block, before any of it runs, until the
+    /// host's debug subcommand lets it through, printing the registers for
+    /// it. Needs the debug stub.
+    pub fn set_step_gate(&mut self, step_gate: bool) {
+        self.step_gate = step_gate;
+    }
 
-        Ok(rom_image)
+    /// Record the wall time and heap use of each compiler pass
+    pub fn set_time_passes(&mut self, time_passes: bool) {
+        self.time_passes = time_passes;
     }
 
-    fn generate_header(&mut self) {
-        // RST 0 - entry point
-        self.emit(0xC3);  // JP
-        self.emit16(CODE_START);
+    /// Make `compile` return only the used part of the ROM image
+    pub fn set_trim(&mut self, trim: bool) {
+        self.trim = trim;
+    }
 
-        // Pad to CODE_START
-        while self.pc < CODE_START {
-            self.emit(0x00);
-        }
+    /// Link the code to run from `origin` instead of 0x0000, e.g. above a
+    /// monitor in the same EPROM. The header and ABI table move with it.
+    pub fn set_origin(&mut self, origin: u16) {
+        self.origin = origin;
     }
 
-    fn generate_init(&mut self) {
-        self.label("init");
+    /// Build for other hardware. This also selects the target's clock and
+    /// origin, so set those afterwards to change them.
+    pub fn set_target(&mut self, target: Target) {
+        self.target = target;
+        self.clock_hz = target.clock_hz();
+        self.origin = target.origin();
+    }
 
-        // Initialize stack pointer (at top of RAM, grows downward)
-        self.emit(0x31);  // LD SP, nn
-        self.emit16(0x0000);  // SP = 0x10000 wraps to 0x0000, grows down into 0xFFFF
+    /// Map the Master System pad's buttons onto other CHIP-8 keys
+    pub fn set_pad(&mut self, pad: PadMap) {
+        self.pad = pad;
+    }
 
-        // Initialize ACIA
-        self.call_label("acia_init");
+    /// Map characters typed at the serial or CP/M console onto other
+    /// CHIP-8 keys
+    pub fn set_keymap(&mut self, keymap: KeyMap) {
+        self.keymap = keymap;
+    }
 
-        // Clear CHIP-8 registers
-        self.ld_hl_nn(CHIP8_V0);
-        self.ld_bc_nn(32);  // Clear V0-VF + I + misc
-        self.label("init_clear");
-        self.xor_a();       // A = 0 (must be inside loop!)
-        self.ld_hl_a();
-        self.inc_hl();
-        self.dec_bc();
-        self.ld_a_b();
-        self.or_c();
-        self.jr_nz("init_clear");
+    /// Record the bank this game fills in a banked EPROM, for the build info
+    pub fn set_bank(&mut self, bank: usize, count: usize) {
+        self.bank = Some((bank, count));
+    }
 
-        // Initialize RNG seed
-        self.ld_hl_nn(CHIP8_RNG);
-        self.ld_a_n(0xAC);
-        self.ld_hl_a();
-        self.inc_hl();
-        self.ld_a_n(0xE1);
-        self.ld_hl_a();
+    /// Select opcode semantics for quirk-dependent instructions
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
 
-        // Clear display
-        self.call_label("cls");
+    /// Drive bit `bit` of output port `port` while the sound timer runs
+    pub fn set_beeper(&mut self, port: u8, bit: u8) {
+        self.beeper = Some(Beeper { port, mask: 1 << (bit & 7) });
+    }
 
-        // Copy font to RAM
-        self.call_label("copy_font");
+    /// Sound the sound timer another way: `Audio::Bell` sends BEL to the
+    /// console as each sound starts, at most every quarter second, and
+    /// `Audio::Ay8910` plays a tone on a PSG while the timer runs
+    pub fn set_audio(&mut self, audio: Audio) {
+        self.audio = Some(audio);
+    }
 
-        // Print banner
-        self.call_label("print_banner");
+    /// Copy the display to video memory at `base`, a row every `stride`
+    /// bytes, instead of drawing it on the serial terminal
+    pub fn set_framebuffer(&mut self, base: u16, stride: u16) {
+        self.framebuffer = Some(Framebuffer { base, stride });
+    }
 
-        // Jump to main
-        self.jp_label("main");
+    /// Lay the framebuffer out turned, mirrored or with the bits of each
+    /// byte reversed, as the display module wants it
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
     }
 
-    fn generate_runtime(&mut self) {
-        // ACIA init
-        self.label("acia_init");
-        self.ld_a_n(0x03);  // Master reset
-        self.out_n_a(ACIA_CTRL);
-        self.ld_a_n(0x15);  // 8N1, /16
-        self.out_n_a(ACIA_CTRL);
-        self.ret();
+    /// Draw each display pixel larger in the framebuffer, for modules of
+    /// 128x64 (or 64x128 on their side)
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
 
-        // Print character in A
-        self.label("print_char");
-        self.push_af();
-        self.label("print_wait");
-        self.in_a_n(ACIA_CTRL);
-        self.emit(0xE6); self.emit(0x02);  // AND 2
-        self.jr_z("print_wait");
-        self.pop_af();
-        self.out_n_a(ACIA_DATA);
-        self.ret();
+    /// Send the display over serial as the bytes that changed since the
+    /// last refresh, for the host's delta decoder, instead of as ANSI art
+    pub fn set_delta_display(&mut self, delta_display: bool) {
+        self.delta_display = delta_display;
+    }
 
-        // Print banner
-        self.label("print_banner");
-        self.ld_hl_label("banner_str");
-        self.label("print_str_loop");
-        self.ld_a_hl();
-        self.or_a();
-        self.ret_z();
-        self.call_label("print_char");
-        self.inc_hl();
-        self.jr_label("print_str_loop");
+    /// Draw the ANSI display with `glyphs`, several pixels to a character
+    /// for the block and braille glyphs
+    pub fn set_glyphs(&mut self, glyphs: Glyphs) {
+        self.glyphs = glyphs;
+    }
 
-        // Banner string
-        self.label("banner_str");
-        for b in b"CHIP-8 on Z80\r\n" {
-            self.emit(*b);
+    /// Fill CHIP-8 RAM at startup, for games that expect zeros (or that
+    /// read what an interpreter happened to leave there)
+    pub fn set_ram_init(&mut self, ram_init: RamInit) {
+        self.ram_init = Some(ram_init);
+    }
+
+    /// Embed the CHIP-8 ROM run-length encoded and inflate it into CHIP-8
+    /// RAM at startup, where sprites and FX65 then read it, instead of
+    /// embedding it as it is
+    pub fn set_compress_rom(&mut self, compress_rom: bool) {
+        self.compress_rom = compress_rom;
+    }
+
+    /// How long a console key stays down after its last character, for
+    /// SKP, SKNP and FX0A; 0 holds it a frame and has FX0A take every
+    /// character. Keyboards and pads report the release themselves.
+    pub fn set_key_release(&mut self, ms: u16) {
+        self.key_release_ms = ms;
+    }
+
+    /// Take serial characters on the ACIA's receive interrupt into a ring
+    /// buffer that get_key reads, so none are lost while the runtime is
+    /// busy drawing. RetroShield only, with the code at 0000 for IM 1.
+    pub fn set_rx_buffer(&mut self, rx_buffer: bool) {
+        self.rx_buffer = rx_buffer;
+    }
+
+    /// Talk to the RetroShield's ACIA at these control/status and data
+    /// ports, for boards that decode it somewhere other than 80 and 81
+    pub fn set_acia_ports(&mut self, ctrl: u8, data: u8) {
+        self.acia_ports = (ctrl, data);
+    }
+
+    /// Tick the 60Hz timers from a CTC's interrupt instead of from the
+    /// estimated cycles of the code, which drift as the game's workload
+    /// changes. RetroShield and RC2014 only; the runtime takes IM 2.
+    pub fn set_timer(&mut self, timer: Timer) {
+        self.timer = timer;
+    }
+
+    /// Run at most `ips` CHIP-8 instructions a second: each block counts
+    /// its instructions against a budget for the frame, and waits for the
+    /// next frame once it is spent. Games that don't wait on the delay
+    /// timer are unplayably fast at the Z80's own speed.
+    pub fn set_speed(&mut self, ips: u32) {
+        self.speed = Some(ips);
+    }
+
+    /// Place the code in a board's ROM and the runtime's variables, CHIP-8
+    /// memory and Z80 stack in its RAM, instead of where the target has
+    /// them. This also moves the origin to the start of the ROM.
+    pub fn set_memory_map(&mut self, map: MemoryMap) {
+        self.memory_map = Some(map);
+        self.origin = map.rom.start;
+    }
+
+    /// Print runtime traps as `E<code> <value>` instead of full messages
+    pub fn set_compact_traps(&mut self, compact: bool) {
+        self.compact_traps = compact;
+    }
+
+    /// Embed a table of the Z80 address each CHIP-8 instruction was
+    /// compiled to, so that traps and the debug monitor can name the
+    /// CHIP-8 address behind a Z80 one without a --debug-map
+    pub fn set_symbol_table(&mut self, symbol_table: bool) {
+        self.symbol_table = symbol_table;
+    }
+
+    /// Compile SYS 0NN as display checkpoint NN, printing checksums of the
+    /// display buffer and video memory over serial, for the display-test kit
+    pub fn set_display_test(&mut self, display_test: bool) {
+        self.display_test = display_test;
+    }
+
+    /// Compile SYS 0NN as a report of the input-test kit, and print each
+    /// change in what the key driver's get_key returns
+    pub fn set_input_test(&mut self, input_test: bool) {
+        self.input_test = input_test;
+    }
+
+    /// Set the game name recorded in the embedded build info
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    /// Set the Z80 clock frequency used to pace the delay timer
+    pub fn set_clock_hz(&mut self, hz: u32) {
+        self.clock_hz = hz;
+    }
+
+    /// Compiler options that affect the generated code, as the build info
+    /// and cache key name them
+    pub fn build_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if self.target != Target::RetroShield {
+            options.push(format!("target={}", self.target.name()));
+        }
+        if self.clock_hz != self.target.clock_hz() {
+            options.push(format!("clock={}", self.clock_hz));
+        }
+        if let Some(map) = self.memory_map {
+            options.push(format!("memory-map={}", map.name()));
+        }
+        if self.origin != self.memory_map.map_or(self.target.origin(), |map| map.rom.start) {
+            options.push(format!("at={:04X}", self.origin));
+        }
+        if let Some((bank, count)) = self.bank {
+            options.push(format!("bank={}/{}", bank, count));
+        }
+        if self.pad != PadMap::default() {
+            let keys: String = self.pad.keys.iter().map(|key| key.map_or('-', |key| char::from_digit(key as u32, 16).unwrap())).collect();
+            options.push(format!("pad={}", keys));
+        }
+        if !self.keymap.keys.is_empty() {
+            let pairs: String = self.keymap.keys.iter().map(|(c, key)| format!("{}{}", c.escape_default(), key.map_or('-', |key| char::from_digit(key as u32, 16).unwrap()))).collect();
+            options.push(format!("keymap={}", pairs));
+        }
+        if let Some(players) = &self.keymap.players {
+            let zones: Vec<String> = players.iter().map(|zone| zone.iter().map(|key| char::from_digit(*key as u32, 16).unwrap()).collect()).collect();
+            options.push(format!("players={}", zones.join("/")));
+        }
+        if self.compact_traps {
+            options.push("compact-traps".to_string());
+        }
+        if self.symbol_table {
+            options.push("symbol-table".to_string());
+        }
+        if self.display_test {
+            options.push("display-test".to_string());
+        }
+        if self.input_test {
+            options.push("input-test".to_string());
+        }
+        if let Some(beeper) = self.beeper {
+            options.push(format!("beeper={:02X}:{}", beeper.port, beeper.mask.trailing_zeros()));
+        }
+        if let Some(audio) = self.audio {
+            match audio {
+                Audio::Ay8910 { register, data } => options.push(format!("audio=ay8910:{:02X},{:02X}", register, data)),
+                _ => options.push(format!("audio={}", audio.name())),
+            }
+        }
+        if let Some(framebuffer) = self.framebuffer {
+            options.push(format!("framebuffer={:04X}:{}", framebuffer.base, framebuffer.stride));
+        }
+        if self.orientation != Orientation::default() {
+            options.push(format!("orientation={}", self.orientation.names().join(",")));
+        }
+        if self.scale != Scale::Single {
+            options.push(format!("scale={}", self.scale.name()));
+        }
+        if self.delta_display {
+            options.push("delta-display".to_string());
+        }
+        if self.glyphs != Glyphs::Ascii {
+            options.push(format!("glyphs={}", self.glyphs.name()));
+        }
+        if let Some(ram_init) = self.ram_init {
+            options.push(format!("ram-init={}", ram_init.name()));
+        }
+        if self.compress_rom {
+            options.push("compress-rom".to_string());
+        }
+        if self.key_release_ms != KEY_RELEASE_MS && self.console_keys() {
+            options.push(format!("key-release={}", self.key_release_ms));
+        }
+        if self.rx_buffer {
+            options.push("rx-buffer".to_string());
+        }
+        if self.acia_ports != (ACIA_CTRL, ACIA_DATA) {
+            options.push(format!("acia-ports={:02X},{:02X}", self.acia_ports.0, self.acia_ports.1));
+        }
+        if let Timer::Ctc { port } = self.timer {
+            options.push(format!("timer=ctc:{:02X}", port));
+        }
+        if let Some(ips) = self.speed {
+            options.push(format!("speed={}", ips));
+        }
+        if self.fallback == Fallback::Interp {
+            options.push("fallback=interp".to_string());
+        }
+        match self.layout {
+            Layout::Source => {}
+            Layout::Loops => options.push("layout=loops".to_string()),
+            Layout::Profile(_) => options.push("layout=profile".to_string()),
+        }
+        if self.style == CodegenStyle::Traceable {
+            options.push("codegen-style=traceable".to_string());
+        }
+        if self.profile_gen {
+            options.push("profile-gen".to_string());
+        }
+        match self.trace {
+            Trace::Off => {}
+            Trace::Pc => options.push("trace".to_string()),
+            Trace::Opcode => options.push("trace=opcode".to_string()),
+        }
+        if self.debug_stub {
+            options.push("debug-stub".to_string());
+        }
+        if self.step_gate {
+            options.push("step-gate".to_string());
+        }
+        for quirk in self.quirks.names() {
+            options.push(format!("quirk:{}", quirk));
+        }
+        options
+    }
+
+    /// Timer tick units per 60Hz frame
+    fn ticks_per_frame(&self) -> u16 {
+        (self.clock_hz / 60 / TICK_UNIT).clamp(1, 0xFFFF) as u16
+    }
+
+    /// What a frame of --speed may spend, where each instruction costs 60:
+    /// the speed in instructions a second, so a frame runs a 60th of them
+    fn pace_budget(&self) -> Result<u16, String> {
+        match self.speed.unwrap_or(0) {
+            0 => Err("--speed 0 would never run an instruction".to_string()),
+            ips => u16::try_from(ips).map_err(|_| format!("--speed {} is over {} instructions a second", ips, u16::MAX)),
+        }
+    }
+
+    /// CTC interrupts per 60Hz frame, and the time constant that spaces
+    /// them (0 for 256), as near 60Hz as a prescaler of 256 comes
+    fn ctc_rate(&self) -> Result<(u8, u8), String> {
+        let (clock, frame) = (self.clock_hz as u64, CTC_PRESCALE as u64 * 60);
+        let interrupts = (clock + frame * 256 - 1) / (frame * 256);
+        let constant = (clock + frame * interrupts / 2) / (frame * interrupts);
+        if constant == 0 || interrupts > 0xFF {
+            return Err(format!("a CTC can't tick 60Hz from a {}Hz clock", self.clock_hz));
+        }
+        Ok((interrupts as u8, constant as u8))
+    }
+
+    /// Build metadata printed by the runtime '?' serial command
+    fn build_info(&self) -> String {
+        let options = self.build_options();
+        let options = if options.is_empty() {
+            "default".to_string()
+        } else {
+            options.join(" ")
+        };
+        format!(
+            "kz80_chip8 v{}\r\nGame: {} ({} bytes)\r\nOptions: {}\r\n",
+            env!("CARGO_PKG_VERSION"),
+            self.name,
+            self.chip8_rom.len(),
+            options
+        )
+    }
+
+    /// Compile a ROM to a padded 32KB ROM image (or just its used prefix
+    /// when trimming). MSX builds are a 16KB cartridge image instead,
+    /// ColecoVision builds the 32KB cartridge at 0x8000, Master System
+    /// builds a 32KB cartridge with its header, and CP/M and CPC builds just
+    /// the code, which loads into RAM.
+    pub fn compile(&mut self, rom: &[u8]) -> Result<Vec<u8>, String> {
+        let compiled = self.compile_code(rom)?;
+        Ok(self.image(compiled))
+    }
+
+    /// The image `compile` makes from code compiled with these options
+    pub fn image(&self, compiled: CompiledCode) -> Vec<u8> {
+        match self.target {
+            Target::Msx => {
+                let mut cartridge = compiled.code;
+                if !self.trim {
+                    cartridge.resize(MSX_CARTRIDGE_SIZE, 0xFF);
+                }
+                return cartridge;
+            }
+            Target::Coleco => {
+                let mut cartridge = compiled.code;
+                if !self.trim {
+                    cartridge.resize(COLECO_CARTRIDGE_SIZE, 0xFF);
+                }
+                return cartridge;
+            }
+            Target::Sms => return sms::rom(&compiled.code),
+            Target::Cpm | Target::Cpc => return compiled.code,
+            _ => {}
+        }
+
+        // Create 32KB ROM image, or one the size of a memory map's ROM
+        let size = match (self.trim, self.memory_map) {
+            (true, _) => compiled.origin as usize + compiled.code.len(),
+            (false, Some(map)) => map.rom.range().end,
+            (false, None) => 32768,
+        };
+        let mut rom_image = vec![0u8; size];
+
+        // Copy code
+        for (i, byte) in compiled.code.iter().enumerate() {
+            let addr = compiled.origin as usize + i;
+            if addr < rom_image.len() {
+                rom_image[addr] = *byte;
+            }
+        }
+
+        // Embed font data at FONT_DATA (but in ROM, we mirror at code location)
+        self.embed_font(&mut rom_image);
+
+        rom_image
+    }
+
+    /// Hash of a ROM with everything that shapes the code compiled from it
+    /// and the warnings reported, for the compilation cache. Packaging
+    /// options such as trimming aren't included.
+    #[cfg(feature = "config")]
+    pub fn cache_key(&self, rom: &[u8]) -> u64 {
+        let mut key = format!("{}\n{}\n{}\n", env!("CARGO_PKG_VERSION"), self.name, self.build_options().join(" "));
+        for category in Category::ALL {
+            key += &format!("{}={:?}\n", category.name(), self.level(category));
+        }
+        if let Layout::Profile(profile) = &self.layout {
+            key += &format!("{:?}\n", profile.counts);
+        }
+        let mut bytes = key.into_bytes();
+        bytes.extend_from_slice(rom);
+        crate::fnv::hash(&bytes)
+    }
+
+    /// Compile one CHIP-8 instruction on its own, to the Z80 bytes it
+    /// becomes at `context.at`. Nothing is known of the registers going in,
+    /// every register is in memory coming out, and the code falls through
+    /// at its end as into the next instruction's block; the timer tick of a
+    /// block is left to the caller. Jumps, calls and skips need the target's
+    /// block among the context's symbols, and the compiler's settings should
+    /// be those the build was compiled with.
+    pub fn compile_single(&mut self, inst: &Instruction, context: &Context) -> Result<Vec<u8>, String> {
+        self.chip8_labels = context.symbols.keys()
+            .filter_map(|name| Some((u16::from_str_radix(name.strip_prefix("c8_")?, 16).ok()?, name.clone())))
+            .collect();
+        self.refresh_plan.clear();
+        self.move_plan.clear();
+        self.menu_plan.clear();
+        let origin = std::mem::replace(&mut self.origin, context.at);
+        let code = self.translate_single(inst, context);
+        self.origin = origin;
+        code
+    }
+
+    /// T-states of the code `compile_single` last gave, with branches not
+    /// taken, for weighing the timer tick of a block of them
+    #[cfg(all(feature = "emulator", feature = "interp"))]
+    pub(crate) fn single_tstates(&self) -> u32 {
+        self.tstates
+    }
+
+    /// The passes of `compile_single`, with the origin at `context.at`
+    fn translate_single(&mut self, inst: &Instruction, context: &Context) -> Result<Vec<u8>, String> {
+        // Twice, as for a whole ROM: the second time with a JR wherever
+        // the first found the target in range
+        self.short_jumps.clear();
+        for pass in 0..2 {
+            if pass == 1 {
+                self.short_jumps = self.jumps_in_range();
+            }
+            self.code.clear();
+            self.pc = context.at;
+            self.labels = context.symbols.iter().map(|(name, &addr)| (name.clone(), addr)).collect();
+            self.forward_refs.clear();
+            self.relative_refs.clear();
+            self.jump_sites.clear();
+            self.entry_regs.clear();
+            self.tstates = 0;
+            self.a_reg = None;
+            self.z_reg = None;
+            self.v_const = [None; 16];
+            self.v_dirty = 0;
+            self.compile_instruction(inst)?;
+            self.flush_consts();
+        }
+        self.resolve_refs()?;
+        Ok(self.code.clone())
+    }
+
+    /// Compile a ROM to just the generated code and its symbol table,
+    /// without padding it out to a ROM image
+    pub fn compile_code(&mut self, rom: &[u8]) -> Result<CompiledCode, String> {
+        self.denied = None;
+        if self.fallback == Fallback::Interp && !cfg!(feature = "interp") {
+            return Err("--fallback interp embeds the CHIP-8 interpreter, and this kz80_chip8 is built without it (the interp feature)".to_string());
+        }
+        if self.profile_gen && self.target != Target::RetroShield {
+            return Err(format!("--profile-gen needs the RetroShield's serial console and RAM at {:04X}, not a {} build", PROFILE_COUNTERS, self.target.name()));
+        }
+        if self.trace != Trace::Off && !matches!(self.target, Target::RetroShield | Target::Rc2014 | Target::Cpm) {
+            return Err(format!("--trace prints on a serial console, which a {} build doesn't have", self.target.name()));
+        }
+        if self.debug_stub && !matches!(self.target, Target::RetroShield | Target::Rc2014 | Target::Cpm) {
+            return Err(format!("--debug-stub talks over a serial console, which a {} build doesn't have", self.target.name()));
+        }
+        if self.step_gate {
+            if !self.debug_stub {
+                return Err("--step-gate stops in the debug stub, which isn't built in".to_string());
+            }
+            if !matches!(self.target, Target::RetroShield | Target::Rc2014) {
+                return Err(format!("--step-gate polls the RetroShield or RC2014 serial port, which a {} build doesn't have", self.target.name()));
+            }
+        }
+        if self.display_test {
+            if !matches!(self.target, Target::RetroShield | Target::Rc2014) {
+                return Err(format!("the display test prints over the RetroShield or RC2014 serial port, which a {} build doesn't have", self.target.name()));
+            }
+            if self.delta_display {
+                return Err("the display test's checksum lines would break into the --delta-display bytes".to_string());
+            }
+            if self.input_test {
+                return Err("the display and input tests are kits of their own; build one at a time".to_string());
+            }
+        }
+        if self.input_test {
+            if !matches!(self.target, Target::RetroShield | Target::Rc2014 | Target::Cpm) {
+                return Err(format!("the input test prints over a serial port or CP/M console, which a {} build doesn't have", self.target.name()));
+            }
+            if self.delta_display {
+                return Err("the input test's lines would break into the --delta-display bytes".to_string());
+            }
+        }
+        if self.delta_display {
+            if !matches!(self.target, Target::RetroShield | Target::Rc2014) {
+                return Err(format!("--delta-display replaces the ANSI display of the RetroShield and RC2014, not a {} build's", self.target.name()));
+            }
+            if self.framebuffer.is_some() {
+                return Err("--delta-display and --framebuffer both replace the ANSI display; pick one".to_string());
+            }
+            if self.profile_gen {
+                return Err(format!("--delta-display keeps its copy of the display at {:04X}, where --profile-gen keeps its counters", DELTA_SHADOW));
+            }
+        }
+        if !self.keymap.is_empty() {
+            if !self.keymap.keys.is_empty() && !matches!(self.target, Target::RetroShield | Target::Rc2014 | Target::Cpm) {
+                return Err(format!("--keymap maps characters typed at a serial or CP/M console; a {} build reads its own keys", self.target.name()));
+            }
+            if self.keymap.players.is_some() && matches!(self.target, Target::Sms | Target::Coleco) {
+                return Err(format!("--keymap players need a keyboard; a {} build reads one controller", self.target.name()));
+            }
+            self.keymap.check()?;
+            if self.profile_gen && self.keymap.keys.contains_key(&'!') {
+                return Err("'!' dumps the counters of a --profile-gen build; it can't be a key".to_string());
+            }
+        }
+        if self.glyphs != Glyphs::Ascii && !self.dirty_rows() {
+            return Err(format!("--glyphs {} draws the ANSI display, which this build doesn't have", self.glyphs.name()));
+        }
+        if let Some(framebuffer) = self.framebuffer {
+            if !matches!(self.target, Target::RetroShield | Target::Rc2014) {
+                return Err(format!("--framebuffer replaces the ANSI display of the RetroShield and RC2014, not a {} build's", self.target.name()));
+            }
+            self.orientation.check()?;
+            let row_bytes = self.orientation.row_bytes() * self.scale.factor();
+            if (framebuffer.stride as usize) < row_bytes {
+                return Err(format!("framebuffer stride {} is shorter than a row's {} bytes", framebuffer.stride, row_bytes));
+            }
+            let span = framebuffer.span(self.orientation, self.scale);
+            let ram = self.ram_area();
+            if span.start < ram.end && ram.start < span.end {
+                return Err(format!("framebuffer at {:04X} runs to {:04X}, into RAM at {:04X}", span.start, span.end - 1, ram.start));
+            }
+        } else if self.orientation != Orientation::default() {
+            return Err("--orientation lays out --framebuffer video memory, which this build doesn't have".to_string());
+        } else if self.scale != Scale::Single {
+            return Err(format!("--scale {} draws --framebuffer video memory larger, which this build doesn't have", self.scale.name()));
+        }
+        if self.audio == Some(Audio::Bell) {
+            if !matches!(self.target, Target::RetroShield | Target::Rc2014 | Target::Cpm) {
+                return Err(format!("--audio bell rings a serial or CP/M console's terminal, which a {} build doesn't have", self.target.name()));
+            }
+            if self.delta_display {
+                return Err("--audio bell would put BEL among the --delta-display bytes".to_string());
+            }
+        }
+        if self.acia_ports != (ACIA_CTRL, ACIA_DATA) {
+            if self.target != Target::RetroShield {
+                return Err(format!("--acia-ports moves the RetroShield's ACIA, which a {} build doesn't have", self.target.name()));
+            }
+            if self.acia_ports.0 == self.acia_ports.1 {
+                return Err(format!("the ACIA's control and data ports are both {:02X}", self.acia_ports.0));
+            }
+        }
+        if self.rx_buffer {
+            if self.target != Target::RetroShield {
+                return Err(format!("--rx-buffer takes the RetroShield ACIA's interrupt, which a {} build doesn't have", self.target.name()));
+            }
+            if self.origin != 0 {
+                return Err(format!("--rx-buffer needs the code at 0000, where IM 1 finds its handler at {:04X}", RX_VECTOR));
+            }
+        }
+        if self.timer != Timer::Cycles {
+            if !matches!(self.target, Target::RetroShield | Target::Rc2014) {
+                return Err(format!("--timer ctc takes IM 2 for a CTC on a RetroShield or RC2014 board, not a {} build", self.target.name()));
+            }
+            if self.rx_buffer {
+                return Err("--timer ctc takes IM 2, which leaves --rx-buffer's ACIA interrupt without a handler".to_string());
+            }
+            if self.audio == Some(Audio::Bell) {
+                return Err("--timer ctc would ring --audio bell from its interrupt, among whatever the console is printing".to_string());
+            }
+            if (self.origin + CTC_TABLE) % 8 != 0 {
+                return Err(format!("--timer ctc needs the code at a multiple of 8, for the CTC's vector to {:04X}", self.origin + CTC_TABLE));
+            }
+            self.ctc_rate()?;
+        }
+        if self.speed.is_some() {
+            self.pace_budget()?;
+        }
+        if self.style == CodegenStyle::Traceable && self.layout != Layout::Source {
+            return Err("--codegen-style traceable keeps the blocks in CHIP-8 address order, so it can't take a hot --layout".to_string());
+        }
+        if let Some(map) = self.memory_map {
+            if !matches!(self.target, Target::RetroShield | Target::Rc2014) {
+                return Err(format!("--memory-map places the RetroShield or RC2014 runtime; a {} build has its own", self.target.name()));
+            }
+            map.check()?;
+            if !map.rom.range().contains(&(self.origin as usize)) {
+                return Err(format!("code at {:04X} is outside the ROM at {:04X}-{:04X}", self.origin, map.rom.start, map.rom.end));
+            }
+            if self.profile_gen {
+                return Err(format!("--profile-gen keeps its counters at {:04X}, not in the --memory-map RAM", PROFILE_COUNTERS));
+            }
+            if self.delta_display {
+                return Err(format!("--delta-display keeps its copy of the display at {:04X}, not in the --memory-map RAM", DELTA_SHADOW));
+            }
+        }
+        match self.target {
+            Target::Spectrum if self.origin < SPECTRUM_LOWEST => {
+                return Err(format!("spectrum code must be at {:04X} or above, clear of BASIC", SPECTRUM_LOWEST));
+            }
+            Target::Msx if self.origin != MSX_ORIGIN => {
+                return Err(format!("msx cartridges start at {:04X}", MSX_ORIGIN));
+            }
+            Target::Cpm if self.origin != CPM_ORIGIN => {
+                return Err(format!("cpm programs load at {:04X}", CPM_ORIGIN));
+            }
+            Target::Cpc if self.origin < CPC_ORIGIN => {
+                return Err(format!("cpc code must be at {:04X} or above, clear of the lower ROM", CPC_ORIGIN));
+            }
+            Target::Coleco if self.origin != COLECO_ORIGIN => {
+                return Err(format!("coleco cartridges start at {:04X}", COLECO_ORIGIN));
+            }
+            Target::Sms if self.origin != 0 => {
+                return Err("sms cartridges start at 0000".to_string());
+            }
+            _ => {}
+        }
+        self.chip8_labels.clear();
+        self.entry_regs.clear();
+        self.diagnostics.clear();
+        self.passes.clear();
+
+        // Store original ROM for sprite data access
+        self.chip8_rom = rom.to_vec();
+
+        // Split the ROM into code and data, then compile only the code
+        let pass = self.pass_start();
+        let map = analysis::classify(rom);
+        let discovered = map.instructions.clone();
+        // Only a ROM that turns hires on gets the buffer and the routines
+        self.hires = self.dirty_rows() && discovered.iter().any(|inst| inst.opcode == 0x00FF);
+        let ram = (self.mem_top() - 0x200) as usize;
+        if self.fallback == Fallback::Interp && rom.len() > ram {
+            return Err(format!(
+                "the interpreter's copy of the {} byte ROM doesn't fit the {} bytes of CHIP-8 RAM",
+                rom.len(), ram
+            ));
+        }
+        if self.compress_rom && rom.len() > ram {
+            return Err(format!("--compress-rom inflates the {} byte ROM into the {} bytes of CHIP-8 RAM, which it doesn't fit", rom.len(), ram));
+        }
+
+        // Stores into the program can't be honoured by compiled code.
+        // With the interpreter fallback the overwritten instructions are
+        // left to the interpreter instead.
+        let mut dynamic = HashSet::new();
+        for write in &map.code_writes {
+            let message = format!(
+                "{:03X}: stores {} byte(s) at {:03X}, overwriting code (self-modifying ROM)",
+                write.at, write.len, write.target
+            );
+            if self.fallback == Fallback::Interp {
+                let written = write.target..write.target + write.len;
+                for inst in &discovered {
+                    if written.start < inst.addr + inst.size() && inst.addr < written.end {
+                        dynamic.insert(inst.addr);
+                    }
+                }
+                self.diagnose(Category::SelfModify, format!("{}; interpreting it", message))?;
+            } else {
+                self.diagnose(Category::SelfModify, message)?;
+            }
+        }
+        for read in &map.uninit_reads {
+            if self.quirks.display_ram && read.source >= 0xF00 && read.source + read.len <= 0x1000 {
+                continue;  // The display, not RAM
+            }
+            let inst = discovered.iter().find(|inst| inst.addr == read.at).expect("read from discovered code");
+            self.diagnose(
+                Category::UninitRead,
+                format!(
+                    "{:03X}: {} may read {:03X}-{:03X}, which neither the font, the ROM nor any store fills",
+                    read.at,
+                    chip8::disasm_instruction(inst),
+                    read.source,
+                    read.source + read.len - 1
+                ),
+            )?;
+        }
+        let instructions: Vec<Instruction> =
+            discovered.iter().copied().filter(|inst| !dynamic.contains(&inst.addr)).collect();
+        self.rom_data = self.reads_rom_data(&discovered);
+        if self.rom_data && rom.len() > ram {
+            return Err(format!(
+                "the game reads its {} byte ROM from CHIP-8 RAM, but the ROM doesn't fit the {} bytes of it",
+                rom.len(), ram
+            ));
+        }
+
+        // First pass: create labels for all CHIP-8 addresses
+        for inst in &discovered {
+            let label = if dynamic.contains(&inst.addr) {
+                format!("interp_{:03X}", inst.addr)
+            } else {
+                format!("c8_{:03X}", inst.addr)
+            };
+            self.chip8_labels.insert(inst.addr, label);
+        }
+        self.pass_end("discover", pass);
+
+        // Generate the code twice: first with every relative jump as a JP,
+        // then with a JR wherever the first pass found the target in range.
+        // Shrinking jumps only brings targets closer, so each JR picked from
+        // the first pass still reaches in the second.
+        self.short_jumps.clear();
+        self.generate(&map, &discovered, &dynamic, &instructions)?;
+        let pass = self.pass_start();
+        self.short_jumps = self.jumps_in_range();
+        self.pass_end("relax", pass);
+        let stats = self.generate(&map, &discovered, &dynamic, &instructions)?;
+        let (limit, what) = self.code_limit();
+        if self.origin as usize + self.code.len() > limit {
+            let embedded = self.embedded_rom().len();
+            return Err(format!(
+                "generated {} bytes, but a {} build holds {} (from {:04X} to {} at {:04X}): \
+                 {} bytes of runtime, {} of compiled code and {} of embedded ROM",
+                self.code.len(), self.target.name(), limit.saturating_sub(self.origin as usize), self.origin, what, limit,
+                self.code.len() - stats.program_bytes - embedded, stats.program_bytes, embedded
+            ));
+        }
+        let ram = self.ram_area();
+        if self.origin as usize + self.code.len() > ram.start && (self.origin as usize) < ram.end {
+            return Err(format!(
+                "code at {:04X} is {} bytes and runs into RAM at {:04X}",
+                self.origin, self.code.len(), ram.start
+            ));
+        }
+        if let Some(framebuffer) = self.framebuffer {
+            let span = framebuffer.span(self.orientation, self.scale);
+            if (self.origin as usize) < span.end && span.start < self.origin as usize + self.code.len() {
+                return Err(format!(
+                    "framebuffer at {:04X}-{:04X} overlaps the code at {:04X}-{:04X}",
+                    span.start, span.end - 1, self.origin, self.origin as usize + self.code.len() - 1
+                ));
+            }
+        }
+
+        Ok(CompiledCode {
+            code: self.code.clone(),
+            origin: self.origin,
+            symbols: self.labels.iter().map(|(name, addr)| (name.clone(), *addr)).collect(),
+            stats,
+            diagnostics: self.diagnostics.clone(),
+            passes: self.passes.clone(),
+        })
+    }
+
+    /// VDP data and control ports: the MSX's, or the ColecoVision's
+    fn vdp_ports(&self) -> (u8, u8) {
+        match self.target {
+            Target::Coleco => (COLECO_VDP_DATA, COLECO_VDP_CTRL),
+            _ => (VDP_DATA, VDP_CTRL),
+        }
+    }
+
+    /// Address of a runtime variable (given with RAM at 0x8000) on the target
+    pub(crate) fn ram(&self, addr: u16) -> u16 {
+        addr - CHIP8_V0 + self.ram_area().start as u16
+    }
+
+    /// First address past the room for the code, and what's there: the end
+    /// of the cartridge or memory-mapped ROM, the Master System header, or
+    /// RAM above the code
+    fn code_limit(&self) -> (usize, &'static str) {
+        if let Some(map) = self.memory_map {
+            return (map.rom.range().end, "the end of ROM");
+        }
+        let ram = self.ram_area();
+        match self.target {
+            Target::Sms => (sms::HEADER, "the cartridge header"),
+            Target::Msx => (MSX_ORIGIN as usize + MSX_CARTRIDGE_SIZE, "the end of the cartridge"),
+            Target::Coleco => (COLECO_ORIGIN as usize + COLECO_CARTRIDGE_SIZE, "the end of the cartridge"),
+            _ if (self.origin as usize) < ram.start => (ram.start, "RAM"),
+            _ => (0x10000, "the end of memory"),
+        }
+    }
+
+    /// RAM the runtime uses: the memory map's, or else the target's
+    fn ram_area(&self) -> Range<usize> {
+        match self.memory_map {
+            Some(map) => map.ram.range(),
+            None => self.target.ram(),
+        }
+    }
+
+    /// First CHIP-8 address beyond the RAM the runtime maps: as much of a
+    /// memory map's RAM as leaves room for the Z80 stack, up to MEM_TOP.
+    /// A hires build keeps its buffer after it, out of that RAM unless the
+    /// target maps only CHIP-8's own 4KB.
+    fn mem_top(&self) -> u16 {
+        let hires = if self.hires { HIRES_SIZE } else { 0 };
+        let Some(map) = self.memory_map else {
+            let top = self.target.mem_top();
+            return if top == MEM_TOP { top - hires } else { top };
+        };
+        let free = map.ram.range().end - memmap::STACK_ROOM as usize - self.ram(CHIP8_RAM) as usize - hires as usize;
+        ((0x200 + free as u16) & 0xFF00).min(MEM_TOP)
+    }
+
+    /// Z80 address of the 128x64 hires display buffer, just past CHIP-8 RAM
+    fn hires_buf(&self) -> u16 {
+        self.ram(CHIP8_RAM) + self.mem_top() - 0x200
+    }
+
+    /// Initial Z80 stack pointer: the top of a memory map's RAM, or else
+    /// the target's
+    fn stack_top(&self) -> Option<u16> {
+        match self.memory_map {
+            Some(map) => Some(map.ram.end.wrapping_add(1)),
+            None => self.target.stack_top(),
+        }
+    }
+
+    /// Whether the display goes to an ANSI terminal, which redraws only the
+    /// rows marked dirty
+    fn dirty_rows(&self) -> bool {
+        self.framebuffer.is_none() && !self.delta_display && matches!(self.target, Target::RetroShield | Target::Rc2014 | Target::Cpm)
+    }
+
+    /// Whether keys are characters typed at a serial or CP/M console
+    fn console_keys(&self) -> bool {
+        matches!(self.target, Target::RetroShield | Target::Rc2014 | Target::Cpm)
+    }
+
+    /// Routine redrawing the display after draw_sprite: just the rows it
+    /// marked, where rows are tracked
+    fn refresh_drawn(&self) -> &'static str {
+        if self.dirty_rows() { "refresh_rows" } else { "refresh_display" }
+    }
+
+    /// Start timing a pass, if passes are timed
+    fn pass_start(&self) -> Option<PassStart> {
+        self.time_passes.then(PassStart::now)
+    }
+
+    /// Finish timing the pass started at `start`
+    fn pass_end(&mut self, name: &'static str, start: Option<PassStart>) {
+        if let Some(start) = start {
+            timing::record(&mut self.passes, name, start);
+        }
+    }
+
+    /// Level of a diagnostic category: as set, or else its default.
+    /// Stores into code only warn when the interpreter runs the overwritten
+    /// code, or when asked to compile them anyway.
+    fn level(&self, category: Category) -> Level {
+        match self.levels.get(category) {
+            Some(level) => level,
+            None if category == Category::SelfModify && (self.allow_self_modify || self.fallback == Fallback::Interp) => {
+                Level::Warn
+            }
+            None => category.default_level(),
+        }
+    }
+
+    /// Report a finding at its category's level: kept as a warning, or
+    /// failing the compile
+    fn diagnose(&mut self, category: Category, message: String) -> Result<(), String> {
+        match self.level(category) {
+            Level::Allow => Ok(()),
+            Level::Warn => {
+                let diagnostic = Diagnostic { category, message };
+                if !self.diagnostics.contains(&diagnostic) {
+                    self.diagnostics.push(diagnostic);  // Once, though relaxation compiles twice
+                }
+                Ok(())
+            }
+            Level::Error => {
+                let error = match self.levels.get(category) {
+                    None if category == Category::SelfModify => format!("{}; use --allow-self-modify or --fallback interp", message),
+                    _ => format!("{} [{}=error]", message, category.name()),
+                };
+                self.denied = Some(Diagnostic { category, message });
+                Err(error)
+            }
+        }
+    }
+
+    /// Test the key in Vx for SKP and SKNP, leaving Z set if it is down
+    fn test_key(&mut self, x: u8) {
+        if let Some(key) = self.v_const[x as usize].filter(|&key| key < 16) {
+            self.keys_read |= 1 << key;
+        }
+        if self.tests_each_key() {
+            self.ld_a_mem(self.ram(CHIP8_V0) + x as u16);
+            self.call_label("key_down");
+            if self.console_keys() {
+                self.tstates += KEY_DOWN_TSTATES;  // Keeps the timers and keys aging on time
+            }
+        } else {
+            self.call_label("get_key");
+            self.ld_hl_nn(self.ram(CHIP8_V0) + x as u16);
+            self.cp_hl();
+        }
+    }
+
+    /// Report a ROM testing keys of both players where a console sends
+    /// one key at a time
+    fn check_players(&mut self) -> Result<(), String> {
+        let Some(players) = self.keymap.players.clone().filter(|_| self.console_keys()) else {
+            return Ok(());
+        };
+        let read: Vec<Vec<u8>> = players.iter().map(|zone| zone.iter().copied().filter(|key| self.keys_read & 1 << key != 0).collect()).collect();
+        if read.iter().any(|keys| keys.is_empty()) {
+            return Ok(());
+        }
+        let names: Vec<String> = read.iter().map(|keys| keys.iter().map(|key| format!("{:X}", key)).collect::<Vec<_>>().join(" ")).collect();
+        self.diagnose(
+            Category::TwoPlayer,
+            format!("keys of both players are read (player 1: {}, player 2: {}), but a {} console sends one key at a time, so the players take turns", names[0], names[1], self.target.name()),
+        )
+    }
+
+    /// Emit the runtime and the compiled program, returning its statistics
+    fn generate(
+        &mut self,
+        map: &RomMap,
+        discovered: &[Instruction],
+        dynamic: &HashSet<u16>,
+        instructions: &[Instruction],
+    ) -> Result<CodeStats, String> {
+        self.code.clear();
+        self.pc = self.origin;
+        // Where the last pass put each label, and what A held on the ways
+        // into each block, to carry A into the hot ones
+        let last_labels = std::mem::take(&mut self.labels);
+        let last_entries = std::mem::take(&mut self.entry_regs);
+        self.forward_refs.clear();
+        self.relative_refs.clear();
+        self.jump_sites.clear();
+        self.tstates = 0;
+        self.a_reg = None;
+        self.z_reg = None;
+        self.v_const = [None; 16];
+        self.v_dirty = 0;
+        self.keys_read = 0;
+
+        // Generate Z80 code
+        let pass = self.pass_start();
+        self.generate_header();
+        self.generate_init();
+        self.generate_runtime();
+
+        // Main entry point - jump to first CHIP-8 instruction
+        self.label("main");
+        if let Some(first_label) = self.chip8_labels.get(&0x200).cloned() {
+            self.jp_label(&first_label);
+        } else {
+            self.jp_label("halt");
+        }
+        self.pass_end("runtime", pass);
+
+        // Compile each CHIP-8 instruction. Code between merge points runs
+        // straight through, so the A register and flags can be carried
+        // from one instruction to the next; the timer is ticked once per
+        // merge point, weighted by the cycles of the code that follows.
+        // Traceable code makes every instruction a merge point of its own,
+        // which leaves nothing to carry or reorder between them.
+        let pass = self.pass_start();
+        let traceable = self.style == CodegenStyle::Traceable;
+        let mut merges = self.merge_points(discovered);
+        for inst in discovered.iter().filter(|inst| dynamic.contains(&inst.addr)) {
+            merges.insert(inst.addr + inst.size());  // Interpreter hands back here
+        }
+        if traceable {
+            merges.extend(instructions.iter().map(|inst| inst.addr));
+        }
+        let scheduled = self.schedule(instructions, &merges);
+        self.pass_end("schedule", pass);
+        let pass = self.pass_start();
+        self.refresh_plan = self.plan_refreshes(instructions, &merges);
+        self.pass_end("plan refreshes", pass);
+        let pass = self.pass_start();
+        // Moves are planned for the 64x32 draw_sprite alone
+        self.move_plan = if self.hires { HashMap::new() } else { self.plan_moves(instructions, &merges) };
+        self.pass_end("plan moves", pass);
+        let pass = self.pass_start();
+        self.menu_plan = if traceable { HashMap::new() } else { self.plan_menus(&scheduled) };
+        self.pass_end("plan menus", pass);
+        let pass = self.pass_start();
+        let blocks = Self::blocks(instructions, &merges);
+        let profile = self.layout_profile(instructions);
+        let order = Self::block_order(instructions, &blocks, profile.as_ref());
+        let hot = Self::hot_blocks(instructions, &blocks, profile.as_ref());
+        let carried = self.carried_registers(&hot, &last_labels, &last_entries);
+        self.pass_end("layout", pass);
+        let pass = self.pass_start();
+        let program_start = self.code.len();
+        let program_tstates = self.tstates;
+        let mut weight_at: Option<(usize, u32)> = None;
+        let (mut pace_at, mut paced): (Option<usize>, u32) = (None, 0);
+        let counters: Vec<u16> = blocks.iter().map(|block| instructions[block.start].addr).collect();
+        let mut falls_in = false;  // Whether the code compiled last runs on into the next label
+        if self.profile_gen && counters.len() * 4 > (PROFILE_END - PROFILE_COUNTERS) as usize {
+            return Err(format!(
+                "{} blocks is too many to profile (at most {})",
+                counters.len(),
+                (PROFILE_END - PROFILE_COUNTERS) / 4
+            ));
+        }
+        // Labels and ticks stay in address order; a moved op takes the slot
+        // (and label) of the instruction it was moved ahead of
+        for (n, &i) in order.iter().enumerate() {
+            let (slot, inst) = (&instructions[i], &scheduled[i]);
+            let label = format!("c8_{:03X}", slot.addr);
+            self.label(&label);
+            if falls_in {
+                self.note_entry(&label, self.a_reg);
+            }
+            if traceable {
+                // JR over the instruction's address and opcode, for traces
+                self.emit(0x18);
+                self.emit(4);
+                self.tstates += 12;
+                self.emit16(slot.addr);
+                self.emit16(slot.opcode);
+            }
+            if self.menu_plan.get(&slot.addr) == Some(&MenuPlan::Ladder) {
+                continue;  // Done by the menu's menu_wait
+            }
+            if self.trace != Trace::Off {
+                self.call_label("trace");
+                self.emit16(inst.addr);
+                if self.trace == Trace::Opcode {
+                    self.emit16(inst.opcode);
+                }
+            }
+            if self.debug_stub {
+                self.flush_consts();  // So that the monitor sees the registers
+                // Watches are checked, and the gate, as each block is entered
+                let label = match merges.contains(&slot.addr) {
+                    true if self.step_gate => "step_gate",
+                    true => "debug_block",
+                    false => "debug_step",
+                };
+                self.call_label(label);
+                self.emit16(inst.addr);
+            }
+            if merges.contains(&slot.addr) {
+                self.patch_tick_weight(weight_at);
+                if self.timer != Timer::Cycles {
+                    // The CTC's interrupt ticks the timers
+                } else if hot.contains(&slot.addr) {
+                    weight_at = Some(self.inline_tick(carried.contains_key(&slot.addr)));
+                } else {
+                    self.ld_a_n(0);  // Weight, patched once the region is compiled
+                    weight_at = Some((self.code.len() - 1, self.tstates));
+                    self.call_label("timer_tick");
+                }
+                if self.speed.is_some() {
+                    self.patch_pace(pace_at, paced);
+                    self.ld_de_nn(0);  // Cost of the region, patched once it is compiled
+                    pace_at = Some(self.code.len() - 2);
+                    paced = 0;
+                    self.call_label("pace");
+                }
+                if self.profile_gen {
+                    if let Ok(index) = counters.binary_search(&slot.addr) {
+                        self.ld_hl_nn(PROFILE_COUNTERS + 4 * index as u16);
+                        self.call_label("profile_count");
+                    }
+                }
+                self.a_reg = carried.get(&slot.addr).copied();
+                self.z_reg = None;
+                self.v_const = [None; 16];
+            }
+            paced += 1;
+            self.compile_instruction(inst)?;
+            if matches!(self.menu_plan.get(&slot.addr), Some(MenuPlan::Wait(_))) {
+                falls_in = false;
+                continue;  // Leaves through its table, never into the ladder
+            }
+
+            // Discovered code is not always contiguous, and blocks may be
+            // placed out of order; chain to the real next instruction when
+            // it isn't the one compiled next
+            let next = slot.addr + slot.size();
+            let contiguous = match order.get(n + 1) {
+                Some(&j) => instructions[j].addr == next,
+                None => !self.chip8_labels.contains_key(&next),
+            };
+            if merges.contains(&next) || !contiguous {
+                self.flush_consts();  // Leaving straight-line code
+            }
+            let calls = slot.opcode >> 12 == 0x2;  // Returns straight to the next label
+            if chip8::falls_through(slot) && !calls && !contiguous {
+                let target = self.chip8_labels.get(&next).cloned().unwrap_or_else(|| "halt".to_string());
+                self.jr_label(&target);
+            }
+            falls_in = chip8::falls_through(slot) && contiguous;
+        }
+        self.patch_tick_weight(weight_at);
+        self.patch_pace(pace_at, paced);
+        self.check_players()?;
+        let program_end = self.code.len();
+        let program_tstates = self.tstates - program_tstates;
+
+        // Generate halt
+        self.label("halt");
+        if self.rx_buffer || self.timer != Timer::Cycles {
+            self.di();  // Or the ACIA's or CTC's interrupt would wake it
+        }
+        if self.target == Target::Cpm {
+            self.emit(0xC3);  // JP 0: warm boot, back to the CCP
+            self.emit16(0x0000);
+        } else {
+            self.emit(0x76);  // HALT
+            self.jp_label("halt");
+        }
+
+        if !self.move_plan.is_empty() {
+            self.generate_move_sprite();
+        }
+
+        if self.timer == Timer::Cycles && !hot.is_empty() && self.carries_a() {
+            // tick_frame for the inlined ticks of blocks that A is carried into
+            self.label("tick_frame_keep_a");
+            self.push_af();
+            self.call_label("tick_frame");
+            self.pop_af();
+            self.ret();
+        }
+
+        if !self.menu_plan.is_empty() {
+            self.generate_menu_wait();
+        }
+
+        if self.profile_gen {
+            self.generate_profile(&counters);
+        }
+
+        if self.trace != Trace::Off {
+            self.generate_trace();
+        }
+
+        if self.debug_stub {
+            self.generate_debug_stub();
+        }
+
+        // BNNN dispatch table: CHIP-8 address -> compiled code
+        if self.fallback == Fallback::Interp || instructions.iter().any(|inst| inst.opcode >> 12 == 0xB) {
+            let entries = instructions.iter().map(|inst| inst.addr).filter(|addr| merges.contains(addr)).collect();
+            self.generate_dispatch(&entries);
+        }
+
+        // Interpreter and the stubs that enter it for uncompiled code
+        if self.fallback == Fallback::Interp {
+            for inst in discovered.iter().filter(|inst| dynamic.contains(&inst.addr)) {
+                self.label(&format!("interp_{:03X}", inst.addr));
+                self.ld_hl_nn(inst.addr);
+                self.jp_label("interp");
+            }
+            self.label("ret_interp");
+            self.ex_de_hl();
+            self.ld_a_h();
+            self.and_n(0x7F);
+            self.ld_h_a();
+            self.jp_label("interp");
+            #[cfg(feature = "interp")]
+            self.generate_interpreter();
+        }
+
+        if self.display_test {
+            self.generate_display_checkpoint();
+        }
+
+        if self.symbol_table {
+            self.generate_symbol_table();
+        }
+
+        // Build info string. It names the game, so it is kept out of the
+        // runtime to give every game built with the same options the same
+        // runtime layout.
+        self.label("info_str");
+        for b in self.build_info().bytes() {
+            self.emit(b);
+        }
+        self.emit(0);
+
+        // Embed CHIP-8 ROM data, copied into CHIP-8 RAM at startup
+        // This label marks the start of embedded ROM (corresponds to CHIP-8 address 0x200)
+        self.label("chip8_rom_data");
+        for byte in self.embedded_rom() {
+            self.emit(byte);
+        }
+        self.pass_end("encode", pass);
+
+        // Resolve forward references
+        let pass = self.pass_start();
+        self.resolve_refs()?;
+        self.pass_end("link", pass);
+
+        Ok(CodeStats {
+            runtime_bytes: (self.labels["main"] - self.origin) as usize,
+            program_bytes: program_end - program_start,
+            data_bytes: self.code.len() - program_end,
+            instructions: instructions.len(),
+            program_tstates,
+            rom_code: map.count(ByteKind::Code),
+            rom_data: map.count(ByteKind::Data),
+            rom_unused: map.count(ByteKind::Unused),
+        })
+    }
+
+    fn generate_header(&mut self) {
+        match self.target {
+            Target::Msx => {
+                // Cartridge header: "AB" and the INIT address the BIOS calls
+                self.emit(b'A');
+                self.emit(b'B');
+            }
+            Target::Coleco => {
+                // Cartridge header: 55 AA skips the BIOS title screen, then
+                // the BIOS's table pointers (unused) and the start address
+                self.emit(0x55);
+                self.emit(0xAA);
+                for _ in 0..8 {
+                    self.emit(0x00);
+                }
+            }
+            _ => {
+                // RST 0 (or the origin) - entry point
+                self.emit(0xC3);  // JP
+            }
+        }
+        self.emit16(self.origin + CODE_START);
+
+        // The BIOS's RST 8-38 and NMI vectors, which nothing uses
+        if self.target == Target::Coleco {
+            for _ in 0..7 {
+                self.emit(0xC9);  // RET
+                self.emit(0x00);
+                self.emit(0x00);
+            }
+            self.emit(0xED);  // RETN
+            self.emit(0x45);
+        }
+
+        // The pause button's NMI
+        if self.target == Target::Sms {
+            while self.pc < 0x0066 {
+                self.emit(0x00);
+            }
+            self.emit(0xED);  // RETN
+            self.emit(0x45);
+        }
+
+        // IM 1's vector, for the ACIA's receive interrupt
+        if self.rx_buffer {
+            while self.pc < RX_VECTOR {
+                self.emit(0x00);
+            }
+            self.jp_label("rx_interrupt");
+        }
+
+        // IM 2's table entry for the CTC's interrupt
+        if self.timer != Timer::Cycles {
+            while self.pc < self.origin + CTC_TABLE {
+                self.emit(0x00);
+            }
+            self.emit_label_ref("ctc_interrupt");
+        }
+
+        // Runtime ABI: version, then a jump table at a fixed address
+        while self.pc < self.origin + abi::VERSION_ADDR {
+            self.emit(0x00);
+        }
+        self.emit(abi::VERSION.0);
+        self.emit(abi::VERSION.1);
+        for entry in abi::ENTRIES {
+            self.jp_label(entry.name);
+        }
+
+        // Pad to CODE_START
+        while self.pc < self.origin + CODE_START {
+            self.emit(0x00);
+        }
+    }
+
+    fn generate_init(&mut self) {
+        self.label("init");
+        match self.target {
+            Target::RetroShield | Target::Cpm | Target::Rc2014 => {}
+            Target::Spectrum => self.emit(0xF3),  // DI: the ROM's interrupt handler needs IY and its own stack
+            Target::Msx => self.emit(0xF3),  // DI: the BIOS handler would read the VDP and keyboard too
+            Target::Cpc => self.emit(0xF3),  // DI: the firmware's handler lives where the stack goes
+            Target::Coleco => {
+                self.emit(0xF3);  // DI, though nothing raises them
+                self.ld_a_n(0x01);  // The Super Game Module's RAM, before anything uses it
+                self.out_n_a(SGM_RAM_PORT);
+            }
+            Target::Sms => {
+                self.emit(0xF3);  // DI: nothing handles the VDP's interrupts
+                self.ld_a_n(SMS_CARTRIDGE_RAM);  // The CHIP-8 RAM, before anything uses it
+                self.ld_mem_a(SMS_RAM_SELECT);
+            }
+        }
+
+        // Initialize stack pointer (at top of RAM, grows downward)
+        match self.stack_top() {
+            Some(top) => {
+                self.emit(0x31);  // LD SP, nn
+                self.emit16(top);  // SP = 0x10000 wraps to 0x0000, grows down into 0xFFFF
+            }
+            None => {
+                // Under the BDOS, which must leave room for the CHIP-8 RAM
+                self.ld_hl_mem(BDOS + 1);
+                self.ld_sp_hl();
+                self.ld_a_h();
+                self.cp_n(CPM_TPA_NEEDED);
+                self.jp_c_label("tpa_too_small");
+            }
+        }
+
+        match self.target {
+            Target::RetroShield => {
+                if self.rx_buffer {
+                    // Nothing received yet
+                    self.ld_a_n((self.ram(RX_RING) & 0xFF) as u8);
+                    self.ld_mem_a(self.ram(RX_TAIL));
+                    self.ld_mem_a(self.ram(RX_HEAD));
+                }
+                self.call_label("acia_init");
+            }
+            Target::Rc2014 => self.call_label("sio_init"),
+            Target::Spectrum | Target::Msx | Target::Cpc | Target::Sms | Target::Coleco => self.call_label("screen_init"),
+            Target::Cpm => {}
+        }
+
+        // Clear CHIP-8 registers
+        self.ld_hl_nn(self.ram(CHIP8_V0));
+        self.ld_bc_nn(32);  // Clear V0-VF + I + misc
+        self.xor_a();
+        self.call_label("memset");
+        if self.debug_stub {
+            // Run until Ctrl-B, with nothing watched and no breakpoints
+            self.ld_hl_nn(self.ram(DEBUG_STEP));
+            self.ld_bc_nn(DEBUG_BREAKS + 2 * BREAK_SLOTS as u16 - DEBUG_STEP);
+            self.xor_a();
+            self.call_label("memset");
+            if self.step_gate {
+                // Wait for the host before the first block
+                self.ld_a_n(1);
+                self.ld_mem_a(self.ram(STEP_GATE));
+            }
+        }
+        if self.console_keys() {
+            // No key down
+            self.ld_hl_nn(self.ram(KEY_STATE));
+            self.ld_bc_nn(16);
+            self.xor_a();
+            self.call_label("memset");
+        }
+
+        if let Some(Audio::Ay8910 { register, data }) = self.audio {
+            // Tone A set up but silent, until the sound timer runs
+            self.ay_write(register, data, 8, 0);
+            self.ay_write(register, data, 0, (AY_TONE & 0xFF) as u8);
+            self.ay_write(register, data, 1, (AY_TONE >> 8) as u8);
+            self.ay_write(register, data, 7, AY_MIXER);
+        }
+
+        if self.audio == Some(Audio::Bell) {
+            // Silent, and free to ring
+            self.xor_a();
+            self.ld_mem_a(self.ram(BELL_WAIT));
+            self.ld_mem_a(self.ram(BELL_ON));
+        }
+
+        if self.delta_display {
+            // Nothing sent yet: the host starts with a clear display too
+            self.ld_hl_nn(DELTA_SHADOW);
+            self.ld_bc_nn(256);
+            self.xor_a();
+            self.call_label("memset");
+        }
+
+        if self.profile_gen {
+            self.ld_hl_nn(PROFILE_COUNTERS);
+            self.ld_bc_nn(PROFILE_END - PROFILE_COUNTERS);
+            self.xor_a();
+            self.call_label("memset");
+        }
+
+        if let Some(ram_init) = self.ram_init {
+            self.ld_hl_nn(self.ram(CHIP8_RAM));
+            self.ld_bc_nn(self.mem_top() - 0x200);
+            match ram_init {
+                RamInit::Zero => {
+                    self.xor_a();
+                    self.call_label("memset");
+                }
+                RamInit::Ff => {
+                    self.ld_a_n(0xFF);
+                    self.call_label("memset");
+                }
+                RamInit::Random(seed) => {
+                    // Galois LFSR (taps B400), stepped once a byte. From 0
+                    // it would never leave 0, so seed 0 starts it at ACE1.
+                    self.ld_de_nn(if seed == 0 { 0xACE1 } else { seed });
+                    self.label("ram_init_loop");
+                    self.srl_d();
+                    self.rr_e();
+                    self.jr_nc("ram_init_byte");
+                    self.ld_a_d();
+                    self.xor_n(0xB4);
+                    self.ld_d_a();
+                    self.label("ram_init_byte");
+                    self.ld_a_e();
+                    self.ld_hl_a();
+                    self.inc_hl();
+                    self.dec_bc();
+                    self.ld_a_b();
+                    self.or_c();
+                    self.jr_nz("ram_init_loop");
+                }
+            }
+        }
+
+        // Initialize RNG seed
+        self.ld_hl_nn(self.ram(CHIP8_RNG));
+        self.ld_a_n(0xAC);
+        self.ld_hl_a();
+        self.inc_hl();
+        self.ld_a_n(0xE1);
+        self.ld_hl_a();
+
+        // CHIP-8 RAM from 0x200 holds the ROM, as on a CHIP-8 machine:
+        // sprites, FX65 and the interpreter read it there, and FX55 writes
+        // over it. A compressed ROM is inflated into it.
+        let rom_len = self.embedded_rom().len();
+        if self.compress_rom {
+            self.ld_hl_label("chip8_rom_data");
+            self.ld_de_nn(self.ram(CHIP8_RAM));
+            self.call_label("rom_inflate");
+        } else if rom_len > 0 {
+            self.ld_hl_label("chip8_rom_data");
+            self.ld_de_nn(self.ram(CHIP8_RAM));
+            self.ld_bc_nn(rom_len as u16);
+            self.ldir();
+        }
+
+        // Start the 60Hz timer countdown, in CTC interrupts or weighted cycles
+        match self.timer {
+            Timer::Ctc { .. } => {
+                let (interrupts, _) = self.ctc_rate().expect("checked before compiling");
+                self.ld_a_n(interrupts);
+                self.ld_mem_a(self.ram(TICK_COUNT));
+            }
+            Timer::Cycles => {
+                self.ld_hl_nn(self.ticks_per_frame());
+                self.ld_mem_hl(self.ram(TICK_COUNT));
+            }
+        }
+
+        // Clear display
+        self.call_label("cls");
+
+        // Copy font to RAM
+        self.call_label("copy_font");
+
+        // Print banner
+        self.call_label("print_banner");
+
+        // Characters from here on go into RX_RING
+        if self.rx_buffer {
+            self.im_1();
+            self.ei();
+        }
+
+        // A full budget of instructions for the first frame
+        if self.speed.is_some() {
+            let budget = self.pace_budget().expect("checked before compiling");
+            self.ld_hl_nn(budget);
+            self.ld_mem_hl(self.ram(PACE_BUDGET));
+            self.ld_a_mem(self.ram(FRAME_COUNT));
+            self.ld_mem_a(self.ram(PACE_FRAME));
+        }
+
+        // Start the CTC: vector, then channel control and time constant
+        if let Timer::Ctc { port } = self.timer {
+            let (_, constant) = self.ctc_rate().expect("checked before compiling");
+            let table = self.origin + CTC_TABLE;
+            self.ld_a_n((table & 0xFF) as u8);
+            self.out_n_a(port);
+            self.ld_a_n(CTC_CONTROL);
+            self.out_n_a(port);
+            self.ld_a_n(constant);
+            self.out_n_a(port);
+            self.ld_a_n((table >> 8) as u8);
+            self.ld_i_a();
+            self.im_2();
+            self.ei();
+        }
+
+        // Jump to main
+        self.jp_label("main");
+    }
+
+    fn generate_runtime(&mut self) {
+        match self.target {
+            Target::RetroShield => self.generate_acia_console(),
+            Target::Spectrum => self.generate_spectrum_console(),
+            Target::Msx | Target::Coleco => self.generate_msx_console(),
+            Target::Cpm => self.generate_bdos_console(),
+            Target::Cpc => self.generate_cpc_console(),
+            Target::Rc2014 => self.generate_sio_console(),
+            Target::Sms => self.generate_sms_console(),
+        }
+
+        // Print banner
+        self.label("print_banner");
+        self.ld_hl_label("banner_str");
+        self.label("print_str");
+        self.ld_a_hl();
+        self.or_a();
+        self.ret_z();
+        self.call_label("print_char");
+        self.inc_hl();
+        self.jr_label("print_str");
+
+        // Banner string
+        self.label("banner_str");
+        for b in b"CHIP-8 on Z80\r\n" {
+            self.emit(*b);
+        }
+        self.emit(0);
+
+        // Print A as two hex digits
+        self.label("print_hex16");
+        self.ld_a_h();
+        self.call_label("print_hex8");
+        self.ld_a_l();
+        self.label("print_hex8");
+        self.push_af();
+        self.rrca();
+        self.rrca();
+        self.rrca();
+        self.rrca();
+        self.call_label("print_nibble");
+        self.pop_af();
+        self.label("print_nibble");
+        self.and_n(0x0F);
+        self.add_a_n(b'0');
+        self.cp_n(b'9' + 1);
+        self.jp_c_label("print_char");
+        self.add_a_n(b'A' - b'9' - 1);
+        self.jp_label("print_char");
+
+        // Print build info (version, game, options)
+        self.label("print_info");
+        self.ld_hl_label("info_str");
+        self.jr_label("print_str");
+
+        self.generate_traps();
+
+        // Translate I to a Z80 RAM address in HL, trapping if out of range
+        // Clobbers A, DE
+        self.label("translate_i");
+        self.ld_hl_mem(self.ram(CHIP8_I));
+        self.ld_a_h();
+        if self.quirks.display_ram {
+            // 0xF00-0xFFF is the display buffer, as on the VIP
+            self.cp_n(0x0F);
+            self.jr_z("translate_i_display");
+        }
+        self.cp_n(0x02);
+        self.jr_c("translate_i_bad");
+        self.cp_n((self.mem_top() >> 8) as u8);
+        self.jr_nc("translate_i_bad");
+        self.ld_de_nn(self.ram(CHIP8_RAM) - 0x200);
+        self.add_hl_de();
+        self.ret();
+        self.label("translate_i_bad");
+        self.jp_label(Trap::BadIndex.label());
+        if self.quirks.display_ram {
+            self.label("translate_i_display");
+            self.ld_h_n((self.ram(DISPLAY_BUF) >> 8) as u8);
+            self.ret();
+
+            // Refresh after a store that went to the display
+            // Clobbers A, B, DE, HL
+            self.label("display_written");
+            self.ld_a_mem(self.ram(CHIP8_I) + 1);
+            self.cp_n(0x0F);
+            self.ret_nz();
+            self.jp_label("refresh_display");
+        }
+
+        // CLS - Clear screen
+        self.label("cls");
+        if self.hires {
+            self.ld_a_mem(self.ram(HIRES_MODE));
+            self.or_a();
+            self.jr_nz("cls_hires");
+        }
+        self.ld_hl_nn(self.ram(DISPLAY_BUF));
+        self.ld_bc_nn(256);
+        self.xor_a();
+        self.call_label("memset");
+        // Refresh display to show cleared screen
+        self.jp_label("refresh_display");
+
+        // Fill BC bytes at HL with A
+        // Clobbers A, BC, E
+        self.label("memset");
+        self.ld_e_a();
+        self.label("memset_loop");
+        self.ld_a_b();
+        self.or_c();
+        self.ret_z();
+        self.ld_hl_e();
+        self.inc_hl();
+        self.dec_bc();
+        self.jr_label("memset_loop");
+
+        if self.compress_rom {
+            self.generate_rom_inflate();
+        }
+
+        // Copy font data
+        self.label("copy_font");
+        self.ld_hl_label("font_rom");
+        self.ld_de_nn(self.ram(FONT_DATA));
+        self.ld_bc_nn(80);  // 16 chars x 5 bytes
+        self.label("copy_font_loop");
+        self.ld_a_hl();
+        self.ld_de_a();
+        self.inc_hl();
+        self.inc_de();
+        self.dec_bc();
+        self.ld_a_b();
+        self.or_c();
+        self.jr_nz("copy_font_loop");
+        self.ret();
+
+        // Font ROM data (0-F sprites, 5 bytes each)
+        self.label("font_rom");
+        // 0
+        self.emit(0xF0); self.emit(0x90); self.emit(0x90); self.emit(0x90); self.emit(0xF0);
+        // 1
+        self.emit(0x20); self.emit(0x60); self.emit(0x20); self.emit(0x20); self.emit(0x70);
+        // 2
+        self.emit(0xF0); self.emit(0x10); self.emit(0xF0); self.emit(0x80); self.emit(0xF0);
+        // 3
+        self.emit(0xF0); self.emit(0x10); self.emit(0xF0); self.emit(0x10); self.emit(0xF0);
+        // 4
+        self.emit(0x90); self.emit(0x90); self.emit(0xF0); self.emit(0x10); self.emit(0x10);
+        // 5
+        self.emit(0xF0); self.emit(0x80); self.emit(0xF0); self.emit(0x10); self.emit(0xF0);
+        // 6
+        self.emit(0xF0); self.emit(0x80); self.emit(0xF0); self.emit(0x90); self.emit(0xF0);
+        // 7
+        self.emit(0xF0); self.emit(0x10); self.emit(0x20); self.emit(0x40); self.emit(0x40);
+        // 8
+        self.emit(0xF0); self.emit(0x90); self.emit(0xF0); self.emit(0x90); self.emit(0xF0);
+        // 9
+        self.emit(0xF0); self.emit(0x90); self.emit(0xF0); self.emit(0x10); self.emit(0xF0);
+        // A
+        self.emit(0xF0); self.emit(0x90); self.emit(0xF0); self.emit(0x90); self.emit(0x90);
+        // B
+        self.emit(0xE0); self.emit(0x90); self.emit(0xE0); self.emit(0x90); self.emit(0xE0);
+        // C
+        self.emit(0xF0); self.emit(0x80); self.emit(0x80); self.emit(0x80); self.emit(0xF0);
+        // D
+        self.emit(0xE0); self.emit(0x90); self.emit(0x90); self.emit(0x90); self.emit(0xE0);
+        // E
+        self.emit(0xF0); self.emit(0x80); self.emit(0xF0); self.emit(0x80); self.emit(0xF0);
+        // F
+        self.emit(0xF0); self.emit(0x80); self.emit(0xF0); self.emit(0x80); self.emit(0x80);
+
+        // RNG - Simple LFSR
+        self.label("rng");
+        self.ld_hl_nn(self.ram(CHIP8_RNG));
+        self.ld_a_hl();
+        self.inc_hl();
+        self.ld_h_hl();
+        self.ld_l_a();
+        // LFSR: x ^= x << 7; x ^= x >> 9; x ^= x << 8
+        self.add_hl_hl();  // Simplified: just rotate
+        self.emit(0xCB); self.emit(0x15);  // RL L
+        self.emit(0xCB); self.emit(0x14);  // RL H
+        self.ld_a_l();
+        self.xor_h();
+        self.ld_l_a();
+        // Store back
+        self.push_hl();
+        self.ld_hl_nn(self.ram(CHIP8_RNG));
+        self.pop_de();
+        self.ld_a_e();
+        self.ld_hl_a();
+        self.inc_hl();
+        self.ld_a_d();
+        self.ld_hl_a();
+        self.ld_a_e();  // Return random byte in A
+        self.ret();
+
+        // Timer tick - called at every merge point of the compiled code
+        // with A = cycles of the code that follows, in TICK_UNIT T-states.
+        // Counts TICK_COUNT down by A and decrements DT and ST once per
+        // 60Hz frame, driving the beeper while ST is non-zero (or ringing
+        // the bell as it starts), and ages the console keys down. With
+        // --timer ctc it returns at once, and the CTC's interrupt calls
+        // tick_frame every TICK_COUNT interrupts.
+        // Clobbers A, DE, HL and flags.
+        self.label("timer_tick");
+        if self.timer == Timer::Cycles {
+            self.ld_e_a();
+            self.ld_d_n(0);
+            self.ld_hl_mem(self.ram(TICK_COUNT));
+            self.or_a();
+            self.sbc_hl_de();
+            self.jr_z("tick_frame");
+            self.jr_c("tick_frame");
+            self.ld_mem_hl(self.ram(TICK_COUNT));
+            self.ret();
+            self.label("tick_frame");
+            self.ld_de_nn(self.ticks_per_frame());
+            self.add_hl_de();  // Carry the overshoot into the next frame
+            self.ld_mem_hl(self.ram(TICK_COUNT));
+        } else {
+            self.ret();
+            self.label("tick_frame");
+        }
+        self.ld_hl_nn(self.ram(FRAME_COUNT));
+        self.inc_hl_ind();
+        if self.console_keys() {
+            self.ld_hl_nn(self.ram(KEY_STATE));
+            self.ld_d_n(16);
+            self.label("tick_keys");
+            self.ld_a_hl();
+            self.or_a();
+            self.jr_z("tick_keys_up");
+            self.dec_hl_ind();
+            self.label("tick_keys_up");
+            self.inc_hl();
+            self.dec_d();
+            self.jr_nz("tick_keys");
+        }
+        self.ld_a_mem(self.ram(CHIP8_DT));
+        self.or_a();
+        self.jr_z("tick_sound");
+        self.dec_a();
+        self.ld_mem_a(self.ram(CHIP8_DT));
+        self.label("tick_sound");
+        if self.audio == Some(Audio::Bell) {
+            // BEL as the sound timer starts, unless it rang within
+            // BELL_GAP frames
+            self.ld_hl_nn(self.ram(BELL_WAIT));
+            self.ld_a_hl();
+            self.or_a();
+            self.jr_z("tick_bell");
+            self.dec_hl_ind();
+            self.label("tick_bell");
+            self.inc_hl();  // BELL_ON
+            self.ld_a_mem(self.ram(CHIP8_ST));
+            self.ld_e_hl();
+            self.ld_hl_a();
+            self.or_a();
+            self.jr_z("tick_bell_done");
+            self.ld_a_e();
+            self.or_a();
+            self.jr_nz("tick_bell_done");  // Still sounding
+            self.dec_hl();  // BELL_WAIT
+            self.ld_a_hl();
+            self.or_a();
+            self.jr_nz("tick_bell_done");
+            self.ld_hl_n(BELL_GAP);
+            self.ld_a_n(0x07);  // BEL
+            self.call_label("print_char");
+            self.label("tick_bell_done");
+        }
+        self.ld_a_mem(self.ram(CHIP8_ST));
+        self.or_a();
+        let psg = match self.audio {
+            Some(Audio::Ay8910 { register, data }) => Some((register, data)),
+            _ => None,
+        };
+        match (self.beeper, psg) {
+            (None, None) => {
+                self.ret_z();
+                self.dec_a();
+                self.ld_mem_a(self.ram(CHIP8_ST));
+                self.ret();
+            }
+            (beeper, psg) => {
+                // On for each frame ST is non-zero, ST=1 included
+                self.jr_z("beep_off");
+                self.dec_a();
+                self.ld_mem_a(self.ram(CHIP8_ST));
+                if let Some(beeper) = beeper {
+                    self.ld_a_n(beeper.mask);
+                    self.out_n_a(beeper.port);
+                }
+                if let Some((register, data)) = psg {
+                    self.ay_write(register, data, 8, AY_VOLUME);
+                }
+                self.ret();
+                self.label("beep_off");
+                if let Some(beeper) = beeper {
+                    self.xor_a();
+                    self.out_n_a(beeper.port);
+                }
+                if let Some((register, data)) = psg {
+                    self.ay_write(register, data, 8, 0);
+                }
+                self.ret();
+            }
+        }
+
+        // The CTC's interrupt: counts TICK_COUNT down and ticks a frame
+        // when it reaches zero
+        if self.timer != Timer::Cycles {
+            let (interrupts, _) = self.ctc_rate().expect("checked before compiling");
+            self.label("ctc_interrupt");
+            self.push_af();
+            self.push_de();
+            self.push_hl();
+            self.ld_hl_nn(self.ram(TICK_COUNT));
+            self.dec_hl_ind();
+            self.jr_nz("ctc_interrupt_done");
+            self.ld_hl_n(interrupts);
+            self.call_label("tick_frame");
+            self.label("ctc_interrupt_done");
+            self.pop_hl();
+            self.pop_de();
+            self.pop_af();
+            self.ei();
+            self.reti();
+        }
+
+        // Wait for the start of the next 60Hz frame, ticking the timers,
+        // or halting until the CTC's interrupt has
+        // Clobbers A, DE, HL
+        self.label("tick_wait");
+        self.push_bc();
+        self.ld_a_mem(self.ram(FRAME_COUNT));
+        self.ld_c_a();
+        self.label("tick_wait_loop");
+        let start = self.tstates;
+        let weight_at = if self.timer == Timer::Cycles {
+            self.ld_a_n(0);  // Weight of one pass, patched below
+            let weight_at = Some((self.code.len() - 1, start));
+            self.call_label("timer_tick");
+            weight_at
+        } else {
+            self.emit(0x76);  // HALT
+            None
+        };
+        self.ld_a_mem(self.ram(FRAME_COUNT));
+        self.cp_c();
+        self.jr_z("tick_wait_loop");
+        self.patch_tick_weight(weight_at);
+        self.pop_bc();
+        self.ret();
+
+        // Pace for --speed, at every merge point of the compiled code with
+        // DE = 60 for each CHIP-8 instruction of the code that follows.
+        // Counts it against PACE_BUDGET, refilled with the speed each
+        // frame, and once that is overdrawn waits for the next frames to
+        // pay the difference.
+        // Clobbers A, DE, HL and flags.
+        if self.speed.is_some() {
+            let budget = self.pace_budget().expect("checked before compiling");
+            self.label("pace");
+            self.ld_a_mem(self.ram(FRAME_COUNT));
+            self.ld_hl_nn(self.ram(PACE_FRAME));
+            self.cp_hl();
+            self.ld_hl_mem(self.ram(PACE_BUDGET));
+            self.jr_z("pace_count");
+            self.ld_mem_a(self.ram(PACE_FRAME));  // A new frame, with a full budget
+            self.ld_hl_nn(budget);
+            self.label("pace_count");
+            self.or_a();
+            self.sbc_hl_de();
+            self.jr_nc("pace_done");
+            self.label("pace_wait");
+            self.push_hl();
+            self.call_label("tick_wait");
+            self.pop_hl();
+            self.ld_de_nn(budget);
+            self.add_hl_de();  // The next frame pays what this one overdrew
+            self.jr_nc("pace_wait");  // Or the frames after, for a region over a frame's budget
+            self.ld_a_mem(self.ram(FRAME_COUNT));
+            self.ld_mem_a(self.ram(PACE_FRAME));
+            self.label("pace_done");
+            self.ld_mem_hl(self.ram(PACE_BUDGET));
+            self.ret();
+        }
+
+        match self.target {
+            Target::RetroShield => self.generate_serial_keys(self.acia_ports.0, self.acia_ports.1),
+            Target::Rc2014 => self.generate_serial_keys(SIO_CTRL, SIO_DATA),
+            Target::Spectrum => self.generate_spectrum_keys(),
+            Target::Msx => self.generate_msx_keys(),
+            Target::Cpm => self.generate_bdos_keys(),
+            Target::Cpc => self.generate_cpc_keys(),
+            Target::Sms => self.generate_sms_keys(),
+            Target::Coleco => self.generate_coleco_keys(),
+        }
+        if self.input_test {
+            self.generate_input_test();
+        }
+        if self.tests_each_key() {
+            self.generate_key_down();
+        }
+
+        self.generate_wait_key();
+
+        // Draw sprite: DE = screen addr, HL = sprite addr, B = height,
+        // C = X & 7. Returns VF in A (1 if collision). Rows and columns
+        // past the edges wrap within the display's page, or are clipped.
+        self.label("draw_sprite");
+        if self.dirty_rows() {
+            // Mark the B rows from the one DE is in
+            self.push_hl();
+            self.push_bc();
+            self.ld_a_e();
+            self.rrca();
+            self.rrca();
+            self.rrca();
+            self.label("draw_dirty");
+            self.and_n(0x1F);
+            self.or_n(self.ram(DIRTY_ROWS) as u8);
+            self.ld_l_a();
+            self.ld_h_n((self.ram(DIRTY_ROWS) >> 8) as u8);
+            self.ld_hl_a();  // Any non-zero byte
+            self.inc_a();
+            if self.quirks.clip {
+                // Not the rows a clipped sprite would wrap to
+                self.ld_l_a();
+                self.and_n(0x1F);
+                self.jr_z("draw_dirty_done");
+                self.ld_a_l();
+            }
+            self.dec_b();
+            self.jr_nz("draw_dirty");
+            if self.quirks.clip {
+                self.label("draw_dirty_done");
+            }
+            self.pop_bc();
+            self.pop_hl();
+        }
+        self.ld_a_c();
+        self.ld_mem_a(self.ram(DRAW_SHIFT));
+        self.xor_a();
+        self.ld_c_a();  // C = collision flag
+        self.label("draw_row");
+        // Get sprite byte
+        self.ld_a_hl();  // A = sprite byte
+        self.push_hl();  // Save sprite pointer
+        self.push_bc();  // Save row count and collision flag
+        // HL = sprite byte shifted right by X & 7 across two display bytes
+        self.ld_h_a();
+        self.ld_l_n(0);
+        self.ld_a_mem(self.ram(DRAW_SHIFT));
+        self.ld_b_a();
+        self.inc_b();
+        self.jr_label("draw_shift_next");
+        self.label("draw_shift");
+        self.srl_h();
+        self.rr_l();
+        self.label("draw_shift_next");
+        self.dec_b();
+        self.jr_nz("draw_shift");
+        self.pop_bc();
+        // XOR the left part into the screen byte
+        self.ld_a_de();    // A = screen byte
+        self.and_h();      // A = screen AND sprite (pixels that collided)
+        self.or_c();
+        self.ld_c_a();     // Update collision flag
+        self.ld_a_de();
+        self.xor_h();
+        self.ld_de_a();    // Write XOR result to screen
+        // And the right part into the next, wrapping to the row's first
+        if self.quirks.clip {
+            self.ld_a_e();
+            self.cpl();
+            self.and_n(0x07);
+            self.jr_z("draw_right_clipped");  // In the row's last column
+        }
+        self.push_de();
+        self.ld_a_e();
+        self.inc_a();
+        self.xor_e();
+        self.and_n(0x07);
+        self.xor_e();
+        self.ld_e_a();     // E = next column in the same row
+        self.ld_a_de();
+        self.and_l();
+        self.or_c();
+        self.ld_c_a();
+        self.ld_a_de();
+        self.xor_l();
+        self.ld_de_a();
+        self.pop_de();     // DE = screen addr
+        if self.quirks.clip {
+            self.label("draw_right_clipped");
+        }
+        self.pop_hl();     // HL = sprite addr
+        self.inc_hl();     // Next sprite byte
+        // Screen += 8 (next row), from the bottom row back to the top
+        self.ld_a_e();
+        self.add_a_n(8);
+        self.ld_e_a();
+        if self.quirks.clip {
+            self.jr_c("draw_done");  // Off the bottom
+        }
+        self.dec_b();
+        self.jr_nz("draw_row");
+        self.label("draw_done");
+        self.ld_a_c();
+        self.or_a();
+        self.ret_z();
+        self.ld_a_n(1);
+        self.ret();
+
+        if self.hires {
+            self.generate_hires();
+        }
+
+        match self.target {
+            Target::RetroShield | Target::Rc2014 if self.framebuffer.is_some() => self.generate_framebuffer_display(),
+            Target::RetroShield | Target::Rc2014 if self.delta_display => self.generate_delta_display(),
+            Target::RetroShield | Target::Cpm | Target::Rc2014 => self.generate_ansi_display(),
+            Target::Spectrum => self.generate_spectrum_display(),
+            Target::Msx | Target::Coleco => self.generate_msx_display(),
+            Target::Cpc => self.generate_cpc_display(),
+            Target::Sms => self.generate_sms_display(),
+        }
+    }
+
+    /// S-CHIP's hires mode: `display_mode` switching to the 128x64 buffer
+    /// (A = 1, 00FF) or back (A = 0, 00FE), and clearing it, `cls_hires`,
+    /// and `draw_hires`, which draws the sprite at HL at X = D, Y = E, B
+    /// rows of 8 pixels or, with B = 0, 16 rows of 16. Draws return A = VF
+    /// as draw_sprite's do, marking the rows they touch in HIRES_ROWS.
+    fn generate_hires(&mut self) {
+        let buffer = self.hires_buf();
+        // Not code, but named for tools reading the display back
+        self.labels.insert("hires_display".to_string(), buffer);
+        self.label("display_mode");
+        self.ld_mem_a(self.ram(HIRES_MODE));
+        // The other mode's lines may run further down the terminal
+        self.ld_hl_label("display_mode_str");
+        self.call_label("print_str");
+        self.jp_label("cls");
+        self.label("display_mode_str");
+        for b in b"\x1b[2;1H\x1b[J" {
+            self.emit(*b);
+        }
+        self.emit(0);
+
+        self.label("cls_hires");
+        self.ld_hl_nn(buffer);
+        self.ld_bc_nn(HIRES_SIZE);
+        self.xor_a();
+        self.call_label("memset");
+        self.jp_label("refresh_display");
+
+        self.label("draw_hires");
+        self.ld_a_d();
+        self.and_n(0x07);
+        self.ld_mem_a(self.ram(DRAW_SHIFT));
+        self.ld_a_d();
+        self.rrca();
+        self.rrca();
+        self.rrca();
+        self.and_n(0x0F);
+        self.ld_d_a();  // D = column byte
+        self.ld_a_e();
+        self.and_n(0x3F);
+        self.ld_e_a();  // E = row
+        self.xor_a();
+        self.ld_mem_a(self.ram(DRAW_HIT));
+        self.ld_mem_a(self.ram(HIRES_WIDE));
+        self.or_b();
+        self.jr_nz("draw_hires_row");
+        self.ld_b_n(16);  // 16x16
+        self.inc_a();
+        self.ld_mem_a(self.ram(HIRES_WIDE));
+        self.label("draw_hires_row");
+        self.push_bc();
+        // Mark the row; HIRES_ROWS doesn't cross a page
+        self.push_hl();
+        self.ld_a_e();
+        self.or_n(self.ram(HIRES_ROWS) as u8);
+        self.ld_l_a();
+        self.ld_h_n((self.ram(HIRES_ROWS) >> 8) as u8);
+        self.ld_hl_h();  // Any non-zero byte
+        self.pop_hl();
+        self.ld_a_hl();
+        self.inc_hl();
+        self.push_hl();
+        self.call_label("draw_hires_byte");
+        self.pop_hl();
+        self.ld_a_mem(self.ram(HIRES_WIDE));
+        self.or_a();
+        self.jr_z("draw_hires_next");
+        // The right half, a byte along
+        self.ld_a_hl();
+        self.inc_hl();
+        self.push_hl();
+        self.push_de();
+        self.ld_b_a();
+        self.ld_a_d();
+        self.inc_a();
+        if self.quirks.clip {
+            self.cp_n(16);
+            self.jr_z("draw_hires_half_clipped");
+        }
+        self.and_n(0x0F);
+        self.ld_d_a();
+        self.ld_a_b();
+        self.call_label("draw_hires_byte");
+        if self.quirks.clip {
+            self.label("draw_hires_half_clipped");
+        }
+        self.pop_de();
+        self.pop_hl();
+        self.label("draw_hires_next");
+        self.pop_bc();
+        self.ld_a_e();
+        self.inc_a();
+        if self.quirks.clip {
+            self.cp_n(64);
+            self.jr_z("draw_hires_done");  // Off the bottom
+        }
+        self.and_n(0x3F);
+        self.ld_e_a();
+        self.dec_b();
+        self.jr_nz("draw_hires_row");
+        self.label("draw_hires_done");
+        self.ld_a_mem(self.ram(DRAW_HIT));
+        self.or_a();
+        self.ret_z();
+        self.ld_a_n(1);
+        self.ret();
+
+        // XOR the sprite byte in A into row E at column byte D, shifted
+        // right by DRAW_SHIFT across two bytes, noting the pixels it turns
+        // off in DRAW_HIT. Clobbers A, BC, HL.
+        self.label("draw_hires_byte");
+        self.ld_h_a();
+        self.ld_l_n(0);
+        self.ld_a_mem(self.ram(DRAW_SHIFT));
+        self.ld_b_a();
+        self.inc_b();
+        self.jr_label("draw_hires_shift_next");
+        self.label("draw_hires_shift");
+        self.srl_h();
+        self.rr_l();
+        self.label("draw_hires_shift_next");
+        self.dec_b();
+        self.jr_nz("draw_hires_shift");
+        self.push_hl();
+        // HL = buffer + E * 16 + D; each row of 16 is within a page
+        self.ld_l_e();
+        self.ld_h_n(0);
+        for _ in 0..4 {
+            self.add_hl_hl();
+        }
+        self.ld_a_l();
+        self.or_d();
+        self.ld_l_a();
+        self.ld_bc_nn(buffer);
+        self.add_hl_bc();
+        self.pop_bc();  // B = the left part, C = the right
+        self.ld_a_hl();
+        self.and_b();
+        self.jr_z("draw_hires_left_clear");
+        self.ld_mem_a(self.ram(DRAW_HIT));
+        self.label("draw_hires_left_clear");
+        self.ld_a_hl();
+        self.xor_b();
+        self.ld_hl_a();
+        if self.quirks.clip {
+            self.ld_a_d();
+            self.cp_n(15);
+            self.ret_z();  // In the row's last column
+        }
+        self.ld_a_l();
+        self.inc_a();
+        self.xor_l();
+        self.and_n(0x0F);
+        self.xor_l();
+        self.ld_l_a();  // The next column in the same row
+        self.ld_a_hl();
+        self.and_a_c();
+        self.jr_z("draw_hires_right_clear");
+        self.ld_mem_a(self.ram(DRAW_HIT));
+        self.label("draw_hires_right_clear");
+        self.ld_a_hl();
+        self.xor_c();
+        self.ld_hl_a();
+        self.ret();
+    }
+
+    /// Serial console on the 6850 ACIA: init and print_char
+    fn generate_acia_console(&mut self) {
+        let (ctrl, data) = self.acia_ports;
+        // ACIA init
+        self.label("acia_init");
+        self.ld_a_n(0x03);  // Master reset
+        self.out_n_a(ctrl);
+        self.ld_a_n(if self.rx_buffer { 0x95 } else { 0x15 });  // 8N1, /16, and the receive interrupt for RX_RING
+        self.out_n_a(ctrl);
+        self.ret();
+
+        if self.rx_buffer {
+            self.generate_rx_ring();
+        }
+
+        // Print character in A
+        self.label("print_char");
+        self.push_af();
+        self.label("print_wait");
+        self.in_a_n(ctrl);
+        self.emit(0xE6); self.emit(0x02);  // AND 2
+        self.jr_z("print_wait");
+        self.pop_af();
+        self.out_n_a(data);
+        self.ret();
+    }
+
+    /// Write `value` to register `reg` of the AY-3-8910. Clobbers A.
+    fn ay_write(&mut self, register: u8, data: u8, reg: u8, value: u8) {
+        self.ld_a_n(reg);
+        self.out_n_a(register);
+        self.ld_a_n(value);
+        self.out_n_a(data);
+    }
+
+    /// The ACIA's receive interrupt, which puts each character into
+    /// RX_RING (or drops it when the ring is full), and rx_read, which
+    /// takes the oldest: A = the character and NZ, or Z with none.
+    /// RX_HEAD and RX_TAIL hold the low byte of their slot's address, and
+    /// only the interrupt moves the head, only rx_read the tail.
+    fn generate_rx_ring(&mut self) {
+        let ring = self.ram(RX_RING);
+        let (ctrl, data) = self.acia_ports;
+        self.label("rx_interrupt");
+        self.push_af();
+        self.in_a_n(ctrl);
+        self.rrca();
+        self.jr_nc("rx_interrupt_done");
+        self.push_hl();
+        self.ld_hl_mem(self.ram(RX_TAIL));  // L = tail, H = head
+        self.ld_a_h();
+        self.inc_a();
+        self.and_n(RX_MASK);
+        self.or_n((ring & 0xFF) as u8);
+        self.cp_l();
+        self.jr_z("rx_interrupt_full");
+        self.ld_l_h();
+        self.ld_h_n((ring >> 8) as u8);
+        self.ld_mem_a(self.ram(RX_HEAD));
+        self.in_a_n(data);
+        self.ld_hl_a();
+        self.jr_label("rx_interrupt_pop");
+        self.label("rx_interrupt_full");
+        self.in_a_n(data);  // Read anyway, to clear the interrupt
+        self.label("rx_interrupt_pop");
+        self.pop_hl();
+        self.label("rx_interrupt_done");
+        self.pop_af();
+        self.ei();
+        self.reti();
+
+        self.label("rx_read");
+        self.push_hl();
+        self.ld_hl_mem(self.ram(RX_TAIL));  // L = tail, H = head
+        self.ld_a_h();
+        self.cp_l();
+        self.jr_z("rx_read_done");
+        self.ld_h_n((ring >> 8) as u8);
+        self.ld_a_l();
+        self.inc_a();
+        self.and_n(RX_MASK);
+        self.or_n((ring & 0xFF) as u8);  // Never 0, so NZ
+        self.ld_mem_a(self.ram(RX_TAIL));
+        self.ld_a_hl();
+        self.label("rx_read_done");
+        self.pop_hl();
+        self.ret();
+    }
+
+    /// Whether anything reads the ROM's copy in CHIP-8 RAM: the interpreter,
+    /// or a sprite or FX65 reaching 0x200. Those are clear of it when every
+    /// address set in I is at least a 16x16 sprite's 32 bytes below, and
+    /// nothing moves I by a register (FX1E) or in a loop (the load/store
+    /// increment). --compress-rom always keeps it.
+    fn reads_rom_data(&self, instructions: &[Instruction]) -> bool {
+        if self.compress_rom || self.fallback == Fallback::Interp {
+            return true;
+        }
+        let reads = instructions.iter().any(|inst| matches!(inst.nibbles(), (0xD, _, _, _) | (0xF, _, 0x6, 0x5)));
+        reads && instructions.iter().any(|inst| match (inst.operand, inst.nibbles()) {
+            (Some(operand), _) => operand > 0x200 - 32,
+            (None, (0xA, _, _, _)) => inst.nnn() > 0x200 - 32,
+            (None, (0xF, _, 0x1, 0xE)) => true,
+            (None, (0xF, _, 0x5 | 0x6, 0x5)) => self.quirks.load_store_increment,
+            _ => false,
+        })
+    }
+
+    /// The ROM as chip8_rom_data holds it: as it is, or run-length encoded
+    /// for --compress-rom. Each code byte is followed by that many literal
+    /// bytes (1-7F), or by one byte to repeat its low 7 bits' worth of
+    /// times (81-FF); 00 ends it.
+    fn embedded_rom(&self) -> Vec<u8> {
+        if !self.rom_data {
+            return Vec::new();
+        }
+        if !self.compress_rom {
+            return self.chip8_rom.clone();  // compile_code refuses one CHIP-8 RAM can't hold
+        }
+        fn flush(packed: &mut Vec<u8>, literals: &mut Vec<u8>) {
+            if !literals.is_empty() {
+                packed.push(literals.len() as u8);
+                packed.append(literals);
+            }
+        }
+        let rom = &self.chip8_rom;
+        let mut packed = Vec::new();
+        let mut literals = Vec::new();
+        let mut i = 0;
+        while i < rom.len() {
+            let run = rom[i..].iter().take(0x7F).take_while(|&&byte| byte == rom[i]).count();
+            if run >= 3 {
+                flush(&mut packed, &mut literals);
+                packed.extend_from_slice(&[0x80 | run as u8, rom[i]]);
+                i += run;
+            } else {
+                literals.push(rom[i]);
+                if literals.len() == 0x7F {
+                    flush(&mut packed, &mut literals);
+                }
+                i += 1;
+            }
+        }
+        flush(&mut packed, &mut literals);
+        packed.push(0);
+        packed
+    }
+
+    /// Inflate the ROM from embedded_rom's encoding at HL into DE
+    /// Clobbers A, BC, DE, HL
+    fn generate_rom_inflate(&mut self) {
+        self.label("rom_inflate");
+        self.ld_a_hl();
+        self.inc_hl();
+        self.or_a();
+        self.ret_z();
+        self.ld_b_n(0);
+        self.rlca();
+        self.jr_c("rom_inflate_run");
+        self.rrca();
+        self.ld_c_a();
+        self.ldir();
+        self.jr_label("rom_inflate");
+        self.label("rom_inflate_run");
+        self.rrca();
+        self.and_n(0x7F);
+        self.ld_b_a();
+        self.ld_a_hl();
+        self.inc_hl();
+        self.label("rom_inflate_fill");
+        self.ld_de_a();
+        self.inc_de();
+        self.dec_b();
+        self.jr_nz("rom_inflate_fill");
+        self.jr_label("rom_inflate");
+    }
+
+    /// A = the next character from the serial port, or on to `none` if
+    /// there isn't one: from RX_RING when the interrupt fills it, or else
+    /// straight from the port
+    fn read_serial(&mut self, status: u8, data: u8, none: &str) {
+        if self.rx_buffer {
+            self.call_label("rx_read");
+            self.jr_z(none);
+        } else {
+            self.in_a_n(status);
+            self.and_n(0x01);
+            self.jr_z(none);
+            self.in_a_n(data);
+        }
+    }
+
+    /// Serial console on channel A of the RC2014's SIO/2: init and print_char
+    fn generate_sio_console(&mut self) {
+        // Reset the channel, then write registers 4, 3, 5 and 1
+        self.label("sio_init");
+        for value in [
+            0x18,        // WR0: channel reset
+            0x04, 0xC4,  // WR4: x64 clock (115200 baud), 1 stop bit, no parity
+            0x03, 0xC1,  // WR3: receive 8 bits, enabled
+            0x05, 0xEA,  // WR5: DTR, transmit 8 bits, enabled, RTS
+            0x01, 0x00,  // WR1: no interrupts
+        ] {
+            self.ld_a_n(value);
+            self.out_n_a(SIO_CTRL);
+        }
+        self.ret();
+
+        // Print character in A
+        self.label("print_char");
+        self.push_af();
+        self.label("print_wait");
+        self.in_a_n(SIO_CTRL);
+        self.emit(0xE6); self.emit(0x04);  // AND 4: transmit buffer empty
+        self.jr_z("print_wait");
+        self.pop_af();
+        self.out_n_a(SIO_DATA);
+        self.ret();
+    }
+
+    /// get_key from the serial console on the ACIA or SIO, which both
+    /// flag a received character in bit 0 of their status
+    fn generate_serial_keys(&mut self, status: u8, data: u8) {
+        // Get key - check for serial input
+        self.label("get_key");
+        if self.step_gate {
+            // A character the step gate took from the port first
+            self.ld_a_mem(self.ram(STEP_KEY));
+            self.or_a();
+            self.jr_z("get_key_port");
+            self.push_af();
+            self.xor_a();
+            self.ld_mem_a(self.ram(STEP_KEY));
+            self.pop_af();
+            self.jr_label("get_key_char");
+            self.label("get_key_port");
+        }
+        self.read_serial(status, data, "get_key_none");
+        if self.step_gate {
+            self.label("get_key_char");
+        }
+        self.generate_ascii_keys();
+    }
+
+    /// Map the character in A to a CHIP-8 key, returning from get_key:
+    /// hex digits are keys unless a key map says otherwise, '?' prints the
+    /// build info and '!' dumps the profile counters
+    fn generate_ascii_keys(&mut self) {
+        // '?' prints the build info instead of being a key
+        self.cp_n(b'?');
+        self.jr_z("get_key_info");
+        // Ctrl-B enters the debug monitor
+        if self.debug_stub {
+            self.cp_n(DEBUG_ESCAPE);
+            self.jr_z("get_key_debug");
+        }
+        // '!' dumps the block counters of a profiling build
+        if self.profile_gen {
+            self.cp_n(b'!');
+            self.jr_z("get_key_profile");
+        }
+        // Ctrl-R sends the whole display to the host's decoder again
+        if self.delta_display {
+            self.cp_n(delta::RESYNC);
+            self.jr_z("get_key_resync");
+        }
+        if !self.keymap.keys.is_empty() {
+            // Map ASCII to CHIP-8 keys through the key map's table
+            self.cp_n(0x80);
+            self.jr_nc("get_key_none");
+            self.push_de();
+            self.push_hl();
+            self.ld_e_a();
+            self.ld_d_n(0);
+            self.ld_hl_label("get_key_map");
+            self.add_hl_de();
+            self.ld_a_hl();
+            self.pop_hl();
+            self.pop_de();
+            self.ret();
+        } else {
+            self.generate_hex_keys();
+        }
+        if self.profile_gen {
+            self.label("get_key_profile");
+            self.call_label("profile_dump");
+            self.jr_label("get_key_none");
+        }
+        if self.debug_stub {
+            self.label("get_key_debug");
+            self.call_label("debug_monitor");
+            self.jr_label("get_key_none");
+        }
+        if self.delta_display {
+            self.label("get_key_resync");
+            self.call_label("delta_resync");
+            self.jr_label("get_key_none");
+        }
+        self.label("get_key_info");
+        self.call_label("print_info");
+        self.label("get_key_none");
+        self.ld_a_n(0xFF);
+        self.ret();
+        if !self.keymap.keys.is_empty() {
+            self.label("get_key_map");
+            for key in self.keymap.table() {
+                self.emit(key);
+            }
+        }
+    }
+
+    /// Map ASCII to CHIP-8 keys: 0-9, and A-F in either case
+    fn generate_hex_keys(&mut self) {
+        self.cp_n(b'0');
+        self.jr_c("get_key_alpha");
+        self.cp_n(b'9' + 1);
+        self.jr_nc("get_key_alpha");
+        self.sub_n(b'0');  // 0-9
+        self.ret();
+        self.label("get_key_alpha");
+        self.cp_n(b'a');
+        self.jr_c("get_key_upper");
+        self.cp_n(b'f' + 1);
+        self.jr_nc("get_key_none");
+        self.sub_n(b'a' - 10);  // a-f -> 10-15
+        self.ret();
+        self.label("get_key_upper");
+        self.cp_n(b'A');
+        self.jr_c("get_key_none");
+        self.cp_n(b'F' + 1);
+        self.jr_nc("get_key_none");
+        self.sub_n(b'A' - 10);  // A-F -> 10-15
+        self.ret();
+    }
+
+    /// Console through the BDOS for CP/M, and the message for a TPA too
+    /// small to hold the CHIP-8 RAM
+    fn generate_bdos_console(&mut self) {
+        // Print character in A
+        self.label("print_char");
+        self.push_af();
+        self.push_bc();
+        self.push_de();
+        self.push_hl();
+        self.ld_e_a();
+        self.ld_c_n(DIRECT_IO);
+        self.call_nn(BDOS);
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.pop_af();
+        self.ret();
+
+        self.label("tpa_too_small");
+        self.ld_hl_label("tpa_str");
+        self.call_label("print_str");
+        self.emit(0xC3);  // JP 0: warm boot
+        self.emit16(0x0000);
+        self.label("tpa_str");
+        for b in format!("Not enough memory: the TPA must reach {:02X}00\r\n", CPM_TPA_NEEDED).bytes() {
+            self.emit(b);
+        }
+        self.emit(0);
+    }
+
+    /// get_key from the BDOS console, polled without waiting
+    fn generate_bdos_keys(&mut self) {
+        self.label("get_key");
+        self.push_bc();
+        self.push_de();
+        self.push_hl();
+        self.ld_e_n(0xFF);  // Input: the character, or 0 if none is waiting
+        self.ld_c_n(DIRECT_IO);
+        self.call_nn(BDOS);
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.or_a();
+        self.jr_z("get_key_none");
+        self.generate_ascii_keys();
+    }
+
+    /// refresh_display drawing the screen on an ANSI terminal. Sending it
+    /// all takes seconds at 9600 baud, so draws go to refresh_rows, which
+    /// sends only the rows marked in DIRTY_ROWS, each after a cursor move.
+    /// Block and braille glyphs send a line for every 2 or 4 rows. With
+    /// S-CHIP's hires mode on, both go on to refresh_hires_display and
+    /// refresh_hires_rows, the same for the 128x64 buffer.
+    fn generate_ansi_display(&mut self) {
+        let lores = Screen { prefix: "refresh", buffer: self.ram(DISPLAY_BUF), row_bytes: 8, height: 32, flags: self.ram(DIRTY_ROWS) };
+        self.generate_ansi_refresh(lores);
+        if self.hires {
+            let hires = Screen { prefix: "refresh_hires", buffer: self.hires_buf(), row_bytes: 16, height: 64, flags: self.ram(HIRES_ROWS) };
+            self.generate_ansi_refresh(hires);
+        }
+        if self.glyphs == Glyphs::Blocks {
+            self.label("refresh_quadrants");
+            for byte in QUADRANTS {
+                self.emit(byte);
+            }
+        }
+    }
+
+    /// `<prefix>_display` and `<prefix>_rows` for one display buffer
+    fn generate_ansi_refresh(&mut self, screen: Screen) {
+        let rows = self.glyphs.rows();
+        let label = |name: &str| format!("{}_{}", screen.prefix, name);
+        // Whole display: mark every row
+        self.label(&label("display"));
+        if self.hires && screen.buffer == self.ram(DISPLAY_BUF) {
+            self.ld_a_mem(self.ram(HIRES_MODE));
+            self.or_a();
+            self.jp_nz_label("refresh_hires_display");
+        }
+        self.ld_hl_nn(screen.flags);
+        self.ld_b_n(screen.height);
+        self.ld_a_n(1);
+        self.label(&label("mark"));
+        self.ld_hl_a();
+        self.inc_hl();
+        self.dec_b();
+        self.jr_nz(&label("mark"));
+
+        self.label(&label("rows"));
+        if self.hires && screen.buffer == self.ram(DISPLAY_BUF) {
+            self.ld_a_mem(self.ram(HIRES_MODE));
+            self.or_a();
+            self.jp_nz_label("refresh_hires_rows");
+        }
+        self.ld_hl_nn(screen.buffer);
+        self.ld_d_n(0);   // Row, or line of glyphs
+        self.label(&label("row"));
+        // Take the row's flag, clearing it
+        self.push_hl();
+        self.ld_a_d();
+        for _ in 0..rows.trailing_zeros() {
+            self.add_a_a();
+        }
+        self.or_n(screen.flags as u8);
+        self.ld_l_a();
+        self.ld_h_n((screen.flags >> 8) as u8);
+        if rows == 1 {
+            self.ld_e_hl();
+            self.xor_a();
+            self.ld_hl_a();
+        } else {
+            // Any of the line's rows
+            self.ld_e_n(0);
+            self.ld_b_n(rows);
+            self.label(&label("flags"));
+            self.ld_a_e();
+            self.or_hl();
+            self.ld_e_a();
+            self.xor_a();
+            self.ld_hl_a();
+            self.inc_l();
+            self.dec_b();
+            self.jr_nz(&label("flags"));
+        }
+        self.pop_hl();
+        self.or_e();
+        self.jr_nz(&label("dirty"));
+        if screen.row_bytes as u16 * screen.height as u16 <= 256 {
+            self.ld_a_l();
+            self.add_a_n(screen.row_bytes * rows);  // The buffer is one page
+            self.ld_l_a();
+        } else {
+            self.ld_bc_nn(screen.row_bytes as u16 * rows as u16);
+            self.add_hl_bc();
+        }
+        self.jr_label(&label("next"));
+
+        // Move cursor to the line, below the banner - ESC[<line + 2>;1H
+        self.label(&label("dirty"));
+        self.ld_a_n(0x1B);
+        self.call_label("print_char");
+        self.ld_a_n(b'[');
+        self.call_label("print_char");
+        self.ld_a_d();
+        self.add_a_n(2);
+        self.ld_b_n(b'0');
+        self.label(&label("tens"));
+        self.cp_n(10);
+        self.jr_c(&label("units"));
+        self.sub_n(10);
+        self.inc_b();
+        self.jr_label(&label("tens"));
+        self.label(&label("units"));
+        self.push_af();
+        self.ld_a_b();
+        self.call_label("print_char");
+        self.pop_af();
+        self.add_a_n(b'0');
+        self.call_label("print_char");
+        self.ld_a_n(b';');
+        self.call_label("print_char");
+        self.ld_a_n(b'1');
+        self.call_label("print_char");
+        self.ld_a_n(b'H');
+        self.call_label("print_char");
+
+        if rows > 1 {
+            self.generate_glyph_line(screen);
+        } else {
+            self.generate_ascii_row(screen);
+        }
+        self.label(&label("next"));
+        self.inc_d();
+        self.ld_a_d();
+        self.cp_n(screen.height / rows);
+        self.jr_nz(&label("row"));
+        self.ret();
+    }
+
+    /// Send the row at HL as `#` and space, leaving HL at the next row
+    fn generate_ascii_row(&mut self, screen: Screen) {
+        let label = |name: &str| format!("{}_{}", screen.prefix, name);
+        self.ld_e_n(screen.row_bytes);  // 8 pixels a byte
+        self.label(&label("byte"));
+        self.ld_a_hl();
+        self.ld_b_n(8);   // 8 bits per byte
+        self.label(&label("bit"));
+        self.emit(0xCB); self.emit(0x07);  // RLC A - rotate left
+        self.push_af();
+        self.jr_nc(&label("space"));
+        self.ld_a_n(b'#');
+        self.jr_label(&label("out"));
+        self.label(&label("space"));
+        self.ld_a_n(b' ');
+        self.label(&label("out"));
+        self.call_label("print_char");
+        self.pop_af();
+        self.dec_b();
+        self.jr_nz(&label("bit"));
+        self.inc_hl();
+        self.dec_e();
+        self.jr_nz(&label("byte"));
+    }
+
+    /// Send the rows from HL as a line of block or braille glyphs, leaving
+    /// HL at the row after them. Each glyph's pixels are shifted out of
+    /// copies of the rows' bytes into C, in the order that makes C the
+    /// braille dots, or the quadrants in its top 4 bits.
+    fn generate_glyph_line(&mut self, screen: Screen) {
+        let label = |name: &str| format!("{}_{}", screen.prefix, name);
+        let rows = self.glyphs.rows();
+        let order: &[u8] = match self.glyphs {
+            Glyphs::Blocks => &[0, 0, 1, 1],
+            _ => &[0, 1, 2, 0, 1, 2, 3, 3],
+        };
+        let one_page = screen.row_bytes as u16 * screen.height as u16 <= 256;
+        let glyph_rows = self.ram(GLYPH_ROWS);
+        self.ld_e_n(screen.row_bytes);  // 4 glyphs a byte
+        self.label(&label("byte"));
+        self.push_hl();
+        for row in 0..rows {
+            if row > 0 {
+                if one_page {
+                    self.ld_a_l();
+                    self.add_a_n(screen.row_bytes);
+                    self.ld_l_a();
+                } else {
+                    self.ld_bc_nn(screen.row_bytes as u16);
+                    self.add_hl_bc();
+                }
+            }
+            self.ld_a_hl();
+            self.ld_mem_a(glyph_rows + row as u16);
+        }
+        self.pop_hl();
+        self.ld_b_n(4);
+        self.label(&label("glyph"));
+        self.push_hl();
+        self.ld_hl_nn(glyph_rows);
+        let mut at = 0;
+        for &row in order {
+            if row != at {
+                self.ld_l_n((glyph_rows + row as u16) as u8);
+                at = row;
+            }
+            self.sla_hl();
+            self.rr_c();
+        }
+        self.pop_hl();
+        self.ld_a_c();
+        if self.glyphs == Glyphs::Blocks {
+            for _ in 0..4 {
+                self.rrca();
+            }
+            self.and_n(0x0F);
+        }
+        self.or_a();
+        self.jr_z(&label("blank"));
+        self.push_af();
+        self.ld_a_n(0xE2);  // UTF-8 of U+2xxx
+        self.call_label("print_char");
+        self.pop_af();
+        if self.glyphs == Glyphs::Blocks {
+            self.push_hl();
+            self.push_bc();
+            self.ld_c_a();
+            self.ld_b_n(0);
+            self.ld_hl_label("refresh_quadrants");
+            self.add_hl_bc();
+            self.pop_bc();
+            self.ld_a_n(0x96);
+            self.call_label("print_char");
+            self.ld_a_hl();
+            self.pop_hl();
+        } else {
+            // U+2800 plus the dots
+            self.rlca();
+            self.rlca();
+            self.and_n(0x03);
+            self.or_n(0xA0);
+            self.call_label("print_char");
+            self.ld_a_c();
+            self.and_n(0x3F);
+            self.or_n(0x80);
+        }
+        self.jr_label(&label("glyph_out"));
+        self.label(&label("blank"));
+        self.ld_a_n(b' ');
+        self.label(&label("glyph_out"));
+        self.call_label("print_char");
+        self.dec_b();
+        self.jr_nz(&label("glyph"));
+        self.inc_hl();
+        self.dec_e();
+        self.jr_nz(&label("byte"));
+        if one_page {
+            self.ld_a_l();
+            self.add_a_n(screen.row_bytes * (rows - 1));
+            self.ld_l_a();
+        } else {
+            self.ld_bc_nn(screen.row_bytes as u16 * (rows - 1) as u16);
+            self.add_hl_bc();
+        }
+    }
+
+    /// refresh_display sending the display bytes that differ from the copy
+    /// last sent, as a frame of (offset, value) records: FRAME, the count
+    /// less one, then the records. Nothing is sent if nothing changed.
+    /// `delta_resync` makes every byte differ, to send them all.
+    fn generate_delta_display(&mut self) {
+        self.label("refresh_display");
+        self.push_bc();
+        // Count the changed bytes; both buffers are page-aligned
+        self.ld_hl_nn(self.ram(DISPLAY_BUF));
+        self.ld_de_nn(DELTA_SHADOW);
+        self.ld_bc_nn(0);
+        self.label("delta_count");
+        self.ld_a_de();
+        self.cp_hl();
+        self.jr_z("delta_count_same");
+        self.inc_bc();
+        self.label("delta_count_same");
+        self.inc_e();
+        self.inc_l();
+        self.jr_nz("delta_count");
+        self.ld_a_b();
+        self.or_c();
+        self.jr_z("delta_done");
+        self.ld_a_n(delta::FRAME);
+        self.call_label("print_char");
+        self.dec_bc();
+        self.ld_a_c();
+        self.call_label("print_char");
+        // Send each changed byte, keeping it as sent
+        self.label("delta_send");
+        self.ld_a_de();
+        self.cp_hl();
+        self.jr_z("delta_send_same");
+        self.ld_a_hl();
+        self.ld_de_a();
+        self.ld_a_l();
+        self.call_label("print_char");
+        self.ld_a_hl();
+        self.call_label("print_char");
+        self.label("delta_send_same");
+        self.inc_e();
+        self.inc_l();
+        self.jr_nz("delta_send");
+        self.label("delta_done");
+        self.pop_bc();
+        self.ret();
+
+        self.label("delta_resync");
+        self.ld_hl_nn(self.ram(DISPLAY_BUF));
+        self.ld_de_nn(DELTA_SHADOW);
+        self.label("delta_resync_byte");
+        self.ld_a_hl();
+        self.cpl();
+        self.ld_de_a();
+        self.inc_e();
+        self.inc_l();
+        self.jr_nz("delta_resync_byte");
+        self.jp_label("refresh_display");
+    }
+
+    /// refresh_display copying the display buffer to video memory, a row
+    /// at a time unless the rows are packed. A turned or mirrored layout
+    /// walks the buffer in the order the video memory wants it.
+    fn generate_framebuffer_display(&mut self) {
+        let framebuffer = self.framebuffer.expect("framebuffer display");
+        let orientation = self.orientation;
+        self.label("refresh_display");
+        if orientation.sideways() {
+            self.generate_sideways_framebuffer(framebuffer);
+            return;
+        }
+        // Upside down reads the rows from the bottom; upside down or
+        // mirrored, but not both, reads each row right to left
+        let upside_down = orientation.rotate == 180;
+        let backwards = upside_down != orientation.mirror;
+        let reverse_bits = backwards != orientation.lsb_first;
+        let mut start = self.ram(DISPLAY_BUF);
+        if upside_down {
+            start += 31 * 8;
+        }
+        if backwards {
+            start += 7;
+        }
+        self.ld_hl_nn(start);
+        self.ld_de_nn(framebuffer.base);
+        let doubled = self.scale != Scale::Single;
+        if !upside_down && !backwards && !reverse_bits && !doubled {
+            if framebuffer.stride == 8 {
+                self.ld_bc_nn(256);
+                self.ldir();
+                self.ret();
+                return;
+            }
+            self.ld_a_n(32);  // 32 rows
+            self.label("refresh_row");
+            self.ld_bc_nn(8);
+            self.ldir();
+            self.ex_de_hl();
+            self.ld_bc_nn(framebuffer.stride - 8);
+            self.add_hl_bc();  // Start of the next row
+            self.ex_de_hl();
+            self.dec_a();
+            self.jr_nz("refresh_row");
+            self.ret();
+            return;
+        }
+        self.ld_b_n(32);  // Rows
+        self.label("refresh_row");
+        self.push_de();
+        self.ld_c_n(8);  // Bytes
+        self.label("refresh_byte");
+        self.ld_a_hl();
+        if reverse_bits {
+            self.push_bc();
+            for _ in 0..8 {
+                self.rla();
+                self.rr_b();
+            }
+            self.ld_a_b();
+            self.pop_bc();
+        }
+        if doubled {
+            // Each half of the byte, leftmost pixels first, as a byte of its own
+            self.push_hl();
+            self.push_af();
+            if !orientation.lsb_first {
+                self.rrca();
+                self.rrca();
+                self.rrca();
+                self.rrca();
+            }
+            self.call_label("fb_double");
+            self.ld_de_a();
+            self.inc_de();
+            self.pop_af();
+            if orientation.lsb_first {
+                self.rrca();
+                self.rrca();
+                self.rrca();
+                self.rrca();
+            }
+            self.call_label("fb_double");
+            self.pop_hl();
+        }
+        self.ld_de_a();
+        self.inc_de();
+        if backwards {
+            self.dec_hl();
+        } else {
+            self.inc_hl();
+        }
+        self.dec_c();
+        self.jr_nz("refresh_byte");
+        self.pop_de();
+        if doubled {
+            self.call_label("fb_second_row");
+        }
+        self.push_bc();
+        let step: i16 = match (upside_down, backwards) {
+            (false, false) | (true, true) => 0,
+            (false, true) => 16,
+            (true, false) => -16,
+        };
+        if step != 0 {
+            self.ld_bc_nn(step as u16);
+            self.add_hl_bc();  // Start of the next row read
+        }
+        self.ex_de_hl();
+        self.ld_bc_nn(framebuffer.stride);
+        self.add_hl_bc();  // Start of the next row
+        self.pop_bc();
+        self.ex_de_hl();
+        self.dec_b();
+        self.jr_nz("refresh_row");
+        self.ret();
+        if doubled {
+            self.generate_framebuffer_doubling(framebuffer);
+        }
+    }
+
+    /// Helpers of the framebuffer drawn at twice the size: fb_double
+    /// turns the low nibble of A into a byte with each bit doubled (keeps
+    /// BC, DE; clobbers HL), and fb_second_row fills the row below the
+    /// one at DE with a copy of it, or clears it for scanlines, leaving
+    /// DE at its start (keeps BC, HL)
+    fn generate_framebuffer_doubling(&mut self, framebuffer: Framebuffer) {
+        self.label("fb_double");
+        self.and_n(0x0F);
+        self.push_bc();
+        self.ld_c_a();
+        self.ld_b_n(0);
+        self.ld_hl_label("fb_double_table");
+        self.add_hl_bc();
+        self.ld_a_hl();
+        self.pop_bc();
+        self.ret();
+        self.label("fb_double_table");
+        for byte in DOUBLED_NIBBLES {
+            self.emit(byte);
+        }
+
+        let row_bytes = (self.orientation.row_bytes() * 2) as u16;
+        self.label("fb_second_row");
+        self.push_bc();
+        self.push_hl();
+        self.ld_h_d();
+        self.ld_l_e();
+        self.ld_bc_nn(framebuffer.stride);
+        self.add_hl_bc();
+        self.ex_de_hl();  // DE = the row below, HL = the row
+        self.push_de();
+        self.ld_bc_nn(row_bytes);
+        if self.scale == Scale::Scanlines {
+            self.ex_de_hl();
+            self.xor_a();
+            self.call_label("memset");
+        } else {
+            self.ldir();
+        }
+        self.pop_de();
+        self.pop_hl();
+        self.pop_bc();
+        self.ret();
+    }
+
+    /// refresh_display for a display on its side: each row of video memory
+    /// is a column of the display, gathered a pixel at a time from the
+    /// buffer's 32 rows. B holds the column's bit, C gathers a byte.
+    fn generate_sideways_framebuffer(&mut self, framebuffer: Framebuffer) {
+        let orientation = self.orientation;
+        // Turned clockwise the left column comes first, read bottom to top;
+        // anticlockwise the right column, top to bottom
+        let clockwise = orientation.rotate == 90;
+        let bottom_up = clockwise != orientation.mirror;
+        let mut start = self.ram(DISPLAY_BUF);
+        if bottom_up {
+            start += 31 * 8;
+        }
+        if !clockwise {
+            start += 7;
+        }
+        self.ld_hl_nn(start);
+        self.ld_de_nn(framebuffer.base);
+        let doubled = self.scale != Scale::Single;
+        let factor = self.scale.factor();
+        self.ld_b_n(if clockwise { 0x80 } else { 0x01 });
+        self.label("refresh_row");
+        self.push_de();
+        self.ld_a_n((4 * factor) as u8);  // Bytes
+        self.label("refresh_byte");
+        self.push_af();
+        for _ in 0..8 / factor {
+            self.ld_a_hl();
+            self.and_b();
+            self.add_a_n(0xFF);  // Carry if the pixel is set
+            if orientation.lsb_first {
+                self.rr_c();
+            } else {
+                self.rl_c();
+            }
+            if doubled {
+                // The same pixel again
+                self.ld_a_c();
+                if orientation.lsb_first {
+                    self.rla();
+                    self.rr_c();
+                } else {
+                    self.rra();
+                    self.rl_c();
+                }
+            }
+            self.ld_a_l();
+            self.add_a_n(if bottom_up { 0xF8 } else { 0x08 });  // The buffer is one page
+            self.ld_l_a();
+        }
+        self.ld_a_c();
+        self.ld_de_a();
+        self.inc_de();
+        self.pop_af();
+        self.dec_a();
+        self.jr_nz("refresh_byte");
+        self.pop_de();
+        if doubled {
+            self.call_label("fb_second_row");
+        }
+        self.ex_de_hl();
+        self.push_bc();
+        self.ld_bc_nn(framebuffer.stride);
+        self.add_hl_bc();  // Start of the next row
+        self.pop_bc();
+        self.ex_de_hl();
+        // Next column, and the next byte of each row past its last bit
+        if clockwise {
+            self.rrc_b();
+            self.jr_nc("refresh_row");
+            self.inc_l();
+            self.ld_a_l();
+            self.cp_n(((start + 8) & 0xFF) as u8);
+        } else {
+            self.rlc_b();
+            self.jr_nc("refresh_row");
+            self.dec_l();
+            self.ld_a_l();
+            self.cp_n(((start - 8) & 0xFF) as u8);
+        }
+        self.jr_nz("refresh_row");
+        self.ret();
+        if doubled {
+            self.generate_framebuffer_doubling(framebuffer);
+        }
+    }
+
+    /// Text console in the bottom rows of the Spectrum screen, drawn with
+    /// the ROM character set: screen_init and print_char
+    fn generate_spectrum_console(&mut self) {
+        // Black border, blank screen in white on black, cursor on the
+        // first text row
+        self.label("screen_init");
+        self.xor_a();
+        self.out_n_a(ULA_PORT);
+        self.ld_hl_nn(SCREEN);
+        self.ld_bc_nn(ATTRIBUTES - SCREEN);
+        self.call_label("memset");
+        self.ld_hl_nn(ATTRIBUTES);
+        self.ld_bc_nn(32 * 24);
+        self.ld_a_n(0x07);  // White ink, black paper
+        self.call_label("memset");
+        self.ld_hl_nn((TEXT_TOP as u16) << 8);  // Column 0
+        self.ld_mem_hl(self.ram(TEXT_POS));
+        self.ret();
+
+        // Print character in A. CR and LF move the cursor, other control
+        // codes (such as the ANSI escapes) are ignored.
+        self.label("print_char");
+        self.push_af();
+        self.push_bc();
+        self.push_de();
+        self.push_hl();
+        self.cp_n(b'\r');
+        self.jr_z("print_cr");
+        self.cp_n(b'\n');
+        self.jr_z("print_lf");
+        self.cp_n(b' ');
+        self.jr_c("print_done");
+        self.cp_n(0x80);
+        self.jr_nc("print_done");
+        self.ld_l_a();
+        self.ld_h_n(0);
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.ld_de_nn(ROM_FONT);
+        self.add_hl_de();
+        self.ex_de_hl();  // DE = glyph
+        self.call_label("text_addr");
+        self.ld_b_n(8);
+        self.label("print_glyph");
+        self.ld_a_de();
+        self.ld_hl_a();
+        self.inc_de();
+        self.inc_h();  // Next pixel line of the cell
+        self.dec_b();
+        self.jr_nz("print_glyph");
+        self.ld_hl_nn(self.ram(TEXT_POS));
+        self.inc_hl_ind();
+        self.ld_a_hl();
+        self.cp_n(32);
+        self.jr_c("print_done");
+        // New line at the right edge or on LF: the rows wrap back to
+        // TEXT_TOP, and the new row is cleared
+        self.label("print_lf");
+        self.ld_hl_nn(self.ram(TEXT_POS) + 1);
+        self.ld_a_hl();
+        self.inc_a();
+        self.cp_n(24);
+        self.jr_c("print_row");
+        self.ld_a_n(TEXT_TOP);
+        self.label("print_row");
+        self.ld_hl_a();
+        self.dec_hl();
+        self.xor_a();
+        self.ld_hl_a();
+        self.call_label("text_addr");
+        self.xor_a();
+        self.ld_b_n(8);
+        self.label("print_clear");
+        self.push_bc();
+        self.push_hl();
+        self.ld_d_h();
+        self.ld_e_l();
+        self.inc_e();
+        self.ld_hl_a();
+        self.ld_bc_nn(31);
+        self.ldir();
+        self.pop_hl();
+        self.pop_bc();
+        self.inc_h();
+        self.dec_b();
+        self.jr_nz("print_clear");
+        self.jr_label("print_done");
+        self.label("print_cr");
+        self.xor_a();
+        self.ld_mem_a(self.ram(TEXT_POS));
+        self.label("print_done");
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.pop_af();
+        self.ret();
+
+        // HL = screen address of the cursor's cell
+        // Clobbers A
+        self.label("text_addr");
+        self.ld_hl_mem(self.ram(TEXT_POS));  // L = column, H = row
+        self.ld_a_h();
+        self.and_n(0x07);
+        self.rrca();
+        self.rrca();
+        self.rrca();
+        self.or_l();
+        self.ld_l_a();
+        self.ld_a_h();
+        self.and_n(0x18);
+        self.or_n((SCREEN >> 8) as u8);
+        self.ld_h_a();
+        self.ret();
+    }
+
+    /// get_key from the Spectrum keyboard matrix, scanning SPECTRUM_KEYS
+    fn generate_spectrum_keys(&mut self) {
+        self.label("get_key");
+        self.push_bc();
+        self.push_hl();
+        self.ld_hl_label("key_matrix");
+        self.ld_c_n(0);
+        self.label("get_key_scan");
+        self.ld_a_hl();  // Half-row, selected by the high address byte
+        self.inc_hl();
+        self.in_a_n(ULA_PORT);
+        self.and_hl();  // Key bit, low when pressed
+        self.inc_hl();
+        self.jr_z("get_key_found");
+        self.inc_c();
+        self.ld_a_c();
+        self.cp_n(16);
+        self.jr_nz("get_key_scan");
+        self.ld_c_n(0xFF);
+        self.label("get_key_found");
+        self.ld_a_c();
+        self.pop_hl();
+        self.pop_bc();
+        self.ret();
+
+        self.label("key_matrix");
+        for (row, bit) in SPECTRUM_KEYS {
+            self.emit(row);
+            self.emit(1 << bit);
+        }
+    }
+
+    /// refresh_display drawing the display 4x scaled in Spectrum screen
+    /// memory: each CHIP-8 row becomes four lines of 32 bytes
+    fn generate_spectrum_display(&mut self) {
+        self.label("refresh_display");
+        self.push_bc();
+        self.ld_hl_nn(self.ram(DISPLAY_BUF));
+        self.ld_a_n(DISPLAY_TOP);
+        self.label("refresh_row");
+        self.push_af();
+        // DE = address of screen line A: 010L LYYY RRRC CCCC, with the
+        // line split into its third (L), row (R) and pixel line (Y)
+        self.ld_b_a();
+        self.and_n(0x07);
+        self.or_n((SCREEN >> 8) as u8);
+        self.ld_d_a();
+        self.ld_a_b();
+        self.and_n(0xC0);
+        self.rrca();
+        self.rrca();
+        self.rrca();
+        self.or_d();
+        self.ld_d_a();
+        self.ld_a_b();
+        self.and_n(0x38);
+        self.rlca();
+        self.rlca();
+        self.ld_e_a();
+        self.push_de();
+        // Each display byte becomes four screen bytes of two pixels
+        self.label("refresh_byte");
+        self.ld_c_hl();
+        self.ld_b_n(4);
+        self.label("refresh_pair");
+        self.xor_a();
+        self.sla_c();
+        self.jr_nc("refresh_left_off");
+        self.or_n(0xF0);
+        self.label("refresh_left_off");
+        self.sla_c();
+        self.jr_nc("refresh_right_off");
+        self.or_n(0x0F);
+        self.label("refresh_right_off");
+        self.ld_de_a();
+        self.inc_e();
+        self.dec_b();
+        self.jr_nz("refresh_pair");
+        self.inc_hl();
+        self.ld_a_l();
+        self.and_n(0x07);  // 8 bytes per row
+        self.jr_nz("refresh_byte");
+        // Repeat the line on the three below it
+        self.ex_sp_hl();  // HL = start of the line, display pointer saved
+        self.ld_a_n(3);
+        self.label("refresh_copy");
+        self.ld_d_h();
+        self.ld_e_l();
+        self.inc_d();
+        self.ld_bc_nn(32);
+        self.push_hl();
+        self.ldir();
+        self.pop_hl();
+        self.inc_h();
+        self.dec_a();
+        self.jr_nz("refresh_copy");
+        self.pop_hl();
+        self.pop_af();
+        self.add_a_n(4);
+        self.cp_n(DISPLAY_TOP + 128);
+        self.jr_nz("refresh_row");
+        self.pop_bc();
+        self.ret();
+    }
+
+    /// Screen setup and print_char for the MSX and ColecoVision: Graphics I
+    /// mode, with the display drawn in 2x2 block patterns and the BIOS font
+    /// (the built-in one on the ColecoVision) for text
+    fn generate_msx_console(&mut self) {
+        let (data, ctrl) = self.vdp_ports();
+        // VDP registers, then VRAM loaded while the display is blanked,
+        // when the VDP takes writes at full speed
+        self.label("screen_init");
+        self.ld_hl_label("vdp_regs");
+        self.ld_b_n(0x80);  // Register 0 (bit 7 marks a register write)
+        self.label("screen_init_reg");
+        self.ld_a_hl();
+        self.out_n_a(ctrl);
+        self.ld_a_b();
+        self.out_n_a(ctrl);
+        self.inc_hl();
+        self.inc_b();
+        self.ld_a_b();
+        self.cp_n(0x80 + VDP_REGS.len() as u8);
+        self.jr_nz("screen_init_reg");
+        self.ld_hl_nn(0);
+        self.call_label("vdp_write_addr");
+        self.ld_bc_nn(0x4000);
+        self.label("screen_init_clear");
+        self.xor_a();
+        self.out_n_a(data);
+        self.dec_bc();
+        self.ld_a_b();
+        self.or_c();
+        self.jr_nz("screen_init_clear");
+        // Patterns 0-15: the 2x2 blocks, top-left pixel in bit 3 down to
+        // bottom-right in bit 0, each half four lines high
+        self.ld_hl_nn(0);
+        self.call_label("vdp_write_addr");
+        self.ld_hl_label("block_patterns");
+        self.ld_b_n(32);
+        self.label("screen_init_block");
+        self.ld_a_hl();
+        for _ in 0..4 {
+            self.out_n_a(data);
+        }
+        self.inc_hl();
+        self.dec_b();
+        self.jr_nz("screen_init_block");
+        self.ld_hl_nn(32 * 8);
+        self.call_label("vdp_write_addr");
+        if self.target == Target::Coleco {
+            // Patterns 32-95: the built-in font, as the BIOS's has no
+            // fixed address
+            self.ld_hl_label("font_tiles");
+            self.ld_bc_nn(sms::FONT.len() as u16 * 8);
+        } else {
+            // Patterns 32-127: the BIOS font
+            self.ld_hl_mem(BIOS_FONT);
+            self.ld_de_nn(32 * 8);
+            self.add_hl_de();
+            self.ld_bc_nn(96 * 8);
+        }
+        self.label("screen_init_font");
+        self.ld_a_hl();
+        self.out_n_a(data);
+        self.inc_hl();
+        self.dec_bc();
+        self.ld_a_b();
+        self.or_c();
+        self.jr_nz("screen_init_font");
+        // White on black, then turn the display on
+        self.ld_hl_nn(COLOR_TABLE);
+        self.call_label("vdp_write_addr");
+        self.ld_b_n(32);
+        self.ld_a_n(0xF1);
+        self.label("screen_init_color");
+        self.out_n_a(data);
+        self.dec_b();
+        self.jr_nz("screen_init_color");
+        self.ld_a_n(VDP_REGS[1] | 0x40);
+        self.out_n_a(ctrl);
+        self.ld_a_n(0x81);
+        self.out_n_a(ctrl);
+        self.ld_hl_nn((TEXT_TOP as u16) << 8);  // Column 0
+        self.ld_mem_hl(self.ram(TEXT_POS));
+        self.ret();
+
+        // Set the VDP to write VRAM from HL
+        // Clobbers A
+        self.label("vdp_write_addr");
+        self.ld_a_l();
+        self.out_n_a(ctrl);
+        self.ld_a_h();
+        self.or_n(0x40);
+        self.out_n_a(ctrl);
+        self.ret();
+
+        // Print character in A. CR and LF move the cursor, other control
+        // codes (such as the ANSI escapes) are ignored.
+        self.label("print_char");
+        self.push_af();
+        self.push_bc();
+        self.push_de();
+        self.push_hl();
+        self.cp_n(b'\r');
+        self.jr_z("print_cr");
+        self.cp_n(b'\n');
+        self.jr_z("print_lf");
+        self.cp_n(b' ');
+        self.jr_c("print_done");
+        self.cp_n(0x80);
+        self.jr_nc("print_done");
+        if self.target == Target::Coleco {
+            self.cp_n(0x60);
+            self.jr_c("print_upper");
+            self.sub_n(0x20);
+            self.label("print_upper");
+        }
+        self.ld_e_a();
+        self.call_label("text_addr");
+        self.ld_a_e();
+        self.out_n_a(data);
+        self.ld_hl_nn(self.ram(TEXT_POS));
+        self.inc_hl_ind();
+        self.ld_a_hl();
+        self.cp_n(32);
+        self.jr_c("print_done");
+        // New line at the right edge or on LF: the rows wrap back to
+        // TEXT_TOP, and the new row is cleared
+        self.label("print_lf");
+        self.ld_hl_nn(self.ram(TEXT_POS) + 1);
+        self.ld_a_hl();
+        self.inc_a();
+        self.cp_n(24);
+        self.jr_c("print_row");
+        self.ld_a_n(TEXT_TOP);
+        self.label("print_row");
+        self.ld_hl_a();
+        self.dec_hl();
+        self.xor_a();
+        self.ld_hl_a();
+        self.call_label("text_addr");
+        self.xor_a();
+        self.ld_b_n(32);
+        self.label("print_clear");
+        self.out_n_a(data);
+        self.dec_b();
+        self.jr_nz("print_clear");
+        self.jr_label("print_done");
+        self.label("print_cr");
+        self.xor_a();
+        self.ld_mem_a(self.ram(TEXT_POS));
+        self.label("print_done");
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.pop_af();
+        self.ret();
+
+        // Set the VDP to write the name table at the cursor's cell
+        // Clobbers A, HL
+        self.label("text_addr");
+        self.ld_hl_mem(self.ram(TEXT_POS));  // L = column, H = row
+        self.ld_a_h();
+        self.rrca();
+        self.rrca();
+        self.rrca();
+        self.ld_h_a();  // Row * 32, with the low and high bits swapped round
+        self.and_n(0xE0);
+        self.or_l();
+        self.out_n_a(ctrl);
+        self.ld_a_h();
+        self.and_n(0x03);
+        self.or_n((NAME_TABLE >> 8) as u8 | 0x40);
+        self.out_n_a(ctrl);
+        self.ret();
+
+        self.label("vdp_regs");
+        for reg in VDP_REGS {
+            self.emit(reg);
+        }
+        self.label("block_patterns");
+        for pattern in 0..16u8 {
+            let half = |left: u8, right: u8| (if pattern & left != 0 { 0xF0 } else { 0 }) | (if pattern & right != 0 { 0x0F } else { 0 });
+            self.emit(half(8, 4));
+            self.emit(half(2, 1));
+        }
+        if self.target == Target::Coleco {
+            self.font_tiles();
+        }
+    }
+
+    /// get_key from the MSX keyboard matrix, scanning MSX_KEYS
+    fn generate_msx_keys(&mut self) {
+        self.label("get_key");
+        self.push_bc();
+        self.push_hl();
+        self.ld_hl_label("key_matrix");
+        self.ld_c_n(0);
+        self.label("get_key_scan");
+        self.in_a_n(PPI_ROW);
+        self.and_n(0xF0);  // Keep the LED and cassette bits
+        self.or_hl();
+        self.out_n_a(PPI_ROW);
+        self.inc_hl();
+        self.in_a_n(PPI_KEYS);
+        self.and_hl();  // Key bit, low when pressed
+        self.inc_hl();
+        self.jr_z("get_key_found");
+        self.inc_c();
+        self.ld_a_c();
+        self.cp_n(16);
+        self.jr_nz("get_key_scan");
+        self.ld_c_n(0xFF);
+        self.label("get_key_found");
+        self.ld_a_c();
+        self.pop_hl();
+        self.pop_bc();
+        self.ret();
+
+        self.label("key_matrix");
+        for (row, bit) in MSX_KEYS {
+            self.emit(row);
+            self.emit(1 << bit);
+        }
+    }
+
+    /// get_key from controller 1: the keypad, then the fire buttons
+    fn generate_coleco_keys(&mut self) {
+        self.label("get_key");
+        self.push_bc();
+        self.push_hl();
+        self.out_n_a(KEYPAD_MODE);
+        self.in_a_n(CONTROLLER_1);
+        self.ld_c_a();
+        self.and_n(0x40);  // Right fire in keypad mode
+        self.ld_a_n(COLECO_RIGHT_FIRE);
+        self.jr_z("get_key_found");
+        self.ld_a_c();
+        self.and_n(0x0F);
+        self.ld_c_a();
+        self.ld_b_n(0);
+        self.ld_hl_label("keypad_keys");
+        self.add_hl_bc();
+        self.ld_a_hl();
+        self.cp_n(0xFF);
+        self.jr_nz("get_key_found");
+        self.out_n_a(JOYSTICK_MODE);
+        self.in_a_n(CONTROLLER_1);
+        self.and_n(0x40);  // Left fire in joystick mode
+        self.ld_a_n(0xFF);
+        self.jr_nz("get_key_found");
+        self.ld_a_n(COLECO_LEFT_FIRE);
+        self.label("get_key_found");
+        self.pop_hl();
+        self.pop_bc();
+        self.ret();
+
+        self.label("keypad_keys");
+        for key in COLECO_KEYPAD {
+            self.emit(key);
+        }
+    }
+
+    /// refresh_display writing the display into the name table: each
+    /// cell shows a 2x2 block of pixels, from two rows of the buffer
+    fn generate_msx_display(&mut self) {
+        let (data, _) = self.vdp_ports();
+        self.label("refresh_display");
+        self.push_bc();
+        self.ld_hl_nn(NAME_TABLE + MSX_DISPLAY_TOP * 32);
+        self.call_label("vdp_write_addr");
+        self.ld_hl_nn(self.ram(DISPLAY_BUF));
+        self.label("refresh_byte");
+        self.ld_c_hl();  // Top row
+        self.ld_a_l();
+        self.add_a_n(8);
+        self.ld_l_a();
+        self.ld_b_hl();  // Bottom row
+        self.ld_a_l();
+        self.sub_n(8);
+        self.ld_l_a();
+        // Four cells of two pixels from each row; the shifts also space
+        // out the VRAM writes enough for the VDP during the active display
+        for _ in 0..4 {
+            self.xor_a();
+            self.sla_c();
+            self.rla();
+            self.sla_c();
+            self.rla();
+            self.sla_b();
+            self.rla();
+            self.sla_b();
+            self.rla();
+            self.out_n_a(data);
+        }
+        self.inc_hl();
+        self.ld_a_l();
+        self.and_n(0x07);  // 8 bytes per row
+        self.jr_nz("refresh_byte");
+        self.ld_a_l();
+        self.add_a_n(8);  // Skip the bottom row, drawn with the top one
+        self.ld_l_a();
+        self.jr_nz("refresh_byte");  // Wraps to 0 after the last row
+        self.pop_bc();
+        self.ret();
+    }
+
+    /// Screen setup and print_char for the Master System: mode 4 tiles in
+    /// white on black, with a font of our own as the console has no ROM
+    fn generate_sms_console(&mut self) {
+        // VDP registers, then VRAM loaded while the display is blanked
+        self.label("screen_init");
+        self.ld_hl_label("vdp_regs");
+        self.ld_b_n(0x80);  // Register 0 (bit 7 marks a register write)
+        self.label("screen_init_reg");
+        self.ld_a_hl();
+        self.out_n_a(SMS_VDP_CTRL);
+        self.ld_a_b();
+        self.out_n_a(SMS_VDP_CTRL);
+        self.inc_hl();
+        self.inc_b();
+        self.ld_a_b();
+        self.cp_n(0x80 + SMS_VDP_REGS.len() as u8);
+        self.jr_nz("screen_init_reg");
+        self.ld_hl_nn(0);
+        self.call_label("vdp_write_addr");
+        self.ld_bc_nn(0x4000);
+        self.label("screen_init_clear");
+        self.xor_a();
+        self.out_n_a(SMS_VDP_DATA);
+        self.dec_bc();
+        self.ld_a_b();
+        self.or_c();
+        self.jr_nz("screen_init_clear");
+        // No sprites: the first Y of 0xD0 ends the table
+        self.ld_hl_nn(SMS_SPRITES);
+        self.call_label("vdp_write_addr");
+        self.ld_a_n(0xD0);
+        self.out_n_a(SMS_VDP_DATA);
+        // Palettes: colour 1 of the tiles white, everything else black
+        self.xor_a();
+        self.out_n_a(SMS_VDP_CTRL);
+        self.ld_a_n(0xC0);  // CRAM write from entry 0
+        self.out_n_a(SMS_VDP_CTRL);
+        self.xor_a();
+        self.out_n_a(SMS_VDP_DATA);
+        self.ld_a_n(0x3F);
+        self.out_n_a(SMS_VDP_DATA);
+        self.ld_b_n(30);
+        self.xor_a();
+        self.label("screen_init_palette");
+        self.out_n_a(SMS_VDP_DATA);
+        self.dec_b();
+        self.jr_nz("screen_init_palette");
+        // Tiles 0-15: the 2x2 blocks, as on the MSX; tiles 32-95: the font
+        self.ld_hl_nn(0);
+        self.call_label("vdp_write_addr");
+        self.ld_hl_label("block_tiles");
+        self.ld_bc_nn(16 * 8);
+        self.call_label("vdp_tile_lines");
+        self.ld_hl_nn(SMS_FONT_TILE * 32);
+        self.call_label("vdp_write_addr");
+        self.ld_hl_label("font_tiles");
+        self.ld_bc_nn(sms::FONT.len() as u16 * 8);
+        self.call_label("vdp_tile_lines");
+        self.ld_a_n(SMS_VDP_REGS[1] | 0x40);  // Display on
+        self.out_n_a(SMS_VDP_CTRL);
+        self.ld_a_n(0x81);
+        self.out_n_a(SMS_VDP_CTRL);
+        self.ld_hl_nn((TEXT_TOP as u16) << 8);  // Column 0
+        self.ld_mem_hl(self.ram(TEXT_POS));
+        self.ret();
+
+        // Write BC tile lines from HL, one byte each, in colour 1
+        self.label("vdp_tile_lines");
+        self.ld_a_hl();
+        self.out_n_a(SMS_VDP_DATA);
+        self.xor_a();
+        for _ in 0..3 {
+            self.out_n_a(SMS_VDP_DATA);  // The other three bitplanes
+        }
+        self.inc_hl();
+        self.dec_bc();
+        self.ld_a_b();
+        self.or_c();
+        self.jr_nz("vdp_tile_lines");
+        self.ret();
+
+        // Set the VDP to write VRAM from HL
+        // Clobbers A
+        self.label("vdp_write_addr");
+        self.ld_a_l();
+        self.out_n_a(SMS_VDP_CTRL);
+        self.ld_a_h();
+        self.or_n(0x40);
+        self.out_n_a(SMS_VDP_CTRL);
+        self.ret();
+
+        // Print character in A, in upper case. CR and LF move the cursor,
+        // other control codes (such as the ANSI escapes) are ignored.
+        self.label("print_char");
+        self.push_af();
+        self.push_bc();
+        self.push_de();
+        self.push_hl();
+        self.cp_n(b'\r');
+        self.jr_z("print_cr");
+        self.cp_n(b'\n');
+        self.jr_z("print_lf");
+        self.cp_n(b' ');
+        self.jr_c("print_done");
+        self.cp_n(0x80);
+        self.jr_nc("print_done");
+        self.cp_n(0x60);
+        self.jr_c("print_upper");
+        self.sub_n(0x20);
+        self.label("print_upper");
+        self.ld_e_a();
+        self.call_label("text_addr");
+        self.ld_a_e();
+        self.out_n_a(SMS_VDP_DATA);
+        self.xor_a();  // Attributes: plain, with the VDP given time between writes
+        self.nop();
+        self.nop();
+        self.nop();
+        self.out_n_a(SMS_VDP_DATA);
+        self.ld_hl_nn(self.ram(TEXT_POS));
+        self.inc_hl_ind();
+        self.ld_a_hl();
+        self.cp_n(32);
+        self.jr_c("print_done");
+        // New line at the right edge or on LF: the rows wrap back to
+        // TEXT_TOP, and the new row is cleared
+        self.label("print_lf");
+        self.ld_hl_nn(self.ram(TEXT_POS) + 1);
+        self.ld_a_hl();
+        self.inc_a();
+        self.cp_n(24);
+        self.jr_c("print_row");
+        self.ld_a_n(TEXT_TOP);
+        self.label("print_row");
+        self.ld_hl_a();
+        self.dec_hl();
+        self.xor_a();
+        self.ld_hl_a();
+        self.call_label("text_addr");
+        self.xor_a();
+        self.ld_b_n(64);  // 32 cells of tile and attributes
+        self.label("print_clear");
+        self.out_n_a(SMS_VDP_DATA);
+        self.dec_b();
+        self.jr_nz("print_clear");
+        self.jr_label("print_done");
+        self.label("print_cr");
+        self.xor_a();
+        self.ld_mem_a(self.ram(TEXT_POS));
+        self.label("print_done");
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.pop_af();
+        self.ret();
+
+        // Set the VDP to write the name table at the cursor's cell
+        // Clobbers A, HL
+        self.label("text_addr");
+        self.ld_hl_mem(self.ram(TEXT_POS));  // L = column, H = row
+        self.ld_a_l();
+        self.add_a_a();
+        self.ld_l_a();  // Column * 2
+        self.ld_a_h();
+        self.rrca();
+        self.rrca();
+        self.ld_h_a();  // Row * 64, with the low and high bits swapped round
+        self.and_n(0xC0);
+        self.or_l();
+        self.out_n_a(SMS_VDP_CTRL);
+        self.ld_a_h();
+        self.and_n(0x07);
+        self.or_n((SMS_NAME_TABLE >> 8) as u8 | 0x40);
+        self.out_n_a(SMS_VDP_CTRL);
+        self.ret();
+
+        self.label("vdp_regs");
+        for reg in SMS_VDP_REGS {
+            self.emit(reg);
+        }
+        self.label("block_tiles");
+        for pattern in 0..16u8 {
+            let half = |left: u8, right: u8| (if pattern & left != 0 { 0xF0 } else { 0 }) | (if pattern & right != 0 { 0x0F } else { 0 });
+            for line in [half(8, 4), half(2, 1)] {
+                for _ in 0..4 {
+                    self.emit(line);
+                }
+            }
+        }
+        self.font_tiles();
+    }
+
+    /// The built-in text font, one byte per tile line, with each glyph in
+    /// lines 1-5 of its tile
+    fn font_tiles(&mut self) {
+        self.label("font_tiles");
+        for glyph in sms::FONT {
+            self.emit(0);
+            for row in 0..5 {
+                self.emit(sms::font_row(glyph, row));
+            }
+            self.emit(0);
+            self.emit(0);
+        }
+    }
+
+    /// get_key from the Master System pad: the first pressed button with
+    /// a key, in the order of the port's bits
+    fn generate_sms_keys(&mut self) {
+        self.label("get_key");
+        self.push_bc();
+        self.push_hl();
+        self.in_a_n(SMS_PAD);
+        self.cpl();
+        self.ld_c_a();  // Pressed buttons
+        self.ld_hl_label("pad_keys");
+        self.ld_b_n(sms::BUTTONS.len() as u8);
+        self.label("get_key_scan");
+        self.srl_c();
+        self.ld_a_hl();
+        self.inc_hl();
+        self.jr_nc("get_key_next");
+        self.cp_n(0xFF);
+        self.jr_nz("get_key_found");
+        self.label("get_key_next");
+        self.dec_b();
+        self.jr_nz("get_key_scan");
+        self.ld_a_n(0xFF);
+        self.label("get_key_found");
+        self.pop_hl();
+        self.pop_bc();
+        self.ret();
+
+        self.label("pad_keys");
+        for key in self.pad.keys {
+            self.emit(key.unwrap_or(0xFF));
+        }
+    }
+
+    /// refresh_display writing the display into the name table, as on
+    /// the MSX but with an attribute byte after each tile
+    fn generate_sms_display(&mut self) {
+        self.label("refresh_display");
+        self.push_bc();
+        self.ld_hl_nn(SMS_NAME_TABLE + MSX_DISPLAY_TOP * 64);
+        self.call_label("vdp_write_addr");
+        self.ld_hl_nn(self.ram(DISPLAY_BUF));
+        self.label("refresh_byte");
+        self.ld_c_hl();  // Top row
+        self.ld_a_l();
+        self.add_a_n(8);
+        self.ld_l_a();
+        self.ld_b_hl();  // Bottom row
+        self.ld_a_l();
+        self.sub_n(8);
+        self.ld_l_a();
+        for _ in 0..4 {
+            self.xor_a();
+            self.sla_c();
+            self.rla();
+            self.sla_c();
+            self.rla();
+            self.sla_b();
+            self.rla();
+            self.sla_b();
+            self.rla();
+            self.out_n_a(SMS_VDP_DATA);
+            self.xor_a();
+            self.nop();
+            self.nop();
+            self.nop();
+            self.out_n_a(SMS_VDP_DATA);
+        }
+        self.inc_hl();
+        self.ld_a_l();
+        self.and_n(0x07);  // 8 bytes per row
+        self.jr_nz("refresh_byte");
+        self.ld_a_l();
+        self.add_a_n(8);  // Skip the bottom row, drawn with the top one
+        self.ld_l_a();
+        self.jr_nz("refresh_byte");  // Wraps to 0 after the last row
+        self.pop_bc();
+        self.ret();
+    }
+
+    /// Screen setup and print_char for the Amstrad CPC: mode 1 in white on
+    /// black, with the lower ROM's font for text
+    fn generate_cpc_console(&mut self) {
+        // Mode 1 with the ROMs paged out, so that reads of 0xC000 see the
+        // screen. Pen 0 and the border black, pen 1 bright white.
+        self.label("screen_init");
+        self.ld_bc_nn(GATE_ARRAY | GA_SCREEN as u16);
+        self.out_c_c();
+        for (pen, colour) in [(0x00, 0x54), (0x01, 0x4B), (0x10, 0x54)] {
+            self.ld_c_n(pen);
+            self.out_c_c();
+            self.ld_c_n(colour);
+            self.out_c_c();
+        }
+        // Screen at 0xC000, without the offset left by firmware scrolling
+        for (reg, value) in [(12, 0x30), (13, 0x00)] {
+            self.ld_bc_nn(CRTC_SELECT | reg);
+            self.out_c_c();
+            self.ld_bc_nn(CRTC_DATA | value);
+            self.out_c_c();
+        }
+        self.ld_hl_nn(CPC_SCREEN);
+        self.ld_bc_nn(0x4000);
+        self.xor_a();
+        self.call_label("memset");
+        self.ld_hl_nn((TEXT_TOP as u16) << 8);  // Column 0
+        self.ld_mem_hl(self.ram(TEXT_POS));
+        self.ret();
+
+        // Print character in A. CR and LF move the cursor, other control
+        // codes (such as the ANSI escapes) are ignored.
+        self.label("print_char");
+        self.push_af();
+        self.push_bc();
+        self.push_de();
+        self.push_hl();
+        self.cp_n(b'\r');
+        self.jr_z("print_cr");
+        self.cp_n(b'\n');
+        self.jr_z("print_lf");
+        self.cp_n(b' ');
+        self.jr_c("print_done");
+        self.cp_n(0x80);
+        self.jr_nc("print_done");
+        self.push_af();
+        self.call_label("text_addr");
+        self.pop_af();
+        self.ex_de_hl();  // DE = cell
+        self.ld_l_a();
+        self.ld_h_n(0);
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.ld_bc_nn(CPC_ROM_FONT);
+        self.add_hl_bc();  // HL = glyph
+        // The font is read with the lower ROM paged in
+        self.ld_bc_nn(GATE_ARRAY | GA_FONT as u16);
+        self.out_c_c();
+        self.ld_c_n(8);
+        self.label("print_glyph");
+        // Each font line becomes two bytes of four pixels in pen 1
+        self.ld_a_hl();
+        self.and_n(0xF0);
+        self.ld_de_a();
+        self.inc_e();
+        self.ld_a_hl();
+        self.rlca();
+        self.rlca();
+        self.rlca();
+        self.rlca();
+        self.and_n(0xF0);
+        self.ld_de_a();
+        self.dec_e();
+        self.inc_hl();
+        self.ld_a_d();
+        self.add_a_n(0x08);  // Next pixel line of the cell
+        self.ld_d_a();
+        self.dec_c();
+        self.jr_nz("print_glyph");
+        self.ld_c_n(GA_SCREEN);
+        self.out_c_c();
+        self.ld_hl_nn(self.ram(TEXT_POS));
+        self.inc_hl_ind();
+        self.ld_a_hl();
+        self.cp_n(40);
+        self.jr_c("print_done");
+        // New line at the right edge or on LF: the rows wrap back to
+        // TEXT_TOP, and the new row is cleared
+        self.label("print_lf");
+        self.ld_hl_nn(self.ram(TEXT_POS) + 1);
+        self.ld_a_hl();
+        self.inc_a();
+        self.cp_n(25);
+        self.jr_c("print_row");
+        self.ld_a_n(TEXT_TOP);
+        self.label("print_row");
+        self.ld_hl_a();
+        self.dec_hl();
+        self.xor_a();
+        self.ld_hl_a();
+        self.call_label("text_addr");
+        self.ld_b_n(8);
+        self.label("print_clear");
+        self.push_bc();
+        self.push_hl();
+        self.ld_d_h();
+        self.ld_e_l();
+        self.inc_de();
+        self.xor_a();
+        self.ld_hl_a();
+        self.ld_bc_nn(79);
+        self.ldir();
+        self.pop_hl();
+        self.pop_bc();
+        self.ld_a_h();
+        self.add_a_n(0x08);
+        self.ld_h_a();
+        self.dec_b();
+        self.jr_nz("print_clear");
+        self.jr_label("print_done");
+        self.label("print_cr");
+        self.xor_a();
+        self.ld_mem_a(self.ram(TEXT_POS));
+        self.label("print_done");
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.pop_af();
+        self.ret();
+
+        // HL = screen address of the cursor's cell: row * 80 + column * 2
+        // Clobbers A and DE
+        self.label("text_addr");
+        self.ld_a_mem(self.ram(TEXT_POS) + 1);
+        self.ld_l_a();
+        self.ld_h_n(0);
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.ld_d_h();
+        self.ld_e_l();
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.add_hl_de();
+        self.ld_a_mem(self.ram(TEXT_POS));
+        self.add_a_a();
+        self.ld_e_a();
+        self.ld_d_n((CPC_SCREEN >> 8) as u8);
+        self.add_hl_de();
+        self.ret();
+    }
+
+    /// get_key from the CPC keyboard matrix, which is read on the PSG's
+    /// port A with the line set on PPI port C, scanning CPC_KEYS
+    fn generate_cpc_keys(&mut self) {
+        self.label("get_key");
+        self.push_bc();
+        self.push_de();
+        self.push_hl();
+        self.cpc_keyboard_on();
+        self.ld_hl_label("key_matrix");
+        self.ld_e_n(0);
+        self.label("get_key_scan");
+        self.cpc_key_line();
+        self.inc_hl();
+        self.jr_z("get_key_found");
+        self.inc_e();
+        self.ld_a_e();
+        self.cp_n(16);
+        self.jr_nz("get_key_scan");
+        self.ld_e_n(0xFF);
+        self.label("get_key_found");
+        self.cpc_keyboard_off();
+        self.ld_a_e();
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.ret();
+
+        self.label("key_matrix");
+        for (line, bit) in CPC_KEYS {
+            self.emit(0x40 | line);
+            self.emit(1 << bit);
+        }
+    }
+
+    /// Select PSG register 14, then turn PPI port A around to read it
+    fn cpc_keyboard_on(&mut self) {
+        self.ld_bc_nn(PSG_PORT | 14);
+        self.out_c_c();
+        self.ld_bc_nn(PSG_CONTROL | 0xC0);  // Latch the register number
+        self.out_c_c();
+        self.ld_bc_nn(PSG_CONTROL);
+        self.out_c_c();
+        self.ld_bc_nn(PPI_CONTROL | 0x92);
+        self.out_c_c();
+    }
+
+    /// Read the line of the key_matrix entry at HL, leaving HL on its bit
+    /// and Z set if the key is down
+    fn cpc_key_line(&mut self) {
+        self.ld_a_hl();  // Line, with the PSG reading
+        self.inc_hl();
+        self.ld_b_n((PSG_CONTROL >> 8) as u8);
+        self.ld_c_a();
+        self.out_c_c();
+        self.ld_b_n((PSG_PORT >> 8) as u8);
+        self.in_a_c();
+        self.and_hl();  // Key bit, low when pressed
+    }
+
+    /// Port A back to output, keeping the flags
+    fn cpc_keyboard_off(&mut self) {
+        self.ld_bc_nn(PPI_CONTROL | 0x82);
+        self.out_c_c();
+        self.ld_bc_nn(PSG_CONTROL);
+        self.out_c_c();
+    }
+
+    /// key_down: Z set if the key in A is down. A keyboard's own keys are
+    /// tested one at a time, so both players can hold theirs; a console's
+    /// keys are down from each character typed until the release time
+    /// passes without another.
+    fn generate_key_down(&mut self) {
+        self.label("key_down");
+        self.push_bc();
+        self.push_de();
+        self.push_hl();
+        if self.console_keys() {
+            self.ld_c_a();  // Key wanted
+            self.call_label("get_key");
+            self.call_label("key_seen");
+            self.ld_a_c();
+        }
+        self.cp_n(16);
+        self.jr_nc("key_down_no");
+        if self.console_keys() {
+            self.ld_e_a();
+            self.ld_d_n(0);
+            self.ld_hl_nn(self.ram(KEY_STATE));
+            self.add_hl_de();
+            self.ld_a_hl();
+            self.or_a();
+            self.jr_z("key_down_no");
+            self.xor_a();  // Z
+            self.jr_label("key_down_done");
+        } else {
+            self.add_a_a();
+            self.ld_e_a();
+            self.ld_d_n(0);
+            self.ld_hl_label("key_matrix");
+            self.add_hl_de();
+            match self.target {
+                Target::Spectrum => {
+                    self.ld_a_hl();  // Half-row, selected by the high address byte
+                    self.inc_hl();
+                    self.in_a_n(ULA_PORT);
+                    self.and_hl();
+                }
+                Target::Msx => {
+                    self.in_a_n(PPI_ROW);
+                    self.and_n(0xF0);
+                    self.or_hl();
+                    self.out_n_a(PPI_ROW);
+                    self.inc_hl();
+                    self.in_a_n(PPI_KEYS);
+                    self.and_hl();
+                }
+                _ => {
+                    // The CPC; Master System and Coleco builds refuse players
+                    self.cpc_keyboard_on();
+                    self.cpc_key_line();
+                    self.cpc_keyboard_off();
+                }
+            }
+            self.jr_label("key_down_done");
+        }
+        self.label("key_down_no");
+        self.or_n(1);  // NZ
+        self.label("key_down_done");
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.ret();
+    }
+
+    /// refresh_display drawing the display 4x scaled on the mode 1 screen:
+    /// each pixel becomes a byte of four pixels, each row four lines
+    fn generate_cpc_display(&mut self) {
+        self.label("refresh_display");
+        self.push_bc();
+        self.ld_hl_nn(self.ram(DISPLAY_BUF));
+        self.ld_de_nn(CPC_DISPLAY);
+        self.label("refresh_row");
+        self.push_de();
+        self.label("refresh_byte");
+        self.ld_c_hl();
+        self.ld_b_n(8);
+        self.label("refresh_pixel");
+        self.sla_c();
+        self.sbc_a_a();
+        self.and_n(0xF0);  // Pen 1 when set
+        self.ld_de_a();
+        self.inc_de();
+        self.dec_b();
+        self.jr_nz("refresh_pixel");
+        self.inc_hl();
+        self.ld_a_l();
+        self.and_n(0x07);  // 8 bytes per row
+        self.jr_nz("refresh_byte");
+        // Repeat the line on the three below it, each 0x800 on
+        self.ex_sp_hl();  // HL = start of the line, display pointer saved
+        for _ in 0..3 {
+            self.ld_d_h();
+            self.ld_e_l();
+            self.ld_a_d();
+            self.add_a_n(0x08);
+            self.ld_d_a();
+            self.push_de();
+            self.ld_bc_nn(64);
+            self.ldir();
+            self.pop_hl();
+        }
+        // HL is on pixel line 3 or 7 of a character row. The next row
+        // starts on line 4 of the same one, or line 0 of the next.
+        self.bit_5_h();
+        self.jr_nz("refresh_next_char");
+        self.ld_a_h();
+        self.add_a_n(0x08);
+        self.ld_h_a();
+        self.jr_label("refresh_next");
+        self.label("refresh_next_char");
+        self.ld_de_nn(80u16.wrapping_sub(0x3800));
+        self.add_hl_de();
+        self.label("refresh_next");
+        self.ex_de_hl();
+        self.pop_hl();
+        self.ld_a_l();
+        self.or_a();
+        self.jr_nz("refresh_row");  // Wraps to 0 after the last row
+        self.pop_bc();
+        self.ret();
+    }
+
+    /// Weight of the timer tick for a block running `tstates` of code
+    pub(crate) fn tick_weight(tstates: u32) -> u8 {
+        ((tstates + TICK_OVERHEAD) / TICK_UNIT).clamp(1, 255) as u8
+    }
+
+    /// Fill in the weight operand of the last merge point's tick call
+    /// Patch the cost of a region's instructions into its call to pace
+    fn patch_pace(&mut self, pace_at: Option<usize>, instructions: u32) {
+        if let Some(offset) = pace_at {
+            let cost = (instructions * 60).min(0xFFFF) as u16;
+            self.code[offset..offset + 2].copy_from_slice(&cost.to_le_bytes());
+        }
+    }
+
+    fn patch_tick_weight(&mut self, weight_at: Option<(usize, u32)>) {
+        if let Some((offset, start)) = weight_at {
+            self.code[offset] = Self::tick_weight(self.tstates - start);
+        }
+    }
+
+    /// Split the instructions into blocks, as index ranges, each running
+    /// from one merge point to the next
+    fn blocks(instructions: &[Instruction], merges: &HashSet<u16>) -> Vec<Range<usize>> {
+        let mut blocks: Vec<Range<usize>> = Vec::new();
+        for (i, inst) in instructions.iter().enumerate() {
+            match blocks.last_mut() {
+                Some(block) if !merges.contains(&inst.addr) => block.end = i + 1,
+                _ => blocks.push(i..i + 1),
+            }
+        }
+        blocks
+    }
+
+    /// Block counts behind a hot layout, if one was asked for
+    fn layout_profile(&self, instructions: &[Instruction]) -> Option<Profile> {
+        match &self.layout {
+            Layout::Source => None,
+            Layout::Loops => Some(Profile::estimate(instructions)),
+            Layout::Profile(profile) => Some(profile.clone()),
+        }
+    }
+
+    /// Order in which to compile the instructions, as indices. With a hot
+    /// layout the blocks are sorted by execution count so the busiest code
+    /// sits together at the start of the program, with ties kept in
+    /// address order.
+    fn block_order(instructions: &[Instruction], blocks: &[Range<usize>], profile: Option<&Profile>) -> Vec<usize> {
+        let mut blocks = blocks.to_vec();
+        if let Some(profile) = profile {
+            blocks.sort_by_key(|block| std::cmp::Reverse(profile.block_count(&instructions[block.clone()])));
+        }
+        blocks.into_iter().flatten().collect()
+    }
+
+    /// Start addresses of the blocks that run at least 1/HOT_FRACTION as
+    /// often as the hottest one
+    fn hot_blocks(instructions: &[Instruction], blocks: &[Range<usize>], profile: Option<&Profile>) -> HashSet<u16> {
+        let Some(profile) = profile else {
+            return HashSet::new();
+        };
+        let counts: Vec<(u16, u64)> = blocks
+            .iter()
+            .map(|block| (instructions[block.start].addr, profile.block_count(&instructions[block.clone()])))
+            .collect();
+        let max = counts.iter().map(|&(_, count)| count).max().unwrap_or(0);
+        counts
+            .into_iter()
+            .filter(|&(_, count)| count > 0 && count * HOT_FRACTION >= max)
+            .map(|(addr, _)| addr)
+            .collect()
+    }
+
+    /// Whether A can be carried into hot blocks: the calls that --speed,
+    /// --profile-gen and the debug monitor make as a block is entered
+    /// don't keep it
+    fn carries_a(&self) -> bool {
+        self.speed.is_none() && !self.profile_gen && !self.debug_stub
+    }
+
+    /// V register that A holds on every way into each hot block, by the
+    /// block's CHIP-8 address: from the last pass's jumps and fall-throughs
+    /// into its label and any label at the same place. Any other reference
+    /// to the label (a table, a return address) counts as not knowing A.
+    fn carried_registers(
+        &self,
+        hot: &HashSet<u16>,
+        labels: &HashMap<String, u16>,
+        entries: &HashMap<String, Vec<Option<u16>>>,
+    ) -> HashMap<u16, u16> {
+        if !self.carries_a() {
+            return HashMap::new();
+        }
+        let mut at: HashMap<u16, Vec<Option<u16>>> = HashMap::new();
+        for (label, regs) in entries {
+            if let Some(&z80) = labels.get(label) {
+                at.entry(z80).or_default().extend(regs);
+            }
+        }
+        hot.iter()
+            .filter_map(|&addr| {
+                let regs = at.get(labels.get(&format!("c8_{:03X}", addr))?)?;
+                let reg = (*regs.first()?)?;
+                regs.iter().all(|&r| r == Some(reg)).then_some((addr, reg))
+            })
+            .collect()
+    }
+
+    /// Timer tick with the common path (no frame boundary) inlined, for
+    /// hot blocks. Counts TICK_COUNT down by the weight in DE and calls
+    /// tick_frame once it reaches zero, keeping A for a block that A is
+    /// carried into. Returns the weight operand to patch,
+    /// with its start adjusted so the weight charges INLINE_TICK instead of
+    /// TICK_OVERHEAD and is rounded to the nearest unit (hot blocks tend to
+    /// be short, so truncating would lose a large share of their cycles).
+    fn inline_tick(&mut self, keep_a: bool) -> (usize, u32) {
+        self.ld_hl_mem(self.ram(TICK_COUNT));
+        self.ld_de_nn(0);  // Weight, patched once the region is compiled
+        let offset = self.code.len() - 2;
+        self.scf();
+        self.sbc_hl_de();  // Carry if the count was <= the weight
+        self.inc_hl();
+        self.ld_mem_hl(self.ram(TICK_COUNT));
+        self.call_c_label(if keep_a { "tick_frame_keep_a" } else { "tick_frame" });
+        (offset, self.tstates + TICK_OVERHEAD - INLINE_TICK - TICK_UNIT / 2)
+    }
+
+    /// Trace line for --trace: called with the instruction's address (and
+    /// opcode) as words after the CALL, prints them as `T <addr> [<opcode>]`
+    /// and returns past them with every register kept
+    fn generate_trace(&mut self) {
+        self.label("trace");
+        self.ex_sp_hl();  // HL = the words, the caller's HL on the stack
+        self.push_af();
+        self.push_de();
+        self.ld_a_n(b'T');
+        self.call_label("print_char");
+        let words = if self.trace == Trace::Opcode { 2 } else { 1 };
+        for _ in 0..words {
+            self.ld_e_hl();
+            self.inc_hl();
+            self.ld_d_hl();
+            self.inc_hl();
+            self.ex_de_hl();
+            self.ld_a_n(b' ');
+            self.call_label("print_char");
+            self.call_label("print_hex16");
+            self.ex_de_hl();
+        }
+        self.ld_a_n(b'\r');
+        self.call_label("print_char");
+        self.ld_a_n(b'\n');
+        self.call_label("print_char");
+        self.pop_de();
+        self.pop_af();
+        self.ex_sp_hl();  // Return past the words
+        self.ret();
+    }
+
+    /// Step gate for --step-gate, called instead of debug_block and going
+    /// on into it. While the gate is open it takes any character waiting
+    /// on the port: STOP closes the gate, anything else is left for
+    /// get_key. At a closed gate it prints `Gate ADDR` and the registers,
+    /// then waits for STEP (stop again at the next block), GO (open the
+    /// gate) or Ctrl-B (the monitor). Keeps every register.
+    fn generate_step_gate(&mut self) {
+        let (status, data) = if self.target == Target::Rc2014 { (SIO_CTRL, SIO_DATA) } else { self.acia_ports };
+        self.label("step_gate");
+        self.push_af();
+        self.ld_a_mem(self.ram(STEP_GATE));
+        self.or_a();
+        self.jr_nz("step_gate_stop");
+        self.read_serial(status, data, "step_gate_open");
+        self.cp_n(stepgate::STOP);
+        self.jr_z("step_gate_stop");
+        self.ld_mem_a(self.ram(STEP_KEY));
+        self.label("step_gate_open");
+        self.pop_af();
+        self.jr_label("debug_block");
+
+        self.label("step_gate_stop");
+        self.pop_af();
+        self.ex_sp_hl();  // HL = the address word
+        self.push_af();
+        self.push_bc();
+        self.push_de();
+        self.push_hl();
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        self.ld_hl_label("step_gate_str");
+        self.call_label("print_str");
+        self.ex_de_hl();
+        self.call_label("print_hex16");
+        self.call_label("debug_print_regs");
+        self.label("step_gate_wait");
+        self.call_label("debug_read");
+        self.cp_n(stepgate::STEP);
+        self.jr_z("step_gate_step");
+        self.cp_n(stepgate::GO);
+        self.jr_z("step_gate_go");
+        self.cp_n(DEBUG_ESCAPE);
+        self.jr_nz("step_gate_wait");
+        self.call_label("debug_monitor");
+        self.jr_label("step_gate_wait");
+        self.label("step_gate_step");
+        self.ld_a_n(1);
+        self.jr_label("step_gate_leave");
+        self.label("step_gate_go");
+        self.xor_a();
+        self.label("step_gate_leave");
+        self.ld_mem_a(self.ram(STEP_GATE));
+        self.ld_a_n(b'\r');
+        self.call_label("print_char");
+        self.ld_a_n(b'\n');
+        self.call_label("print_char");
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.pop_af();
+        self.ex_sp_hl();  // As called, for debug_block, which follows
+    }
+
+    /// Debug stub for --debug-stub. `debug_step` is called before each
+    /// instruction with its address as a word after the CALL, and stops in
+    /// the monitor when single-stepping; `debug_block` is called instead
+    /// at the start of each block, and first prints the watches that have
+    /// changed. `debug_monitor` reads one-key commands until told to go on:
+    /// r (registers), s (stack), m ADDR (16 bytes of CHIP-8 memory),
+    /// w WHAT (watch Vx, I or ADDR[..END]), b ADDR (breakpoint on the block
+    /// there, which debug_block stops at), n (step) and c (continue).
+    /// All three keep every register.
+    fn generate_debug_stub(&mut self) {
+        if self.step_gate {
+            self.generate_step_gate();
+        }
+        self.label("debug_block");
+        self.ex_sp_hl();  // HL = the address word, the caller's HL on the stack
+        self.push_af();
+        self.push_bc();
+        self.push_de();
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        self.dec_hl();
+        self.push_hl();
+        self.ld_hl_nn(self.ram(DEBUG_WATCHES));
+        self.ld_b_n(WATCH_SLOTS);
+        self.label("debug_block_slot");
+        self.ld_a_hl();
+        self.or_a();
+        self.jr_z("debug_block_next");
+        self.call_label("debug_watch_check");
+        self.label("debug_block_next");
+        self.ld_a_l();
+        self.add_a_n(WATCH_SIZE);  // The watches don't cross a page
+        self.ld_l_a();
+        self.dec_b();
+        self.jr_nz("debug_block_slot");
+        // Stop in the monitor at a breakpoint on the block
+        self.ld_hl_nn(self.ram(DEBUG_BREAKS));
+        self.ld_b_n(BREAK_SLOTS);
+        self.label("debug_block_break");
+        self.ld_a_hl();
+        self.inc_hl();
+        self.cp_e();
+        self.jr_nz("debug_block_unbroken");
+        self.ld_a_hl();
+        self.cp_d();
+        self.jr_z("debug_block_hit");
+        self.label("debug_block_unbroken");
+        self.inc_hl();
+        self.dec_b();
+        self.jr_nz("debug_block_break");
+        self.jr_label("debug_block_done");
+        self.label("debug_block_hit");
+        self.ld_hl_label("debug_break_str");
+        self.call_label("print_str");
+        self.ex_de_hl();
+        self.call_label("print_hex16");
+        self.call_label("debug_monitor");
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.pop_af();
+        self.inc_hl();
+        self.inc_hl();
+        self.ex_sp_hl();  // Return past the word: `n` stops at the next instruction
+        self.ret();
+        self.label("debug_block_done");
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.pop_af();
+        self.ex_sp_hl();  // As called, for the step check
+
+        self.label("debug_step");
+        self.ex_sp_hl();
+        self.push_af();
+        self.ld_a_mem(self.ram(DEBUG_STEP));
+        self.or_a();
+        self.jr_nz("debug_step_stop");
+        self.pop_af();
+        self.inc_hl();
+        self.inc_hl();
+        self.ex_sp_hl();  // Return past the word
+        self.ret();
+        self.label("debug_step_stop");
+        self.push_de();
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        self.inc_hl();
+        self.push_hl();
+        self.ld_hl_label("debug_stopped_str");
+        self.call_label("print_str");
+        self.ex_de_hl();
+        self.call_label("print_hex16");
+        self.call_label("debug_monitor");
+        self.pop_hl();
+        self.pop_de();
+        self.pop_af();
+        self.ex_sp_hl();
+        self.ret();
+
+        // Compare the watch at HL with the bytes last seen, and print it as
+        // `Watch V4: 07 at 0234` (DE = the block's address) if it changed.
+        // Keeps HL, DE and B.
+        self.label("debug_watch_check");
+        self.push_bc();
+        self.push_de();
+        self.push_hl();
+        self.ld_b_hl();  // Length
+        for _ in 0..2 {
+            self.inc_hl();
+        }
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();  // DE = Z80 address
+        for _ in 0..3 {
+            self.inc_hl();
+        }
+        self.label("debug_watch_compare");
+        self.ld_a_de();
+        self.cp_hl();
+        self.jr_nz("debug_watch_changed");
+        self.inc_de();
+        self.inc_hl();
+        self.dec_b();
+        self.jr_nz("debug_watch_compare");
+        self.jp_label("debug_watch_done");
+        self.label("debug_watch_changed");
+        self.pop_hl();
+        self.push_hl();
+        self.call_label("debug_watch_snap");
+        self.ld_hl_label("debug_watch_str");
+        self.call_label("print_str");
+        self.pop_hl();
+        self.push_hl();
+        self.ld_b_hl();  // Length
+        self.inc_hl();
+        self.ld_c_hl();  // Kind
+        for _ in 0..3 {
+            self.inc_hl();
+        }
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();  // DE = register or CHIP-8 address
+        self.inc_hl();   // HL = the new bytes
+        self.ld_a_c();
+        self.cp_n(b'V');
+        self.jr_nz("debug_watch_not_v");
+        self.call_label("print_char");
+        self.ld_a_e();
+        self.call_label("print_nibble");
+        self.jr_label("debug_watch_bytes");
+        self.label("debug_watch_not_v");
+        self.cp_n(b'I');
+        self.jr_nz("debug_watch_mem");
+        self.call_label("print_char");
+        self.ld_a_n(b':');
+        self.call_label("print_char");
+        self.ld_a_n(b' ');
+        self.call_label("print_char");
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        self.ex_de_hl();
+        self.call_label("print_hex16");
+        self.jr_label("debug_watch_at");
+        self.label("debug_watch_mem");
+        self.ex_de_hl();
+        self.call_label("print_hex16");
+        self.ex_de_hl();
+        self.label("debug_watch_bytes");
+        self.ld_a_n(b':');
+        self.call_label("print_char");
+        self.call_label("debug_bytes");
+        self.label("debug_watch_at");
+        self.ld_hl_label("debug_at_str");
+        self.call_label("print_str");
+        self.pop_hl();
+        self.pop_de();
+        self.push_de();
+        self.push_hl();
+        self.ex_de_hl();
+        self.call_label("print_hex16");
+        self.label("debug_watch_done");
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.ret();
+
+        // Copy the watched bytes of the watch at HL to its last seen ones.
+        // Clobbers A, BC, DE, HL.
+        self.label("debug_watch_snap");
+        self.ld_c_hl();  // Length
+        self.ld_b_n(0);
+        self.inc_hl();
+        self.inc_hl();
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        for _ in 0..3 {
+            self.inc_hl();
+        }
+        self.ex_de_hl();  // HL = watched bytes, DE = last seen
+        self.ldir();
+        self.ret();
+
+        self.label("debug_monitor");
+        self.push_af();
+        self.push_bc();
+        self.push_de();
+        self.push_hl();
+        self.label("debug_prompt");
+        self.ld_hl_label("debug_prompt_str");
+        self.call_label("print_str");
+        self.call_label("debug_read");
+        self.call_label("print_char");
+        self.or_n(0x20);  // Lower case
+        for (key, label) in [
+            (b'r', "debug_regs"),
+            (b's', "debug_stack"),
+            (b'm', "debug_mem"),
+            (b'w', "debug_watch"),
+            (b'b', "debug_break"),
+            (b'n', "debug_next"),
+            (b'c', "debug_go"),
+        ] {
+            self.cp_n(key);
+            self.jp_z_label(label);
+        }
+        self.ld_hl_label("debug_help_str");
+        self.call_label("print_str");
+        self.jr_label("debug_prompt");
+        self.label("debug_next");
+        self.ld_a_n(1);
+        self.jr_label("debug_leave");
+        self.label("debug_go");
+        self.xor_a();
+        self.label("debug_leave");
+        self.ld_mem_a(self.ram(DEBUG_STEP));
+        if self.console_keys() {
+            // Keys held before the stop were let go while the monitor ran
+            self.ld_hl_nn(self.ram(KEY_STATE));
+            self.ld_bc_nn(16);
+            self.xor_a();
+            self.call_label("memset");
+        }
+        self.ld_a_n(b'\r');
+        self.call_label("print_char");
+        self.ld_a_n(b'\n');
+        self.call_label("print_char");
+        self.pop_hl();
+        self.pop_de();
+        self.pop_bc();
+        self.pop_af();
+        self.ret();
+
+        self.label("debug_regs");
+        self.call_label("debug_print_regs");
+        self.jp_label("debug_prompt");
+
+        // V 00 01 ... 0F, then I, the timers and the stack depth.
+        // Clobbers A, B, HL.
+        self.label("debug_print_regs");
+        self.ld_hl_label("debug_v_str");
+        self.call_label("print_str");
+        self.ld_hl_nn(self.ram(CHIP8_V0));
+        self.ld_b_n(16);
+        self.call_label("debug_bytes");
+        self.ld_hl_label("debug_i_str");
+        self.call_label("print_str");
+        self.ld_hl_mem(self.ram(CHIP8_I));
+        self.call_label("print_hex16");
+        for (text, addr) in [("debug_dt_str", CHIP8_DT), ("debug_st_str", CHIP8_ST), ("debug_sp_str", CHIP8_SP)] {
+            self.ld_hl_label(text);
+            self.call_label("print_str");
+            self.ld_a_mem(self.ram(addr));
+            self.call_label("print_hex8");
+        }
+        self.ret();
+
+        // Z80 return addresses on the CHIP-8 stack, oldest first
+        self.label("debug_stack");
+        self.ld_hl_label("debug_s_str");
+        self.call_label("print_str");
+        self.ld_a_mem(self.ram(CHIP8_SP));
+        self.or_a();
+        self.jp_z_label("debug_prompt");
+        self.ld_b_a();
+        self.ld_hl_nn(self.ram(CHIP8_STACK));
+        self.label("debug_stack_loop");
+        self.ld_a_n(b' ');
+        self.call_label("print_char");
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        self.inc_hl();
+        self.ex_de_hl();
+        self.call_label("print_hex16");
+        if self.symbol_table {
+            // =<CHIP-8 address> the return point is the code of
+            self.call_label("symbolicate");
+            self.jr_z("debug_stack_next");
+            self.ld_a_n(b'=');
+            self.call_label("print_char");
+            self.call_label("print_hex16");
+            self.label("debug_stack_next");
+        }
+        self.ex_de_hl();
+        self.dec_b();
+        self.jr_nz("debug_stack_loop");
+        self.jp_label("debug_prompt");
+
+        // Hex address up to Enter, then 16 bytes of CHIP-8 RAM from there
+        self.label("debug_mem");
+        self.call_label("debug_read_hex");
+        self.cp_n(b'\r');
+        self.jp_nz_label("debug_bad");
+        self.call_label("debug_chip8_ram");
+        self.jp_c_label("debug_bad");
+        self.ex_de_hl();
+        self.ld_a_n(b'\r');
+        self.call_label("print_char");
+        self.ld_a_n(b'\n');
+        self.call_label("print_char");
+        self.call_label("print_hex16");
+        self.ld_a_n(b':');
+        self.call_label("print_char");
+        self.ex_de_hl();
+        self.ld_b_n(16);
+        self.call_label("debug_bytes");
+        self.jp_label("debug_prompt");
+
+        // Watch Vx, I or ADDR[..END], up to WATCH_BYTES bytes; Enter alone
+        // drops every watch
+        self.label("debug_watch");
+        self.call_label("debug_read");
+        self.cp_n(b'\r');
+        self.jr_z("debug_watch_clear");
+        self.call_label("print_char");
+        self.or_n(0x20);
+        self.cp_n(b' ');
+        self.jr_z("debug_watch");
+        self.cp_n(b'v');
+        self.jr_z("debug_watch_v");
+        self.cp_n(b'i');
+        self.jr_z("debug_watch_i");
+        self.call_label("debug_hex_from");
+        self.ld_d_h();
+        self.ld_e_l();  // DE = start
+        self.cp_n(b'\r');
+        self.jr_z("debug_watch_range");
+        self.cp_n(b'.');
+        self.jp_nz_label("debug_bad");
+        self.call_label("debug_read");
+        self.cp_n(b'.');
+        self.jp_nz_label("debug_bad");
+        self.call_label("print_char");
+        self.push_de();
+        self.call_label("debug_read_hex");
+        self.pop_de();
+        self.cp_n(b'\r');
+        self.jp_nz_label("debug_bad");
+        self.label("debug_watch_range");
+        self.or_a();
+        self.sbc_hl_de();  // HL = END - start
+        self.jp_c_label("debug_bad");
+        self.ld_a_h();
+        self.or_a();
+        self.jp_nz_label("debug_bad");
+        self.ld_a_l();
+        self.cp_n(WATCH_BYTES);
+        self.jr_nc("debug_bad");
+        self.inc_a();
+        self.ld_b_a();  // Length
+        self.ex_de_hl();
+        self.call_label("debug_chip8_ram");
+        self.jp_c_label("debug_bad");
+        self.ld_c_n(b'M');
+        self.jr_label("debug_watch_add");
+        self.label("debug_watch_v");
+        self.call_label("debug_read_hex");
+        self.cp_n(b'\r');
+        self.jp_nz_label("debug_bad");
+        self.ld_a_h();
+        self.or_a();
+        self.jp_nz_label("debug_bad");
+        self.ld_a_l();
+        self.cp_n(16);
+        self.jr_nc("debug_bad");
+        self.ex_de_hl();  // DE = register
+        self.ld_hl_nn(self.ram(CHIP8_V0));
+        self.add_hl_de();
+        self.ld_bc_nn(0x0100 | b'V' as u16);
+        self.jr_label("debug_watch_add");
+        self.label("debug_watch_i");
+        self.call_label("debug_read");
+        self.cp_n(b'\r');
+        self.jp_nz_label("debug_bad");
+        self.ld_hl_nn(self.ram(CHIP8_I));
+        self.ld_bc_nn(0x0200 | b'I' as u16);
+        // HL = Z80 address, DE = register or CHIP-8 address, B = length,
+        // C = kind: fill the first free slot
+        self.label("debug_watch_add");
+        self.push_hl();
+        self.push_de();
+        self.ld_hl_nn(self.ram(DEBUG_WATCHES));
+        self.ld_de_nn(WATCH_SIZE as u16);
+        self.label("debug_watch_find");
+        self.ld_a_hl();
+        self.or_a();
+        self.jr_z("debug_watch_free");
+        self.add_hl_de();
+        self.ld_a_l();
+        self.cp_n((self.ram(DEBUG_WATCHES) as u8).wrapping_add(WATCH_SLOTS * WATCH_SIZE));
+        self.jr_nz("debug_watch_find");
+        self.pop_de();
+        self.pop_hl();
+        self.ld_hl_label("debug_full_str");
+        self.call_label("print_str");
+        self.jp_label("debug_prompt");
+        self.label("debug_watch_free");
+        self.pop_de();
+        self.ex_sp_hl();  // HL = Z80 address, the slot on the stack
+        self.ex_de_hl();  // DE = Z80 address, HL = register or CHIP-8 address
+        self.ex_sp_hl();  // HL = slot, the register or CHIP-8 address on the stack
+        self.push_hl();
+        self.ld_hl_b();
+        self.inc_hl();
+        self.ld_hl_c();
+        self.inc_hl();
+        self.ld_hl_e();
+        self.inc_hl();
+        self.ld_hl_d();
+        self.inc_hl();
+        self.pop_bc();    // BC = slot
+        self.pop_de();    // DE = register or CHIP-8 address
+        self.ld_hl_e();
+        self.inc_hl();
+        self.ld_hl_d();
+        self.ld_h_b();
+        self.ld_l_c();
+        self.call_label("debug_watch_snap");
+        self.jp_label("debug_prompt");
+        self.label("debug_watch_clear");
+        self.ld_hl_nn(self.ram(DEBUG_WATCHES));
+        self.ld_bc_nn(WATCH_SLOTS as u16 * WATCH_SIZE as u16);
+        self.xor_a();
+        self.call_label("memset");
+        self.jp_label("debug_prompt");
+
+        // Toggle a breakpoint on the block at a hex address; Enter alone
+        // drops them all. Then list them: `B 0234 0246`
+        self.label("debug_break");
+        self.call_label("debug_read_hex");
+        self.cp_n(b'\r');
+        self.jp_nz_label("debug_bad");
+        self.ld_a_h();
+        self.or_l();
+        self.jr_z("debug_break_clear");
+        self.ex_de_hl();  // DE = address
+        self.ld_hl_nn(self.ram(DEBUG_BREAKS));
+        self.ld_b_n(BREAK_SLOTS);
+        self.label("debug_break_find");
+        self.ld_a_hl();
+        self.inc_hl();
+        self.cp_e();
+        self.jr_nz("debug_break_other");
+        self.ld_a_hl();
+        self.cp_d();
+        self.jr_z("debug_break_set");  // Set already: drop it
+        self.label("debug_break_other");
+        self.inc_hl();
+        self.dec_b();
+        self.jr_nz("debug_break_find");
+        self.ld_hl_nn(self.ram(DEBUG_BREAKS) + 1);
+        self.ld_b_n(BREAK_SLOTS);
+        self.label("debug_break_free");
+        self.ld_a_hl();
+        self.dec_hl();
+        self.or_hl();
+        self.inc_hl();
+        self.jr_z("debug_break_add");
+        self.inc_hl();
+        self.inc_hl();
+        self.dec_b();
+        self.jr_nz("debug_break_free");
+        self.ld_hl_label("debug_full_str");
+        self.call_label("print_str");
+        self.jp_label("debug_prompt");
+        self.label("debug_break_set");
+        self.ld_de_nn(0);
+        self.label("debug_break_add");
+        self.ld_hl_d();
+        self.dec_hl();
+        self.ld_hl_e();
+        self.jr_label("debug_break_list");
+        self.label("debug_break_clear");
+        self.ld_hl_nn(self.ram(DEBUG_BREAKS));
+        self.ld_bc_nn(2 * BREAK_SLOTS as u16);
+        self.xor_a();
+        self.call_label("memset");
+        self.label("debug_break_list");
+        self.ld_hl_label("debug_b_str");
+        self.call_label("print_str");
+        self.ld_hl_nn(self.ram(DEBUG_BREAKS));
+        self.ld_b_n(BREAK_SLOTS);
+        self.label("debug_break_show");
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        self.inc_hl();
+        self.ld_a_d();
+        self.or_e();
+        self.jr_z("debug_break_next");
+        self.ld_a_n(b' ');
+        self.call_label("print_char");
+        self.ex_de_hl();
+        self.call_label("print_hex16");
+        self.ex_de_hl();
+        self.label("debug_break_next");
+        self.dec_b();
+        self.jr_nz("debug_break_show");
+        self.jp_label("debug_prompt");
+
+        self.label("debug_bad");
+        self.ld_a_n(b'?');
+        self.call_label("print_char");
+        self.jp_label("debug_prompt");
+
+        // Z80 address in HL of the CHIP-8 RAM at HL, kept in DE; carry set
+        // if it is outside CHIP-8 RAM
+        self.label("debug_chip8_ram");
+        self.ld_a_h();
+        self.cp_n(0x02);
+        self.ret_c();
+        self.cp_n((self.mem_top() >> 8) as u8);
+        self.ccf();
+        self.ret_c();
+        self.ld_d_h();
+        self.ld_e_l();
+        self.push_de();
+        self.ld_de_nn(self.ram(CHIP8_RAM) - 0x200);
+        self.add_hl_de();  // Clears carry
+        self.pop_de();
+        self.ret();
+
+        // Read hex digits (echoed, 'x' ignored) into HL, returning the
+        // character that ended them in A, lower case unless it is Enter.
+        // `debug_hex_from` starts from the echoed character in A.
+        // Clobbers C.
+        self.label("debug_read_hex");
+        self.ld_hl_nn(0);
+        self.call_label("debug_read");
+        self.cp_n(b'\r');
+        self.ret_z();
+        self.call_label("print_char");
+        self.jr_label("debug_hex_digit");
+        self.label("debug_hex_from");
+        self.ld_hl_nn(0);
+        self.label("debug_hex_digit");
+        self.or_n(0x20);  // Lower case; digits already have bit 5 set
+        self.cp_n(b'x');
+        self.jr_z("debug_hex_next");
+        self.ld_c_a();
+        self.sub_n(b'0');
+        self.jr_c("debug_hex_end");
+        self.cp_n(10);
+        self.jr_c("debug_hex_add");
+        self.sub_n(b'a' - b'0');
+        self.jr_c("debug_hex_end");
+        self.cp_n(6);
+        self.jr_nc("debug_hex_end");
+        self.add_a_n(10);
+        self.label("debug_hex_add");
+        for _ in 0..4 {
+            self.add_hl_hl();
+        }
+        self.or_l();
+        self.ld_l_a();
+        self.label("debug_hex_next");
+        self.call_label("debug_read");
+        self.cp_n(b'\r');
+        self.ret_z();
+        self.call_label("print_char");
+        self.jr_label("debug_hex_digit");
+        self.label("debug_hex_end");
+        self.ld_a_c();
+        self.ret();
+
+        // Print B bytes from HL, each after a space
+        self.label("debug_bytes");
+        self.ld_a_n(b' ');
+        self.call_label("print_char");
+        self.ld_a_hl();
+        self.call_label("print_hex8");
+        self.inc_hl();
+        self.dec_b();
+        self.jr_nz("debug_bytes");
+        self.ret();
+
+        // Wait for a character from the console, without mapping it to a key
+        self.label("debug_read");
+        match self.target {
+            Target::Cpm => {
+                self.push_bc();
+                self.push_de();
+                self.push_hl();
+                self.label("debug_read_wait");
+                self.ld_e_n(0xFF);
+                self.ld_c_n(DIRECT_IO);
+                self.call_nn(BDOS);
+                self.or_a();
+                self.jr_z("debug_read_wait");
+                self.pop_hl();
+                self.pop_de();
+                self.pop_bc();
+                self.ret();
+            }
+            _ => {
+                let (status, data) = if self.target == Target::Rc2014 { (SIO_CTRL, SIO_DATA) } else { self.acia_ports };
+                self.read_serial(status, data, "debug_read");
+                self.ret();
+            }
+        }
+
+        for (label, text) in [
+            ("debug_stopped_str", "\r\nStopped at "),
+            ("debug_prompt_str", "\r\ndebug> "),
+            ("debug_help_str", " r regs, s stack, m ADDR memory, w WHAT watch, b ADDR break, n step, c continue"),
+            ("debug_v_str", "\r\nV"),
+            ("debug_i_str", "\r\nI "),
+            ("debug_dt_str", " DT "),
+            ("debug_st_str", " ST "),
+            ("debug_sp_str", " SP "),
+            ("debug_s_str", "\r\nS"),
+            ("debug_watch_str", "\r\nWatch "),
+            ("debug_at_str", " at "),
+            ("debug_full_str", " full"),
+            ("debug_break_str", "\r\nBreak at "),
+            ("debug_b_str", "\r\nB"),
+            ("step_gate_str", "\r\nGate "),
+        ] {
+            self.label(label);
+            for b in text.bytes() {
+                self.emit(b);
+            }
+            self.emit(0);
+        }
+    }
+
+    /// Block counters for --profile-gen: `profile_count` bumps the 32-bit
+    /// counter at HL, and `profile_dump` prints every counter as
+    /// `P <block> <count>` lines for `profile-convert`
+    fn generate_profile(&mut self, blocks: &[u16]) {
+        // Clobbers HL
+        self.label("profile_count");
+        for i in 0..4 {
+            self.inc_hl_ind();
+            if i < 3 {
+                self.ret_nz();
+                self.inc_hl();
+            }
+        }
+        self.ret();
+
+        // Clobbers A, HL
+        self.label("profile_dump");
+        self.push_bc();
+        self.push_de();
+        self.ld_hl_label("profile_blocks");
+        self.ld_de_nn(PROFILE_COUNTERS);
+        self.label("profile_dump_loop");
+        self.ld_c_hl();  // Block address, low byte
+        self.inc_hl();
+        self.ld_a_hl();
+        self.inc_hl();
+        self.cp_n(0xFF);  // End of table
+        self.jr_z("profile_dump_done");
+        self.push_hl();
+        self.ld_h_a();
+        self.ld_l_c();
+        self.ld_a_n(b'P');
+        self.call_label("print_char");
+        self.ld_a_n(b' ');
+        self.call_label("print_char");
+        self.call_label("print_hex16");
+        self.ld_a_n(b' ');
+        self.call_label("print_char");
+        // Counter, most significant byte first
+        self.ex_de_hl();
+        self.inc_hl();
+        self.inc_hl();
+        self.inc_hl();
+        self.ld_b_n(4);
+        self.label("profile_dump_byte");
+        self.ld_a_hl();
+        self.call_label("print_hex8");
+        self.dec_hl();
+        self.dec_b();
+        self.jr_nz("profile_dump_byte");
+        self.ld_de_nn(5);
+        self.add_hl_de();
+        self.ex_de_hl();  // DE = next counter
+        self.ld_a_n(b'\r');
+        self.call_label("print_char");
+        self.ld_a_n(b'\n');
+        self.call_label("print_char");
+        self.pop_hl();
+        self.jr_label("profile_dump_loop");
+        self.label("profile_dump_done");
+        self.pop_de();
+        self.pop_bc();
+        self.ret();
+
+        // CHIP-8 address of each counter's block
+        self.label("profile_blocks");
+        for &addr in blocks {
+            self.emit16(addr);
+        }
+        self.emit16(0xFFFF);
+    }
+
+    /// CHIP-8 addresses reachable other than by falling through from the
+    /// previous instruction: jump, call and skip targets, return points,
+    /// and every instruction when BNNN can jump anywhere
+    fn merge_points(&self, instructions: &[Instruction]) -> HashSet<u16> {
+        let mut merges = HashSet::new();
+        merges.insert(0x200);
+        let computed_jumps = instructions.iter().any(|inst| inst.opcode >> 12 == 0xB);
+        for inst in instructions {
+            if computed_jumps {
+                merges.insert(inst.addr);
+            }
+            match inst.nibbles() {
+                (0x1, _, _, _) => {
+                    merges.insert(inst.nnn());
+                }
+                (0x2, _, _, _) => {
+                    merges.insert(inst.nnn());
+                    merges.insert(inst.addr + 2);
+                }
+                (0x3, _, _, _) | (0x4, _, _, _) | (0x5, _, _, 0x0) | (0x9, _, _, 0x0)
+                | (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1) => {
+                    merges.insert(self.skip_target(inst));
+                }
+                _ => {}
+            }
+        }
+        merges
+    }
+
+    /// Reorder straight-line code so that an arithmetic op sits directly
+    /// before the `3X00`/`4X00` skip that tests its result, letting the skip
+    /// reuse the Z flag instead of reloading the register. Only an
+    /// independent register op is moved, and only when nothing can jump
+    /// into the middle of the group.
+    fn schedule(&self, instructions: &[Instruction], merges: &HashSet<u16>) -> Vec<Instruction> {
+        let mut order = instructions.to_vec();
+        let mut i = 0;
+        while i + 2 < order.len() {
+            let (producer, mover, skip) = (&order[i], &order[i + 1], &order[i + 2]);
+            let straight = mover.addr == producer.addr + producer.size()
+                && skip.addr == mover.addr + mover.size();
+            let tested = match skip.nibbles() {
+                (0x3, x, 0, 0) | (0x4, x, 0, 0) => Some(self.ram(CHIP8_V0) + x as u16),
+                _ => None,
+            };
+            let swap = straight
+                && !merges.contains(&mover.addr)
+                && !merges.contains(&skip.addr)
+                && tested.is_some()
+                && self.z_result(producer) == tested
+                && match (self.reg_effects(producer), self.reg_effects(mover)) {
+                    (Some((pr, pw)), Some((mr, mw))) => pw & (mr | mw) == 0 && mw & pr == 0,
+                    _ => false,
+                };
+            if swap {
+                order.swap(i, i + 1);
+                i += 3;
+            } else {
+                i += 1;
+            }
+        }
+        order
+    }
+
+    /// Registers read and written by a plain register op, as bitmasks
+    /// (bit 16 = I). None for anything with control flow or memory effects.
+    fn reg_effects(&self, inst: &Instruction) -> Option<(u32, u32)> {
+        let bit = |r: u8| 1u32 << r;
+        let vf = bit(0xF);
+        let (_, x, y, _) = inst.nibbles();
+        match inst.nibbles() {
+            (0x6, _, _, _) => Some((0, bit(x))),
+            (0x7, _, _, _) => Some((bit(x), bit(x))),
+            (0x8, _, _, 0x0) => Some((bit(y), bit(x))),
+            (0x8, _, _, 0x1) | (0x8, _, _, 0x2) | (0x8, _, _, 0x3) => {
+                let reset = if self.quirks.vf_reset { vf } else { 0 };
+                Some((bit(x) | bit(y), bit(x) | reset))
+            }
+            (0x8, _, _, 0x4) | (0x8, _, _, 0x5) | (0x8, _, _, 0x7) => Some((bit(x) | bit(y), bit(x) | vf)),
+            (0x8, _, _, 0x6) | (0x8, _, _, 0xE) => {
+                let src = if self.quirks.shift_vy { y } else { x };
+                Some((bit(src), bit(x) | vf))
+            }
+            (0xA, _, _, _) => Some((0, 1 << 16)),
+            (0xC, _, _, _) => Some((0, bit(x))),
+            _ => None,
+        }
+    }
+
+    /// Register whose value an instruction leaves reflected in the Z flag
+    fn z_result(&self, inst: &Instruction) -> Option<u16> {
+        let x = self.ram(CHIP8_V0) + inst.x() as u16;
+        match inst.nibbles() {
+            (0x7, _, _, _) | (0xC, _, _, _) => Some(x),
+            (0x8, _, _, 0x1) | (0x8, _, _, 0x2) | (0x8, _, _, 0x3) => {
+                Some(if self.quirks.vf_reset { self.ram(CHIP8_V0) + 0xF } else { x })
+            }
+            (0x8, _, _, 0x4) | (0x8, _, _, 0x5) | (0x8, _, _, 0x6) | (0x8, _, _, 0x7) | (0x8, _, _, 0xE) => {
+                Some(self.ram(CHIP8_V0) + 0xF)
+            }
+            _ => None,
+        }
+    }
+
+    /// Find draws whose refresh can be dropped or shared:
+    /// - a draw followed in the same block by another draw, with only
+    ///   register and I updates in between, leaves the refresh to the last;
+    /// - `DRW; SE/SNE VF; DRW` at the same location (draw, then undo on
+    ///   collision) refreshes once, after whichever path was taken.
+    fn plan_refreshes(&self, instructions: &[Instruction], merges: &HashSet<u16>) -> HashMap<u16, RefreshPlan> {
+        let is_draw = |inst: &Instruction| inst.opcode >> 12 == 0xD;
+        let follows = |a: &Instruction, b: &Instruction| b.addr == a.addr + a.size() && !merges.contains(&b.addr);
+        let mut plan = HashMap::new();
+
+        for (i, inst) in instructions.iter().enumerate() {
+            if !is_draw(inst) || plan.contains_key(&inst.addr) {
+                continue;
+            }
+
+            // Draw / test VF / redraw at the same place
+            if let [_, skip, redraw, ..] = &instructions[i..] {
+                let tests_vf = matches!(skip.nibbles(), (0x3, 0xF, _, _) | (0x4, 0xF, _, _));
+                if tests_vf && redraw.opcode == inst.opcode && follows(inst, skip) && follows(skip, redraw) {
+                    let join = format!("draw_join_{:03X}", inst.addr);
+                    plan.insert(inst.addr, RefreshPlan::Deferred);
+                    plan.insert(skip.addr, RefreshPlan::SkipTo(join.clone()));
+                    plan.insert(redraw.addr, RefreshPlan::JoinAt(join));
+                    continue;
+                }
+            }
+
+            // Another draw later in the same block
+            let mut prev = inst;
+            for next in &instructions[i + 1..] {
+                if !follows(prev, next) {
+                    break;
+                }
+                if is_draw(next) {
+                    plan.insert(inst.addr, RefreshPlan::Deferred);
+                    break;
+                }
+                let quiet = self.reg_effects(next).is_some()
+                    || matches!(next.nibbles(), (0xF, _, 0x1, 0xE) | (0xF, _, 0x2, 0x9))
+                    || next.operand.is_some();
+                if !quiet {
+                    break;
+                }
+                prev = next;
+            }
+        }
+        plan
+    }
+
+    /// HL = display buffer address of the byte (Vx, Vy) is in. Coordinates
+    /// known at compile time are folded into the address.
+    fn draw_screen_addr(&mut self, x: u8, y: u8) {
+        let row = self.v_const[y as usize].map(|vy| (vy & 0x1F) as u16 * 8);
+        let column = self.v_const[x as usize].map(|vx| ((vx & 0x3F) >> 3) as u16);
+        match (row, column) {
+            (Some(row), Some(column)) => {
+                self.ld_hl_nn(self.ram(DISPLAY_BUF) + row + column);
+                return;
+            }
+            (Some(row), None) => {
+                self.ld_a_mem(self.ram(CHIP8_V0) + x as u16);
+                self.and_n(0x3F);  // Wrap X
+                self.srl_a();
+                self.srl_a();
+                self.srl_a();
+                self.ld_l_a();
+                self.ld_h_n(0);
+                self.ld_de_nn(self.ram(DISPLAY_BUF) + row);
+                self.add_hl_de();
+                return;
+            }
+            (None, Some(column)) => {
+                self.ld_a_mem(self.ram(CHIP8_V0) + y as u16);
+                self.and_n(0x1F);  // Wrap Y
+                self.ld_l_a();
+                self.ld_h_n(0);
+                self.add_hl_hl();
+                self.add_hl_hl();
+                self.add_hl_hl();
+                self.ld_de_nn(self.ram(DISPLAY_BUF) + column);
+                self.add_hl_de();
+                return;
+            }
+            (None, None) => {}
+        }
+
+        // Calculate screen address: (Vy * 8) + (Vx / 8) + DISPLAY_BUF
+        self.ld_a_mem(self.ram(CHIP8_V0) + y as u16);
+        self.and_n(0x1F);  // Wrap Y
+        self.ld_l_a();
+        self.ld_h_n(0);
+        // *8 (8 bytes per row)
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.add_hl_hl();
+        // Add X/8
+        self.ld_a_mem(self.ram(CHIP8_V0) + x as u16);
+        self.and_n(0x3F);  // Wrap X
+        self.srl_a();  // Divide by 2
+        self.srl_a();  // Divide by 4
+        self.srl_a();  // Divide by 8
+        self.ld_e_a();
+        self.ld_d_n(0);
+        self.add_hl_de();
+        self.ld_de_nn(self.ram(DISPLAY_BUF));
+        self.add_hl_de();
+    }
+
+    /// A = Vx & 7, the bits a sprite is right of its first display byte
+    fn draw_shift(&mut self, x: u8) {
+        match self.v_const[x as usize] {
+            Some(vx) => self.ld_a_n(vx & 0x07),
+            None => {
+                self.ld_a_mem(self.ram(CHIP8_V0) + x as u16);
+                self.and_n(0x07);
+            }
+        }
+    }
+
+    /// HL = Z80 address of the sprite at I (font or CHIP-8 RAM)
+    fn draw_sprite_addr(&mut self, inst: &Instruction) {
+        // Get sprite address from I
+        self.ld_hl_nn(self.ram(CHIP8_I));
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        // Add FONT_DATA base if I < 0x50 (font sprite)
+        // Use unique labels per DRW to avoid conflicts
+        let not_font_label = format!("draw_not_font_{:03X}", inst.addr);
+        let have_sprite_label = format!("draw_have_sprite_{:03X}", inst.addr);
+        self.ld_a_d();
+        self.or_a();
+        self.jr_nz(&not_font_label);
+        self.ld_a_e();
+        self.cp_n(0x50);  // Font data is 0-0x50
+        self.jr_nc(&not_font_label);
+        // Font sprite: HL = FONT_DATA + I
+        self.ld_hl_nn(self.ram(FONT_DATA));
+        self.add_hl_de();
+        self.jr_label(&have_sprite_label);
+        self.label(&not_font_label);
+        if self.quirks.display_ram {
+            // Sprite from the display itself: HL = DISPLAY_BUF + I - 0xF00
+            let rom_label = format!("draw_rom_sprite_{:03X}", inst.addr);
+            self.ld_a_d();
+            self.cp_n(0x0F);
+            self.jr_nz(&rom_label);
+            self.ld_h_n((self.ram(DISPLAY_BUF) >> 8) as u8);
+            self.ld_l_e();
+            self.jr_label(&have_sprite_label);
+            self.label(&rom_label);
+        }
+        // Custom sprite: I is CHIP-8 address (>= 0x200)
+        // Convert to Z80 address: CHIP8_RAM + (I - 0x200)
+        // Since CHIP8_RAM corresponds to CHIP-8 0x200, we just add the offset
+        self.ld_hl_nn(0x200);  // Subtract CHIP-8 base
+        self.ex_de_hl();       // DE = 0x200, HL = I
+        self.or_a();           // Clear carry
+        self.sbc_hl_de();      // HL = I - 0x200
+        self.ex_de_hl();       // DE = I - 0x200
+        self.ld_hl_nn(self.ram(CHIP8_RAM));  // The ROM is copied there at startup
+        self.add_hl_de();      // HL = CHIP8_RAM + (I - 0x200)
+        self.label(&have_sprite_label);
+    }
+
+    /// Find the sprite-move idiom: a draw (erasing the sprite), updates
+    /// to its coordinates, then the same draw again. Nothing in between
+    /// may touch I or VF, since the erase's collision result is dropped.
+    fn plan_moves(&self, instructions: &[Instruction], merges: &HashSet<u16>) -> HashMap<u16, MovePlan> {
+        let mut plan = HashMap::new();
+        for (i, erase) in instructions.iter().enumerate() {
+            if erase.opcode >> 12 != 0xD
+                || plan.contains_key(&erase.addr)
+                || matches!(self.refresh_plan.get(&erase.addr), Some(RefreshPlan::JoinAt(_)))
+            {
+                continue;
+            }
+            let mut prev = erase;
+            for next in &instructions[i + 1..] {
+                if next.addr != prev.addr + prev.size() || merges.contains(&next.addr) {
+                    break;
+                }
+                if next.opcode == erase.opcode {
+                    plan.insert(erase.addr, MovePlan::Erase);
+                    plan.insert(next.addr, MovePlan::Redraw);
+                    break;
+                }
+                match self.reg_effects(next) {
+                    Some((reads, writes)) if (reads | writes) & (1 << 0xF | 1 << 16) == 0 => prev = next,
+                    _ => break,
+                }
+            }
+        }
+        plan
+    }
+
+    /// Find key menus: an FX0A followed by a ladder of `SNE Vx, key` and
+    /// `JP entry` pairs, ending with a jump back to the FX0A to wait again.
+    /// Only the FX0A may lead into the ladder, and computed jumps (which
+    /// could land anywhere in it) rule menus out.
+    fn plan_menus(&self, instructions: &[Instruction]) -> HashMap<u16, MenuPlan> {
+        let mut plan = HashMap::new();
+        if self.fallback == Fallback::Interp || instructions.iter().any(|inst| inst.opcode >> 12 == 0xB) {
+            return plan;
+        }
+        for (i, wait) in instructions.iter().enumerate() {
+            let (0xF, x, 0x0, 0xA) = wait.nibbles() else {
+                continue;
+            };
+            let mut entries = Vec::new();
+            let mut end = None;
+            let mut j = i + 1;
+            while let Some(test) = instructions.get(j) {
+                if test.addr != instructions[j - 1].addr + 2 {
+                    break;
+                }
+                match (test.nibbles(), instructions.get(j + 1)) {
+                    ((0x4, tx, _, _), Some(jump))
+                        if tx == x && test.nn() < 16 && jump.addr == test.addr + 2 && jump.opcode >> 12 == 0x1 =>
+                    {
+                        entries.push((test.nn(), jump.nnn()));
+                        j += 2;
+                    }
+                    ((0x1, _, _, _), _) if test.nnn() == wait.addr => {
+                        end = Some(j);
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+            let Some(end) = end else {
+                continue;
+            };
+            let ladder: HashSet<u16> = instructions[i + 1..=end].iter().map(|inst| inst.addr).collect();
+            let entered = instructions
+                .iter()
+                .filter(|inst| inst.addr != wait.addr && !ladder.contains(&inst.addr))
+                .any(|inst| self.successors(inst).iter().any(|addr| ladder.contains(addr)));
+            if entries.len() < 2 || entered || entries.iter().any(|(_, target)| ladder.contains(target)) {
+                continue;
+            }
+            plan.insert(wait.addr, MenuPlan::Wait(entries));
+            for addr in ladder {
+                plan.insert(addr, MenuPlan::Ladder);
+            }
+        }
+        plan
+    }
+
+    /// CHIP-8 addresses an instruction can continue at
+    fn successors(&self, inst: &Instruction) -> Vec<u16> {
+        let mut next = Vec::new();
+        if chip8::falls_through(inst) {
+            next.push(inst.addr + inst.size());
+        }
+        match inst.nibbles() {
+            (0x1, _, _, _) | (0x2, _, _, _) => next.push(inst.nnn()),
+            (0x3, _, _, _) | (0x4, _, _, _) | (0x5, _, _, 0x0) | (0x9, _, _, 0x0)
+            | (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1) => next.push(self.skip_target(inst)),
+            _ => {}
+        }
+        next
+    }
+
+    /// A key menu's FX0A: wait for one of the menu's keys, store it in Vx
+    /// and go to its entry, through a table after the code
+    fn compile_menu(&mut self, addr: u16, x: u8, entries: &[(u8, u16)]) {
+        let table = format!("menu_{:03X}", addr);
+        self.ld_hl_label(&table);
+        self.call_label("menu_wait");
+        self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
+        self.jp_hl();
+        self.label(&table);
+        self.emit(entries.len() as u8);
+        for (key, target) in entries {
+            let label = self.chip8_labels[target].clone();
+            self.emit(*key);
+            self.emit_label_ref(&label);
+        }
+    }
+
+    /// menu_wait: HL = menu table (entry count, then key and code address
+    /// of each entry). Waits for a key in the table, ticking the timers,
+    /// and returns the key in A and the entry's code in HL.
+    fn generate_menu_wait(&mut self) {
+        self.label("menu_wait");
+        self.push_hl();
+        self.label("menu_wait_loop");
+        let start = self.tstates;
+        self.ld_a_n(0);  // Weight of one pass, patched below
+        let weight_at = Some((self.code.len() - 1, start));
+        self.call_label("timer_tick");
+        self.call_label("get_key");
+        if self.console_keys() {
+            self.call_label("key_seen");
+        }
+        self.pop_hl();
+        self.push_hl();
+        self.ld_b_hl();
+        self.inc_hl();
+        self.label("menu_wait_scan");
+        self.cp_hl();
+        self.inc_hl();
+        self.jr_z("menu_wait_found");
+        self.inc_hl();
+        self.inc_hl();
+        self.dec_b();
+        self.jr_nz("menu_wait_scan");
+        self.patch_tick_weight(weight_at);
+        self.jr_label("menu_wait_loop");  // No key, or not one of the menu's
+        self.label("menu_wait_found");
+        if !self.console_keys() {
+            self.push_hl();
+            self.call_label("key_release");
+            self.pop_hl();
+        }
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        self.ex_de_hl();
+        self.pop_de();
+        self.ret();
+    }
+
+    /// wait_key for FX0A: a key press, then its release, with the timers
+    /// running. A console doesn't report releases, so there the press is
+    /// taken at once and the key counts as down until it stops repeating.
+    fn generate_wait_key(&mut self) {
+        self.label("wait_key");
+        let start = self.tstates;
+        self.ld_a_n(0);  // Weight of one pass, patched below
+        let weight_at = Some((self.code.len() - 1, start));
+        self.call_label("timer_tick");
+        self.call_label("get_key");
+        if self.console_keys() {
+            self.call_label("key_seen");
+        }
+        self.cp_n(0xFF);
+        self.patch_tick_weight(weight_at);
+        self.jr_z("wait_key");
+        if self.console_keys() {
+            self.ret();
+        }
+
+        if !self.console_keys() {
+            // A = key; returns it once released. Preserves BC.
+            self.label("key_release");
+            self.push_bc();
+            self.ld_b_a();
+            self.label("key_release_wait");
+            let start = self.tstates;
+            self.ld_a_n(0);
+            let weight_at = Some((self.code.len() - 1, start));
+            self.call_label("timer_tick");
+            self.call_label("get_key");
+            self.cp_b();
+            self.patch_tick_weight(weight_at);
+            self.jr_z("key_release_wait");
+            self.ld_a_b();
+            self.pop_bc();
+            self.ret();
+        }
+
+        if self.console_keys() {
+            // A = key from get_key; marks it down for the release time and
+            // returns it, or 0xFF for none or for a key that was already
+            // down (a repeat) when FX0A skips those. Clobbers HL.
+            self.label("key_seen");
+            self.cp_n(16);
+            self.ret_nc();
+            self.push_de();
+            self.ld_e_a();
+            self.ld_d_n(0);
+            self.ld_hl_nn(self.ram(KEY_STATE));
+            self.add_hl_de();
+            self.ld_a_hl();  // Frames it had left
+            self.ld_hl_n(self.release_frames());
+            if self.key_repeats() {
+                self.or_a();
+                self.ld_a_n(0xFF);  // A repeat, keeping NZ
+                self.jr_nz("key_seen_done");
+            }
+            self.ld_a_e();
+            self.label("key_seen_done");
+            self.pop_de();
+            self.ret();
+        }
+    }
+
+    /// Whether FX0A skips console keys repeating while held
+    fn key_repeats(&self) -> bool {
+        self.console_keys() && self.key_release_ms != 0
+    }
+
+    /// Whether SKP and SKNP test their key through key_down, rather than
+    /// comparing it with the first key down
+    fn tests_each_key(&self) -> bool {
+        self.console_keys() || self.keymap.players.is_some()
+    }
+
+    /// Frames a console key stays down after its last character
+    fn release_frames(&self) -> u8 {
+        ((self.key_release_ms as u32 * 60 + 999) / 1000).clamp(1, 255) as u8
+    }
+
+    /// move_sprite: erase the sprite at (DRAW_OLD) and (DRAW_OLD_SHIFT),
+    /// then draw it at DE and C. HL = sprite, B = height; returns the new
+    /// draw's collision in A.
+    fn generate_move_sprite(&mut self) {
+        self.label("move_sprite");
+        self.push_de();  // New screen address
+        self.push_hl();
+        self.push_bc();
+        self.ld_de_mem(self.ram(DRAW_OLD));
+        self.ld_a_mem(self.ram(DRAW_OLD_SHIFT));
+        self.ld_c_a();
+        self.call_label("draw_sprite");  // Erase; collision ignored
+        self.pop_bc();
+        self.pop_hl();
+        self.pop_de();
+        self.jp_label("draw_sprite");
+    }
+
+    /// Label a skip instruction jumps to when it skips
+    fn skip_label(&self, inst: &Instruction) -> Option<String> {
+        if let Some(RefreshPlan::SkipTo(label)) = self.refresh_plan.get(&inst.addr) {
+            return Some(label.clone());
+        }
+        self.chip8_labels.get(&self.skip_target(inst)).cloned()
+    }
+
+    /// Computed jump support for BNNN.
+    /// `dispatch` takes a CHIP-8 address in HL and jumps to its compiled
+    /// code via a table with one entry per ROM word (0 = not code).
+    /// Only `entries` get a table slot: code that can be entered without
+    /// falling through from the previous instruction.
+    /// Addresses with no compiled code trap, or go to the interpreter.
+    fn generate_dispatch(&mut self, entries: &HashSet<u16>) {
+        let span = (self.chip8_rom.len() as u16 + 1) & !1;
+
+        self.label("dispatch");
+        self.call_label("dispatch_find");
+        self.jr_z("dispatch_bad");
+        self.ex_de_hl();
+        self.jp_hl();
+        self.label("dispatch_bad");
+        match self.fallback {
+            Fallback::Trap => self.jp_label(Trap::BadJump.label()),
+            Fallback::Interp => self.jp_label("interp"),
+        }
+
+        // DE = compiled code for CHIP-8 address HL, Z set if there is none.
+        // Preserves HL.
+        self.label("dispatch_find");
+        self.push_hl();
+        self.bit_0_l();
+        self.jr_nz("dispatch_none");  // Odd address
+        self.ld_de_nn(0x200);
+        self.or_a();
+        self.sbc_hl_de();
+        self.jr_c("dispatch_none");   // Below the program
+        self.ld_de_nn(span);
+        self.or_a();
+        self.sbc_hl_de();
+        self.jr_nc("dispatch_none");  // Past the end of the ROM
+        self.add_hl_de();             // HL = target - 0x200 = table offset
+        self.ld_de_label("dispatch_table");
+        self.add_hl_de();
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        self.pop_hl();
+        self.ld_a_d();
+        self.or_e();                  // Z if not an instruction
+        self.ret();
+        self.label("dispatch_none");
+        self.pop_hl();
+        self.xor_a();
+        self.ret();
+
+        self.label("dispatch_table");
+        for addr in (0x200..0x200 + span).step_by(2) {
+            match self.chip8_labels.get(&addr) {
+                Some(label) if entries.contains(&addr) => {
+                    let label = label.clone();
+                    self.emit_label_ref(&label);
+                }
+                _ => self.emit16(0),
+            }
+        }
+    }
+
+    /// Embedded CHIP-8 interpreter for `--fallback interp`.
+    /// Entered by JP with HL = CHIP-8 address; runs code the compiler
+    /// couldn't (self-modified, or only reached by computed jumps) from the
+    /// RAM copy of the ROM, and hands back to compiled code at the first
+    /// merge point it reaches. Opcode is held in BC while executing.
+    #[cfg(feature = "interp")]
+    fn generate_interpreter(&mut self) {
+        let v_hi = (self.ram(CHIP8_V0) >> 8) as u8;
+        let v_lo = self.ram(CHIP8_V0) as u8;
+
+        self.label("interp");
+        self.ld_mem_hl(self.ram(INTERP_PC));
+        self.label("interp_next");
+        self.ld_a_n(INTERP_WEIGHT);
+        self.call_label("timer_tick");
+        if self.speed.is_some() {
+            self.ld_de_nn(60);
+            self.call_label("pace");
+        }
+        self.ld_hl_mem(self.ram(INTERP_PC));
+        self.call_label("dispatch_find");
+        self.jr_z("interp_fetch");
+        self.ex_de_hl();
+        self.jp_hl();  // Compiled code from here on
+
+        self.label("interp_fetch");
+        self.ld_a_h();
+        self.cp_n(0x02);
+        self.jr_c("interp_bad");
+        self.cp_n((self.mem_top() >> 8) as u8);
+        self.jr_nc("interp_bad");
+        self.ld_de_nn(self.ram(CHIP8_RAM) - 0x200);
+        self.add_hl_de();
+        self.ld_b_hl();
+        self.inc_hl();
+        self.ld_c_hl();  // BC = opcode
+        self.ld_hl_mem(self.ram(INTERP_PC));
+        self.inc_hl();
+        self.inc_hl();
+        self.ld_mem_hl(self.ram(INTERP_PC));
+        // Jump through the table by the high nibble
+        self.ld_a_b();
+        self.rrca();
+        self.rrca();
+        self.rrca();
+        self.rrca();
+        self.and_n(0x0F);
+        self.add_a_a();
+        self.ld_l_a();
+        self.ld_h_n(0);
+        self.ld_de_label("interp_ops");
+        self.add_hl_de();
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        self.ex_de_hl();
+        self.jp_hl();
+        self.label("interp_bad");
+        self.jp_label(Trap::BadJump.label());
+
+        self.label("interp_ops");
+        for n in 0..16 {
+            self.emit_label_ref(&format!("interp_op{:X}", n));
         }
-        self.emit(0);
 
-        // CLS - Clear screen
-        self.label("cls");
-        self.ld_hl_nn(DISPLAY_BUF);
-        self.ld_bc_nn(256);
-        self.label("cls_loop");
-        self.xor_a();       // A = 0 (must be inside loop!)
-        self.ld_hl_a();
-        self.inc_hl();
-        self.dec_bc();
+        // HL = &Vx, A = Vx (X from B). Preserves DE.
+        self.label("interp_vx");
         self.ld_a_b();
-        self.or_c();
-        self.jr_nz("cls_loop");
-        // Refresh display to show cleared screen
-        self.jp_label("refresh_display");
+        self.and_n(0x0F);
+        self.add_a_n(v_lo);
+        self.ld_l_a();
+        self.ld_h_n(v_hi);
+        self.ld_a_hl();
+        self.ret();
 
-        // Copy font data
-        self.label("copy_font");
-        self.ld_hl_label("font_rom");
-        self.ld_de_nn(FONT_DATA);
-        self.ld_bc_nn(80);  // 16 chars x 5 bytes
-        self.label("copy_font_loop");
+        // DE = &Vy, A = Vy (Y from C). Preserves HL.
+        self.label("interp_vy");
+        self.ld_a_c();
+        self.rrca();
+        self.rrca();
+        self.rrca();
+        self.rrca();
+        self.and_n(0x0F);
+        self.add_a_n(v_lo);
+        self.ld_e_a();
+        self.ld_d_n(v_hi);
+        self.ld_a_de();
+        self.ret();
+
+        // Skip the next instruction (4 bytes for F000 NNNN)
+        self.label("interp_skip");
+        self.ld_hl_mem(self.ram(INTERP_PC));
+        self.push_hl();
+        self.ld_de_nn(self.ram(CHIP8_RAM) - 0x200);
+        self.add_hl_de();
         self.ld_a_hl();
-        self.ld_de_a();
+        self.cp_n(0xF0);
+        self.jr_nz("interp_skip_short");
         self.inc_hl();
-        self.inc_de();
-        self.dec_bc();
+        self.ld_a_hl();
+        self.or_a();
+        self.jr_nz("interp_skip_short");
+        self.pop_hl();
+        self.inc_hl();
+        self.inc_hl();
+        self.jr_label("interp_skip_over");
+        self.label("interp_skip_short");
+        self.pop_hl();
+        self.label("interp_skip_over");
+        self.inc_hl();
+        self.inc_hl();
+        self.ld_mem_hl(self.ram(INTERP_PC));
+        self.jp_label("interp_next");
+
+        // VF = carry, then continue
+        self.label("interp_carry_vf");
+        self.ld_a_n(0);
+        self.adc_a_n(0);
+        self.ld_mem_a(self.ram(CHIP8_V0) + 0xF);
+        self.jp_label("interp_next");
+
+        // 0NNN: CLS, RET, EXIT; SYS is ignored
+        self.label("interp_op0");
         self.ld_a_b();
-        self.or_c();
-        self.jr_nz("copy_font_loop");
+        self.or_a();
+        self.jp_nz_label("interp_next");
+        self.ld_a_c();
+        self.cp_n(0xE0);
+        self.jr_nz("interp_op0_ret");
+        self.call_label("cls");
+        self.jp_label("interp_next");
+        self.label("interp_op0_ret");
+        self.cp_n(0xEE);
+        self.jr_nz("interp_op0_exit");
+        self.ld_hl_nn(self.ram(CHIP8_SP));
+        self.ld_a_hl();
+        self.or_a();
+        self.jr_nz("interp_ret_ok");
+        self.call_label("interp_this");
+        self.jp_label(Trap::StackUnderflow.label());
+        self.label("interp_ret_ok");
+        self.dec_a();
+        self.ld_hl_a();  // SP--
+        self.ld_l_a();
+        self.ld_h_n(0);
+        self.add_hl_hl();
+        self.ld_de_nn(self.ram(CHIP8_STACK));
+        self.add_hl_de();
+        self.ld_e_hl();
+        self.inc_hl();
+        self.ld_d_hl();
+        self.ld_a_d();
+        self.or_a();
+        self.jp_p_label("interp_ret_native");
+        self.and_n(0x7F);  // Pushed by the interpreter: CHIP-8 address
+        self.ld_h_a();
+        self.ld_l_e();
+        self.ld_mem_hl(self.ram(INTERP_PC));
+        self.jp_label("interp_next");
+        self.label("interp_ret_native");
+        self.ex_de_hl();
+        self.jp_hl();  // Return into compiled code
+        self.label("interp_op0_exit");
+        self.cp_n(0xFD);
+        self.jp_z_label("halt");
+        if self.hires {
+            self.cp_n(0xFE);
+            self.jp_c_label("interp_next");
+            self.and_n(0x01);  // 00FE: 0, 00FF: 1
+            self.call_label("display_mode");
+        }
+        self.jp_label("interp_next");
+
+        // HL = address of the instruction being executed, for traps
+        self.label("interp_this");
+        self.ld_hl_mem(self.ram(INTERP_PC));
+        self.dec_hl();
+        self.dec_hl();
         self.ret();
 
-        // Font ROM data (0-F sprites, 5 bytes each)
-        self.label("font_rom");
-        // 0
-        self.emit(0xF0); self.emit(0x90); self.emit(0x90); self.emit(0x90); self.emit(0xF0);
-        // 1
-        self.emit(0x20); self.emit(0x60); self.emit(0x20); self.emit(0x20); self.emit(0x70);
-        // 2
-        self.emit(0xF0); self.emit(0x10); self.emit(0xF0); self.emit(0x80); self.emit(0xF0);
-        // 3
-        self.emit(0xF0); self.emit(0x10); self.emit(0xF0); self.emit(0x10); self.emit(0xF0);
-        // 4
-        self.emit(0x90); self.emit(0x90); self.emit(0xF0); self.emit(0x10); self.emit(0x10);
-        // 5
-        self.emit(0xF0); self.emit(0x80); self.emit(0xF0); self.emit(0x10); self.emit(0xF0);
-        // 6
-        self.emit(0xF0); self.emit(0x80); self.emit(0xF0); self.emit(0x90); self.emit(0xF0);
-        // 7
-        self.emit(0xF0); self.emit(0x10); self.emit(0x20); self.emit(0x40); self.emit(0x40);
-        // 8
-        self.emit(0xF0); self.emit(0x90); self.emit(0xF0); self.emit(0x90); self.emit(0xF0);
-        // 9
-        self.emit(0xF0); self.emit(0x90); self.emit(0xF0); self.emit(0x10); self.emit(0xF0);
-        // A
-        self.emit(0xF0); self.emit(0x90); self.emit(0xF0); self.emit(0x90); self.emit(0x90);
-        // B
-        self.emit(0xE0); self.emit(0x90); self.emit(0xE0); self.emit(0x90); self.emit(0xE0);
-        // C
-        self.emit(0xF0); self.emit(0x80); self.emit(0x80); self.emit(0x80); self.emit(0xF0);
-        // D
-        self.emit(0xE0); self.emit(0x90); self.emit(0x90); self.emit(0x90); self.emit(0xE0);
-        // E
-        self.emit(0xF0); self.emit(0x80); self.emit(0xF0); self.emit(0x80); self.emit(0xF0);
-        // F
-        self.emit(0xF0); self.emit(0x80); self.emit(0xF0); self.emit(0x80); self.emit(0x80);
+        // 1NNN - JP
+        self.label("interp_op1");
+        self.ld_a_b();
+        self.and_n(0x0F);
+        self.ld_h_a();
+        self.ld_l_c();
+        self.ld_mem_hl(self.ram(INTERP_PC));
+        self.jp_label("interp_next");
 
-        // RNG - Simple LFSR
-        self.label("rng");
-        self.ld_hl_nn(CHIP8_RNG);
+        // 2NNN - CALL: pushes the return address with bit 15 set
+        self.label("interp_op2");
+        self.ld_hl_nn(self.ram(CHIP8_SP));
         self.ld_a_hl();
+        self.cp_n(STACK_DEPTH);
+        self.jr_c("interp_call_ok");
+        self.call_label("interp_this");
+        self.jp_label(Trap::StackOverflow.label());
+        self.label("interp_call_ok");
+        self.inc_hl_ind();  // SP++
+        self.ld_l_a();
+        self.ld_h_n(0);
+        self.add_hl_hl();
+        self.ld_de_nn(self.ram(CHIP8_STACK));
+        self.add_hl_de();
+        self.ld_de_mem(self.ram(INTERP_PC));
+        self.ld_hl_e();
         self.inc_hl();
-        self.ld_h_hl();
+        self.ld_a_d();
+        self.or_n(0x80);
+        self.ld_hl_a();
+        self.jp_label("interp_op1");
+
+        // 3XNN / 4XNN - SE / SNE Vx, byte
+        self.label("interp_op3");
+        self.call_label("interp_vx");
+        self.cp_c();
+        self.jp_z_label("interp_skip");
+        self.jp_label("interp_next");
+        self.label("interp_op4");
+        self.call_label("interp_vx");
+        self.cp_c();
+        self.jp_nz_label("interp_skip");
+        self.jp_label("interp_next");
+
+        // 5XY0 / 9XY0 - SE / SNE Vx, Vy
+        self.label("interp_op5");
+        self.call_label("interp_vy");
+        self.call_label("interp_vx");
+        self.ex_de_hl();
+        self.cp_hl();
+        self.jp_z_label("interp_skip");
+        self.jp_label("interp_next");
+        self.label("interp_op9");
+        self.call_label("interp_vy");
+        self.call_label("interp_vx");
+        self.ex_de_hl();
+        self.cp_hl();
+        self.jp_nz_label("interp_skip");
+        self.jp_label("interp_next");
+
+        // 6XNN / 7XNN - LD / ADD Vx, byte
+        self.label("interp_op6");
+        self.call_label("interp_vx");
+        self.ld_hl_c();
+        self.jp_label("interp_next");
+        self.label("interp_op7");
+        self.call_label("interp_vx");
+        self.add_a_c();
+        self.ld_hl_a();
+        self.jp_label("interp_next");
+
+        // 8XYN - register ALU; DE = &Vy, HL = &Vx
+        self.label("interp_op8");
+        self.call_label("interp_vy");
+        self.call_label("interp_vx");
+        self.ld_a_c();
+        self.and_n(0x0F);
+        for n in [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE] {
+            self.cp_n(n);
+            self.jp_z_label(&format!("interp_8xy{:X}", n));
+        }
+        self.jp_label("interp_next");
+        self.label("interp_8xy0");
+        self.ld_a_de();
+        self.ld_hl_a();
+        self.jp_label("interp_next");
+        for (n, op) in [(1, 0xB6u8), (2, 0xA6), (3, 0xAE)] {
+            self.label(&format!("interp_8xy{}", n));
+            self.ld_a_de();
+            match op {
+                0xB6 => self.or_hl(),
+                0xA6 => self.and_hl(),
+                _ => self.xor_hl(),
+            }
+            self.ld_hl_a();
+            if self.quirks.vf_reset {
+                self.xor_a();
+                self.ld_mem_a(self.ram(CHIP8_V0) + 0xF);
+            }
+            self.jp_label("interp_next");
+        }
+        self.label("interp_8xy4");
+        self.ld_a_de();
+        self.add_a_hl();
+        self.ld_hl_a();
+        self.jp_label("interp_carry_vf");
+        self.label("interp_8xy5");
+        self.ld_a_hl();
+        self.ex_de_hl();
+        self.sub_hl();
+        self.ex_de_hl();
+        self.ld_hl_a();
+        self.ccf();
+        self.jp_label("interp_carry_vf");
+        self.label("interp_8xy7");
+        self.ld_a_de();
+        self.sub_hl();
+        self.ld_hl_a();
+        self.ccf();
+        self.jp_label("interp_carry_vf");
+        for (n, left) in [(6, false), (0xE, true)] {
+            self.label(&format!("interp_8xy{:X}", n));
+            if self.quirks.shift_vy {
+                self.ld_a_de();
+            } else {
+                self.ld_a_hl();
+            }
+            if left {
+                self.sla_a();
+            } else {
+                self.srl_a();
+            }
+            self.ld_hl_a();
+            self.jp_label("interp_carry_vf");
+        }
+
+        // ANNN - LD I, addr
+        self.label("interp_opA");
+        self.ld_a_b();
+        self.and_n(0x0F);
+        self.ld_h_a();
+        self.ld_l_c();
+        self.ld_mem_hl(self.ram(CHIP8_I));
+        self.jp_label("interp_next");
+
+        // BNNN - JP V0 (or VX), addr
+        self.label("interp_opB");
+        if self.quirks.jump_vx {
+            self.call_label("interp_vx");
+        } else {
+            self.ld_a_mem(self.ram(CHIP8_V0));
+        }
+        self.ld_e_a();
+        self.ld_d_n(0);
+        self.ld_a_b();
+        self.and_n(0x0F);
+        self.ld_h_a();
+        self.ld_l_c();
+        self.add_hl_de();
+        self.ld_mem_hl(self.ram(INTERP_PC));
+        self.jp_label("interp_next");
+
+        // CXNN - RND Vx, byte
+        self.label("interp_opC");
+        self.push_bc();
+        self.call_label("rng");
+        self.pop_bc();
+        self.and_a_c();
+        self.push_af();
+        self.call_label("interp_vx");
+        self.pop_af();
+        self.ld_hl_a();
+        self.jp_label("interp_next");
+
+        // DXYN - DRW Vx, Vy, nibble (sprites from the RAM copy)
+        self.label("interp_opD");
+        self.call_label("interp_vx");
+        self.and_n(0x3F);
+        self.srl_a();
+        self.srl_a();
+        self.srl_a();
+        self.push_af();
+        self.call_label("interp_vy");
+        self.and_n(0x1F);
         self.ld_l_a();
-        // LFSR: x ^= x << 7; x ^= x >> 9; x ^= x << 8
-        self.add_hl_hl();  // Simplified: just rotate
-        self.emit(0xCB); self.emit(0x15);  // RL L
-        self.emit(0xCB); self.emit(0x14);  // RL H
+        self.ld_h_n(0);
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.pop_af();
+        self.ld_e_a();
+        self.ld_d_n(0);
+        self.add_hl_de();
+        self.ld_de_nn(self.ram(DISPLAY_BUF));
+        self.add_hl_de();
+        self.push_hl();  // Screen address
+        self.ld_hl_mem(self.ram(CHIP8_I));
+        self.ld_a_h();
+        self.or_a();
+        self.jr_nz("interp_drw_ram");
         self.ld_a_l();
-        self.xor_h();
-        self.ld_l_a();
-        // Store back
-        self.push_hl();
-        self.ld_hl_nn(CHIP8_RNG);
+        self.cp_n(0x50);
+        self.jr_nc("interp_drw_ram");
+        self.ld_de_nn(self.ram(FONT_DATA));
+        self.jr_label("interp_drw_go");
+        self.label("interp_drw_ram");
+        if self.quirks.display_ram {
+            self.ld_a_h();
+            self.cp_n(0x0F);
+            self.jr_nz("interp_drw_not_display");
+            self.ld_h_n((self.ram(DISPLAY_BUF) >> 8) as u8);
+            self.jr_label("interp_drw_sprite");
+            self.label("interp_drw_not_display");
+        }
+        self.ld_de_nn(self.ram(CHIP8_RAM) - 0x200);
+        self.label("interp_drw_go");
+        self.add_hl_de();
+        if self.quirks.display_ram {
+            self.label("interp_drw_sprite");
+        }
         self.pop_de();
-        self.ld_a_e();
+        if self.hires {
+            self.ld_a_mem(self.ram(HIRES_MODE));
+            self.or_a();
+            self.jr_z("interp_drw_lores");
+            self.call_label("interp_vy");
+            self.push_af();
+            self.push_hl();
+            self.call_label("interp_vx");
+            self.pop_hl();
+            self.ld_d_a();  // D = X
+            self.pop_af();
+            self.ld_e_a();  // E = Y
+            self.ld_a_c();
+            self.and_n(0x0F);
+            self.ld_b_a();
+            self.call_label("draw_hires");
+            self.jr_label("interp_drw_done");
+            self.label("interp_drw_lores");
+        }
+        self.push_hl();
+        self.call_label("interp_vx");
+        self.pop_hl();
+        self.and_n(0x07);
+        self.push_af();  // X & 7
+        self.ld_a_c();
+        self.and_n(0x0F);
+        self.ld_b_a();
+        self.pop_af();
+        self.ld_c_a();
+        self.call_label("draw_sprite");
+        if self.hires {
+            self.label("interp_drw_done");
+        }
+        self.ld_mem_a(self.ram(CHIP8_V0) + 0xF);
+        self.call_label(self.refresh_drawn());
+        self.jp_label("interp_next");
+
+        // EX9E / EXA1 - SKP / SKNP Vx
+        self.label("interp_opE");
+        if self.tests_each_key() {
+            self.call_label("interp_vx");
+            self.call_label("key_down");
+        } else {
+            self.push_bc();
+            self.call_label("get_key");
+            self.pop_bc();
+            self.push_af();
+            self.call_label("interp_vx");
+            self.pop_af();
+            self.cp_hl();
+        }
+        self.ld_a_c();
+        self.jr_z("interp_key_down");
+        self.cp_n(0xA1);
+        self.jp_z_label("interp_skip");
+        self.jp_label("interp_next");
+        self.label("interp_key_down");
+        self.cp_n(0x9E);
+        self.jp_z_label("interp_skip");
+        self.jp_label("interp_next");
+
+        // FXNN - timers, keys, I and memory
+        self.label("interp_opF");
+        self.ld_a_c();
+        for nn in [0x00, 0x07, 0x0A, 0x15, 0x18, 0x1E, 0x29, 0x33, 0x55, 0x65] {
+            self.cp_n(nn);
+            self.jp_z_label(&format!("interp_fx{:02X}", nn));
+        }
+        self.jp_label("interp_next");
+
+        // F000 NNNN - LD I, long addr
+        self.label("interp_fx00");
+        self.ld_a_b();
+        self.cp_n(0xF0);
+        self.jp_nz_label("interp_next");
+        self.ld_hl_mem(self.ram(INTERP_PC));
+        self.push_hl();
+        self.ld_de_nn(self.ram(CHIP8_RAM) - 0x200);
+        self.add_hl_de();
+        self.ld_d_hl();
+        self.inc_hl();
+        self.ld_e_hl();
+        self.ex_de_hl();
+        self.ld_mem_hl(self.ram(CHIP8_I));
+        self.pop_hl();
+        self.inc_hl();
+        self.inc_hl();
+        self.ld_mem_hl(self.ram(INTERP_PC));
+        self.jp_label("interp_next");
+
+        self.label("interp_fx07");
+        self.call_label("interp_vx");
+        self.ld_a_mem(self.ram(CHIP8_DT));
+        self.ld_hl_a();
+        self.jp_label("interp_next");
+
+        self.label("interp_fx0A");
+        self.push_bc();
+        self.call_label("wait_key");
+        self.pop_bc();
+        self.push_af();
+        self.call_label("interp_vx");
+        self.pop_af();
         self.ld_hl_a();
+        self.jp_label("interp_next");
+
+        self.label("interp_fx15");
+        self.call_label("interp_vx");
+        self.ld_mem_a(self.ram(CHIP8_DT));
+        self.jp_label("interp_next");
+
+        self.label("interp_fx18");
+        self.call_label("interp_vx");
+        self.ld_mem_a(self.ram(CHIP8_ST));
+        self.jp_label("interp_next");
+
+        self.label("interp_fx1E");
+        self.call_label("interp_vx");
+        self.ld_e_a();
+        self.ld_d_n(0);
+        self.ld_hl_mem(self.ram(CHIP8_I));
+        self.add_hl_de();
+        self.ld_mem_hl(self.ram(CHIP8_I));
+        self.jp_label("interp_next");
+
+        self.label("interp_fx29");
+        self.call_label("interp_vx");
+        self.and_n(0x0F);
+        self.ld_l_a();
+        self.ld_h_n(0);
+        self.add_hl_hl();
+        self.add_hl_hl();
+        self.ld_e_a();
+        self.ld_d_n(0);
+        self.add_hl_de();  // Vx * 5
+        self.ld_mem_hl(self.ram(CHIP8_I));
+        self.jp_label("interp_next");
+
+        self.label("interp_fx33");
+        self.call_label("interp_vx");
+        self.push_af();
+        self.call_label("translate_i");
+        self.pop_af();
+        self.ld_b_n(0);
+        self.label("interp_bcd_hundreds");
+        self.cp_n(100);
+        self.jr_c("interp_bcd_tens");
+        self.sub_n(100);
+        self.inc_b();
+        self.jr_label("interp_bcd_hundreds");
+        self.label("interp_bcd_tens");
+        self.ld_hl_b();
+        self.inc_hl();
+        self.ld_b_n(0);
+        self.label("interp_bcd_tens_loop");
+        self.cp_n(10);
+        self.jr_c("interp_bcd_ones");
+        self.sub_n(10);
+        self.inc_b();
+        self.jr_label("interp_bcd_tens_loop");
+        self.label("interp_bcd_ones");
+        self.ld_hl_b();
         self.inc_hl();
-        self.ld_a_d();
         self.ld_hl_a();
-        self.ld_a_e();  // Return random byte in A
-        self.ret();
+        if self.quirks.display_ram {
+            self.call_label("display_written");
+        }
+        self.jp_label("interp_next");
 
-        // Get key - check for serial input
-        self.label("get_key");
-        self.in_a_n(ACIA_CTRL);
-        self.emit(0xE6); self.emit(0x01);  // AND 1
-        self.ret_z();  // No key, A=0
-        self.in_a_n(ACIA_DATA);
-        // Map ASCII to CHIP-8 keys (0-9, A-F)
-        self.cp_n(b'0');
-        self.jr_c("get_key_alpha");
-        self.cp_n(b'9' + 1);
-        self.jr_nc("get_key_alpha");
-        self.sub_n(b'0');  // 0-9
-        self.ret();
-        self.label("get_key_alpha");
-        self.cp_n(b'a');
-        self.jr_c("get_key_upper");
-        self.cp_n(b'f' + 1);
-        self.jr_nc("get_key_none");
-        self.sub_n(b'a' - 10);  // a-f -> 10-15
-        self.ret();
-        self.label("get_key_upper");
-        self.cp_n(b'A');
-        self.jr_c("get_key_none");
-        self.cp_n(b'F' + 1);
-        self.jr_nc("get_key_none");
-        self.sub_n(b'A' - 10);  // A-F -> 10-15
-        self.ret();
-        self.label("get_key_none");
-        self.ld_a_n(0xFF);
-        self.ret();
+        for (nn, store) in [(0x55, true), (0x65, false)] {
+            let copy = format!("interp_fx{:02X}_loop", nn);
+            self.label(&format!("interp_fx{:02X}", nn));
+            self.call_label("translate_i");  // HL = I in RAM
+            self.ld_a_b();
+            self.and_n(0x0F);
+            self.inc_a();
+            self.ld_b_a();
+            self.ld_c_a();  // C = count, for the increment quirk
+            self.ld_de_nn(self.ram(CHIP8_V0));
+            if store {
+                self.ex_de_hl();  // DE = destination, HL = V0
+            }
+            self.label(&copy);
+            self.ld_a_hl();
+            self.ld_de_a();
+            self.inc_hl();
+            self.inc_de();
+            self.dec_b();
+            self.jr_nz(&copy);
+            if store && self.quirks.display_ram {
+                self.call_label("display_written");
+            }
+            if self.quirks.load_store_increment {
+                self.ld_hl_mem(self.ram(CHIP8_I));
+                self.ld_e_c();
+                self.ld_d_n(0);
+                self.add_hl_de();
+                self.ld_mem_hl(self.ram(CHIP8_I));
+            }
+            self.jp_label("interp_next");
+        }
+    }
 
-        // Wait for key - blocking
-        self.label("wait_key");
-        self.call_label("get_key");
+    /// Input test: `get_key` becomes the driver's get_key printing `R<kk>`
+    /// each time what it returns changes (`R--` for no key), and
+    /// `input_report` takes the kit's SYS number in A. SYS 001 starts with
+    /// nothing printed yet, SYS 002 prints `W<V0>` for the key FX0A left in
+    /// V0, and SYS 003 gathers what EX9E and EXA1 said of each key from the
+    /// 16 pairs of bytes the kit stores at 300 (down by EXA1, down by EX9E),
+    /// and prints `K<EX9E> <EXA1>` if either changed, bit N for key N.
+    fn generate_input_test(&mut self) {
+        let driver = self.labels["get_key"];
+        self.labels.insert("get_key_driver".to_string(), driver);
+        self.label("get_key");
+        self.call_label("get_key_driver");
+        self.push_hl();
+        self.ld_hl_nn(self.ram(INPUT_RAW));
+        self.cp_hl();
+        self.jr_z("get_key_same");
+        self.ld_hl_a();
+        self.push_af();
+        self.ld_a_n(b'R');
+        self.call_label("print_char");
+        self.pop_af();
+        self.push_af();
         self.cp_n(0xFF);
-        self.jr_z("wait_key");
+        self.jr_nz("get_key_print");
+        self.ld_a_n(b'-');
+        self.call_label("print_char");
+        self.call_label("print_char");
+        self.jr_label("get_key_printed");
+        self.label("get_key_print");
+        self.call_label("print_hex8");
+        self.label("get_key_printed");
+        self.call_label("input_line_end");
+        self.pop_af();
+        self.label("get_key_same");
+        self.pop_hl();
         self.ret();
 
-        // Draw sprite: DE = screen addr, HL = sprite addr, B = height
-        // Returns VF in A (1 if collision)
-        self.label("draw_sprite");
+        self.label("input_report");
+        self.cp_n(2);
+        self.jr_z("input_wait");
+        self.jr_nc("input_keys");
+        // SYS 001: nothing down, no key returned
+        self.ld_a_n(0xFF);
+        self.ld_mem_a(self.ram(INPUT_RAW));
+        self.ld_hl_nn(self.ram(INPUT_STATES));
+        self.ld_bc_nn(4);
         self.xor_a();
-        self.ld_c_a();  // C = collision flag
-        self.label("draw_row");
-        // Get sprite byte
-        self.ld_a_hl();  // A = sprite byte
-        self.push_hl();  // Save sprite pointer
-        self.push_de();  // Save screen pointer
-        // XOR with screen
-        self.ex_de_hl();   // HL = screen addr
-        self.ld_e_a();     // E = sprite byte
-        self.ld_a_hl();    // A = screen byte
-        self.push_af();    // Save screen byte
-        self.ld_a_e();     // A = sprite byte
-        self.xor_hl();     // A = sprite XOR screen
-        self.ld_hl_a();    // Write XOR result to screen
-        self.pop_af();     // A = original screen byte
-        self.and_a_e();    // A = screen AND sprite (pixels that collided)
-        self.or_c();
-        self.ld_c_a();     // Update collision flag
-        // Restore and advance pointers
-        self.pop_de();     // DE = screen addr
-        self.pop_hl();     // HL = sprite addr
-        self.inc_hl();     // Next sprite byte
-        // Screen += 8 (next row)
-        self.push_hl();
-        self.ld_hl_nn(8);
-        self.add_hl_de();
-        self.ex_de_hl();   // DE = screen + 8
-        self.pop_hl();     // HL = sprite
-        self.dec_b();
-        self.jr_nz("draw_row");
-        self.ld_a_c();
-        self.or_a();
-        self.ret_z();
-        self.ld_a_n(1);
+        self.jp_label("memset");
+
+        // SYS 002: the key FX0A waited for
+        self.label("input_wait");
+        self.ld_a_n(b'W');
+        self.call_label("print_char");
+        self.ld_a_mem(self.ram(CHIP8_V0));
+        self.call_label("print_hex8");
+        self.jr_label("input_line_end");
+
+        // SYS 003: BC = keys down by EXA1, DE = by EX9E, key 0 in bit 0
+        self.label("input_keys");
+        self.ld_hl_nn(self.ram(CHIP8_RAM) + INPUT_TABLE - 0x200);
+        self.ld_a_n(16);
+        self.ld_mem_a(self.ram(INPUT_COUNT));
+        self.label("input_keys_loop");
+        self.ld_a_hl();
+        self.rra();
+        self.rr_b();
+        self.rr_c();
+        self.inc_hl();
+        self.ld_a_hl();
+        self.rra();
+        self.rr_d();
+        self.rr_e();
+        self.inc_hl();
+        self.ld_a_mem(self.ram(INPUT_COUNT));
+        self.dec_a();
+        self.ld_mem_a(self.ram(INPUT_COUNT));
+        self.jr_nz("input_keys_loop");
+        self.ld_hl_nn(self.ram(INPUT_STATES));
+        let loads: [fn(&mut Self); 4] = [Self::ld_a_e, Self::ld_a_d, Self::ld_a_c, Self::ld_a_b];
+        for (i, load) in loads.into_iter().enumerate() {
+            load(self);
+            self.cp_hl();
+            self.jr_nz("input_keys_changed");
+            if i < 3 {
+                self.inc_hl();
+            }
+        }
         self.ret();
-
-        // Refresh display to terminal (ANSI)
-        self.label("refresh_display");
-        // Move cursor to row 2 (below banner) - ESC[2;1H
-        self.ld_a_n(0x1B);
-        self.call_label("print_char");
-        self.ld_a_n(b'[');
-        self.call_label("print_char");
-        self.ld_a_n(b'2');
-        self.call_label("print_char");
-        self.ld_a_n(b';');
+        self.label("input_keys_changed");
+        self.ld_hl_nn(self.ram(INPUT_STATES));
+        self.ld_hl_e();
+        self.inc_hl();
+        self.ld_hl_d();
+        self.inc_hl();
+        self.ld_hl_c();
+        self.inc_hl();
+        self.ld_hl_b();
+        self.ld_a_n(b'K');
         self.call_label("print_char");
-        self.ld_a_n(b'1');
+        self.ex_de_hl();
+        self.call_label("print_hex16");
+        self.ld_a_n(b' ');
         self.call_label("print_char");
-        self.ld_a_n(b'H');
+        self.ld_h_b();
+        self.ld_l_c();
+        self.call_label("print_hex16");
+        self.label("input_line_end");
+        self.ld_a_n(b'\r');
         self.call_label("print_char");
+        self.ld_a_n(b'\n');
+        self.jp_label("print_char");
+    }
 
-        self.ld_hl_nn(DISPLAY_BUF);
-        self.ld_d_n(32);  // 32 rows
-        self.label("refresh_row");
-        self.ld_e_n(8);   // 8 bytes per row (64 pixels)
-        self.label("refresh_byte");
-        self.ld_a_hl();
-        self.ld_b_n(8);   // 8 bits per byte
-        self.label("refresh_bit");
-        self.emit(0xCB); self.emit(0x07);  // RLC A - rotate left
+    /// Display test: `display_checkpoint` prints `D<NN> <display> <video>`
+    /// for checkpoint NN in A, the checksums of the display buffer and of
+    /// the rows of framebuffer video memory (left out without one), each
+    /// byte added to the sum turned left a bit. The display test module
+    /// works out the same sums on the host.
+    fn generate_display_checkpoint(&mut self) {
+        self.label("display_checkpoint");
         self.push_af();
-        self.jr_nc("refresh_space");
-        self.ld_a_n(b'#');
-        self.jr_label("refresh_out");
-        self.label("refresh_space");
+        self.ld_hl_label("display_checkpoint_str");
+        self.call_label("print_str");
+        self.pop_af();
+        self.call_label("print_hex8");
+        self.ld_de_nn(0);
+        self.ld_hl_nn(self.ram(DISPLAY_BUF));
+        self.ld_b_n(0);  // 256 bytes
+        self.call_label("checksum_bytes");
+        self.call_label("print_checksum");
+        if let Some(framebuffer) = self.framebuffer {
+            let factor = self.scale.factor();
+            self.ld_de_nn(0);
+            self.ld_hl_nn(framebuffer.base);
+            self.ld_c_n((self.orientation.rows() * factor) as u8);
+            self.label("display_checkpoint_row");
+            self.push_hl();
+            self.ld_b_n((self.orientation.row_bytes() * factor) as u8);
+            self.call_label("checksum_bytes");
+            self.pop_hl();
+            self.push_de();
+            self.ld_de_nn(framebuffer.stride);
+            self.add_hl_de();
+            self.pop_de();
+            self.dec_c();
+            self.jr_nz("display_checkpoint_row");
+            self.call_label("print_checksum");
+        }
+        self.ld_a_n(b'\r');
+        self.call_label("print_char");
+        self.ld_a_n(b'\n');
+        self.jp_label("print_char");
+
+        // Print a space and the checksum in DE
+        self.label("print_checksum");
         self.ld_a_n(b' ');
-        self.label("refresh_out");
         self.call_label("print_char");
-        self.pop_af();
+        self.ex_de_hl();
+        self.jp_label("print_hex16");
+
+        // DE = DE turned left a bit plus each of B bytes at HL (256 if B
+        // is 0), HL past them. Clobbers A, B.
+        self.label("checksum_bytes");
+        self.ex_de_hl();
+        self.add_hl_hl();
+        self.jr_nc("checksum_carry");
+        self.inc_l();
+        self.label("checksum_carry");
+        self.ex_de_hl();
+        self.ld_a_e();
+        self.add_a_hl();
+        self.ld_e_a();
+        self.jr_nc("checksum_next");
+        self.inc_d();
+        self.label("checksum_next");
+        self.inc_hl();
         self.dec_b();
-        self.jr_nz("refresh_bit");
+        self.jr_nz("checksum_bytes");
+        self.ret();
+
+        self.label("display_checkpoint_str");
+        for b in b"\r\nD\0" {
+            self.emit(*b);
+        }
+    }
+
+    /// --symbol-table: `symbol_table` holds a Z80 address and the CHIP-8
+    /// address whose code starts there for each compiled instruction, in
+    /// Z80 address order, then the end of the program with CHIP-8 address
+    /// FFFF. Instructions folded into the next share its address, which
+    /// names the last of them, as in the debug map. `symbolicate` looks an
+    /// address up in it.
+    fn generate_symbol_table(&mut self) {
+        // HL = CHIP-8 address of the instruction whose code holds Z80
+        // address HL, Z set if there is none. Preserves BC and DE.
+        self.label("symbolicate");
+        self.push_bc();
+        self.push_de();
+        self.ex_de_hl();
+        self.ld_hl_label("symbol_table");
+        self.ld_bc_nn(0xFFFF);
+        self.label("symbolicate_loop");
+        self.ld_a_e();
+        self.sub_hl();
         self.inc_hl();
-        self.dec_e();
-        self.jr_nz("refresh_byte");
-        // Newline
+        self.ld_a_d();
+        self.sbc_a_hl();
+        self.inc_hl();
+        self.jr_c("symbolicate_done");  // Starts past the address
+        self.ld_c_hl();
+        self.inc_hl();
+        self.ld_b_hl();
+        self.inc_hl();
+        self.ld_a_b();
+        self.inc_a();
+        self.jr_nz("symbolicate_loop");
+        self.label("symbolicate_done");
+        self.ld_h_b();
+        self.ld_l_c();
+        self.ld_a_b();
+        self.inc_a();  // Z if FFFF
+        self.pop_de();
+        self.pop_bc();
+        self.ret();
+
+        let mut instructions: Vec<(u16, u16)> = self
+            .labels
+            .iter()
+            .filter_map(|(name, &at)| Some((at, u16::from_str_radix(name.strip_prefix("c8_")?, 16).ok()?)))
+            .collect();
+        instructions.sort();
+        let mut table: BTreeMap<u16, u16> = instructions.into_iter().collect();  // The last at each address
+        table.insert(self.labels["halt"], 0xFFFF);
+        self.label("symbol_table");
+        for (at, addr) in table {
+            self.emit16(at);
+            self.emit16(addr);
+        }
+    }
+
+    /// Trap stubs: entered by JP with HL = value to report.
+    /// Each loads its code (and message) and falls into the common
+    /// handler, which prints the diagnostic and halts. A --symbol-table
+    /// build follows it with the CHIP-8 address of the instruction whose
+    /// code called the routine that trapped, where it can tell.
+    fn generate_traps(&mut self) {
+        for trap in Trap::ALL {
+            self.label(trap.label());
+            self.ld_a_n(trap.code());
+            if !self.compact_traps {
+                self.ld_de_label(&format!("{}_msg", trap.label()));
+            }
+            self.jp_label("trap");
+        }
+
+        self.label("trap");
+        if self.symbol_table {
+            self.push_af();  // For the site, after the value
+        }
+        self.push_hl();
+        self.push_af();
         self.ld_a_n(b'\r');
         self.call_label("print_char");
         self.ld_a_n(b'\n');
         self.call_label("print_char");
-        self.dec_d();
-        self.jr_nz("refresh_row");
-        self.ret();
+        if self.compact_traps {
+            // E<code> <value>
+            self.ld_a_n(b'E');
+            self.call_label("print_char");
+            self.pop_af();
+            self.call_label("print_hex8");
+        } else {
+            // <message> <value>
+            self.pop_af();
+            self.ex_de_hl();
+            self.call_label("print_str");
+        }
+        self.ld_a_n(b' ');
+        self.call_label("print_char");
+        self.pop_hl();
+        self.call_label("print_hex16");
+        if self.symbol_table {
+            // ` at <CHIP-8 address>` of the caller a called trap returns to
+            self.pop_af();
+            for trap in Trap::ALL.iter().filter(|trap| trap.called()) {
+                self.cp_n(trap.code());
+                self.jr_z("trap_site");
+            }
+            self.jr_label("trap_end");
+            self.label("trap_site");
+            self.pop_hl();
+            self.push_hl();
+            self.dec_hl();  // In the CALL
+            self.call_label("symbolicate");
+            self.jr_z("trap_end");
+            self.push_hl();
+            self.ld_hl_label("trap_at_str");
+            self.call_label("print_str");
+            self.pop_hl();
+            self.call_label("print_hex16");
+            self.label("trap_end");
+        }
+        self.ld_a_n(b'\r');
+        self.call_label("print_char");
+        self.ld_a_n(b'\n');
+        self.call_label("print_char");
+        self.jp_label("halt");
+        if self.symbol_table {
+            self.label("trap_at_str");
+            for b in b" at \0" {
+                self.emit(*b);
+            }
+        }
+
+        if !self.compact_traps {
+            for trap in Trap::ALL {
+                self.label(&format!("{}_msg", trap.label()));
+                for b in trap.message().bytes() {
+                    self.emit(b);
+                }
+                self.emit(0);
+            }
+        }
     }
 
     fn compile_instruction(&mut self, inst: &Instruction) -> Result<(), String> {
         let (n0, n1, n2, n3) = inst.nibbles();
 
+        // Register ops on known values only update the constants, which are
+        // stored when something needs the registers in memory
+        match self.fold(inst) {
+            Some(Fold::Set(results)) => {
+                for (r, value) in results {
+                    let addr = self.ram(CHIP8_V0) + r as u16;
+                    self.v_const[r as usize] = Some(value);
+                    self.v_dirty |= 1 << r;
+                    if self.a_reg == Some(addr) {
+                        self.a_reg = None;
+                    }
+                    if self.z_reg == Some(addr) {
+                        self.z_reg = None;
+                    }
+                }
+                return Ok(());
+            }
+            Some(Fold::Skip(taken)) => {
+                if taken {
+                    self.flush_consts();
+                    if let Some(label) = self.skip_label(inst) {
+                        self.jr_label(&label);
+                    }
+                }
+                return Ok(());
+            }
+            None => {
+                self.flush_consts();
+                let written = self.v_writes(inst);
+                for r in 0..16 {
+                    if written & (1 << r) != 0 {
+                        self.v_const[r] = None;
+                    }
+                }
+            }
+        }
+
+        // What the previous instruction left in A and the Z flag. Each arm
+        // records what it leaves behind; anything else invalidates both.
+        let a_in = self.a_reg.take();
+        let z_in = self.z_reg.take();
+
         match (n0, n1, n2, n3) {
             // 00E0 - CLS
             (0x0, 0x0, 0xE, 0x0) => {
@@ -440,25 +7799,65 @@ impl Compiler {
             // 00EE - RET
             (0x0, 0x0, 0xE, 0xE) => {
                 // Pop return address from CHIP-8 stack
-                self.ld_hl_nn(CHIP8_SP);
-                self.dec_hl();
+                let ok_label = format!("ret_ok_{:03X}", inst.addr);
+                self.ld_hl_nn(self.ram(CHIP8_SP));
                 self.ld_a_hl();  // SP
+                self.or_a();
+                self.jr_nz(&ok_label);
+                self.ld_hl_nn(inst.addr);
+                self.jp_label(Trap::StackUnderflow.label());
+                self.label(&ok_label);
                 self.dec_a();
                 self.ld_hl_a();  // SP--
                 // Get address from stack
                 self.ld_l_a();
                 self.ld_h_n(0);
                 self.add_hl_hl();  // *2
-                self.ld_de_nn(CHIP8_STACK);
+                self.ld_de_nn(self.ram(CHIP8_STACK));
                 self.add_hl_de();
                 self.ld_e_hl();
                 self.inc_hl();
                 self.ld_d_hl();
+                if self.fallback == Fallback::Interp {
+                    // Bit 15 marks a return address pushed by the interpreter
+                    self.ld_a_d();
+                    self.or_a();
+                    self.jp_m_label("ret_interp");
+                }
                 // Jump to DE
                 self.push_de();
                 self.ret();  // RET pops address
             }
 
+            // 00FE / 00FF - LOW / HIGH (SUPER-CHIP): 64x32 or 128x64
+            (0x0, 0x0, 0xF, 0xE) | (0x0, 0x0, 0xF, 0xF) if self.hires => {
+                self.ld_a_n(n3 & 1);
+                self.call_label("display_mode");
+            }
+            (0x0, 0x0, 0xF, 0xF) => {
+                self.diagnose(
+                    Category::UnknownOpcode,
+                    format!("{:03X}: HIGH (00FF) needs the ANSI terminal display for 128x64, not this {} build's; compiled as a NOP", inst.addr, self.target.name()),
+                )?;
+            }
+
+            // 00FD - EXIT (SUPER-CHIP)
+            (0x0, 0x0, 0xF, 0xD) => {
+                self.jp_label("halt");
+            }
+
+            // 0NNN - SYS: a display checkpoint in the display-test kit
+            (0x0, _, _, _) if self.display_test => {
+                self.ld_a_n(inst.nnn() as u8);
+                self.call_label("display_checkpoint");
+            }
+
+            // 0NNN - SYS: a report in the input-test kit
+            (0x0, _, _, _) if self.input_test => {
+                self.ld_a_n(inst.nnn() as u8);
+                self.call_label("input_report");
+            }
+
             // 0NNN - SYS (ignored on modern interpreters)
             (0x0, _, _, _) => {
                 // NOP
@@ -468,7 +7867,8 @@ impl Compiler {
             (0x1, _, _, _) => {
                 let addr = inst.nnn();
                 if let Some(label) = self.chip8_labels.get(&addr) {
-                    self.jp_label(&label.clone());
+                    self.a_reg = a_in;  // Still in A at the target
+                    self.jr_label(&label.clone());
                 } else {
                     return Err(format!("Jump to unknown address {:03X}", addr));
                 }
@@ -477,72 +7877,80 @@ impl Compiler {
             // 2NNN - CALL addr
             (0x2, _, _, _) => {
                 let addr = inst.nnn();
+                let target = match self.chip8_labels.get(&addr) {
+                    Some(label) => label.clone(),
+                    None => return Err(format!("Call to unknown address {:03X}", addr)),
+                };
                 // Push return address to CHIP-8 stack
-                // Return address is next CHIP-8 instruction
+                // Return address is the compiled code of the next CHIP-8 instruction,
+                // which is only compiled if the subroutine can return
                 let ret_addr = inst.addr + 2;
-                self.ld_hl_nn(CHIP8_SP);
+                let ret_label = self.chip8_labels.get(&ret_addr).cloned().unwrap_or_else(|| "halt".to_string());
+                let ok_label = format!("call_ok_{:03X}", inst.addr);
+                self.ld_hl_nn(self.ram(CHIP8_SP));
                 self.ld_a_hl();  // A = SP
+                self.cp_n(STACK_DEPTH);
+                self.jr_c(&ok_label);
+                self.ld_hl_nn(inst.addr);
+                self.jp_label(Trap::StackOverflow.label());
+                self.label(&ok_label);
                 self.ld_l_a();
                 self.ld_h_n(0);
                 self.add_hl_hl();  // *2
-                self.ld_de_nn(CHIP8_STACK);
+                self.ld_de_nn(self.ram(CHIP8_STACK));
                 self.add_hl_de();
                 // Store return address
-                self.ld_a_n((ret_addr & 0xFF) as u8);
-                self.ld_hl_a();
+                self.ld_de_label(&ret_label);
+                self.ld_hl_e();
                 self.inc_hl();
-                self.ld_a_n((ret_addr >> 8) as u8);
-                self.ld_hl_a();
+                self.ld_hl_d();
                 // Increment SP
-                self.ld_hl_nn(CHIP8_SP);
+                self.ld_hl_nn(self.ram(CHIP8_SP));
                 self.inc_hl_ind();
                 // Jump to subroutine
-                if let Some(label) = self.chip8_labels.get(&addr) {
-                    self.jp_label(&label.clone());
-                } else {
-                    return Err(format!("Call to unknown address {:03X}", addr));
-                }
+                self.jr_label(&target);
             }
 
             // 3XNN - SE Vx, byte (skip if equal)
             (0x3, _, _, _) => {
                 let x = inst.x();
                 let nn = inst.nn();
-                // Load Vx
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.cp_n(nn);
+                self.compare_v_n(x, nn, a_in, z_in);
                 // Skip next instruction if equal
-                let next_addr = inst.addr + 4;  // Skip 2 bytes (one CHIP-8 instruction)
-                if let Some(label) = self.chip8_labels.get(&next_addr) {
-                    self.jp_z_label(&label.clone());
+                let next_addr = self.skip_target(inst);  // Skip one CHIP-8 instruction
+                if let Some(label) = self.skip_label(inst) {
+                    self.jr_z(&label);
                 } else {
-                    eprintln!("Warning: SE at {:03X} skip target {:03X} has no label", inst.addr, next_addr);
+                    self.diagnose(
+                        Category::SkipTarget,
+                        format!("SE at {:03X} skip target {:03X} has no label", inst.addr, next_addr),
+                    )?;
                 }
+                self.after_compare(x, nn);
             }
 
             // 4XNN - SNE Vx, byte (skip if not equal)
             (0x4, _, _, _) => {
                 let x = inst.x();
                 let nn = inst.nn();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.cp_n(nn);
-                let next_addr = inst.addr + 4;
-                if let Some(label) = self.chip8_labels.get(&next_addr) {
-                    self.jp_nz_label(&label.clone());
+                self.compare_v_n(x, nn, a_in, z_in);
+                if let Some(label) = self.skip_label(inst) {
+                    self.jr_nz(&label);
                 }
+                self.after_compare(x, nn);
             }
 
             // 5XY0 - SE Vx, Vy
             (0x5, _, _, 0x0) => {
                 let x = inst.x();
                 let y = inst.y();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.ld_hl_nn(CHIP8_V0 + y as u16);
+                self.load_v(x, a_in);
+                self.ld_hl_nn(self.ram(CHIP8_V0) + y as u16);
                 self.cp_hl();
-                let next_addr = inst.addr + 4;
-                if let Some(label) = self.chip8_labels.get(&next_addr) {
-                    self.jp_z_label(&label.clone());
+                if let Some(label) = self.skip_label(inst) {
+                    self.jr_z(&label);
                 }
+                self.a_reg = Some(self.ram(CHIP8_V0) + x as u16);
             }
 
             // 6XNN - LD Vx, byte
@@ -550,143 +7958,168 @@ impl Compiler {
                 let x = inst.x();
                 let nn = inst.nn();
                 self.ld_a_n(nn);
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
+                self.a_reg = Some(self.ram(CHIP8_V0) + x as u16);
             }
 
             // 7XNN - ADD Vx, byte
             (0x7, _, _, _) => {
                 let x = inst.x();
                 let nn = inst.nn();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
+                self.load_v(x, a_in);
                 self.add_a_n(nn);
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
+                // ADD leaves Z set from the new Vx
+                self.a_reg = Some(self.ram(CHIP8_V0) + x as u16);
+                self.z_reg = Some(self.ram(CHIP8_V0) + x as u16);
             }
 
             // 8XY0 - LD Vx, Vy
             (0x8, _, _, 0x0) => {
                 let x = inst.x();
                 let y = inst.y();
-                self.ld_a_mem(CHIP8_V0 + y as u16);
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                self.load_v(y, a_in);
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
+                self.a_reg = Some(self.ram(CHIP8_V0) + x as u16);
             }
 
             // 8XY1 - OR Vx, Vy
             (0x8, _, _, 0x1) => {
                 let x = inst.x();
                 let y = inst.y();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.ld_hl_nn(CHIP8_V0 + y as u16);
+                self.load_v(x, a_in);
+                self.ld_hl_nn(self.ram(CHIP8_V0) + y as u16);
                 self.or_hl();
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
+                self.set_a_z(self.ram(CHIP8_V0) + x as u16);
+                if self.quirks.vf_reset {
+                    self.xor_a();
+                    self.ld_mem_a(self.ram(CHIP8_V0) + 0xF);
+                    self.set_a_z(self.ram(CHIP8_V0) + 0xF);
+                }
             }
 
             // 8XY2 - AND Vx, Vy
             (0x8, _, _, 0x2) => {
                 let x = inst.x();
                 let y = inst.y();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.ld_hl_nn(CHIP8_V0 + y as u16);
+                self.load_v(x, a_in);
+                self.ld_hl_nn(self.ram(CHIP8_V0) + y as u16);
                 self.and_hl();
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
+                self.set_a_z(self.ram(CHIP8_V0) + x as u16);
+                if self.quirks.vf_reset {
+                    self.xor_a();
+                    self.ld_mem_a(self.ram(CHIP8_V0) + 0xF);
+                    self.set_a_z(self.ram(CHIP8_V0) + 0xF);
+                }
             }
 
             // 8XY3 - XOR Vx, Vy
             (0x8, _, _, 0x3) => {
                 let x = inst.x();
                 let y = inst.y();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.ld_hl_nn(CHIP8_V0 + y as u16);
+                self.load_v(x, a_in);
+                self.ld_hl_nn(self.ram(CHIP8_V0) + y as u16);
                 self.xor_hl();
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
+                self.set_a_z(self.ram(CHIP8_V0) + x as u16);
+                if self.quirks.vf_reset {
+                    self.xor_a();
+                    self.ld_mem_a(self.ram(CHIP8_V0) + 0xF);
+                    self.set_a_z(self.ram(CHIP8_V0) + 0xF);
+                }
             }
 
             // 8XY4 - ADD Vx, Vy (with carry to VF)
             (0x8, _, _, 0x4) => {
                 let x = inst.x();
                 let y = inst.y();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.ld_hl_nn(CHIP8_V0 + y as u16);
+                self.load_v(x, a_in);
+                self.ld_hl_nn(self.ram(CHIP8_V0) + y as u16);
                 self.add_a_hl();
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
                 // Set VF to carry
-                self.ld_a_n(0);
-                self.emit(0xCE); self.emit(0x00);  // ADC A, 0
-                self.ld_mem_a(CHIP8_V0 + 0xF);
+                self.carry_to_vf();
             }
 
             // 8XY5 - SUB Vx, Vy (VF = NOT borrow)
             (0x8, _, _, 0x5) => {
                 let x = inst.x();
                 let y = inst.y();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.ld_hl_nn(CHIP8_V0 + y as u16);
+                self.load_v(x, a_in);
+                self.ld_hl_nn(self.ram(CHIP8_V0) + y as u16);
                 self.sub_hl();
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
                 // VF = NOT borrow (1 if no borrow)
-                self.ld_a_n(1);
-                self.jr_nc("no_borrow_8xy5");
-                self.xor_a();
-                self.label("no_borrow_8xy5");
-                self.ld_mem_a(CHIP8_V0 + 0xF);
+                self.ccf();
+                self.carry_to_vf();
             }
 
             // 8XY6 - SHR Vx (VF = LSB)
             (0x8, _, _, 0x6) => {
                 let x = inst.x();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.emit(0xCB); self.emit(0x3F);  // SRL A
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                let src = if self.quirks.shift_vy { inst.y() } else { x };
+                self.load_v(src, a_in);
+                self.srl_a();
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
                 // VF = old LSB
-                self.ld_a_n(0);
-                self.emit(0xCE); self.emit(0x00);  // ADC A, 0
-                self.ld_mem_a(CHIP8_V0 + 0xF);
+                self.carry_to_vf();
             }
 
             // 8XY7 - SUBN Vx, Vy (Vx = Vy - Vx, VF = NOT borrow)
             (0x8, _, _, 0x7) => {
                 let x = inst.x();
                 let y = inst.y();
-                self.ld_a_mem(CHIP8_V0 + y as u16);
-                self.ld_hl_nn(CHIP8_V0 + x as u16);
+                self.load_v(y, a_in);
+                self.ld_hl_nn(self.ram(CHIP8_V0) + x as u16);
                 self.sub_hl();
-                self.ld_mem_a(CHIP8_V0 + x as u16);
-                self.ld_a_n(1);
-                self.jr_nc("no_borrow_8xy7");
-                self.xor_a();
-                self.label("no_borrow_8xy7");
-                self.ld_mem_a(CHIP8_V0 + 0xF);
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
+                self.ccf();
+                self.carry_to_vf();
             }
 
             // 8XYE - SHL Vx (VF = MSB)
             (0x8, _, _, 0xE) => {
                 let x = inst.x();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.emit(0xCB); self.emit(0x27);  // SLA A
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                let src = if self.quirks.shift_vy { inst.y() } else { x };
+                self.load_v(src, a_in);
+                self.sla_a();
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
                 // VF = old MSB (now in carry)
-                self.ld_a_n(0);
-                self.emit(0xCE); self.emit(0x00);  // ADC A, 0
-                self.ld_mem_a(CHIP8_V0 + 0xF);
+                self.carry_to_vf();
             }
 
             // 9XY0 - SNE Vx, Vy
             (0x9, _, _, 0x0) => {
                 let x = inst.x();
                 let y = inst.y();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.ld_hl_nn(CHIP8_V0 + y as u16);
+                self.load_v(x, a_in);
+                self.ld_hl_nn(self.ram(CHIP8_V0) + y as u16);
                 self.cp_hl();
-                let next_addr = inst.addr + 4;
-                if let Some(label) = self.chip8_labels.get(&next_addr) {
-                    self.jp_nz_label(&label.clone());
+                if let Some(label) = self.skip_label(inst) {
+                    self.jr_nz(&label);
                 }
+                self.a_reg = Some(self.ram(CHIP8_V0) + x as u16);
+            }
+
+            // F000 NNNN - LD I, long addr (XO-CHIP)
+            (0xF, 0x0, 0x0, 0x0) if inst.operand.is_some() => {
+                let nnnn = inst.operand.unwrap_or(0);
+                self.ld_hl_nn(nnnn);
+                self.ld_de_nn(self.ram(CHIP8_I));
+                self.ld_a_l();
+                self.ld_de_a();
+                self.inc_de();
+                self.ld_a_h();
+                self.ld_de_a();
             }
 
             // ANNN - LD I, addr
             (0xA, _, _, _) => {
                 let nnn = inst.nnn();
                 self.ld_hl_nn(nnn);
-                self.ld_de_nn(CHIP8_I);
+                self.ld_de_nn(self.ram(CHIP8_I));
                 self.ld_a_l();
                 self.ld_de_a();
                 self.inc_de();
@@ -694,18 +8127,17 @@ impl Compiler {
                 self.ld_de_a();
             }
 
-            // BNNN - JP V0, addr
+            // BNNN - JP V0, addr (BXNN - JP VX, addr with the jump quirk)
             (0xB, _, _, _) => {
                 let nnn = inst.nnn();
-                self.ld_a_mem(CHIP8_V0);
+                let offset_reg = if self.quirks.jump_vx { inst.x() } else { 0 };
+                self.ld_a_mem(self.ram(CHIP8_V0) + offset_reg as u16);
                 self.ld_l_a();
                 self.ld_h_n(0);
                 self.ld_de_nn(nnn);
                 self.add_hl_de();
-                // This is tricky for static compilation - need runtime jump table
-                // For now, just use a simple computed jump
-                self.push_hl();
-                self.ret();  // Jump to HL
+                // Look the CHIP-8 target up in the dispatch table
+                self.jp_label("dispatch");
             }
 
             // CXNN - RND Vx, byte
@@ -714,7 +8146,8 @@ impl Compiler {
                 let nn = inst.nn();
                 self.call_label("rng");
                 self.and_n(nn);
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
+                self.set_a_z(self.ram(CHIP8_V0) + x as u16);
             }
 
             // DXYN - DRW Vx, Vy, nibble
@@ -723,149 +8156,135 @@ impl Compiler {
                 let y = inst.y();
                 let n = inst.n();
 
-                // Calculate screen address: (Vy * 8) + (Vx / 8) + DISPLAY_BUF
-                // For simplicity, we'll use byte-aligned X
-                self.ld_a_mem(CHIP8_V0 + y as u16);
-                self.emit(0xE6); self.emit(0x1F);  // AND 31 (wrap Y)
-                self.ld_l_a();
-                self.ld_h_n(0);
-                // *8 (8 bytes per row)
-                self.add_hl_hl();
-                self.add_hl_hl();
-                self.add_hl_hl();
-                // Add X/8
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.emit(0xE6); self.emit(0x3F);  // AND 63 (wrap X)
-                self.emit(0xCB); self.emit(0x3F);  // SRL A (divide by 2)
-                self.emit(0xCB); self.emit(0x3F);  // SRL A (divide by 4)
-                self.emit(0xCB); self.emit(0x3F);  // SRL A (divide by 8)
-                self.ld_e_a();
-                self.ld_d_n(0);
-                self.add_hl_de();
-                self.ld_de_nn(DISPLAY_BUF);
-                self.add_hl_de();
+                if self.hires {
+                    // At either resolution, as the mode is when it runs
+                    let lores_label = format!("draw_lores_{:03X}", inst.addr);
+                    let drawn_label = format!("draw_drawn_{:03X}", inst.addr);
+                    self.draw_sprite_addr(inst);
+                    self.ld_a_mem(self.ram(HIRES_MODE));
+                    self.or_a();
+                    self.jr_z(&lores_label);
+                    self.ld_a_mem(self.ram(CHIP8_V0) + x as u16);
+                    self.ld_d_a();
+                    self.ld_a_mem(self.ram(CHIP8_V0) + y as u16);
+                    self.ld_e_a();
+                    self.ld_b_n(n);
+                    self.call_label("draw_hires");
+                    self.jr_label(&drawn_label);
+                    self.label(&lores_label);
+                    self.push_hl();  // Save sprite address
+                    self.draw_screen_addr(x, y);
+                    self.ex_de_hl();  // DE = screen address
+                    self.pop_hl();
+                    self.draw_shift(x);
+                    self.ld_c_a();
+                    self.ld_b_n(n);
+                    self.call_label("draw_sprite");
+                    self.label(&drawn_label);
+                    self.ld_mem_a(self.ram(CHIP8_V0) + 0xF);
+                    self.call_label(self.refresh_drawn());
+                    return Ok(());
+                }
+                self.draw_screen_addr(x, y);
+                if self.move_plan.get(&inst.addr) == Some(&MovePlan::Erase) {
+                    // Erased together with the redraw by move_sprite
+                    self.ld_mem_hl(self.ram(DRAW_OLD));
+                    self.draw_shift(x);
+                    self.ld_mem_a(self.ram(DRAW_OLD_SHIFT));
+                    return Ok(());
+                }
                 self.push_hl();  // Save screen address
-
-                // Get sprite address from I
-                self.ld_hl_nn(CHIP8_I);
-                self.ld_e_hl();
-                self.inc_hl();
-                self.ld_d_hl();
-                // Add FONT_DATA base if I < 0x50 (font sprite)
-                // Use unique labels per DRW to avoid conflicts
-                let not_font_label = format!("draw_not_font_{:03X}", inst.addr);
-                let have_sprite_label = format!("draw_have_sprite_{:03X}", inst.addr);
-                self.ld_a_d();
-                self.or_a();
-                self.jr_nz(&not_font_label);
-                self.ld_a_e();
-                self.cp_n(0x50);  // Font data is 0-0x50
-                self.jr_nc(&not_font_label);
-                // Font sprite: HL = FONT_DATA + I
-                self.ld_hl_nn(FONT_DATA);
-                self.add_hl_de();
-                self.jr_label(&have_sprite_label);
-                self.label(&not_font_label);
-                // Custom sprite: I is CHIP-8 address (>= 0x200)
-                // Convert to Z80 address: chip8_rom_data + (I - 0x200)
-                // Since chip8_rom_data corresponds to CHIP-8 0x200, we just add the offset
-                self.ld_hl_nn(0x200);  // Subtract CHIP-8 base
-                self.ex_de_hl();       // DE = 0x200, HL = I
-                self.or_a();           // Clear carry
-                self.sbc_hl_de();      // HL = I - 0x200
-                self.ex_de_hl();       // DE = I - 0x200
-                self.ld_hl_label("chip8_rom_data");
-                self.add_hl_de();      // HL = chip8_rom_data + (I - 0x200)
-                self.label(&have_sprite_label);
+                self.draw_sprite_addr(inst);
                 // HL = sprite address
                 self.pop_de();  // DE = screen address
+                self.draw_shift(x);
+                self.ld_c_a();
                 self.ld_b_n(n);
-                self.call_label("draw_sprite");
+                if self.move_plan.get(&inst.addr) == Some(&MovePlan::Redraw) {
+                    self.call_label("move_sprite");
+                } else {
+                    self.call_label("draw_sprite");
+                }
                 // Store VF
-                self.ld_mem_a(CHIP8_V0 + 0xF);
+                self.ld_mem_a(self.ram(CHIP8_V0) + 0xF);
                 // Refresh display
-                self.call_label("refresh_display");
+                match self.refresh_plan.get(&inst.addr).cloned() {
+                    Some(RefreshPlan::Deferred) => {}
+                    Some(RefreshPlan::JoinAt(label)) => {
+                        self.label(&label);
+                        self.call_label(self.refresh_drawn());
+                    }
+                    _ => self.call_label(self.refresh_drawn()),
+                }
             }
 
             // EX9E - SKP Vx (skip if key pressed)
             (0xE, _, 0x9, 0xE) => {
-                let x = inst.x();
-                self.call_label("get_key");
-                self.ld_hl_nn(CHIP8_V0 + x as u16);
-                self.cp_hl();
-                let next_addr = inst.addr + 4;
-                if let Some(label) = self.chip8_labels.get(&next_addr) {
-                    self.jp_z_label(&label.clone());
+                self.test_key(inst.x());
+                if let Some(label) = self.skip_label(inst) {
+                    self.jr_z(&label);
                 }
             }
 
             // EXA1 - SKNP Vx (skip if key not pressed)
             (0xE, _, 0xA, 0x1) => {
-                let x = inst.x();
-                self.call_label("get_key");
-                self.ld_hl_nn(CHIP8_V0 + x as u16);
-                self.cp_hl();
-                let next_addr = inst.addr + 4;
-                if let Some(label) = self.chip8_labels.get(&next_addr) {
-                    self.jp_nz_label(&label.clone());
+                self.test_key(inst.x());
+                if let Some(label) = self.skip_label(inst) {
+                    self.jr_nz(&label);
                 }
             }
 
             // FX07 - LD Vx, DT
             (0xF, _, 0x0, 0x7) => {
                 let x = inst.x();
-                self.ld_a_mem(CHIP8_DT);
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                self.ld_a_mem(self.ram(CHIP8_DT));
+                self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
+                self.a_reg = Some(self.ram(CHIP8_V0) + x as u16);
             }
 
             // FX0A - LD Vx, K (wait for key)
             (0xF, _, 0x0, 0xA) => {
                 let x = inst.x();
-                self.call_label("wait_key");
-                self.ld_mem_a(CHIP8_V0 + x as u16);
+                if let Some(MenuPlan::Wait(entries)) = self.menu_plan.get(&inst.addr).cloned() {
+                    self.compile_menu(inst.addr, x, &entries);
+                } else {
+                    self.call_label("wait_key");
+                    self.ld_mem_a(self.ram(CHIP8_V0) + x as u16);
+                }
             }
 
             // FX15 - LD DT, Vx
             (0xF, _, 0x1, 0x5) => {
                 let x = inst.x();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.ld_mem_a(CHIP8_DT);
+                self.load_v(x, a_in);
+                self.ld_mem_a(self.ram(CHIP8_DT));
+                self.a_reg = Some(self.ram(CHIP8_V0) + x as u16);
             }
 
             // FX18 - LD ST, Vx
             (0xF, _, 0x1, 0x8) => {
                 let x = inst.x();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.ld_mem_a(CHIP8_ST);
+                self.load_v(x, a_in);
+                self.ld_mem_a(self.ram(CHIP8_ST));
+                self.a_reg = Some(self.ram(CHIP8_V0) + x as u16);
             }
 
             // FX1E - ADD I, Vx
             (0xF, _, 0x1, 0xE) => {
                 let x = inst.x();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.ld_l_a();
-                self.ld_h_n(0);
-                self.ld_de_nn(CHIP8_I);
-                self.push_de();
-                self.ld_a_de();
+                self.ld_a_mem(self.ram(CHIP8_V0) + x as u16);
                 self.ld_e_a();
-                self.inc_de();
-                self.ld_a_de();
-                self.ld_d_a();
+                self.ld_d_n(0);
+                self.ld_hl_mem(self.ram(CHIP8_I));
                 self.add_hl_de();
-                self.pop_de();
-                self.ld_a_l();
-                self.ld_de_a();
-                self.inc_de();
-                self.ld_a_h();
-                self.ld_de_a();
+                self.ld_mem_hl(self.ram(CHIP8_I));
             }
 
             // FX29 - LD F, Vx (point I to font sprite)
             (0xF, _, 0x2, 0x9) => {
                 let x = inst.x();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
-                self.emit(0xE6); self.emit(0x0F);  // AND 0x0F
+                self.ld_a_mem(self.ram(CHIP8_V0) + x as u16);
+                self.and_n(0x0F);
                 // Multiply by 5 (each font char is 5 bytes)
                 self.ld_l_a();
                 self.ld_h_n(0);
@@ -875,7 +8294,7 @@ impl Compiler {
                 self.ld_d_n(0);
                 self.add_hl_de();  // *5 = offset into font (0-0x4F)
                 // Store offset in I (don't add FONT_DATA here - DRW will handle it)
-                self.ld_de_nn(CHIP8_I);
+                self.ld_de_nn(self.ram(CHIP8_I));
                 self.ld_a_l();
                 self.ld_de_a();
                 self.inc_de();
@@ -886,24 +8305,22 @@ impl Compiler {
             // FX33 - LD B, Vx (BCD)
             (0xF, _, 0x3, 0x3) => {
                 let x = inst.x();
-                self.ld_a_mem(CHIP8_V0 + x as u16);
+                let hundreds = format!("bcd_hundreds_{:03X}", inst.addr);
+                let tens = format!("bcd_tens_{:03X}", inst.addr);
+                let tens_loop = format!("bcd_tens_loop_{:03X}", inst.addr);
+                let ones = format!("bcd_ones_{:03X}", inst.addr);
                 // Get I address
-                self.ld_hl_nn(CHIP8_I);
-                self.ld_e_hl();
-                self.inc_hl();
-                self.ld_d_hl();
-                // Add RAM base
-                self.ld_hl_nn(CHIP8_RAM - 0x200);
-                self.add_hl_de();
+                self.call_label("translate_i");
+                self.ld_a_mem(self.ram(CHIP8_V0) + x as u16);
                 // Store hundreds
                 self.ld_b_n(0);
-                self.label("bcd_hundreds");
+                self.label(&hundreds);
                 self.cp_n(100);
-                self.jr_c("bcd_tens");
+                self.jr_c(&tens);
                 self.sub_n(100);
                 self.inc_b();
-                self.jr_label("bcd_hundreds");
-                self.label("bcd_tens");
+                self.jr_label(&hundreds);
+                self.label(&tens);
                 self.push_af();
                 self.ld_a_b();
                 self.ld_hl_a();
@@ -911,13 +8328,13 @@ impl Compiler {
                 self.pop_af();
                 // Store tens
                 self.ld_b_n(0);
-                self.label("bcd_tens_loop");
+                self.label(&tens_loop);
                 self.cp_n(10);
-                self.jr_c("bcd_ones");
+                self.jr_c(&ones);
                 self.sub_n(10);
                 self.inc_b();
-                self.jr_label("bcd_tens_loop");
-                self.label("bcd_ones");
+                self.jr_label(&tens_loop);
+                self.label(&ones);
                 self.push_af();
                 self.ld_a_b();
                 self.ld_hl_a();
@@ -925,59 +8342,211 @@ impl Compiler {
                 self.pop_af();
                 // Store ones
                 self.ld_hl_a();
+                if self.quirks.display_ram {
+                    self.call_label("display_written");
+                }
             }
 
             // FX55 - LD [I], Vx (store V0-Vx)
             (0xF, _, 0x5, 0x5) => {
                 let x = inst.x();
+                let store_label = format!("store_regs_{:03X}", inst.addr);
                 // Get I
-                self.ld_hl_nn(CHIP8_I);
-                self.ld_e_hl();
-                self.inc_hl();
-                self.ld_d_hl();
-                self.ld_hl_nn(CHIP8_RAM - 0x200);
-                self.add_hl_de();
+                self.call_label("translate_i");
                 self.ex_de_hl();  // DE = destination
-                self.ld_hl_nn(CHIP8_V0);
+                self.ld_hl_nn(self.ram(CHIP8_V0));
                 self.ld_b_n(x + 1);
-                self.label("store_regs");
+                self.label(&store_label);
                 self.ld_a_hl();
                 self.ld_de_a();
                 self.inc_hl();
                 self.inc_de();
                 self.dec_b();
-                self.jr_nz("store_regs");
+                self.jr_nz(&store_label);
+                if self.quirks.display_ram {
+                    self.call_label("display_written");
+                }
+                if self.quirks.load_store_increment {
+                    self.advance_i(x as u16 + 1);
+                }
             }
 
             // FX65 - LD Vx, [I] (load V0-Vx)
             (0xF, _, 0x6, 0x5) => {
                 let x = inst.x();
+                let load_label = format!("load_regs_{:03X}", inst.addr);
                 // Get I
-                self.ld_hl_nn(CHIP8_I);
-                self.ld_e_hl();
-                self.inc_hl();
-                self.ld_d_hl();
-                self.ld_hl_nn(CHIP8_RAM - 0x200);
-                self.add_hl_de();  // HL = source
-                self.ld_de_nn(CHIP8_V0);
+                self.call_label("translate_i");  // HL = source
+                self.ld_de_nn(self.ram(CHIP8_V0));
                 self.ld_b_n(x + 1);
-                self.label("load_regs");
+                self.label(&load_label);
                 self.ld_a_hl();
                 self.ld_de_a();
                 self.inc_hl();
                 self.inc_de();
                 self.dec_b();
-                self.jr_nz("load_regs");
+                self.jr_nz(&load_label);
+                if self.quirks.load_store_increment {
+                    self.advance_i(x as u16 + 1);
+                }
             }
 
             _ => {
-                // Unknown opcode - NOP
+                self.diagnose(
+                    Category::UnknownOpcode,
+                    format!("{:03X}: unknown opcode {:04X}, compiled as a NOP", inst.addr, inst.opcode),
+                )?;
             }
         }
 
         Ok(())
     }
 
+    /// Result of an instruction whose register operands are all known
+    fn fold(&self, inst: &Instruction) -> Option<Fold> {
+        let known = |r: u8| self.v_const[r as usize];
+        let (_, x, y, _) = inst.nibbles();
+        let vf = 0xF;
+        let src = if self.quirks.shift_vy { y } else { x };
+        let fold = match inst.nibbles() {
+            (0x3, _, _, _) => Fold::Skip(known(x)? == inst.nn()),
+            (0x4, _, _, _) => Fold::Skip(known(x)? != inst.nn()),
+            (0x5, _, _, 0x0) => Fold::Skip(known(x)? == known(y)?),
+            (0x9, _, _, 0x0) => Fold::Skip(known(x)? != known(y)?),
+            (0x6, _, _, _) => Fold::Set(vec![(x, inst.nn())]),
+            (0x7, _, _, _) => Fold::Set(vec![(x, known(x)?.wrapping_add(inst.nn()))]),
+            (0x8, _, _, 0x0) => Fold::Set(vec![(x, known(y)?)]),
+            (0x8, _, _, op @ 0x1..=0x3) => {
+                let (a, b) = (known(x)?, known(y)?);
+                let value = match op {
+                    0x1 => a | b,
+                    0x2 => a & b,
+                    _ => a ^ b,
+                };
+                let mut results = vec![(x, value)];
+                if self.quirks.vf_reset {
+                    results.push((vf, 0));
+                }
+                Fold::Set(results)
+            }
+            (0x8, _, _, 0x4) => {
+                let (value, carry) = known(x)?.overflowing_add(known(y)?);
+                Fold::Set(vec![(x, value), (vf, carry as u8)])
+            }
+            (0x8, _, _, 0x5) => {
+                let (a, b) = (known(x)?, known(y)?);
+                Fold::Set(vec![(x, a.wrapping_sub(b)), (vf, (a >= b) as u8)])
+            }
+            (0x8, _, _, 0x7) => {
+                let (a, b) = (known(x)?, known(y)?);
+                Fold::Set(vec![(x, b.wrapping_sub(a)), (vf, (b >= a) as u8)])
+            }
+            (0x8, _, _, 0x6) => {
+                let value = known(src)?;
+                Fold::Set(vec![(x, value >> 1), (vf, value & 1)])
+            }
+            (0x8, _, _, 0xE) => {
+                let value = known(src)?;
+                Fold::Set(vec![(x, value << 1), (vf, value >> 7)])
+            }
+            _ => return None,
+        };
+        Some(fold)
+    }
+
+    /// V registers an instruction may write, as a bitmask
+    fn v_writes(&self, inst: &Instruction) -> u16 {
+        match inst.nibbles() {
+            (0xD, _, _, _) => 1 << 0xF,
+            (0xF, _, 0x0, 0x7) | (0xF, _, 0x0, 0xA) => 1 << inst.x(),
+            (0xF, x, 0x6, 0x5) => (2u32 << x) as u16 - 1,
+            _ => self.reg_effects(inst).map_or(0, |(_, writes)| writes as u16),
+        }
+    }
+
+    /// Store the constants not yet written to their registers, loading A
+    /// once per distinct value
+    fn flush_consts(&mut self) {
+        while self.v_dirty != 0 {
+            let first = self.v_dirty.trailing_zeros() as usize;
+            let value = self.v_const[first].unwrap_or(0);
+            self.ld_a_n(value);
+            for r in first..16 {
+                if self.v_dirty & (1 << r) != 0 && self.v_const[r] == Some(value) {
+                    self.ld_mem_a(self.ram(CHIP8_V0) + r as u16);
+                    self.v_dirty &= !(1 << r);
+                    self.a_reg = Some(self.ram(CHIP8_V0) + r as u16);
+                }
+            }
+        }
+    }
+
+    /// I += n (load/store increment quirk)
+    fn advance_i(&mut self, n: u16) {
+        self.ld_hl_mem(self.ram(CHIP8_I));
+        self.ld_de_nn(n);
+        self.add_hl_de();
+        self.ld_mem_hl(self.ram(CHIP8_I));
+    }
+
+    /// CHIP-8 address reached when a skip instruction at `inst` skips.
+    /// XO-CHIP skips step over the whole 4-byte F000 NNNN form.
+    fn skip_target(&self, inst: &Instruction) -> u16 {
+        let next = (inst.addr + 2 - 0x200) as usize;
+        if next + 1 < self.chip8_rom.len() {
+            let opcode = ((self.chip8_rom[next] as u16) << 8) | (self.chip8_rom[next + 1] as u16);
+            if Instruction::is_long_prefix(opcode) {
+                return inst.addr + 6;
+            }
+        }
+        inst.addr + 4
+    }
+
+    /// Load Vx into A unless the previous instruction already left it there
+    fn load_v(&mut self, x: u8, a_in: Option<u16>) {
+        let addr = self.ram(CHIP8_V0) + x as u16;
+        if a_in != Some(addr) {
+            self.ld_a_mem(addr);
+        }
+    }
+
+    /// Set Z for `Vx == nn`, reusing A and the flags where possible
+    fn compare_v_n(&mut self, x: u8, nn: u8, a_in: Option<u16>, z_in: Option<u16>) {
+        let addr = self.ram(CHIP8_V0) + x as u16;
+        if nn == 0 && z_in == Some(addr) && a_in == Some(addr) {
+            return; // Z already reflects Vx
+        }
+        self.load_v(x, a_in);
+        if nn == 0 {
+            self.or_a();
+        } else {
+            self.cp_n(nn);
+        }
+    }
+
+    /// Record what a 3XNN/4XNN comparison left in A and the flags
+    fn after_compare(&mut self, x: u8, nn: u8) {
+        let addr = self.ram(CHIP8_V0) + x as u16;
+        self.a_reg = Some(addr);
+        if nn == 0 {
+            self.z_reg = Some(addr);
+        }
+    }
+
+    /// Store the carry flag into VF as 0 or 1
+    fn carry_to_vf(&mut self) {
+        self.ld_a_n(0);
+        self.adc_a_n(0); // A = carry; Z set when it is 0
+        self.ld_mem_a(self.ram(CHIP8_V0) + 0xF);
+        self.set_a_z(self.ram(CHIP8_V0) + 0xF);
+    }
+
+    /// A holds `addr` and Z reflects it
+    fn set_a_z(&mut self, addr: u16) {
+        self.a_reg = Some(addr);
+        self.z_reg = Some(addr);
+    }
+
     fn embed_font(&self, _rom: &mut [u8]) {
         // Font is already embedded in code via font_rom label
     }
@@ -985,7 +8554,7 @@ impl Compiler {
     // Helper methods for emitting Z80 code
     fn emit(&mut self, byte: u8) {
         self.code.push(byte);
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);  // Past FFFF only in code too big to fit, which compile_code refuses
     }
 
     fn emit16(&mut self, word: u16) {
@@ -998,158 +8567,322 @@ impl Compiler {
     }
 
     fn emit_label_ref(&mut self, name: &str) {
+        self.note_entry(name, None);
         self.forward_refs.push((self.pc, name.to_string()));
         self.emit16(0);  // Placeholder
     }
 
+    /// Record a way into a CHIP-8 label and what A holds going in, None
+    /// when unknown (or the reference isn't a jump from here)
+    fn note_entry(&mut self, name: &str, a: Option<u16>) {
+        if name.starts_with("c8_") {
+            self.entry_regs.entry(name.to_string()).or_default().push(a);
+        }
+    }
+
     fn resolve_refs(&mut self) -> Result<(), String> {
         for (addr, name) in &self.forward_refs {
             let target = self.labels.get(name)
                 .ok_or_else(|| format!("Undefined label: {}", name))?;
-            let offset = *addr as usize;  // Direct index since pc starts at 0
+            let offset = addr.wrapping_sub(self.origin) as usize;
             self.code[offset] = (*target & 0xFF) as u8;
             self.code[offset + 1] = (*target >> 8) as u8;
         }
+        for (addr, name) in &self.relative_refs {
+            let target = self.labels.get(name)
+                .ok_or_else(|| format!("Undefined label: {}", name))?;
+            let offset = target.wrapping_sub(addr.wrapping_add(1)) as i16 as i32;
+            if !(-128..=127).contains(&offset) {
+                return Err(format!("Relative jump to {} out of range", name));
+            }
+            self.code[addr.wrapping_sub(self.origin) as usize] = offset as i8 as u8;
+        }
         Ok(())
     }
 
-    // Z80 instruction helpers
+    /// Jump sites of the last pass whose target a JR can reach
+    fn jumps_in_range(&self) -> HashMap<usize, String> {
+        self.jump_sites
+            .iter()
+            .enumerate()
+            .filter(|(_, (site, label))| {
+                self.labels.get(label).is_some_and(|&target| {
+                    (-128..=127).contains(&(target as i32 - (*site as i32 + 2)))
+                })
+            })
+            .map(|(i, (_, label))| (i, label.clone()))
+            .collect()
+    }
+
+}
+
+// Z80 instruction helpers
+impl Compiler {
     fn jp_label(&mut self, label: &str) {
         self.emit(0xC3);
         self.emit_label_ref(label);
+        self.tstates += 10;
     }
 
     fn jp_z_label(&mut self, label: &str) {
         self.emit(0xCA);
         self.emit_label_ref(label);
+        self.tstates += 10;
     }
 
     fn jp_nz_label(&mut self, label: &str) {
         self.emit(0xC2);
         self.emit_label_ref(label);
+        self.tstates += 10;
     }
 
-    fn jr_label(&mut self, label: &str) {
-        // For simplicity, use JP instead of JR for labels
-        self.jp_label(label);
+    fn jp_m_label(&mut self, label: &str) {
+        self.emit(0xFA);
+        self.emit_label_ref(label);
+        self.tstates += 10;
     }
 
-    fn jr_z(&mut self, label: &str) {
-        self.jp_z_label(label);
+    #[cfg_attr(not(feature = "interp"), allow(dead_code))]
+    fn jp_p_label(&mut self, label: &str) {
+        self.emit(0xF2);
+        self.emit_label_ref(label);
+        self.tstates += 10;
     }
 
-    fn jr_nz(&mut self, label: &str) {
-        self.jp_nz_label(label);
+    fn jp_c_label(&mut self, label: &str) {
+        self.emit(0xDA);
+        self.emit_label_ref(label);
+        self.tstates += 10;
     }
 
-    fn jr_c(&mut self, label: &str) {
-        self.emit(0xDA);  // JP C
-        self.emit_label_ref(label);
+    /// A JR when the relaxation pass found the target in range, otherwise
+    /// the equivalent JP. Counts a conditional JR as not taken.
+    fn relative_jump(&mut self, jr: u8, jp: u8, label: &str) {
+        let site = self.jump_sites.len();
+        self.jump_sites.push((self.pc, label.to_string()));
+        self.note_entry(label, self.a_reg);
+        if self.short_jumps.get(&site).is_some_and(|target| target == label) {
+            self.emit(jr);
+            self.relative_refs.push((self.pc, label.to_string()));
+            self.emit(0);  // Offset, resolved with the labels
+            self.tstates += if jr == 0x18 { 12 } else { 7 };
+        } else {
+            self.emit(jp);
+            self.forward_refs.push((self.pc, label.to_string()));
+            self.emit16(0);  // Placeholder
+            self.tstates += 10;
+        }
     }
 
-    fn jr_nc(&mut self, label: &str) {
-        self.emit(0xD2);  // JP NC
+    fn jr_label(&mut self, label: &str) { self.relative_jump(0x18, 0xC3, label); }
+    fn jr_z(&mut self, label: &str) { self.relative_jump(0x28, 0xCA, label); }
+    fn jr_nz(&mut self, label: &str) { self.relative_jump(0x20, 0xC2, label); }
+    fn jr_c(&mut self, label: &str) { self.relative_jump(0x38, 0xDA, label); }
+    fn jr_nc(&mut self, label: &str) { self.relative_jump(0x30, 0xD2, label); }
+
+    fn call_c_label(&mut self, label: &str) {
+        self.emit(0xDC);  // CALL C
         self.emit_label_ref(label);
+        self.tstates += 10;  // Not taken
     }
 
     fn call_label(&mut self, label: &str) {
         self.emit(0xCD);
         self.emit_label_ref(label);
+        self.tstates += 17;
     }
 
-    fn ret(&mut self) { self.emit(0xC9); }
-    fn ret_z(&mut self) { self.emit(0xC8); }
-
-    fn ld_hl_nn(&mut self, nn: u16) { self.emit(0x21); self.emit16(nn); }
-    fn ld_de_nn(&mut self, nn: u16) { self.emit(0x11); self.emit16(nn); }
-    fn ld_bc_nn(&mut self, nn: u16) { self.emit(0x01); self.emit16(nn); }
-    fn ld_hl_label(&mut self, label: &str) { self.emit(0x21); self.emit_label_ref(label); }
-
-    fn ld_a_n(&mut self, n: u8) { self.emit(0x3E); self.emit(n); }
-    fn ld_b_n(&mut self, n: u8) { self.emit(0x06); self.emit(n); }
-    fn ld_c_n(&mut self, n: u8) { self.emit(0x0E); self.emit(n); }
-    fn ld_d_n(&mut self, n: u8) { self.emit(0x16); self.emit(n); }
-    fn ld_e_n(&mut self, n: u8) { self.emit(0x1E); self.emit(n); }
-    fn ld_h_n(&mut self, n: u8) { self.emit(0x26); self.emit(n); }
-    fn ld_l_n(&mut self, n: u8) { self.emit(0x2E); self.emit(n); }
-
-    fn ld_a_hl(&mut self) { self.emit(0x7E); }
-    fn ld_hl_a(&mut self) { self.emit(0x77); }
-    fn ld_a_de(&mut self) { self.emit(0x1A); }
-    fn ld_de_a(&mut self) { self.emit(0x12); }
-    fn ld_a_b(&mut self) { self.emit(0x78); }
-    fn ld_a_c(&mut self) { self.emit(0x79); }
-    fn ld_a_d(&mut self) { self.emit(0x7A); }
-    fn ld_a_e(&mut self) { self.emit(0x7B); }
-    fn ld_a_l(&mut self) { self.emit(0x7D); }
-    fn ld_a_h(&mut self) { self.emit(0x7C); }
-    fn ld_l_a(&mut self) { self.emit(0x6F); }
-    fn ld_h_a(&mut self) { self.emit(0x67); }
-    fn ld_e_a(&mut self) { self.emit(0x5F); }
-    fn ld_d_a(&mut self) { self.emit(0x57); }
-    fn ld_b_a(&mut self) { self.emit(0x47); }
-    fn ld_c_a(&mut self) { self.emit(0x4F); }
-    fn ld_e_hl(&mut self) { self.emit(0x5E); }
-    fn ld_d_hl(&mut self) { self.emit(0x56); }
-    fn ld_l_e(&mut self) { self.emit(0x6B); }
-    fn ld_h_d(&mut self) { self.emit(0x62); }
-    fn ld_h_hl(&mut self) { self.emit(0x66); }
-
-    fn ld_a_mem(&mut self, addr: u16) { self.emit(0x3A); self.emit16(addr); }
-    fn ld_mem_a(&mut self, addr: u16) { self.emit(0x32); self.emit16(addr); }
-
-    fn inc_hl(&mut self) { self.emit(0x23); }
-    fn inc_de(&mut self) { self.emit(0x13); }
-    fn inc_bc(&mut self) { self.emit(0x03); }
-    fn inc_a(&mut self) { self.emit(0x3C); }
-    fn inc_b(&mut self) { self.emit(0x04); }
-    fn inc_hl_ind(&mut self) { self.emit(0x34); }
-
-    fn dec_a(&mut self) { self.emit(0x3D); }
-    fn dec_b(&mut self) { self.emit(0x05); }
-    fn dec_c(&mut self) { self.emit(0x0D); }
-    fn dec_d(&mut self) { self.emit(0x15); }
-    fn dec_e(&mut self) { self.emit(0x1D); }
-    fn dec_hl(&mut self) { self.emit(0x2B); }
-    fn dec_bc(&mut self) { self.emit(0x0B); }
-
-    fn add_hl_de(&mut self) { self.emit(0x19); }
-    fn add_hl_hl(&mut self) { self.emit(0x29); }
-    fn add_a_n(&mut self, n: u8) { self.emit(0xC6); self.emit(n); }
-    fn add_a_hl(&mut self) { self.emit(0x86); }
-
-    fn sbc_hl_de(&mut self) { self.emit(0xED); self.emit(0x52); }
-
-    fn sub_n(&mut self, n: u8) { self.emit(0xD6); self.emit(n); }
-    fn sub_hl(&mut self) { self.emit(0x96); }
-
-    fn and_n(&mut self, n: u8) { self.emit(0xE6); self.emit(n); }
-    fn and_a_b(&mut self) { self.emit(0xA0); }
-    fn and_a_c(&mut self) { self.emit(0xA1); }
-    fn and_a_d(&mut self) { self.emit(0xA2); }
-    fn and_a_e(&mut self) { self.emit(0xA3); }
-    fn and_hl(&mut self) { self.emit(0xA6); }
-
-    fn or_a(&mut self) { self.emit(0xB7); }
-    fn or_c(&mut self) { self.emit(0xB1); }
-    fn or_hl(&mut self) { self.emit(0xB6); }
-
-    fn xor_a(&mut self) { self.emit(0xAF); }
-    fn xor_h(&mut self) { self.emit(0xAC); }
-    fn xor_hl(&mut self) { self.emit(0xAE); }
-
-    fn cp_n(&mut self, n: u8) { self.emit(0xFE); self.emit(n); }
-    fn cp_hl(&mut self) { self.emit(0xBE); }
-
-    fn push_af(&mut self) { self.emit(0xF5); }
-    fn push_hl(&mut self) { self.emit(0xE5); }
-    fn push_de(&mut self) { self.emit(0xD5); }
-    fn pop_af(&mut self) { self.emit(0xF1); }
-    fn pop_hl(&mut self) { self.emit(0xE1); }
-    fn pop_de(&mut self) { self.emit(0xD1); }
-
-    fn ex_de_hl(&mut self) { self.emit(0xEB); }
-
-    fn out_n_a(&mut self, port: u8) { self.emit(0xD3); self.emit(port); }
-    fn in_a_n(&mut self, port: u8) { self.emit(0xDB); self.emit(port); }
+    fn call_nn(&mut self, addr: u16) {
+        self.emit(0xCD);
+        self.emit16(addr);
+        self.tstates += 17;
+    }
+
+    fn ret(&mut self) { self.emit(0xC9); self.tstates += 10; }
+    fn ret_z(&mut self) { self.emit(0xC8); self.tstates += 11; }
+    fn ret_nz(&mut self) { self.emit(0xC0); self.tstates += 11; }
+    fn ret_c(&mut self) { self.emit(0xD8); self.tstates += 11; }
+    fn ret_nc(&mut self) { self.emit(0xD0); self.tstates += 11; }
+
+    fn ld_hl_nn(&mut self, nn: u16) { self.emit(0x21); self.emit16(nn); self.tstates += 10; }
+    fn ld_de_nn(&mut self, nn: u16) { self.emit(0x11); self.emit16(nn); self.tstates += 10; }
+    fn ld_bc_nn(&mut self, nn: u16) { self.emit(0x01); self.emit16(nn); self.tstates += 10; }
+    fn ld_hl_label(&mut self, label: &str) { self.emit(0x21); self.emit_label_ref(label); self.tstates += 10; }
+    fn ld_de_label(&mut self, label: &str) { self.emit(0x11); self.emit_label_ref(label); self.tstates += 10; }
+
+    fn ld_a_n(&mut self, n: u8) { self.emit(0x3E); self.emit(n); self.tstates += 7; }
+    fn ld_b_n(&mut self, n: u8) { self.emit(0x06); self.emit(n); self.tstates += 7; }
+    fn ld_c_n(&mut self, n: u8) { self.emit(0x0E); self.emit(n); self.tstates += 7; }
+    fn ld_d_n(&mut self, n: u8) { self.emit(0x16); self.emit(n); self.tstates += 7; }
+    fn ld_e_n(&mut self, n: u8) { self.emit(0x1E); self.emit(n); self.tstates += 7; }
+    fn ld_h_n(&mut self, n: u8) { self.emit(0x26); self.emit(n); self.tstates += 7; }
+    fn ld_l_n(&mut self, n: u8) { self.emit(0x2E); self.emit(n); self.tstates += 7; }
+
+    fn ld_a_hl(&mut self) { self.emit(0x7E); self.tstates += 7; }
+    fn ld_hl_a(&mut self) { self.emit(0x77); self.tstates += 7; }
+    fn ld_hl_n(&mut self, n: u8) { self.emit(0x36); self.emit(n); self.tstates += 10; }
+    fn ld_a_de(&mut self) { self.emit(0x1A); self.tstates += 7; }
+    fn ld_de_a(&mut self) { self.emit(0x12); self.tstates += 7; }
+    fn ld_a_b(&mut self) { self.emit(0x78); self.tstates += 4; }
+    fn ld_a_c(&mut self) { self.emit(0x79); self.tstates += 4; }
+    fn ld_a_d(&mut self) { self.emit(0x7A); self.tstates += 4; }
+    fn ld_a_e(&mut self) { self.emit(0x7B); self.tstates += 4; }
+    fn ld_a_l(&mut self) { self.emit(0x7D); self.tstates += 4; }
+    fn ld_a_h(&mut self) { self.emit(0x7C); self.tstates += 4; }
+    fn ld_l_a(&mut self) { self.emit(0x6F); self.tstates += 4; }
+    fn ld_h_a(&mut self) { self.emit(0x67); self.tstates += 4; }
+    fn ld_e_a(&mut self) { self.emit(0x5F); self.tstates += 4; }
+    fn ld_d_a(&mut self) { self.emit(0x57); self.tstates += 4; }
+    fn ld_b_a(&mut self) { self.emit(0x47); self.tstates += 4; }
+    fn ld_c_a(&mut self) { self.emit(0x4F); self.tstates += 4; }
+    fn ld_e_hl(&mut self) { self.emit(0x5E); self.tstates += 7; }
+    fn ld_d_hl(&mut self) { self.emit(0x56); self.tstates += 7; }
+    fn ld_l_e(&mut self) { self.emit(0x6B); self.tstates += 4; }
+    fn ld_l_h(&mut self) { self.emit(0x6C); self.tstates += 4; }
+    fn ld_h_d(&mut self) { self.emit(0x62); self.tstates += 4; }
+    fn ld_h_b(&mut self) { self.emit(0x60); self.tstates += 4; }
+    fn ld_d_h(&mut self) { self.emit(0x54); self.tstates += 4; }
+    fn ld_e_l(&mut self) { self.emit(0x5D); self.tstates += 4; }
+    fn ld_h_hl(&mut self) { self.emit(0x66); self.tstates += 7; }
+    fn ld_hl_h(&mut self) { self.emit(0x74); self.tstates += 7; }
+    fn ld_hl_d(&mut self) { self.emit(0x72); self.tstates += 7; }
+    fn ld_hl_e(&mut self) { self.emit(0x73); self.tstates += 7; }
+    fn ld_hl_c(&mut self) { self.emit(0x71); self.tstates += 7; }
+    fn ld_hl_b(&mut self) { self.emit(0x70); self.tstates += 7; }
+    fn ld_l_c(&mut self) { self.emit(0x69); self.tstates += 4; }
+    #[cfg_attr(not(feature = "interp"), allow(dead_code))]
+    fn ld_e_c(&mut self) { self.emit(0x59); self.tstates += 4; }
+    fn ld_b_hl(&mut self) { self.emit(0x46); self.tstates += 7; }
+    fn ld_c_hl(&mut self) { self.emit(0x4E); self.tstates += 7; }
+
+    fn ld_a_mem(&mut self, addr: u16) { self.emit(0x3A); self.emit16(addr); self.tstates += 13; }
+    fn ld_mem_a(&mut self, addr: u16) { self.emit(0x32); self.emit16(addr); self.tstates += 13; }
+    fn ld_hl_mem(&mut self, addr: u16) { self.emit(0x2A); self.emit16(addr); self.tstates += 16; }
+    fn ld_de_mem(&mut self, addr: u16) { self.emit(0xED); self.emit(0x5B); self.emit16(addr); self.tstates += 20; }
+    fn ld_mem_hl(&mut self, addr: u16) { self.emit(0x22); self.emit16(addr); self.tstates += 16; }
+
+    fn inc_hl(&mut self) { self.emit(0x23); self.tstates += 6; }
+    fn inc_de(&mut self) { self.emit(0x13); self.tstates += 6; }
+    fn inc_bc(&mut self) { self.emit(0x03); self.tstates += 6; }
+    fn inc_a(&mut self) { self.emit(0x3C); self.tstates += 4; }
+    fn inc_b(&mut self) { self.emit(0x04); self.tstates += 4; }
+    fn inc_c(&mut self) { self.emit(0x0C); self.tstates += 4; }
+    fn inc_d(&mut self) { self.emit(0x14); self.tstates += 4; }
+    fn inc_e(&mut self) { self.emit(0x1C); self.tstates += 4; }
+    fn inc_h(&mut self) { self.emit(0x24); self.tstates += 4; }
+    fn inc_l(&mut self) { self.emit(0x2C); self.tstates += 4; }
+    fn dec_l(&mut self) { self.emit(0x2D); self.tstates += 4; }
+    fn inc_hl_ind(&mut self) { self.emit(0x34); self.tstates += 11; }
+    fn dec_hl_ind(&mut self) { self.emit(0x35); self.tstates += 11; }
+
+    fn dec_a(&mut self) { self.emit(0x3D); self.tstates += 4; }
+    fn dec_b(&mut self) { self.emit(0x05); self.tstates += 4; }
+    fn dec_c(&mut self) { self.emit(0x0D); self.tstates += 4; }
+    fn dec_d(&mut self) { self.emit(0x15); self.tstates += 4; }
+    fn dec_e(&mut self) { self.emit(0x1D); self.tstates += 4; }
+    fn dec_hl(&mut self) { self.emit(0x2B); self.tstates += 6; }
+    fn dec_bc(&mut self) { self.emit(0x0B); self.tstates += 6; }
+
+    fn add_hl_de(&mut self) { self.emit(0x19); self.tstates += 11; }
+    fn add_hl_hl(&mut self) { self.emit(0x29); self.tstates += 11; }
+    fn add_hl_bc(&mut self) { self.emit(0x09); self.tstates += 11; }
+    fn add_a_n(&mut self, n: u8) { self.emit(0xC6); self.emit(n); self.tstates += 7; }
+    fn add_a_hl(&mut self) { self.emit(0x86); self.tstates += 7; }
+
+    fn sbc_hl_de(&mut self) { self.emit(0xED); self.emit(0x52); self.tstates += 15; }
+    fn sbc_a_a(&mut self) { self.emit(0x9F); self.tstates += 4; }
+
+    fn sub_n(&mut self, n: u8) { self.emit(0xD6); self.emit(n); self.tstates += 7; }
+    fn sub_hl(&mut self) { self.emit(0x96); self.tstates += 7; }
+    fn sbc_a_hl(&mut self) { self.emit(0x9E); self.tstates += 7; }
+
+    fn and_n(&mut self, n: u8) { self.emit(0xE6); self.emit(n); self.tstates += 7; }
+    fn and_a_c(&mut self) { self.emit(0xA1); self.tstates += 4; }
+    fn or_n(&mut self, n: u8) { self.emit(0xF6); self.emit(n); self.tstates += 7; }
+    fn add_a_a(&mut self) { self.emit(0x87); self.tstates += 4; }
+    #[cfg_attr(not(feature = "interp"), allow(dead_code))]
+    fn add_a_c(&mut self) { self.emit(0x81); self.tstates += 4; }
+    fn and_hl(&mut self) { self.emit(0xA6); self.tstates += 7; }
+    fn and_h(&mut self) { self.emit(0xA4); self.tstates += 4; }
+    fn and_l(&mut self) { self.emit(0xA5); self.tstates += 4; }
+
+    fn or_a(&mut self) { self.emit(0xB7); self.tstates += 4; }
+    fn or_c(&mut self) { self.emit(0xB1); self.tstates += 4; }
+    fn and_b(&mut self) { self.emit(0xA0); self.tstates += 4; }
+    fn or_b(&mut self) { self.emit(0xB0); self.tstates += 4; }
+    fn or_d(&mut self) { self.emit(0xB2); self.tstates += 4; }
+    fn or_l(&mut self) { self.emit(0xB5); self.tstates += 4; }
+    fn or_e(&mut self) { self.emit(0xB3); self.tstates += 4; }
+    fn or_hl(&mut self) { self.emit(0xB6); self.tstates += 7; }
+
+    fn xor_a(&mut self) { self.emit(0xAF); self.tstates += 4; }
+    fn xor_b(&mut self) { self.emit(0xA8); self.tstates += 4; }
+    fn xor_c(&mut self) { self.emit(0xA9); self.tstates += 4; }
+    fn xor_h(&mut self) { self.emit(0xAC); self.tstates += 4; }
+    fn xor_l(&mut self) { self.emit(0xAD); self.tstates += 4; }
+    fn xor_e(&mut self) { self.emit(0xAB); self.tstates += 4; }
+    fn xor_hl(&mut self) { self.emit(0xAE); self.tstates += 7; }
+    fn xor_n(&mut self, n: u8) { self.emit(0xEE); self.emit(n); self.tstates += 7; }
+
+    fn cp_n(&mut self, n: u8) { self.emit(0xFE); self.emit(n); self.tstates += 7; }
+    fn cp_hl(&mut self) { self.emit(0xBE); self.tstates += 7; }
+    fn cp_b(&mut self) { self.emit(0xB8); self.tstates += 4; }
+    fn cp_c(&mut self) { self.emit(0xB9); self.tstates += 4; }
+    fn cp_d(&mut self) { self.emit(0xBA); self.tstates += 4; }
+    fn cp_e(&mut self) { self.emit(0xBB); self.tstates += 4; }
+    fn cp_l(&mut self) { self.emit(0xBD); self.tstates += 4; }
+
+    fn push_af(&mut self) { self.emit(0xF5); self.tstates += 11; }
+    fn push_hl(&mut self) { self.emit(0xE5); self.tstates += 11; }
+    fn push_de(&mut self) { self.emit(0xD5); self.tstates += 11; }
+    fn push_bc(&mut self) { self.emit(0xC5); self.tstates += 11; }
+    fn pop_af(&mut self) { self.emit(0xF1); self.tstates += 10; }
+    fn pop_hl(&mut self) { self.emit(0xE1); self.tstates += 10; }
+    fn pop_de(&mut self) { self.emit(0xD1); self.tstates += 10; }
+    fn pop_bc(&mut self) { self.emit(0xC1); self.tstates += 10; }
+
+    fn ex_de_hl(&mut self) { self.emit(0xEB); self.tstates += 4; }
+    fn ex_sp_hl(&mut self) { self.emit(0xE3); self.tstates += 19; }
+    fn ld_sp_hl(&mut self) { self.emit(0xF9); self.tstates += 6; }
+    fn ldir(&mut self) { self.emit(0xED); self.emit(0xB0); self.tstates += 21; }
+
+    fn ccf(&mut self) { self.emit(0x3F); self.tstates += 4; }
+    fn scf(&mut self) { self.emit(0x37); self.tstates += 4; }
+    fn adc_a_n(&mut self, n: u8) { self.emit(0xCE); self.emit(n); self.tstates += 7; }
+    fn srl_a(&mut self) { self.emit(0xCB); self.emit(0x3F); self.tstates += 8; }
+    fn sla_a(&mut self) { self.emit(0xCB); self.emit(0x27); self.tstates += 8; }
+    fn sla_b(&mut self) { self.emit(0xCB); self.emit(0x20); self.tstates += 8; }
+    fn sla_c(&mut self) { self.emit(0xCB); self.emit(0x21); self.tstates += 8; }
+    fn sla_hl(&mut self) { self.emit(0xCB); self.emit(0x26); self.tstates += 15; }
+    fn rr_b(&mut self) { self.emit(0xCB); self.emit(0x18); self.tstates += 8; }
+    fn rr_c(&mut self) { self.emit(0xCB); self.emit(0x19); self.tstates += 8; }
+    fn rl_c(&mut self) { self.emit(0xCB); self.emit(0x11); self.tstates += 8; }
+    fn rrc_b(&mut self) { self.emit(0xCB); self.emit(0x08); self.tstates += 8; }
+    fn rlc_b(&mut self) { self.emit(0xCB); self.emit(0x00); self.tstates += 8; }
+    fn rr_d(&mut self) { self.emit(0xCB); self.emit(0x1A); self.tstates += 8; }
+    fn rr_e(&mut self) { self.emit(0xCB); self.emit(0x1B); self.tstates += 8; }
+    fn rr_l(&mut self) { self.emit(0xCB); self.emit(0x1D); self.tstates += 8; }
+    fn rla(&mut self) { self.emit(0x17); self.tstates += 4; }
+    fn rra(&mut self) { self.emit(0x1F); self.tstates += 4; }
+    fn rrca(&mut self) { self.emit(0x0F); self.tstates += 4; }
+    fn rlca(&mut self) { self.emit(0x07); self.tstates += 4; }
+    fn bit_0_l(&mut self) { self.emit(0xCB); self.emit(0x45); self.tstates += 8; }
+    fn bit_5_h(&mut self) { self.emit(0xCB); self.emit(0x6C); self.tstates += 8; }
+    fn srl_c(&mut self) { self.emit(0xCB); self.emit(0x39); self.tstates += 8; }
+    fn srl_d(&mut self) { self.emit(0xCB); self.emit(0x3A); self.tstates += 8; }
+    fn srl_h(&mut self) { self.emit(0xCB); self.emit(0x3C); self.tstates += 8; }
+    fn cpl(&mut self) { self.emit(0x2F); self.tstates += 4; }
+    fn nop(&mut self) { self.emit(0x00); self.tstates += 4; }
+    fn di(&mut self) { self.emit(0xF3); self.tstates += 4; }
+    fn ei(&mut self) { self.emit(0xFB); self.tstates += 4; }
+    fn im_1(&mut self) { self.emit(0xED); self.emit(0x56); self.tstates += 8; }
+    fn im_2(&mut self) { self.emit(0xED); self.emit(0x5E); self.tstates += 8; }
+    fn ld_i_a(&mut self) { self.emit(0xED); self.emit(0x47); self.tstates += 9; }
+    fn reti(&mut self) { self.emit(0xED); self.emit(0x4D); self.tstates += 14; }
+    fn jp_hl(&mut self) { self.emit(0xE9); self.tstates += 4; }
+
+    fn out_n_a(&mut self, port: u8) { self.emit(0xD3); self.emit(port); self.tstates += 11; }
+    fn in_a_n(&mut self, port: u8) { self.emit(0xDB); self.emit(port); self.tstates += 11; }
+    fn out_c_c(&mut self) { self.emit(0xED); self.emit(0x49); self.tstates += 12; }
+    fn in_a_c(&mut self) { self.emit(0xED); self.emit(0x78); self.tstates += 12; }
 }
@@ -0,0 +1,95 @@
+// Amstrad CPC disc images
+// A DATA format .dsk holding the compiled code as an AMSDOS binary file,
+// so that RUN"NAME loads the game and starts it
+
+const FILE_TYPE_BINARY: u8 = 2;
+const HEADER_SIZE: usize = 128;
+
+// DATA format: 40 tracks of 9 sectors of 512 bytes, numbered from C1
+const TRACKS: usize = 40;
+const SECTORS: usize = 9;
+const SECTOR_SIZE: usize = 512;
+const FIRST_SECTOR: u8 = 0xC1;
+const BLOCK_SIZE: usize = 1024;
+const DIR_BLOCKS: usize = 2;       // 64 directory entries of 32 bytes
+const EXTENT_BLOCKS: usize = 16;   // Blocks named by one directory entry
+const RECORD_SIZE: usize = 128;    // CP/M record, the unit of an entry's length
+
+/// AMSDOS name of a game: up to 8 upper case letters and digits
+pub fn file_name(name: &str) -> String {
+    let name: String = name.chars().filter(|c| c.is_ascii_alphanumeric()).take(8).collect();
+    if name.is_empty() { "CHIP8".to_string() } else { name.to_ascii_uppercase() }
+}
+
+/// Binary file: the code linked at `origin` behind the 128-byte AMSDOS
+/// header that gives its load and entry addresses
+pub fn amsdos(name: &str, code: &[u8], origin: u16) -> Vec<u8> {
+    let mut header = vec![0u8; HEADER_SIZE];
+    header[1..12].copy_from_slice(&padded_name(name));
+    header[18] = FILE_TYPE_BINARY;
+    header[21..23].copy_from_slice(&origin.to_le_bytes());
+    header[24..26].copy_from_slice(&(code.len() as u16).to_le_bytes());
+    header[26..28].copy_from_slice(&origin.to_le_bytes());
+    header[64..67].copy_from_slice(&(code.len() as u32).to_le_bytes()[..3]);
+    let checksum = header[..67].iter().map(|&b| b as u16).fold(0u16, u16::wrapping_add);
+    header[67..69].copy_from_slice(&checksum.to_le_bytes());
+    header.extend_from_slice(code);
+    header
+}
+
+/// Disc image with one file on it, in the CPCEMU .dsk format
+pub fn dsk(name: &str, file: &[u8]) -> Result<Vec<u8>, String> {
+    // The disc's sectors in order, directory first
+    let mut data = vec![0xE5u8; TRACKS * SECTORS * SECTOR_SIZE];
+    let blocks = (file.len() + BLOCK_SIZE - 1) / BLOCK_SIZE;
+    if DIR_BLOCKS + blocks > data.len() / BLOCK_SIZE {
+        return Err(format!("{} bytes don't fit on a DATA format disc", file.len()));
+    }
+    data[DIR_BLOCKS * BLOCK_SIZE..DIR_BLOCKS * BLOCK_SIZE + file.len()].copy_from_slice(file);
+    let records = (file.len() + RECORD_SIZE - 1) / RECORD_SIZE;
+    for extent in 0..(blocks + EXTENT_BLOCKS - 1) / EXTENT_BLOCKS {
+        let entry = &mut data[extent * 32..extent * 32 + 32];
+        entry.fill(0);
+        entry[1..12].copy_from_slice(&padded_name(name));
+        entry[12] = extent as u8;
+        entry[15] = (records - extent * EXTENT_BLOCKS * BLOCK_SIZE / RECORD_SIZE).min(0x80) as u8;
+        for n in 0..EXTENT_BLOCKS {
+            let block = extent * EXTENT_BLOCKS + n;
+            if block < blocks {
+                entry[16 + n] = (DIR_BLOCKS + block) as u8;
+            }
+        }
+    }
+
+    let track_size = 0x100 + SECTORS * SECTOR_SIZE;
+    let mut out = Vec::with_capacity(0x100 + TRACKS * track_size);
+    out.extend_from_slice(b"MV - CPCEMU Disk-File\r\nDisk-Info\r\n");
+    out.extend_from_slice(b"kz80_chip8    ");
+    out.push(TRACKS as u8);
+    out.push(1);  // Sides
+    out.extend((track_size as u16).to_le_bytes());
+    out.resize(0x100, 0);
+    for (track, sectors) in data.chunks(SECTORS * SECTOR_SIZE).enumerate() {
+        let start = out.len();
+        out.extend_from_slice(b"Track-Info\r\n");
+        out.resize(start + 0x10, 0);
+        out.extend([track as u8, 0, 0, 0]);
+        out.extend([2, SECTORS as u8, 0x4E, 0xE5]);  // 512-byte sectors, gap 3, filler
+        for sector in 0..SECTORS as u8 {
+            out.extend([track as u8, 0, FIRST_SECTOR + sector, 2, 0, 0, 0, 0]);
+        }
+        out.resize(start + 0x100, 0);
+        out.extend_from_slice(sectors);
+    }
+    Ok(out)
+}
+
+/// Name and extension as the header and directory hold them
+fn padded_name(name: &str) -> [u8; 11] {
+    let mut padded = [b' '; 11];
+    for (slot, b) in padded.iter_mut().zip(file_name(name).bytes()) {
+        *slot = b;
+    }
+    padded[8..].copy_from_slice(b"BIN");
+    padded
+}
@@ -0,0 +1,99 @@
+// Debug map
+// Where each CHIP-8 instruction's code and each runtime label sit in the
+// Z80 address space, for debuggers and bus captures that see only Z80
+// addresses and need the game location behind them
+
+use crate::codegen::CompiledCode;
+#[cfg(feature = "config")]
+use serde_json::json;
+use std::ops::Range;
+
+/// Debug map file format version
+pub const VERSION: u64 = 1;
+
+/// Z80 code of one CHIP-8 instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionSpan {
+    pub chip8: u16,
+    pub z80: Range<u16>,
+}
+
+/// Code from a runtime (or other non-instruction) label to the next label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelSpan {
+    pub name: String,
+    pub z80: Range<u16>,
+}
+
+/// Every compiled instruction and label, by Z80 address
+#[derive(Debug, Clone, Default)]
+pub struct DebugMap {
+    pub instructions: Vec<InstructionSpan>,
+    pub labels: Vec<LabelSpan>,
+}
+
+impl DebugMap {
+    /// Map of compiled code from its symbol table. An instruction runs to
+    /// the next instruction (or the program's end), so the code of a fused
+    /// or moved op counts towards the slot it was compiled in, and one whose
+    /// work was folded into a later instruction has no code of its own.
+    pub fn new(code: &CompiledCode) -> DebugMap {
+        let end = (code.origin as usize + code.code.len()) as u16;
+        let mut symbols: Vec<(u16, &str)> = code
+            .symbols
+            .iter()
+            .map(|(name, &addr)| (addr, name.as_str()))
+            .filter(|&(addr, _)| addr >= code.origin && addr < end)
+            .collect();
+        symbols.sort();
+        let chip8 = |name: &str| name.strip_prefix("c8_").and_then(|hex| u16::from_str_radix(hex, 16).ok());
+        let program_end = code.symbols.get("halt").copied().unwrap_or(end);
+        let mut map = DebugMap::default();
+        for (i, &(start, name)) in symbols.iter().enumerate() {
+            let later = &symbols[i + 1..];
+            match chip8(name) {
+                Some(addr) => {
+                    let stop = later
+                        .iter()
+                        .find(|&&(_, other)| chip8(other).is_some())
+                        .map_or(program_end, |&(at, _)| at.min(program_end));
+                    map.instructions.push(InstructionSpan { chip8: addr, z80: start..stop.max(start) });
+                }
+                None => {
+                    let stop = later.iter().find(|&&(at, _)| at > start).map_or(end, |&(at, _)| at);
+                    map.labels.push(LabelSpan { name: name.to_string(), z80: start..stop });
+                }
+            }
+        }
+        map
+    }
+
+    /// CHIP-8 instruction whose code holds the Z80 address `addr`
+    pub fn chip8_at(&self, addr: u16) -> Option<u16> {
+        self.instructions.iter().find(|span| span.z80.contains(&addr)).map(|span| span.chip8)
+    }
+
+    /// Label whose code holds the Z80 address `addr`
+    pub fn label_at(&self, addr: u16) -> Option<&str> {
+        self.labels.iter().find(|span| span.z80.contains(&addr)).map(|span| span.name.as_str())
+    }
+
+    /// `{"version": 1, "instructions": [{"chip8": 512, "start": 291,
+    /// "end": 297}, ...], "labels": [{"name": "cls", ...}]}`, with each end
+    /// one past the last byte
+    #[cfg(feature = "config")]
+    pub fn to_json(&self) -> String {
+        let instructions: Vec<_> = self
+            .instructions
+            .iter()
+            .map(|span| json!({ "chip8": span.chip8, "start": span.z80.start, "end": span.z80.end }))
+            .collect();
+        let labels: Vec<_> = self
+            .labels
+            .iter()
+            .map(|span| json!({ "name": span.name, "start": span.z80.start, "end": span.z80.end }))
+            .collect();
+        serde_json::to_string_pretty(&json!({ "version": VERSION, "instructions": instructions, "labels": labels }))
+            .expect("debug map serializes")
+    }
+}
@@ -0,0 +1,89 @@
+// Delta display protocol
+// A --delta-display build sends only the display bytes that changed since
+// the last refresh; this decodes them back into the display on the host
+
+/// Starts a frame (DLE): then the number of records less one, and that
+/// many (offset, value) pairs of display buffer bytes
+pub const FRAME: u8 = 0x10;
+/// Asks the board to send the whole display again (Ctrl-R)
+pub const RESYNC: u8 = 0x12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Text,
+    Count,
+    Offset(u16),      // Records left
+    Value(u16, u8),   // Records left, and this one's offset
+}
+
+/// The display as the frames received so far left it, 8 bytes per row with
+/// the leftmost pixel in bit 7
+pub struct Decoder {
+    display: [u8; 256],
+    state: State,
+    frames: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder { display: [0; 256], state: State::Text, frames: 0 }
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Add serial output, returning the console text between frames and
+    /// whether any frame finished
+    pub fn feed(&mut self, bytes: &[u8]) -> (Vec<u8>, bool) {
+        let mut text = Vec::new();
+        let mut finished = false;
+        for &byte in bytes {
+            self.state = match self.state {
+                State::Text if byte == FRAME => State::Count,
+                State::Text => {
+                    text.push(byte);
+                    State::Text
+                }
+                State::Count => State::Offset(byte as u16 + 1),
+                State::Offset(left) => State::Value(left, byte),
+                State::Value(left, offset) => {
+                    self.display[offset as usize] = byte;
+                    if left > 1 {
+                        State::Offset(left - 1)
+                    } else {
+                        self.frames += 1;
+                        finished = true;
+                        State::Text
+                    }
+                }
+            };
+        }
+        (text, finished)
+    }
+
+    pub fn display(&self) -> &[u8; 256] {
+        &self.display
+    }
+
+    /// Frames decoded so far
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// The display as 32 lines of `#` and space
+    pub fn render(&self) -> String {
+        let mut out = String::with_capacity(32 * 65);
+        for row in self.display.chunks(8) {
+            for byte in row {
+                for bit in 0..8 {
+                    out.push(if byte << bit & 0x80 != 0 { '#' } else { ' ' });
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
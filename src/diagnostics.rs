@@ -0,0 +1,122 @@
+// Compile-time diagnostics
+// Findings are grouped in categories whose level (allow, warn or error)
+// can be changed, like rustc's lint levels
+
+#[cfg(feature = "config")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Kind of finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "kebab-case"))]
+pub enum Category {
+    SelfModify,     // A store through I lands on compiled code
+    UnknownOpcode,  // Reachable word that isn't an instruction, compiled as a NOP
+    SkipTarget,     // Skip whose following instruction wasn't compiled
+    TwoPlayer,      // Keys of both players read from a console typing one at a time
+    UninitRead,     // FX65 or DXYN may read RAM that nothing initializes
+}
+
+impl Category {
+    pub const ALL: [Category; 5] = [
+        Category::SelfModify,
+        Category::UnknownOpcode,
+        Category::SkipTarget,
+        Category::TwoPlayer,
+        Category::UninitRead,
+    ];
+
+    /// Name used by `--warn`
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::SelfModify => "self-modify",
+            Category::UnknownOpcode => "unknown-opcode",
+            Category::SkipTarget => "skip-target",
+            Category::TwoPlayer => "two-player",
+            Category::UninitRead => "uninit-read",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Category> {
+        Category::ALL.into_iter().find(|category| category.name() == name)
+    }
+
+    /// Level unless overridden. Self-modifying stores are an error because
+    /// the compiled code ignores them, unless something handles them.
+    pub fn default_level(self) -> Level {
+        match self {
+            Category::SelfModify => Level::Error,
+            Category::UnknownOpcode | Category::SkipTarget | Category::TwoPlayer | Category::UninitRead => Level::Warn,
+        }
+    }
+}
+
+/// What happens when a finding is made
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum Level {
+    Allow,  // Say nothing
+    Warn,   // Report it and carry on
+    Error,  // Fail the build
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "error" | "deny" => Some(Level::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Levels set for each category, over their defaults
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(transparent))]
+pub struct Levels {
+    set: BTreeMap<Category, Level>,
+}
+
+impl Levels {
+    pub fn set(&mut self, category: Category, level: Level) {
+        self.set.insert(category, level);
+    }
+
+    /// Level the user chose for a category, if any
+    pub fn get(&self, category: Category) -> Option<Level> {
+        self.set.get(&category).copied()
+    }
+
+    /// Apply one `--warn` argument, `category=allow|warn|error`
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        let (name, level) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("'{}' should be category=allow|warn|error", spec))?;
+        let category = Category::from_name(name).ok_or_else(|| {
+            let names: Vec<&str> = Category::ALL.iter().map(|c| c.name()).collect();
+            format!("unknown diagnostic category '{}' (expected {})", name, names.join(", "))
+        })?;
+        let level = Level::from_name(level)
+            .ok_or_else(|| format!("invalid level '{}' for '{}' (expected allow, warn or error)", level, name))?;
+        self.set(category, level);
+        Ok(())
+    }
+}
+
+/// A finding reported as a warning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub category: Category,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Warning: {} [{}]", self.message, self.category.name())
+    }
+}
@@ -0,0 +1,157 @@
+// Display driver test kit
+// A built-in ROM that puts the display through each of its operations,
+// stopping at SYS checkpoints that print checksums over serial, and the
+// checksums a working display gives, worked out on the host
+
+use crate::codegen::{CompiledCode, Orientation, Scale, DISPLAY_BUF};
+use crate::options::CompileOptions;
+use crate::z80emu::{StopReason, Z80};
+use std::fmt;
+
+/// The kit's CHIP-8 program. Each step ends in SYS 0NN, checkpoint NN.
+pub const ROM: &[u8] = &[
+    0x00, 0xE0,              // 200: CLS
+    0xA2, 0x64,              // 202: LD I, 264
+    0x60, 0x00, 0x61, 0x00,  // 204: LD V0, 0 / LD V1, 0
+    0xD0, 0x15, 0x00, 0x01,  // 208: DRW V0, V1, 5 / SYS 001
+    0x60, 0x38, 0x61, 0x1B,  // 20C: LD V0, 56 / LD V1, 27
+    0xD0, 0x15, 0x00, 0x02,  // 210: DRW V0, V1, 5 / SYS 002
+    0x60, 0x0D, 0x61, 0x0A,  // 214: LD V0, 13 / LD V1, 10
+    0xD0, 0x15, 0x00, 0x03,  // 218: DRW V0, V1, 5 / SYS 003
+    0x60, 0x0F, 0x61, 0x0B,  // 21C: LD V0, 15 / LD V1, 11
+    0xD0, 0x15, 0x82, 0xF0,  // 220: DRW V0, V1, 5 / LD V2, VF
+    0xF2, 0x29, 0x63, 0x28,  // 224: LD F, V2 / LD V3, 40
+    0x64, 0x02, 0xD3, 0x45,  // 228: LD V4, 2 / DRW V3, V4, 5
+    0x00, 0x04,              // 22C: SYS 004
+    0xA2, 0x64,              // 22E: LD I, 264
+    0x60, 0x1E, 0x61, 0x14,  // 230: LD V0, 30 / LD V1, 20
+    0xD0, 0x15, 0x82, 0xF0,  // 234: DRW V0, V1, 5 / LD V2, VF
+    0xF2, 0x29, 0x63, 0x2D,  // 238: LD F, V2 / LD V3, 45
+    0xD3, 0x45, 0x00, 0x05,  // 23C: DRW V3, V4, 5 / SYS 005
+    0xA2, 0x64,              // 240: LD I, 264
+    0x60, 0x3D, 0x61, 0x1D,  // 242: LD V0, 61 / LD V1, 29
+    0xD0, 0x15, 0x00, 0x06,  // 246: DRW V0, V1, 5 / SYS 006
+    0x00, 0xE0, 0x00, 0x07,  // 24A: CLS / SYS 007
+    0x60, 0x00, 0x61, 0x00,  // 24E: LD V0, 0 / LD V1, 0
+    0x62, 0x0D,              // 252: LD V2, 13
+    0xF0, 0x29, 0xD1, 0x25,  // 254: LD F, V0 / DRW V1, V2, 5
+    0x70, 0x01, 0x71, 0x04,  // 258: ADD V0, 1 / ADD V1, 4
+    0x30, 0x10, 0x12, 0x54,  // 25C: SE V0, 16 / JP 254
+    0x00, 0x08, 0x00, 0xFD,  // 260: SYS 008 / EXIT
+    0xF8, 0x80, 0xF0, 0x80, 0x81,  // 264: an F, with a pixel in the last column
+];
+
+/// What the display should show at each checkpoint, from 1
+pub const STEPS: &[&str] = &[
+    "a sprite drawn at 0,0",
+    "a sprite drawn into the bottom right corner",
+    "a sprite drawn across two bytes",
+    "a sprite drawn over it, and the 1 of VF",
+    "a sprite drawn clear of the others, and the 0 of VF",
+    "a sprite run off the right edge and the bottom, wrapped or clipped",
+    "the display cleared",
+    "the 16 font digits, drawn 4 pixels apart",
+];
+
+/// Checksums printed at a checkpoint: `D<NN> <display> <video>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub step: u8,
+    pub display: u16,        // Of the 256-byte display buffer
+    pub video: Option<u16>,  // Of the rows of framebuffer video memory, if the build has one
+}
+
+impl Checkpoint {
+    /// Read a line the build printed, None if it isn't a checkpoint
+    pub fn parse(line: &str) -> Option<Checkpoint> {
+        let mut fields = line.trim().strip_prefix('D')?.split(' ');
+        let step = u8::from_str_radix(fields.next()?, 16).ok()?;
+        let display = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let video = match fields.next() {
+            Some(field) => Some(u16::from_str_radix(field, 16).ok()?),
+            None => None,
+        };
+        fields.next().is_none().then_some(Checkpoint { step, display, video })
+    }
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "D{:02X} {:04X}", self.step, self.display)?;
+        if let Some(video) = self.video {
+            write!(f, " {:04X}", video)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checkpoints in a board's serial output, in the order printed
+pub fn parse(output: &str) -> Vec<Checkpoint> {
+    output.lines().filter_map(Checkpoint::parse).collect()
+}
+
+/// Sum of bytes, the sum turned left a bit before each is added, as the
+/// build's checksum_bytes works it out
+pub fn checksum<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u16 {
+    bytes.into_iter().fold(0, |sum: u16, &b| sum.rotate_left(1).wrapping_add(b as u16))
+}
+
+/// Rows of framebuffer video memory showing a display buffer, laid out as
+/// `orientation` says and drawn `scale` times as large, without the gap
+/// the stride leaves after each
+pub fn video_rows(display: &[u8], orientation: Orientation, scale: Scale) -> Vec<Vec<u8>> {
+    let factor = if scale == Scale::Single { 1 } else { 2 };
+    let (width, height) = if orientation.rotate % 180 == 90 { (32, 64) } else { (64, 32) };
+    let lit = |x: usize, y: usize| display[y * 8 + x / 8] & 0x80 >> (x % 8) != 0;
+    (0..height * factor)
+        .map(|row| {
+            let mut bytes = vec![0u8; width * factor / 8];
+            if scale == Scale::Scanlines && row % 2 == 1 {
+                return bytes;
+            }
+            for column in 0..width * factor {
+                // Turned clockwise, then mirrored
+                let x = column / factor;
+                let x = if orientation.mirror { width - 1 - x } else { x };
+                let y = row / factor;
+                let (from_x, from_y) = match orientation.rotate {
+                    0 => (x, y),
+                    90 => (y, 31 - x),
+                    180 => (63 - x, 31 - y),
+                    _ => (63 - y, x),
+                };
+                if lit(from_x, from_y) {
+                    let bit = if orientation.lsb_first { column % 8 } else { 7 - column % 8 };
+                    bytes[column / 8] |= 1 << bit;
+                }
+            }
+            bytes
+        })
+        .collect()
+}
+
+/// The kit compiled with `options`, and the checkpoints a working display
+/// prints. The display buffer at each checkpoint comes from running the
+/// build in the emulator; the video memory sums are worked out from it
+/// here, not read back from the emulated video memory.
+pub fn build(options: &CompileOptions) -> Result<(CompiledCode, Vec<Checkpoint>), String> {
+    let mut compiler = options.compiler();
+    compiler.set_name("display-test");
+    compiler.set_display_test(true);
+    let code = compiler.compile_code(ROM)?;
+    let mut cpu = Z80::new();
+    cpu.load(code.origin, &code.code);
+    cpu.pc = code.symbols.get("init").copied().unwrap_or(code.origin);
+    let checkpoint = code.symbols["display_checkpoint"];
+    let start = compiler.ram(DISPLAY_BUF) as usize;
+    let mut expected = Vec::new();
+    while expected.len() < STEPS.len() {
+        if cpu.run(10_000_000, Some(checkpoint)) != StopReason::Breakpoint {
+            return Err(format!("the display test stopped at {:04X} after {} of {} checkpoints", cpu.pc, expected.len(), STEPS.len()));
+        }
+        let display = &cpu.mem[start..start + 256];
+        let video = options.framebuffer.map(|_| checksum(video_rows(display, options.orientation, options.scale).iter().flatten()));
+        expected.push(Checkpoint { step: cpu.a, display: checksum(display), video });
+    }
+    Ok((code, expected))
+}
@@ -0,0 +1,8 @@
+// FNV-1a hashing
+// A 64-bit hash that comes out the same on every platform and release, for
+// display goldens and compile cache keys
+
+/// FNV-1a hash of some bytes
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01B3))
+}
@@ -0,0 +1,125 @@
+// Display regression goldens
+// Each ROM runs in the emulator for a set number of frames, with no keys
+// pressed and the runtime's fixed RNG seed, and the hash of its display
+// buffer is checked against the one recorded when it was last blessed
+
+use crate::codegen::{CompiledCode, DISPLAY_BUF, FRAME_COUNT, HIRES_MODE, HIRES_SIZE};
+use crate::trap::Trap;
+use crate::z80emu::{StopReason, Z80};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Frames run before the display is hashed unless set
+pub const DEFAULT_FRAMES: u32 = 120;
+
+/// Recorded display of one ROM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Golden {
+    pub frames: u32,  // 60Hz frames run first
+    pub hash: u64,    // FNV-1a hash of the display buffer
+}
+
+/// Goldens file: one `ROM FRAMES HASH` line per ROM, by path
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Goldens {
+    entries: BTreeMap<String, Golden>,
+}
+
+impl Goldens {
+    pub fn parse(text: &str) -> Result<Goldens, String> {
+        let mut goldens = Goldens::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // Split from the right, so that ROM paths may hold spaces
+            let mut fields = line.rsplitn(3, ' ');
+            let (Some(hash), Some(frames), Some(rom)) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(format!("line {}: expected ROM FRAMES HASH", n + 1));
+            };
+            let frames = frames.parse().map_err(|_| format!("line {}: bad frame count '{}'", n + 1, frames))?;
+            let hash = u64::from_str_radix(hash, 16).map_err(|_| format!("line {}: bad hash '{}'", n + 1, hash))?;
+            goldens.set(rom.trim_end(), Golden { frames, hash });
+        }
+        Ok(goldens)
+    }
+
+    pub fn get(&self, rom: &str) -> Option<Golden> {
+        self.entries.get(rom).copied()
+    }
+
+    pub fn set(&mut self, rom: &str, golden: Golden) {
+        self.entries.insert(rom.to_string(), golden);
+    }
+
+    /// ROM paths and their goldens, in path order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Golden)> {
+        self.entries.iter().map(|(rom, &golden)| (rom.as_str(), golden))
+    }
+}
+
+impl fmt::Display for Goldens {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# Display goldens: ROM, frames run, hash of the display buffer")?;
+        writeln!(f, "# Check with `kz80_chip8 golden`, update with --bless")?;
+        for (rom, golden) in self.iter() {
+            writeln!(f, "{} {} {:016x}", rom, golden.frames, golden.hash)?;
+        }
+        Ok(())
+    }
+}
+
+/// Display buffer after the code has run for `frames` frames, or until it
+/// halts
+pub fn run(code: &CompiledCode, clock_hz: u32, frames: u32) -> Vec<u8> {
+    let cpu = emulate(code, clock_hz, frames);
+    display(code, &cpu).to_vec()
+}
+
+/// Display buffer the machine is showing: the 128x64 one once a ROM has
+/// switched to hires, else the 64x32 one
+pub fn display<'a>(code: &CompiledCode, cpu: &'a Z80) -> &'a [u8] {
+    match code.symbols.get("hires_display") {
+        Some(&start) if cpu.mem[HIRES_MODE as usize] != 0 => &cpu.mem[start as usize..start as usize + HIRES_SIZE as usize],
+        _ => &cpu.mem[DISPLAY_BUF as usize..DISPLAY_BUF as usize + 256],
+    }
+}
+
+/// Machine after the code has run for `frames` frames, or until it halts
+/// or enters a runtime trap.
+/// Frames are counted by the runtime's own 60Hz frame counter, so that code
+/// which gets faster or slower still stops at the same point of a game
+/// paced by its timers; `clock_hz` only bounds the run.
+pub fn emulate(code: &CompiledCode, clock_hz: u32, frames: u32) -> Z80 {
+    let mut cpu = Z80::new();
+    cpu.load(code.origin, &code.code);
+    cpu.pc = code.symbols.get("init").copied().unwrap_or(code.origin);
+    let budget = 4 * frames as u64 * clock_hz as u64 / 60;
+    let mut last = cpu.mem[FRAME_COUNT as usize];
+    let mut seen = 0;
+    let trap = code.symbols.get("trap").copied();
+    while seen < frames && cpu.cycles < budget {
+        if cpu.run(1, None) == StopReason::Halted || Some(cpu.pc) == trap {
+            break;
+        }
+        if cpu.mem[FRAME_COUNT as usize] != last {
+            last = cpu.mem[FRAME_COUNT as usize];
+            seen += 1;
+        }
+    }
+    cpu
+}
+
+/// Runtime trap the machine stopped in, with its value (the CALL's
+/// address, the value of I, ...), if `emulate` ended in one
+pub fn trapped(code: &CompiledCode, cpu: &Z80) -> Option<(Trap, u16)> {
+    if code.symbols.get("trap") != Some(&cpu.pc) {
+        return None;
+    }
+    let trap = Trap::ALL.iter().copied().find(|trap| trap.code() == cpu.a)?;
+    Some((trap, cpu.hl()))
+}
+
+/// FNV-1a hash of a display buffer, the same on every platform and release
+pub use crate::fnv::hash;
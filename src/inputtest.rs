@@ -0,0 +1,113 @@
+// Input driver test kit
+// A built-in ROM that waits on FX0A and polls EX9E and EXA1 for every key,
+// with the build printing what the key driver returns and each change in
+// the keys down, so a new driver can be tried before any game relies on it
+
+use crate::codegen::CompiledCode;
+use crate::options::CompileOptions;
+use std::fmt;
+
+/// The kit's CHIP-8 program: three FX0A waits, then EX9E and EXA1 of each
+/// key stored as pairs at 300 and reported, over and over
+pub const ROM: &[u8] = &[
+    0x00, 0x01, 0x67, 0x03,  // 200: SYS 001 / LD V7, 3
+    0xF0, 0x0A, 0x00, 0x02,  // 204: LD V0, K / SYS 002
+    0x77, 0xFF, 0x37, 0x00,  // 208: ADD V7, FF / SE V7, 0
+    0x12, 0x04,              // 20C: JP 204
+    0x65, 0x00,              // 20E: LD V5, 0
+    0x60, 0x00, 0xE5, 0xA1,  // 210: LD V0, 0 / SKNP V5
+    0x60, 0x01, 0x61, 0x01,  // 214: LD V0, 1 / LD V1, 1
+    0xE5, 0x9E, 0x61, 0x00,  // 218: SKP V5 / LD V1, 0
+    0xA3, 0x00, 0xF5, 0x1E,  // 21C: LD I, 300 / ADD I, V5
+    0xF5, 0x1E, 0xF1, 0x55,  // 220: ADD I, V5 / LD [I], V0..V1
+    0x75, 0x01, 0x35, 0x10,  // 224: ADD V5, 1 / SE V5, 16
+    0x12, 0x10, 0xA3, 0x00,  // 228: JP 210 / LD I, 300
+    0x00, 0x03, 0x12, 0x0E,  // 22C: SYS 003 / JP 20E
+];
+
+/// FX0A waits before the kit starts polling
+pub const WAITS: usize = 3;
+
+/// A line the build prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// `R<kk>`: the driver's get_key returned a different key, `R--` none
+    Raw(Option<u8>),
+    /// `W<kk>`: FX0A returned a key
+    Wait(u8),
+    /// `K<EX9E> <EXA1>`: the keys each says are down changed, bit N for key N
+    Keys { ex9e: u16, exa1: u16 },
+}
+
+impl Event {
+    /// Read a line the build printed, None if it isn't an event
+    pub fn parse(line: &str) -> Option<Event> {
+        let line = line.trim();
+        let hex8 = |field: &str| (field.len() == 2).then(|| u8::from_str_radix(field, 16).ok()).flatten();
+        if let Some(key) = line.strip_prefix('R') {
+            return if key == "--" { Some(Event::Raw(None)) } else { Some(Event::Raw(Some(hex8(key)?))) };
+        }
+        if let Some(key) = line.strip_prefix('W') {
+            return Some(Event::Wait(hex8(key)?));
+        }
+        let (ex9e, exa1) = line.strip_prefix('K')?.split_once(' ')?;
+        let hex16 = |field: &str| (field.len() == 4).then(|| u16::from_str_radix(field, 16).ok()).flatten();
+        Some(Event::Keys { ex9e: hex16(ex9e)?, exa1: hex16(exa1)? })
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Raw(Some(key)) => write!(f, "R{:02X}", key),
+            Event::Raw(None) => write!(f, "R--"),
+            Event::Wait(key) => write!(f, "W{:02X}", key),
+            Event::Keys { ex9e, exa1 } => write!(f, "K{:04X} {:04X}", ex9e, exa1),
+        }
+    }
+}
+
+/// Events in a board's serial output, in the order printed
+pub fn parse(output: &str) -> Vec<Event> {
+    output.lines().filter_map(Event::parse).collect()
+}
+
+/// Keys set in a mask, as hex digits, `-` for none
+pub fn keys(mask: u16) -> String {
+    let down: String = (0..16).filter(|key| mask & 1 << key != 0).map(|key| format!("{:X}", key)).collect();
+    if down.is_empty() { "-".to_string() } else { down }
+}
+
+/// What a capture shows the driver getting wrong: a key the driver never
+/// returned, EX9E and EXA1 disagreeing, or an FX0A wait it never finished
+pub fn problems(events: &[Event]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut waits = 0;
+    for event in events {
+        match *event {
+            Event::Raw(Some(key)) if key > 0x0F => problems.push(format!("{}: the driver returned {:02X}, not a key", event, key)),
+            Event::Wait(key) => {
+                waits += 1;
+                if key > 0x0F {
+                    problems.push(format!("{}: FX0A returned {:02X}, not a key", event, key));
+                }
+            }
+            Event::Keys { ex9e, exa1 } if ex9e != exa1 => {
+                problems.push(format!("{}: EX9E has {} down, EXA1 has {}", event, keys(ex9e), keys(exa1)));
+            }
+            _ => {}
+        }
+    }
+    if waits < WAITS {
+        problems.push(format!("FX0A returned {} of {} times", waits, WAITS));
+    }
+    problems
+}
+
+/// The kit compiled with `options`
+pub fn build(options: &CompileOptions) -> Result<CompiledCode, String> {
+    let mut compiler = options.compiler();
+    compiler.set_name("input-test");
+    compiler.set_input_test(true);
+    compiler.compile_code(ROM)
+}
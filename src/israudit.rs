@@ -0,0 +1,112 @@
+// Interrupt shared-state audit
+// Which memory interrupt handlers and the mainline code each write while a
+// build runs in the emulator, and which multi-byte values they share with
+// interrupts left enabled around a piecewise load or store
+
+use crate::codegen::CompiledCode;
+use crate::z80emu::{Access, Z80};
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+/// Addresses each side wrote, and the values both use
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Audit {
+    pub isr_writes: BTreeSet<u16>,
+    pub main_writes: BTreeSet<u16>,
+    pub shared: Vec<Shared>,
+}
+
+/// A value written on one side and read or written on the other. Values
+/// are single bytes, or the bytes some instruction loads or stores 16 bits
+/// at a time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shared {
+    pub addr: u16,
+    pub width: u8,
+    pub isr_writes: bool,
+    pub main_writes: bool,
+    /// Mainline instructions that take a multi-byte value apart while an
+    /// interrupt may change it, or put it together while one may read it
+    pub unsynchronized: Vec<u16>,
+}
+
+impl Audit {
+    /// Shared values that mainline code can tear
+    pub fn races(&self) -> impl Iterator<Item = &Shared> {
+        self.shared.iter().filter(|shared| !shared.unsynchronized.is_empty())
+    }
+}
+
+/// Audit what `code` does in `cycles` at the reset address, with `input`
+/// waiting at the serial port to wake a receive interrupt
+pub fn run(code: &CompiledCode, input: &[u8], cycles: u64) -> Audit {
+    let mut cpu = Z80::new();
+    cpu.load(code.origin, &code.code);
+    cpu.pc = code.symbols.get("init").copied().unwrap_or(code.origin);
+    cpu.access_log = Some(Vec::new());
+    cpu.send(input);
+    cpu.run(cycles, None);
+    audit(cpu.access_log.as_deref().unwrap_or_default())
+}
+
+/// Audit a log of loads and stores, as the emulator's `access_log` keeps
+pub fn audit(accesses: &[Access]) -> Audit {
+    let mut audit = Audit::default();
+    for access in accesses.iter().filter(|access| access.write) {
+        let writes = if access.isr { &mut audit.isr_writes } else { &mut audit.main_writes };
+        writes.extend(span(access).map(|addr| addr as u16));
+    }
+    let values = values(accesses);
+    let mut touching: Vec<Vec<&Access>> = vec![Vec::new(); values.len()];
+    for access in accesses {
+        touching[values.partition_point(|value| value.end <= access.addr as usize)].push(access);
+    }
+    for (value, touching) in values.into_iter().zip(touching) {
+        let side = |isr: bool, write: bool| touching.iter().any(|access| access.isr == isr && access.write == write);
+        let (isr_reads, isr_writes, main_reads, main_writes) = (side(true, false), side(true, true), side(false, false), side(false, true));
+        if !(isr_writes && (main_reads || main_writes) || main_writes && isr_reads) {
+            continue;
+        }
+        // Piecewise: more than one of the value's bytes, one at a time,
+        // with nothing masking the interrupt between them
+        let pieces = |write: bool| {
+            let split: Vec<&&Access> = touching.iter().filter(|access| !access.isr && !access.masked && access.write == write && (access.width as usize) < value.len()).collect();
+            let bytes: BTreeSet<u16> = split.iter().map(|access| access.addr).collect();
+            if bytes.len() > 1 { split.iter().map(|access| access.pc).collect() } else { BTreeSet::new() }
+        };
+        let mut unsynchronized = BTreeSet::new();
+        if isr_writes {
+            unsynchronized.extend(pieces(false));
+        }
+        if isr_reads || isr_writes {
+            unsynchronized.extend(pieces(true));
+        }
+        audit.shared.push(Shared {
+            addr: value.start as u16,
+            width: value.len() as u8,
+            isr_writes,
+            main_writes,
+            unsynchronized: unsynchronized.into_iter().collect(),
+        });
+    }
+    audit
+}
+
+/// Addresses an access covers
+fn span(access: &Access) -> Range<usize> {
+    access.addr as usize..access.addr as usize + access.width as usize
+}
+
+/// Every address accessed, in order, with the bytes of 16-bit accesses
+/// joined into one value
+fn values(accesses: &[Access]) -> Vec<Range<usize>> {
+    let spans: BTreeSet<(usize, usize)> = accesses.iter().map(|access| (span(access).start, span(access).end)).collect();
+    let mut values: Vec<Range<usize>> = Vec::new();
+    for (start, end) in spans {
+        match values.last_mut() {
+            Some(last) if start < last.end => last.end = last.end.max(end),
+            _ => values.push(start..end),
+        }
+    }
+    values
+}
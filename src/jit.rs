@@ -0,0 +1,244 @@
+// Dynamic recompiler
+// An experimental way to run a ROM in the emulator: each CHIP-8 block is
+// translated with compile_single the first time it runs, and again once the
+// game has written over it, as a testbed for the translations
+
+use crate::chip8::{self, Instruction};
+use crate::codegen::{CompiledCode, Compiler, Context, Fallback, CHIP8_RAM, CHIP8_V0, FRAME_COUNT};
+use crate::z80emu::{StopReason, Z80};
+use std::collections::HashMap;
+
+/// Instructions a block holds at most before it goes on to the next
+const BLOCK_LIMIT: usize = 64;
+
+/// Bytes of a stub: LD HL, addr / JP exit
+const STUB_SIZE: u16 = 6;
+
+/// What the recompiler did in a run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub blocks: usize,        // Blocks translated, counting the ones translated again
+    pub retranslated: usize,  // Blocks translated again after the game wrote over them
+    pub flushes: usize,       // Times the translations filled their room and were dropped
+}
+
+/// A block's translation, and the CHIP-8 bytes it was translated from
+struct Block {
+    at: u16,
+    source: Vec<u8>,
+}
+
+/// A ROM's runtime in the emulator, with its CHIP-8 code translated as it
+/// is reached. Translated code leaves a block through a stub that loads
+/// the CHIP-8 address to go on at into HL and jumps to `exit`, where the
+/// recompiler takes over. Stubs stay put, since the CHIP-8 stack holds
+/// their addresses; translations are dropped when they run out of room.
+pub struct Jit {
+    pub cpu: Z80,
+    pub code: CompiledCode,  // The build the runtime comes from
+    pub stats: Stats,
+    compiler: Compiler,
+    context: Context,  // The runtime's symbols, with a c8_XXX for each stub
+    blocks: HashMap<u16, Block>,
+    exit: u16,
+    start: u16,       // First byte for translations
+    next: u16,        // Where the next translation goes
+    stubs: u16,       // Lowest stub; they grow down from RAM
+    chip8_ram: u16,   // Z80 address of CHIP-8 0x200
+}
+
+impl Jit {
+    /// Load the runtime of `rom` as `compiler` builds it, ready to start at
+    /// 0x200. The build takes the interpreter fallback for its copy of the
+    /// ROM in CHIP-8 RAM, where the recompiler reads the code from.
+    pub fn new(mut compiler: Compiler, rom: &[u8]) -> Result<Jit, String> {
+        compiler.set_fallback(Fallback::Interp);
+        let code = compiler.compile_code(rom)?;
+        let ram = compiler.ram(CHIP8_V0);
+        let exit = code.origin as usize + code.code.len();
+        if exit + 0x100 > ram as usize {
+            return Err(format!("the build runs to {:04X}, leaving no room for translations below RAM at {:04X}", exit, ram));
+        }
+        let exit = exit as u16;
+        let mut cpu = Z80::new();
+        cpu.load(code.origin, &code.code);
+        cpu.load(exit, &[0x76]);  // HALT, never reached: the run stops on arriving
+        cpu.pc = code.symbols.get("init").copied().unwrap_or(code.origin);
+        // Blocks jump to their targets' stubs, and BNNN to `dispatch` with
+        // its target in HL, as a stub leaves it
+        let mut symbols = code.symbols.clone();
+        symbols.retain(|name, _| !name.starts_with("c8_") && !name.starts_with("interp_"));
+        symbols.insert("dispatch".to_string(), exit);
+        let chip8_ram = compiler.ram(CHIP8_RAM);
+        let main = code.symbols["main"];
+        let mut jit = Jit {
+            cpu,
+            code,
+            stats: Stats::default(),
+            compiler,
+            context: Context { at: 0, symbols },
+            blocks: HashMap::new(),
+            exit,
+            start: exit + 1,
+            next: exit + 1,
+            stubs: ram,
+            chip8_ram,
+        };
+        // main: JP c8_200
+        let first = jit.stub(0x200)?;
+        jit.cpu.load(main + 1, &first.to_le_bytes());
+        Ok(jit)
+    }
+
+    /// Run one instruction, and when it arrives at the exit, go on to the
+    /// block whose address it left in HL
+    pub fn step(&mut self) -> Result<StopReason, String> {
+        let stop = self.cpu.run(1, Some(self.exit));
+        if stop == StopReason::Breakpoint {
+            self.enter(self.cpu.hl())?;
+        }
+        Ok(stop)
+    }
+
+    /// Go on at a CHIP-8 address, translating its block first if it hasn't
+    /// been, or the game has since written over it
+    fn enter(&mut self, addr: u16) -> Result<(), String> {
+        let at = match self.blocks.get(&addr) {
+            Some(block) if self.chip8_bytes(addr, block.source.len())? == block.source[..] => block.at,
+            Some(_) => {
+                self.stats.retranslated += 1;
+                self.translate(addr)?
+            }
+            None => self.translate(addr)?,
+        };
+        self.cpu.pc = at;
+        Ok(())
+    }
+
+    /// Translate the block at a CHIP-8 address, returning where it went
+    fn translate(&mut self, addr: u16) -> Result<u16, String> {
+        self.stats.blocks += 1;
+        if let Some((at, source)) = self.try_translate(addr)? {
+            self.blocks.insert(addr, Block { at, source });
+            return Ok(at);
+        }
+        self.blocks.clear();
+        self.next = self.start;
+        self.stats.flushes += 1;
+        let (at, source) = self.try_translate(addr)?.ok_or(format!("the block at {:03X} doesn't fit the room for translations", addr))?;
+        self.blocks.insert(addr, Block { at, source });
+        Ok(at)
+    }
+
+    /// Translate the block at a CHIP-8 address after the last translation,
+    /// if it fits: a timer tick weighted by the code, then one instruction
+    /// after another up to one that leaves, or a JP on to the next
+    fn try_translate(&mut self, addr: u16) -> Result<Option<(u16, Vec<u8>)>, String> {
+        let start = self.next;
+        let mut at = start + 5;  // LD A, weight / CALL timer_tick
+        let mut code = Vec::new();
+        let mut tstates = 0;
+        let mut pc = addr;
+        for count in 1.. {
+            let inst = self.fetch(pc)?;
+            for target in targets(&inst) {
+                self.stub(target)?;
+            }
+            self.context.at = at;
+            let bytes = self.compiler.compile_single(&inst, &self.context).map_err(|e| format!("{:03X}: {}", inst.addr, e))?;
+            tstates += self.compiler.single_tstates();
+            at += bytes.len() as u16;
+            code.extend(bytes);
+            pc = inst.addr + inst.size();
+            if !chip8::falls_through(&inst) || inst.opcode >> 12 == 0x2 {
+                break;
+            }
+            if count == BLOCK_LIMIT {
+                let next = self.stub(pc)?;
+                code.push(0xC3);  // JP next
+                code.extend(next.to_le_bytes());
+                break;
+            }
+        }
+        let end = start as usize + 5 + code.len();
+        if end > self.stubs as usize {
+            return Ok(None);
+        }
+        let timer_tick = self.context.symbols["timer_tick"].to_le_bytes();
+        let tick = [0x3E, Compiler::tick_weight(tstates + 17), 0xCD, timer_tick[0], timer_tick[1]];
+        self.cpu.load(start, &tick);
+        self.cpu.load(start + 5, &code);
+        self.next = end as u16;
+        Ok(Some((start, self.chip8_bytes(addr, (pc - addr) as usize)?)))
+    }
+
+    /// The instruction at a CHIP-8 address, as the game's memory holds it now
+    fn fetch(&self, addr: u16) -> Result<Instruction, String> {
+        let bytes = self.chip8_bytes(addr, 2)?;
+        let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+        if !Instruction::is_long_prefix(opcode) {
+            return Ok(Instruction::new(opcode, addr));
+        }
+        let operand = self.chip8_bytes(addr + 2, 2)?;
+        Ok(Instruction::long(opcode, addr, u16::from_be_bytes([operand[0], operand[1]])))
+    }
+
+    /// Bytes of CHIP-8 memory from 0x200 up
+    fn chip8_bytes(&self, addr: u16, len: usize) -> Result<Vec<u8>, String> {
+        if !(0x200..0x1000).contains(&addr) {
+            return Err(format!("code at {:03X} is outside CHIP-8 RAM", addr));
+        }
+        let start = (self.chip8_ram + addr - 0x200) as usize;
+        Ok(self.cpu.mem[start..start + len].to_vec())
+    }
+
+    /// The stub going on at a CHIP-8 address, made the first time it's asked
+    /// for
+    fn stub(&mut self, addr: u16) -> Result<u16, String> {
+        let label = format!("c8_{:03X}", addr);
+        if let Some(&at) = self.context.symbols.get(&label) {
+            return Ok(at);
+        }
+        if self.stubs - STUB_SIZE < self.next {
+            return Err(format!("no room for a stub going on at {:03X}", addr));
+        }
+        self.stubs -= STUB_SIZE;
+        let [low, high] = addr.to_le_bytes();
+        let [exit_low, exit_high] = self.exit.to_le_bytes();
+        self.cpu.load(self.stubs, &[0x21, low, high, 0xC3, exit_low, exit_high]);
+        self.context.symbols.insert(label, self.stubs);
+        Ok(self.stubs)
+    }
+}
+
+/// CHIP-8 addresses an instruction's translation may jump to
+fn targets(inst: &Instruction) -> Vec<u16> {
+    match inst.nibbles() {
+        (0x1, _, _, _) => vec![inst.nnn()],
+        (0x2, _, _, _) => vec![inst.nnn(), inst.addr + 2],
+        _ if inst.is_skip() => vec![inst.addr + 4, inst.addr + 6],  // Past a long instruction too
+        _ => Vec::new(),
+    }
+}
+
+/// Machine after the ROM has run for `frames` frames under the
+/// recompiler, or until it halts or enters a runtime trap, as
+/// `golden::emulate` runs a compiled build
+pub fn emulate(compiler: Compiler, rom: &[u8], clock_hz: u32, frames: u32) -> Result<Jit, String> {
+    let mut jit = Jit::new(compiler, rom)?;
+    let budget = 4 * frames as u64 * clock_hz as u64 / 60;
+    let frame_count = jit.compiler.ram(FRAME_COUNT) as usize;
+    let mut last = jit.cpu.mem[frame_count];
+    let mut seen = 0;
+    let trap = jit.code.symbols.get("trap").copied();
+    while seen < frames && jit.cpu.cycles < budget {
+        if jit.step()? == StopReason::Halted || Some(jit.cpu.pc) == trap {
+            break;
+        }
+        if jit.cpu.mem[frame_count] != last {
+            last = jit.cpu.mem[frame_count];
+            seen += 1;
+        }
+    }
+    Ok(jit)
+}
@@ -0,0 +1,226 @@
+// Key maps
+// Which characters typed at the terminal are which CHIP-8 keys, and which
+// keys belong to each of two players, read from a JSON object or TOML-style
+// lines and built into get_key as a table
+
+#[cfg(feature = "config")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// CHIP-8 key of each character a map changes, or None to make it no key.
+/// Characters it leaves out keep their default keys: 0-9 and A-F, in
+/// either case.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+pub struct KeyMap {
+    #[cfg_attr(feature = "config", serde(flatten))]
+    pub keys: BTreeMap<char, Option<u8>>,
+    /// CHIP-8 keys of player 1 and player 2, when a game has two
+    #[cfg_attr(feature = "config", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub players: Option<[Vec<u8>; 2]>,
+}
+
+impl KeyMap {
+    /// Read a map file: a JSON object such as `{"w": 5, "x": null}`, or
+    /// `CHAR = KEY` lines such as `w = 5` and `"," = "A"`. A key is 0-15,
+    /// a quoted hex digit, or none. `player1` and `player2` list the keys
+    /// of each player, as `player1 = [1, 4]` or `"player1": [1, 4]`.
+    #[cfg(feature = "config")]
+    pub fn parse(text: &str) -> Result<KeyMap, String> {
+        let mut map = KeyMap::default();
+        let mut players: [Option<Vec<u8>>; 2] = [None, None];
+        if text.trim_start().starts_with('{') {
+            let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(text).map_err(|e| format!("invalid key map: {}", e))?;
+            for (name, value) in object {
+                if let Some(player) = player(&name) {
+                    let serde_json::Value::Array(keys) = value else {
+                        return Err(format!("bad keys {} for {} (expected a list)", value, name));
+                    };
+                    let keys = keys.into_iter().map(|value| json_key(value, &name)).collect::<Result<Vec<_>, _>>()?;
+                    players[player] = Some(zone(keys, &name)?);
+                    continue;
+                }
+                let key = json_key(value, &name)?;
+                map.keys.insert(character(&format!("\"{}\"", name))?, key);
+            }
+        } else {
+            for (n, line) in text.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let Some((name, value)) = split(line) else {
+                    return Err(format!("line {}: expected CHAR = KEY", n + 1));
+                };
+                let value = value.split(" #").next().unwrap_or("").trim();
+                if let Some(player) = player(name) {
+                    let keys = list(value).map_err(|e| format!("line {}: {}", n + 1, e))?;
+                    players[player] = Some(zone(keys, name).map_err(|e| format!("line {}: {}", n + 1, e))?);
+                    continue;
+                }
+                let key = key(value).map_err(|e| format!("line {}: {}", n + 1, e))?;
+                map.keys.insert(character(name).map_err(|e| format!("line {}: {}", n + 1, e))?, key);
+            }
+        }
+        map.players = match players {
+            [None, None] => None,
+            [Some(one), Some(two)] => Some([one, two]),
+            _ => return Err("a two-player map needs both player1 and player2".to_string()),
+        };
+        map.check()?;
+        Ok(map)
+    }
+
+    /// Whether the map leaves every character its default key, with one
+    /// player
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.players.is_none()
+    }
+
+    /// Player (0 or 1) whose zone a key is in
+    pub fn player(&self, key: u8) -> Option<usize> {
+        self.players.as_ref()?.iter().position(|zone| zone.contains(&key))
+    }
+
+    /// Refuse characters that aren't ASCII or are already commands, and
+    /// keys beyond F
+    pub fn check(&self) -> Result<(), String> {
+        for (&c, &key) in &self.keys {
+            if !c.is_ascii() {
+                return Err(format!("'{}' isn't an ASCII character", c));
+            }
+            if c == '?' {
+                return Err("'?' prints the build info; it can't be a key".to_string());
+            }
+            if let Some(key) = key.filter(|&key| key > 15) {
+                return Err(format!("bad key {} for '{}' (expected 0-15)", key, c.escape_default()));
+            }
+        }
+        if let Some([one, two]) = &self.players {
+            for (n, zone) in [one, two].into_iter().enumerate() {
+                if zone.is_empty() {
+                    return Err(format!("player{} has no keys", n + 1));
+                }
+                if let Some(key) = zone.iter().find(|&&key| key > 15) {
+                    return Err(format!("bad key {} for player{} (expected 0-15)", key, n + 1));
+                }
+            }
+            if let Some(key) = one.iter().find(|key| two.contains(key)) {
+                return Err(format!("key {:X} is in both players' zones", key));
+            }
+        }
+        Ok(())
+    }
+
+    /// CHIP-8 key of each ASCII character, 0xFF for none. A letter in the
+    /// map stands for both cases, unless the map gives the other too.
+    pub fn table(&self) -> [u8; 128] {
+        let mut table = [0xFF; 128];
+        for (c, key) in (b'0'..=b'9').zip(0..) {
+            table[c as usize] = key;
+        }
+        for (c, key) in (b'a'..=b'f').zip(10..) {
+            table[c as usize] = key;
+            table[c.to_ascii_uppercase() as usize] = key;
+        }
+        for (&c, &key) in &self.keys {
+            table[c.to_ascii_lowercase() as usize] = key.unwrap_or(0xFF);
+            table[c.to_ascii_uppercase() as usize] = key.unwrap_or(0xFF);
+        }
+        for (&c, &key) in &self.keys {
+            table[c as usize] = key.unwrap_or(0xFF);
+        }
+        table
+    }
+}
+
+/// Player of a `player1` or `player2` entry
+#[cfg(feature = "config")]
+fn player(name: &str) -> Option<usize> {
+    match unquote(name) {
+        "player1" => Some(0),
+        "player2" => Some(1),
+        _ => None,
+    }
+}
+
+/// A player's keys, in order and each once; none isn't a key here
+#[cfg(feature = "config")]
+fn zone(keys: Vec<Option<u8>>, name: &str) -> Result<Vec<u8>, String> {
+    let mut zone: Vec<u8> = keys.into_iter().collect::<Option<_>>().ok_or(format!("{} can't have none as a key", unquote(name)))?;
+    zone.sort_unstable();
+    zone.dedup();
+    Ok(zone)
+}
+
+/// A JSON key: a number, a hex digit string, or null
+#[cfg(feature = "config")]
+fn json_key(value: serde_json::Value, name: &str) -> Result<Option<u8>, String> {
+    match value {
+        serde_json::Value::Null => Ok(None),
+        serde_json::Value::Number(n) => Ok(Some(n.as_u64().filter(|&n| n < 16).ok_or(format!("bad key {} for '{}' (expected 0-15)", n, name))? as u8)),
+        serde_json::Value::String(s) => key(&format!("\"{}\"", s)).map_err(|e| format!("'{}': {}", name, e)),
+        other => Err(format!("bad key {} for '{}' (expected 0-15, a hex digit or null)", other, name)),
+    }
+}
+
+/// `[KEY, KEY, ...]`
+#[cfg(feature = "config")]
+fn list(value: &str) -> Result<Vec<Option<u8>>, String> {
+    let inner = value.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')).ok_or(format!("expected a list of keys, not '{}'", value))?;
+    inner.split(',').map(str::trim).filter(|item| !item.is_empty()).map(key).collect()
+}
+
+/// `CHAR = KEY`, where the character may be a quoted `=`
+#[cfg(feature = "config")]
+fn split(line: &str) -> Option<(&str, &str)> {
+    let at = match line.chars().next()? {
+        quote @ ('"' | '\'') => line[1..].find(quote)? + 2,
+        _ => 0,
+    };
+    let (name, value) = line.split_at(at + line[at..].find('=')?);
+    Some((name.trim(), &value[1..]))
+}
+
+/// A character, bare or in quotes
+#[cfg(feature = "config")]
+fn character(name: &str) -> Result<char, String> {
+    let bare = unquote(name);
+    let mut chars = bare.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("'{}' isn't a single character", bare)),
+    }
+}
+
+/// A key: 0-15 or 0x0-0xF bare, a hex digit in quotes, or none
+#[cfg(feature = "config")]
+fn key(value: &str) -> Result<Option<u8>, String> {
+    let bare = unquote(value);
+    if bare.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    let key = if let Some(hex) = bare.strip_prefix("0x") {
+        u8::from_str_radix(hex, 16).ok()
+    } else if bare != value || !bare.bytes().all(|b| b.is_ascii_digit()) {
+        u8::from_str_radix(bare, 16).ok().filter(|_| bare.len() == 1)
+    } else {
+        bare.parse().ok()
+    };
+    match key {
+        Some(key) if key < 16 => Ok(Some(key)),
+        _ => Err(format!("bad key '{}' (expected 0-15, a hex digit or none)", value)),
+    }
+}
+
+#[cfg(feature = "config")]
+fn unquote(text: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = text.strip_prefix(quote).and_then(|rest| rest.strip_suffix(quote)) {
+            if !inner.is_empty() {
+                return inner;
+            }
+        }
+    }
+    text
+}
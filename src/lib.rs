@@ -0,0 +1,44 @@
+// kz80_chip8 - CHIP-8 to Z80 Static Compiler
+// Library interface: parse, disassemble and compile CHIP-8 ROMs
+
+pub mod abi;
+pub mod analysis;
+pub mod banks;
+pub mod chip8;
+pub mod codegen;
+#[cfg(feature = "config")]
+pub mod cache;
+pub mod cpc;
+pub mod debugmap;
+pub mod delta;
+pub mod diagnostics;
+#[cfg(feature = "emulator")]
+pub mod displaytest;
+pub mod fnv;
+#[cfg(feature = "emulator")]
+pub mod golden;
+#[cfg(feature = "emulator")]
+pub mod inputtest;
+#[cfg(feature = "emulator")]
+pub mod israudit;
+#[cfg(all(feature = "emulator", feature = "interp"))]
+pub mod jit;
+pub mod keymap;
+pub mod loader;
+pub mod memmap;
+pub mod options;
+pub mod package;
+pub mod profile;
+pub mod quirks;
+pub mod report;
+pub mod sms;
+#[cfg(feature = "emulator")]
+pub mod snapshot;
+#[cfg(feature = "emulator")]
+pub mod soundtest;
+pub mod spectrum;
+pub mod stepgate;
+pub mod timing;
+pub mod trap;
+#[cfg(feature = "emulator")]
+pub mod z80emu;
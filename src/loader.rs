@@ -0,0 +1,387 @@
+// Serial loader
+// A small Z80 boot ROM that receives a compiled game over the ACIA in
+// checksummed chunks, and the host side of the protocol
+//
+// Host to target, one frame at a time:
+//   'D' addr_lo addr_hi len data[len] sum_lo sum_hi   store len bytes (0 = 256)
+//   'C' addr_lo addr_hi len_lo len_hi sum_lo sum_hi   CRC of len bytes at addr
+//   'G' addr_lo addr_hi sum_lo sum_hi                 jump to addr
+// The sum is the 16-bit total of the bytes between the type and the sum.
+// The loader buffers the data and only stores it once the sum matches. It
+// answers each frame with '+' (accepted) or '-' (bad checksum), and prints
+// '>' once it is ready. Bytes outside a frame are ignored. A 'C' frame's
+// '+' is followed by the CRC-16 (CCITT, from FFFF) of that memory, low
+// byte first, so the host can tell what the board holds is what it sent.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// Z80 address the loader copies itself to, clear of a game at 0x0000-0x7FFF
+pub const LOADER_BASE: u16 = 0xFF00;
+/// Frame buffer; the loader's stack sits below it
+pub const LOADER_BUF: u16 = 0xFE00;
+/// Loaded images must end below this address
+pub const LOAD_LIMIT: u16 = 0xFD00;
+
+/// Data bytes per frame sent by `send`
+pub const CHUNK: usize = 128;
+
+pub const READY: u8 = b'>';
+pub const ACK: u8 = b'+';
+pub const NAK: u8 = b'-';
+
+// Longest frame: type, address, length, 256 data bytes, sum
+const MAX_FRAME: usize = 262;
+// Sent to complete a broken frame; a frame of it never has a valid sum
+const FILLER: u8 = 0xFF;
+// Length of the reset stub that copies the body up
+const STUB_LEN: u16 = 0x12;
+// Attempts per frame before giving up
+const RETRIES: usize = 10;
+// Time allowed for the loader's reply to a frame
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+// ACIA ports, unless the board decodes it elsewhere
+const ACIA_CTRL: u8 = 0x80;
+const ACIA_DATA: u8 = 0x81;
+
+/// Build the loader ROM. It runs from reset, copies its body up to
+/// LOADER_BASE and receives the game into RAM from 0x0000, so it suits
+/// boards whose low 32KB is writable.
+pub fn loader_rom() -> Vec<u8> {
+    loader_rom_for(ACIA_CTRL, ACIA_DATA)
+}
+
+/// Build the loader ROM for an ACIA at these control/status and data ports
+pub fn loader_rom_for(ctrl: u8, data: u8) -> Vec<u8> {
+    let body = loader_body(ctrl, data);
+    let mut asm = Asm::new(0);
+    asm.op(&[0xF3]);                           // DI
+    asm.op(&[0x31]); asm.word(LOADER_BUF);     // LD SP,LOADER_BUF
+    asm.op(&[0x21]); asm.word(STUB_LEN);       // LD HL,body
+    asm.op(&[0x11]); asm.word(LOADER_BASE);    // LD DE,LOADER_BASE
+    asm.op(&[0x01]); asm.word(body.len() as u16);  // LD BC,len
+    asm.op(&[0xED, 0xB0]);                     // LDIR
+    asm.op(&[0xC3]); asm.word(LOADER_BASE);    // JP LOADER_BASE
+    let mut rom = asm.finish();
+    debug_assert_eq!(rom.len(), STUB_LEN as usize);
+    rom.extend_from_slice(&body);
+    rom
+}
+
+/// The relocated part of the loader, assembled for LOADER_BASE
+fn loader_body(ctrl: u8, data: u8) -> Vec<u8> {
+    let mut asm = Asm::new(LOADER_BASE);
+    asm.op(&[0x3E, 0x03, 0xD3, ctrl]);       // Master reset
+    asm.op(&[0x3E, 0x15, 0xD3, ctrl]);       // 8N1, /16
+    asm.op(&[0x3E, READY]);
+    asm.call("putc");
+
+    // Wait for a frame type, ignoring anything else
+    asm.label("hunt");
+    asm.call("getc");
+    asm.op(&[0xFE, b'D']);
+    asm.jr(0x28, "data");                    // JR Z
+    asm.op(&[0xFE, b'C']);
+    asm.jr(0x28, "crc");                     // JR Z
+    asm.op(&[0xFE, b'G']);
+    asm.jr(0x20, "hunt");                    // JR NZ
+
+    // 'G': check the address, acknowledge and jump to it
+    asm.call("header");
+    asm.call("check");
+    asm.op(&[0x3E, NAK]);
+    asm.jr(0x20, "reply");                   // JR NZ
+    asm.op(&[0x3E, ACK]);
+    asm.call("putc");
+    asm.label("drain");                      // Let the ACK go out first
+    asm.op(&[0xDB, ctrl, 0xE6, 0x02]);       // IN A,(CTRL); AND 2
+    asm.jr(0x28, "drain");                   // JR Z
+    asm.op(&[0xE9]);                         // JP (HL)
+
+    // 'D': buffer the data, then copy it into place if the sum matches
+    asm.label("data");
+    asm.call("header");
+    asm.call("getsum");
+    asm.op(&[0x47, 0xE5, 0xC5]);             // LD B,A (0 = 256); PUSH HL; PUSH BC
+    asm.op(&[0x21]); asm.word(LOADER_BUF);   // LD HL,LOADER_BUF
+    asm.label("store");
+    asm.call("getsum");
+    asm.op(&[0x77, 0x23]);                   // LD (HL),A; INC HL
+    asm.jr(0x10, "store");                   // DJNZ
+    asm.call("check");
+    asm.op(&[0xC1, 0xD1, 0x3E, NAK]);        // POP BC; POP DE; LD A,NAK
+    asm.jr(0x20, "reply");                   // JR NZ
+    asm.op(&[0x21]); asm.word(LOADER_BUF);   // LD HL,LOADER_BUF
+    asm.label("copy");
+    asm.op(&[0x7E, 0x12, 0x23, 0x13]);       // LD A,(HL); LD (DE),A; INC HL; INC DE
+    asm.jr(0x10, "copy");                    // DJNZ
+    asm.op(&[0x3E, ACK]);
+    asm.label("reply");
+    asm.call("putc");
+    asm.jr(0x18, "hunt");                    // JR
+
+    // 'C': acknowledge, then send the CRC of the memory asked for
+    asm.label("crc");
+    asm.call("header");
+    asm.op(&[0xE5]);                         // PUSH HL
+    asm.call("getsum");
+    asm.op(&[0x6F]);                         // LD L,A
+    asm.call("getsum");
+    asm.op(&[0x67, 0xE5]);                   // LD H,A; PUSH HL
+    asm.call("check");
+    asm.op(&[0xC1, 0xE1, 0x3E, NAK]);        // POP BC; POP HL; LD A,NAK
+    asm.jr(0x20, "reply");                   // JR NZ
+    asm.op(&[0x3E, ACK]);
+    asm.call("putc");
+    asm.op(&[0x11, 0xFF, 0xFF]);             // LD DE,FFFF
+    asm.label("crc_byte");
+    asm.op(&[0x78, 0xB1]);                   // LD A,B; OR C
+    asm.jr(0x28, "crc_done");                // JR Z
+    asm.op(&[0x7E, 0x23, 0xAA, 0x57]);       // LD A,(HL); INC HL; XOR D; LD D,A
+    asm.op(&[0xC5, 0x06, 0x08]);             // PUSH BC; LD B,8
+    asm.label("crc_bit");
+    asm.op(&[0xCB, 0x23, 0xCB, 0x12]);       // SLA E; RL D
+    asm.jr(0x30, "crc_next");                // JR NC
+    asm.op(&[0x7A, 0xEE, 0x10, 0x57]);       // LD A,D; XOR 10; LD D,A
+    asm.op(&[0x7B, 0xEE, 0x21, 0x5F]);       // LD A,E; XOR 21; LD E,A
+    asm.label("crc_next");
+    asm.jr(0x10, "crc_bit");                 // DJNZ
+    asm.op(&[0xC1, 0x0B]);                   // POP BC; DEC BC
+    asm.jr(0x18, "crc_byte");                // JR
+    asm.label("crc_done");
+    asm.op(&[0x7B]);                         // LD A,E
+    asm.call("putc");
+    asm.op(&[0x7A]);                         // LD A,D
+    asm.jr(0x18, "reply");                   // JR
+
+    // Clear the sum and read the address into HL
+    asm.label("header");
+    asm.op(&[0x11, 0x00, 0x00]);             // LD DE,0
+    asm.call("getsum");
+    asm.op(&[0x6F]);                         // LD L,A
+    asm.call("getsum");
+    asm.op(&[0x67]);                         // LD H,A
+    asm.op(&[0xC9]);                         // RET
+
+    // Compare the next two bytes with the sum in DE; Z if they match
+    asm.label("check");
+    asm.call("getc");
+    asm.op(&[0x93, 0x4F]);                   // SUB E; LD C,A
+    asm.call("getc");
+    asm.op(&[0x92, 0xB1, 0xC9]);             // SUB D; OR C; RET
+
+    // Read a byte into A and add it to the sum in DE (clobbers C)
+    asm.label("getsum");
+    asm.call("getc");
+    asm.op(&[0x4F, 0x83, 0x5F]);             // LD C,A; ADD A,E; LD E,A
+    asm.op(&[0x7A, 0xCE, 0x00, 0x57]);       // LD A,D; ADC A,0; LD D,A
+    asm.op(&[0x79, 0xC9]);                   // LD A,C; RET
+
+    // Wait for a received byte
+    asm.label("getc");
+    asm.op(&[0xDB, ctrl, 0xE6, 0x01]);       // IN A,(CTRL); AND 1
+    asm.jr(0x28, "getc");                    // JR Z
+    asm.op(&[0xDB, data, 0xC9]);             // IN A,(DATA); RET
+
+    // Transmit A
+    asm.label("putc");
+    asm.op(&[0xF5]);                         // PUSH AF
+    asm.label("putc_wait");
+    asm.op(&[0xDB, ctrl, 0xE6, 0x02]);       // IN A,(CTRL); AND 2
+    asm.jr(0x28, "putc_wait");               // JR Z
+    asm.op(&[0xF1, 0xD3, data, 0xC9]);       // POP AF; OUT (DATA),A; RET
+    asm.finish()
+}
+
+/// A frame storing `data` (1 to 256 bytes) at `addr`
+pub fn data_frame(addr: u16, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![b'D'];
+    let mut payload = addr.to_le_bytes().to_vec();
+    payload.push(data.len() as u8);
+    payload.extend_from_slice(data);
+    frame.extend_from_slice(&payload);
+    frame.extend_from_slice(&checksum(&payload).to_le_bytes());
+    frame
+}
+
+/// A frame starting the loaded program at `addr`
+pub fn go_frame(addr: u16) -> Vec<u8> {
+    let mut frame = vec![b'G'];
+    frame.extend_from_slice(&addr.to_le_bytes());
+    frame.extend_from_slice(&checksum(&addr.to_le_bytes()).to_le_bytes());
+    frame
+}
+
+/// A frame asking for the CRC of `len` bytes at `addr`
+pub fn crc_frame(addr: u16, len: u16) -> Vec<u8> {
+    let mut frame = vec![b'C'];
+    let mut payload = addr.to_le_bytes().to_vec();
+    payload.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(&payload);
+    frame.extend_from_slice(&checksum(&payload).to_le_bytes());
+    frame
+}
+
+/// CRC-16 of `bytes` as the loader works it out: CCITT polynomial 1021,
+/// from FFFF, most significant bit first
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xFFFF, |crc, &b| {
+        (0..8).fold(crc ^ ((b as u16) << 8), |crc, _| if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 })
+    })
+}
+
+fn checksum(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |sum, b| sum.wrapping_add(*b as u16))
+}
+
+/// Frames loading `image` at 0x0000 and starting it. Trailing zero
+/// padding is left out; it is never executed.
+pub fn frames(image: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let used = loaded(image)?;
+    let mut frames: Vec<Vec<u8>> = image[..used]
+        .chunks(CHUNK)
+        .enumerate()
+        .map(|(n, chunk)| data_frame((n * CHUNK) as u16, chunk))
+        .collect();
+    frames.push(go_frame(0x0000));
+    Ok(frames)
+}
+
+/// Bytes of `image` that are sent, up to its trailing zero padding
+fn loaded(image: &[u8]) -> Result<usize, String> {
+    let used = image.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    if used > LOAD_LIMIT as usize {
+        return Err(format!("image is {} bytes, overlapping the loader at {:04X}", used, LOAD_LIMIT));
+    }
+    Ok(used)
+}
+
+/// Send frames one at a time, resending on a NAK or a missing reply.
+/// Before a resend the link is flushed with a frame's worth of filler, so
+/// a loader stuck part-way through a frame rejects it and resynchronises.
+/// Returns the number of resends.
+pub fn send<W: Write>(port: &mut W, replies: &Receiver<u8>, frames: &[Vec<u8>]) -> Result<usize, String> {
+    let mut resends = 0;
+    for (n, frame) in frames.iter().enumerate() {
+        resends += exchange(port, replies, frame)
+            .map_err(|e| format!("frame {} of {} {}", n + 1, frames.len(), e))?;
+    }
+    Ok(resends)
+}
+
+/// The CRC of `len` bytes at `addr` on the board, asked of the loader
+pub fn request_crc<W: Write>(port: &mut W, replies: &Receiver<u8>, addr: u16, len: u16) -> Result<u16, String> {
+    let frame = crc_frame(addr, len);
+    exchange(port, replies, &frame).map_err(|e| format!("CRC request for {:04X}-{:04X} {}", addr, addr as u32 + len as u32 - 1, e))?;
+    // The loader takes about 1000 T-states a byte
+    let timeout = REPLY_TIMEOUT + Duration::from_millis(len as u64 / 2);
+    let mut crc = [0u8; 2];
+    for byte in &mut crc {
+        *byte = replies.recv_timeout(timeout).map_err(|_| format!("no CRC for {:04X}-{:04X}", addr, addr as u32 + len as u32 - 1))?;
+    }
+    Ok(u16::from_le_bytes(crc))
+}
+
+/// Check what the loader stored against the image it was sent: the CRC of
+/// the whole, then of each frame's worth when that differs. Returns the
+/// ranges the board holds something else in, none when it all matches.
+pub fn verify<W: Write>(port: &mut W, replies: &Receiver<u8>, image: &[u8]) -> Result<Vec<Range<u16>>, String> {
+    let used = loaded(image)?;
+    if used == 0 || request_crc(port, replies, 0, used as u16)? == crc16(&image[..used]) {
+        return Ok(Vec::new());
+    }
+    let mut bad = Vec::new();
+    for (n, chunk) in image[..used].chunks(CHUNK).enumerate() {
+        let addr = (n * CHUNK) as u16;
+        if request_crc(port, replies, addr, chunk.len() as u16)? != crc16(chunk) {
+            bad.push(addr..addr + chunk.len() as u16);
+        }
+    }
+    Ok(bad)
+}
+
+/// Send one frame until the loader accepts it, returning the resends
+/// this took. Before a resend the link is flushed with a frame's worth of
+/// filler, so a loader stuck part-way through a frame rejects it.
+fn exchange<W: Write>(port: &mut W, replies: &Receiver<u8>, frame: &[u8]) -> Result<usize, String> {
+    let mut attempt = 0;
+    loop {
+        port.write_all(frame).and_then(|_| port.flush()).map_err(|e| format!("writing: {}", e))?;
+        match wait_reply(replies) {
+            Ok(ACK) => return Ok(attempt),
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err("serial port closed".to_string()),
+        }
+        attempt += 1;
+        if attempt == RETRIES {
+            return Err(format!("not accepted after {} attempts", RETRIES));
+        }
+        port.write_all(&[FILLER; MAX_FRAME]).map_err(|e| format!("writing: {}", e))?;
+        while replies.recv_timeout(REPLY_TIMEOUT / 4).is_ok() {}
+    }
+}
+
+/// Next ACK or NAK, skipping the ready prompt and other noise
+fn wait_reply(replies: &Receiver<u8>) -> Result<u8, RecvTimeoutError> {
+    loop {
+        if let byte @ (ACK | NAK) = replies.recv_timeout(REPLY_TIMEOUT)? {
+            return Ok(byte);
+        }
+    }
+}
+
+/// Just enough of an assembler for the loader: bytes, labels, CALL and JR
+struct Asm {
+    code: Vec<u8>,
+    origin: u16,
+    labels: HashMap<&'static str, u16>,
+    fixups: Vec<(usize, &'static str, bool)>,  // Offset, label, relative
+}
+
+impl Asm {
+    fn new(origin: u16) -> Self {
+        Self { code: Vec::new(), origin, labels: HashMap::new(), fixups: Vec::new() }
+    }
+
+    fn op(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn word(&mut self, word: u16) {
+        self.code.extend_from_slice(&word.to_le_bytes());
+    }
+
+    fn label(&mut self, name: &'static str) {
+        self.labels.insert(name, self.origin + self.code.len() as u16);
+    }
+
+    fn call(&mut self, name: &'static str) {
+        self.code.push(0xCD);
+        self.fixups.push((self.code.len(), name, false));
+        self.word(0);
+    }
+
+    fn jr(&mut self, opcode: u8, name: &'static str) {
+        self.code.push(opcode);
+        self.fixups.push((self.code.len(), name, true));
+        self.code.push(0);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for (at, name, relative) in std::mem::take(&mut self.fixups) {
+            let target = self.labels[name];
+            if relative {
+                let offset = target as i32 - (self.origin as i32 + at as i32 + 1);
+                assert!((-128..128).contains(&offset), "loader JR to {} out of range", name);
+                self.code[at] = offset as u8;
+            } else {
+                self.code[at..at + 2].copy_from_slice(&target.to_le_bytes());
+            }
+        }
+        self.code
+    }
+}
@@ -1,75 +1,1490 @@
 // kz80_chip8 - CHIP-8 to Z80 Static Compiler
 // Compiles CHIP-8 ROMs to native Z80 code for RetroShield
 
-mod chip8;
-mod codegen;
+use clap::{Args, Parser, Subcommand};
+use kz80_chip8::options::{CompileOptions, CompileOptionsBuilder};
+use kz80_chip8::{abi, analysis, banks, cache, chip8, codegen, cpc, debugmap, delta, diagnostics, displaytest, golden, inputtest, jit, keymap, loader, memmap, package, profile, quirks, report, sms, snapshot, soundtest, spectrum, stepgate, timing, trap};
 
-use std::env;
 use std::fs;
+use std::ops::Range;
 use std::process;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+// Counts heap use for --time-passes
+#[global_allocator]
+static ALLOC: timing::CountingAlloc = timing::CountingAlloc;
+
+// Command line: one subcommand per task, `compile` being the usual one
+#[derive(Parser)]
+#[command(name = "kz80_chip8", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile a CHIP-8 ROM to a Z80 ROM image
+    Compile(CompileArgs),
+    /// Compile several ROMs into one banked EPROM image, one game per bank
+    Banks(BanksArgs),
+    /// Disassemble a CHIP-8 ROM, listing sprite data apart from code
+    Disasm {
+        /// CHIP-8 ROM
+        input: String,
+        #[command(flatten)]
+        regions: RegionArgs,
+    },
+    /// Show how a ROM splits into code and data and what it compiles to,
+    /// without writing anything
+    Analyze {
+        /// CHIP-8 ROM
+        input: String,
+        #[command(flatten)]
+        regions: RegionArgs,
+        #[command(flatten)]
+        codegen: CodegenArgs,
+    },
+    /// Run each ROM for a number of frames and check its display against
+    /// the recorded golden
+    Golden(GoldenArgs),
+    /// Run a ROM for a number of frames and write its 4KB CHIP-8 memory as
+    /// a raw image for other CHIP-8 tools
+    Snapshot(SnapshotArgs),
+    /// Compile the display test kit and list the checksums a working
+    /// display prints, or check a serial capture of its run against them
+    DisplayTest(DisplayTestArgs),
+    /// Compile the input test kit, which prints key driver events and the
+    /// keys EX9E and EXA1 see down, or check a serial capture of its run
+    InputTest(InputTestArgs),
+    /// Compile the sound test kit, which beeps for known numbers of
+    /// frames, or time its beeps on the build's beeper and PSG in the
+    /// emulator
+    SoundTest(SoundTestArgs),
+    /// Expand a compact runtime trap code into its message
+    Explain {
+        /// Trap line as printed, e.g. "E01 0234"
+        #[arg(required = true)]
+        code: Vec<String>,
+    },
+    /// List the runtime entry points callable from Z80 code
+    Abi {
+        /// Address the build is linked to run from
+        #[arg(long, value_name = "ADDR", default_value = "0", value_parser = parse_origin)]
+        at: u16,
+    },
+    /// Turn a serial capture of a --profile-gen build into a counters file
+    ProfileConvert {
+        /// Serial capture containing the counter dump
+        capture: String,
+        /// Counters file to write [default: stdout]
+        output: Option<String>,
+    },
+    /// Write the serial loader ROM
+    Loader {
+        #[arg(default_value = "loader.bin")]
+        output: String,
+        /// Control/status and data ports of the board's ACIA [default:
+        /// 0x80,0x81]
+        #[arg(long, value_name = "CTRL,DATA", value_parser = parse_acia_ports)]
+        acia_ports: Option<(u8, u8)>,
+    },
+    /// Send a compiled image to a board running the loader
+    Send {
+        /// Compiled image
+        image: String,
+        /// Serial port, configured beforehand (e.g. stty -F /dev/ttyUSB0 115200 raw -echo)
+        port: String,
+        /// Check the CRC of what the loader stored before starting the
+        /// game, and leave it stopped if that isn't the image
+        #[arg(long)]
+        verify: bool,
+    },
+    /// Step a --step-gate build on the board a block at a time, showing the
+    /// registers that change
+    Debug {
+        /// Serial port, configured beforehand as for send
+        port: String,
+    },
+    /// Draw the display of a --delta-display build running on the board,
+    /// sending what is typed as keys
+    View {
+        /// Serial port, configured beforehand as for send
+        port: String,
+    },
+}
+
+/// Options that change the generated code
+#[derive(Args)]
+struct CodegenArgs {
+    /// Hardware to run on: retroshield, spectrum for a 48K ZX Spectrum .tap,
+    /// msx for an MSX cartridge, cpm for a CP/M .COM program, cpc for an
+    /// Amstrad CPC .dsk, rc2014 for an RC2014 ROM with the SIO/2, sms for a
+    /// Sega Master System cartridge, or coleco for a ColecoVision cartridge
+    #[arg(long, value_name = "TARGET", default_value = "retroshield", value_parser = parse_target)]
+    target: codegen::Target,
+    /// Z80 clock frequency, used to pace the 60Hz timers [default: 4000000, 3500000 for spectrum, 3579545 for msx, 3300000 for cpc, 7372800 for rc2014, 3579545 for sms and coleco]
+    #[arg(long, value_name = "HZ", value_parser = parse_clock)]
+    clock: Option<u32>,
+    /// Print runtime errors as short codes (expand them with `explain`)
+    #[arg(long)]
+    compact_traps: bool,
+    /// Embed the CHIP-8 address of each compiled instruction, so that
+    /// runtime errors and the debug monitor's stack show CHIP-8 addresses
+    #[arg(long)]
+    symbol_table: bool,
+    /// Toggle an output port bit while the sound timer runs
+    #[arg(long, value_name = "PORT[:BIT]", value_parser = parse_beeper)]
+    beeper: Option<(u8, u8)>,
+    /// Sound the sound timer without a beeper: bell sends BEL to the
+    /// serial or CP/M console as each sound starts, ay8910 plays a tone on
+    /// an AY-3-8910 or YM2149 while it runs
+    #[arg(long, value_name = "AUDIO", value_parser = parse_audio)]
+    audio: Option<codegen::Audio>,
+    /// Register select and data ports of --audio ay8910 [default:
+    /// 0xD8,0xD0, the RC2014 sound module]
+    #[arg(long, value_name = "REGISTER,DATA", value_parser = parse_ay_ports, requires = "audio")]
+    ay_ports: Option<(u8, u8)>,
+    /// Copy the display to memory-mapped video at ADDR instead of drawing it
+    /// on the terminal, one bit per pixel, a row every STRIDE bytes [default
+    /// stride: 8]
+    #[arg(long, value_name = "ADDR[:STRIDE]", value_parser = parse_framebuffer)]
+    framebuffer: Option<(u16, u16)>,
+    /// Framebuffer layout: rotate=0|90|180|270 (clockwise), portrait
+    /// (rotate=90), mirror, lsb-first or msb-first
+    #[arg(long = "orientation", value_name = "ORIENTATION", value_parser = parse_orientation)]
+    orientation: Vec<String>,
+    /// Framebuffer pixel size: 1, 2 (128x64) or scanlines (128x64, each
+    /// row over a dark line)
+    #[arg(long, value_name = "SCALE", default_value = "1", value_parser = parse_scale)]
+    scale: codegen::Scale,
+    /// Send only the display bytes that changed over serial, for the view
+    /// subcommand to draw, instead of the whole screen as ANSI art
+    #[arg(long, conflicts_with = "framebuffer")]
+    delta_display: bool,
+    /// Characters of the terminal display: ascii, blocks (2x2 pixels each)
+    /// or braille (2x4)
+    #[arg(long, value_name = "GLYPHS", default_value = "ascii", value_parser = parse_glyphs)]
+    glyphs: codegen::Glyphs,
+    /// Fill of CHIP-8 RAM at startup: zero, ff or random(SEED)
+    #[arg(long, value_name = "FILL", value_parser = parse_ram_init)]
+    ram_init: Option<codegen::RamInit>,
+    /// Embed the ROM run-length encoded and inflate it into CHIP-8 RAM at
+    /// startup, to save ROM space
+    #[arg(long)]
+    compress_rom: bool,
+    /// Milliseconds a key typed at a serial or CP/M console stays down
+    /// after its last character, for SKP/SKNP and FX0A (default 200; 0
+    /// holds it a frame and has FX0A take every character)
+    #[arg(long, value_name = "MS")]
+    key_release: Option<u16>,
+    /// Take serial characters on the ACIA's receive interrupt into a
+    /// buffer, so none are lost while the game draws (RetroShield, code at
+    /// 0000)
+    #[arg(long)]
+    rx_buffer: bool,
+    /// Control/status and data ports of the RetroShield's ACIA [default:
+    /// 0x80,0x81]
+    #[arg(long, value_name = "CTRL,DATA", value_parser = parse_acia_ports)]
+    acia_ports: Option<(u8, u8)>,
+    /// Pace the 60Hz timers by the code's estimated cycles, or by the
+    /// interrupt of a CTC's channel 0 at PORT (RetroShield, RC2014)
+    #[arg(long, value_name = "cycles|ctc:PORT", value_parser = parse_timer, default_value = "cycles")]
+    timer: codegen::Timer,
+    /// Run at most IPS CHIP-8 instructions a second, e.g. 700 for the
+    /// COSMAC VIP's pace, instead of as fast as the Z80 goes
+    #[arg(long, value_name = "IPS", value_parser = parse_speed)]
+    speed: Option<u32>,
+    /// Opcode semantics: shift=y|x, load-store-increment, jump-v0=vx, vf-reset, display-ram, clip
+    #[arg(long = "quirk", value_name = "QUIRK", value_parser = parse_quirk)]
+    quirks: Vec<String>,
+    /// Compile ROMs that store into their own code anyway
+    #[arg(long)]
+    allow_self_modify: bool,
+    /// Diagnostic level: self-modify, unknown-opcode, skip-target,
+    /// two-player or uninit-read, set to allow, warn or error
+    #[arg(long = "warn", value_name = "CATEGORY=LEVEL", value_parser = parse_warn)]
+    warn: Vec<String>,
+    /// What to do with code that can't be compiled
+    #[arg(long, value_name = "MODE", default_value = "trap", value_parser = ["trap", "interp"])]
+    fallback: String,
+    /// Order of the compiled blocks
+    #[arg(long, value_name = "ORDER", default_value = "source", value_parser = ["source", "loops"])]
+    layout: String,
+    /// Optimized code, or a block per CHIP-8 instruction behind a marker
+    /// of its address and opcode, for logic analyzer traces
+    #[arg(long, value_name = "STYLE", default_value = "compact", value_parser = ["compact", "traceable"])]
+    codegen_style: String,
+    /// Count block executions; the counters are dumped on '!'
+    #[arg(long)]
+    profile_gen: bool,
+    /// Place hot blocks first, using a counters file from profile-convert
+    #[arg(long, value_name = "FILE", value_parser = parse_profile, conflicts_with = "layout")]
+    profile_use: Option<profile::Profile>,
+    /// Print each instruction's CHIP-8 address on the serial console before
+    /// it runs, with its opcode too for `--trace opcode`
+    #[arg(long, value_name = "WHAT", num_args = 0..=1, default_missing_value = "pc", value_parser = ["pc", "opcode"])]
+    trace: Option<String>,
+    /// Build in a debug monitor, entered with Ctrl-B on the serial console,
+    /// to show the registers, stack and memory and step the game
+    #[arg(long)]
+    debug_stub: bool,
+    /// Stop at the start of each block until the debug subcommand steps
+    /// it, showing the registers (implies --debug-stub)
+    #[arg(long)]
+    step_gate: bool,
+    /// Link the code to run from this address [default: 0x0000, 0x6000 for spectrum, 0x4000 for msx and cpc, 0x0100 for cpm, 0x8000 for coleco]
+    #[arg(long, value_name = "ADDR", value_parser = parse_origin)]
+    at: Option<u16>,
+    /// Board memory map: `[rom]` and `[ram]` sections, each with a `start`
+    /// and `end` address, for a RetroShield or RC2014 style board with its
+    /// ROM or RAM somewhere else; the code starts at the ROM's start
+    #[arg(long, value_name = "FILE", value_parser = parse_memory_map)]
+    memory_map: Option<memmap::MemoryMap>,
+    /// Master System pad mapping: `BUTTON = KEY` lines for up, down, left,
+    /// right, 1 and 2 [default: 5 8 7 9 6 4]
+    #[arg(long, value_name = "FILE", value_parser = parse_pad)]
+    pad: Option<sms::PadMap>,
+    /// Serial key map: a JSON object or `CHAR = KEY` lines giving the
+    /// CHIP-8 key of each character typed [default: 0-9 and A-F]
+    #[arg(long, value_name = "FILE", value_parser = parse_keymap)]
+    keymap: Option<keymap::KeyMap>,
+}
+
+#[derive(Args)]
+struct CompileArgs {
+    /// CHIP-8 ROM
+    input: String,
+    /// Output file [default: the input with the package's extension]
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<String>,
+    #[command(flatten)]
+    codegen: CodegenArgs,
+    #[command(flatten)]
+    package: PackageArgs,
+    /// Leave the free space off the end of the image
+    #[arg(long)]
+    trim: bool,
+    /// Fail without writing if the image uses more than this
+    #[arg(long, value_name = "BYTES", value_parser = parse_size)]
+    max_size: Option<usize>,
+    /// Exit with an error on warnings, code over N bytes, or a runtime trap
+    /// in the first frames of an emulated run (warnings, size>N, verify)
+    #[arg(long = "fail-on", value_name = "RULE", value_parser = parse_fail_on)]
+    fail_on: Vec<FailOn>,
+    /// Existing ROM (e.g. a monitor) to place the game in at --at
+    #[arg(long, value_name = "FILE", requires = "at")]
+    base_rom: Option<String>,
+    /// Print code size and cycle statistics
+    #[arg(long)]
+    stats: bool,
+    /// Write a JSON map of each CHIP-8 instruction's and runtime label's Z80
+    /// addresses, for debuggers and logic analyzers
+    #[arg(long, value_name = "FILE")]
+    debug_map: Option<String>,
+    /// Print the wall time and peak heap use of each compiler pass
+    #[arg(long)]
+    time_passes: bool,
+    /// Reuse the code compiled by an earlier run with the same ROM and
+    /// options, kept in this directory
+    #[arg(long, value_name = "DIR")]
+    cache: Option<String>,
+}
+
+#[derive(Args)]
+struct BanksArgs {
+    /// CHIP-8 ROMs, in bank order
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Output file
+    #[arg(short, long, value_name = "FILE")]
+    output: String,
+    /// Manifest file [default: the output with a .txt extension]
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<String>,
+    /// Size of each bank, a power of two up to 0x8000
+    #[arg(long, value_name = "BYTES", default_value = "0x8000", value_parser = parse_size)]
+    bank_size: usize,
+    /// Exit with an error on warnings, code over N bytes, or a runtime trap
+    /// in the first frames of an emulated run (warnings, size>N, verify)
+    #[arg(long = "fail-on", value_name = "RULE", value_parser = parse_fail_on)]
+    fail_on: Vec<FailOn>,
+    #[command(flatten)]
+    codegen: CodegenArgs,
+    #[command(flatten)]
+    package: PackageArgs,
+    #[command(flatten)]
+    progress: ProgressArgs,
+}
+
+#[derive(Args)]
+struct GoldenArgs {
+    /// CHIP-8 ROMs, or directories of .ch8 files
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Goldens file
+    #[arg(long, value_name = "FILE", default_value = "test/goldens.txt")]
+    goldens: String,
+    /// Frames to run before hashing the display [default: the golden's, or 120]
+    #[arg(long, value_name = "N")]
+    frames: Option<u32>,
+    /// Record the displays as the new goldens instead of checking them
+    #[arg(long)]
+    bless: bool,
+    /// Reuse the code compiled by earlier runs, kept in this directory
+    #[arg(long, value_name = "DIR")]
+    cache: Option<String>,
+    /// Run each ROM under the experimental dynamic recompiler, which
+    /// translates its blocks as they are reached, instead of its build
+    #[arg(long, conflicts_with = "cache")]
+    jit: bool,
+    /// Also fail ROMs with warnings, code over N bytes, or a runtime trap in
+    /// the golden run (warnings, size>N, verify)
+    #[arg(long = "fail-on", value_name = "RULE", value_parser = parse_fail_on)]
+    fail_on: Vec<FailOn>,
+    #[command(flatten)]
+    codegen: CodegenArgs,
+    #[command(flatten)]
+    progress: ProgressArgs,
+}
+
+#[derive(Args)]
+struct ProgressArgs {
+    /// Leave out the line for each ROM and the summary table
+    #[arg(long)]
+    quiet: bool,
+    /// Don't colour the results (also off when NO_COLOR is set or the output
+    /// isn't a terminal)
+    #[arg(long)]
+    no_color: bool,
+}
+
+#[derive(Args)]
+struct SnapshotArgs {
+    /// CHIP-8 ROM
+    input: String,
+    /// Image to write [default: the input with a .mem extension]
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Frames to run first
+    #[arg(long, value_name = "N", default_value_t = golden::DEFAULT_FRAMES)]
+    frames: u32,
+    #[command(flatten)]
+    codegen: CodegenArgs,
+}
 
-    if args.len() < 2 {
-        eprintln!("Usage: {} <input.ch8> [-o output.bin]", args[0]);
-        eprintln!("       {} --disasm <input.ch8>", args[0]);
-        process::exit(1);
+#[derive(Args)]
+struct DisplayTestArgs {
+    /// Image to write
+    #[arg(short, long, default_value = "display-test.bin")]
+    output: String,
+    /// Serial capture of the kit's run to check instead of writing the image
+    #[arg(long, value_name = "CAPTURE")]
+    check: Option<String>,
+    #[command(flatten)]
+    codegen: CodegenArgs,
+}
+
+#[derive(Args)]
+struct InputTestArgs {
+    /// Image to write
+    #[arg(short, long, default_value = "input-test.bin")]
+    output: String,
+    /// Serial capture of the kit's run to check instead of writing the image
+    #[arg(long, value_name = "CAPTURE")]
+    check: Option<String>,
+    #[command(flatten)]
+    codegen: CodegenArgs,
+}
+
+#[derive(Args)]
+struct SoundTestArgs {
+    /// Image to write
+    #[arg(short, long, default_value = "sound-test.bin")]
+    output: String,
+    /// Time the kit's beeps in the emulator instead of writing the image
+    #[arg(long)]
+    measure: bool,
+    #[command(flatten)]
+    codegen: CodegenArgs,
+}
+
+/// Known code and data, for what the control-flow walk gets wrong
+#[derive(Args)]
+struct RegionArgs {
+    /// Disassemble START-END (hex, inclusive) as code
+    #[arg(long = "code", value_name = "START-END", value_parser = parse_range)]
+    code: Vec<Range<u16>>,
+    /// List START-END (hex, inclusive) as data
+    #[arg(long = "data", value_name = "START-END", value_parser = parse_range)]
+    data: Vec<Range<u16>>,
+}
+
+impl From<RegionArgs> for chip8::RegionMap {
+    fn from(args: RegionArgs) -> Self {
+        chip8::RegionMap { code: args.code, data: args.data }
     }
+}
 
-    // Check for disassembly mode
-    if args[1] == "--disasm" || args[1] == "-d" {
-        if args.len() < 3 {
-            eprintln!("Usage: {} --disasm <input.ch8>", args[0]);
-            process::exit(1);
+/// How the image is written out for the programmer
+#[derive(Args)]
+struct PackageArgs {
+    /// Output format: bin, hex, srec, uf2 or wav
+    #[arg(long = "package", value_name = "FORMAT", default_value = "bin", value_parser = parse_package)]
+    format: package::Package,
+    /// Address of the image in the programmer's address space
+    #[arg(long, value_name = "ADDR", default_value_t = 0, value_parser = parse_num)]
+    flash_base: u32,
+    /// Bit rate of a wav tape: 300, 600 or 1200 [default: 300]
+    #[arg(long, value_name = "BAUD")]
+    tape_baud: Option<u32>,
+    /// Length of a wav tape's leader tone in milliseconds [default: 2000]
+    #[arg(long, value_name = "MS")]
+    tape_leader: Option<u32>,
+}
+
+impl PackageArgs {
+    /// The format, with any tape settings
+    fn format(&self) -> Result<package::Package, String> {
+        match self.format {
+            package::Package::Wav(mut tape) => {
+                tape.baud = self.tape_baud.unwrap_or(tape.baud);
+                tape.leader_ms = self.tape_leader.unwrap_or(tape.leader_ms);
+                Ok(package::Package::Wav(tape))
+            }
+            _ if self.tape_baud.is_some() || self.tape_leader.is_some() => {
+                Err("--tape-baud and --tape-leader are for --package wav".to_string())
+            }
+            format => Ok(format),
         }
-        let input_path = &args[2];
-        match fs::read(input_path) {
-            Ok(rom) => {
-                chip8::disassemble(&rom);
+    }
+}
+
+/// Why a command failed, which picks its exit status. Bad arguments exit
+/// with 2, from the argument parser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    Error = 1,     // Anything else, such as an output that can't be written
+    Input = 3,     // A ROM or other input that can't be read or used
+    Codegen = 4,   // The compiler rejected the ROM
+    Size = 5,      // Over --max-size or a --fail-on size budget
+    Verify = 6,    // A display golden mismatch, or a trap under --fail-on verify
+    Warnings = 7,  // Warnings under --fail-on warnings, or a diagnostic set to error
+}
+
+/// A failed command: its exit status and what to print
+struct Failed {
+    failure: Failure,
+    message: String,
+}
+
+impl From<String> for Failed {
+    fn from(message: String) -> Failed {
+        Failed { failure: Failure::Error, message }
+    }
+}
+
+/// Turn an error message into a failure of the given kind, for map_err
+fn fail(failure: Failure) -> impl Fn(String) -> Failed {
+    move |message| Failed { failure, message }
+}
+
+/// Failure of a compile: a diagnostic set to error, or the compiler's own
+fn codegen_failure(compiler: &codegen::Compiler) -> Failure {
+    match compiler.denied() {
+        Some(_) => Failure::Warnings,
+        None => Failure::Codegen,
+    }
+}
+
+/// A `--fail-on` rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailOn {
+    Warnings,     // Any diagnostic
+    Size(usize),  // Compiled code over this many bytes
+    Verify,       // A runtime trap within the golden run's frames
+}
+
+/// Check a compiled ROM against the `--fail-on` rules, in the order given
+fn check_fail_on(rules: &[FailOn], compiled: &codegen::CompiledCode, clock_hz: u32) -> Result<(), Failed> {
+    for rule in rules {
+        match *rule {
+            FailOn::Warnings if !compiled.diagnostics.is_empty() => {
+                let count = compiled.diagnostics.len();
+                return Err(Failed {
+                    failure: Failure::Warnings,
+                    message: format!("{} warning{} with --fail-on warnings", count, if count == 1 { "" } else { "s" }),
+                });
             }
-            Err(e) => {
-                eprintln!("Error reading {}: {}", input_path, e);
-                process::exit(1);
+            FailOn::Size(max) if compiled.code.len() > max => {
+                return Err(Failed {
+                    failure: Failure::Size,
+                    message: format!("code is {} bytes, {} over the --fail-on size>{} budget", compiled.code.len(), compiled.code.len() - max, max),
+                });
             }
+            FailOn::Verify => {
+                let cpu = golden::emulate(compiled, clock_hz, golden::DEFAULT_FRAMES);
+                if let Some((trap, value)) = golden::trapped(compiled, &cpu) {
+                    return Err(Failed {
+                        failure: Failure::Verify,
+                        message: format!("trapped within {} frames: {} {:04X} ({})", golden::DEFAULT_FRAMES, trap.message(), value, trap.value()),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Compile(args) => compile(&args),
+        Command::Banks(args) => build_banks(&args),
+        Command::Disasm { input, regions } => disasm(&input, regions).map_err(fail(Failure::Input)),
+        Command::Analyze { input, regions, codegen } => analyze(&input, regions, &codegen).map_err(Failed::from),
+        Command::Golden(args) => check_goldens(&args),
+        Command::Snapshot(args) => write_snapshot(&args).map_err(Failed::from),
+        Command::DisplayTest(args) => display_test(&args),
+        Command::InputTest(args) => input_test(&args),
+        Command::SoundTest(args) => sound_test(&args),
+        Command::Explain { code } => explain(&code.join(" ")).map_err(fail(Failure::Input)),
+        Command::Abi { at } => {
+            print_abi(at);
+            Ok(())
+        }
+        Command::ProfileConvert { capture, output } => profile_convert(&capture, output.as_deref()).map_err(Failed::from),
+        Command::Loader { output, acia_ports } => write_loader(&output, acia_ports).map_err(Failed::from),
+        Command::Send { image, port, verify } => send(&image, &port, verify),
+        Command::Debug { port } => debug(&port).map_err(Failed::from),
+        Command::View { port } => view(&port).map_err(Failed::from),
+    };
+    if let Err(failed) = result {
+        eprintln!("Error: {}", failed.message);
+        process::exit(failed.failure as i32);
+    }
+}
+
+/// Read a CHIP-8 ROM
+fn read_rom(path: &str) -> Result<Vec<u8>, String> {
+    let rom = fs::read(path).map_err(|e| format!("reading {}: {}", path, e))?;
+    if rom.is_empty() {
+        return Err(format!("{} is empty", path));
+    }
+    Ok(rom)
+}
+
+/// Open the compilation cache, if one was given, for this build of the
+/// compiler
+fn open_cache(dir: &Option<String>) -> Result<Option<cache::Cache>, String> {
+    let Some(dir) = dir else { return Ok(None) };
+    // Entries hold the runtime, so a rebuilt compiler mustn't reuse them
+    let exe = std::env::current_exe().and_then(fs::read).map_err(|e| format!("reading the compiler for its build: {}", e))?;
+    Ok(Some(cache::Cache::open(dir, golden::hash(&exe))?))
+}
+
+/// Compile a ROM, or take its code from the cache. Also says whether it
+/// came from the cache.
+fn compile_cached(compiler: &mut codegen::Compiler, rom: &[u8], cache: Option<&cache::Cache>) -> Result<(codegen::CompiledCode, bool), String> {
+    let Some(cache) = cache else { return Ok((compiler.compile_code(rom)?, false)) };
+    let key = compiler.cache_key(rom);
+    if let Some(compiled) = cache.get(key) {
+        return Ok((compiled, true));
+    }
+    let compiled = compiler.compile_code(rom)?;
+    cache.put(key, &compiled)?;
+    Ok((compiled, false))
+}
+
+/// Options set by the code generation flags
+fn codegen_options(args: &CodegenArgs) -> Result<CompileOptionsBuilder, String> {
+    let mut options = CompileOptions::builder().target(args.target);
+    if let Some(hz) = args.clock {
+        options = options.clock_hz(hz);
+    }
+    if let Some((port, bit)) = args.beeper {
+        options = options.beeper(port, bit);
+    }
+    if let Some(audio) = args.audio {
+        options = options.audio(match (audio, args.ay_ports) {
+            (codegen::Audio::Ay8910 { .. }, Some((register, data))) => codegen::Audio::Ay8910 { register, data },
+            (_, Some(_)) => return Err("--ay-ports sets the ports of --audio ay8910".to_string()),
+            (audio, None) => audio,
+        });
+    }
+    if let Some((base, stride)) = args.framebuffer {
+        options = options.framebuffer(base, stride);
+    }
+    options = options.delta_display(args.delta_display).glyphs(args.glyphs);
+    if let Some(ram_init) = args.ram_init {
+        options = options.ram_init(ram_init);
+    }
+    options = options.compress_rom(args.compress_rom);
+    if let Some(ms) = args.key_release {
+        options = options.key_release(ms);
+    }
+    options = options.rx_buffer(args.rx_buffer);
+    if let Some((ctrl, data)) = args.acia_ports {
+        options = options.acia_ports(ctrl, data);
+    }
+    options = options.timer(args.timer);
+    if let Some(ips) = args.speed {
+        options = options.speed(ips);
+    }
+    let mut quirks = quirks::Quirks::default();
+    for quirk in &args.quirks {
+        quirks.apply(quirk)?;
+    }
+    let mut orientation = codegen::Orientation::default();
+    for spec in &args.orientation {
+        orientation.apply(spec)?;
+    }
+    options = options.orientation(orientation).scale(args.scale);
+    let mut levels = diagnostics::Levels::default();
+    for spec in &args.warn {
+        levels.apply(spec)?;
+    }
+    options = options
+        .compact_traps(args.compact_traps)
+        .symbol_table(args.symbol_table)
+        .quirks(quirks)
+        .allow_self_modify(args.allow_self_modify)
+        .levels(levels)
+        .fallback(match args.fallback.as_str() {
+            "interp" => codegen::Fallback::Interp,
+            _ => codegen::Fallback::Trap,
+        })
+        .layout(match (&args.profile_use, args.layout.as_str()) {
+            (Some(profile), _) => codegen::Layout::Profile(profile.clone()),
+            (None, "loops") => codegen::Layout::Loops,
+            (None, _) => codegen::Layout::Source,
+        })
+        .codegen_style(match args.codegen_style.as_str() {
+            "traceable" => codegen::CodegenStyle::Traceable,
+            _ => codegen::CodegenStyle::Compact,
+        })
+        .profile_gen(args.profile_gen)
+        .trace(match args.trace.as_deref() {
+            Some("opcode") => codegen::Trace::Opcode,
+            Some(_) => codegen::Trace::Pc,
+            None => codegen::Trace::Off,
+        })
+        .debug_stub(args.debug_stub || args.step_gate)
+        .step_gate(args.step_gate);
+    if let Some(map) = args.memory_map {
+        options = options.memory_map(map);
+    }
+    if let Some(addr) = args.at {
+        options = options.origin(addr);
+    }
+    if let Some(pad) = args.pad {
+        options = options.pad(pad);
+    }
+    if let Some(keymap) = &args.keymap {
+        options = options.keymap(keymap.clone());
+    }
+    Ok(options)
+}
+
+/// Set up a compiler for a ROM from the options
+fn named_compiler(input: &str, options: &CompileOptions) -> codegen::Compiler {
+    let mut compiler = options.compiler();
+    if let Some(name) = std::path::Path::new(input).file_name() {
+        compiler.set_name(&name.to_string_lossy());
+    }
+    compiler
+}
+
+/// Set up a compiler from the code generation flags
+fn build_compiler(input: &str, args: &CodegenArgs) -> Result<codegen::Compiler, String> {
+    Ok(named_compiler(input, &codegen_options(args)?.build()?))
+}
+
+/// Compile a ROM and write the packaged image
+fn compile(args: &CompileArgs) -> Result<(), Failed> {
+    let rom = read_rom(&args.input).map_err(fail(Failure::Input))?;
+    let options = codegen_options(&args.codegen)
+        .and_then(|options| options.trim(args.trim).package(args.package.format()?).flash_base(args.package.flash_base).build())
+        .map_err(fail(Failure::Input))?;
+    let target = options.target;
+    let spectrum = target == codegen::Target::Spectrum;
+    if spectrum && (options.package != package::Package::Bin || args.base_rom.is_some()) {
+        return Err(Failed::from("spectrum builds are written as a .tap; --package and --base-rom don't apply".to_string()));
+    }
+    if target == codegen::Target::Cpm && (options.package != package::Package::Bin || args.base_rom.is_some()) {
+        return Err(Failed::from("cpm builds are written as a .COM program; --package and --base-rom don't apply".to_string()));
+    }
+    let cpc = target == codegen::Target::Cpc;
+    if cpc && (options.package != package::Package::Bin || args.base_rom.is_some()) {
+        return Err(Failed::from("cpc builds are written as a .dsk; --package and --base-rom don't apply".to_string()));
+    }
+    if matches!(target, codegen::Target::Msx | codegen::Target::Sms | codegen::Target::Coleco) && args.base_rom.is_some() {
+        return Err(Failed::from(format!("{} builds are a cartridge of their own; --base-rom doesn't apply", target.name())));
+    }
+    if args.fail_on.contains(&FailOn::Verify) && matches!(target, codegen::Target::Cpm | codegen::Target::Coleco) {
+        return Err(Failed::from(format!("--fail-on verify runs the build from its reset, which {} builds can't", target.name())));
+    }
+    let base_rom = match &args.base_rom {
+        Some(path) => Some(fs::read(path).map_err(|e| Failed { failure: Failure::Input, message: format!("reading {}: {}", path, e) })?),
+        None => None,
+    };
+
+    let mut compiler = named_compiler(&args.input, &options);
+    compiler.set_time_passes(args.time_passes);
+    let cache = open_cache(&args.cache)?;
+    let (compiled, cached) = compile_cached(&mut compiler, &rom, cache.as_ref()).map_err(fail(codegen_failure(&compiler)))?;
+    for diagnostic in &compiled.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    if args.time_passes && !cached {
+        print_passes(&compiled.passes);
+    }
+
+    let input = &args.input;
+    let stem = input.strip_suffix(".ch8").unwrap_or(input);
+    let extension = match (target, options.package) {
+        (codegen::Target::Spectrum, _) => "tap",
+        (codegen::Target::Msx, package::Package::Bin) => "rom",
+        (codegen::Target::Sms, package::Package::Bin) => "sms",
+        (codegen::Target::Coleco, package::Package::Bin) => "col",
+        (codegen::Target::Cpm, _) => "com",
+        (codegen::Target::Cpc, _) => "dsk",
+        (_, format) => format.extension(),
+    };
+    let output_path = args.output.clone().unwrap_or_else(|| format!("{}.{}", stem, extension));
+
+    // Check the used size against the budget before writing anything
+    // (The image starts at 0x0000 on the RetroShield, RC2014 and Master System, at the code elsewhere)
+    let rom_at_zero = matches!(target, codegen::Target::RetroShield | codegen::Target::Rc2014 | codegen::Target::Sms);
+    let used = compiled.code.len() + if rom_at_zero { compiled.origin as usize } else { 0 };
+    if let Some(max) = args.max_size {
+        if used > max {
+            return Err(Failed {
+                failure: Failure::Size,
+                message: format!("image uses {} bytes, {} over the --max-size budget of {}", used, used - max, max),
+            });
         }
-        return;
     }
+    check_fail_on(&args.fail_on, &compiled, options.clock_hz.unwrap_or(target.clock_hz()))?;
 
-    let input_path = &args[1];
-    let output_path = if args.len() >= 4 && args[2] == "-o" {
-        args[3].clone()
+    let name = std::path::Path::new(stem).file_name().map_or(stem.into(), |name| name.to_string_lossy());
+    let binary = if spectrum {
+        spectrum::tap(&name, &compiled.code, compiled.origin)
+    } else if cpc {
+        cpc::dsk(&name, &cpc::amsdos(&name, &compiled.code, compiled.origin))?
     } else {
-        input_path.replace(".ch8", ".bin")
+        let image = match &base_rom {
+            Some(base) => package::merge(base, &compiled.code, compiled.origin, if options.trim { 0 } else { 32768 })?,
+            None => compiler.image(compiled.clone()),
+        };
+        options.package.write(&image, options.flash_base)?
+    };
+    fs::write(&output_path, &binary).map_err(|e| format!("writing {}: {}", output_path, e))?;
+    println!("Compiled {} -> {} ({} bytes)", input, output_path, binary.len());
+    if cached {
+        println!("  Reused the cached compile");
+    }
+    if cpc {
+        println!("  Start it with RUN\"{}\"", cpc::file_name(&name));
+    }
+    if let Some(path) = &args.debug_map {
+        fs::write(path, debugmap::DebugMap::new(&compiled).to_json()).map_err(|e| format!("writing {}: {}", path, e))?;
+        println!("  Debug map {}", path);
+    }
+    if let Some(max) = args.max_size {
+        println!("  Used {} of {} bytes ({}%), {} free", used, max, used * 100 / max, max - used);
+    }
+
+    if args.stats {
+        print_stats(&compiled.stats);
+    }
+    Ok(())
+}
+
+/// Run each ROM and compare its display with its golden, or record it
+fn check_goldens(args: &GoldenArgs) -> Result<(), Failed> {
+    match args.codegen.target {
+        codegen::Target::Cpm => return Err(Failed::from("golden runs need a target that boots on its own, not cpm".to_string())),
+        codegen::Target::Coleco => return Err(Failed::from("golden runs read the display at 8200, which coleco builds keep elsewhere".to_string())),
+        _ => {}
+    }
+    let mut goldens = match fs::read_to_string(&args.goldens) {
+        Ok(text) => golden::Goldens::parse(&text).map_err(|e| Failed { failure: Failure::Input, message: format!("{}: {}", args.goldens, e) })?,
+        Err(_) if args.bless => golden::Goldens::default(),
+        Err(e) => return Err(Failed { failure: Failure::Input, message: format!("reading {}: {}", args.goldens, e) }),
+    };
+    let mut roms = Vec::new();
+    for input in &args.inputs {
+        if std::path::Path::new(input).is_dir() {
+            let entries = fs::read_dir(input).map_err(|e| Failed { failure: Failure::Input, message: format!("reading {}: {}", input, e) })?;
+            let mut found: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path().to_string_lossy().into_owned())
+                .filter(|path| path.ends_with(".ch8"))
+                .collect();
+            found.sort();
+            roms.extend(found);
+        } else {
+            roms.push(input.clone());
+        }
+    }
+
+    let clock_hz = args.codegen.clock.unwrap_or(args.codegen.target.clock_hz());
+    let cache = open_cache(&args.cache)?;
+    let mut report = report::Report::new(roms.len(), report::use_color(args.progress.no_color));
+    let mut first_failure = None;
+    for input in &roms {
+        let rom = read_rom(input).map_err(fail(Failure::Input))?;
+        let recorded = goldens.get(input);
+        let frames = args.frames.or(recorded.map(|golden| golden.frames)).unwrap_or(golden::DEFAULT_FRAMES);
+        let mut compiler = build_compiler(input, &args.codegen).map_err(fail(Failure::Input))?;
+        let (compiled, cpu) = if args.jit {
+            let jit = jit::emulate(compiler, &rom, clock_hz, frames)
+                .map_err(|e| Failed { failure: Failure::Codegen, message: format!("{}: {}", input, e) })?;
+            (jit.code, jit.cpu)
+        } else {
+            let (compiled, _) = compile_cached(&mut compiler, &rom, cache.as_ref())
+                .map_err(|e| Failed { failure: codegen_failure(&compiler), message: format!("{}: {}", input, e) })?;
+            let cpu = golden::emulate(&compiled, clock_hz, frames);
+            (compiled, cpu)
+        };
+        let display = golden::display(&compiled, &cpu);
+        let current = golden::Golden { frames, hash: golden::hash(display) };
+        // The run above is the verify run, for however many frames the golden is
+        let rules: Vec<FailOn> = args.fail_on.iter().copied().filter(|&rule| rule != FailOn::Verify).collect();
+        let trapped = golden::trapped(&compiled, &cpu).filter(|_| args.fail_on.contains(&FailOn::Verify));
+        let (status, note) = match (trapped, check_fail_on(&rules, &compiled, clock_hz), recorded) {
+            (Some((trap, value)), _, _) => {
+                first_failure.get_or_insert(Failure::Verify);
+                (report::Status::Failed, Some(format!("trapped: {} {:04X} ({})", trap.message(), value, trap.value())))
+            }
+            (None, Err(failed), _) => {
+                first_failure.get_or_insert(failed.failure);
+                (report::Status::Failed, Some(failed.message))
+            }
+            _ if args.bless => {
+                goldens.set(input, current);
+                (report::Status::Blessed, None)
+            }
+            (_, _, Some(golden)) if golden == current => (report::Status::Ok, None),
+            (_, _, Some(golden)) if golden.frames != frames => {
+                first_failure.get_or_insert(Failure::Verify);
+                (report::Status::Failed, Some(format!("golden is for {} frames, not {}", golden.frames, frames)))
+            }
+            (_, _, Some(golden)) => {
+                first_failure.get_or_insert(Failure::Verify);
+                (report::Status::Failed, Some(format!("display {:016x}, golden {:016x}", current.hash, golden.hash)))
+            }
+            (_, _, None) => {
+                first_failure.get_or_insert(Failure::Verify);
+                (report::Status::Failed, Some("no golden; --bless records one".to_string()))
+            }
+        };
+        let entry = report::Entry {
+            name: input.clone(),
+            status,
+            bytes: compiled.code.len(),
+            warnings: compiled.diagnostics.len(),
+            note,
+        };
+        let line = report.add(entry);
+        if !args.progress.quiet {
+            println!("{}", line);
+        }
+    }
+    if !args.progress.quiet {
+        print!("{}", report.summary());
+    }
+
+    let failed = report.failed();
+    if args.bless && failed == 0 {
+        fs::write(&args.goldens, goldens.to_string()).map_err(|e| format!("writing {}: {}", args.goldens, e))?;
+        println!("Blessed {} goldens in {}", roms.len(), args.goldens);
+        return Ok(());
+    }
+    if let Some(failure) = first_failure {
+        let message = match failure {
+            _ if args.bless => format!("{} of {} ROMs failed; no goldens blessed", failed, roms.len()),
+            Failure::Verify => format!("{} of {} displays don't match their goldens", failed, roms.len()),
+            _ => format!("{} of {} ROMs failed", failed, roms.len()),
+        };
+        return Err(Failed { failure, message });
+    }
+    println!("{} displays match their goldens", roms.len());
+    Ok(())
+}
+
+/// Run a ROM in the emulator and write its CHIP-8 memory image
+fn write_snapshot(args: &SnapshotArgs) -> Result<(), String> {
+    match args.codegen.target {
+        codegen::Target::Cpm => return Err("snapshots need a target that boots on its own, not cpm".to_string()),
+        codegen::Target::Coleco => return Err("snapshots read the CHIP-8 state at 8000, which coleco builds keep elsewhere".to_string()),
+        _ => {}
+    }
+    let rom = read_rom(&args.input)?;
+    let compiled = build_compiler(&args.input, &args.codegen)?.compile_code(&rom)?;
+    let clock_hz = args.codegen.clock.unwrap_or(args.codegen.target.clock_hz());
+    let cpu = golden::emulate(&compiled, clock_hz, args.frames);
+    let image = snapshot::memory_image(&cpu, &rom);
+    let stem = args.input.strip_suffix(".ch8").unwrap_or(&args.input);
+    let output = args.output.clone().unwrap_or_else(|| format!("{}.mem", stem));
+    fs::write(&output, &image).map_err(|e| format!("writing {}: {}", output, e))?;
+    println!("Wrote {} after {} frames -> {} ({} bytes)", args.input, args.frames, output, image.len());
+    Ok(())
+}
+
+/// Write the display test kit and the checkpoints a working display
+/// prints, or check a capture of a run against them
+fn display_test(args: &DisplayTestArgs) -> Result<(), Failed> {
+    let options = codegen_options(&args.codegen).and_then(|options| options.build()).map_err(fail(Failure::Input))?;
+    let (compiled, expected) = displaytest::build(&options).map_err(fail(Failure::Codegen))?;
+    let Some(capture) = &args.check else {
+        let image = options.compiler().image(compiled);
+        fs::write(&args.output, &image).map_err(|e| format!("writing {}: {}", args.output, e))?;
+        println!("Wrote the display test -> {} ({} bytes)", args.output, image.len());
+        println!("A working display prints:");
+        for (checkpoint, step) in expected.iter().zip(displaytest::STEPS) {
+            println!("  {}  {}", checkpoint, step);
+        }
+        return Ok(());
+    };
+    let text = fs::read_to_string(capture).map_err(|e| Failed { failure: Failure::Input, message: format!("reading {}: {}", capture, e) })?;
+    let printed = displaytest::parse(&text);
+    let mut failed = 0;
+    for (checkpoint, step) in expected.iter().zip(displaytest::STEPS) {
+        match printed.iter().find(|p| p.step == checkpoint.step) {
+            Some(p) if p == checkpoint => println!("  ok    {}  {}", p, step),
+            Some(p) => {
+                println!("  FAIL  {}  {}, expected {}", p, step, checkpoint);
+                failed += 1;
+            }
+            None => {
+                println!("  FAIL  D{:02X} missing  {}", checkpoint.step, step);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(Failed { failure: Failure::Verify, message: format!("{} of {} display checkpoints failed", failed, expected.len()) });
+    }
+    println!("All {} display checkpoints passed", expected.len());
+    Ok(())
+}
+
+/// Write the input test kit, or list the events in a capture of its run
+/// and what they show the driver getting wrong
+fn input_test(args: &InputTestArgs) -> Result<(), Failed> {
+    let options = codegen_options(&args.codegen).and_then(|options| options.build()).map_err(fail(Failure::Input))?;
+    let compiled = inputtest::build(&options).map_err(fail(Failure::Codegen))?;
+    let Some(capture) = &args.check else {
+        let image = options.compiler().image(compiled);
+        fs::write(&args.output, &image).map_err(|e| format!("writing {}: {}", args.output, e))?;
+        println!("Wrote the input test -> {} ({} bytes)", args.output, image.len());
+        println!("Press {} keys for FX0A, then hold, release and combine keys; it prints:", inputtest::WAITS);
+        println!("  R<kk>           get_key returned key kk (R-- for none)");
+        println!("  W<kk>           FX0A returned key kk");
+        println!("  K<EX9E> <EXA1>  the keys each sees down, bit N for key N");
+        return Ok(());
     };
+    let text = fs::read_to_string(capture).map_err(|e| Failed { failure: Failure::Input, message: format!("reading {}: {}", capture, e) })?;
+    let events = inputtest::parse(&text);
+    for event in &events {
+        match event {
+            inputtest::Event::Keys { ex9e, .. } => println!("  {}  down: {}", event, inputtest::keys(*ex9e)),
+            _ => println!("  {}", event),
+        }
+    }
+    let problems = inputtest::problems(&events);
+    for problem in &problems {
+        println!("  FAIL  {}", problem);
+    }
+    if !problems.is_empty() {
+        return Err(Failed { failure: Failure::Verify, message: format!("{} input problems in {} events", problems.len(), events.len()) });
+    }
+    println!("{} input events, none wrong", events.len());
+    Ok(())
+}
+
+/// Write the sound test kit, or time its beeps in the emulator and list
+/// what they show the driver getting wrong
+fn sound_test(args: &SoundTestArgs) -> Result<(), Failed> {
+    let options = codegen_options(&args.codegen).and_then(|options| options.build()).map_err(fail(Failure::Input))?;
+    let compiled = soundtest::build(&options).map_err(fail(Failure::Codegen))?;
+    if !args.measure {
+        let image = options.compiler().image(compiled);
+        fs::write(&args.output, &image).map_err(|e| format!("writing {}: {}", args.output, e))?;
+        println!("Wrote the sound test -> {} ({} bytes)", args.output, image.len());
+        let beeps: Vec<String> = soundtest::BEEPS.iter().map(|frames| frames.to_string()).collect();
+        println!("It beeps for {} frames, {} frames apart, then halts", beeps.join(", "), soundtest::GAP);
+        return Ok(());
+    }
+    let beeps = soundtest::measure(&options).map_err(fail(Failure::Input))?;
+    let clock_hz = options.clock_hz.unwrap_or(options.target.clock_hz());
+    let ms = |cycles: u64| cycles as f64 * 1000.0 / clock_hz as f64;
+    for beep in &beeps {
+        match (beep.cycles(), beep.latency(), beep.length()) {
+            (Some((latency, length)), Some(ticks), Some(frames)) => println!(
+                "  {}  ST={:<2}  on at tick {} ({:.1}ms), for {} frames ({:.1}ms)",
+                beep.output, beep.frames, ticks, ms(latency), frames, ms(length)
+            ),
+            _ => println!("  {}  ST={:<2}  {}", beep.output, beep.frames, if beep.on.is_some() { "never stopped" } else { "never sounded" }),
+        }
+    }
+    let problems = soundtest::problems(&beeps);
+    for problem in &problems {
+        println!("  FAIL  {}", problem);
+    }
+    if !problems.is_empty() {
+        return Err(Failed { failure: Failure::Verify, message: format!("{} sound problems in {} beeps", problems.len(), beeps.len()) });
+    }
+    println!("{} beeps, each on at the first tick and for its ST", beeps.len());
+    Ok(())
+}
 
-    // Read CHIP-8 ROM
-    let rom = match fs::read(input_path) {
-        Ok(data) => data,
-        Err(e) => {
-            eprintln!("Error reading {}: {}", input_path, e);
-            process::exit(1);
+/// Compile each ROM into its own bank and write the image and its manifest
+fn build_banks(args: &BanksArgs) -> Result<(), Failed> {
+    if args.codegen.target != codegen::Target::RetroShield {
+        return Err(Failed::from(format!("banked EPROMs are for ROM targets, not {}", args.codegen.target.name())));
+    }
+    let clock_hz = args.codegen.clock.unwrap_or(args.codegen.target.clock_hz());
+    let mut games = Vec::new();
+    let mut report = report::Report::new(args.inputs.len(), report::use_color(args.progress.no_color));
+    for (n, input) in args.inputs.iter().enumerate() {
+        let rom = read_rom(input).map_err(fail(Failure::Input))?;
+        let mut compiler = build_compiler(input, &args.codegen).map_err(fail(Failure::Input))?;
+        compiler.set_bank(n, args.inputs.len());
+        let game = compiler
+            .compile_code(&rom)
+            .map_err(|e| Failed { failure: codegen_failure(&compiler), message: format!("{}: {}", input, e) })?;
+        for diagnostic in &game.diagnostics {
+            eprintln!("{}: {}", input, diagnostic);
+        }
+        let used = game.origin as usize + game.code.len();
+        if used > args.bank_size {
+            return Err(Failed {
+                failure: Failure::Size,
+                message: format!("{}: needs {} bytes, more than the {} byte bank", input, used, args.bank_size),
+            });
+        }
+        check_fail_on(&args.fail_on, &game, clock_hz).map_err(|failed| Failed { message: format!("{}: {}", input, failed.message), ..failed })?;
+        let entry = report::Entry {
+            name: input.clone(),
+            status: report::Status::Ok,
+            bytes: game.code.len(),
+            warnings: game.diagnostics.len(),
+            note: None,
+        };
+        let line = report.add(entry);
+        if !args.progress.quiet {
+            println!("{}", line);
         }
+        games.push(game);
+    }
+    let image = banks::combine(&games, args.bank_size)?;
+    let binary = args.package.format()?.write(&image, args.package.flash_base)?;
+    fs::write(&args.output, &binary).map_err(|e| format!("writing {}: {}", args.output, e))?;
+
+    let output = &args.output;
+    let manifest_path = args.manifest.clone().unwrap_or_else(|| {
+        let stem = output.rsplit_once('.').map_or(output.as_str(), |(stem, _)| stem);
+        format!("{}.txt", stem)
+    });
+    let names: Vec<String> = args.inputs.iter()
+        .map(|input| std::path::Path::new(input).file_name().map_or(input.clone(), |name| name.to_string_lossy().into_owned()))
+        .collect();
+    let manifest = banks::manifest(&names, &games, args.bank_size);
+    fs::write(&manifest_path, &manifest).map_err(|e| format!("writing {}: {}", manifest_path, e))?;
+    println!("Compiled {} games -> {} ({} bytes), manifest {}", games.len(), output, binary.len(), manifest_path);
+    if !args.progress.quiet {
+        print!("{}", manifest);
+    }
+    Ok(())
+}
+
+/// Print the ROM's code/data map and the statistics of compiling it
+fn disasm(input: &str, regions: RegionArgs) -> Result<(), String> {
+    let rom = read_rom(input)?;
+    for line in chip8::disassemble_with_regions(&rom, &regions.into()) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn analyze(input: &str, regions: RegionArgs, args: &CodegenArgs) -> Result<(), String> {
+    let rom = read_rom(input)?;
+    let map = analysis::classify_with_regions(&rom, &regions.into());
+    println!("{} ({} bytes)", input, rom.len());
+    for region in map.regions() {
+        println!("  {:03X}-{:03X}  {:<6} {:5} bytes", region.start, region.end() - 1, format!("{:?}", region.kind), region.len);
+    }
+    for write in &map.code_writes {
+        println!("  {:03X}: stores {} byte(s) into code at {:03X}", write.at, write.len, write.target);
+    }
+    for read in &map.unwritten_reads {
+        println!("  {:03X}: loads {} byte(s) from {:03X}, which nothing stores: they are what --ram-init leaves", read.at, read.len, read.source);
+    }
+
+    let compiled = build_compiler(input, args)?.compile_code(&rom)?;
+    for diagnostic in &compiled.diagnostics {
+        println!("  {}", diagnostic);
+    }
+    println!("Compiled: {} bytes at {:04X}", compiled.code.len(), compiled.origin);
+    print_stats(&compiled.stats);
+    Ok(())
+}
+
+/// Expand a compact runtime trap code into its message
+fn explain(line: &str) -> Result<(), String> {
+    let text = trap::explain(line).ok_or_else(|| format!("unknown trap code '{}'", line))?;
+    println!("{}", text);
+    Ok(())
+}
+
+/// List the runtime entry points callable from Z80 code in a build
+/// linked at `origin`
+fn print_abi(origin: u16) {
+    println!("Runtime ABI {}.{}", abi::VERSION.0, abi::VERSION.1);
+    for entry in abi::ENTRIES {
+        println!("{:04X}  {:<16} in: {}; out: {}; clobbers: {}",
+            entry.address(origin), entry.name, entry.inputs, entry.outputs, entry.clobbers);
+    }
+}
+
+/// Turn a serial capture of a --profile-gen build into a counters file
+fn profile_convert(capture: &str, output: Option<&str>) -> Result<(), String> {
+    let text = fs::read_to_string(capture).map_err(|e| format!("reading {}: {}", capture, e))?;
+    let json = profile::Profile::from_dump(&text)?.to_json();
+    match output {
+        Some(path) => fs::write(path, json + "\n").map_err(|e| format!("writing {}: {}", path, e))?,
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+/// Write the serial loader ROM
+fn write_loader(path: &str, acia_ports: Option<(u8, u8)>) -> Result<(), String> {
+    let rom = match acia_ports {
+        Some((ctrl, data)) => loader::loader_rom_for(ctrl, data),
+        None => loader::loader_rom(),
     };
+    fs::write(path, &rom).map_err(|e| format!("writing {}: {}", path, e))?;
+    println!("Wrote loader -> {} ({} bytes)", path, rom.len());
+    Ok(())
+}
 
-    if rom.is_empty() {
-        eprintln!("Error: ROM file is empty");
-        process::exit(1);
+/// Load a compiled image through the serial loader. The port is used as
+/// configured (e.g. `stty -F /dev/ttyUSB0 115200 raw -echo`).
+fn send(image_path: &str, port_path: &str, verify: bool) -> Result<(), Failed> {
+    let image = fs::read(image_path).map_err(|e| Failed { failure: Failure::Input, message: format!("reading {}: {}", image_path, e) })?;
+    let frames = loader::frames(&image).map_err(fail(Failure::Input))?;
+    let mut port = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(port_path)
+        .map_err(|e| format!("opening {}: {}", port_path, e))?;
+    let mut input = port.try_clone().map_err(|e| format!("opening {}: {}", port_path, e))?;
+
+    // Replies are read on a thread so that waiting for one can time out
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut byte = [0u8];
+        while let Ok(1) = std::io::Read::read(&mut input, &mut byte) {
+            if tx.send(byte[0]).is_err() {
+                break;
+            }
+        }
+    });
+
+    // The game starts with the last frame, once what was stored checks out
+    let (go, data) = frames.split_last().expect("a go frame");
+    let resends = loader::send(&mut port, &rx, data)?;
+    if verify {
+        let bad = loader::verify(&mut port, &rx, &image)?;
+        for range in &bad {
+            println!("  FAIL  {:04X}-{:04X} isn't what was sent", range.start, range.end - 1);
+        }
+        if !bad.is_empty() {
+            let message = format!("{} of {}'s {} frames don't match on the board; not starting it", bad.len(), image_path, data.len());
+            return Err(Failed { failure: Failure::Verify, message });
+        }
+    }
+    loader::send(&mut port, &rx, std::slice::from_ref(go))?;
+    let checked = if verify { ", CRC checked" } else { "" };
+    println!("Sent {} -> {} ({} frames, {} resent{})", image_path, port_path, frames.len(), resends, checked);
+    Ok(())
+}
+
+/// Drive the step gate of a board's game from commands typed on stdin,
+/// passing its console through with a note of what changed at each stop
+fn debug(port_path: &str) -> Result<(), String> {
+    use std::io::{Read, Write};
+    let mut port = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(port_path)
+        .map_err(|e| format!("opening {}: {}", port_path, e))?;
+    let mut input = port.try_clone().map_err(|e| format!("opening {}: {}", port_path, e))?;
+
+    std::thread::spawn(move || {
+        let mut session = stepgate::Session::new();
+        let mut buffer = [0u8; 256];
+        while let Ok(count @ 1..) = input.read(&mut buffer) {
+            let mut out = std::io::stdout().lock();
+            let _ = out.write_all(&buffer[..count]);
+            for note in session.feed(&buffer[..count]) {
+                let _ = write!(out, "\r\n# {}\r\n", note);
+            }
+            let _ = out.flush();
+        }
+    });
+
+    println!("Debugging on {}: Enter or s steps, g goes, stop stops, k KEY presses a key, b ADDR breaks, q quits", port_path);
+    for line in std::io::stdin().lines() {
+        let line = line.map_err(|e| format!("reading commands: {}", e))?;
+        match stepgate::command(&line) {
+            Ok(Some(bytes)) => port.write_all(&bytes).map_err(|e| format!("writing {}: {}", port_path, e))?,
+            Ok(None) => break,
+            Err(message) => eprintln!("{}", message),
+        }
     }
+    Ok(())
+}
 
-    // Compile to Z80
-    let mut compiler = codegen::Compiler::new();
-    match compiler.compile(&rom) {
-        Ok(binary) => {
-            if let Err(e) = fs::write(&output_path, &binary) {
-                eprintln!("Error writing {}: {}", output_path, e);
-                process::exit(1);
+/// Draw the display of a delta build from its serial output, asking for
+/// all of it first, and pass stdin to the board as keys
+fn view(port_path: &str) -> Result<(), String> {
+    use std::io::{Read, Write};
+    let mut port = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(port_path)
+        .map_err(|e| format!("opening {}: {}", port_path, e))?;
+    let mut keys = port.try_clone().map_err(|e| format!("opening {}: {}", port_path, e))?;
+    std::thread::spawn(move || {
+        let mut byte = [0u8];
+        while let Ok(1) = std::io::stdin().read(&mut byte) {
+            if keys.write_all(&byte).is_err() {
+                break;
             }
-            println!("Compiled {} -> {} ({} bytes)", input_path, output_path, binary.len());
         }
-        Err(e) => {
-            eprintln!("Compilation error: {}", e);
-            process::exit(1);
+    });
+
+    port.write_all(&[delta::RESYNC]).map_err(|e| format!("writing {}: {}", port_path, e))?;
+    let mut decoder = delta::Decoder::new();
+    let mut buffer = [0u8; 256];
+    let mut out = std::io::stdout();
+    print!("\x1b[2J\x1b[34;1H");  // Console text below the display
+    loop {
+        let count = port.read(&mut buffer).map_err(|e| format!("reading {}: {}", port_path, e))?;
+        if count == 0 {
+            return Ok(());
+        }
+        let (text, drawn) = decoder.feed(&buffer[..count]);
+        if drawn {
+            let _ = write!(out, "\x1b[s\x1b[1;1H{}\x1b[u", decoder.render().replace('\n', "\r\n"));
         }
+        let _ = out.write_all(&text);
+        let _ = out.flush();
+    }
+}
+
+/// Print the code size and cycle report for `--stats`
+fn print_stats(stats: &codegen::CodeStats) {
+    println!("  Runtime:      {:6} bytes", stats.runtime_bytes);
+    println!("  Program:      {:6} bytes", stats.program_bytes);
+    println!("  Data:         {:6} bytes", stats.data_bytes);
+    println!("  Instructions: {:6}", stats.instructions);
+    println!(
+        "  CHIP-8 ROM:   {:6} code, {} data, {} unreferenced bytes",
+        stats.rom_code, stats.rom_data, stats.rom_unused
+    );
+    println!("  T-states:     {:6} (static, excluding runtime calls)", stats.program_tstates);
+    if stats.instructions > 0 {
+        println!(
+            "  Average:      {:6.1} T-states / {:.1} bytes per instruction",
+            stats.program_tstates as f64 / stats.instructions as f64,
+            stats.program_bytes as f64 / stats.instructions as f64
+        );
     }
 }
+
+/// Print the pass timings for `--time-passes`, on stderr so that they
+/// stay out of the way of anything reading the report
+fn print_passes(passes: &[timing::PassTime]) {
+    eprintln!("  {:<16} {:>4} {:>10} {:>10}", "Pass", "Runs", "Time", "Peak heap");
+    for pass in passes {
+        let peak = pass.peak_bytes.map_or("-".to_string(), |bytes| format!("{:.1} KB", bytes as f64 / 1024.0));
+        eprintln!("  {:<16} {:>4} {:>7.3} ms {:>10}", pass.name, pass.runs, pass.time.as_secs_f64() * 1000.0, peak);
+    }
+    let total: std::time::Duration = passes.iter().map(|pass| pass.time).sum();
+    eprintln!("  {:<16} {:>4} {:>7.3} ms", "Total", "", total.as_secs_f64() * 1000.0);
+}
+
+/// Parse a number given in decimal or with a 0x prefix
+fn parse_num(s: &str) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number '{}'", s))
+}
+
+/// Parse an inclusive CHIP-8 address range "START-END" in hex
+fn parse_range(s: &str) -> Result<Range<u16>, String> {
+    let hex = |n: &str| u16::from_str_radix(n.trim_start_matches("0x"), 16).ok();
+    match s.split_once('-').map(|(start, end)| (hex(start), hex(end))) {
+        Some((Some(start), Some(end))) if (0x200..0xFFFF).contains(&start) && start <= end && end < 0xFFFF => Ok(start..end + 1),
+        _ => Err(format!("invalid range '{}' (expected START-END, from 200)", s)),
+    }
+}
+
+fn parse_clock(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(hz) if hz > 0 => Ok(hz),
+        _ => Err(format!("invalid clock frequency '{}'", s)),
+    }
+}
+
+fn parse_size(s: &str) -> Result<usize, String> {
+    match parse_num(s)? {
+        0 => Err("size must be above 0".to_string()),
+        bytes => Ok(bytes as usize),
+    }
+}
+
+/// Parse a `--fail-on` rule: warnings, verify or size>N
+fn parse_fail_on(s: &str) -> Result<FailOn, String> {
+    match s {
+        "warnings" => Ok(FailOn::Warnings),
+        "verify" => Ok(FailOn::Verify),
+        _ => match s.strip_prefix("size>") {
+            Some(size) => parse_size(size).map(FailOn::Size),
+            None => Err(format!("invalid rule '{}' (expected warnings, size>N or verify)", s)),
+        },
+    }
+}
+
+/// Code addresses stop at 0x8000, where RAM starts
+fn parse_origin(s: &str) -> Result<u16, String> {
+    match parse_num(s)? {
+        addr if addr < 0x8000 => Ok(addr as u16),
+        _ => Err(format!("address '{}' is not below 0x8000", s)),
+    }
+}
+
+/// Parse a beeper spec "PORT[:BIT]" (bit defaults to 0)
+fn parse_beeper(s: &str) -> Result<(u8, u8), String> {
+    let (port, bit) = match s.split_once(':') {
+        Some((port, bit)) => (port, parse_num(bit)?),
+        None => (s, 0),
+    };
+    let port = parse_num(port)?;
+    if port > 0xFF || bit > 7 {
+        return Err(format!("invalid beeper '{}' (expected PORT[:BIT])", s));
+    }
+    Ok((port as u8, bit as u8))
+}
+
+/// Parse a framebuffer spec "ADDR[:STRIDE]" (stride defaults to 8)
+fn parse_framebuffer(s: &str) -> Result<(u16, u16), String> {
+    let (base, stride) = match s.split_once(':') {
+        Some((base, stride)) => (base, parse_num(stride)?),
+        None => (s, 8),
+    };
+    let base = parse_num(base)?;
+    if base > 0xFFFF || !(4..=0x800).contains(&stride) {
+        return Err(format!("invalid framebuffer '{}' (expected ADDR[:STRIDE], a stride of 4 to 0x800)", s));
+    }
+    Ok((base as u16, stride as u16))
+}
+
+/// Check a quirk spec now so that mistakes are reported with the usage
+fn parse_quirk(s: &str) -> Result<String, String> {
+    quirks::Quirks::default().apply(s)?;
+    Ok(s.to_string())
+}
+
+/// Check an --orientation spec now so that mistakes are reported with the usage
+fn parse_orientation(s: &str) -> Result<String, String> {
+    codegen::Orientation::default().apply(s)?;
+    Ok(s.to_string())
+}
+
+/// Check a --warn spec now so that mistakes are reported with the usage
+fn parse_warn(s: &str) -> Result<String, String> {
+    diagnostics::Levels::default().apply(s)?;
+    Ok(s.to_string())
+}
+
+fn parse_target(s: &str) -> Result<codegen::Target, String> {
+    codegen::Target::from_name(s).ok_or_else(|| format!("invalid target '{}' (expected retroshield, spectrum, msx, cpm, cpc, rc2014, sms or coleco)", s))
+}
+
+fn parse_ram_init(s: &str) -> Result<codegen::RamInit, String> {
+    codegen::RamInit::from_name(s).ok_or_else(|| format!("invalid RAM fill '{}' (expected zero, ff or random(SEED))", s))
+}
+
+fn parse_speed(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(ips) if ips > 0 => Ok(ips),
+        _ => Err(format!("invalid speed '{}' (expected CHIP-8 instructions a second)", s)),
+    }
+}
+
+fn parse_timer(s: &str) -> Result<codegen::Timer, String> {
+    codegen::Timer::from_name(s).ok_or_else(|| format!("invalid timer '{}' (expected cycles or ctc:PORT)", s))
+}
+
+fn parse_audio(s: &str) -> Result<codegen::Audio, String> {
+    codegen::Audio::from_name(s).ok_or_else(|| format!("invalid audio '{}' (expected bell or ay8910)", s))
+}
+
+/// Parse AY-3-8910 ports "REGISTER,DATA"
+fn parse_ay_ports(s: &str) -> Result<(u8, u8), String> {
+    parse_port_pair(s).ok_or_else(|| format!("invalid AY ports '{}' (expected REGISTER,DATA)", s))
+}
+
+/// Parse ACIA ports "CTRL,DATA"
+fn parse_acia_ports(s: &str) -> Result<(u8, u8), String> {
+    parse_port_pair(s).ok_or_else(|| format!("invalid ACIA ports '{}' (expected CTRL,DATA)", s))
+}
+
+fn parse_port_pair(s: &str) -> Option<(u8, u8)> {
+    let (first, second) = s.split_once(',')?;
+    match (parse_num(first).ok()?, parse_num(second).ok()?) {
+        (first @ 0..=0xFF, second @ 0..=0xFF) => Some((first as u8, second as u8)),
+        _ => None,
+    }
+}
+
+fn parse_scale(s: &str) -> Result<codegen::Scale, String> {
+    codegen::Scale::from_name(s).ok_or_else(|| format!("invalid scale '{}' (expected 1, 2 or scanlines)", s))
+}
+
+fn parse_glyphs(s: &str) -> Result<codegen::Glyphs, String> {
+    codegen::Glyphs::from_name(s).ok_or_else(|| format!("invalid glyphs '{}' (expected ascii, blocks or braille)", s))
+}
+
+fn parse_package(s: &str) -> Result<package::Package, String> {
+    package::Package::from_name(s).ok_or_else(|| format!("invalid package '{}' (expected bin, hex, srec, uf2 or wav)", s))
+}
+
+fn parse_pad(path: &str) -> Result<sms::PadMap, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path, e))?;
+    sms::PadMap::parse(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_keymap(path: &str) -> Result<keymap::KeyMap, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path, e))?;
+    keymap::KeyMap::parse(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_memory_map(path: &str) -> Result<memmap::MemoryMap, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path, e))?;
+    memmap::MemoryMap::parse(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_profile(path: &str) -> Result<profile::Profile, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path, e))?;
+    profile::Profile::from_json(&text)
+}
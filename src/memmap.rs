@@ -0,0 +1,132 @@
+// Memory maps
+// Where a board decodes its ROM and RAM, read from TOML-style sections,
+// so the runtime can be placed on boards without RAM at 0x8000
+
+#[cfg(feature = "config")]
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// RAM a map must have: the runtime's page of variables, the display and
+/// font, CHIP-8's own 4KB and room for the Z80 stack
+pub const RAM_NEEDED: u32 = 0x400 + 0xE00 + STACK_ROOM as u32;
+
+/// Top of RAM kept for the Z80 stack
+pub const STACK_ROOM: u16 = 0x200;
+
+/// Addresses from `start` to `end`, both included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Region {
+    /// The region as a range that ends after `end`
+    pub fn range(self) -> Range<usize> {
+        self.start as usize..self.end as usize + 1
+    }
+
+    fn len(self) -> u32 {
+        self.end as u32 - self.start as u32 + 1
+    }
+
+    fn overlaps(self, other: Region) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    fn name(self) -> String {
+        format!("{:04X}-{:04X}", self.start, self.end)
+    }
+}
+
+/// A board's ROM, where the code goes, and its RAM, where the runtime keeps
+/// everything else from the CHIP-8 registers down to the Z80 stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+pub struct MemoryMap {
+    pub rom: Region,
+    pub ram: Region,
+}
+
+impl MemoryMap {
+    /// Read a map file of `[rom]` and `[ram]` sections, each with a
+    /// `start` and an `end` address, in hex as `0xC000` or decimal. `#`
+    /// starts a comment.
+    #[cfg(feature = "config")]
+    pub fn parse(text: &str) -> Result<MemoryMap, String> {
+        let mut regions: [(Option<u16>, Option<u16>); 2] = [(None, None); 2];
+        let mut section = None;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                section = match name.trim() {
+                    "rom" => Some(0),
+                    "ram" => Some(1),
+                    other => return Err(format!("line {}: unknown section [{}] (expected [rom] or [ram])", n + 1, other)),
+                };
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected KEY = ADDRESS", n + 1));
+            };
+            let Some(section) = section else {
+                return Err(format!("line {}: {} is outside [rom] or [ram]", n + 1, key.trim()));
+            };
+            let address = address(value.trim()).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            let slot = match key.trim() {
+                "start" => &mut regions[section].0,
+                "end" => &mut regions[section].1,
+                other => return Err(format!("line {}: unknown key '{}' (expected start or end)", n + 1, other)),
+            };
+            if slot.replace(address).is_some() {
+                return Err(format!("line {}: {} is set twice", n + 1, key.trim()));
+            }
+        }
+        let region = |index: usize, name: &str| match regions[index] {
+            (Some(start), Some(end)) => Ok(Region { start, end }),
+            _ => Err(format!("[{}] needs a start and an end", name)),
+        };
+        let map = MemoryMap { rom: region(0, "rom")?, ram: region(1, "ram")? };
+        map.check()?;
+        Ok(map)
+    }
+
+    /// Refuse regions that end before they start or overlap, and RAM that
+    /// can't hold the runtime
+    pub fn check(&self) -> Result<(), String> {
+        for (name, region) in [("rom", self.rom), ("ram", self.ram)] {
+            if region.end < region.start {
+                return Err(format!("{} ends at {:04X}, before it starts at {:04X}", name, region.end, region.start));
+            }
+        }
+        if self.rom.overlaps(self.ram) {
+            return Err(format!("rom at {} overlaps ram at {}", self.rom.name(), self.ram.name()));
+        }
+        if self.ram.start & 0xFF != 0 {
+            return Err(format!("ram should start on a page boundary, not at {:04X}", self.ram.start));
+        }
+        if self.ram.len() < RAM_NEEDED {
+            return Err(format!("ram at {} is {} bytes; the runtime needs {}", self.ram.name(), self.ram.len(), RAM_NEEDED));
+        }
+        Ok(())
+    }
+
+    /// The map as the build info shows it
+    pub fn name(&self) -> String {
+        format!("rom:{},ram:{}", self.rom.name(), self.ram.name())
+    }
+}
+
+/// An address from 0 to FFFF
+#[cfg(feature = "config")]
+fn address(text: &str) -> Result<u16, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => text.replace('_', "").parse::<u32>(),
+    };
+    parsed.ok().and_then(|value| u16::try_from(value).ok()).ok_or(format!("bad address {} (expected 0 to 0xFFFF)", text))
+}
@@ -0,0 +1,404 @@
+// Compile options
+// Everything that shapes a build apart from the ROM and its name, in one
+// struct that the command line, JSON files and library callers all fill in
+// the same way and that configures a Compiler from a single place
+
+use crate::codegen::{Audio, CodegenStyle, Compiler, Fallback, Glyphs, Layout, Orientation, RamInit, Scale, Target, Timer, Trace};
+use crate::diagnostics::Levels;
+use crate::keymap::KeyMap;
+use crate::memmap::MemoryMap;
+use crate::package::Package;
+use crate::quirks::Quirks;
+use crate::sms::PadMap;
+#[cfg(feature = "config")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "config")]
+use serde_json::{json, Value};
+
+/// Version of the options file format, written as its `version` key.
+/// Files without one are from version 1.
+#[cfg(feature = "config")]
+pub const VERSION: u64 = 2;
+
+/// Keys renamed since version 1: the version that renamed each, its old
+/// name and its new one
+#[cfg(feature = "config")]
+const RENAMED: &[(u64, &str, &str)] = &[
+    (2, "clock_hz", "clock-hz"),
+    (2, "allow_self_modify", "allow-self-modify"),
+    (2, "profile_gen", "profile-gen"),
+    (2, "compact_traps", "compact-traps"),
+    (2, "flash_base", "flash-base"),
+];
+
+/// Settings for a build; fields left out of a file take their defaults
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default, deny_unknown_fields, rename_all = "kebab-case"))]
+pub struct CompileOptions {
+    pub target: Target,
+    pub clock_hz: Option<u32>,      // Target's own clock if not set
+    pub origin: Option<u16>,        // Target's own origin if not set
+    pub memory_map: Option<MemoryMap>,  // Target's own ROM and RAM if not set
+    pub quirks: Quirks,
+    pub levels: Levels,             // Diagnostic levels over the defaults
+    pub allow_self_modify: bool,
+    pub fallback: Fallback,
+    pub layout: Layout,
+    pub codegen_style: CodegenStyle,
+    pub profile_gen: bool,
+    pub trace: Trace,
+    pub debug_stub: bool,
+    pub step_gate: bool,            // Needs debug_stub
+    pub compact_traps: bool,
+    pub symbol_table: bool,
+    pub beeper: Option<(u8, u8)>,   // Output port and bit
+    pub audio: Option<Audio>,
+    pub framebuffer: Option<(u16, u16)>,  // Video memory base and row stride
+    pub orientation: Orientation,   // Pixel layout of the framebuffer
+    pub scale: Scale,               // Pixel size in the framebuffer
+    pub delta_display: bool,
+    pub glyphs: Glyphs,
+    pub ram_init: Option<RamInit>,  // CHIP-8 RAM left as found if not set
+    pub compress_rom: bool,
+    pub key_release: Option<u16>,   // Milliseconds; the compiler's default if not set
+    pub rx_buffer: bool,            // RetroShield only
+    pub acia_ports: Option<(u8, u8)>,  // Control/status and data; RetroShield only
+    pub timer: Timer,               // RetroShield and RC2014 only, for a CTC
+    pub speed: Option<u32>,         // CHIP-8 instructions a second; unpaced if not set
+    pub pad: Option<PadMap>,        // Master System only
+    pub keymap: Option<KeyMap>,     // Serial and CP/M consoles only
+    pub trim: bool,
+    pub package: Package,
+    pub flash_base: u32,
+}
+
+impl CompileOptions {
+    pub fn builder() -> CompileOptionsBuilder {
+        CompileOptionsBuilder::default()
+    }
+
+    /// Options from JSON, e.g. `{"target": "msx", "quirks": {"vf-reset": true}}`,
+    /// with a warning for each key an older version called something else.
+    /// Keys this version doesn't know are an error, not ignored.
+    #[cfg(feature = "config")]
+    pub fn from_json(text: &str) -> Result<(CompileOptions, Vec<String>), String> {
+        let mut value: Value = serde_json::from_str(text).map_err(|e| format!("invalid options: {}", e))?;
+        let object = value.as_object_mut().ok_or("options should be a JSON object")?;
+        let version = match object.remove("version") {
+            None => 1,
+            Some(version) => version.as_u64().filter(|&v| v >= 1).ok_or("options version should be a number from 1")?,
+        };
+        if version > VERSION {
+            return Err(format!("options are version {}, newer than the {} this kz80_chip8 reads; update it", version, VERSION));
+        }
+        let mut warnings = Vec::new();
+        for &(since, old, new) in RENAMED {
+            let Some(setting) = object.remove(old) else { continue };
+            if version >= since {
+                return Err(format!("unknown option '{}': version {} calls it '{}'", old, version, new));
+            }
+            if object.contains_key(new) {
+                return Err(format!("options set both '{}' and '{}', its name since version {}", old, new, since));
+            }
+            warnings.push(format!("option '{}' is '{}' since version {}; the old name still works for now", old, new, since));
+            object.insert(new.to_string(), setting);
+        }
+        let known = serde_json::to_value(CompileOptions::default()).expect("options serialize");
+        let known = known.as_object().expect("options are an object");
+        if let Some(key) = object.keys().find(|key| !known.contains_key(*key)) {
+            let names: Vec<&str> = known.keys().map(|name| name.as_str()).collect();
+            return Err(format!("unknown option '{}' (expected {})", key, names.join(", ")));
+        }
+        let options: CompileOptions = serde_json::from_value(value).map_err(|e| format!("invalid options: {}", e))?;
+        options.check()?;
+        Ok((options, warnings))
+    }
+
+    /// Serialize in the format read by `from_json`, marked with the current
+    /// version
+    #[cfg(feature = "config")]
+    pub fn to_json(&self) -> String {
+        let mut value = serde_json::to_value(self).expect("options serialize");
+        value["version"] = json!(VERSION);
+        serde_json::to_string_pretty(&value).expect("options serialize")
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.pad.is_some() && self.target != Target::Sms {
+            return Err(format!("a pad mapping is for the Master System's pad; it doesn't apply to {}", self.target.name()));
+        }
+        if let Some(keymap) = &self.keymap {
+            keymap.check()?;
+        }
+        if let Some(map) = &self.memory_map {
+            map.check()?;
+        }
+        self.orientation.check()
+    }
+
+    /// Compiler set up with these options. Packaging is left to the caller.
+    pub fn compiler(&self) -> Compiler {
+        let mut compiler = Compiler::new();
+        compiler.set_target(self.target);
+        if let Some(hz) = self.clock_hz {
+            compiler.set_clock_hz(hz);
+        }
+        if let Some(map) = self.memory_map {
+            compiler.set_memory_map(map);
+        }
+        if let Some(origin) = self.origin {
+            compiler.set_origin(origin);
+        }
+        compiler.set_quirks(self.quirks);
+        compiler.set_levels(self.levels.clone());
+        compiler.set_allow_self_modify(self.allow_self_modify);
+        compiler.set_fallback(self.fallback);
+        compiler.set_layout(self.layout.clone());
+        compiler.set_codegen_style(self.codegen_style);
+        compiler.set_profile_gen(self.profile_gen);
+        compiler.set_trace(self.trace);
+        compiler.set_debug_stub(self.debug_stub);
+        compiler.set_step_gate(self.step_gate);
+        compiler.set_compact_traps(self.compact_traps);
+        compiler.set_symbol_table(self.symbol_table);
+        if let Some((port, bit)) = self.beeper {
+            compiler.set_beeper(port, bit);
+        }
+        if let Some(audio) = self.audio {
+            compiler.set_audio(audio);
+        }
+        if let Some((base, stride)) = self.framebuffer {
+            compiler.set_framebuffer(base, stride);
+        }
+        compiler.set_orientation(self.orientation);
+        compiler.set_scale(self.scale);
+        compiler.set_delta_display(self.delta_display);
+        compiler.set_glyphs(self.glyphs);
+        if let Some(ram_init) = self.ram_init {
+            compiler.set_ram_init(ram_init);
+        }
+        compiler.set_compress_rom(self.compress_rom);
+        if let Some(ms) = self.key_release {
+            compiler.set_key_release(ms);
+        }
+        compiler.set_rx_buffer(self.rx_buffer);
+        if let Some((ctrl, data)) = self.acia_ports {
+            compiler.set_acia_ports(ctrl, data);
+        }
+        compiler.set_timer(self.timer);
+        if let Some(ips) = self.speed {
+            compiler.set_speed(ips);
+        }
+        if let Some(pad) = self.pad {
+            compiler.set_pad(pad);
+        }
+        if let Some(keymap) = &self.keymap {
+            compiler.set_keymap(keymap.clone());
+        }
+        compiler.set_trim(self.trim);
+        compiler
+    }
+}
+
+/// Builds CompileOptions one setting at a time
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptionsBuilder {
+    options: CompileOptions,
+}
+
+impl CompileOptionsBuilder {
+    pub fn target(mut self, target: Target) -> Self {
+        self.options.target = target;
+        self
+    }
+
+    pub fn clock_hz(mut self, hz: u32) -> Self {
+        self.options.clock_hz = Some(hz);
+        self
+    }
+
+    pub fn origin(mut self, origin: u16) -> Self {
+        self.options.origin = Some(origin);
+        self
+    }
+
+    /// Where the board has its ROM and RAM, when that isn't where the
+    /// target has them
+    pub fn memory_map(mut self, map: MemoryMap) -> Self {
+        self.options.memory_map = Some(map);
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.options.quirks = quirks;
+        self
+    }
+
+    pub fn levels(mut self, levels: Levels) -> Self {
+        self.options.levels = levels;
+        self
+    }
+
+    pub fn allow_self_modify(mut self, allow: bool) -> Self {
+        self.options.allow_self_modify = allow;
+        self
+    }
+
+    pub fn fallback(mut self, fallback: Fallback) -> Self {
+        self.options.fallback = fallback;
+        self
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.options.layout = layout;
+        self
+    }
+
+    pub fn codegen_style(mut self, style: CodegenStyle) -> Self {
+        self.options.codegen_style = style;
+        self
+    }
+
+    pub fn profile_gen(mut self, profile_gen: bool) -> Self {
+        self.options.profile_gen = profile_gen;
+        self
+    }
+
+    pub fn trace(mut self, trace: Trace) -> Self {
+        self.options.trace = trace;
+        self
+    }
+
+    pub fn debug_stub(mut self, debug_stub: bool) -> Self {
+        self.options.debug_stub = debug_stub;
+        self
+    }
+
+    pub fn step_gate(mut self, step_gate: bool) -> Self {
+        self.options.step_gate = step_gate;
+        self
+    }
+
+    pub fn compact_traps(mut self, compact: bool) -> Self {
+        self.options.compact_traps = compact;
+        self
+    }
+
+    /// Embed the CHIP-8 address of each instruction's code, for traps and
+    /// the debug monitor
+    pub fn symbol_table(mut self, symbol_table: bool) -> Self {
+        self.options.symbol_table = symbol_table;
+        self
+    }
+
+    /// Drive bit `bit` of output port `port` while the sound timer runs
+    pub fn beeper(mut self, port: u8, bit: u8) -> Self {
+        self.options.beeper = Some((port, bit));
+        self
+    }
+
+    pub fn audio(mut self, audio: Audio) -> Self {
+        self.options.audio = Some(audio);
+        self
+    }
+
+    /// Copy the display to video memory at `base`, a row every `stride` bytes
+    pub fn framebuffer(mut self, base: u16, stride: u16) -> Self {
+        self.options.framebuffer = Some((base, stride));
+        self
+    }
+
+    /// Turn, mirror or bit-reverse the framebuffer for its display module
+    pub fn orientation(mut self, orientation: Orientation) -> Self {
+        self.options.orientation = orientation;
+        self
+    }
+
+    pub fn scale(mut self, scale: Scale) -> Self {
+        self.options.scale = scale;
+        self
+    }
+
+    /// Send only the changed display bytes over serial, for `view`
+    pub fn delta_display(mut self, delta_display: bool) -> Self {
+        self.options.delta_display = delta_display;
+        self
+    }
+
+    pub fn glyphs(mut self, glyphs: Glyphs) -> Self {
+        self.options.glyphs = glyphs;
+        self
+    }
+
+    pub fn ram_init(mut self, ram_init: RamInit) -> Self {
+        self.options.ram_init = Some(ram_init);
+        self
+    }
+
+    /// Embed the ROM run-length encoded, inflated into RAM at startup
+    pub fn compress_rom(mut self, compress_rom: bool) -> Self {
+        self.options.compress_rom = compress_rom;
+        self
+    }
+
+    pub fn key_release(mut self, ms: u16) -> Self {
+        self.options.key_release = Some(ms);
+        self
+    }
+
+    /// Buffer serial characters on the ACIA's receive interrupt
+    pub fn rx_buffer(mut self, rx_buffer: bool) -> Self {
+        self.options.rx_buffer = rx_buffer;
+        self
+    }
+
+    /// Ports of an ACIA decoded somewhere other than 80 and 81
+    pub fn acia_ports(mut self, ctrl: u8, data: u8) -> Self {
+        self.options.acia_ports = Some((ctrl, data));
+        self
+    }
+
+    /// Tick the 60Hz timers from a CTC's interrupt, not the code's cycles
+    pub fn timer(mut self, timer: Timer) -> Self {
+        self.options.timer = timer;
+        self
+    }
+
+    /// Run at most `ips` CHIP-8 instructions a second
+    pub fn speed(mut self, ips: u32) -> Self {
+        self.options.speed = Some(ips);
+        self
+    }
+
+    pub fn pad(mut self, pad: PadMap) -> Self {
+        self.options.pad = Some(pad);
+        self
+    }
+
+    pub fn keymap(mut self, keymap: KeyMap) -> Self {
+        self.options.keymap = Some(keymap);
+        self
+    }
+
+    pub fn trim(mut self, trim: bool) -> Self {
+        self.options.trim = trim;
+        self
+    }
+
+    pub fn package(mut self, package: Package) -> Self {
+        self.options.package = package;
+        self
+    }
+
+    pub fn flash_base(mut self, base: u32) -> Self {
+        self.options.flash_base = base;
+        self
+    }
+
+    /// The options, once checked against each other
+    pub fn build(self) -> Result<CompileOptions, String> {
+        self.options.check()?;
+        Ok(self.options)
+    }
+}
@@ -0,0 +1,251 @@
+// Output packaging
+// Wraps a ROM image in the file format a programmer expects: raw binary,
+// Intel HEX, Motorola S-records or UF2, optionally placed at a flash offset,
+// or a WAV to play into a cassette loader, and merges compiled code into an
+// existing ROM such as a monitor
+
+#[cfg(feature = "config")]
+use serde::{Deserialize, Serialize};
+
+/// Output file format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum Package {
+    #[default]
+    Bin,   // Raw image, padded with 0xFF up to the flash base
+    Hex,   // Intel HEX
+    Srec,  // Motorola S-records
+    Uf2,   // USB flashing format, 256-byte payload blocks
+    Wav(Tape),  // FSK audio for a cassette interface
+}
+
+/// How a `--package wav` image is played to the cassette interface:
+/// Kansas City Standard tones, 1200Hz for a 0 and 2400Hz for a 1, each byte
+/// framed by a start bit and two stop bits, after a leader of 1s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default, deny_unknown_fields, rename_all = "kebab-case"))]
+pub struct Tape {
+    pub baud: u32,       // 300 (Kansas City), 600 or 1200 (CUTS)
+    pub leader_ms: u32,  // Mark tone for the loader to lock on to first
+}
+
+impl Default for Tape {
+    fn default() -> Tape {
+        Tape { baud: 300, leader_ms: 2000 }
+    }
+}
+
+// Bytes per Intel HEX / S-record data record
+const RECORD_LEN: usize = 16;
+// Largest flash base a raw binary is padded out to
+const MAX_BIN_PADDING: u32 = 0x100_0000;
+
+// UF2 block layout
+const UF2_MAGIC_START0: u32 = 0x0A32_4655;
+const UF2_MAGIC_START1: u32 = 0x9E5D_5157;
+const UF2_MAGIC_END: u32 = 0x0AB1_6F30;
+const UF2_BLOCK: usize = 512;
+const UF2_PAYLOAD: usize = 256;
+
+// WAV samples: 8-bit unsigned mono, at a rate with a whole number of
+// samples in each half cycle of both tones
+const WAV_RATE: u32 = 48_000;
+const SPACE_HZ: u32 = 1200;  // A 0 bit
+const MARK_HZ: u32 = 2400;   // A 1 bit, and the leader
+const WAV_HIGH: u8 = 0xE0;
+const WAV_LOW: u8 = 0x20;
+
+impl Package {
+    /// Look up a format by its `--package` name
+    pub fn from_name(name: &str) -> Option<Package> {
+        match name {
+            "bin" => Some(Package::Bin),
+            "hex" => Some(Package::Hex),
+            "srec" => Some(Package::Srec),
+            "uf2" => Some(Package::Uf2),
+            "wav" => Some(Package::Wav(Tape::default())),
+            _ => None,
+        }
+    }
+
+    /// Default file extension for the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Package::Bin => "bin",
+            Package::Hex => "hex",
+            Package::Srec => "srec",
+            Package::Uf2 => "uf2",
+            Package::Wav(_) => "wav",
+        }
+    }
+
+    /// Package an image that is to appear at `flash_base` in the
+    /// programmer's address space
+    pub fn write(self, image: &[u8], flash_base: u32) -> Result<Vec<u8>, String> {
+        if flash_base as u64 + image.len() as u64 > 1 << 32 {
+            return Err(format!("image does not fit above flash base {:X}", flash_base));
+        }
+        Ok(match self {
+            Package::Bin if flash_base > MAX_BIN_PADDING => {
+                return Err(format!("flash base {:X} is too high for a raw binary; use hex, srec or uf2", flash_base));
+            }
+            Package::Bin => {
+                let mut out = vec![0xFF; flash_base as usize];
+                out.extend_from_slice(image);
+                out
+            }
+            Package::Hex => intel_hex(image, flash_base).into_bytes(),
+            Package::Srec => srec(image, flash_base).into_bytes(),
+            Package::Uf2 => uf2(image, flash_base),
+            Package::Wav(_) if flash_base != 0 => {
+                return Err("a tape has no flash base; the loader picks where the image goes".to_string());
+            }
+            Package::Wav(tape) => tape.wav(image)?,
+        })
+    }
+}
+
+/// Place `code` at `at` inside an existing ROM image, padding with 0xFF
+/// (erased EPROM) to at least `size` bytes. The base ROM must be blank,
+/// 0x00 or 0xFF, wherever the code lands.
+pub fn merge(base: &[u8], code: &[u8], at: u16, size: usize) -> Result<Vec<u8>, String> {
+    let start = at as usize;
+    let end = start + code.len();
+    let used = base.iter().enumerate().skip(start).take(code.len()).find(|(_, &b)| b != 0x00 && b != 0xFF);
+    if let Some((addr, _)) = used {
+        return Err(format!(
+            "base ROM has data at {:04X}, inside the compiled code at {:04X}-{:04X}",
+            addr, start, end - 1
+        ));
+    }
+    let mut out = base.to_vec();
+    out.resize(out.len().max(end).max(size), 0xFF);
+    out[start..end].copy_from_slice(code);
+    Ok(out)
+}
+
+/// Intel HEX with extended linear address records above 64KB
+fn intel_hex(image: &[u8], base: u32) -> String {
+    let mut out = String::new();
+    let mut upper = 0;
+    for (n, chunk) in image.chunks(RECORD_LEN).enumerate() {
+        let addr = base + (n * RECORD_LEN) as u32;
+        if addr >> 16 != upper {
+            upper = addr >> 16;
+            out += &hex_record(0, 0x04, &(upper as u16).to_be_bytes());
+        }
+        out += &hex_record(addr as u16, 0x00, chunk);
+    }
+    out += &hex_record(0, 0x01, &[]);
+    out
+}
+
+fn hex_record(addr: u16, kind: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&addr.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(data);
+    let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+    bytes.push(checksum);
+    format!(":{}\r\n", to_hex(&bytes))
+}
+
+/// Motorola S-records, using the narrowest address width that fits
+fn srec(image: &[u8], base: u32) -> String {
+    let top = base as u64 + image.len() as u64;
+    let (data, end, width) = match top {
+        t if t <= 0x1_0000 => (1, 9, 2),
+        t if t <= 0x100_0000 => (2, 8, 3),
+        _ => (3, 7, 4),
+    };
+    let mut out = srec_record(0, 0, 2, b"kz80_chip8");
+    for (n, chunk) in image.chunks(RECORD_LEN).enumerate() {
+        out += &srec_record(data, base + (n * RECORD_LEN) as u32, width, chunk);
+    }
+    out += &srec_record(end, 0, width, &[]);
+    out
+}
+
+fn srec_record(kind: u8, addr: u32, width: usize, data: &[u8]) -> String {
+    let mut bytes = vec![(width + data.len() + 1) as u8];
+    bytes.extend_from_slice(&addr.to_be_bytes()[4 - width..]);
+    bytes.extend_from_slice(data);
+    let checksum = !bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes.push(checksum);
+    format!("S{}{}\r\n", kind, to_hex(&bytes))
+}
+
+/// UF2: one 512-byte block per 256 bytes of image, no family ID
+fn uf2(image: &[u8], base: u32) -> Vec<u8> {
+    let blocks = (image.len() + UF2_PAYLOAD - 1) / UF2_PAYLOAD;
+    let mut out = Vec::with_capacity(blocks * UF2_BLOCK);
+    for (n, chunk) in image.chunks(UF2_PAYLOAD).enumerate() {
+        let header = [
+            UF2_MAGIC_START0,
+            UF2_MAGIC_START1,
+            0,  // Flags
+            base + (n * UF2_PAYLOAD) as u32,
+            UF2_PAYLOAD as u32,
+            n as u32,
+            blocks as u32,
+            0,  // File size / family ID
+        ];
+        let start = out.len();
+        for word in header {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        out.extend_from_slice(chunk);
+        out.resize(start + UF2_BLOCK - 4, 0);
+        out.extend_from_slice(&UF2_MAGIC_END.to_le_bytes());
+    }
+    out
+}
+
+impl Tape {
+    /// The image as a WAV file of tones
+    pub fn wav(&self, image: &[u8]) -> Result<Vec<u8>, String> {
+        if !matches!(self.baud, 300 | 600 | 1200) {
+            return Err(format!("tape baud {} isn't 300, 600 or 1200", self.baud));
+        }
+        let bit = (WAV_RATE / self.baud) as usize;
+        // Whole cycles of leader, so the first start bit begins on one
+        let cycle = (WAV_RATE / MARK_HZ) as usize;
+        let leader = WAV_RATE as usize * self.leader_ms as usize / 1000 / cycle * cycle;
+        let mut samples = Vec::with_capacity(leader + image.len() * 11 * bit);
+        tone(&mut samples, MARK_HZ, leader);
+        for &byte in image {
+            // Start bit, 8 data bits from bit 0, two stop bits
+            let bits = 0b11 << 9 | (byte as u16) << 1;
+            for n in 0..11 {
+                tone(&mut samples, if bits >> n & 1 != 0 { MARK_HZ } else { SPACE_HZ }, bit);
+            }
+        }
+        let mut out = Vec::with_capacity(44 + samples.len());
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());  // PCM
+        out.extend_from_slice(&1u16.to_le_bytes());  // Mono
+        out.extend_from_slice(&WAV_RATE.to_le_bytes());
+        out.extend_from_slice(&WAV_RATE.to_le_bytes());  // Bytes per second
+        out.extend_from_slice(&1u16.to_le_bytes());  // Bytes per sample
+        out.extend_from_slice(&8u16.to_le_bytes());  // Bits per sample
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+        out.extend_from_slice(&samples);
+        Ok(out)
+    }
+}
+
+/// `len` samples of a square wave at `hz`, starting high
+fn tone(samples: &mut Vec<u8>, hz: u32, len: usize) {
+    let half = (WAV_RATE / hz / 2) as usize;
+    samples.extend((0..len).map(|n| if n / half % 2 == 0 { WAV_HIGH } else { WAV_LOW }));
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
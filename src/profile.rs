@@ -0,0 +1,110 @@
+// Execution profiles
+// How often each block of a CHIP-8 program runs, measured on a target or
+// estimated from its loops, used to lay out the hot code first
+
+use crate::chip8::Instruction;
+#[cfg(feature = "config")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Profile file format version written to and expected in `counters.json`
+pub const FORMAT_VERSION: u64 = 1;
+
+// Loop nesting depth beyond which an estimate stops growing
+const MAX_LOOP_DEPTH: u32 = 6;
+
+/// Execution counts keyed by CHIP-8 address
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+pub struct Profile {
+    pub counts: BTreeMap<u16, u64>,  // Block start -> times entered
+}
+
+impl Profile {
+    /// Parse a counters file:
+    /// `{"version": 1, "blocks": {"0x200": 1, "0x2A4": 5120}}`
+    #[cfg(feature = "config")]
+    pub fn from_json(text: &str) -> Result<Profile, String> {
+        let value: serde_json::Value =
+            serde_json::from_str(text).map_err(|e| format!("invalid profile: {}", e))?;
+        match value.get("version").and_then(|v| v.as_u64()) {
+            Some(FORMAT_VERSION) => {}
+            Some(v) => return Err(format!("unsupported profile version {}", v)),
+            None => return Err("profile has no version".to_string()),
+        }
+        let blocks = value
+            .get("blocks")
+            .and_then(|b| b.as_object())
+            .ok_or("profile has no blocks")?;
+        let mut counts = BTreeMap::new();
+        for (addr, count) in blocks {
+            let digits = addr.trim_start_matches("0x").trim_start_matches("0X");
+            let addr = u16::from_str_radix(digits, 16)
+                .map_err(|_| format!("invalid block address '{}' in profile", addr))?;
+            let count = count
+                .as_u64()
+                .ok_or_else(|| format!("invalid count for block {:03X} in profile", addr))?;
+            counts.insert(addr, count);
+        }
+        Ok(Profile { counts })
+    }
+
+    /// Collect the counters from a serial capture of a `--profile-gen`
+    /// build, which prints `P <block> <count>` lines (in hex) when sent
+    /// '!'. Later dumps replace earlier ones, as the counters only grow.
+    pub fn from_dump(text: &str) -> Result<Profile, String> {
+        let mut counts = BTreeMap::new();
+        for line in text.lines() {
+            let mut fields = line.split_whitespace();
+            if fields.next() != Some("P") {
+                continue;
+            }
+            let (Some(addr), Some(count)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            if let (Ok(addr), Ok(count)) = (u16::from_str_radix(addr, 16), u64::from_str_radix(count, 16)) {
+                counts.insert(addr, count);
+            }
+        }
+        if counts.is_empty() {
+            return Err("no profile counters found (send '!' to a --profile-gen build)".to_string());
+        }
+        Ok(Profile { counts })
+    }
+
+    /// Serialize in the format read by `from_json`
+    #[cfg(feature = "config")]
+    pub fn to_json(&self) -> String {
+        let blocks: serde_json::Map<String, serde_json::Value> = self
+            .counts
+            .iter()
+            .map(|(addr, count)| (format!("0x{:03X}", addr), (*count).into()))
+            .collect();
+        let value = serde_json::json!({ "version": FORMAT_VERSION, "blocks": blocks });
+        serde_json::to_string_pretty(&value).unwrap_or_default()
+    }
+
+    /// Estimate counts without running the program: every backward jump
+    /// closes a loop, and code nested n loops deep counts as 10^n
+    pub fn estimate(instructions: &[Instruction]) -> Profile {
+        let loops: Vec<(u16, u16)> = instructions
+            .iter()
+            .filter(|inst| inst.opcode >> 12 == 0x1 && inst.nnn() <= inst.addr)
+            .map(|inst| (inst.nnn(), inst.addr))
+            .collect();
+        let counts = instructions
+            .iter()
+            .map(|inst| {
+                let depth = loops.iter().filter(|(start, end)| (*start..=*end).contains(&inst.addr)).count();
+                (inst.addr, 10u64.pow(depth.min(MAX_LOOP_DEPTH as usize) as u32))
+            })
+            .collect();
+        Profile { counts }
+    }
+
+    /// Count for a block: the largest count recorded for any of its
+    /// instructions, so profiles taken with other block boundaries still apply
+    pub fn block_count(&self, block: &[Instruction]) -> u64 {
+        block.iter().filter_map(|inst| self.counts.get(&inst.addr)).copied().max().unwrap_or(0)
+    }
+}
@@ -0,0 +1,90 @@
+// CHIP-8 quirk flags
+// Different interpreter eras disagree on a few opcodes; these flags pick
+// which semantics the compiler emits
+
+#[cfg(feature = "config")]
+use serde::{Deserialize, Serialize};
+
+/// Selectable opcode semantics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default, rename_all = "kebab-case"))]
+pub struct Quirks {
+    /// 8XY6/8XYE shift Vy into Vx (COSMAC VIP) instead of shifting Vx
+    pub shift_vy: bool,
+    /// FX55/FX65 leave I pointing past the last register transferred
+    pub load_store_increment: bool,
+    /// BNNN jumps to NNN + VX (SUPER-CHIP BXNN) instead of NNN + V0
+    pub jump_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// I addresses 0xF00-0xFFF reach the display buffer, as on the COSMAC VIP
+    pub display_ram: bool,
+    /// Sprites running off the right or bottom edge are clipped (COSMAC VIP,
+    /// SUPER-CHIP) instead of wrapping to the other side
+    pub clip: bool,
+}
+
+impl Quirks {
+    /// Apply one `--quirk` argument: `shift=y|x`, `load-store-increment`,
+    /// `jump-v0=vx|v0`, `vf-reset`, `display-ram` or `clip` (`wrap` for
+    /// clip=off), optionally as `name=on|off`
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        let (name, value) = match spec.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (spec, None),
+        };
+        let flag = |value: Option<&str>| match value {
+            None | Some("on") | Some("y") | Some("yes") | Some("true") => Ok(true),
+            Some("off") | Some("n") | Some("no") | Some("false") => Ok(false),
+            Some(v) => Err(format!("invalid value '{}' for quirk '{}'", v, name)),
+        };
+        match name {
+            "shift" => {
+                self.shift_vy = match value {
+                    Some("y") | Some("vy") => true,
+                    Some("x") | Some("vx") => false,
+                    _ => return Err("quirk 'shift' expects shift=y or shift=x".to_string()),
+                };
+            }
+            "jump-v0" | "jump" => {
+                self.jump_vx = match value {
+                    Some("vx") => true,
+                    Some("v0") => false,
+                    _ => return Err(format!("quirk '{}' expects {}=vx or {}=v0", name, name, name)),
+                };
+            }
+            "load-store-increment" | "memory" => self.load_store_increment = flag(value)?,
+            "vf-reset" => self.vf_reset = flag(value)?,
+            "display-ram" => self.display_ram = flag(value)?,
+            "clip" => self.clip = flag(value)?,
+            "wrap" => self.clip = !flag(value)?,
+            _ => return Err(format!("unknown quirk '{}'", name)),
+        }
+        Ok(())
+    }
+
+    /// Non-default quirks, in `--quirk` syntax
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        if self.shift_vy {
+            names.push("shift=y".to_string());
+        }
+        if self.load_store_increment {
+            names.push("load-store-increment".to_string());
+        }
+        if self.jump_vx {
+            names.push("jump-v0=vx".to_string());
+        }
+        if self.vf_reset {
+            names.push("vf-reset".to_string());
+        }
+        if self.display_ram {
+            names.push("display-ram".to_string());
+        }
+        if self.clip {
+            names.push("clip".to_string());
+        }
+        names
+    }
+}
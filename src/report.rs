@@ -0,0 +1,136 @@
+// Batch progress and summaries
+// Commands that work through many ROMs print a status line for each as it
+// finishes and a table of them all at the end, coloured on a terminal
+
+use std::io::IsTerminal;
+
+/// How one ROM of a batch went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Blessed,
+    Failed,
+}
+
+impl Status {
+    fn word(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Blessed => "blessed",
+            Status::Failed => "FAILED",
+        }
+    }
+
+    /// ANSI colour of the status word
+    fn color(self) -> &'static str {
+        match self {
+            Status::Ok => "32",       // Green
+            Status::Blessed => "36",  // Cyan
+            Status::Failed => "31",   // Red
+        }
+    }
+}
+
+/// One ROM's result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub status: Status,
+    pub bytes: usize,          // Compiled code
+    pub warnings: usize,
+    pub note: Option<String>,  // Why it failed, say
+}
+
+/// Results of a batch, added as each ROM finishes
+pub struct Report {
+    total: usize,
+    color: bool,
+    entries: Vec<Entry>,
+}
+
+/// Colour output unless asked not to, told not to by NO_COLOR, or not
+/// writing to a terminal
+pub fn use_color(no_color: bool) -> bool {
+    !no_color && std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+}
+
+fn plural(count: usize, word: &str) -> String {
+    format!("{} {}{}", count, word, if count == 1 { "" } else { "s" })
+}
+
+impl Report {
+    /// Report on a batch of `total` ROMs
+    pub fn new(total: usize, color: bool) -> Report {
+        Report { total, color, entries: Vec::new() }
+    }
+
+    /// Status word padded to `width`, coloured if wanted
+    fn status(&self, status: Status, width: usize) -> String {
+        let word = format!("{:<width$}", status.word(), width = width);
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", status.color(), word)
+        } else {
+            word
+        }
+    }
+
+    /// Record a ROM's result, returning its progress line:
+    /// `[ 3/12] ok      pong.ch8 (3005 bytes, 1 warning)`
+    pub fn add(&mut self, entry: Entry) -> String {
+        let width = self.total.to_string().len();
+        let mut line = format!(
+            "[{:>width$}/{}] {} {} ({} bytes",
+            self.entries.len() + 1,
+            self.total,
+            self.status(entry.status, 7),
+            entry.name,
+            entry.bytes,
+            width = width
+        );
+        if entry.warnings > 0 {
+            line += &format!(", {}", plural(entry.warnings, "warning"));
+        }
+        line += ")";
+        if let Some(note) = &entry.note {
+            line += &format!(": {}", note);
+        }
+        self.entries.push(entry);
+        line
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn failed(&self) -> usize {
+        self.entries.iter().filter(|entry| entry.status == Status::Failed).count()
+    }
+
+    /// Table of every ROM so far, then the totals
+    pub fn summary(&self) -> String {
+        let name_width = self.entries.iter().map(|entry| entry.name.len()).max().unwrap_or(0).max(3);
+        let mut out = format!("{:<name_width$}  {:<7}  {:>6}  {:>8}\n", "ROM", "Result", "Bytes", "Warnings", name_width = name_width);
+        for entry in &self.entries {
+            out += &format!(
+                "{:<name_width$}  {}  {:>6}  {:>8}\n",
+                entry.name,
+                self.status(entry.status, 7),
+                entry.bytes,
+                entry.warnings,
+                name_width = name_width
+            );
+        }
+        let failed = self.failed();
+        let bytes: usize = self.entries.iter().map(|entry| entry.bytes).sum();
+        let warnings: usize = self.entries.iter().map(|entry| entry.warnings).sum();
+        out += &format!(
+            "{}: {} passed, {} failed, {}, {} bytes\n",
+            plural(self.entries.len(), "ROM"),
+            self.entries.len() - failed,
+            failed,
+            plural(warnings, "warning"),
+            bytes
+        );
+        out
+    }
+}
@@ -0,0 +1,149 @@
+// Sega Master System cartridges
+// A 32KB ROM carrying the header the export BIOS checks before it starts
+// the game, the controller mapping, and the text font the runtime uploads
+
+#[cfg(feature = "config")]
+use serde::{Deserialize, Serialize};
+
+/// Size of the cartridge: two 16KB banks, with no paging needed
+pub const ROM_SIZE: usize = 0x8000;
+/// Header at the end of the first 32KB: "TMR SEGA", checksum, region
+pub const HEADER: usize = 0x7FF0;
+const REGION_SIZE: u8 = 0x4C;  // SMS export, checksum over 32KB
+
+/// Controller buttons in the order of their bits in port 0xDC
+pub const BUTTONS: [&str; 6] = ["up", "down", "left", "right", "1", "2"];
+
+/// CHIP-8 key of each controller button, in BUTTONS order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+pub struct PadMap {
+    pub keys: [Option<u8>; 6],
+}
+
+impl Default for PadMap {
+    /// The d-pad on 5/7/8/9 and the buttons on 6 and 4, where most
+    /// keyboard layouts put WASD, E and Q
+    fn default() -> Self {
+        PadMap { keys: [Some(0x5), Some(0x8), Some(0x7), Some(0x9), Some(0x6), Some(0x4)] }
+    }
+}
+
+impl PadMap {
+    /// Read a mapping file: `BUTTON = KEY` lines, with the key a hex
+    /// digit or `none`. Buttons it leaves out keep their default keys.
+    pub fn parse(text: &str) -> Result<PadMap, String> {
+        let mut map = PadMap::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((button, key)) = line.split_once('=') else {
+                return Err(format!("line {}: expected BUTTON = KEY", n + 1));
+            };
+            let (button, key) = (button.trim().to_ascii_lowercase(), key.trim());
+            let button = button.strip_prefix("button").unwrap_or(&button);
+            let Some(index) = BUTTONS.iter().position(|&name| name == button) else {
+                return Err(format!("line {}: unknown button '{}' (expected up, down, left, right, 1 or 2)", n + 1, button));
+            };
+            map.keys[index] = match key {
+                "none" => None,
+                _ => match u8::from_str_radix(key, 16) {
+                    Ok(key) if key < 16 => Some(key),
+                    _ => return Err(format!("line {}: bad key '{}' (expected 0-F or none)", n + 1, key)),
+                },
+            };
+        }
+        Ok(map)
+    }
+}
+
+/// Cartridge image of the code linked at 0x0000, with the header. The
+/// code must end below HEADER.
+pub fn rom(code: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0xFF; ROM_SIZE];
+    rom[..code.len()].copy_from_slice(code);
+    rom[HEADER..HEADER + 8].copy_from_slice(b"TMR SEGA");
+    rom[HEADER + 8..HEADER + 10].fill(0);
+    let checksum = rom[..HEADER].iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+    rom[HEADER + 10..HEADER + 12].copy_from_slice(&checksum.to_le_bytes());
+    rom[HEADER + 12..HEADER + 15].fill(0);  // Product code and version
+    rom[HEADER + 15] = REGION_SIZE;
+    rom
+}
+
+/// Text font for characters 0x20-0x5F, 3x5 pixels, top row in the high bits
+pub const FONT: [u16; 64] = [
+    0b000_000_000_000_000,  // space
+    0b010_010_010_000_010,  // !
+    0b101_101_000_000_000,  // "
+    0b101_111_101_111_101,  // #
+    0b011_110_010_011_110,  // $
+    0b101_001_010_100_101,  // %
+    0b010_101_010_101_011,  // &
+    0b010_010_000_000_000,  // '
+    0b001_010_010_010_001,  // (
+    0b100_010_010_010_100,  // )
+    0b000_101_010_101_000,  // *
+    0b000_010_111_010_000,  // +
+    0b000_000_000_010_100,  // ,
+    0b000_000_111_000_000,  // -
+    0b000_000_000_000_010,  // .
+    0b001_001_010_100_100,  // /
+    0b111_101_101_101_111,  // 0
+    0b010_110_010_010_111,  // 1
+    0b111_001_111_100_111,  // 2
+    0b111_001_111_001_111,  // 3
+    0b101_101_111_001_001,  // 4
+    0b111_100_111_001_111,  // 5
+    0b111_100_111_101_111,  // 6
+    0b111_001_001_001_001,  // 7
+    0b111_101_111_101_111,  // 8
+    0b111_101_111_001_111,  // 9
+    0b000_010_000_010_000,  // :
+    0b000_010_000_010_100,  // ;
+    0b001_010_100_010_001,  // <
+    0b000_111_000_111_000,  // =
+    0b100_010_001_010_100,  // >
+    0b111_001_011_000_010,  // ?
+    0b010_101_111_100_011,  // @
+    0b010_101_111_101_101,  // A
+    0b110_101_110_101_110,  // B
+    0b011_100_100_100_011,  // C
+    0b110_101_101_101_110,  // D
+    0b111_100_110_100_111,  // E
+    0b111_100_110_100_100,  // F
+    0b011_100_101_101_011,  // G
+    0b101_101_111_101_101,  // H
+    0b111_010_010_010_111,  // I
+    0b001_001_001_101_010,  // J
+    0b101_101_110_101_101,  // K
+    0b100_100_100_100_111,  // L
+    0b101_111_111_101_101,  // M
+    0b110_101_101_101_101,  // N
+    0b010_101_101_101_010,  // O
+    0b110_101_110_100_100,  // P
+    0b010_101_101_110_011,  // Q
+    0b110_101_110_101_101,  // R
+    0b011_100_010_001_110,  // S
+    0b111_010_010_010_010,  // T
+    0b101_101_101_101_111,  // U
+    0b101_101_101_101_010,  // V
+    0b101_101_111_111_101,  // W
+    0b101_101_010_101_101,  // X
+    0b101_101_010_010_010,  // Y
+    0b111_001_010_100_111,  // Z
+    0b011_010_010_010_011,  // [
+    0b100_100_010_001_001,  // \
+    0b110_010_010_010_110,  // ]
+    0b010_101_000_000_000,  // ^
+    0b000_000_000_000_111,  // _
+];
+
+/// Row (0-4) of a glyph, each pixel doubled in width, as one tile line
+pub fn font_row(glyph: u16, row: usize) -> u8 {
+    let bits = (glyph >> (12 - 3 * row)) & 7;
+    let wide = (0..3).fold(0u8, |wide, bit| if bits & (4 >> bit) != 0 { wide | (0xC0 >> (2 * bit)) } else { wide });
+    wide >> 1
+}
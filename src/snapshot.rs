@@ -0,0 +1,34 @@
+// CHIP-8 memory images
+// Rebuilds the 4KB CHIP-8 address space from a machine running compiled
+// code, as the raw image other CHIP-8 tools load at address 0
+
+use crate::codegen::{CHIP8_RAM, DISPLAY_BUF, FONT_DATA};
+use crate::z80emu::Z80;
+
+/// Bytes in an image: the COSMAC VIP's 4KB
+pub const IMAGE_SIZE: usize = 0x1000;
+
+const FONT_SIZE: usize = 80;       // 16 characters of 5 bytes, at 0x000
+const DISPLAY_AT: usize = 0xF00;   // The VIP's display page, 256 bytes
+
+/// CHIP-8 memory of a machine that was started with `rom`: the font at
+/// 0x000, the ROM at 0x200 with RAM over it, and the display at 0xF00.
+/// A build copies the ROM into RAM unless nothing reads it there, so a RAM
+/// byte replaces the ROM's where it is non-zero.
+pub fn memory_image(cpu: &Z80, rom: &[u8]) -> Vec<u8> {
+    let mut image = vec![0u8; IMAGE_SIZE];
+    let font = FONT_DATA as usize;
+    image[..FONT_SIZE].copy_from_slice(&cpu.mem[font..font + FONT_SIZE]);
+    let rom = &rom[..rom.len().min(DISPLAY_AT - 0x200)];
+    image[0x200..0x200 + rom.len()].copy_from_slice(rom);
+    let ram = CHIP8_RAM as usize - 0x200;
+    for (addr, byte) in image.iter_mut().enumerate().take(DISPLAY_AT).skip(0x200) {
+        let stored = cpu.mem[ram + addr];
+        if stored != 0 || addr >= 0x200 + rom.len() {
+            *byte = stored;
+        }
+    }
+    let display = DISPLAY_BUF as usize;
+    image[DISPLAY_AT..].copy_from_slice(&cpu.mem[display..display + IMAGE_SIZE - DISPLAY_AT]);
+    image
+}
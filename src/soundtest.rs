@@ -0,0 +1,203 @@
+// Sound driver test kit
+// A built-in ROM that runs the sound timer for known numbers of frames,
+// and a run of it in the emulator timing when the beeper or PSG goes on
+// and off, so a driver's beeps can be checked against what ST asked for
+
+use crate::codegen::{Audio, CompiledCode, Target, Timer, CHIP8_ST, FRAME_COUNT};
+use crate::options::CompileOptions;
+use crate::z80emu::{Ctc, StopReason, Z80};
+use std::fmt;
+
+/// The kit's CHIP-8 program: each beep of the table at 220 in turn, with
+/// DT waiting out the beep and GAP frames of silence after it
+pub const ROM: &[u8] = &[
+    0x62, 0x00, 0xA2, 0x20,  // 200: LD V2, 0 / LD I, 220
+    0xF2, 0x1E, 0xF0, 0x65,  // 204: ADD I, V2 / LD V0, [I]
+    0x30, 0x00, 0x12, 0x0E,  // 208: SE V0, 0 / JP 20E
+    0x00, 0xFD,              // 20C: EXIT
+    0xF0, 0x18, 0x70, GAP,   // 20E: LD ST, V0 / ADD V0, GAP
+    0xF0, 0x15, 0xF1, 0x07,  // 212: LD DT, V0 / LD V1, DT
+    0x31, 0x00, 0x12, 0x14,  // 216: SE V1, 0 / JP 214
+    0x72, 0x01, 0x12, 0x02,  // 21A: ADD V2, 1 / JP 202
+    0x00, 0x00,              // 21E: (pad)
+    1, 2, 3, 6, 15, 30, 0,   // 220: BEEPS, then the end
+];
+
+/// Frames of each beep, in order
+pub const BEEPS: &[u8] = &[1, 2, 3, 6, 15, 30];
+
+/// Frames of silence between beeps
+pub const GAP: u8 = 10;
+
+/// A sound output of the build, and how to tell it is sounding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Beeper { port: u8, mask: u8 },       // On while the bit is set
+    Psg { register: u8, data: u8 },      // On while tone A's level is non-zero
+}
+
+impl Output {
+    /// Outputs a build with `options` drives, beeper first
+    pub fn of(options: &CompileOptions) -> Vec<Output> {
+        let mut outputs = Vec::new();
+        if let Some((port, bit)) = options.beeper {
+            outputs.push(Output::Beeper { port, mask: 1 << (bit & 7) });
+        }
+        if let Some(Audio::Ay8910 { register, data }) = options.audio {
+            outputs.push(Output::Psg { register, data });
+        }
+        outputs
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Output::Beeper { port, mask } => write!(f, "beeper {:02X}:{}", port, mask.trailing_zeros()),
+            Output::Psg { register, data } => write!(f, "ay8910 {:02X},{:02X}", register, data),
+        }
+    }
+}
+
+/// A moment of the run: T-states from its start, and 60Hz frames the
+/// runtime has ticked by then
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct At {
+    pub cycles: u64,
+    pub frame: u32,
+}
+
+/// One beep as the emulator saw it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Beep {
+    pub output: Output,
+    pub frames: u8,       // ST as set
+    pub set: At,          // When LD ST set it
+    pub on: Option<At>,   // When the output went on, if it did
+    pub off: Option<At>,  // When it went off again, if it did
+}
+
+impl Beep {
+    /// Frame ticks from LD ST to the output going on: 1 when the driver
+    /// starts the beep at the first tick
+    pub fn latency(&self) -> Option<u32> {
+        Some(self.on?.frame - self.set.frame)
+    }
+
+    /// Frame ticks the output stayed on, ST's when the driver is right
+    pub fn length(&self) -> Option<u32> {
+        Some(self.off?.frame - self.on?.frame)
+    }
+
+    /// T-states from LD ST to the output going on, and it staying on
+    pub fn cycles(&self) -> Option<(u64, u64)> {
+        Some((self.on?.cycles - self.set.cycles, self.off?.cycles - self.on?.cycles))
+    }
+}
+
+/// The kit compiled with `options`
+pub fn build(options: &CompileOptions) -> Result<CompiledCode, String> {
+    if Output::of(options).is_empty() {
+        return Err("the sound test times a beeper (--beeper) or PSG (--audio ay8910); this build has neither".to_string());
+    }
+    let mut compiler = options.compiler();
+    compiler.set_name("sound-test");
+    compiler.compile_code(ROM)
+}
+
+/// Run the kit in the emulator and time each beep on each output
+pub fn measure(options: &CompileOptions) -> Result<Vec<Beep>, String> {
+    if !matches!(options.target, Target::RetroShield | Target::Rc2014) || options.memory_map.is_some() {
+        return Err(format!("the emulator times the sound test of a RetroShield or RC2014 build with its own memory, not a {} build", options.target.name()));
+    }
+    let code = build(options)?;
+    let clock_hz = options.clock_hz.unwrap_or(options.target.clock_hz());
+    let outputs = Output::of(options);
+    let mut cpu = Z80::new();
+    cpu.load(code.origin, &code.code);
+    cpu.pc = code.symbols.get("init").copied().unwrap_or(code.origin);
+    if let Timer::Ctc { port } = options.timer {
+        cpu.ctc = Some(Ctc::new(port));
+    }
+    let total: u32 = BEEPS.iter().map(|&beep| (2 * beep + GAP) as u32).sum();
+    let budget = 2 * total as u64 * clock_hz as u64 / 60;
+    let mut beeps: Vec<Beep> = Vec::new();
+    let mut sounding = vec![false; outputs.len()];
+    let mut selected = None;  // PSG register last selected
+    let mut logged = 0;
+    let mut st = 0;
+    let mut frame = 0;
+    let mut count = cpu.mem[FRAME_COUNT as usize];
+    while cpu.cycles < budget {
+        if cpu.run(1, None) == StopReason::Halted {
+            break;
+        }
+        if cpu.mem[FRAME_COUNT as usize] != count {
+            count = cpu.mem[FRAME_COUNT as usize];
+            frame += 1;
+        }
+        let now = cpu.mem[CHIP8_ST as usize];
+        if now > st {
+            // Set by LD ST, not ticked: the kit only sets it from 0
+            let set = At { cycles: cpu.cycles, frame };
+            for &output in &outputs {
+                beeps.push(Beep { output, frames: now, set, on: None, off: None });
+            }
+        }
+        st = now;
+        for &(cycles, port, value) in &cpu.out_log[logged..] {
+            for (n, output) in outputs.iter().enumerate() {
+                let on = match *output {
+                    Output::Beeper { port: beeper, mask } if port == beeper => value & mask != 0,
+                    Output::Psg { register, .. } if port == register => {
+                        selected = Some(value);
+                        continue;
+                    }
+                    Output::Psg { data, .. } if port == data && selected == Some(8) => value != 0,
+                    _ => continue,
+                };
+                if on == sounding[n] {
+                    continue;
+                }
+                sounding[n] = on;
+                let Some(beep) = beeps.iter_mut().rev().find(|beep| beep.output == *output) else {
+                    return Err(format!("the {} went {} before the kit set ST", output, if on { "on" } else { "off" }));
+                };
+                if on && beep.on.is_none() {
+                    beep.on = Some(At { cycles, frame });
+                } else if !on && beep.on.is_some() && beep.off.is_none() {
+                    beep.off = Some(At { cycles, frame });
+                }
+            }
+        }
+        logged = cpu.out_log.len();
+    }
+    Ok(beeps)
+}
+
+/// What the timings show the driver getting wrong: a beep that never
+/// sounded or never stopped, started after the first frame tick, or
+/// lasted other than ST's frames
+pub fn problems(beeps: &[Beep]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for beep in beeps {
+        let name = format!("{} ST={}", beep.output, beep.frames);
+        match (beep.latency(), beep.length()) {
+            (None, _) => problems.push(format!("{}: never sounded", name)),
+            (Some(_), None) => problems.push(format!("{}: never stopped", name)),
+            (Some(latency), Some(length)) => {
+                if latency > 1 {
+                    problems.push(format!("{}: sounded {} frames after LD ST", name, latency));
+                }
+                if length != beep.frames as u32 {
+                    problems.push(format!("{}: sounded for {} frames", name, length));
+                }
+            }
+        }
+    }
+    let set = beeps.iter().filter(|beep| Some(beep.output) == beeps.first().map(|first| first.output)).count();
+    if set < BEEPS.len() {
+        problems.push(format!("the kit set ST {} of {} times", set, BEEPS.len()));
+    }
+    problems
+}
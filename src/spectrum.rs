@@ -0,0 +1,71 @@
+// ZX Spectrum tape images
+// A .tap file holding a one-line BASIC loader and the compiled code, so
+// that LOAD "" loads the game and starts it
+
+// Header block types
+const PROGRAM: u8 = 0;
+const CODE: u8 = 3;
+
+// BASIC tokens
+const CLEAR: u8 = 0xFD;
+const LOAD: u8 = 0xEF;
+const CODE_TOKEN: u8 = 0xAF;
+const RANDOMIZE: u8 = 0xF9;
+const USR: u8 = 0xC0;
+const NUMBER: u8 = 0x0E;  // Marks the hidden binary form of a number
+
+/// Tape image of the code linked at `origin`, loaded and started by
+/// `10 CLEAR origin-1: LOAD "" CODE : RANDOMIZE USR origin`
+pub fn tap(name: &str, code: &[u8], origin: u16) -> Vec<u8> {
+    let loader = basic_loader(origin);
+    let mut out = Vec::new();
+    out.extend(block(0x00, &header(PROGRAM, name, loader.len() as u16, 10, loader.len() as u16)));
+    out.extend(block(0xFF, &loader));
+    out.extend(block(0x00, &header(CODE, name, code.len() as u16, origin, 0x8000)));
+    out.extend(block(0xFF, code));
+    out
+}
+
+/// Line 10 of the loader program
+fn basic_loader(origin: u16) -> Vec<u8> {
+    let mut line = vec![CLEAR];
+    line.extend(number(origin - 1));
+    line.extend([b':', LOAD, b'"', b'"', CODE_TOKEN, b':', RANDOMIZE, USR]);
+    line.extend(number(origin));
+    line.push(b'\r');
+
+    let mut program = 10u16.to_be_bytes().to_vec();
+    program.extend((line.len() as u16).to_le_bytes());
+    program.extend(line);
+    program
+}
+
+/// A number as BASIC stores it: the digits, then the small integer form
+fn number(n: u16) -> Vec<u8> {
+    let mut out = n.to_string().into_bytes();
+    out.extend([NUMBER, 0, 0]);
+    out.extend(n.to_le_bytes());
+    out.push(0);
+    out
+}
+
+/// 17-byte tape header: type, 10-character name, length and two parameters
+fn header(kind: u8, name: &str, len: u16, param1: u16, param2: u16) -> Vec<u8> {
+    let mut out = vec![kind];
+    let mut padded: Vec<u8> = name.bytes().filter(|b| b.is_ascii_graphic() || *b == b' ').take(10).collect();
+    padded.resize(10, b' ');
+    out.extend(padded);
+    out.extend(len.to_le_bytes());
+    out.extend(param1.to_le_bytes());
+    out.extend(param2.to_le_bytes());
+    out
+}
+
+/// Tape block: length, flag byte, data and an XOR checksum
+fn block(flag: u8, data: &[u8]) -> Vec<u8> {
+    let mut out = ((data.len() + 2) as u16).to_le_bytes().to_vec();
+    out.push(flag);
+    out.extend_from_slice(data);
+    out.push(data.iter().fold(flag, |sum, b| sum ^ b));
+    out
+}
@@ -0,0 +1,163 @@
+// Step gate protocol
+// A --step-gate build stops at the start of each block until the host lets
+// it through; this is the host's side, used by the debug subcommand
+
+use crate::codegen::DEBUG_ESCAPE;
+
+/// Closes the gate of a running game at its next block (Ctrl-G)
+pub const STOP: u8 = 0x07;
+/// Lets a stopped game run one block, stopping at the next
+pub const STEP: u8 = b's';
+/// Opens the gate, letting the game run until stopped again
+pub const GO: u8 = b'g';
+
+/// The CHIP-8 state a block printed as it stopped at the gate:
+/// `Gate 0204`, then the monitor's `V ...` and `I ... DT .. ST .. SP ..`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stop {
+    pub addr: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub dt: u8,
+    pub st: u8,
+    pub sp: u8,
+}
+
+/// Hex of exactly `digits` digits
+fn hex(text: &str, digits: usize) -> Option<u16> {
+    if text.len() != digits {
+        return None;
+    }
+    u16::from_str_radix(text, 16).ok()
+}
+
+/// Length of the `I 0300 DT 00 ST 00 SP 01` line, which ends a stop
+/// without a line break until the game goes on
+const I_LINE: usize = 24;
+
+impl Stop {
+    /// Parse a stop from its three lines
+    fn parse(gate: &str, v: &str, i: &str) -> Option<Stop> {
+        let mut stop = Stop { addr: hex(gate.strip_prefix("Gate ")?, 4)?, v: [0; 16], i: 0, dt: 0, st: 0, sp: 0 };
+        let bytes: Vec<&str> = v.strip_prefix("V ")?.split(' ').collect();
+        if bytes.len() != 16 {
+            return None;
+        }
+        for (reg, byte) in stop.v.iter_mut().zip(bytes) {
+            *reg = hex(byte, 2)? as u8;
+        }
+        let fields: Vec<&str> = i.split(' ').collect();
+        match fields[..] {
+            ["I", i, "DT", dt, "ST", st, "SP", sp] => {
+                stop.i = hex(i, 4)?;
+                stop.dt = hex(dt, 2)? as u8;
+                stop.st = hex(st, 2)? as u8;
+                stop.sp = hex(sp, 2)? as u8;
+            }
+            _ => return None,
+        }
+        Some(stop)
+    }
+
+    /// Registers that differ from `before`, as `V4 02->03`
+    pub fn changes(&self, before: &Stop) -> Vec<String> {
+        let mut changes = Vec::new();
+        for (reg, (old, new)) in before.v.iter().zip(&self.v).enumerate() {
+            if old != new {
+                changes.push(format!("V{:X} {:02X}->{:02X}", reg, old, new));
+            }
+        }
+        if before.i != self.i {
+            changes.push(format!("I {:04X}->{:04X}", before.i, self.i));
+        }
+        for (name, old, new) in [("DT", before.dt, self.dt), ("ST", before.st, self.st), ("SP", before.sp, self.sp)] {
+            if old != new {
+                changes.push(format!("{} {:02X}->{:02X}", name, old, new));
+            }
+        }
+        changes
+    }
+}
+
+/// The console output of a gated game, picking out each stop as its
+/// lines come in
+#[derive(Default)]
+pub struct Session {
+    line: String,
+    lines: Vec<String>,  // The last three
+    last: Option<Stop>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// Add console output, returning a note for each stop it completes:
+    /// `stopped at 0204: V0 00->05, I 0000->0300`
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<String> {
+        let mut notes = Vec::new();
+        for &byte in bytes {
+            match byte {
+                b'\r' | b'\n' => self.end_line(&mut notes),
+                _ => {
+                    self.line.push(byte as char);
+                    if self.line.len() == I_LINE && self.line.starts_with("I ") {
+                        self.end_line(&mut notes);
+                    }
+                }
+            }
+        }
+        notes
+    }
+
+    fn end_line(&mut self, notes: &mut Vec<String>) {
+        if self.line.is_empty() {
+            return;
+        }
+        self.lines.push(std::mem::take(&mut self.line));
+        if self.lines.len() > 3 {
+            self.lines.remove(0);
+        }
+        let Some(stop) = (match &self.lines[..] {
+            [gate, v, i] => Stop::parse(gate, v, i),
+            _ => None,
+        }) else {
+            return;
+        };
+        let mut note = format!("stopped at {:04X}", stop.addr);
+        if let Some(last) = &self.last {
+            let changes = stop.changes(last);
+            if !changes.is_empty() {
+                note += &format!(": {}", changes.join(", "));
+            }
+        }
+        notes.push(note);
+        self.last = Some(stop);
+    }
+
+    /// The state at the latest stop
+    pub fn last(&self) -> Option<&Stop> {
+        self.last.as_ref()
+    }
+}
+
+/// Bytes to send for a line typed at the host: Enter or `s` steps, `g`
+/// goes, `stop` stops, `k KEY` presses a CHIP-8 key, and `b ADDR` toggles
+/// a breakpoint (`b` alone drops them all) through the monitor. None for
+/// `q`.
+pub fn command(line: &str) -> Result<Option<Vec<u8>>, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    Ok(Some(match words[..] {
+        [] | ["s"] | ["step"] => vec![STEP],
+        ["g"] | ["go"] => vec![GO],
+        ["stop"] => vec![STOP],
+        ["k", key] | ["key", key] if key.len() == 1 && key.as_bytes()[0].is_ascii_hexdigit() => key.as_bytes().to_vec(),
+        ["b"] | ["break"] => vec![DEBUG_ESCAPE, b'b', b'\r', b'c'],
+        ["b", addr] | ["break", addr] if (1..=3).contains(&addr.len()) && addr.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            [&[DEBUG_ESCAPE, b'b'], addr.as_bytes(), b"\rc"].concat()
+        }
+        ["q"] | ["quit"] => return Ok(None),
+        _ => return Err(format!("{}? s step, g go, stop, k KEY, b ADDR, q quit", line.trim())),
+    }))
+}
@@ -0,0 +1,86 @@
+// Compiler pass timing
+// Wall time and peak heap use of each compiler pass, for --time-passes.
+// Heap use is only known when the program counts its allocations by
+// installing CountingAlloc as its global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);  // Bytes allocated now
+static PEAK: AtomicUsize = AtomicUsize::new(0);     // Most since the last pass started
+static COUNTING: AtomicBool = AtomicBool::new(false);
+
+/// The system allocator, keeping count of the bytes allocated
+pub struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            added(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+            added(new_size);
+        }
+        new
+    }
+}
+
+fn added(size: usize) {
+    COUNTING.store(true, Ordering::Relaxed);
+    let now = CURRENT.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(now, Ordering::Relaxed);
+}
+
+/// Time and heap use of one pass, summed over its runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassTime {
+    pub name: &'static str,
+    pub runs: u32,                  // Jump relaxation generates the code twice
+    pub time: Duration,
+    pub peak_bytes: Option<usize>,  // Most allocated above the start, over all runs
+}
+
+/// Start of a pass being timed
+#[derive(Debug, Clone, Copy)]
+pub struct PassStart {
+    at: Instant,
+    heap: usize,
+}
+
+impl PassStart {
+    pub fn now() -> PassStart {
+        let heap = CURRENT.load(Ordering::Relaxed);
+        PEAK.store(heap, Ordering::Relaxed);
+        PassStart { at: Instant::now(), heap }
+    }
+}
+
+/// Add a finished pass to `passes`, merging it with earlier runs of the
+/// same pass. Passes must not overlap, as they share the peak count.
+pub fn record(passes: &mut Vec<PassTime>, name: &'static str, start: PassStart) {
+    let time = start.at.elapsed();
+    let peak_bytes = COUNTING
+        .load(Ordering::Relaxed)
+        .then(|| PEAK.load(Ordering::Relaxed).saturating_sub(start.heap));
+    match passes.iter_mut().find(|pass| pass.name == name) {
+        Some(pass) => {
+            pass.runs += 1;
+            pass.time += time;
+            pass.peak_bytes = pass.peak_bytes.max(peak_bytes);
+        }
+        None => passes.push(PassTime { name, runs: 1, time, peak_bytes }),
+    }
+}
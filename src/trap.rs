@@ -0,0 +1,87 @@
+// Runtime trap codes
+// Shared by the code generator (which emits the trap stubs) and the host
+// tools (which expand compact trap output back into messages)
+
+/// A fatal condition detected by the compiled runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    StackOverflow,
+    StackUnderflow,
+    BadIndex,
+    BadJump,
+}
+
+impl Trap {
+    /// Every trap, in code order
+    pub const ALL: &'static [Trap] = &[
+        Trap::StackOverflow,
+        Trap::StackUnderflow,
+        Trap::BadIndex,
+        Trap::BadJump,
+    ];
+
+    /// Numeric code printed in compact mode (`E01`, `E02`, ...)
+    pub fn code(self) -> u8 {
+        match self {
+            Trap::StackOverflow => 0x01,
+            Trap::StackUnderflow => 0x02,
+            Trap::BadIndex => 0x03,
+            Trap::BadJump => 0x04,
+        }
+    }
+
+    /// Runtime label of the trap stub
+    pub fn label(self) -> &'static str {
+        match self {
+            Trap::StackOverflow => "trap_stack_overflow",
+            Trap::StackUnderflow => "trap_stack_underflow",
+            Trap::BadIndex => "trap_bad_index",
+            Trap::BadJump => "trap_bad_jump",
+        }
+    }
+
+    /// Full message printed in the default mode
+    pub fn message(self) -> &'static str {
+        match self {
+            Trap::StackOverflow => "CHIP-8 stack overflow at",
+            Trap::StackUnderflow => "CHIP-8 stack underflow at",
+            Trap::BadIndex => "I out of range:",
+            Trap::BadJump => "Computed jump to non-code address",
+        }
+    }
+
+    /// Meaning of the hex value printed after the code
+    pub fn value(self) -> &'static str {
+        match self {
+            Trap::StackOverflow | Trap::StackUnderflow => "CHIP-8 address of the CALL/RET",
+            Trap::BadIndex => "value of I",
+            Trap::BadJump => "CHIP-8 jump target",
+        }
+    }
+
+    /// Whether the trap is taken in a runtime routine that the failing
+    /// instruction's code called, whose return address is then on the Z80
+    /// stack for a --symbol-table build to name
+    pub fn called(self) -> bool {
+        self == Trap::BadIndex
+    }
+
+    /// Look up a trap by its code, accepting `E01`, `01` or `1`
+    pub fn from_code(code: &str) -> Option<Trap> {
+        let digits = code.trim().trim_start_matches(['E', 'e']);
+        let value = u8::from_str_radix(digits, 16).ok()?;
+        Trap::ALL.iter().copied().find(|t| t.code() == value)
+    }
+}
+
+/// Expand a compact trap line (`E01 0234`, or `E03 1234 at 0206` from a
+/// --symbol-table build) into its full message
+pub fn explain(line: &str) -> Option<String> {
+    let mut parts = line.split_whitespace();
+    let trap = Trap::from_code(parts.next()?)?;
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(value), Some("at"), Some(site)) => Some(format!("{} {} ({}) at {}", trap.message(), value, trap.value(), site)),
+        (Some(value), _, _) => Some(format!("{} {} ({})", trap.message(), value, trap.value())),
+        (None, _, _) => Some(format!("{} <{}>", trap.message(), trap.value())),
+    }
+}
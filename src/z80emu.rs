@@ -0,0 +1,1193 @@
+// Z80 emulator for testing compiled output
+// CPU core with 64KB of memory, an ACIA (or SIO) stub on the console
+// ports, an optional CTC timer channel and a simple latch for every other
+// I/O port
+
+use std::collections::VecDeque;
+
+// Flag bits
+const FLAG_C: u8 = 0x01;
+const FLAG_N: u8 = 0x02;
+const FLAG_PV: u8 = 0x04;
+const FLAG_H: u8 = 0x10;
+const FLAG_Z: u8 = 0x40;
+const FLAG_S: u8 = 0x80;
+
+/// Why `run` returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// HALT executed with interrupts disabled
+    Halted,
+    /// Cycle budget used up
+    CycleLimit,
+    /// PC reached the requested breakpoint
+    Breakpoint,
+}
+
+/// One data load or store, as `access_log` records it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub pc: u16,      // Address of the instruction
+    pub addr: u16,
+    pub width: u8,    // 1, or 2 for a 16-bit load or store
+    pub write: bool,
+    pub isr: bool,    // Made by an interrupt handler
+    pub masked: bool, // Made with interrupts disabled
+}
+
+/// Channel 0 of a Z80 CTC in timer mode, raising an interrupt each time
+/// its count runs out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ctc {
+    pub port: u8,
+    pub vector: u8,            // Interrupt vector, as last written
+    pub fired: u64,            // Interrupts raised so far
+    control: u8,               // Channel control word, 0 until written
+    awaiting: bool,            // The next byte is a time constant
+    period: u64,               // T-states between interrupts, 0 while reset
+    next: u64,                 // Cycle of the next interrupt
+}
+
+impl Ctc {
+    pub fn new(port: u8) -> Self {
+        Self { port, vector: 0, fired: 0, control: 0x03, awaiting: false, period: 0, next: 0 }
+    }
+
+    /// Take a byte written to the channel's port at `cycles`
+    fn write(&mut self, value: u8, cycles: u64) {
+        if self.awaiting {
+            self.awaiting = false;
+            // A time constant, 0 meaning 256, which starts the count
+            let prescale = if self.control & 0x20 != 0 { 256 } else { 16 };
+            let constant = if value == 0 { 256 } else { value as u64 };
+            self.period = prescale * constant;
+            self.next = cycles + self.period;
+        } else if value & 0x01 != 0 {
+            self.control = value;
+            if value & 0x02 != 0 {
+                self.period = 0;
+            }
+            self.awaiting = value & 0x04 != 0;
+        } else {
+            self.vector = value & 0xF8;
+        }
+    }
+
+    /// Whether the count ran out by `cycles` with the interrupt enabled
+    fn due(&mut self, cycles: u64) -> bool {
+        if self.period == 0 || cycles < self.next {
+            return false;
+        }
+        while self.next <= cycles {
+            self.next += self.period;
+        }
+        if self.control & 0x80 == 0 {
+            return false;
+        }
+        self.fired += 1;
+        true
+    }
+}
+
+/// Z80 CPU, memory and I/O
+pub struct Z80 {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub alt: [u8; 8],  // A' F' B' C' D' E' H' L'
+    pub ix: u16,
+    pub iy: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub i: u8,
+    pub r: u8,
+    pub iff1: bool,
+    pub iff2: bool,
+    pub im: u8,
+    pub halted: bool,
+    pub cycles: u64,
+    pub mem: Vec<u8>,
+    /// Bytes waiting to be received by the ACIA
+    pub rx: VecDeque<u8>,
+    /// Bytes transmitted through the ACIA
+    pub tx: Vec<u8>,
+    /// Last value written to each output port
+    pub ports: [u8; 256],
+    /// Every OUT as (cycle, port, value)
+    pub out_log: Vec<(u64, u8, u8)>,
+    /// Every data load and store, when set to Some; instruction fetches
+    /// and stack pushes and pops aren't included
+    pub access_log: Option<Vec<Access>>,
+    /// Values returned by IN from non-ACIA ports
+    pub in_ports: [u8; 256],
+    /// ACIA control/status and data ports
+    pub acia_ctrl: u8,
+    pub acia_data: u8,
+    /// Raise an interrupt when the ACIA has received data (RX IRQ enabled)
+    pub acia_irq: bool,
+    /// Data bus value supplied on IM 2 / IM 0 interrupt acknowledge
+    pub int_vector: u8,
+    /// A CTC whose channel 0 raises IM 2 interrupts, when set to Some
+    pub ctc: Option<Ctc>,
+    int_pending: bool,
+    ei_delay: bool,
+    isr_depth: u8,  // Interrupts accepted and not yet returned from
+    op_pc: u16,     // Address of the instruction running
+    disp: Option<i8>,
+}
+
+impl Default for Z80 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Z80 {
+    pub fn new() -> Self {
+        Self {
+            a: 0xFF,
+            f: 0xFF,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            alt: [0; 8],
+            ix: 0,
+            iy: 0,
+            sp: 0xFFFF,
+            pc: 0,
+            i: 0,
+            r: 0,
+            iff1: false,
+            iff2: false,
+            im: 0,
+            halted: false,
+            cycles: 0,
+            mem: vec![0; 0x10000],
+            rx: VecDeque::new(),
+            tx: Vec::new(),
+            ports: [0; 256],
+            out_log: Vec::new(),
+            access_log: None,
+            in_ports: [0xFF; 256],
+            acia_ctrl: 0x80,
+            acia_data: 0x81,
+            acia_irq: false,
+            int_vector: 0xFF,
+            ctc: None,
+            int_pending: false,
+            ei_delay: false,
+            isr_depth: 0,
+            op_pc: 0,
+            disp: None,
+        }
+    }
+
+    /// Create a machine with `image` loaded at address 0
+    pub fn with_rom(image: &[u8]) -> Self {
+        let mut cpu = Self::new();
+        cpu.load(0, image);
+        cpu
+    }
+
+    /// Copy bytes into memory
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.mem[(addr as usize + i) & 0xFFFF] = *byte;
+        }
+    }
+
+    /// Queue bytes for the ACIA receiver
+    pub fn send(&mut self, data: &[u8]) {
+        self.rx.extend(data.iter().copied());
+    }
+
+    /// Everything transmitted so far, lossily decoded
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.tx).into_owned()
+    }
+
+    /// Request a maskable interrupt
+    pub fn interrupt(&mut self) {
+        self.int_pending = true;
+    }
+
+    /// Run until HALT, the cycle budget, or `stop_at`
+    pub fn run(&mut self, max_cycles: u64, stop_at: Option<u16>) -> StopReason {
+        let limit = self.cycles + max_cycles;
+        while self.cycles < limit {
+            if self.halted && !self.iff1 {
+                return StopReason::Halted;
+            }
+            self.step();
+            if Some(self.pc) == stop_at {
+                return StopReason::Breakpoint;
+            }
+        }
+        StopReason::CycleLimit
+    }
+
+    /// Call the subroutine at `addr` and run until it returns
+    pub fn call(&mut self, addr: u16, max_cycles: u64) -> StopReason {
+        const RETURN_TRAP: u16 = 0xFFFE;
+        self.sp = self.sp.wrapping_sub(2);
+        self.write16(self.sp, RETURN_TRAP);
+        self.pc = addr;
+        self.halted = false;
+        self.run(max_cycles, Some(RETURN_TRAP))
+    }
+
+    /// Execute one instruction (or one halted cycle), handling interrupts
+    pub fn step(&mut self) {
+        if self.acia_irq && !self.rx.is_empty() {
+            self.int_pending = true;
+        }
+        if let Some(ctc) = &mut self.ctc {
+            if ctc.due(self.cycles) {
+                self.int_pending = true;
+                self.int_vector = ctc.vector;
+            }
+        }
+        if self.int_pending && self.iff1 && !self.ei_delay {
+            self.int_pending = false;
+            self.accept_interrupt();
+            return;
+        }
+        self.ei_delay = false;
+        if self.halted {
+            self.cycles += 4;
+            return;
+        }
+        self.op_pc = self.pc;
+        let op = self.fetch();
+        self.execute(op);
+    }
+
+    fn accept_interrupt(&mut self) {
+        self.isr_depth = self.isr_depth.saturating_add(1);
+        self.halted = false;
+        self.iff1 = false;
+        self.iff2 = false;
+        match self.im {
+            2 => {
+                let vector = ((self.i as u16) << 8) | (self.int_vector & 0xFE) as u16;
+                let target = self.read16(vector);
+                self.push(self.pc);
+                self.pc = target;
+                self.cycles += 19;
+            }
+            _ => {
+                // IM 0 with RST 38 on the bus behaves like IM 1
+                self.push(self.pc);
+                self.pc = 0x0038;
+                self.cycles += 13;
+            }
+        }
+    }
+
+    // Memory access
+
+    pub fn read(&self, addr: u16) -> u8 {
+        self.mem[addr as usize]
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        self.mem[addr as usize] = value;
+    }
+
+    pub fn read16(&self, addr: u16) -> u16 {
+        (self.read(addr) as u16) | ((self.read(addr.wrapping_add(1)) as u16) << 8)
+    }
+
+    pub fn write16(&mut self, addr: u16, value: u16) {
+        self.write(addr, value as u8);
+        self.write(addr.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    fn data_read(&mut self, addr: u16) -> u8 {
+        self.log_access(addr, 1, false);
+        self.read(addr)
+    }
+
+    fn data_read16(&mut self, addr: u16) -> u16 {
+        self.log_access(addr, 2, false);
+        self.read16(addr)
+    }
+
+    fn data_write(&mut self, addr: u16, value: u8) {
+        self.log_access(addr, 1, true);
+        self.write(addr, value);
+    }
+
+    fn data_write16(&mut self, addr: u16, value: u16) {
+        self.log_access(addr, 2, true);
+        self.write16(addr, value);
+    }
+
+    fn log_access(&mut self, addr: u16, width: u8, write: bool) {
+        let (pc, isr, masked) = (self.op_pc, self.isr_depth > 0, !self.iff1);
+        if let Some(log) = &mut self.access_log {
+            log.push(Access { pc, addr, width, write, isr, masked });
+        }
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let v = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        self.r = (self.r & 0x80) | (self.r.wrapping_add(1) & 0x7F);
+        v
+    }
+
+    fn fetch16(&mut self) -> u16 {
+        let lo = self.fetch() as u16;
+        let hi = self.fetch() as u16;
+        lo | (hi << 8)
+    }
+
+    fn push(&mut self, value: u16) {
+        self.sp = self.sp.wrapping_sub(2);
+        self.write16(self.sp, value);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let v = self.read16(self.sp);
+        self.sp = self.sp.wrapping_add(2);
+        v
+    }
+
+    // I/O
+
+    fn port_in(&mut self, port: u8) -> u8 {
+        if port == self.acia_ctrl {
+            // Bit 0 = receive data full, bit 1 = transmit data empty; the
+            // SIO's RR0 has the same bit 0 and flags transmit empty in bit 2
+            let rdrf = if self.rx.is_empty() { 0 } else { 0x01 };
+            return rdrf | 0x02 | 0x04;
+        }
+        if port == self.acia_data {
+            return self.rx.pop_front().unwrap_or(0);
+        }
+        self.in_ports[port as usize]
+    }
+
+    fn port_out(&mut self, port: u8, value: u8) {
+        if port == self.acia_data {
+            self.tx.push(value);
+        } else if port == self.acia_ctrl {
+            // Bit 7 enables the receive interrupt
+            self.acia_irq = value & 0x80 != 0 && value & 0x03 != 0x03;
+        } else if let Some(ctc) = self.ctc.as_mut().filter(|ctc| ctc.port == port) {
+            ctc.write(value, self.cycles);
+        }
+        self.ports[port as usize] = value;
+        self.out_log.push((self.cycles, port, value));
+    }
+
+    // Register pairs
+
+    pub fn bc(&self) -> u16 {
+        ((self.b as u16) << 8) | self.c as u16
+    }
+
+    pub fn de(&self) -> u16 {
+        ((self.d as u16) << 8) | self.e as u16
+    }
+
+    pub fn hl(&self) -> u16 {
+        ((self.h as u16) << 8) | self.l as u16
+    }
+
+    pub fn af(&self) -> u16 {
+        ((self.a as u16) << 8) | self.f as u16
+    }
+
+    pub fn set_bc(&mut self, v: u16) {
+        self.b = (v >> 8) as u8;
+        self.c = v as u8;
+    }
+
+    pub fn set_de(&mut self, v: u16) {
+        self.d = (v >> 8) as u8;
+        self.e = v as u8;
+    }
+
+    pub fn set_hl(&mut self, v: u16) {
+        self.h = (v >> 8) as u8;
+        self.l = v as u8;
+    }
+
+    pub fn set_af(&mut self, v: u16) {
+        self.a = (v >> 8) as u8;
+        self.f = v as u8;
+    }
+
+    fn flag(&self, mask: u8) -> bool {
+        self.f & mask != 0
+    }
+
+    /// Register by 3-bit index (6 = (HL) or (IX+d))
+    fn get_r(&mut self, idx: u8, index: Index) -> u8 {
+        match idx {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => match index {
+                Index::Hl => self.h,
+                Index::Ix => (self.ix >> 8) as u8,
+                Index::Iy => (self.iy >> 8) as u8,
+            },
+            5 => match index {
+                Index::Hl => self.l,
+                Index::Ix => self.ix as u8,
+                Index::Iy => self.iy as u8,
+            },
+            6 => {
+                let addr = self.indirect_addr(index);
+                self.data_read(addr)
+            }
+            _ => self.a,
+        }
+    }
+
+    fn set_r(&mut self, idx: u8, index: Index, v: u8) {
+        match idx {
+            0 => self.b = v,
+            1 => self.c = v,
+            2 => self.d = v,
+            3 => self.e = v,
+            4 => match index {
+                Index::Hl => self.h = v,
+                Index::Ix => self.ix = (self.ix & 0x00FF) | ((v as u16) << 8),
+                Index::Iy => self.iy = (self.iy & 0x00FF) | ((v as u16) << 8),
+            },
+            5 => match index {
+                Index::Hl => self.l = v,
+                Index::Ix => self.ix = (self.ix & 0xFF00) | v as u16,
+                Index::Iy => self.iy = (self.iy & 0xFF00) | v as u16,
+            },
+            6 => {
+                let addr = self.indirect_addr(index);
+                self.data_write(addr, v);
+            }
+            _ => self.a = v,
+        }
+    }
+
+    /// Address of (HL) or (IX+d); the displacement is fetched once per
+    /// instruction and cached in `disp`
+    fn indirect_addr(&mut self, index: Index) -> u16 {
+        match index {
+            Index::Hl => self.hl(),
+            Index::Ix => self.ix.wrapping_add(self.displacement() as u16),
+            Index::Iy => self.iy.wrapping_add(self.displacement() as u16),
+        }
+    }
+
+    fn displacement(&mut self) -> i8 {
+        if let Some(d) = self.disp {
+            return d;
+        }
+        let d = self.fetch() as i8;
+        self.disp = Some(d);
+        d
+    }
+
+    fn get_rp(&self, p: u8, index: Index) -> u16 {
+        match p {
+            0 => self.bc(),
+            1 => self.de(),
+            2 => self.get_index(index),
+            _ => self.sp,
+        }
+    }
+
+    fn set_rp(&mut self, p: u8, index: Index, v: u16) {
+        match p {
+            0 => self.set_bc(v),
+            1 => self.set_de(v),
+            2 => self.set_index(index, v),
+            _ => self.sp = v,
+        }
+    }
+
+    fn get_rp2(&self, p: u8, index: Index) -> u16 {
+        if p == 3 { self.af() } else { self.get_rp(p, index) }
+    }
+
+    fn set_rp2(&mut self, p: u8, index: Index, v: u16) {
+        if p == 3 { self.set_af(v) } else { self.set_rp(p, index, v) }
+    }
+
+    fn get_index(&self, index: Index) -> u16 {
+        match index {
+            Index::Hl => self.hl(),
+            Index::Ix => self.ix,
+            Index::Iy => self.iy,
+        }
+    }
+
+    fn set_index(&mut self, index: Index, v: u16) {
+        match index {
+            Index::Hl => self.set_hl(v),
+            Index::Ix => self.ix = v,
+            Index::Iy => self.iy = v,
+        }
+    }
+
+    fn condition(&self, cc: u8) -> bool {
+        match cc {
+            0 => !self.flag(FLAG_Z),
+            1 => self.flag(FLAG_Z),
+            2 => !self.flag(FLAG_C),
+            3 => self.flag(FLAG_C),
+            4 => !self.flag(FLAG_PV),
+            5 => self.flag(FLAG_PV),
+            6 => !self.flag(FLAG_S),
+            _ => self.flag(FLAG_S),
+        }
+    }
+
+    // ALU
+
+    fn szp(v: u8) -> u8 {
+        let mut f = v & FLAG_S;
+        if v == 0 {
+            f |= FLAG_Z;
+        }
+        if v.count_ones() % 2 == 0 {
+            f |= FLAG_PV;
+        }
+        f
+    }
+
+    fn alu(&mut self, op: u8, v: u8) {
+        let a = self.a;
+        match op {
+            0 | 1 => {
+                // ADD / ADC
+                let carry = if op == 1 && self.flag(FLAG_C) { 1 } else { 0 };
+                let result = a as u16 + v as u16 + carry as u16;
+                let r = result as u8;
+                let mut f = r & FLAG_S;
+                if r == 0 { f |= FLAG_Z; }
+                if (a & 0xF) + (v & 0xF) + carry > 0xF { f |= FLAG_H; }
+                if (a ^ v) & 0x80 == 0 && (a ^ r) & 0x80 != 0 { f |= FLAG_PV; }
+                if result > 0xFF { f |= FLAG_C; }
+                self.f = f;
+                self.a = r;
+            }
+            2 | 3 | 7 => {
+                // SUB / SBC / CP
+                let carry = if op == 3 && self.flag(FLAG_C) { 1 } else { 0 };
+                let result = (a as i16) - (v as i16) - carry as i16;
+                let r = result as u8;
+                let mut f = (r & FLAG_S) | FLAG_N;
+                if r == 0 { f |= FLAG_Z; }
+                if ((a & 0xF) as i16) - ((v & 0xF) as i16) - (carry as i16) < 0 { f |= FLAG_H; }
+                if (a ^ v) & 0x80 != 0 && (a ^ r) & 0x80 != 0 { f |= FLAG_PV; }
+                if result < 0 { f |= FLAG_C; }
+                self.f = f;
+                if op != 7 {
+                    self.a = r;
+                }
+            }
+            4 => {
+                self.a = a & v;
+                self.f = Self::szp(self.a) | FLAG_H;
+            }
+            5 => {
+                self.a = a ^ v;
+                self.f = Self::szp(self.a);
+            }
+            _ => {
+                self.a = a | v;
+                self.f = Self::szp(self.a);
+            }
+        }
+    }
+
+    fn inc8(&mut self, v: u8) -> u8 {
+        let r = v.wrapping_add(1);
+        let mut f = (self.f & FLAG_C) | (r & FLAG_S);
+        if r == 0 { f |= FLAG_Z; }
+        if v & 0xF == 0xF { f |= FLAG_H; }
+        if v == 0x7F { f |= FLAG_PV; }
+        self.f = f;
+        r
+    }
+
+    fn dec8(&mut self, v: u8) -> u8 {
+        let r = v.wrapping_sub(1);
+        let mut f = (self.f & FLAG_C) | (r & FLAG_S) | FLAG_N;
+        if r == 0 { f |= FLAG_Z; }
+        if v & 0xF == 0 { f |= FLAG_H; }
+        if v == 0x80 { f |= FLAG_PV; }
+        self.f = f;
+        r
+    }
+
+    fn add16(&mut self, a: u16, b: u16) -> u16 {
+        let result = a as u32 + b as u32;
+        let mut f = self.f & (FLAG_S | FLAG_Z | FLAG_PV);
+        if (a & 0xFFF) + (b & 0xFFF) > 0xFFF { f |= FLAG_H; }
+        if result > 0xFFFF { f |= FLAG_C; }
+        self.f = f;
+        result as u16
+    }
+
+    fn adc16(&mut self, a: u16, b: u16) -> u16 {
+        let carry = if self.flag(FLAG_C) { 1 } else { 0 };
+        let result = a as u32 + b as u32 + carry;
+        let r = result as u16;
+        let mut f = ((r >> 8) as u8) & FLAG_S;
+        if r == 0 { f |= FLAG_Z; }
+        if (a & 0xFFF) as u32 + (b & 0xFFF) as u32 + carry > 0xFFF { f |= FLAG_H; }
+        if (a ^ b) & 0x8000 == 0 && (a ^ r) & 0x8000 != 0 { f |= FLAG_PV; }
+        if result > 0xFFFF { f |= FLAG_C; }
+        self.f = f;
+        r
+    }
+
+    fn sbc16(&mut self, a: u16, b: u16) -> u16 {
+        let carry = if self.flag(FLAG_C) { 1 } else { 0 };
+        let result = a as i32 - b as i32 - carry;
+        let r = result as u16;
+        let mut f = (((r >> 8) as u8) & FLAG_S) | FLAG_N;
+        if r == 0 { f |= FLAG_Z; }
+        if ((a & 0xFFF) as i32) - ((b & 0xFFF) as i32) - carry < 0 { f |= FLAG_H; }
+        if (a ^ b) & 0x8000 != 0 && (a ^ r) & 0x8000 != 0 { f |= FLAG_PV; }
+        if result < 0 { f |= FLAG_C; }
+        self.f = f;
+        r
+    }
+
+    fn rot(&mut self, op: u8, v: u8) -> u8 {
+        let carry_in = self.flag(FLAG_C) as u8;
+        let (r, c) = match op {
+            0 => (v.rotate_left(1), v >> 7),                 // RLC
+            1 => (v.rotate_right(1), v & 1),                 // RRC
+            2 => ((v << 1) | carry_in, v >> 7),              // RL
+            3 => ((v >> 1) | (carry_in << 7), v & 1),        // RR
+            4 => (v << 1, v >> 7),                           // SLA
+            5 => ((v >> 1) | (v & 0x80), v & 1),             // SRA
+            6 => ((v << 1) | 1, v >> 7),                     // SLL
+            _ => (v >> 1, v & 1),                            // SRL
+        };
+        self.f = Self::szp(r) | if c != 0 { FLAG_C } else { 0 };
+        r
+    }
+
+    fn daa(&mut self) {
+        let a = self.a;
+        let mut correction = 0u8;
+        let mut carry = self.flag(FLAG_C);
+        if self.flag(FLAG_H) || a & 0xF > 9 {
+            correction |= 0x06;
+        }
+        if carry || a > 0x99 {
+            correction |= 0x60;
+            carry = true;
+        }
+        let n = self.flag(FLAG_N);
+        let r = if n { a.wrapping_sub(correction) } else { a.wrapping_add(correction) };
+        let h = if n {
+            self.flag(FLAG_H) && a & 0xF < 6
+        } else {
+            a & 0xF > 9
+        };
+        self.a = r;
+        self.f = Self::szp(r)
+            | if n { FLAG_N } else { 0 }
+            | if h { FLAG_H } else { 0 }
+            | if carry { FLAG_C } else { 0 };
+    }
+
+    // Decoder
+
+    fn execute(&mut self, op: u8) {
+        self.disp = None;
+        match op {
+            0xDD => self.execute_indexed(Index::Ix),
+            0xFD => self.execute_indexed(Index::Iy),
+            0xCB => self.execute_cb(Index::Hl),
+            0xED => self.execute_ed(),
+            _ => self.execute_main(op, Index::Hl),
+        }
+    }
+
+    fn execute_indexed(&mut self, index: Index) {
+        self.cycles += 4;
+        let op = self.fetch();
+        match op {
+            0xCB => self.execute_cb(index),
+            0xDD | 0xFD | 0xED => {
+                // Prefix chain: the first prefix acts as a NOP
+                self.pc = self.pc.wrapping_sub(1);
+            }
+            _ => self.execute_main(op, index),
+        }
+    }
+
+    fn execute_main(&mut self, op: u8, index: Index) {
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        let indexed = index != Index::Hl;
+        // (IX+d) forms take 8 extra T-states over (HL)
+        let idx_mem = if indexed { 8 } else { 0 };
+
+        match x {
+            0 => match z {
+                0 => match y {
+                    0 => self.cycles += 4,  // NOP
+                    1 => {
+                        // EX AF,AF'
+                        let (a, f) = (self.a, self.f);
+                        self.a = self.alt[0];
+                        self.f = self.alt[1];
+                        self.alt[0] = a;
+                        self.alt[1] = f;
+                        self.cycles += 4;
+                    }
+                    2 => {
+                        // DJNZ
+                        let d = self.fetch() as i8;
+                        self.b = self.b.wrapping_sub(1);
+                        if self.b != 0 {
+                            self.pc = self.pc.wrapping_add(d as u16);
+                            self.cycles += 13;
+                        } else {
+                            self.cycles += 8;
+                        }
+                    }
+                    3 => {
+                        let d = self.fetch() as i8;
+                        self.pc = self.pc.wrapping_add(d as u16);
+                        self.cycles += 12;
+                    }
+                    _ => {
+                        let d = self.fetch() as i8;
+                        if self.condition(y - 4) {
+                            self.pc = self.pc.wrapping_add(d as u16);
+                            self.cycles += 12;
+                        } else {
+                            self.cycles += 7;
+                        }
+                    }
+                },
+                1 => {
+                    if q == 0 {
+                        let nn = self.fetch16();
+                        self.set_rp(p, index, nn);
+                        self.cycles += 10;
+                    } else {
+                        let hl = self.get_index(index);
+                        let rp = self.get_rp(p, index);
+                        let r = self.add16(hl, rp);
+                        self.set_index(index, r);
+                        self.cycles += 11;
+                    }
+                }
+                2 => {
+                    match (q, p) {
+                        (0, 0) => { let a = self.bc(); self.data_write(a, self.a); self.cycles += 7; }
+                        (0, 1) => { let a = self.de(); self.data_write(a, self.a); self.cycles += 7; }
+                        (0, 2) => { let nn = self.fetch16(); let v = self.get_index(index); self.data_write16(nn, v); self.cycles += 16; }
+                        (0, _) => { let nn = self.fetch16(); self.data_write(nn, self.a); self.cycles += 13; }
+                        (1, 0) => { self.a = self.data_read(self.bc()); self.cycles += 7; }
+                        (1, 1) => { self.a = self.data_read(self.de()); self.cycles += 7; }
+                        (1, 2) => { let nn = self.fetch16(); let v = self.data_read16(nn); self.set_index(index, v); self.cycles += 16; }
+                        _ => { let nn = self.fetch16(); self.a = self.data_read(nn); self.cycles += 13; }
+                    }
+                }
+                3 => {
+                    let v = self.get_rp(p, index);
+                    let v = if q == 0 { v.wrapping_add(1) } else { v.wrapping_sub(1) };
+                    self.set_rp(p, index, v);
+                    self.cycles += 6;
+                }
+                4 | 5 => {
+                    let v = self.get_r(y, index);
+                    let r = if z == 4 { self.inc8(v) } else { self.dec8(v) };
+                    // Write back to the same (IX+d) using the cached displacement
+                    self.set_r(y, index, r);
+                    self.cycles += if y == 6 { 11 + idx_mem } else { 4 };
+                }
+                6 => {
+                    if y == 6 {
+                        let addr = self.indirect_addr(index);
+                        let n = self.fetch();
+                        self.data_write(addr, n);
+                        self.cycles += 10 + if indexed { 5 } else { 0 };
+                    } else {
+                        let n = self.fetch();
+                        self.set_r(y, index, n);
+                        self.cycles += 7;
+                    }
+                }
+                _ => {
+                    match y {
+                        0 => { let c = self.a >> 7; self.a = self.a.rotate_left(1); self.acc_rot_flags(c); }
+                        1 => { let c = self.a & 1; self.a = self.a.rotate_right(1); self.acc_rot_flags(c); }
+                        2 => { let c = self.a >> 7; self.a = (self.a << 1) | self.flag(FLAG_C) as u8; self.acc_rot_flags(c); }
+                        3 => { let c = self.a & 1; self.a = (self.a >> 1) | ((self.flag(FLAG_C) as u8) << 7); self.acc_rot_flags(c); }
+                        4 => self.daa(),
+                        5 => { self.a = !self.a; self.f |= FLAG_H | FLAG_N; }
+                        6 => { self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_PV)) | FLAG_C; }
+                        _ => {
+                            let c = self.flag(FLAG_C);
+                            self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_PV)) | if c { FLAG_H } else { FLAG_C };
+                        }
+                    }
+                    self.cycles += 4;
+                }
+            },
+            1 => {
+                if z == 6 && y == 6 {
+                    // HALT
+                    self.halted = true;
+                    self.cycles += 4;
+                } else if y == 6 {
+                    // LD (HL),r - r is never remapped to IXH/IXL
+                    let v = self.get_r(z, Index::Hl);
+                    self.set_r(6, index, v);
+                    self.cycles += 7 + idx_mem;
+                } else if z == 6 {
+                    let v = self.get_r(6, index);
+                    self.set_r(y, Index::Hl, v);
+                    self.cycles += 7 + idx_mem;
+                } else {
+                    let v = self.get_r(z, index);
+                    self.set_r(y, index, v);
+                    self.cycles += 4;
+                }
+            }
+            2 => {
+                let v = self.get_r(z, index);
+                self.alu(y, v);
+                self.cycles += if z == 6 { 7 + idx_mem } else { 4 };
+            }
+            _ => match z {
+                0 => {
+                    if self.condition(y) {
+                        self.pc = self.pop();
+                        self.cycles += 11;
+                    } else {
+                        self.cycles += 5;
+                    }
+                }
+                1 => {
+                    if q == 0 {
+                        let v = self.pop();
+                        self.set_rp2(p, index, v);
+                        self.cycles += 10;
+                    } else {
+                        match p {
+                            0 => { self.pc = self.pop(); self.cycles += 10; }
+                            1 => {
+                                // EXX
+                                let (b, c, d, e, h, l) = (self.b, self.c, self.d, self.e, self.h, self.l);
+                                self.b = self.alt[2]; self.c = self.alt[3];
+                                self.d = self.alt[4]; self.e = self.alt[5];
+                                self.h = self.alt[6]; self.l = self.alt[7];
+                                self.alt[2] = b; self.alt[3] = c;
+                                self.alt[4] = d; self.alt[5] = e;
+                                self.alt[6] = h; self.alt[7] = l;
+                                self.cycles += 4;
+                            }
+                            2 => { self.pc = self.get_index(index); self.cycles += 4; }
+                            _ => { self.sp = self.get_index(index); self.cycles += 6; }
+                        }
+                    }
+                }
+                2 => {
+                    let nn = self.fetch16();
+                    if self.condition(y) {
+                        self.pc = nn;
+                    }
+                    self.cycles += 10;
+                }
+                3 => match y {
+                    0 => { self.pc = self.fetch16(); self.cycles += 10; }
+                    2 => { let n = self.fetch(); self.port_out(n, self.a); self.cycles += 11; }
+                    3 => { let n = self.fetch(); self.a = self.port_in(n); self.cycles += 11; }
+                    4 => {
+                        // EX (SP),HL
+                        let v = self.read16(self.sp);
+                        let hl = self.get_index(index);
+                        self.write16(self.sp, hl);
+                        self.set_index(index, v);
+                        self.cycles += 19;
+                    }
+                    5 => {
+                        // EX DE,HL (never affected by the index prefix)
+                        let de = self.de();
+                        let hl = self.hl();
+                        self.set_de(hl);
+                        self.set_hl(de);
+                        self.cycles += 4;
+                    }
+                    6 => { self.iff1 = false; self.iff2 = false; self.cycles += 4; }
+                    7 => { self.iff1 = true; self.iff2 = true; self.ei_delay = true; self.cycles += 4; }
+                    _ => self.cycles += 4,  // CB is handled by the prefix dispatcher
+                },
+                4 => {
+                    let nn = self.fetch16();
+                    if self.condition(y) {
+                        self.push(self.pc);
+                        self.pc = nn;
+                        self.cycles += 17;
+                    } else {
+                        self.cycles += 10;
+                    }
+                }
+                5 => {
+                    if q == 0 {
+                        let v = self.get_rp2(p, index);
+                        self.push(v);
+                        self.cycles += 11;
+                    } else {
+                        // Only CALL nn reaches here; prefixes are dispatched earlier
+                        let nn = self.fetch16();
+                        self.push(self.pc);
+                        self.pc = nn;
+                        self.cycles += 17;
+                    }
+                }
+                6 => {
+                    let n = self.fetch();
+                    self.alu(y, n);
+                    self.cycles += 7;
+                }
+                _ => {
+                    self.push(self.pc);
+                    self.pc = (y as u16) * 8;
+                    self.cycles += 11;
+                }
+            },
+        }
+    }
+
+    fn acc_rot_flags(&mut self, carry: u8) {
+        self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_PV)) | if carry != 0 { FLAG_C } else { 0 };
+    }
+
+    fn execute_cb(&mut self, index: Index) {
+        let indexed = index != Index::Hl;
+        if indexed {
+            // DD CB d op: displacement comes before the opcode
+            self.displacement();
+        }
+        let op = self.fetch();
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = if indexed { 6 } else { op & 7 };
+        let v = self.get_r(z, index);
+        let mem = z == 6;
+        match x {
+            0 => {
+                let r = self.rot(y, v);
+                self.set_r(z, index, r);
+                self.cycles += if mem { if indexed { 19 } else { 15 } } else { 8 };
+            }
+            1 => {
+                let bit = v & (1 << y);
+                let mut f = (self.f & FLAG_C) | FLAG_H;
+                if bit == 0 { f |= FLAG_Z | FLAG_PV; }
+                if y == 7 && bit != 0 { f |= FLAG_S; }
+                self.f = f;
+                self.cycles += if mem { if indexed { 16 } else { 12 } } else { 8 };
+            }
+            2 => {
+                self.set_r(z, index, v & !(1 << y));
+                self.cycles += if mem { if indexed { 19 } else { 15 } } else { 8 };
+            }
+            _ => {
+                self.set_r(z, index, v | (1 << y));
+                self.cycles += if mem { if indexed { 19 } else { 15 } } else { 8 };
+            }
+        }
+    }
+
+    fn execute_ed(&mut self) {
+        let op = self.fetch();
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        match (x, z) {
+            (1, 0) => {
+                let v = self.port_in(self.c);
+                if y != 6 {
+                    self.set_r(y, Index::Hl, v);
+                }
+                self.f = (self.f & FLAG_C) | Self::szp(v);
+                self.cycles += 12;
+            }
+            (1, 1) => {
+                let v = if y == 6 { 0 } else { self.get_r(y, Index::Hl) };
+                self.port_out(self.c, v);
+                self.cycles += 12;
+            }
+            (1, 2) => {
+                let hl = self.hl();
+                let rp = self.get_rp(p, Index::Hl);
+                let r = if q == 0 { self.sbc16(hl, rp) } else { self.adc16(hl, rp) };
+                self.set_hl(r);
+                self.cycles += 15;
+            }
+            (1, 3) => {
+                let nn = self.fetch16();
+                if q == 0 {
+                    let v = self.get_rp(p, Index::Hl);
+                    self.data_write16(nn, v);
+                } else {
+                    let v = self.data_read16(nn);
+                    self.set_rp(p, Index::Hl, v);
+                }
+                self.cycles += 20;
+            }
+            (1, 4) => {
+                // NEG
+                let a = self.a;
+                self.a = 0;
+                self.alu(2, a);
+                self.cycles += 8;
+            }
+            (1, 5) => {
+                // RETN / RETI
+                self.pc = self.pop();
+                self.iff1 = self.iff2;
+                self.isr_depth = self.isr_depth.saturating_sub(1);
+                self.cycles += 14;
+            }
+            (1, 6) => {
+                self.im = match y & 3 {
+                    0 | 1 => 0,
+                    2 => 1,
+                    _ => 2,
+                };
+                self.cycles += 8;
+            }
+            (1, 7) => {
+                match y {
+                    0 => self.i = self.a,
+                    1 => self.r = self.a,
+                    2 | 3 => {
+                        self.a = if y == 2 { self.i } else { self.r };
+                        let mut f = (self.f & FLAG_C) | (self.a & FLAG_S);
+                        if self.a == 0 { f |= FLAG_Z; }
+                        if self.iff2 { f |= FLAG_PV; }
+                        self.f = f;
+                    }
+                    4 => {
+                        // RRD
+                        let m = self.data_read(self.hl());
+                        let a = self.a;
+                        self.data_write(self.hl(), (a << 4) | (m >> 4));
+                        self.a = (a & 0xF0) | (m & 0x0F);
+                        self.f = (self.f & FLAG_C) | Self::szp(self.a);
+                        self.cycles += 9;
+                    }
+                    5 => {
+                        // RLD
+                        let m = self.data_read(self.hl());
+                        let a = self.a;
+                        self.data_write(self.hl(), (m << 4) | (a & 0x0F));
+                        self.a = (a & 0xF0) | (m >> 4);
+                        self.f = (self.f & FLAG_C) | Self::szp(self.a);
+                        self.cycles += 9;
+                    }
+                    _ => {}
+                }
+                self.cycles += 9;
+            }
+            (2, _) if y >= 4 && z <= 3 => self.block(y, z),
+            _ => self.cycles += 8,  // NONI
+        }
+    }
+
+    /// LDI/CPI/INI/OUTI and their D/R variants
+    fn block(&mut self, y: u8, z: u8) {
+        let dec = y & 1 == 1;
+        let repeat = y >= 6;
+        let step = |v: u16| if dec { v.wrapping_sub(1) } else { v.wrapping_add(1) };
+        match z {
+            0 => {
+                let v = self.data_read(self.hl());
+                self.data_write(self.de(), v);
+                self.set_hl(step(self.hl()));
+                self.set_de(step(self.de()));
+                self.set_bc(self.bc().wrapping_sub(1));
+                self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_C)) | if self.bc() != 0 { FLAG_PV } else { 0 };
+                if repeat && self.bc() != 0 {
+                    self.pc = self.pc.wrapping_sub(2);
+                    self.cycles += 21;
+                } else {
+                    self.cycles += 16;
+                }
+            }
+            1 => {
+                let v = self.data_read(self.hl());
+                let r = self.a.wrapping_sub(v);
+                self.set_hl(step(self.hl()));
+                self.set_bc(self.bc().wrapping_sub(1));
+                let mut f = (self.f & FLAG_C) | FLAG_N | (r & FLAG_S);
+                if r == 0 { f |= FLAG_Z; }
+                if (self.a & 0xF) < (v & 0xF) { f |= FLAG_H; }
+                if self.bc() != 0 { f |= FLAG_PV; }
+                self.f = f;
+                if repeat && self.bc() != 0 && r != 0 {
+                    self.pc = self.pc.wrapping_sub(2);
+                    self.cycles += 21;
+                } else {
+                    self.cycles += 16;
+                }
+            }
+            2 => {
+                let v = self.port_in(self.c);
+                self.data_write(self.hl(), v);
+                self.set_hl(step(self.hl()));
+                self.b = self.b.wrapping_sub(1);
+                self.f = FLAG_N | if self.b == 0 { FLAG_Z } else { 0 };
+                if repeat && self.b != 0 {
+                    self.pc = self.pc.wrapping_sub(2);
+                    self.cycles += 21;
+                } else {
+                    self.cycles += 16;
+                }
+            }
+            _ => {
+                let v = self.data_read(self.hl());
+                self.b = self.b.wrapping_sub(1);
+                self.port_out(self.c, v);
+                self.set_hl(step(self.hl()));
+                self.f = FLAG_N | if self.b == 0 { FLAG_Z } else { 0 };
+                if repeat && self.b != 0 {
+                    self.pc = self.pc.wrapping_sub(2);
+                    self.cycles += 21;
+                } else {
+                    self.cycles += 16;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Index {
+    Hl,
+    Ix,
+    Iy,
+}
@@ -0,0 +1,54 @@
+// Runtime ABI stability tests
+// The jump table is a promise to hand-written Z80 code: these check that
+// every entry exists and that released slots never move
+
+use kz80_chip8::abi;
+use kz80_chip8::codegen::Compiler;
+
+/// Slots of ABI 1.0, in order. Append new entries; never edit this list.
+const V1_0: &[&str] = &[
+    "print_char",
+    "print_str",
+    "print_hex8",
+    "refresh_display",
+    "memset",
+    "translate_i",
+    "tick_wait",
+];
+
+fn compile(rom: &[u8]) -> kz80_chip8::codegen::CompiledCode {
+    Compiler::new().compile_code(rom).expect("compile")
+}
+
+#[test]
+fn released_slots_are_unchanged() {
+    let names: Vec<&str> = abi::ENTRIES.iter().map(|e| e.name).collect();
+    assert!(names.len() >= V1_0.len());
+    assert_eq!(&names[..V1_0.len()], V1_0);
+    assert_eq!(abi::VERSION.0, 1, "major bump: update V1_0 and the README");
+}
+
+#[test]
+fn table_jumps_to_each_entry() {
+    // IBM-style ROM: CLS, LD I, DRW, JP self; the table moves with the origin
+    for origin in [0x0000, 0x1000] {
+        let mut compiler = Compiler::new();
+        compiler.set_origin(origin);
+        let code = compiler.compile_code(&[0x00, 0xE0, 0xA2, 0x08, 0xD0, 0x15, 0x12, 0x06, 0xF0, 0x90]).expect("compile");
+        for entry in abi::ENTRIES {
+            let slot = (entry.address(origin) - code.origin) as usize;
+            let target = *code.symbols.get(entry.name).unwrap_or_else(|| panic!("{} missing", entry.name));
+            assert_eq!(code.code[slot], 0xC3, "{} slot is not a JP", entry.name);
+            assert_eq!(u16::from_le_bytes([code.code[slot + 1], code.code[slot + 2]]), target, "{}", entry.name);
+        }
+    }
+}
+
+#[test]
+fn table_fits_below_code() {
+    let end = abi::TABLE_BASE + 3 * abi::ENTRIES.len() as u16;
+    assert!(end <= 0x0100);
+    let code = compile(&[0x12, 0x00]);
+    let version = abi::VERSION_ADDR as usize;
+    assert_eq!((code.code[version], code.code[version + 1]), abi::VERSION);
+}
@@ -0,0 +1,73 @@
+// ACIA port tests
+// With --acia-ports the game and the serial loader must talk to the ACIA
+// only where the board decodes it, and only a RetroShield build can move it
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+use kz80_chip8::codegen::{Compiler, Target};
+use kz80_chip8::loader::{self, ACK, READY};
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+// 200: LD V0, K / JP 200
+const READ_KEY: &[u8] = &[0xF0, 0x0A, 0x12, 0x00];
+
+/// An emulator with its ACIA at 10 and 11
+fn board(rom: &[u8]) -> Z80 {
+    let mut cpu = Z80::with_rom(rom);
+    cpu.acia_ctrl = 0x10;
+    cpu.acia_data = 0x11;
+    cpu
+}
+
+#[test]
+fn the_game_uses_the_ports_given() {
+    for rx_buffer in [false, true] {
+        let mut compiler = Compiler::new();
+        compiler.set_acia_ports(0x10, 0x11);
+        compiler.set_rx_buffer(rx_buffer);
+        let code = compiler.compile_code(READ_KEY).expect("compile");
+        let mut cpu = board(&code.code);
+        cpu.run(2_000_000, None);
+        assert!(cpu.output().contains("CHIP-8 on Z80"), "no banner");
+        cpu.send(b"7");
+        cpu.run(1_000_000, None);
+        assert_eq!(cpu.mem[0x8000], 7);
+        assert!(cpu.out_log.iter().all(|&(_, port, _)| port != 0x80 && port != 0x81), "wrote to 80 or 81");
+    }
+}
+
+#[test]
+fn the_loader_uses_them_too() {
+    let mut compiler = Compiler::new();
+    compiler.set_acia_ports(0x10, 0x11);
+    let image = compiler.compile(include_bytes!("../test/classic/ibm_logo.ch8")).expect("compile");
+    let frames = loader::frames(&image).unwrap();
+    let mut cpu = board(&loader::loader_rom_for(0x10, 0x11));
+    cpu.send(&frames.concat());
+    assert_eq!(cpu.run(20_000_000, Some(0x0000)), StopReason::Breakpoint);
+    assert_eq!(cpu.tx[0], READY);
+    assert_eq!(cpu.tx[1..], vec![ACK; frames.len()][..]);
+    // Then the game starts, on the same ACIA
+    cpu.tx.clear();
+    cpu.run(5_000_000, None);
+    assert!(cpu.output().contains("CHIP-8 on Z80"));
+    assert!(String::from_utf8_lossy(&image).contains("acia-ports=10,11"), "no build info");
+}
+
+#[test]
+fn only_the_retroshield_moves_it() {
+    let build = |target: Target, ctrl: u8, data: u8| {
+        let mut compiler = Compiler::new();
+        compiler.set_target(target);
+        compiler.set_acia_ports(ctrl, data);
+        compiler.compile_code(READ_KEY).map(|_| ())
+    };
+    assert!(build(Target::RetroShield, 0x80, 0x81).is_ok());
+    assert!(build(Target::Rc2014, 0x80, 0x81).is_ok());
+    assert!(build(Target::Rc2014, 0x10, 0x11).unwrap_err().contains("a rc2014 build doesn't have"));
+    assert!(build(Target::RetroShield, 0x10, 0x10).unwrap_err().contains("both 10"));
+    let options = CompileOptions::builder().acia_ports(0xA0, 0xA1).build().unwrap();
+    let (read, _) = CompileOptions::from_json(&options.to_json()).unwrap();
+    assert_eq!(read.acia_ports, Some((0xA0, 0xA1)));
+}
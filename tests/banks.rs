@@ -0,0 +1,53 @@
+// Banked EPROM tests
+// Every bank must start its own game from 0x0000 with the runtime at the
+// same addresses, and the manifest must name the switch settings
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::banks;
+use kz80_chip8::codegen::{CompiledCode, Compiler};
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+fn games() -> Vec<CompiledCode> {
+    let roms: [&[u8]; 3] = [
+        include_bytes!("../test/classic/ibm_logo.ch8"),
+        include_bytes!("../test/classic/maze.ch8"),
+        include_bytes!("../test/classic/pong.ch8"),
+    ];
+    roms.iter().map(|rom| Compiler::new().compile_code(rom).expect("compile")).collect()
+}
+
+#[test]
+fn each_bank_runs_its_own_game() {
+    let games = games();
+    let image = banks::combine(&games, 0x8000).unwrap();
+    assert_eq!(image.len(), 3 * 0x8000);
+    for (n, game) in games.iter().enumerate() {
+        assert_eq!(game.symbols["timer_tick"], games[0].symbols["timer_tick"]);
+        let bank = &image[n * 0x8000..(n + 1) * 0x8000];
+        assert_eq!(&bank[..game.code.len()], &game.code[..]);
+        assert!(bank[game.code.len()..].iter().all(|&b| b == 0xFF));
+        let mut cpu = Z80::with_rom(bank);
+        assert_eq!(cpu.run(2_000_000, None), StopReason::CycleLimit);
+        assert!(cpu.output().contains("CHIP-8 on Z80"));
+    }
+}
+
+#[test]
+fn manifest_lists_the_select_lines() {
+    let games = games();
+    let names = ["ibm_logo.ch8", "maze.ch8", "pong.ch8"].map(String::from);
+    let manifest = banks::manifest(&names, &games, 0x4000);
+    let lines: Vec<&str> = manifest.lines().collect();
+    assert!(lines[0].ends_with("selected by A15 A14"), "{}", lines[0]);
+    assert!(lines[4].starts_with("2     0x008000  10 "), "{}", lines[4]);
+    assert!(lines[4].ends_with("pong.ch8"));
+}
+
+#[test]
+fn games_must_fit_their_bank() {
+    let games = games();
+    let err = banks::combine(&games, 0x400).unwrap_err();
+    assert!(err.contains("bank 0"), "{}", err);
+    assert!(banks::combine(&games, 0x3000).is_err());
+}
@@ -0,0 +1,68 @@
+// Terminal bell tests
+// --audio bell must send one BEL as each sound starts, no more often than
+// the gap between bells allows, and only where there's a terminal to ring
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+mod common;
+
+use kz80_chip8::codegen::{Audio, Compiler, Target};
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::Z80;
+
+/// A ROM that sounds for `length` frames every `every` frames
+fn beeping(length: u8, every: u8) -> Vec<u8> {
+    // 200: LD V0, length / LD ST, V0 / LD V1, every / LD DT, V1 /
+    // 208: LD V1, DT / SE V1, 0 / JP 208 / JP 200
+    vec![0x60, length, 0xF0, 0x18, 0x61, every, 0xF1, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x08, 0x12, 0x00]
+}
+
+/// BELs sent in the first `seconds` at 4MHz
+fn bells(target: Target, rom: &[u8], seconds: u64) -> usize {
+    let mut compiler = Compiler::new();
+    compiler.set_target(target);
+    compiler.set_audio(Audio::Bell);
+    let code = compiler.compile_code(rom).expect("compile");
+    let mut cpu = common::boot(&code);
+    cpu.run(seconds * 4_000_000, None);
+    cpu.tx.iter().filter(|&&c| c == 0x07).count()
+}
+
+#[test]
+fn each_sound_rings_once() {
+    // Half a second apart: two a second
+    let rung = bells(Target::RetroShield, &beeping(3, 30), 2);
+    assert!((4..=5).contains(&rung), "{} bells", rung);
+    // A sound kept going rings only as it starts
+    assert_eq!(bells(Target::Rc2014, &beeping(255, 30), 2), 1);
+    let mut compiler = Compiler::new();
+    let code = compiler.compile_code(&beeping(3, 30)).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.run(8_000_000, None);
+    assert!(!cpu.tx.contains(&0x07), "rang without --audio bell");
+}
+
+#[test]
+fn close_sounds_ring_every_quarter_second() {
+    // About 10 sounds a second, but no more than 4 bells
+    let rung = bells(Target::RetroShield, &beeping(2, 5), 2);
+    assert!((6..=8).contains(&rung), "{} bells", rung);
+}
+
+#[test]
+fn only_consoles_ring() {
+    let build = |target: Target, delta: bool| {
+        let mut compiler = Compiler::new();
+        compiler.set_target(target);
+        compiler.set_delta_display(delta);
+        compiler.set_audio(Audio::Bell);
+        compiler.compile_code(&beeping(3, 30)).map(|code| String::from_utf8_lossy(&code.code).into_owned())
+    };
+    assert!(build(Target::Cpm, false).unwrap().contains("audio=bell"), "no build info");
+    assert!(build(Target::Spectrum, false).unwrap_err().contains("a spectrum build doesn't have"));
+    assert!(build(Target::RetroShield, true).unwrap_err().contains("--delta-display"));
+    assert_eq!(Audio::from_name("bell"), Some(Audio::Bell));
+    let options = CompileOptions::builder().audio(Audio::Bell).build().unwrap();
+    assert!(options.to_json().contains("\"audio\": \"bell\""));
+    assert_eq!(CompileOptions::from_json(&options.to_json()).unwrap().0, options);
+}
@@ -0,0 +1,62 @@
+// Breakpoint tests
+// Breakpoints set from the monitor while the game runs must stop it in the
+// monitor as their block is entered, without rebuilding the game
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Compiler, DEBUG_ESCAPE};
+use kz80_chip8::stepgate;
+use kz80_chip8::z80emu::Z80;
+
+// LD V0, 1 / SKP V0 / JP 202 / ADD V1, 1 / JP 202
+const ROM: &[u8] = &[0x60, 0x01, 0xE0, 0x9E, 0x12, 0x02, 0x71, 0x01, 0x12, 0x02];
+
+/// Console output after each of `inputs` is sent in turn
+fn session(step_gate: bool, inputs: &[&[u8]]) -> Vec<String> {
+    let mut compiler = Compiler::new();
+    compiler.set_debug_stub(true);
+    compiler.set_step_gate(step_gate);
+    let code = compiler.compile_code(ROM).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    let mut outputs = Vec::new();
+    let mut seen = 0;
+    for input in inputs {
+        cpu.send(input);
+        cpu.run(3_000_000, None);
+        let output = cpu.output();
+        outputs.push(output[seen..].to_string());
+        seen = output.len();
+    }
+    outputs
+}
+
+#[test]
+fn a_breakpoint_stops_its_block() {
+    let outputs = session(false, &[b"\x02b206\rc", b"1", b"r", b"c", b"1"]);
+    assert!(outputs[0].ends_with("b206\r\nB 0206\r\ndebug> c\r\n"), "{:?}", outputs[0]);
+    assert!(outputs[1].ends_with("\r\nBreak at 0206\r\ndebug> "), "{:?}", outputs[1]);
+    assert!(outputs[2].contains("\r\nV 01 00 "), "{:?}", outputs[2]);
+    assert!(!outputs[3].contains("Break"), "{:?}", outputs[3]);
+    assert!(outputs[4].contains("\r\nBreak at 0206"), "{:?}", outputs[4]);
+}
+
+#[test]
+fn breakpoints_toggle_and_clear() {
+    let outputs = session(false, &[b"\x02b300\rb302\rb304\rb306\rb308\r", b"b302\rb0x200\r", b"b\rc1"]);
+    assert!(outputs[0].contains("\r\nB 0300 0302 0304 0306\r\ndebug> b308 full"), "{:?}", outputs[0]);
+    assert!(outputs[1].contains("b302\r\nB 0300 0304 0306"), "{:?}", outputs[1]);
+    assert!(outputs[1].contains("\r\nB 0300 0200 0304 0306"), "{:?}", outputs[1]);
+    assert!(outputs[2].starts_with("b\r\nB\r\ndebug> c"), "{:?}", outputs[2]);
+    assert!(!outputs[2].contains("Break"), "{:?}", outputs[2]);
+}
+
+#[test]
+fn the_host_sets_breakpoints_at_the_gate() {
+    let set = stepgate::command("b 206").unwrap().unwrap();
+    assert_eq!(set, [&[DEBUG_ESCAPE][..], b"b206\rc"].concat());
+    let outputs = session(true, &[b"", &set, &[stepgate::GO], b"1"]);
+    assert!(outputs[0].contains("Gate 0200"), "{:?}", outputs[0]);
+    assert!(outputs[1].contains("\r\nB 0206"), "{:?}", outputs[1]);
+    assert!(!outputs[2].contains("Break"), "{:?}", outputs[2]);
+    assert!(outputs[3].contains("\r\nBreak at 0206"), "{:?}", outputs[3]);
+}
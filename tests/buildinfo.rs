@@ -0,0 +1,78 @@
+// Build info tests
+// Every code option must come back from an options file as it went in and
+// be named in the build options, which '?' prints as the build info
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+mod common;
+
+use kz80_chip8::codegen::{CodegenStyle, Orientation, Scale};
+use kz80_chip8::options::CompileOptions;
+
+/// LD V0, K / JP 200: answers '?' while it waits for a key
+const WAIT_KEY: &[u8] = &[0xF0, 0x0A, 0x12, 0x00];
+
+/// Options, what the options file says of them, and their build options
+struct Case {
+    options: CompileOptions,
+    json: &'static str,
+    words: &'static str,
+}
+
+fn cases() -> Vec<Case> {
+    vec![
+        Case {
+            options: CompileOptions::builder().codegen_style(CodegenStyle::Traceable).build().unwrap(),
+            json: "\"codegen-style\": \"traceable\"",
+            words: "codegen-style=traceable",
+        },
+        Case {
+            options: CompileOptions::builder().framebuffer(0x6000, 16).build().unwrap(),
+            json: "\"framebuffer\": [\n    24576,\n    16\n  ]",
+            words: "framebuffer=6000:16",
+        },
+        Case {
+            options: CompileOptions::builder()
+                .framebuffer(0x6000, 4)
+                .orientation(Orientation { rotate: 270, mirror: false, lsb_first: true })
+                .build()
+                .unwrap(),
+            json: "\"orientation\": {\n    \"lsb-first\": true,\n    \"mirror\": false,\n    \"rotate\": 270\n  }",
+            words: "framebuffer=6000:4 orientation=rotate=270,lsb-first",
+        },
+        Case {
+            options: CompileOptions::builder().framebuffer(0x6000, 16).scale(Scale::Scanlines).build().unwrap(),
+            json: "\"scale\": \"scanlines\"",
+            words: "framebuffer=6000:16 scale=scanlines",
+        },
+    ]
+}
+
+/// What the build prints when sent '?'
+fn build_info(options: &CompileOptions) -> String {
+    let code = options.compiler().compile_code(WAIT_KEY).expect("compile");
+    let mut cpu = common::boot(&code);
+    cpu.run(1_000_000, None);
+    cpu.send(b"?");
+    cpu.run(1_000_000, None);
+    cpu.output()
+}
+
+#[test]
+fn options_are_kept_and_named_in_the_build_info() {
+    for case in cases() {
+        let json = case.options.to_json();
+        assert!(json.contains(case.json), "{}", json);
+        assert_eq!(CompileOptions::from_json(&json).unwrap().0, case.options);
+        assert_eq!(case.options.compiler().build_options().join(" "), case.words);
+        let info = build_info(&case.options);
+        assert!(info.contains(&format!("\r\nOptions: {}\r\n", case.words)), "{:?}", info);
+    }
+}
+
+#[test]
+fn default_builds_say_so() {
+    let options = CompileOptions::default();
+    assert!(options.compiler().build_options().is_empty());
+    assert!(build_info(&options).contains("\r\nOptions: default\r\n"));
+}
@@ -0,0 +1,86 @@
+// Compilation cache tests
+// Cached code must come back exactly as compiled, under a key that only
+// matches the same ROM with the same code-shaping options
+
+#![cfg(feature = "cli")]
+
+use kz80_chip8::cache::{self, Cache};
+use kz80_chip8::codegen::{Compiler, Target};
+use kz80_chip8::keymap::KeyMap;
+use kz80_chip8::quirks::Quirks;
+use std::process::Command;
+
+const IBM_LOGO: &[u8] = include_bytes!("../test/classic/ibm_logo.ch8");
+
+#[test]
+fn entries_round_trip() {
+    // An unknown opcode, so there's a diagnostic to keep
+    let compiled = Compiler::new().compile_code(&[0x5A, 0xB1, 0x12, 0x00]).expect("compile");
+    assert!(!compiled.diagnostics.is_empty());
+    let back = cache::from_json(&cache::to_json(&compiled)).unwrap();
+    assert_eq!(back.code, compiled.code);
+    assert_eq!(back.origin, compiled.origin);
+    assert_eq!(back.symbols, compiled.symbols);
+    assert_eq!(back.diagnostics, compiled.diagnostics);
+    assert_eq!(format!("{:?}", back.stats), format!("{:?}", compiled.stats));
+
+    let dir = std::env::temp_dir().join(format!("kz80_chip8_cache_{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+    let cache = Cache::open(dir, 1).unwrap();
+    assert!(cache.get(7).is_none());
+    cache.put(7, &compiled).unwrap();
+    assert_eq!(cache.get(7).map(|c| c.code), Some(compiled.code));
+    assert!(Cache::open(dir, 2).unwrap().get(7).is_none());  // Another compiler build
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn key_follows_rom_and_code_options() {
+    let key = |setup: &dyn Fn(&mut Compiler), rom: &[u8]| {
+        let mut compiler = Compiler::new();
+        setup(&mut compiler);
+        compiler.cache_key(rom)
+    };
+    let plain = key(&|_| {}, IBM_LOGO);
+    assert_eq!(key(&|c| c.set_trim(true), IBM_LOGO), plain);
+    assert_ne!(key(&|_| {}, &IBM_LOGO[..IBM_LOGO.len() - 1]), plain);
+    assert_ne!(key(&|c| c.set_target(Target::Rc2014), IBM_LOGO), plain);
+    assert_ne!(key(&|c| c.set_name("logo.ch8"), IBM_LOGO), plain);
+    assert_ne!(key(&|c| c.set_allow_self_modify(true), IBM_LOGO), plain);
+    let mut quirks = Quirks::default();
+    quirks.apply("vf-reset").unwrap();
+    assert_ne!(key(&|c| c.set_quirks(quirks), IBM_LOGO), plain);
+    // The whole key map counts, not just how many keys it changes
+    let keymap = |text: &str| KeyMap::parse(text).unwrap();
+    let wasd = key(&|c| c.set_keymap(keymap("w = 5\na = 7")), IBM_LOGO);
+    assert_ne!(wasd, plain);
+    assert_eq!(key(&|c| c.set_keymap(keymap("w = 5\na = 7")), IBM_LOGO), wasd);
+    assert_ne!(key(&|c| c.set_keymap(keymap("w = 5\na = 8")), IBM_LOGO), wasd);
+    assert_ne!(key(&|c| c.set_keymap(keymap("w = 5\nd = 7")), IBM_LOGO), wasd);
+    let players = key(&|c| c.set_keymap(keymap("w = 5\na = 7\nplayer1 = [5]\nplayer2 = [7]")), IBM_LOGO);
+    assert_ne!(players, wasd);
+    assert_ne!(key(&|c| c.set_keymap(keymap("w = 5\na = 7\nplayer1 = [7]\nplayer2 = [5]")), IBM_LOGO), players);
+}
+
+#[test]
+fn second_compile_reuses_the_first() {
+    let dir = std::env::temp_dir().join(format!("kz80_chip8_cli_cache_{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+    let output = format!("{}/logo.hex", dir);
+    let compile = |extra: &[&str]| {
+        let result = Command::new(env!("CARGO_BIN_EXE_kz80_chip8"))
+            .args(["compile", "test/classic/ibm_logo.ch8", "--cache", dir, "--package", "hex", "-o", &output])
+            .args(extra)
+            .output()
+            .expect("run kz80_chip8");
+        assert!(result.status.success());
+        (String::from_utf8_lossy(&result.stdout).contains("Reused the cached compile"), std::fs::read(&output).unwrap())
+    };
+    let (reused, first) = compile(&[]);
+    assert!(!reused);
+    let (reused, second) = compile(&[]);
+    assert!(reused);
+    assert_eq!(second, first);
+    assert!(!compile(&["--quirk", "vf-reset"]).0);
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,67 @@
+// Command line tests
+// Run the built binary to check that the subcommands reach the library
+// and that bad arguments are refused before anything is written
+
+#![cfg(feature = "cli")]
+
+use kz80_chip8::codegen::Compiler;
+use std::process::Command;
+
+fn kz80_chip8(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_kz80_chip8")).args(args).output().expect("run kz80_chip8")
+}
+
+#[test]
+fn compile_writes_the_library_image() {
+    let out = std::env::temp_dir().join(format!("kz80_chip8_cli_{}.bin", std::process::id()));
+    let result = kz80_chip8(&["compile", "test/classic/ibm_logo.ch8", "-o", out.to_str().unwrap(), "--clock", "2000000"]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let written = std::fs::read(&out).unwrap();
+    std::fs::remove_file(&out).unwrap();
+
+    let mut compiler = Compiler::new();
+    compiler.set_name("ibm_logo.ch8");
+    compiler.set_clock_hz(2_000_000);
+    assert_eq!(written, compiler.compile(include_bytes!("../test/classic/ibm_logo.ch8")).unwrap());
+}
+
+#[test]
+fn bad_arguments_are_usage_errors() {
+    for args in [
+        &["compile"][..],
+        &["compile", "test/classic/ibm_logo.ch8", "--quirk", "bogus"],
+        &["compile", "test/classic/ibm_logo.ch8", "--warn", "bogus=error"],
+        &["compile", "test/classic/ibm_logo.ch8", "--base-rom", "monitor.bin"],
+        &["compile", "test/classic/ibm_logo.ch8", "--at", "0x8000"],
+        &["frobnicate"],
+    ] {
+        let result = kz80_chip8(args);
+        assert_eq!(result.status.code(), Some(2), "{:?}", args);
+        assert!(String::from_utf8_lossy(&result.stderr).contains("--help"), "{:?}", args);
+    }
+    let result = kz80_chip8(&["explain", "E01", "0234"]);
+    assert!(String::from_utf8_lossy(&result.stdout).contains("stack overflow"));
+}
+
+#[test]
+fn time_passes_reports_each_pass() {
+    let rom = include_bytes!("../test/classic/ibm_logo.ch8");
+    let mut compiler = Compiler::new();
+    assert!(compiler.compile_code(rom).unwrap().passes.is_empty());
+    compiler.set_time_passes(true);
+    let passes = compiler.compile_code(rom).unwrap().passes;
+    let names: Vec<&str> = passes.iter().map(|pass| pass.name).collect();
+    assert_eq!(names.first(), Some(&"discover"));
+    for name in ["schedule", "layout", "encode", "link", "relax"] {
+        assert!(names.contains(&name), "{:?}", names);
+    }
+    // Generated twice, for jump relaxation
+    assert_eq!(passes.iter().find(|pass| pass.name == "encode").unwrap().runs, 2);
+
+    // Only the binary counts its allocations
+    let out = std::env::temp_dir().join(format!("kz80_chip8_passes_{}.bin", std::process::id()));
+    let result = kz80_chip8(&["compile", "test/classic/ibm_logo.ch8", "-o", out.to_str().unwrap(), "--time-passes"]);
+    std::fs::remove_file(&out).unwrap();
+    let report = String::from_utf8_lossy(&result.stderr);
+    assert!(report.lines().any(|line| line.contains("encode") && line.ends_with(" KB")), "{}", report);
+}
@@ -0,0 +1,130 @@
+// Code generation goldens
+// Each fixture ROM is compiled and listed, labels and all, and the listing
+// is checked against the one in test/codegen; `BLESS=1 cargo test --test
+// codegen` rewrites the listings once a change to them has been looked at
+
+use kz80_chip8::analysis;
+use kz80_chip8::chip8;
+use kz80_chip8::codegen::{CompiledCode, Compiler, Target};
+use std::fmt::Write;
+use std::path::Path;
+
+const SMALL_ROMS: &[&str] = &[
+    "cls_loop", "cls_only", "custom", "custom2", "draw0", "draw00", "drawonce", "font", "halt", "hello", "sprite",
+];
+
+/// Code compiled as the golden subcommand builds it, named after the file
+fn compile(path: &str, target: Target) -> (Vec<u8>, CompiledCode) {
+    let rom = std::fs::read(path).unwrap();
+    let mut compiler = Compiler::new();
+    compiler.set_name(path.rsplit('/').next().unwrap());
+    compiler.set_target(target);
+    let code = compiler.compile_code(&rom).unwrap();
+    (rom, code)
+}
+
+/// Hex dump of the code, 16 bytes a line, broken at each label; the label
+/// of a CHIP-8 instruction carries its disassembly
+fn listing(name: &str, rom: &[u8], code: &CompiledCode) -> String {
+    let map = analysis::classify(rom);
+    let mut labels: Vec<(u16, &str)> = code.symbols.iter().map(|(name, &addr)| (addr, name.as_str())).collect();
+    labels.sort();
+    let mut text = format!("; {}: {} bytes at {:04X}\n", name, code.code.len(), code.origin);
+    let end = code.origin as usize + code.code.len();
+    let mut addr = code.origin as usize;
+    let mut next = labels.iter().peekable();
+    while addr < end {
+        while let Some(&(at, label)) = next.next_if(|&&(at, _)| at as usize <= addr) {
+            let inst = label
+                .strip_prefix("c8_")
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                .and_then(|c8| map.instructions.iter().find(|inst| inst.addr == c8));
+            match inst {
+                Some(inst) => writeln!(text, "{}:  ; {:04X}  {}", label, inst.opcode, chip8::disasm_instruction(inst)),
+                None if (at as usize) < addr => writeln!(text, "{}:  ; at {:04X}", label, at),
+                None => writeln!(text, "{}:", label),
+            }
+            .unwrap();
+        }
+        let stop = next.peek().map_or(end, |&&(at, _)| (at as usize).min(end)).min(addr + 16);
+        let bytes: Vec<String> = code.code[addr - code.origin as usize..stop - code.origin as usize]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        writeln!(text, "{:04X}  {}", addr, bytes.join(" ")).unwrap();
+        addr = stop;
+    }
+    text
+}
+
+/// Check a listing against its golden, or write it when blessing
+fn check(golden: &str, listing: &str) -> Result<(), String> {
+    let path = Path::new("test/codegen").join(golden);
+    if std::env::var_os("BLESS").is_some() {
+        std::fs::create_dir_all("test/codegen").unwrap();
+        std::fs::write(&path, listing).unwrap();
+        return Ok(());
+    }
+    let expected = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    match expected.lines().zip(listing.lines()).position(|(a, b)| a != b) {
+        Some(line) => Err(format!(
+            "{} line {}: expected `{}`, compiled `{}`",
+            path.display(),
+            line + 1,
+            expected.lines().nth(line).unwrap(),
+            listing.lines().nth(line).unwrap()
+        )),
+        None if expected.lines().count() != listing.lines().count() => {
+            Err(format!("{}: {} lines expected, {} compiled", path.display(), expected.lines().count(), listing.lines().count()))
+        }
+        None => Ok(()),
+    }
+}
+
+fn check_all(fixtures: &[(String, Target, String)]) {
+    let failures: Vec<String> = fixtures
+        .iter()
+        .filter_map(|(rom, target, golden)| {
+            let (bytes, code) = compile(rom, *target);
+            check(golden, &listing(rom, &bytes, &code)).err()
+        })
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "the code generated has changed; check it, then `BLESS=1 cargo test --test codegen`\n{}",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn small_roms_match_their_listings() {
+    let fixtures: Vec<_> = SMALL_ROMS
+        .iter()
+        .map(|name| (format!("test/{}.ch8", name), Target::RetroShield, format!("{}.lst", name)))
+        .collect();
+    check_all(&fixtures);
+}
+
+#[test]
+fn every_target_matches_its_listing() {
+    let targets = [Target::Spectrum, Target::Msx, Target::Cpm, Target::Cpc, Target::Rc2014, Target::Sms, Target::Coleco];
+    let fixtures: Vec<_> = targets
+        .iter()
+        .map(|&target| ("test/classic/ibm_logo.ch8".to_string(), target, format!("ibm_logo.{}.lst", target.name())))
+        .collect();
+    check_all(&fixtures);
+}
+
+#[test]
+fn listing_labels_chip8_instructions() {
+    let (rom, code) = compile("test/cls_only.ch8", Target::RetroShield);
+    let text = listing("cls_only.ch8", &rom, &code);
+    assert!(text.starts_with(&format!("; cls_only.ch8: {} bytes at 0000\n", code.code.len())));
+    assert!(text.contains("\nc8_200:  ; 00E0  CLS\n"), "{}", text);
+    let dumped: usize = text
+        .lines()
+        .filter(|line| line.get(4..6) == Some("  ") && line[..4].bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(|line| line[6..].split(' ').count())
+        .sum();
+    assert_eq!(dumped, code.code.len());
+}
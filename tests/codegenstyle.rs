@@ -0,0 +1,56 @@
+// Codegen style tests
+// Traceable code must draw what compact code draws, with a marker of the
+// CHIP-8 address and opcode skipped over at the start of every instruction
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{CodegenStyle, CompiledCode, Compiler, Layout, DEFAULT_CLOCK_HZ, DISPLAY_BUF};
+use kz80_chip8::golden;
+
+const IBM_LOGO: &[u8] = include_bytes!("../test/classic/ibm_logo.ch8");
+const PONG: &[u8] = include_bytes!("../test/classic/pong.ch8");
+
+fn build(rom: &[u8], style: CodegenStyle) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_codegen_style(style);
+    compiler.compile_code(rom).expect("compile")
+}
+
+#[test]
+fn traceable_code_draws_the_same() {
+    let display = |code: &CompiledCode| {
+        let cpu = golden::emulate(code, DEFAULT_CLOCK_HZ, 60);
+        cpu.mem[DISPLAY_BUF as usize..DISPLAY_BUF as usize + 256].to_vec()
+    };
+    let compact = build(IBM_LOGO, CodegenStyle::Compact);
+    let traceable = build(IBM_LOGO, CodegenStyle::Traceable);
+    assert!(display(&compact).iter().any(|&byte| byte != 0), "nothing drawn");
+    assert_eq!(display(&traceable), display(&compact));
+    assert!(traceable.stats.program_bytes > compact.stats.program_bytes);
+}
+
+#[test]
+fn every_instruction_is_marked() {
+    let code = build(PONG, CodegenStyle::Traceable);
+    let compact = build(PONG, CodegenStyle::Compact);
+    let mut marked = 0;
+    for (label, &at) in &code.symbols {
+        let Some(addr) = label.strip_prefix("c8_").and_then(|addr| u16::from_str_radix(addr, 16).ok()) else { continue };
+        let opcode = &PONG[addr as usize - 0x200..addr as usize - 0x200 + 2];
+        let at = (at - code.origin) as usize;
+        let addr = addr.to_le_bytes();
+        assert_eq!(code.code[at..at + 6], [0x18, 0x04, addr[0], addr[1], opcode[1], opcode[0]], "{}", label);
+        marked += 1;
+    }
+    assert!(marked > 100, "{} marked", marked);
+    let start = compact.symbols["c8_200"] as usize;
+    assert_ne!(compact.code[start..start + 2], [0x18, 0x04]);
+}
+
+#[test]
+fn hot_layouts_are_refused() {
+    let mut compiler = Compiler::new();
+    compiler.set_codegen_style(CodegenStyle::Traceable);
+    compiler.set_layout(Layout::Loops);
+    assert!(compiler.compile_code(IBM_LOGO).unwrap_err().contains("can't take a hot --layout"));
+}
@@ -0,0 +1,99 @@
+// ColecoVision target tests
+// The cartridge must start from its header at 0x8000, keep the CHIP-8
+// state in the Super Game Module's RAM and read keys from the keypad
+
+#![cfg(feature = "emulator")]
+
+mod common;
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, Target};
+use kz80_chip8::z80emu::Z80;
+
+const V0: u16 = 0x2000;
+const DISPLAY: u16 = 0x2200;
+
+fn compile(rom: &[u8]) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Coleco);
+    compiler.compile_code(rom).expect("compile")
+}
+
+/// Start the cartridge as the BIOS does, from the address in its header
+fn boot(code: &CompiledCode) -> Z80 {
+    let mut cpu = common::boot(code);
+    cpu.pc = u16::from_le_bytes([code.code[0x0A], code.code[0x0B]]);
+    cpu
+}
+
+/// VRAM after replaying the writes to the VDP's control and data ports
+fn vram(cpu: &Z80) -> Vec<u8> {
+    let mut vram = vec![0u8; 0x4000];
+    let (mut latch, mut addr) = (None, 0usize);
+    for &(_, port, value) in &cpu.out_log {
+        match port {
+            0xBF => match latch.take() {
+                None => latch = Some(value),
+                Some(low) if value & 0x80 == 0 => addr = (low as usize | (value as usize) << 8) & 0x3FFF,
+                Some(_) => {}  // Register write
+            },
+            0xBE => {
+                latch = None;
+                vram[addr] = value;
+                addr = (addr + 1) & 0x3FFF;
+            }
+            _ => {}
+        }
+    }
+    vram
+}
+
+#[test]
+fn cartridge_header_skips_the_title_screen() {
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Coleco);
+    let rom = compiler.compile(&[0x12, 0x00]).expect("compile");
+    assert_eq!(rom.len(), 0x8000);
+    assert_eq!(&rom[..2], &[0x55, 0xAA]);
+    assert_eq!(&rom[0x0A..0x0C], &[0x00, 0x81]);
+    assert_eq!(&rom[0x21..0x23], &[0xED, 0x45]);  // RETN for the VDP's NMI
+    compiler.set_origin(0x4000);
+    assert!(compiler.compile(&[0x12, 0x00]).is_err());
+}
+
+#[test]
+fn display_and_state_live_in_module_ram() {
+    let code = compile(include_bytes!("../test/classic/ibm_logo.ch8"));
+    let mut cpu = boot(&code);
+    cpu.run(6_000_000, None);
+    assert_eq!(cpu.out_log.iter().find(|&&(_, port, _)| port == 0x53).map(|&(_, _, value)| value), Some(0x01));
+    assert!((0..256).any(|i| cpu.read(DISPLAY + i) != 0));
+    let vram = vram(&cpu);
+    let pixel = |x: usize, y: usize| cpu.read(DISPLAY + (y * 8 + x / 8) as u16) & (0x80 >> (x % 8)) != 0;
+    for row in 0..16 {
+        for column in 0..32 {
+            let (x, y) = (column * 2, row * 2);
+            let block = (pixel(x, y) as u8) << 3 | (pixel(x + 1, y) as u8) << 2 | (pixel(x, y + 1) as u8) << 1 | pixel(x + 1, y + 1) as u8;
+            assert_eq!(vram[0x1800 + (row + 2) * 32 + column], block, "cell {},{}", column, row);
+        }
+    }
+    assert_eq!(&vram[0x1800 + 20 * 32..][..13], b"CHIP-8 ON Z80");
+}
+
+#[test]
+fn keypad_and_fire_are_chip8_keys() {
+    // FX0A into V0 until it is non-zero, then spin
+    let key = |controller: u8| {
+        let code = compile(&[0xF0, 0x0A, 0x40, 0x00, 0x12, 0x00, 0x12, 0x06]);
+        let mut cpu = boot(&code);
+        cpu.run(1_000_000, None);
+        cpu.in_ports[0xFC] = controller;
+        cpu.run(1_000_000, None);
+        cpu.in_ports[0xFC] = 0xFF;  // FX0A waits for the release
+        cpu.run(1_000_000, None);
+        cpu.read(V0)
+    };
+    assert_eq!(key(0xFF), 0x0);  // Nothing pressed
+    assert_eq!(key(0xFD), 0x1);
+    assert_eq!(key(0xF9), 0xB);  // #
+    assert_eq!(key(0xBF), 0xD);  // Right fire
+}
@@ -0,0 +1,119 @@
+// Collision tests
+// VF after a draw at the display's edges must come from the pixels drawn,
+// wrapped or clipped, and be the same compiled, interpreted or moved
+
+#![cfg(all(feature = "emulator", feature = "interp"))]
+
+use kz80_chip8::codegen::{Compiler, Fallback, DISPLAY_BUF};
+use kz80_chip8::quirks::Quirks;
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+const SPRITE: [u8; 3] = [0xF8, 0x88, 0xF8];
+
+/// X and Y of a draw
+type At = (u8, u8);
+
+/// First and second sprite positions, near the edges
+const DRAWS: &[(At, At)] = &[
+    ((0, 0), (60, 31)),   // Meet only where the second wraps both ways
+    ((0, 1), (62, 0)),    // Meet only where the second wraps right
+    ((8, 0), (10, 30)),   // Meet only where the second wraps down
+    ((59, 29), (61, 30)), // Meet on the display, then both run off it
+    ((5, 5), (70, 40)),   // The second starts at 6,8: X and Y always wrap
+];
+
+/// Display and VF after drawing SPRITE at `first` then `second` on the host
+fn model(first: At, second: At, clip: bool) -> (Vec<u8>, u8) {
+    let mut display = vec![0u8; 256];
+    let mut vf = 0;
+    for (x, y) in [first, second] {
+        vf = 0;
+        for (row, &bits) in SPRITE.iter().enumerate() {
+            for bit in 0..8 {
+                let (px, py) = ((x % 64) as usize + bit, (y % 32) as usize + row);
+                if bits & 0x80 >> bit == 0 || (clip && (px >= 64 || py >= 32)) {
+                    continue;
+                }
+                let (byte, mask) = ((py % 32) * 8 + (px % 64) / 8, 0x80 >> (px % 8));
+                if display[byte] & mask != 0 {
+                    vf = 1;
+                }
+                display[byte] ^= mask;
+            }
+        }
+    }
+    (display, vf)
+}
+
+/// Display and VF after the ROM's draws: compiled with the positions
+/// folded, compiled from a jump table so nothing is, or interpreted
+fn run(body: &[u16], clip: bool, prefix: [u16; 3]) -> (Vec<u8>, u8) {
+    let mut words = prefix.to_vec();
+    words.extend_from_slice(body);
+    let mut rom: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+    rom.extend(SPRITE);
+    let mut compiler = Compiler::new();
+    compiler.set_quirks(Quirks { clip, ..Quirks::default() });
+    compiler.set_fallback(Fallback::Interp);
+    let code = compiler.compile_code(&rom).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    assert_eq!(cpu.run(5_000_000, None), StopReason::Halted);
+    (cpu.mem[DISPLAY_BUF as usize..DISPLAY_BUF as usize + 256].to_vec(), cpu.mem[0x800F])
+}
+
+/// Draw at `first`, then `second`, then EXIT, with the sprite after it
+fn draws(first: At, second: At) -> Vec<u16> {
+    let sprite = 0x206 + 2 * 8;
+    vec![0xA000 | sprite, 0x6A00 | first.0 as u16, 0x6B00 | first.1 as u16, 0xDAB3, 0x6C00 | second.0 as u16, 0x6D00 | second.1 as u16, 0xDCD3, 0x00FD]
+}
+
+#[test]
+fn vf_counts_the_pixels_drawn() {
+    for clip in [false, true] {
+        for &(first, second) in DRAWS {
+            let expected = model(first, second, clip);
+            for prefix in [[0x6002, 0x6202, 0x1206], [0x6000, 0x6200, 0xB206], [0x6002, 0x6202, 0xB204]] {
+                let at = (first, second, clip, prefix[2]);
+                assert_eq!(run(&draws(first, second), clip, prefix), expected, "{:?}", at);
+            }
+        }
+    }
+}
+
+#[test]
+fn the_edges_make_the_difference() {
+    // Each draw but the last meets the first sprite only off the edges
+    for &(first, second) in &DRAWS[..3] {
+        assert_eq!(model(first, second, false).1, 1, "{:?}", (first, second));
+        assert_eq!(model(first, second, true).1, 0, "{:?}", (first, second));
+    }
+    assert_eq!(model(DRAWS[3].0, DRAWS[3].1, true).1, 1);
+}
+
+#[test]
+fn moved_sprites_collide_where_they_land() {
+    // Draw the sprite at the first position, then at 0,0 as an obstacle,
+    // then move the first: erase it, step it to the second position and
+    // draw it again, which the compiler turns into a move_sprite
+    for clip in [false, true] {
+        for &(first, second) in &DRAWS[..3] {
+            let sprite = 0x206 + 2 * 11;
+            let body = [
+                0xA000 | sprite,
+                0x6A00 | first.0 as u16,
+                0x6B00 | first.1 as u16,
+                0xDAB3,
+                0x6000,
+                0xD003,
+                0xDAB3,
+                0x6A00 | second.0 as u16,
+                0x6B00 | second.1 as u16,
+                0xDAB3,
+                0x00FD,
+            ];
+            let (display, vf) = run(&body, clip, [0x6002, 0x6202, 0x1206]);
+            let (expected, expected_vf) = model((0, 0), second, clip);
+            assert_eq!((display, vf), (expected, expected_vf), "{:?}", (first, second, clip));
+        }
+    }
+}
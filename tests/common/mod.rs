@@ -0,0 +1,14 @@
+// Shared test helpers
+// Compiled code loaded into the emulator and started where its reset
+// vector would start it, for the tests that drive the machine themselves
+
+use kz80_chip8::codegen::CompiledCode;
+use kz80_chip8::z80emu::Z80;
+
+/// The build loaded at its origin, stopped at `init`
+pub fn boot(code: &CompiledCode) -> Z80 {
+    let mut cpu = Z80::new();
+    cpu.load(code.origin, &code.code);
+    cpu.pc = code.symbols["init"];
+    cpu
+}
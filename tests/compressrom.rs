@@ -0,0 +1,61 @@
+// Compressed ROM tests
+// With --compress-rom the embedded copy must shrink when the ROM has runs
+// in it, and inflate at startup into exactly the ROM, which draws the same
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+use kz80_chip8::codegen::{Compiler, Target, CHIP8_RAM, DEFAULT_CLOCK_HZ};
+use kz80_chip8::golden;
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::Z80;
+
+const IBM_LOGO: &[u8] = include_bytes!("../test/classic/ibm_logo.ch8");
+
+fn compile(rom: &[u8], compress_rom: bool) -> kz80_chip8::codegen::CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_compress_rom(compress_rom);
+    compiler.compile_code(rom).expect("compile")
+}
+
+#[test]
+fn the_rom_inflates_into_ram() {
+    // 200: LD I, 20A / LD V0..V0, [I] / DRW V0, V1, 1 / JP 206, then runs
+    // of each length around the encoding's limits, between literals
+    let mut rom = vec![0xA2, 0x0A, 0xF0, 0x65, 0xD0, 0x11, 0x12, 0x06, 0x00, 0x00, 0x42];
+    for (length, byte) in [(2, 0x11), (3, 0x22), (127, 0x00), (128, 0xFF), (129, 0x33), (300, 0x00)] {
+        rom.extend(std::iter::repeat(byte).take(length));
+        rom.extend(1..=5);
+    }
+    rom.extend((0..=255).cycle().take(400));
+    let packed = compile(&rom, true);
+    let plain = compile(&rom, false);
+    assert!(plain.code.len() - packed.code.len() > 500, "{} bytes against {}", packed.code.len(), plain.code.len());
+    let mut cpu = Z80::with_rom(&packed.code);
+    cpu.run(2_000_000, None);
+    let ram = CHIP8_RAM as usize;
+    assert_eq!(cpu.mem[ram..ram + rom.len()], rom[..]);
+    // FX65 now loads the ROM's own bytes
+    assert_eq!(cpu.mem[0x8000], 0x42);
+}
+
+#[test]
+fn sprites_draw_the_same() {
+    let plain = golden::run(&compile(IBM_LOGO, false), DEFAULT_CLOCK_HZ, 30);
+    let packed = golden::run(&compile(IBM_LOGO, true), DEFAULT_CLOCK_HZ, 30);
+    assert!(plain.iter().any(|&byte| byte != 0), "nothing drawn");
+    assert_eq!(packed, plain);
+}
+
+#[test]
+fn the_rom_must_fit_ram() {
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Cpm);
+    compiler.set_compress_rom(true);
+    let mut rom = IBM_LOGO.to_vec();
+    rom.resize(0xE01, 0);
+    assert!(compiler.compile_code(&rom).unwrap_err().contains("into the 3584 bytes of CHIP-8 RAM"));
+    let options = CompileOptions::builder().compress_rom(true).build().unwrap();
+    assert!(CompileOptions::from_json(&options.to_json()).unwrap().0.compress_rom);
+    let code = options.compiler().compile_code(IBM_LOGO).unwrap();
+    assert!(String::from_utf8_lossy(&code.code).contains("compress-rom"), "no build info");
+}
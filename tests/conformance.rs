@@ -0,0 +1,216 @@
+// Quirk conformance tests
+// Each quirk-dependent opcode is run on the Z80 emulator as compiled code
+// (folded at compile time and computed at run time) and on the embedded
+// interpreter, and all must match the behaviour documented in the README's
+// quirk table
+
+#![cfg(all(feature = "emulator", feature = "interp"))]
+
+use kz80_chip8::codegen::{Compiler, Fallback};
+use kz80_chip8::quirks::Quirks;
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+// RAM layout of the compiled runtime
+const CHIP8_V0: u16 = 0x8000;
+const CHIP8_I: u16 = 0x8010;
+const CHIP8_RAM: u16 = 0x8400;
+const DISPLAY_BUF: u16 = 0x8200;
+
+// Test bodies start here, after two register loads and the jump into the body
+const BODY: u16 = 0x206;
+
+/// How the body gets run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Folded,       // Compiled, with the operands known constants
+    Compiled,     // Compiled, with every instruction a jump target
+    Interpreted,  // Left to the embedded interpreter
+}
+
+const MODES: [Mode; 3] = [Mode::Folded, Mode::Compiled, Mode::Interpreted];
+
+/// Expected machine state after a case has run
+#[derive(Debug, Clone, Copy)]
+enum Check {
+    V(u8, u8),        // Register, value
+    I(u16),           // Value of I
+    Mem(u16, u8),     // CHIP-8 address, value
+    Display(u16, u8), // Display buffer offset, value
+}
+
+/// One opcode under one quirk setting
+struct Case {
+    name: &'static str,
+    quirk: Option<&'static str>,  // `--quirk` argument; None for the defaults
+    body: &'static [u16],         // Program at BODY, followed by 00FD
+    expect: &'static [Check],
+}
+
+const CASES: &[Case] = &[
+    // 8XY6 / 8XYE: shift Vx, or shift Vy into Vx
+    Case { name: "8XY6", quirk: None, body: &[0x6105, 0x6280, 0x8126], expect: &[Check::V(1, 0x02), Check::V(0xF, 1)] },
+    Case { name: "8XY6", quirk: Some("shift=y"), body: &[0x6105, 0x6280, 0x8126], expect: &[Check::V(1, 0x40), Check::V(0xF, 0)] },
+    Case { name: "8XYE", quirk: None, body: &[0x6181, 0x6201, 0x812E], expect: &[Check::V(1, 0x02), Check::V(0xF, 1)] },
+    Case { name: "8XYE", quirk: Some("shift=y"), body: &[0x6181, 0x6201, 0x812E], expect: &[Check::V(1, 0x02), Check::V(0xF, 0)] },
+    // FX55 / FX65: I is left alone, or advanced past the last register
+    Case {
+        name: "FX55",
+        quirk: None,
+        body: &[0xA300, 0x6001, 0x6102, 0x6203, 0xF255],
+        expect: &[Check::I(0x300), Check::Mem(0x300, 1), Check::Mem(0x301, 2), Check::Mem(0x302, 3)],
+    },
+    Case {
+        name: "FX55",
+        quirk: Some("load-store-increment"),
+        body: &[0xA300, 0x6001, 0x6102, 0x6203, 0xF255],
+        expect: &[Check::I(0x303), Check::Mem(0x300, 1), Check::Mem(0x301, 2), Check::Mem(0x302, 3)],
+    },
+    Case {
+        name: "FX65",
+        quirk: None,
+        body: &[0xA300, 0x6001, 0x6102, 0x6203, 0xF255, 0x6000, 0x6100, 0x6200, 0xA300, 0xF265],
+        expect: &[Check::I(0x300), Check::V(0, 1), Check::V(1, 2), Check::V(2, 3)],
+    },
+    Case {
+        name: "FX65",
+        quirk: Some("load-store-increment"),
+        body: &[0xA300, 0x6001, 0x6102, 0x6203, 0xF255, 0x6000, 0x6100, 0x6200, 0xA300, 0xF265],
+        expect: &[Check::I(0x303), Check::V(0, 1), Check::V(1, 2), Check::V(2, 3)],
+    },
+    // BNNN: NNN + V0, or NNN + VX with X the top nibble of NNN (V2 here).
+    // The entry at 0x214 sets VA and VB, the one at 0x216 only VB.
+    Case {
+        name: "BNNN",
+        quirk: None,
+        body: &[0x6004, 0x6206, 0xB210, 0x00FD, 0x00FD, 0x00FD, 0x00FD, 0x6A01, 0x6B01],
+        expect: &[Check::V(0xA, 1), Check::V(0xB, 1)],
+    },
+    Case {
+        name: "BNNN",
+        quirk: Some("jump-v0=vx"),
+        body: &[0x6004, 0x6206, 0xB210, 0x00FD, 0x00FD, 0x00FD, 0x00FD, 0x6A01, 0x6B01],
+        expect: &[Check::V(0xA, 0), Check::V(0xB, 1)],
+    },
+    // 8XY1 / 8XY2 / 8XY3: VF is untouched, or reset to 0
+    Case { name: "8XY1", quirk: None, body: &[0x610F, 0x62F0, 0x6F55, 0x8121], expect: &[Check::V(1, 0xFF), Check::V(0xF, 0x55)] },
+    Case { name: "8XY1", quirk: Some("vf-reset"), body: &[0x610F, 0x62F0, 0x6F55, 0x8121], expect: &[Check::V(1, 0xFF), Check::V(0xF, 0)] },
+    Case { name: "8XY2", quirk: None, body: &[0x610F, 0x62F0, 0x6F55, 0x8122], expect: &[Check::V(1, 0x00), Check::V(0xF, 0x55)] },
+    Case { name: "8XY2", quirk: Some("vf-reset"), body: &[0x610F, 0x62F0, 0x6F55, 0x8122], expect: &[Check::V(1, 0x00), Check::V(0xF, 0)] },
+    Case { name: "8XY3", quirk: None, body: &[0x610F, 0x62FF, 0x6F55, 0x8123], expect: &[Check::V(1, 0xF0), Check::V(0xF, 0x55)] },
+    Case { name: "8XY3", quirk: Some("vf-reset"), body: &[0x610F, 0x62FF, 0x6F55, 0x8123], expect: &[Check::V(1, 0xF0), Check::V(0xF, 0)] },
+    // FX55 at 0xF00: RAM, or the display buffer
+    Case {
+        name: "FX55 at F00",
+        quirk: None,
+        body: &[0xAF00, 0x60A5, 0xF055],
+        expect: &[Check::Mem(0xF00, 0xA5), Check::Display(0, 0)],
+    },
+    Case {
+        name: "FX55 at F00",
+        quirk: Some("display-ram"),
+        body: &[0xAF00, 0x60A5, 0xF055],
+        expect: &[Check::Mem(0xF00, 0), Check::Display(0, 0xA5)],
+    },
+    // DXYN at 62,30: the font's 0 wraps to the left edge and the top, or
+    // is clipped at the right edge and the bottom
+    Case {
+        name: "DXYN at the edges",
+        quirk: None,
+        body: &[0xA000, 0x633E, 0x641E, 0xD345],
+        expect: &[Check::Display(247, 0x03), Check::Display(240, 0xC0), Check::Display(7, 0x02), Check::Display(0, 0x40)],
+    },
+    Case {
+        name: "DXYN at the edges",
+        quirk: Some("clip"),
+        body: &[0xA000, 0x633E, 0x641E, 0xD345],
+        expect: &[Check::Display(247, 0x03), Check::Display(240, 0), Check::Display(7, 0), Check::Display(0, 0)],
+    },
+];
+
+/// Build the ROM for a case. The body follows a plain JP, which the
+/// compiler discovers and folds; a BNNN into a jump table at the body, which
+/// makes every instruction a possible target so nothing is folded; or a
+/// BNNN to an address it can't see, which leaves the body to the interpreter.
+fn rom(case: &Case, mode: Mode) -> Vec<u8> {
+    let prefix = match mode {
+        Mode::Folded => [0x6002, 0x6202, 0x1000 | BODY],
+        Mode::Compiled => [0x6000, 0x6200, 0xB000 | BODY],
+        Mode::Interpreted => [0x6002, 0x6202, 0xB204],
+    };
+    let mut words = prefix.to_vec();
+    words.extend_from_slice(case.body);
+    words.push(0x00FD);
+    words.iter().flat_map(|w| w.to_be_bytes()).collect()
+}
+
+/// Compile and run a case to its EXIT, returning the emulator
+fn run(case: &Case, mode: Mode) -> Z80 {
+    let mut quirks = Quirks::default();
+    if let Some(spec) = case.quirk {
+        quirks.apply(spec).unwrap();
+    }
+    let mut compiler = Compiler::new();
+    compiler.set_quirks(quirks);
+    compiler.set_fallback(Fallback::Interp);
+    let code = compiler.compile_code(&rom(case, mode)).expect("compile");
+    let body = format!("c8_{:03X}", BODY);
+    let compiled = mode != Mode::Interpreted;
+    assert_eq!(code.symbols.contains_key(&body), compiled, "{} ({:?}): body compiled as expected", describe(case), mode);
+    let mut cpu = Z80::with_rom(&code.code);
+    assert_eq!(cpu.run(5_000_000, None), StopReason::Halted, "{} did not reach EXIT", describe(case));
+    cpu
+}
+
+fn describe(case: &Case) -> String {
+    format!("{} with {}", case.name, case.quirk.unwrap_or("default quirks"))
+}
+
+#[test]
+fn compiled_and_interpreted_match_the_documented_behaviour() {
+    for case in CASES {
+        for mode in MODES {
+            let cpu = run(case, mode);
+            for check in case.expect {
+                let (actual, expected) = match *check {
+                    Check::V(x, value) => (cpu.read(CHIP8_V0 + x as u16) as u16, value as u16),
+                    Check::I(value) => (cpu.read16(CHIP8_I), value),
+                    Check::Mem(addr, value) => (cpu.read(CHIP8_RAM + addr - 0x200) as u16, value as u16),
+                    Check::Display(offset, value) => (cpu.read(DISPLAY_BUF + offset) as u16, value as u16),
+                };
+                assert_eq!(actual, expected, "{} ({:?}): {:?}", describe(case), mode, check);
+            }
+        }
+    }
+}
+
+#[test]
+fn every_documented_quirk_is_covered() {
+    let readme = include_str!("../README.md");
+    let documented: Vec<&str> = readme
+        .lines()
+        .filter_map(|line| line.strip_prefix("| `--quirk "))
+        .filter_map(|rest| rest.split('`').next())
+        .collect();
+    assert!(!documented.is_empty(), "README quirk table not found");
+    for spec in &documented {
+        assert!(CASES.iter().any(|c| c.quirk == Some(spec)), "--quirk {} has no conformance case", spec);
+        let mut quirks = Quirks::default();
+        assert!(quirks.apply(spec).is_ok(), "--quirk {} is documented but not accepted", spec);
+    }
+    for case in CASES {
+        if let Some(spec) = case.quirk {
+            assert!(documented.contains(&spec), "--quirk {} is tested but not documented", spec);
+        }
+    }
+}
+
+#[test]
+fn every_quirk_affected_opcode_is_tested_both_ways() {
+    let mut names: Vec<&str> = CASES.iter().map(|c| c.name).collect();
+    names.dedup();
+    for name in names {
+        let cases: Vec<&Case> = CASES.iter().filter(|c| c.name == name).collect();
+        assert!(cases.iter().any(|c| c.quirk.is_none()), "{} has no default case", name);
+        assert!(cases.iter().any(|c| c.quirk.is_some()), "{} has no quirk case", name);
+    }
+}
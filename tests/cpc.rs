@@ -0,0 +1,84 @@
+// Amstrad CPC target tests
+// The game must draw on the mode 1 screen, read the keyboard through the
+// PSG and come on a disc image holding an AMSDOS binary
+
+#![cfg(feature = "emulator")]
+
+mod common;
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, Target};
+use kz80_chip8::cpc;
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+fn compile(rom: &[u8]) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Cpc);
+    compiler.compile_code(rom).expect("compile")
+}
+
+/// Byte of the mode 1 screen: four pixels at column `x` of line `y`
+fn screen_byte(cpu: &Z80, x: usize, y: usize) -> u8 {
+    cpu.mem[0xC000 + (y / 8) * 80 + (y % 8) * 0x800 + x]
+}
+
+#[test]
+fn screen_shows_the_display_buffer() {
+    let code = compile(include_bytes!("../test/classic/ibm_logo.ch8"));
+    assert_eq!(code.origin, 0x4000);
+    let mut cpu = common::boot(&code);
+    assert_eq!(cpu.run(20_000_000, None), StopReason::CycleLimit);
+    let mut lit = 0;
+    for y in 0..32 {
+        for x in 0..64 {
+            let chip8 = cpu.mem[0x8200 + y * 8 + x / 8] & (0x80 >> (x % 8)) != 0;
+            for line in 0..4 {
+                let expected = if chip8 { 0xF0 } else { 0x00 };
+                assert_eq!(screen_byte(&cpu, 8 + x, 24 + y * 4 + line), expected, "({}, {})", x, y);
+            }
+            lit += chip8 as usize;
+        }
+    }
+    assert!(lit > 100);
+}
+
+#[test]
+fn keypad_is_read_through_the_psg() {
+    // FX0A into V0, then spin
+    let code = compile(&[0xF0, 0x0A, 0x12, 0x02]);
+    // Port A reads back on the low byte of the line select, 0x40 + line
+    for (line, bit, key) in [(7, 7, 0x0), (8, 0, 0x1), (6, 7, 0xF)] {
+        let mut cpu = common::boot(&code);
+        cpu.in_ports[0x40 + line] = !(1 << bit);
+        cpu.run(2_000_000, None);
+        cpu.in_ports[0x40 + line] = 0xFF;
+        cpu.run(2_000_000, None);
+        assert_eq!(cpu.mem[0x8000], key, "line {} bit {}", line, bit);
+    }
+}
+
+#[test]
+fn disc_holds_the_binary() {
+    let code = compile(include_bytes!("../test/classic/ibm_logo.ch8"));
+    let file = cpc::amsdos("ibm_logo", &code.code, code.origin);
+    assert_eq!(&file[1..12], b"IBMLOGO BIN");
+    assert_eq!(file[18], 2);
+    assert_eq!(u16::from_le_bytes([file[21], file[22]]), 0x4000);
+    assert_eq!(u16::from_le_bytes([file[26], file[27]]), 0x4000);
+    let checksum: u16 = file[..67].iter().map(|&b| b as u16).sum();
+    assert_eq!(u16::from_le_bytes([file[67], file[68]]), checksum);
+    assert_eq!(&file[128..], &code.code[..]);
+
+    let dsk = cpc::dsk("ibm_logo", &file).unwrap();
+    assert!(dsk.starts_with(b"MV - CPCEMU Disk-File\r\nDisk-Info\r\n"));
+    assert_eq!(dsk.len(), 0x100 + 40 * 0x1300);
+    // Track 0: the directory in sector C1, the file from block 2 (C5)
+    let track = &dsk[0x100..0x100 + 0x1300];
+    assert_eq!(&track[..12], b"Track-Info\r\n");
+    assert_eq!(&track[0x18..0x1A], &[0, 0]);
+    assert_eq!(track[0x1A], 0xC1);
+    let entry = &track[0x100..0x120];
+    assert_eq!(&entry[..12], b"\x00IBMLOGO BIN");
+    assert_eq!(entry[15] as usize, (file.len() + 127) / 128);
+    assert_eq!(entry[16], 2);
+    assert_eq!(&track[0x100 + 4 * 512..0x100 + 4 * 512 + 128], &file[..128]);
+}
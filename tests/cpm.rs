@@ -0,0 +1,72 @@
+// CP/M target tests
+// The .COM program must talk to the console only through the BDOS, keep
+// its stack under it, and go back to CP/M when the game ends
+
+#![cfg(feature = "emulator")]
+
+mod common;
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, Target};
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+fn compile(rom: &[u8]) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Cpm);
+    compiler.compile_code(rom).expect("compile")
+}
+
+/// Load the program into a stand-in CP/M: warm boot at 0x0000 halts, and
+/// the BDOS at `bdos` handles function 6 on the emulator's ACIA
+fn boot(code: &CompiledCode, bdos: u16) -> Z80 {
+    let mut cpu = common::boot(code);
+    cpu.load(0x0000, &[0x76]);
+    cpu.load(0x0005, &[0xC3, bdos as u8, (bdos >> 8) as u8]);
+    cpu.load(bdos, &[
+        0x79, 0xFE, 0x06, 0xC0,  // LD A,C; CP 6; RET NZ
+        0x7B, 0xFE, 0xFF, 0x28, 0x03,  // LD A,E; CP FF; JR Z,input
+        0xD3, 0x81, 0xC9,  // OUT (81),A; RET
+        0xDB, 0x80, 0xE6, 0x01, 0xC8,  // input: IN A,(80); AND 1; RET Z
+        0xDB, 0x81, 0xC9,  // IN A,(81); RET
+    ]);
+    cpu
+}
+
+#[test]
+fn game_runs_on_the_bdos_console() {
+    let code = compile(include_bytes!("../test/classic/ibm_logo.ch8"));
+    assert_eq!(code.origin, 0x0100);
+    assert!(!code.symbols.contains_key("acia_init"));
+    let mut cpu = boot(&code, 0xE406);
+    assert_eq!(cpu.run(3_000_000, None), StopReason::CycleLimit);
+    assert!(cpu.sp <= 0xE406 && cpu.sp > 0xE000, "SP {:04X}", cpu.sp);
+    let output = cpu.output();
+    assert!(output.contains("CHIP-8 on Z80"));
+    assert!(output.contains("####"));
+}
+
+#[test]
+fn exit_returns_to_cp_m() {
+    let code = compile(&[0x60, 0x07, 0x00, 0xFD]);  // LD V0,7; EXIT
+    let mut cpu = boot(&code, 0xE406);
+    assert_eq!(cpu.run(1_000_000, None), StopReason::Halted);
+    assert_eq!(cpu.pc, 0x0001);
+    assert_eq!(cpu.read(0x8000), 7);
+}
+
+#[test]
+fn small_tpa_is_refused() {
+    let code = compile(&[0x00, 0xFD]);
+    let mut cpu = boot(&code, 0x9006);
+    assert_eq!(cpu.run(1_000_000, None), StopReason::Halted);
+    assert_eq!(cpu.output(), "Not enough memory: the TPA must reach 9300\r\n");
+}
+
+#[test]
+fn keys_come_from_the_console() {
+    // FX0A into V0, then spin
+    let code = compile(&[0xF0, 0x0A, 0x12, 0x02]);
+    let mut cpu = boot(&code, 0xE406);
+    cpu.send(b"b");
+    cpu.run(2_000_000, None);
+    assert_eq!(cpu.read(0x8000), 0x0B);
+}
@@ -0,0 +1,108 @@
+// CTC timer tests
+// With --timer ctc the 60Hz timers must tick from the CTC's interrupt,
+// keeping time whatever the game is doing, and builds that need IM 1 or
+// print from the ticks must be refused
+
+#![cfg(feature = "emulator")]
+
+mod common;
+
+use kz80_chip8::codegen::{Audio, CompiledCode, Target, Timer, CHIP8_ST, FRAME_COUNT};
+use kz80_chip8::israudit;
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::{Ctc, Z80};
+
+const PORT: u8 = 0x88;
+const CLOCK: u64 = 4_000_000;
+
+fn build(rom: &[u8], timer: Timer) -> CompiledCode {
+    let options = CompileOptions::builder().timer(timer).beeper(0x40, 0).build().unwrap();
+    options.compiler().compile_code(rom).expect("compile")
+}
+
+/// The build started on a board with a CTC at PORT
+fn start(code: &CompiledCode) -> Z80 {
+    let mut cpu = common::boot(code);
+    cpu.ctc = Some(Ctc::new(PORT));
+    cpu
+}
+
+/// Frames the runtime ticks in a second of the board's time
+fn frames_per_second(code: &CompiledCode) -> u32 {
+    let mut cpu = start(code);
+    cpu.run(CLOCK / 10, None);
+    let mut frames = 0;
+    let mut count = cpu.mem[FRAME_COUNT as usize];
+    let end = cpu.cycles + CLOCK;
+    while cpu.cycles < end {
+        cpu.run(1000, None);
+        if cpu.mem[FRAME_COUNT as usize] != count {
+            count = cpu.mem[FRAME_COUNT as usize];
+            frames += 1;
+        }
+    }
+    frames
+}
+
+#[test]
+fn frames_keep_time_whatever_the_game_does() {
+    let idle = [0x12, 0x00];  // JP 200
+    let drawing = [0xA2, 0x06, 0xD0, 0x1F, 0x12, 0x02];  // LD I, 206 / DRW V0, V1, 15 / JP 202
+    let ctc = Timer::Ctc { port: PORT };
+    for rom in [&idle[..], &drawing[..]] {
+        let frames = frames_per_second(&build(rom, ctc));
+        assert!((59..=61).contains(&frames), "{} frames a second", frames);
+    }
+    // No weights are counted at the merge points, and the vector is the
+    // table's at 0040
+    let code = build(&drawing, ctc);
+    let program = |code: &CompiledCode| code.symbols["halt"] - code.symbols["main"];
+    assert!(program(&code) < program(&build(&drawing, Timer::Cycles)));
+    let mut cpu = start(&code);
+    cpu.run(CLOCK / 10, None);
+    assert_eq!((cpu.im, cpu.i, cpu.ctc.as_ref().unwrap().vector), (2, 0x00, 0x40));
+}
+
+#[test]
+fn the_interrupt_counts_the_timers_down() {
+    // LD V0, 60 / LD DT, V0 / LD ST, V0 / JP 206
+    let code = build(&[0x60, 0x3C, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06], Timer::Ctc { port: PORT });
+    let mut cpu = start(&code);
+    cpu.access_log = Some(Vec::new());
+    while cpu.mem[CHIP8_ST as usize] == 0 {
+        cpu.run(1, None);
+    }
+    let fired = cpu.ctc.as_ref().unwrap().fired;
+    cpu.run(CLOCK / 2, None);
+    let (dt, st) = (cpu.mem[CHIP8_ST as usize - 1], cpu.mem[CHIP8_ST as usize]);
+    assert!((28..=32).contains(&dt) && dt == st, "DT {} ST {}", dt, st);
+    assert_eq!(cpu.ports[0x40], 0x01, "the beeper is off");
+    // Two interrupts a frame at 4MHz: 130 x 256 T-states apart
+    let fired = cpu.ctc.as_ref().unwrap().fired - fired;
+    assert!((58..=62).contains(&fired), "{} interrupts", fired);
+    let audit = israudit::audit(cpu.access_log.as_deref().unwrap());
+    assert!(audit.isr_writes.contains(&(CHIP8_ST - 1)) && audit.isr_writes.contains(&CHIP8_ST));
+    assert_eq!(audit.races().count(), 0, "{:?}", audit.races().collect::<Vec<_>>());
+    // Run out, they stop, and so does the beeper
+    cpu.run(CLOCK, None);
+    assert_eq!((cpu.mem[CHIP8_ST as usize - 1], cpu.mem[CHIP8_ST as usize], cpu.ports[0x40]), (0, 0, 0));
+}
+
+#[test]
+fn builds_that_need_im_1_or_print_from_the_tick_are_refused() {
+    let ctc = Timer::Ctc { port: PORT };
+    let compile = |options: CompileOptions| options.compiler().compile_code(&[0x12, 0x00]).map(|_| ()).unwrap_err();
+    let options = CompileOptions::builder().timer(ctc);
+    assert!(compile(options.clone().rx_buffer(true).build().unwrap()).contains("--rx-buffer"));
+    assert!(compile(options.clone().audio(Audio::Bell).build().unwrap()).contains("--audio bell"));
+    assert!(compile(options.clone().target(Target::Spectrum).build().unwrap()).contains("RetroShield or RC2014"));
+    assert!(compile(options.clone().origin(0x1004).build().unwrap()).contains("multiple of 8"));
+    // The port is in the build info, and names round-trip
+    let code = build(&[0x12, 0x00], ctc);
+    assert!(String::from_utf8_lossy(&code.code).contains("timer=ctc:88"));
+    assert_eq!(Timer::from_name(&ctc.name()), Some(ctc));
+    assert_eq!(Timer::from_name("ctc:136"), Some(ctc));
+    assert_eq!(Timer::from_name("ctc:0x100"), None);
+    let rc2014 = CompileOptions::builder().target(Target::Rc2014).timer(ctc).build().unwrap();
+    assert!(rc2014.compiler().compile_code(&[0x12, 0x00]).is_ok());
+}
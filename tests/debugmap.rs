@@ -0,0 +1,65 @@
+// Debug map tests
+// Every compiled instruction must map to the Z80 code that runs it, so an
+// address seen on the bus leads back to the CHIP-8 instruction behind it
+
+#![cfg(feature = "cli")]
+
+use kz80_chip8::codegen::{Compiler, Layout};
+use kz80_chip8::debugmap::DebugMap;
+use kz80_chip8::z80emu::Z80;
+use std::process::Command;
+
+const PONG: &[u8] = include_bytes!("../test/classic/pong.ch8");
+
+#[test]
+fn instructions_tile_the_program() {
+    for layout in [Layout::Source, Layout::Loops] {
+        let mut compiler = Compiler::new();
+        compiler.set_layout(layout);
+        let code = compiler.compile_code(PONG).expect("compile");
+        let map = DebugMap::new(&code);
+        let mut spans: Vec<_> = map.instructions.iter().map(|span| span.z80.clone()).collect();
+        spans.sort_by_key(|span| (span.start, span.end));
+        assert!(spans.first().unwrap().start >= code.symbols["main"]);
+        assert_eq!(spans.last().unwrap().end, code.symbols["halt"]);
+        assert!(spans.windows(2).all(|pair| pair[0].end == pair[1].start), "gaps or overlaps");
+        for span in &map.instructions {
+            assert_eq!(span.z80.start, code.symbols[&format!("c8_{:03X}", span.chip8)]);
+        }
+    }
+}
+
+#[test]
+fn bus_addresses_lead_back_to_the_game() {
+    // LD V0, 1 / ADD V0, 1 / JP 202
+    let code = Compiler::new().compile_code(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]).expect("compile");
+    let map = DebugMap::new(&code);
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.run(2_000_000, None);
+    assert!(map.chip8_at(cpu.pc).is_some() || map.label_at(cpu.pc).is_some(), "{:04X}", cpu.pc);
+    cpu.run(2_000_000, Some(code.symbols["c8_204"]));
+    assert_eq!(map.chip8_at(cpu.pc), Some(0x204));
+    let cls = code.symbols["cls"];
+    assert_eq!(map.chip8_at(cls), None);
+    assert_eq!(map.label_at(cls), Some("cls"));
+    assert_eq!(map.label_at(code.symbols["main"]), Some("main"));
+}
+
+#[test]
+fn compile_writes_the_map() {
+    let path = std::env::temp_dir().join(format!("kz80_chip8_map_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    let output = std::env::temp_dir().join(format!("kz80_chip8_map_{}.bin", std::process::id()));
+    let result = Command::new(env!("CARGO_BIN_EXE_kz80_chip8"))
+        .args(["compile", "test/classic/ibm_logo.ch8", "--debug-map", path, "-o", output.to_str().unwrap()])
+        .output()
+        .expect("run kz80_chip8");
+    assert!(result.status.success());
+    let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(output).unwrap();
+    assert_eq!(json["version"], 1);
+    assert_eq!(json["instructions"][0]["chip8"], 0x200);
+    let labels = json["labels"].as_array().unwrap();
+    assert!(labels.iter().any(|label| label["name"] == "draw_sprite" && label["end"].as_u64() > label["start"].as_u64()));
+}
@@ -0,0 +1,80 @@
+// Debug stub tests
+// Ctrl-B on the console must stop the game in the monitor, which shows the
+// CHIP-8 state and can step through the game an instruction at a time
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, Target, DEFAULT_CLOCK_HZ};
+use kz80_chip8::golden;
+use kz80_chip8::z80emu::Z80;
+
+// LD V0, 5 / LD I, 300 / LD [I], V0 / CALL 20A / JP 208 / LD V2, K / RET
+const ROM: &[u8] = &[0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x22, 0x0A, 0x12, 0x08, 0xF2, 0x0A, 0x00, 0xEE];
+
+fn debug_build(rom: &[u8]) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_debug_stub(true);
+    compiler.compile_code(rom).expect("compile")
+}
+
+/// Console output after typing `input` into the running game
+fn session(input: &[u8]) -> (CompiledCode, String) {
+    session_with(ROM, input)
+}
+
+fn session_with(rom: &[u8], input: &[u8]) -> (CompiledCode, String) {
+    let code = debug_build(rom);
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.send(input);
+    cpu.run(5_000_000, None);
+    (code, cpu.output())
+}
+
+#[test]
+fn monitor_shows_the_chip8_state() {
+    let (code, output) = session(b"\x02rsm300\rc");
+    assert!(output.contains("\r\nV 05 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\r\nI 0300 DT 00 ST 00 SP 01"), "{}", output);
+    assert!(output.contains(&format!("\r\nS {:04X}", code.symbols["c8_208"])), "{}", output);
+    assert!(output.contains("\r\n0300: 05 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"), "{}", output);
+    let (_, output) = session(b"\x02m10\rx");
+    assert!(output.contains("m10?"), "{}", output);
+    assert!(output.contains("r regs, s stack, m ADDR memory, w WHAT watch, b ADDR break, n step, c continue"), "{}", output);
+}
+
+#[test]
+fn stepping_stops_at_each_instruction() {
+    // Step out of the key wait, which takes key 7, then out of the call
+    let (_, output) = session(b"\x02n7rnc");
+    let stops: Vec<&str> = output.matches("Stopped at ").collect();
+    assert_eq!(stops.len(), 2, "{}", output);
+    assert!(output.contains("Stopped at 020C\r\ndebug> r\r\nV 05 00 07"), "{}", output);
+    assert!(output.contains("Stopped at 0208\r\ndebug> c"), "{}", output);
+}
+
+#[test]
+fn watches_print_changes_at_block_starts() {
+    // LD I, 300 / LD V5, E / ADD V4, 1 / SKNP V5 / JP 208 / LD [I], V4 / JP 204
+    let rom = [0xA3, 0x00, 0x65, 0x0E, 0x74, 0x01, 0xE5, 0xA1, 0x12, 0x08, 0xF4, 0x55, 0x12, 0x04];
+    let (_, output) = session_with(&rom, b"\x02wv4\rw i\rw0x300..0x304\rc");
+    assert!(output.contains("\r\nWatch 0300: 00 00 00 00 01 at 0204\r\nWatch V4: 02 at 020A\r\nWatch 0300: 00 00 00 00 02"), "{}", output);
+    assert!(!output.contains("Watch I"), "{}", output);
+    let (_, output) = session_with(&rom, b"\x02w V4\rw\rc");
+    assert!(!output.contains("Watch V4"), "{}", output);
+    let (_, output) = session(b"\x02wv4\rwv5\rwv6\rwv7\rwv8\rw200..210\rc");
+    assert!(output.contains("wv8 full") && output.contains("w200..210?"), "{}", output);
+}
+
+#[test]
+fn the_stub_leaves_the_game_alone() {
+    let logo = include_bytes!("../test/classic/ibm_logo.ch8");
+    let plain = Compiler::new().compile_code(logo).expect("compile");
+    let code = debug_build(logo);
+    assert!(code.symbols.contains_key("debug_monitor") && !plain.symbols.contains_key("debug_monitor"));
+    assert_eq!(golden::run(&code, DEFAULT_CLOCK_HZ, 60), golden::run(&plain, DEFAULT_CLOCK_HZ, 60));
+    for (target, ok) in [(Target::Rc2014, true), (Target::Cpm, true), (Target::Spectrum, false), (Target::Coleco, false)] {
+        let mut compiler = Compiler::new();
+        compiler.set_target(target);
+        compiler.set_debug_stub(true);
+        assert_eq!(compiler.compile_code(logo).is_ok(), ok, "{}", target.name());
+    }
+}
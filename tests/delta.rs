@@ -0,0 +1,92 @@
+// Delta display tests
+// A delta build must send only the display bytes that changed, and the
+// host's decoder must rebuild the display from them, or from a resync
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, Target, DISPLAY_BUF};
+use kz80_chip8::delta::{self, Decoder};
+use kz80_chip8::z80emu::Z80;
+
+// CLS / LD V0, 8 / LD V1, 10 / LD I, 210 / DRW V0, V1, 3 / SKP V1 / JP 20A / JP 20A
+const ROM: &[u8] = &[
+    0x00, 0xE0, 0x60, 0x08, 0x61, 0x0A, 0xA2, 0x10, 0xD0, 0x13, 0xE1, 0x9E, 0x12, 0x0A, 0x12, 0x0A, 0xF0, 0x90, 0xF0,
+];
+
+fn delta_code(rom: &[u8]) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_delta_display(true);
+    compiler.compile_code(rom).expect("compile")
+}
+
+fn delta_build(rom: &[u8]) -> Z80 {
+    Z80::with_rom(&delta_code(rom).code)
+}
+
+fn display(cpu: &Z80) -> &[u8] {
+    &cpu.mem[DISPLAY_BUF as usize..DISPLAY_BUF as usize + 256]
+}
+
+#[test]
+fn a_draw_sends_only_its_bytes() {
+    let mut cpu = delta_build(ROM);
+    cpu.run(3_000_000, None);
+    assert!(!cpu.tx.contains(&0x1B), "no ANSI: {:?}", cpu.output());
+    // The clears change nothing; the sprite is three bytes in column 1
+    let frame = cpu.tx.iter().position(|&byte| byte == delta::FRAME).unwrap();
+    assert_eq!(cpu.tx[frame..], [delta::FRAME, 2, 81, 0xF0, 89, 0x90, 97, 0xF0]);
+    let mut decoder = Decoder::new();
+    let (text, drawn) = decoder.feed(&cpu.tx);
+    assert!(drawn && String::from_utf8(text).unwrap().contains("CHIP-8 on Z80"));
+    assert_eq!(&decoder.display()[..], display(&cpu));
+    assert!(decoder.render().lines().nth(11).unwrap().starts_with("        #  #"));
+}
+
+#[test]
+fn the_decoder_keeps_up_with_a_game() {
+    let code = delta_code(include_bytes!("../test/classic/pong.ch8"));
+    let mut cpu = Z80::with_rom(&code.code);
+    let mut decoder = Decoder::new();
+    let mut seen = 0;
+    for _ in 0..30 {
+        // Stopping between a byte kept and the byte sent, they'd differ
+        cpu.run(500_000, None);
+        cpu.run(500_000, Some(code.symbols["delta_done"]));
+        decoder.feed(&cpu.tx[seen..]);
+        seen = cpu.tx.len();
+        // As sent: the copy the board keeps
+        assert_eq!(&decoder.display()[..], &cpu.mem[0xF200..0xF300]);
+    }
+    assert!(decoder.frames() > 5, "{} frames", decoder.frames());
+    assert!(decoder.display().iter().any(|&byte| byte != 0));
+}
+
+#[test]
+fn a_resync_sends_everything() {
+    let mut cpu = delta_build(ROM);
+    cpu.run(3_000_000, None);
+    let before = cpu.tx.len();
+    // A decoder that missed the game so far, as when the host starts late
+    cpu.send(&[delta::RESYNC]);
+    cpu.run(1_000_000, None);
+    let resent = &cpu.tx[before..];
+    assert_eq!(&resent[..2], [delta::FRAME, 255]);
+    assert_eq!(resent.len(), 2 + 2 * 256);
+    let mut decoder = Decoder::new();
+    decoder.feed(resent);
+    assert_eq!(&decoder.display()[..], display(&cpu));
+}
+
+#[test]
+fn delta_builds_need_the_serial_display() {
+    let compile = |setup: &dyn Fn(&mut Compiler)| {
+        let mut compiler = Compiler::new();
+        compiler.set_delta_display(true);
+        setup(&mut compiler);
+        compiler.compile_code(ROM).err().unwrap_or_default()
+    };
+    assert!(compile(&|compiler| compiler.set_target(Target::Cpm)).contains("not a cpm build"));
+    assert!(compile(&|compiler| compiler.set_framebuffer(0x6000, 8)).contains("pick one"));
+    assert!(compile(&|compiler| compiler.set_profile_gen(true)).contains("profile-gen"));
+    assert!(compile(&|compiler| compiler.set_target(Target::Rc2014)).is_empty());
+}
@@ -0,0 +1,55 @@
+// Diagnostic level tests
+// Each category must be silenced, reported or made fatal as set, from the
+// library and from --warn
+
+use kz80_chip8::codegen::Compiler;
+use kz80_chip8::diagnostics::{Category, Level, Levels};
+
+// 200: LD I,206; 202: LD [I],V0 (onto the EXIT); 204: JP 206; 206: EXIT
+const SELF_MODIFYING: [u8; 8] = [0xA2, 0x06, 0xF0, 0x55, 0x12, 0x06, 0x00, 0xFD];
+// 200: 5XY1, not an instruction; 202: EXIT
+const UNKNOWN_OPCODE: [u8; 4] = [0x50, 0x01, 0x00, 0xFD];
+
+fn compile(rom: &[u8], levels: &[(Category, Level)]) -> Result<Vec<Category>, String> {
+    let mut compiler = Compiler::new();
+    for &(category, level) in levels {
+        compiler.set_level(category, level);
+    }
+    let code = compiler.compile_code(rom)?;
+    assert_eq!(code.diagnostics, compiler.diagnostics());
+    Ok(code.diagnostics.iter().map(|d| d.category).collect())
+}
+
+#[test]
+fn self_modifying_stores_fail_unless_lowered() {
+    let err = compile(&SELF_MODIFYING, &[]).unwrap_err();
+    assert!(err.contains("--allow-self-modify"), "{}", err);
+    assert_eq!(compile(&SELF_MODIFYING, &[(Category::SelfModify, Level::Warn)]), Ok(vec![Category::SelfModify]));
+    assert_eq!(compile(&SELF_MODIFYING, &[(Category::SelfModify, Level::Allow)]), Ok(vec![]));
+
+    let mut compiler = Compiler::new();
+    compiler.set_allow_self_modify(true);
+    let code = compiler.compile_code(&SELF_MODIFYING).unwrap();
+    assert_eq!(code.diagnostics[0].to_string(), "Warning: 202: stores 1 byte(s) at 206, overwriting code (self-modifying ROM) [self-modify]");
+}
+
+#[test]
+fn unknown_opcodes_warn_unless_raised() {
+    assert_eq!(compile(&UNKNOWN_OPCODE, &[]), Ok(vec![Category::UnknownOpcode]));
+    assert_eq!(compile(&UNKNOWN_OPCODE, &[(Category::UnknownOpcode, Level::Allow)]), Ok(vec![]));
+    let err = compile(&UNKNOWN_OPCODE, &[(Category::UnknownOpcode, Level::Error)]).unwrap_err();
+    assert_eq!(err, "200: unknown opcode 5001, compiled as a NOP [unknown-opcode=error]");
+}
+
+#[test]
+fn warn_specs_name_a_category_and_level() {
+    let mut levels = Levels::default();
+    levels.apply("self-modify=allow").unwrap();
+    levels.apply("unknown-opcode=error").unwrap();
+    assert_eq!(levels.get(Category::SelfModify), Some(Level::Allow));
+    assert_eq!(levels.get(Category::UnknownOpcode), Some(Level::Error));
+    assert_eq!(levels.get(Category::SkipTarget), None);
+    for bad in ["self-modify", "bogus=warn", "self-modify=loud"] {
+        assert!(levels.apply(bad).is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,82 @@
+// Dirty row tests
+// The ANSI display must resend only the rows a draw touched, each after its
+// own cursor move, and still leave the terminal showing the display
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Compiler, Target, DISPLAY_BUF};
+use kz80_chip8::z80emu::Z80;
+
+// CLS / LD V0, 0 / LD V1, 10 / LD I, 20C / DRW V0, V1, 3 / JP 20A
+const ROM: &[u8] = &[0x00, 0xE0, 0x60, 0x00, 0x61, 0x0A, 0xA2, 0x0C, 0xD0, 0x13, 0x12, 0x0A, 0xF0, 0x90, 0xF0];
+
+/// Play ANSI output onto a 32x64 terminal of '#' and ' ', for the rows
+/// each placed by a cursor move
+fn terminal(output: &str, screen: &mut [String]) {
+    for part in output.split("\x1b[").skip(1) {
+        let Some((row, rest)) = part.split_once(";1H") else { continue };
+        let Ok(row) = row.parse::<usize>() else { continue };
+        if (2..34).contains(&row) && rest.len() >= 64 {
+            screen[row - 2] = rest[..64].to_string();
+        }
+    }
+}
+
+/// A display buffer row as the terminal shows it
+fn row(cpu: &Z80, row: usize) -> String {
+    let start = DISPLAY_BUF as usize + row * 8;
+    cpu.mem[start..start + 8]
+        .iter()
+        .flat_map(|byte| (0..8).map(move |bit| if byte << bit & 0x80 != 0 { '#' } else { ' ' }))
+        .collect()
+}
+
+#[test]
+fn a_draw_resends_only_its_rows() {
+    let code = Compiler::new().compile_code(ROM).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.run(3_000_000, Some(code.symbols["c8_20A"]));
+    let output = cpu.output();
+    // Everything after the clear's last row
+    let (_, after) = output.rsplit_once("\x1b[33;1H").unwrap();
+    let moves: Vec<&str> = after.split("\x1b[").skip(1).map(|part| &part[..part.find('H').unwrap() + 1]).collect();
+    assert_eq!(moves, ["12;1H", "13;1H", "14;1H"]);
+    assert!(after.contains("\x1b[13;1H#  #    "), "{:?}", after);
+}
+
+#[test]
+fn the_terminal_keeps_up_with_the_display() {
+    let code = Compiler::new().compile_code(include_bytes!("../test/classic/pong.ch8")).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    let mut screen = vec![String::new(); 32];
+    let mut seen = 0;
+    let refresh = code.symbols["refresh_rows"];
+    for refreshes in 0..40 {
+        cpu.run(2_000_000, Some(refresh));
+        let output = cpu.output();
+        terminal(&output[seen..], &mut screen);
+        seen = output.len();
+        // Rows not marked since the last refresh must already be right
+        for (r, shown) in screen.iter().enumerate() {
+            if cpu.mem[0x80E0 + r] == 0 {  // Its DIRTY_ROWS flag
+                assert_eq!(*shown, row(&cpu, r), "row {} after {} refreshes", r, refreshes);
+            }
+        }
+    }
+}
+
+#[test]
+fn only_ansi_displays_track_rows() {
+    let symbols = |target: Target, framebuffer: Option<(u16, u16)>| {
+        let mut compiler = Compiler::new();
+        compiler.set_target(target);
+        if let Some((base, stride)) = framebuffer {
+            compiler.set_framebuffer(base, stride);
+        }
+        compiler.compile_code(ROM).expect("compile").symbols
+    };
+    assert!(symbols(Target::Rc2014, None).contains_key("refresh_rows"));
+    assert!(symbols(Target::Cpm, None).contains_key("refresh_rows"));
+    assert!(!symbols(Target::RetroShield, Some((0x6000, 8))).contains_key("refresh_rows"));
+    assert!(!symbols(Target::Spectrum, None).contains_key("refresh_rows"));
+}
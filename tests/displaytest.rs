@@ -0,0 +1,70 @@
+// Display test kit tests
+// The kit must print the checksums worked out on the host for every video
+// memory layout, catch a display that doesn't show what it should, and
+// read its lines back out of a capture
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Orientation, Scale, Target, DEFAULT_CLOCK_HZ};
+use kz80_chip8::displaytest::{self, Checkpoint};
+use kz80_chip8::golden;
+use kz80_chip8::options::CompileOptions;
+
+/// Checkpoints the kit printed running in the emulator, and the ones
+/// expected of it
+fn run(options: &CompileOptions) -> (Vec<Checkpoint>, Vec<Checkpoint>) {
+    let (code, expected) = displaytest::build(options).expect("build");
+    let cpu = golden::emulate(&code, DEFAULT_CLOCK_HZ, 600);
+    (displaytest::parse(&cpu.output()), expected)
+}
+
+#[test]
+fn every_layout_prints_its_expected_checksums() {
+    let layouts = [
+        (None, Orientation::default(), Scale::Single),
+        (Some((0x6000, 10)), Orientation::default(), Scale::Single),
+        (Some((0x6000, 4)), Orientation { rotate: 90, mirror: true, lsb_first: false }, Scale::Single),
+        (Some((0x6000, 8)), Orientation { rotate: 180, mirror: false, lsb_first: true }, Scale::Single),
+        (Some((0x6000, 16)), Orientation::default(), Scale::Double),
+        (Some((0x6000, 8)), Orientation { rotate: 270, ..Orientation::default() }, Scale::Scanlines),
+    ];
+    for (framebuffer, orientation, scale) in layouts {
+        let mut builder = CompileOptions::builder().orientation(orientation).scale(scale);
+        if let Some((base, stride)) = framebuffer {
+            builder = builder.framebuffer(base, stride);
+        }
+        let (printed, expected) = run(&builder.build().unwrap());
+        assert_eq!(printed, expected, "{:?} {:?} {:?}", framebuffer, orientation, scale);
+        assert_eq!(expected.len(), displaytest::STEPS.len());
+        assert_eq!(expected[6].display, 0, "the display was cleared");
+    }
+}
+
+#[test]
+fn a_display_drawn_wrong_fails() {
+    // Checked against an upright layout, the turned copy matches only
+    // where nothing is drawn
+    let upright = CompileOptions::builder().framebuffer(0x6000, 8).build().unwrap();
+    let turned = CompileOptions::builder().framebuffer(0x6000, 8).orientation(Orientation { rotate: 180, ..Orientation::default() }).build().unwrap();
+    let (printed, _) = run(&turned);
+    let (_, expected) = displaytest::build(&upright).unwrap();
+    for (printed, expected) in printed.iter().zip(&expected) {
+        assert_eq!(printed.display, expected.display);
+        assert_eq!(printed.video == expected.video, printed.step == 7, "{} against {}", printed, expected);
+    }
+}
+
+#[test]
+fn captures_and_targets() {
+    let capture = "CHIP-8 on Z80\r\n\x1b[H\r\nD01 0135 5A5A\r\nD02 039F\r\nDRW\r\nD03 85CC 1 2\r\n";
+    assert_eq!(
+        displaytest::parse(capture),
+        [Checkpoint { step: 1, display: 0x0135, video: Some(0x5A5A) }, Checkpoint { step: 2, display: 0x039F, video: None }]
+    );
+    assert_eq!(Checkpoint { step: 8, display: 0xC9F2, video: Some(0x0042) }.to_string(), "D08 C9F2 0042");
+    let spectrum = CompileOptions::builder().target(Target::Spectrum).build().unwrap();
+    assert!(displaytest::build(&spectrum).unwrap_err().contains("serial port"));
+    // Outside the kit SYS is still ignored
+    let code = CompileOptions::default().compiler().compile_code(displaytest::ROM).unwrap();
+    assert!(!code.symbols.contains_key("display_checkpoint"));
+}
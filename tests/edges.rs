@@ -0,0 +1,63 @@
+// Sprite edge tests
+// Sprites running off the right or bottom edge must wrap within the
+// display, or be clipped with the clip quirk, and never draw past it
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Compiler, DEFAULT_CLOCK_HZ, DISPLAY_BUF, FONT_DATA};
+use kz80_chip8::golden;
+use kz80_chip8::quirks::Quirks;
+use kz80_chip8::z80emu::Z80;
+
+/// Machine after a ROM has run for a few frames, clipping or not
+fn run(rom: &[u8], clip: bool) -> Z80 {
+    let mut compiler = Compiler::new();
+    compiler.set_quirks(Quirks { clip, ..Quirks::default() });
+    golden::emulate(&compiler.compile_code(rom).expect("compile"), DEFAULT_CLOCK_HZ, 10)
+}
+
+#[test]
+fn rows_past_the_bottom_stay_on_the_display() {
+    // LD V0, 0 / LD V1, 1F / LD I, 20C / DRW V0, V1, 4 / EXIT / (pad)
+    // / 20C: FF FF FF FF
+    let rom = [0x60, 0x00, 0x61, 0x1F, 0xA2, 0x0C, 0xD0, 0x14, 0x00, 0xFD, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF];
+    let display = DISPLAY_BUF as usize;
+    let font = FONT_DATA as usize;
+    let wrapped = run(&rom, false);
+    assert_eq!(wrapped.mem[display + 31 * 8], 0xFF);
+    assert_eq!(wrapped.mem[display..display + 24].iter().step_by(8).collect::<Vec<_>>(), [&0xFF; 3]);
+    assert_eq!(wrapped.mem[font..font + 5], [0xF0, 0x90, 0x90, 0x90, 0xF0], "drawn over the font");
+    let clipped = run(&rom, true);
+    assert_eq!(clipped.mem[display + 31 * 8], 0xFF);
+    assert!(clipped.mem[display..display + 31 * 8].iter().all(|&byte| byte == 0));
+    assert_eq!(clipped.mem[font..font + 5], [0xF0, 0x90, 0x90, 0x90, 0xF0], "drawn over the font");
+}
+
+#[test]
+fn clipped_pixels_never_collide() {
+    // LD V0, 0 / LD I, 20E / DRW V0, V0, 1 / LD V1, 3C / LD V2, 1F
+    // / DRW V1, V2, 2 / EXIT / 20E: F8 F8, the second draw's right part and
+    // second row landing on the first's only when they wrap
+    let rom = [0x60, 0x00, 0xA2, 0x0E, 0xD0, 0x01, 0x61, 0x3C, 0x62, 0x1F, 0xD1, 0x22, 0x00, 0xFD, 0xF8, 0xF8];
+    assert_eq!(run(&rom, false).mem[0x800F], 1);
+    let clipped = run(&rom, true);
+    assert_eq!(clipped.mem[0x800F], 0);
+    assert_eq!(clipped.mem[DISPLAY_BUF as usize..DISPLAY_BUF as usize + 8], [0xF8, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(clipped.mem[DISPLAY_BUF as usize + 255], 0x0F);
+}
+
+#[test]
+fn the_quirk_is_named_clip_or_wrap() {
+    let mut quirks = Quirks::default();
+    quirks.apply("clip").unwrap();
+    assert!(quirks.clip);
+    assert_eq!(quirks.names(), ["clip"]);
+    quirks.apply("wrap").unwrap();
+    assert!(!quirks.clip);
+    quirks.apply("wrap=off").unwrap();
+    assert!(quirks.clip);
+    let mut compiler = Compiler::new();
+    compiler.set_quirks(quirks);
+    let code = compiler.compile_code(&[0x12, 0x00]).expect("compile");
+    assert!(String::from_utf8_lossy(&code.code).contains("clip"), "no build info");
+}
@@ -0,0 +1,59 @@
+// Exit code tests
+// Each kind of failure exits with its own status, so that scripts can tell
+// a missing ROM from an oversized build or a broken display
+
+#![cfg(feature = "cli")]
+
+use std::process::Command;
+
+fn status(args: &[&str]) -> Option<i32> {
+    let output = std::env::temp_dir().join(format!("kz80_chip8_exit_{}.bin", std::process::id()));
+    let result = Command::new(env!("CARGO_BIN_EXE_kz80_chip8"))
+        .args(args)
+        .args(["-o", output.to_str().unwrap()])
+        .output()
+        .expect("run kz80_chip8");
+    let _ = std::fs::remove_file(output);
+    result.status.code()
+}
+
+#[test]
+fn compile_failures_have_their_own_codes() {
+    assert_eq!(status(&["compile", "test/no_such_rom.ch8"]), Some(3));
+    assert_eq!(status(&["compile", "test/hello.ch8", "--warn", "unknown-opcode=error"]), Some(7));
+    assert_eq!(status(&["compile", "test/hello.ch8", "--max-size", "100"]), Some(5));
+    assert_eq!(status(&["compile", "test/hello.ch8", "--fail-on", "size>"]), Some(2));
+}
+
+#[test]
+fn fail_on_rules_pick_the_status() {
+    assert_eq!(status(&["compile", "test/hello.ch8"]), Some(0));
+    assert_eq!(status(&["compile", "test/hello.ch8", "--fail-on", "warnings"]), Some(7));
+    assert_eq!(status(&["compile", "test/hello.ch8", "--fail-on", "size>100"]), Some(5));
+    assert_eq!(status(&["compile", "test/classic/pong.ch8", "--fail-on", "warnings", "--fail-on", "verify"]), Some(0));
+    // LD I, 100 / LD V0, [I] / JP 204: I below 0x200 traps
+    let trap = std::env::temp_dir().join(format!("kz80_chip8_trap_{}.ch8", std::process::id()));
+    std::fs::write(&trap, [0xA1, 0x00, 0xF0, 0x65, 0x12, 0x04]).unwrap();
+    assert_eq!(status(&["compile", trap.to_str().unwrap(), "--fail-on", "verify"]), Some(6));
+    std::fs::remove_file(&trap).unwrap();
+    assert_eq!(status(&["banks", "test/classic/pong.ch8", "test/hello.ch8", "--fail-on", "warnings", "--quiet"]), Some(7));
+}
+
+#[test]
+fn golden_mismatches_are_verify_failures() {
+    let goldens = std::env::temp_dir().join(format!("kz80_chip8_exit_{}.txt", std::process::id()));
+    std::fs::write(&goldens, "test/classic/maze.ch8 5 0123456789abcdef\n").unwrap();
+    let golden = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_kz80_chip8"))
+            .args(["golden", "test/classic/maze.ch8", "--frames", "5", "--quiet", "--goldens", goldens.to_str().unwrap()])
+            .args(extra)
+            .output()
+            .expect("run kz80_chip8")
+            .status
+            .code()
+    };
+    assert_eq!(golden(&[]), Some(6));
+    assert_eq!(golden(&["--fail-on", "size>100"]), Some(5));
+    std::fs::remove_file(&goldens).unwrap();
+    assert_eq!(golden(&[]), Some(3));
+}
@@ -0,0 +1,47 @@
+// Framebuffer display tests
+// A --framebuffer build must copy every row of the display to video memory
+// at its stride, and refuse video memory that would land on code or RAM
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Compiler, Target, DEFAULT_CLOCK_HZ, DISPLAY_BUF};
+use kz80_chip8::golden;
+
+const IBM_LOGO: &[u8] = include_bytes!("../test/classic/ibm_logo.ch8");
+
+#[test]
+fn rows_are_copied_at_the_stride() {
+    for stride in [8, 20] {
+        let mut compiler = Compiler::new();
+        compiler.set_framebuffer(0x6000, stride);
+        let code = compiler.compile_code(IBM_LOGO).expect("compile");
+        assert!(!code.code.windows(4).any(|w| w == b"\x1b[2;"), "ANSI display still built");
+        let cpu = golden::emulate(&code, DEFAULT_CLOCK_HZ, 60);
+        let display = &cpu.mem[DISPLAY_BUF as usize..DISPLAY_BUF as usize + 256];
+        assert!(display.iter().any(|&b| b != 0));
+        for row in 0..32 {
+            let at = 0x6000 + row * stride as usize;
+            assert_eq!(&cpu.mem[at..at + 8], &display[row * 8..row * 8 + 8], "row {} at stride {}", row, stride);
+        }
+    }
+}
+
+#[test]
+fn video_memory_must_be_clear_of_code_and_ram() {
+    for (base, stride, target, error) in [
+        (0x7F80, 8, Target::RetroShield, "into RAM at 8000"),
+        (0x0200, 8, Target::RetroShield, "overlaps the code"),
+        (0x6000, 4, Target::RetroShield, "shorter than a row"),
+        (0x6000, 8, Target::Msx, "not a msx build's"),
+    ] {
+        let mut compiler = Compiler::new();
+        compiler.set_target(target);
+        compiler.set_framebuffer(base, stride);
+        let result = compiler.compile_code(IBM_LOGO).map(|_| ());
+        assert!(result.as_ref().is_err_and(|e| e.contains(error)), "{:04X}: {:?}", base, result);
+    }
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Rc2014);
+    compiler.set_framebuffer(0x7000, 0x40);
+    assert!(compiler.compile_code(IBM_LOGO).is_ok());
+}
@@ -0,0 +1,120 @@
+// Glyph display tests
+// Block and braille displays must draw several pixels to a character, so
+// that the terminal decoded back into pixels matches the display buffer
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Compiler, Glyphs, Target, DISPLAY_BUF};
+use kz80_chip8::z80emu::Z80;
+
+// CLS / LD V0, 0 / LD V1, 10 / LD I, 20C / DRW V0, V1, 3 / JP 20A
+const ROM: &[u8] = &[0x00, 0xE0, 0x60, 0x00, 0x61, 0x0A, 0xA2, 0x0C, 0xD0, 0x13, 0x12, 0x0A, 0xF0, 0x90, 0xF0];
+
+// Quadrant blocks by their pixels: 1 top left, 2 top right, 4 bottom left, 8 bottom right
+const QUADRANTS: &str = " ▘▝▀▖▌▞▛▗▚▐▜▄▙▟█";
+
+fn build(glyphs: Glyphs, rom: &[u8]) -> (Z80, u16) {
+    let mut compiler = Compiler::new();
+    compiler.set_glyphs(glyphs);
+    let code = compiler.compile_code(rom).expect("compile");
+    (Z80::with_rom(&code.code), code.symbols["refresh_rows"])
+}
+
+/// Play the output's lines of glyphs onto 32 rows of 64 pixels, for the
+/// lines each placed by a cursor move
+fn terminal(output: &str, glyphs: Glyphs, screen: &mut [[bool; 64]; 32]) {
+    let rows = if glyphs == Glyphs::Blocks { 2 } else { 4 };
+    for part in output.split("\x1b[").skip(1) {
+        let Some((line, rest)) = part.split_once(";1H") else { continue };
+        let Ok(line) = line.parse::<usize>() else { continue };
+        let cells: Vec<char> = rest.chars().take(32).collect();
+        if !(2..2 + 32 / rows).contains(&line) || cells.len() < 32 {
+            continue;
+        }
+        for (x, cell) in cells.into_iter().enumerate() {
+            // Pixels as (row, column) in the glyph
+            let pixels: Vec<(usize, usize)> = match (glyphs, cell) {
+                (_, ' ') => vec![],
+                (Glyphs::Blocks, _) => {
+                    let bits = QUADRANTS.chars().position(|quadrant| quadrant == cell).expect("quadrant");
+                    [(0, 0), (0, 1), (1, 0), (1, 1)].into_iter().enumerate().filter(|(n, _)| bits >> n & 1 != 0).map(|(_, at)| at).collect()
+                }
+                _ => {
+                    let dots = cell as u32 - 0x2800;
+                    assert!(dots < 0x100, "{:?}", cell);
+                    let at = [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1), (3, 0), (3, 1)];
+                    at.into_iter().enumerate().filter(|(n, _)| dots >> n & 1 != 0).map(|(_, at)| at).collect()
+                }
+            };
+            for row in 0..rows {
+                screen[(line - 2) * rows + row][2 * x..2 * x + 2].fill(false);
+            }
+            for (row, column) in pixels {
+                screen[(line - 2) * rows + row][2 * x + column] = true;
+            }
+        }
+    }
+}
+
+fn pixel(cpu: &Z80, row: usize, column: usize) -> bool {
+    cpu.mem[DISPLAY_BUF as usize + row * 8 + column / 8] << (column % 8) & 0x80 != 0
+}
+
+#[test]
+fn a_draw_resends_only_its_lines() {
+    let (mut cpu, _) = build(Glyphs::Blocks, ROM);
+    cpu.run(3_000_000, None);
+    let output = cpu.output();
+    // Everything after the clear's last line, of 16
+    let (_, after) = output.rsplit_once("\x1b[17;1H").unwrap();
+    let moves: Vec<&str> = after.split("\x1b[").skip(1).map(|part| &part[..part.find('H').unwrap() + 1]).collect();
+    assert_eq!(moves, ["07;1H", "08;1H"]);
+    // Rows 10-11 then 12-13 of F0 90 F0
+    assert!(after.contains("\x1b[07;1H▛▜ "), "{:?}", after);
+    assert!(after.contains("\x1b[08;1H▀▀ "), "{:?}", after);
+}
+
+#[test]
+fn the_terminal_keeps_up_with_the_display() {
+    for glyphs in [Glyphs::Blocks, Glyphs::Braille] {
+        let (mut cpu, refresh) = build(glyphs, include_bytes!("../test/classic/pong.ch8"));
+        let mut screen = [[false; 64]; 32];
+        let mut seen = 0;
+        for refreshes in 0..40 {
+            cpu.run(2_000_000, Some(refresh));
+            let output = cpu.output();
+            terminal(&output[seen..], glyphs, &mut screen);
+            seen = output.len();
+            // Rows not marked since the last refresh must already be right
+            // (a line is only sent whole, so any row marked holds it back)
+            let rows = if glyphs == Glyphs::Blocks { 2 } else { 4 };
+            for line in 0..32 / rows {
+                if (0..rows).any(|row| cpu.mem[0x80E0 + line * rows + row] != 0) {
+                    continue;
+                }
+                for (row, shown) in screen.iter().enumerate().skip(line * rows).take(rows) {
+                    for (column, &lit) in shown.iter().enumerate() {
+                        assert_eq!(lit, pixel(&cpu, row, column), "{:?} pixel {},{} after {} refreshes", glyphs, column, row, refreshes);
+                    }
+                }
+            }
+        }
+        assert!(screen.iter().flatten().any(|&lit| lit), "{:?} drew nothing", glyphs);
+    }
+}
+
+#[test]
+fn glyphs_need_the_ansi_display() {
+    let compile = |target: Target, glyphs: Glyphs| {
+        let mut compiler = Compiler::new();
+        compiler.set_target(target);
+        compiler.set_glyphs(glyphs);
+        compiler.compile_code(ROM)
+    };
+    assert!(compile(Target::Cpm, Glyphs::Braille).is_ok());
+    assert!(compile(Target::Spectrum, Glyphs::Blocks).unwrap_err().contains("--glyphs blocks"));
+    let mut compiler = Compiler::new();
+    compiler.set_glyphs(Glyphs::Braille);
+    compiler.set_delta_display(true);
+    assert!(compiler.compile_code(ROM).is_err());
+}
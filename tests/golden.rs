@@ -0,0 +1,63 @@
+// Display golden tests
+// The test ROMs must still draw what test/goldens.txt recorded, and the
+// golden subcommand must bless and check its own file
+
+#![cfg(feature = "cli")]
+
+use kz80_chip8::codegen::{Compiler, DEFAULT_CLOCK_HZ};
+use kz80_chip8::golden::{self, Golden, Goldens};
+use std::process::Command;
+
+#[test]
+fn test_roms_match_their_goldens() {
+    let goldens = Goldens::parse(include_str!("../test/goldens.txt")).unwrap();
+    let mut checked = 0;
+    for (rom, expected) in goldens.iter() {
+        // As the golden subcommand builds it, named after the file
+        let mut compiler = Compiler::new();
+        compiler.set_name(rom.rsplit('/').next().unwrap());
+        let code = compiler.compile_code(&std::fs::read(rom).unwrap()).unwrap();
+        let hash = golden::hash(&golden::run(&code, DEFAULT_CLOCK_HZ, expected.frames));
+        assert_eq!(
+            hash, expected.hash,
+            "{} draws something else now; check it, then `kz80_chip8 golden test/classic test --bless`",
+            rom
+        );
+        checked += 1;
+    }
+    assert!(checked >= 10);
+}
+
+#[test]
+fn goldens_file_round_trips() {
+    let mut goldens = Goldens::default();
+    goldens.set("roms/space game.ch8", Golden { frames: 30, hash: 0x0123_4567_89AB_CDEF });
+    goldens.set("a.ch8", Golden { frames: 120, hash: 1 });
+    let text = goldens.to_string();
+    assert!(text.contains("\na.ch8 120 0000000000000001\n"), "{}", text);
+    assert_eq!(Goldens::parse(&text).unwrap(), goldens);
+    assert!(Goldens::parse("a.ch8 120").unwrap_err().contains("line 1"));
+    assert!(Goldens::parse("a.ch8 x 01").is_err());
+}
+
+#[test]
+fn bless_then_check() {
+    let file = std::env::temp_dir().join(format!("kz80_chip8_goldens_{}.txt", std::process::id()));
+    let file = file.to_str().unwrap();
+    let golden = |frames: &str, extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_kz80_chip8"))
+            .args(["golden", "test/classic/ibm_logo.ch8", "--frames", frames, "--goldens", file])
+            .args(extra)
+            .output()
+            .expect("run kz80_chip8")
+    };
+    assert!(!golden("10", &[]).status.success());  // No goldens file yet
+    assert!(golden("10", &["--bless"]).status.success());
+    let result = golden("10", &[]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stdout));
+    // A golden for a different frame count is a mismatch, not a new golden
+    let result = golden("5", &[]);
+    std::fs::remove_file(file).unwrap();
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stdout).contains("golden is for 10 frames"));
+}
@@ -0,0 +1,72 @@
+// S-CHIP hires tests
+// 00FF must switch draws and CLS to the 128x64 buffer, 16x16 sprites
+// included, compiled or interpreted, and builds without the terminal
+// display must say they can't show it
+
+#![cfg(all(feature = "emulator", feature = "interp"))]
+
+mod common;
+
+use kz80_chip8::codegen::{Compiler, Fallback, Target, DISPLAY_BUF, HIRES_MODE};
+use kz80_chip8::golden;
+use kz80_chip8::z80emu::StopReason;
+
+/// The ROM run to its EXIT, with the display it shows and VF
+fn run(rom: &[u8], fallback: Fallback) -> (Vec<u8>, u8, u8) {
+    let mut compiler = Compiler::new();
+    compiler.set_fallback(fallback);
+    let code = compiler.compile_code(rom).expect("compile");
+    let mut cpu = common::boot(&code);
+    assert_eq!(cpu.run(20_000_000, None), StopReason::Halted);
+    (golden::display(&code, &cpu).to_vec(), cpu.mem[0x800F], cpu.mem[HIRES_MODE as usize])
+}
+
+/// HIGH / LD V0, 7A / LD V1, 3F / LD I, sprite / DRW V0, V1, 2 / DRW V0, V1, 1
+/// / EXIT, with a 2-row sprite
+const DRAW: &[u8] = &[0x00, 0xFF, 0x60, 0x7A, 0x61, 0x3F, 0xA2, 0x10, 0xD0, 0x12, 0xD0, 0x11, 0x00, 0xFD, 0, 0, 0xFF, 0x81];
+
+#[test]
+fn hires_draws_land_in_the_128x64_buffer() {
+    for fallback in [Fallback::Interp, Fallback::Trap] {
+        let (display, vf, mode) = run(DRAW, fallback);
+        assert_eq!((display.len(), vf, mode), (1024, 1, 1), "{:?}", fallback);
+        // The first row was drawn twice and is gone; the second wrapped
+        // to the top and past the right edge
+        let mut expected = vec![0u8; 1024];
+        expected[15] = 0x20;
+        expected[0] = 0x40;
+        assert_eq!(display, expected, "{:?}", fallback);
+    }
+}
+
+#[test]
+fn big_sprites_and_low_go_back_to_64x32() {
+    // HIGH / LD V0, 4 / LD I, 210 / DRW V0, V0, 0 / LOW / DRW V0, V0, 1
+    // / EXIT / 210: 16 rows of FF 00
+    let mut rom = vec![0x00, 0xFF, 0x60, 0x04, 0xA2, 0x10, 0xD0, 0x00, 0x00, 0xFE, 0xD0, 0x01, 0x00, 0xFD, 0, 0];
+    rom.extend([0xFF, 0x00].repeat(16));
+    let mut compiler = Compiler::new();
+    compiler.set_fallback(Fallback::Interp);
+    let code = compiler.compile_code(&rom).expect("compile");
+    let mut cpu = common::boot(&code);
+    assert_eq!(cpu.run(20_000_000, None), StopReason::Halted);
+    let hires = code.symbols["hires_display"] as usize;
+    for row in 4..20 {
+        assert_eq!(cpu.mem[hires + row * 16..hires + row * 16 + 3], [0x0F, 0xF0, 0x00], "row {}", row);
+    }
+    // LOW cleared the 64x32 display before the last draw
+    assert_eq!(cpu.mem[HIRES_MODE as usize], 0);
+    let display = &cpu.mem[DISPLAY_BUF as usize..DISPLAY_BUF as usize + 256];
+    assert_eq!(display.iter().filter(|&&b| b != 0).collect::<Vec<_>>(), [&0x0F, &0xF0]);
+}
+
+#[test]
+fn only_the_terminal_display_goes_hires() {
+    let code = Compiler::new().compile_code(&[0x00, 0xFE, 0x12, 0x02]).expect("compile");
+    assert!(!code.symbols.contains_key("hires_display"), "no 00FF, no buffer");
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Spectrum);
+    let code = compiler.compile_code(DRAW).expect("compile");
+    assert!(!code.symbols.contains_key("hires_display"));
+    assert!(code.diagnostics.iter().any(|d| d.message.contains("HIGH (00FF)")), "{:?}", code.diagnostics);
+}
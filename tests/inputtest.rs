@@ -0,0 +1,85 @@
+// Input test kit tests
+// The kit must report each key the driver returns, what FX0A waited for
+// and the keys EX9E and EXA1 see down, and read its lines back out of a
+// capture, catching a driver the two disagree on
+
+#![cfg(feature = "emulator")]
+
+mod common;
+
+use kz80_chip8::codegen::Target;
+use kz80_chip8::inputtest::{self, Event};
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::Z80;
+
+/// The kit started on a machine, ready for keys
+fn start(options: &CompileOptions) -> Z80 {
+    let code = inputtest::build(options).expect("build");
+    let mut cpu = common::boot(&code);
+    cpu.run(1_000_000, None);
+    cpu
+}
+
+/// Type `keys` at the machine and let it run on for `cycles`
+fn press(cpu: &mut Z80, keys: &str, cycles: u64) {
+    cpu.send(keys.as_bytes());
+    cpu.run(cycles, None);
+}
+
+#[test]
+fn the_kit_reports_presses_waits_and_releases() {
+    let mut cpu = start(&CompileOptions::default());
+    for key in ["1", "2", "F"] {
+        press(&mut cpu, key, 2_000_000);
+    }
+    let waits: Vec<Event> = inputtest::parse(&cpu.output()).into_iter().filter(|e| matches!(e, Event::Wait(_))).collect();
+    assert_eq!(waits, [Event::Wait(1), Event::Wait(2), Event::Wait(0xF)]);
+    // Two keys at once are both down, then both let go
+    press(&mut cpu, "4C", 500_000);
+    press(&mut cpu, "", 20_000_000);
+    let events = inputtest::parse(&cpu.output());
+    for key in [1, 2, 0xF, 4, 0xC] {
+        assert!(events.contains(&Event::Raw(Some(key))), "no R{:02X} in {:?}", key, events);
+    }
+    let keys: Vec<Event> = events.iter().copied().filter(|e| matches!(e, Event::Keys { .. })).collect();
+    assert!(keys.contains(&Event::Keys { ex9e: 0x1010, exa1: 0x1010 }), "{:?}", keys);
+    assert_eq!(keys.last(), Some(&Event::Keys { ex9e: 0, exa1: 0 }));
+    assert!(inputtest::problems(&events).is_empty(), "{:?}", inputtest::problems(&events));
+}
+
+#[test]
+fn the_kit_builds_for_serial_and_console_targets() {
+    for target in [Target::RetroShield, Target::Rc2014, Target::Cpm] {
+        let options = CompileOptions::builder().target(target).build().unwrap();
+        let code = inputtest::build(&options).unwrap_or_else(|e| panic!("{}: {}", target.name(), e));
+        assert!(code.symbols.contains_key("input_report"));
+        assert!(code.symbols["get_key"] != code.symbols["get_key_driver"], "get_key isn't wrapped");
+    }
+    let spectrum = CompileOptions::builder().target(Target::Spectrum).build().unwrap();
+    assert!(inputtest::build(&spectrum).unwrap_err().contains("serial port"));
+    // Outside the kit SYS is still ignored
+    let code = CompileOptions::default().compiler().compile_code(inputtest::ROM).unwrap();
+    assert!(!code.symbols.contains_key("input_report"));
+}
+
+#[test]
+fn captures_show_what_the_driver_got_wrong() {
+    let capture = "CHIP-8 on Z80\r\nR05\r\nW05\r\nR--\r\nW07\r\nW08\r\nK0020 0020\r\nK0120 0020\r\nR5Z\r\nK0000\r\n";
+    let events = inputtest::parse(capture);
+    assert_eq!(
+        events,
+        [
+            Event::Raw(Some(5)),
+            Event::Wait(5),
+            Event::Raw(None),
+            Event::Wait(7),
+            Event::Wait(8),
+            Event::Keys { ex9e: 0x0020, exa1: 0x0020 },
+            Event::Keys { ex9e: 0x0120, exa1: 0x0020 },
+        ]
+    );
+    assert_eq!(events.iter().map(Event::to_string).collect::<Vec<_>>()[..3], ["R05", "W05", "R--"]);
+    assert_eq!(inputtest::problems(&events), ["K0120 0020: EX9E has 58 down, EXA1 has 5"]);
+    assert_eq!(inputtest::problems(&events[..4]), ["FX0A returned 2 of 3 times"]);
+    assert_eq!(inputtest::keys(0), "-");
+}
@@ -0,0 +1,69 @@
+// Interrupt shared-state audit tests
+// The audit must find what a receive interrupt writes, pass the ring buffer
+// it shares with get_key, and flag a 16-bit counter read a byte at a time
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::Compiler;
+use kz80_chip8::israudit::{self, Audit};
+use kz80_chip8::z80emu::Z80;
+
+// An interrupt handler at 0038 counting interrupts at 9000:
+// PUSH HL / LD HL,(9000) / INC HL / LD (9000),HL / POP HL / EI / RETI
+const COUNT_INTERRUPTS: &[u8] = &[0xE5, 0x2A, 0x00, 0x90, 0x23, 0x22, 0x00, 0x90, 0xE1, 0xFB, 0xED, 0x4D];
+
+/// Audit mainline code at 0000 (after IM 1 / EI) with an interrupt every
+/// 500 cycles
+fn audit_counter(main: &[u8]) -> Audit {
+    let mut program = vec![0xED, 0x56, 0xFB];
+    program.extend_from_slice(main);
+    let mut cpu = Z80::with_rom(&program);
+    cpu.load(0x0038, COUNT_INTERRUPTS);
+    cpu.access_log = Some(Vec::new());
+    for _ in 0..100 {
+        cpu.run(500, None);
+        cpu.interrupt();
+    }
+    israudit::audit(cpu.access_log.as_deref().unwrap())
+}
+
+#[test]
+fn the_receive_ring_is_shared_safely() {
+    let mut compiler = Compiler::new();
+    compiler.set_rx_buffer(true);
+    // 200: LD V0, K / JP 200
+    let code = compiler.compile_code(&[0xF0, 0x0A, 0x12, 0x00]).expect("compile");
+    let audit = israudit::run(&code, b"12345", 4_000_000);
+    // The handler moves the head and fills the ring, and nothing else
+    assert!(audit.isr_writes.contains(&0x809A), "{:?}", audit.isr_writes);
+    assert!(audit.isr_writes.iter().all(|&addr| addr == 0x809A || (0x80A0..0x80C0).contains(&addr)), "{:?}", audit.isr_writes);
+    assert!(audit.main_writes.contains(&0x8099));
+    // Tail and head are read together, but each is written alone
+    let ends = audit.shared.iter().find(|shared| shared.addr == 0x8099).expect("tail and head shared");
+    assert_eq!((ends.width, ends.isr_writes, ends.main_writes), (2, true, true));
+    assert_eq!(audit.races().count(), 0, "{:?}", audit.shared);
+}
+
+#[test]
+fn a_counter_read_in_halves_is_flagged() {
+    // 0003: LD A,(9000) / LD B,A / LD A,(9001) / JR 0003
+    let audit = audit_counter(&[0x3A, 0x00, 0x90, 0x47, 0x3A, 0x01, 0x90, 0x18, 0xF7]);
+    assert_eq!(audit.isr_writes, [0x9000, 0x9001].into());
+    assert!(audit.main_writes.is_empty());
+    let races: Vec<_> = audit.races().collect();
+    assert_eq!(races.len(), 1);
+    assert_eq!((races[0].addr, races[0].width, races[0].isr_writes), (0x9000, 2, true));
+    assert_eq!(races[0].unsynchronized, [0x0003, 0x0007]);
+}
+
+#[test]
+fn masked_or_whole_reads_are_safe() {
+    // 0003: DI / LD A,(9000) / LD B,A / LD A,(9001) / EI / JR 0003
+    let masked = audit_counter(&[0xF3, 0x3A, 0x00, 0x90, 0x47, 0x3A, 0x01, 0x90, 0xFB, 0x18, 0xF5]);
+    // 0003: LD HL,(9000) / JR 0003
+    let whole = audit_counter(&[0x2A, 0x00, 0x90, 0x18, 0xFB]);
+    for audit in [masked, whole] {
+        assert_eq!(audit.shared.len(), 1, "{:?}", audit.shared);
+        assert_eq!(audit.races().count(), 0, "{:?}", audit.shared);
+    }
+}
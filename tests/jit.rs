@@ -0,0 +1,47 @@
+// Dynamic recompiler tests
+// Blocks translated as they are reached must run a game as its build does,
+// follow computed jumps, and be translated again once the game rewrites them
+
+#![cfg(all(feature = "emulator", feature = "interp"))]
+
+use kz80_chip8::codegen::{Compiler, DEFAULT_CLOCK_HZ, DISPLAY_BUF};
+use kz80_chip8::{golden, jit};
+
+const PONG: &[u8] = include_bytes!("../test/classic/pong.ch8");
+
+/// VA after running a ROM under the recompiler until it exits
+fn va(rom: &[u8]) -> (u8, jit::Stats) {
+    let jit = jit::emulate(Compiler::new(), rom, DEFAULT_CLOCK_HZ, 10).expect("recompile");
+    (jit.cpu.mem[0x800A], jit.stats)
+}
+
+#[test]
+fn games_run_as_their_builds_do() {
+    let built = golden::emulate(&Compiler::new().compile_code(PONG).unwrap(), DEFAULT_CLOCK_HZ, 120);
+    let jit = jit::emulate(Compiler::new(), PONG, DEFAULT_CLOCK_HZ, 120).expect("recompile");
+    let display = DISPLAY_BUF as usize..DISPLAY_BUF as usize + 256;
+    assert!(built.mem[display.clone()].iter().any(|&byte| byte != 0), "nothing drawn");
+    assert_eq!(jit.cpu.mem[display.clone()], built.mem[display]);
+    assert!(jit.stats.blocks > 10, "{:?}", jit.stats);
+    assert_eq!(jit.stats.retranslated, 0);
+}
+
+#[test]
+fn computed_jumps_find_their_block() {
+    // 200: LD V0, 4 / JP V0, 208 / 20C: LD VA, 7 / EXIT
+    let rom = [0x60, 0x04, 0xB2, 0x08, 0, 0, 0, 0, 0, 0, 0, 0, 0x6A, 0x07, 0x00, 0xFD];
+    assert_eq!(va(&rom).0, 7);
+}
+
+#[test]
+fn rewritten_blocks_are_translated_again() {
+    // 200: CALL 210 / LD I, 210 / LD V0, 6A / LD V1, 42 / LD [I], V0..V1
+    // / CALL 210 / EXIT, and at 210: LD VA, 01 / RET, rewritten to LD VA, 42
+    let rom = [
+        0x22, 0x10, 0xA2, 0x10, 0x60, 0x6A, 0x61, 0x42, 0xF1, 0x55, 0x22, 0x10, 0x00, 0xFD, 0, 0,
+        0x6A, 0x01, 0x00, 0xEE,
+    ];
+    let (va, stats) = va(&rom);
+    assert_eq!(va, 0x42);
+    assert_eq!(stats.retranslated, 1, "{:?}", stats);
+}
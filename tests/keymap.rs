@@ -0,0 +1,68 @@
+// Key map tests
+// A key map file, in either format, must pick the CHIP-8 key of each
+// character typed, leave the others their hex keys, and reach the game
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+use kz80_chip8::codegen::{Compiler, Target};
+use kz80_chip8::keymap::KeyMap;
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::Z80;
+
+// LD V0, 5 / SKP V0 / JP 202 / LD V1, 1 / JP 208
+const WAIT_FOR_5: &[u8] = &[0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0x61, 0x01, 0x12, 0x08];
+
+const WASD: &str = "# WASD on the 5/7/8/9 keys\nw = 5\na = 7\ns = 8\nd = 9\n\",\" = \"A\"  # comma\n5 = none\n";
+
+/// V1 after `typed` reaches a build of WAIT_FOR_5
+fn v1_after(keymap: Option<&KeyMap>, typed: &[u8]) -> u8 {
+    let mut compiler = Compiler::new();
+    if let Some(keymap) = keymap {
+        compiler.set_keymap(keymap.clone());
+    }
+    let code = compiler.compile_code(WAIT_FOR_5).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.run(1_000_000, None);
+    cpu.send(typed);
+    cpu.run(1_000_000, None);
+    cpu.mem[0x8001]
+}
+
+#[test]
+fn both_formats_read_the_same_map() {
+    let lines = KeyMap::parse(WASD).unwrap();
+    let json = KeyMap::parse(r#"{"w": 5, "a": 7, "s": "8", "d": 9, ",": "a", "5": null}"#).unwrap();
+    assert_eq!(lines, json);
+    let table = lines.table();
+    assert_eq!((table[b'w' as usize], table[b'W' as usize], table[b',' as usize]), (5, 5, 10));
+    assert_eq!((table[b'5' as usize], table[b'6' as usize], table[b'A' as usize]), (0xFF, 6, 7));
+    assert_eq!(table[b'e' as usize], 14);
+    // A letter given in both cases keeps each
+    assert_eq!(KeyMap::parse("q = 4\nQ = C").unwrap().table()[b'q' as usize], 4);
+}
+
+#[test]
+fn bad_maps_are_refused() {
+    assert!(KeyMap::parse("w = 5\nx = 16").unwrap_err().starts_with("line 2: bad key '16'"));
+    assert!(KeyMap::parse("w 5").unwrap_err().contains("expected CHAR = KEY"));
+    assert!(KeyMap::parse("ww = 5").unwrap_err().contains("single character"));
+    assert!(KeyMap::parse("\"?\" = 1").unwrap_err().contains("build info"));
+    assert!(KeyMap::parse(r#"{"w": true}"#).is_err());
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Spectrum);
+    compiler.set_keymap(KeyMap::parse(WASD).unwrap());
+    assert!(compiler.compile_code(WAIT_FOR_5).unwrap_err().contains("--keymap"));
+}
+
+#[test]
+fn mapped_keys_reach_the_game() {
+    let keymap = KeyMap::parse(WASD).unwrap();
+    assert_eq!(v1_after(None, b"w"), 0);
+    assert_eq!(v1_after(None, b"5"), 1);
+    assert_eq!(v1_after(Some(&keymap), b"W"), 1);
+    assert_eq!(v1_after(Some(&keymap), b"5"), 0);
+    // And through an options file
+    let options = CompileOptions::builder().keymap(keymap.clone()).build().unwrap();
+    let (read, _) = CompileOptions::from_json(&options.to_json()).unwrap();
+    assert_eq!(read.keymap, Some(keymap));
+}
@@ -0,0 +1,51 @@
+// Console key state tests
+// A key typed at a serial console must stay down for SKP and SKNP until the
+// release time passes without it being typed again
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Compiler, Target};
+use kz80_chip8::z80emu::Z80;
+
+// 200: LD V0, 5 / SKNP V0 / LD V1, 1 / JP 202
+const MARK_5: &[u8] = &[0x60, 0x05, 0xE0, 0xA1, 0x61, 0x01, 0x12, 0x02];
+
+/// Whether the game sees key 5 down at each of `after` milliseconds
+/// since it was typed
+fn down_at(target: Target, key_release: Option<u16>, after: &[u32]) -> Vec<bool> {
+    let mut compiler = Compiler::new();
+    compiler.set_target(target);
+    if let Some(ms) = key_release {
+        compiler.set_key_release(ms);
+    }
+    let code = compiler.compile_code(MARK_5).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.run(1_000_000, Some(code.symbols["c8_202"]));
+    cpu.send(b"5");
+    let mut now = 0;
+    after
+        .iter()
+        .map(|&ms| {
+            cpu.run(u64::from(ms - now) * 4_000, None);  // 4MHz
+            cpu.mem[0x8001] = 0;
+            cpu.run(4_000, None);
+            now = ms + 1;
+            cpu.mem[0x8001] == 1
+        })
+        .collect()
+}
+
+#[test]
+fn a_typed_key_stays_down() {
+    // Long after its one character was read, the key is still down
+    assert_eq!(down_at(Target::RetroShield, None, &[20, 50, 150]), [true, true, true]);
+    assert_eq!(down_at(Target::Rc2014, None, &[20, 150]), [true, true]);
+}
+
+#[test]
+fn keys_go_up_after_the_release_time() {
+    assert_eq!(down_at(Target::RetroShield, None, &[150, 400]), [true, false]);
+    assert_eq!(down_at(Target::RetroShield, Some(1000), &[400, 800, 1400]), [true, true, false]);
+    // Even with no wait for FX0A, a key is down for a frame
+    assert_eq!(down_at(Target::RetroShield, Some(0), &[5, 100]), [true, false]);
+}
@@ -0,0 +1,69 @@
+// FX0A key wait tests
+// A key must be taken once per press: on a keyboard FX0A waits for its
+// release, and on a console a key repeating while held is taken only once
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+mod common;
+
+use kz80_chip8::codegen::{Compiler, Target};
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::Z80;
+
+// 200: LD V0, K / ADD V1, 1 / JP 200
+const COUNT_KEYS: &[u8] = &[0xF0, 0x0A, 0x71, 0x01, 0x12, 0x00];
+
+/// V0 and the keys counted after each burst of typing, the bursts a
+/// second apart and their characters 25ms apart
+fn typed(key_release: Option<u16>, bursts: &[&[u8]]) -> Vec<(u8, u8)> {
+    let mut compiler = Compiler::new();
+    if let Some(ms) = key_release {
+        compiler.set_key_release(ms);
+    }
+    let code = compiler.compile_code(COUNT_KEYS).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    let mut seen = Vec::new();
+    for burst in bursts {
+        cpu.run(4_000_000, None);
+        for &c in *burst {
+            cpu.send(&[c]);
+            cpu.run(100_000, None);
+        }
+        seen.push((cpu.mem[0x8000], cpu.mem[0x8001]));
+    }
+    seen
+}
+
+#[test]
+fn a_console_wait_needs_a_key() {
+    // Nothing typed: the wait used to take a key 0 it never had
+    assert_eq!(typed(None, &[b"", b"5"]), [(0, 0), (5, 1)]);
+    assert_eq!(typed(None, &[b"?", b"x", b"a"]), [(0, 0), (0, 0), (10, 1)]);
+}
+
+#[test]
+fn a_repeating_key_counts_once() {
+    // A held key repeats; another key, or the same one a second later, counts
+    assert_eq!(typed(None, &[b"5555", b"5", b"56"]), [(5, 1), (5, 2), (6, 4)]);
+    assert_eq!(typed(Some(0), &[b"5555"]), [(5, 4)]);
+    let options = CompileOptions::builder().key_release(0).build().unwrap();
+    let (read, _) = CompileOptions::from_json(&options.to_json()).unwrap();
+    assert_eq!(read.key_release, Some(0));
+}
+
+#[test]
+fn a_keyboard_wait_ends_at_the_release() {
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Spectrum);
+    let code = compiler.compile_code(COUNT_KEYS).expect("compile");
+    let mut cpu = common::boot(&code);
+    cpu.run(2_000_000, None);
+    let mut counted = Vec::new();
+    for port in [!0x01, !0x01, 0xFF, !0x01, 0xFF] {
+        cpu.in_ports[0xFE] = port;  // Key 1, held, let go, pressed again
+        cpu.run(2_000_000, None);
+        counted.push(cpu.mem[0x8001]);
+    }
+    assert_eq!(counted, [0, 0, 1, 1, 2]);
+    assert_eq!(cpu.mem[0x8000], 1);
+}
@@ -0,0 +1,109 @@
+// Serial loader tests
+// The loader ROM runs on the Z80 emulator, receives a compiled game through
+// the ACIA and must start it; damaged frames must be refused, and the CRC
+// it reports must show what it stored
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::Compiler;
+use kz80_chip8::loader::{self, ACK, NAK, READY};
+use kz80_chip8::z80emu::{StopReason, Z80};
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Sender};
+
+fn game() -> Vec<u8> {
+    let rom = include_bytes!("../test/classic/ibm_logo.ch8");
+    Compiler::new().compile(rom).expect("compile")
+}
+
+#[test]
+fn loads_and_starts_a_game() {
+    let image = game();
+    let frames = loader::frames(&image).unwrap();
+    let mut cpu = Z80::with_rom(&loader::loader_rom());
+    cpu.send(&frames.concat());
+    assert_eq!(cpu.run(20_000_000, Some(0x0000)), StopReason::Breakpoint);
+    assert_eq!(cpu.tx[0], READY);
+    assert_eq!(cpu.tx[1..], vec![ACK; frames.len()][..]);
+    let used = image.iter().rposition(|&b| b != 0).unwrap() + 1;
+    assert_eq!(&cpu.mem[..used], &image[..used]);
+
+    // Then the game itself starts
+    cpu.tx.clear();
+    assert_eq!(cpu.run(5_000_000, None), StopReason::CycleLimit);
+    assert!(cpu.output().contains("CHIP-8 on Z80"));
+}
+
+#[test]
+fn damaged_frames_are_refused() {
+    let mut cpu = Z80::with_rom(&loader::loader_rom());
+    let mut bad = loader::data_frame(0x4000, &[1, 2, 3, 4]);
+    bad[5] ^= 0x10;
+    let good = loader::data_frame(0x4000, &[5, 6, 7, 8]);
+    // A frame cut short, completed by the filler the host sends on a resend
+    let short = &loader::data_frame(0x4100, &[9; 200])[..20];
+    cpu.send(&bad);
+    cpu.send(short);
+    cpu.send(&[0xFF; 262]);
+    cpu.send(&good);
+    cpu.run(5_000_000, None);
+    assert_eq!(cpu.tx, [READY, NAK, NAK, ACK]);
+    assert_eq!(&cpu.mem[0x4000..0x4004], &[5, 6, 7, 8]);
+    assert!(cpu.mem[0x4100..0x4200].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn images_overlapping_the_loader_are_rejected() {
+    assert!(loader::frames(&vec![1; 0xFE00]).is_err());
+    let frames = loader::frames(&[0xC3, 0, 0, 0, 0]).unwrap();
+    assert_eq!(frames, [loader::data_frame(0, &[0xC3]), loader::go_frame(0)]);
+}
+
+/// A board running the loader, as its serial port: what is written to it
+/// is received once flushed, and what it sends back goes to `replies`
+struct Board {
+    cpu: Z80,
+    replies: Sender<u8>,
+}
+
+impl Write for Board {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.cpu.send(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Until it has taken everything and gone quiet for longer than a
+        // CRC of the game takes
+        while !self.cpu.rx.is_empty() {
+            self.cpu.run(100_000, None);
+        }
+        loop {
+            let sent = self.cpu.tx.len();
+            self.cpu.run(2_000_000, None);
+            if self.cpu.tx.len() == sent {
+                break;
+            }
+        }
+        for byte in self.cpu.tx.drain(..) {
+            self.replies.send(byte).unwrap();
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn the_crc_shows_what_the_board_stored() {
+    assert_eq!(loader::crc16(b"123456789"), 0x29B1);
+    let image = game();
+    let frames = loader::frames(&image).unwrap();
+    let (replies, rx) = mpsc::channel();
+    let mut board = Board { cpu: Z80::with_rom(&loader::loader_rom()), replies };
+    loader::send(&mut board, &rx, &frames[..frames.len() - 1]).unwrap();
+    assert_eq!(loader::verify(&mut board, &rx, &image).unwrap(), []);
+    assert_eq!(loader::request_crc(&mut board, &rx, 0x0100, 4).unwrap(), loader::crc16(&image[0x100..0x104]));
+    // A byte that didn't take, as on a bad RAM chip
+    board.cpu.mem[0x0123] ^= 0x40;
+    let bad = loader::verify(&mut board, &rx, &image).unwrap();
+    assert_eq!(bad.iter().map(|range| (range.start, range.end)).collect::<Vec<_>>(), [(0x0100, 0x0180)]);
+}
@@ -0,0 +1,49 @@
+// XO-CHIP long I load tests
+// F000 NNNN must decode as one 4-byte instruction that loads all 16 bits of
+// I, and a skip over it must step past both of its words
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::chip8::{self, Instruction};
+use kz80_chip8::codegen::{Compiler, DEFAULT_CLOCK_HZ};
+use kz80_chip8::golden;
+use kz80_chip8::z80emu::Z80;
+
+/// Machine after a ROM has run for a few frames
+fn run(rom: &[u8]) -> Z80 {
+    golden::emulate(&Compiler::new().compile_code(rom).expect("compile"), DEFAULT_CLOCK_HZ, 10)
+}
+
+/// I, as the runtime keeps it
+fn i(cpu: &Z80) -> u16 {
+    u16::from_le_bytes([cpu.mem[0x8010], cpu.mem[0x8011]])
+}
+
+/// LD V0, 1 / SE V0, `nn` / LD I, 1234 (F000 1234) / LD V1, 5 / JP 20A
+fn skip_over(nn: u8) -> Vec<u8> {
+    vec![0x60, 0x01, 0x30, nn, 0xF0, 0x00, 0x12, 0x34, 0x61, 0x05, 0x12, 0x0A]
+}
+
+#[test]
+fn long_loads_decode_as_one_instruction() {
+    let instructions = chip8::parse(&skip_over(1));
+    let addrs: Vec<u16> = instructions.iter().map(|inst| inst.addr).collect();
+    assert_eq!(addrs, [0x200, 0x202, 0x204, 0x208, 0x20A]);
+    assert_eq!(instructions[2], Instruction::long(0xF000, 0x204, 0x1234));
+    assert_eq!(instructions[2].size(), 4);
+    assert_eq!(chip8::disasm_instruction(&instructions[2]), "LD   I, 1234");
+}
+
+#[test]
+fn long_loads_set_all_of_i() {
+    let cpu = run(&skip_over(2));
+    assert_eq!(i(&cpu), 0x1234);
+    assert_eq!(cpu.mem[0x8001], 5, "V1");
+}
+
+#[test]
+fn skips_step_over_both_words() {
+    let cpu = run(&skip_over(1));
+    assert_eq!(i(&cpu), 0);
+    assert_eq!(cpu.mem[0x8001], 5, "V1");
+}
@@ -0,0 +1,78 @@
+// Memory map tests
+// With --memory-map the code must stay in the board's ROM and the runtime
+// in its RAM, and maps whose regions overlap or don't fit must be refused
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+use kz80_chip8::codegen::{Compiler, Target};
+use kz80_chip8::memmap::MemoryMap;
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::Z80;
+
+const IBM_LOGO: &[u8] = include_bytes!("../test/classic/ibm_logo.ch8");
+
+const HIGH_RAM: &str = "\
+# 16KB of ROM, and RAM only at the top
+[rom]
+start = 0x0000
+end = 0x3FFF
+
+[ram]
+start = 0xC000
+end = 0xFFFF
+";
+
+/// Memory after the logo has been drawn for a second
+fn draw(compiler: &mut Compiler) -> Vec<u8> {
+    let image = compiler.compile(IBM_LOGO).expect("compile");
+    let mut cpu = Z80::with_rom(&image);
+    cpu.run(4_000_000, None);
+    assert!(cpu.output().contains("CHIP-8 on Z80"), "no banner");
+    cpu.mem
+}
+
+#[test]
+fn the_runtime_moves_into_the_maps_ram() {
+    let map = MemoryMap::parse(HIGH_RAM).unwrap();
+    let mut compiler = Compiler::new();
+    compiler.set_memory_map(map);
+    assert_eq!(compiler.compile(IBM_LOGO).unwrap().len(), 0x4000);
+    let mapped = draw(&mut compiler);
+    let default = draw(&mut Compiler::new());
+    // The same display, 0x4000 higher, and nothing between ROM and RAM
+    assert_eq!(mapped[0xC200..0xC300], default[0x8200..0x8300]);
+    assert!(default[0x8200..0x8300].iter().any(|&byte| byte != 0), "nothing drawn");
+    assert!(mapped[0x4000..0xC000].iter().all(|&byte| byte == 0), "wrote outside RAM");
+}
+
+#[test]
+fn maps_must_hold_together() {
+    let parse = |rom: &str, ram: &str| MemoryMap::parse(&format!("[rom]\n{}\n[ram]\n{}\n", rom, ram));
+    assert!(parse("start = 0\nend = 0x7FFF", "start = 0x8000\nend = 65535").is_ok());
+    assert!(parse("start = 0\nend = 0xC000", "start = 0xC000\nend = 0xFFFF").unwrap_err().contains("overlaps ram"));
+    assert!(parse("start = 0\nend = 0x3FFF", "start = 0xF000\nend = 0xFFFF").unwrap_err().contains("the runtime needs"));
+    assert!(parse("start = 0\nend = 0x3FFF", "start = 0xC080\nend = 0xFFFF").unwrap_err().contains("page boundary"));
+    assert!(parse("start = 0\nend = 0x3FFF", "start = 0xC000").unwrap_err().contains("[ram] needs a start and an end"));
+    assert!(MemoryMap::parse("[eeprom]\nstart = 0").unwrap_err().contains("line 1: unknown section"));
+    assert!(MemoryMap::parse("[rom]\nstart = 0x10000").unwrap_err().contains("line 2: bad address"));
+}
+
+#[test]
+fn the_code_must_fit_the_rom() {
+    let build = |map: &str, target: Target, origin: Option<u16>| {
+        let mut compiler = Compiler::new();
+        compiler.set_target(target);
+        compiler.set_memory_map(MemoryMap::parse(map).unwrap());
+        if let Some(origin) = origin {
+            compiler.set_origin(origin);
+        }
+        compiler.compile_code(IBM_LOGO).map(|code| String::from_utf8_lossy(&code.code).into_owned())
+    };
+    let tiny_rom = HIGH_RAM.replace("0x3FFF", "0x03FF");
+    assert!(build(HIGH_RAM, Target::Rc2014, None).unwrap().contains("memory-map=rom:0000-3FFF,ram:C000-FFFF"), "no build info");
+    assert!(build(&tiny_rom, Target::RetroShield, None).unwrap_err().contains("holds 1024 (from 0000 to the end of ROM at 0400)"));
+    assert!(build(HIGH_RAM, Target::RetroShield, Some(0x8000)).unwrap_err().contains("outside the ROM at 0000-3FFF"));
+    assert!(build(HIGH_RAM, Target::Msx, None).unwrap_err().contains("a msx build has its own"));
+    let options = CompileOptions::builder().memory_map(MemoryMap::parse(HIGH_RAM).unwrap()).build().unwrap();
+    assert_eq!(CompileOptions::from_json(&options.to_json()).unwrap().0, options);
+}
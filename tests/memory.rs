@@ -0,0 +1,61 @@
+// CHIP-8 memory tests
+// The ROM must be copied into CHIP-8 RAM at startup, and sprites and FX65
+// must read that one writable copy, stores and all
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::analysis;
+use kz80_chip8::codegen::{Compiler, RamInit, Target, DEFAULT_CLOCK_HZ, DISPLAY_BUF};
+use kz80_chip8::golden;
+use kz80_chip8::z80emu::Z80;
+
+/// Machine after a ROM has run for a few frames, over RAM filled with FF
+fn run(rom: &[u8]) -> Z80 {
+    let mut compiler = Compiler::new();
+    compiler.set_ram_init(RamInit::Ff);
+    golden::emulate(&compiler.compile_code(rom).expect("compile"), DEFAULT_CLOCK_HZ, 10)
+}
+
+#[test]
+fn sprites_are_drawn_as_stored() {
+    // LD I, 20E / LD V0, 3C / LD [I], V0 / LD I, 20E / LD V0, 0
+    // / DRW V0, V0, 1 / JP 20C, with the sprite at 20E a row of 81
+    let rom = [0xA2, 0x0E, 0x60, 0x3C, 0xF0, 0x55, 0xA2, 0x0E, 0x60, 0x00, 0xD0, 0x01, 0x12, 0x0C, 0x81, 0x00];
+    let cpu = run(&rom);
+    assert_eq!(cpu.mem[DISPLAY_BUF as usize], 0x3C);
+}
+
+#[test]
+fn loads_from_rom_tables_get_the_table() {
+    // LD I, 208 / LD V0..V3, [I] / EXIT / (pad) / 208: 11 22 33 44
+    let rom = [0xA2, 0x08, 0xF3, 0x65, 0x00, 0xFD, 0x00, 0x00, 0x11, 0x22, 0x33, 0x44];
+    let cpu = run(&rom);
+    assert_eq!(cpu.mem[0x8000..0x8004], [0x11, 0x22, 0x33, 0x44]);
+    assert!(analysis::classify(&rom).unwritten_reads.is_empty());
+}
+
+#[test]
+fn add_i_adds_to_both_bytes_of_i() {
+    // LD I, 3F8 / LD V1, 12 / ADD I, V1 / LD V0, 5A / LD [I], V0 / EXIT:
+    // I's low byte and high byte both change, to 40A
+    let rom = [0xA3, 0xF8, 0x61, 0x12, 0xF1, 0x1E, 0x60, 0x5A, 0xF0, 0x55, 0x00, 0xFD];
+    let cpu = run(&rom);
+    assert_eq!(cpu.mem[0x8010..0x8012], [0x0A, 0x04]);
+    assert_eq!(cpu.mem[0x8400 + 0x40A - 0x200], 0x5A);
+}
+
+#[test]
+fn a_rom_past_chip8_ram_is_refused() {
+    // LD I, 204 / DRW V0, V0, 1 / JP 204, padded to CP/M's 3.5KB of CHIP-8
+    // RAM from 0x200, which holds it, and to one byte more, which doesn't
+    let compile = |len: usize| {
+        let mut rom = vec![0xA2, 0x04, 0xD0, 0x01, 0x12, 0x04];
+        rom.resize(len, 0xAA);
+        let mut compiler = Compiler::new();
+        compiler.set_target(Target::Cpm);
+        compiler.compile_code(&rom).map(|code| code.code[(code.symbols["chip8_rom_data"] - code.origin) as usize..].to_vec())
+    };
+    assert_eq!(compile(0xE00).expect("compile").len(), 0xE00);
+    let error = compile(0xE01).unwrap_err();
+    assert!(error.contains("3585 byte ROM") && error.contains("3584 bytes"), "{}", error);
+}
@@ -0,0 +1,63 @@
+// Key menu tests
+// An FX0A followed by a ladder of key compares must compile to one
+// menu_wait call that behaves like the ladder, and only when nothing else
+// can reach the ladder
+
+#![cfg(feature = "emulator")]
+
+mod common;
+
+use kz80_chip8::codegen::{CompiledCode, Compiler};
+
+// 200: LD V0, K / SNE V0, 1 / JP 210 / SNE V0, 2 / JP 214 / JP 200
+// 20C: LD V2, 0 / JP 20C (unused)
+// 210: LD V1, 1 / JP 212
+// 214: LD V1, 2 / JP 216
+const MENU: [u8; 24] = [
+    0xF0, 0x0A, 0x40, 0x01, 0x12, 0x10, 0x40, 0x02, 0x12, 0x14, 0x12, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x61, 0x01, 0x12, 0x12, 0x61, 0x02, 0x12, 0x16,
+];
+
+fn compile(rom: &[u8]) -> CompiledCode {
+    Compiler::new().compile_code(rom).expect("compile")
+}
+
+/// V0 and V1 after the keys are typed, one at a time
+fn run(code: &CompiledCode, keys: &[u8]) -> (u8, u8) {
+    let mut cpu = common::boot(code);
+    cpu.run(500_000, None);
+    for &key in keys {
+        cpu.send(&[key]);
+        cpu.run(500_000, None);
+    }
+    (cpu.mem[0x8000], cpu.mem[0x8001])
+}
+
+#[test]
+fn menu_folds_the_ladder_into_menu_wait() {
+    let code = compile(&MENU);
+    assert!(code.symbols.contains_key("menu_wait"));
+    assert!(code.symbols.contains_key("menu_200"));
+    // The compares and jumps of the ladder compile to nothing
+    assert_eq!(code.symbols["c8_202"], code.symbols["c8_20A"]);
+}
+
+#[test]
+fn menu_takes_only_its_own_keys() {
+    let code = compile(&MENU);
+    assert_eq!(run(&code, b"5"), (0, 0));  // Not in the menu: still waiting
+    assert_eq!(run(&code, b"52"), (2, 2));
+    assert_eq!(run(&code, b"1"), (1, 1));
+}
+
+#[test]
+fn ladder_reached_from_elsewhere_is_compiled_as_is() {
+    // Key 2 goes to 20C: LD V1, 2 / JP 206, back into the ladder, so it
+    // can't be folded
+    let mut rom = MENU;
+    rom[8..10].copy_from_slice(&[0x12, 0x0C]);
+    rom[12..16].copy_from_slice(&[0x61, 0x02, 0x12, 0x06]);
+    let code = compile(&rom);
+    assert!(!code.symbols.contains_key("menu_wait"));
+    assert_eq!(run(&code, b"52"), (2, 2));
+}
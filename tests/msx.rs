@@ -0,0 +1,105 @@
+// MSX target tests
+// The cartridge must carry the header the BIOS looks for, draw through the
+// VDP and read the keyboard matrix through the PPI
+
+#![cfg(feature = "emulator")]
+
+mod common;
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, Target};
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+fn compile(rom: &[u8]) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Msx);
+    compiler.compile_code(rom).expect("compile")
+}
+
+/// Start the cartridge the way the BIOS does, through its INIT address,
+/// with a stand-in font (each glyph filled with its character code)
+fn boot(code: &CompiledCode) -> Z80 {
+    let mut cpu = common::boot(code);
+    cpu.load(0x1000, &(0..=255u8).flat_map(|c| [c; 8]).collect::<Vec<u8>>());
+    cpu.load(0x0004, &[0x00, 0x10]);
+    cpu.pc = cpu.read16(code.origin + 2);
+    cpu
+}
+
+/// VRAM and registers after replaying the OUTs to the VDP ports
+fn vdp(cpu: &Z80) -> (Vec<u8>, [u8; 8]) {
+    let (mut vram, mut regs) = (vec![0u8; 0x4000], [0u8; 8]);
+    let (mut addr, mut latch) = (0usize, None);
+    for &(_, port, value) in &cpu.out_log {
+        match (port, latch) {
+            (0x98, _) => {
+                vram[addr] = value;
+                addr = (addr + 1) & 0x3FFF;
+            }
+            (0x99, None) => latch = Some(value),
+            (0x99, Some(low)) => {
+                if value & 0x80 != 0 {
+                    regs[(value & 7) as usize] = low;
+                } else {
+                    addr = ((value as usize & 0x3F) << 8) | low as usize;
+                }
+                latch = None;
+            }
+            _ => {}
+        }
+    }
+    (vram, regs)
+}
+
+#[test]
+fn cartridge_header_calls_init() {
+    let code = compile(include_bytes!("../test/classic/ibm_logo.ch8"));
+    assert_eq!(code.origin, 0x4000);
+    assert_eq!(&code.code[..4], b"AB\x00\x41");
+    assert_eq!(code.symbols["init"], 0x4100);
+
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Msx);
+    let cartridge = compiler.compile(include_bytes!("../test/classic/ibm_logo.ch8")).unwrap();
+    assert_eq!(cartridge.len(), 0x4000);
+    assert_eq!(&cartridge[..code.code.len()], &code.code[..]);
+    assert!(cartridge[code.code.len()..].iter().all(|&b| b == 0xFF));
+}
+
+#[test]
+fn name_table_shows_the_display_buffer() {
+    let code = compile(include_bytes!("../test/classic/ibm_logo.ch8"));
+    let mut cpu = boot(&code);
+    assert_eq!(cpu.run(5_000_000, None), StopReason::CycleLimit);
+    let (vram, regs) = vdp(&cpu);
+    assert_eq!(regs[1], 0xC0);
+    assert_eq!(&vram[b'C' as usize * 8..b'C' as usize * 8 + 8], &[b'C'; 8]);
+    assert_eq!(&vram[0x1800 + 20 * 32..0x1800 + 20 * 32 + 13], b"CHIP-8 on Z80");
+
+    let pixel = |x: usize, y: usize| cpu.mem[0x8200 + y * 8 + x / 8] & (0x80 >> (x % 8)) != 0;
+    let mut lit = 0;
+    for row in 0..16 {
+        for col in 0..32 {
+            let (x, y) = (col * 2, row * 2);
+            let cell = [pixel(x, y), pixel(x + 1, y), pixel(x, y + 1), pixel(x + 1, y + 1)];
+            let pattern = cell.iter().fold(0, |bits, &on| bits << 1 | on as u8);
+            assert_eq!(vram[0x1800 + (2 + row) * 32 + col], pattern, "cell ({}, {})", col, row);
+            lit += pattern.count_ones();
+        }
+    }
+    assert!(lit > 100);
+}
+
+#[test]
+fn keypad_is_read_from_the_matrix() {
+    // FX0A into V0, then spin
+    let code = compile(&[0xF0, 0x0A, 0x12, 0x02]);
+    // Every row reads the same here, so the first key in the table wins
+    for (bit, key) in [(5, 0x0), (1, 0x1), (0, 0x8)] {
+        let mut cpu = boot(&code);
+        cpu.in_ports[0xA9] = !(1 << bit);
+        cpu.run(2_000_000, None);
+        cpu.in_ports[0xA9] = 0xFF;
+        cpu.run(2_000_000, None);
+        assert_eq!(cpu.mem[0x8000], key, "bit {}", bit);
+    }
+}
@@ -0,0 +1,98 @@
+// Compile options tests
+// Options built in code or read from JSON must set up the same compiler as
+// the setters do, and survive a round trip through their file format
+
+#![cfg(feature = "config")]
+
+use kz80_chip8::codegen::{Compiler, Fallback, Layout, Target};
+use kz80_chip8::diagnostics::{Category, Level, Levels};
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::package::Package;
+use kz80_chip8::profile::Profile;
+use kz80_chip8::quirks::Quirks;
+use kz80_chip8::sms::PadMap;
+
+const PONG: &[u8] = include_bytes!("../test/classic/pong.ch8");
+
+#[test]
+fn builder_sets_up_the_compiler() {
+    let mut quirks = Quirks::default();
+    quirks.apply("vf-reset").unwrap();
+    let options = CompileOptions::builder()
+        .target(Target::Rc2014)
+        .clock_hz(6_000_000)
+        .quirks(quirks)
+        .layout(Layout::Loops)
+        .beeper(0x10, 3)
+        .compact_traps(true)
+        .build()
+        .unwrap();
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Rc2014);
+    compiler.set_clock_hz(6_000_000);
+    compiler.set_quirks(quirks);
+    compiler.set_layout(Layout::Loops);
+    compiler.set_beeper(0x10, 3);
+    compiler.set_compact_traps(true);
+    let expected = compiler.compile_code(PONG).unwrap();
+    assert_eq!(options.compiler().compile_code(PONG).unwrap().code, expected.code);
+    assert_ne!(CompileOptions::default().compiler().compile_code(PONG).unwrap().code, expected.code);
+}
+
+#[test]
+fn json_round_trips() {
+    let mut levels = Levels::default();
+    levels.set(Category::UnknownOpcode, Level::Error);
+    let mut profile = Profile::default();
+    profile.counts.insert(0x2A4, 5120);
+    let options = CompileOptions::builder()
+        .target(Target::Sms)
+        .origin(0x0100)
+        .levels(levels)
+        .fallback(Fallback::Interp)
+        .layout(Layout::Profile(profile))
+        .pad(PadMap { keys: [Some(2), Some(8), Some(4), Some(6), Some(5), None] })
+        .package(Package::Hex)
+        .flash_base(0x10000)
+        .build()
+        .unwrap();
+    let json = options.to_json();
+    assert!(json.contains("\"target\": \"sms\""), "{}", json);
+    assert!(json.contains("\"unknown-opcode\": \"error\""), "{}", json);
+    assert!(json.contains("\"flash-base\": 65536") && json.contains("\"version\": 2"), "{}", json);
+    assert_eq!(CompileOptions::from_json(&json).unwrap(), (options, Vec::new()));
+
+    // Anything left out takes its default
+    let partial = CompileOptions::from_json(r#"{"target": "msx", "quirks": {"shift-vy": true}}"#).unwrap().0;
+    assert_eq!(partial.target, Target::Msx);
+    assert!(partial.quirks.shift_vy && !partial.quirks.vf_reset);
+    assert_eq!(partial.layout, Layout::Source);
+    let error = CompileOptions::from_json(r#"{"taget": "msx"}"#).unwrap_err();
+    assert!(error.contains("unknown option 'taget' (expected ") && error.contains("target"), "{}", error);
+    assert!(CompileOptions::from_json(r#"{"target": "c64"}"#).is_err());
+}
+
+#[test]
+fn pad_needs_the_master_system() {
+    let error = CompileOptions::builder().target(Target::Msx).pad(PadMap::default()).build().unwrap_err();
+    assert!(error.contains("doesn't apply to msx"), "{}", error);
+    assert!(CompileOptions::from_json(r#"{"pad": {"keys": [5, 8, 7, 9, 6, 4]}}"#).is_err());
+    assert!(CompileOptions::from_json(r#"{"target": "sms", "pad": {"keys": [5, 8, 7, 9, 6, null]}}"#).is_ok());
+}
+
+#[test]
+fn older_versions_migrate() {
+    // Version 1 had no version key and snake_case names
+    let (options, warnings) = CompileOptions::from_json(r#"{"target": "cpm", "clock_hz": 2000000, "profile_gen": false}"#).unwrap();
+    assert_eq!(options.clock_hz, Some(2_000_000));
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings[0].contains("'clock_hz' is 'clock-hz' since version 2"), "{}", warnings[0]);
+    let (same, warnings) = CompileOptions::from_json(r#"{"version": 2, "target": "cpm", "clock-hz": 2000000}"#).unwrap();
+    assert_eq!((same, warnings.len()), (options, 0));
+
+    let error = |json: &str| CompileOptions::from_json(json).unwrap_err();
+    assert!(error(r#"{"version": 2, "clock_hz": 1}"#).contains("version 2 calls it 'clock-hz'"));
+    assert!(error(r#"{"clock_hz": 1, "clock-hz": 2}"#).contains("both"));
+    assert!(error(r#"{"version": 3}"#).contains("newer"));
+    assert!(error(r#"{"version": "2"}"#).contains("version should be a number"));
+}
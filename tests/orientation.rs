@@ -0,0 +1,84 @@
+// Framebuffer orientation tests
+// Turned, mirrored and bit-reversed layouts must put every pixel of the
+// display where the module expects it, and only where there is room
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+use kz80_chip8::codegen::{Compiler, Orientation, DEFAULT_CLOCK_HZ, DISPLAY_BUF};
+use kz80_chip8::golden;
+use kz80_chip8::options::CompileOptions;
+
+const IBM_LOGO: &[u8] = include_bytes!("../test/classic/ibm_logo.ch8");
+
+fn orientation(specs: &[&str]) -> Orientation {
+    let mut orientation = Orientation::default();
+    for spec in specs {
+        orientation.apply(spec).unwrap();
+    }
+    orientation
+}
+
+/// Display pixel shown at (x, y) of video memory: turned clockwise, then
+/// mirrored
+fn source(orientation: Orientation, x: usize, y: usize) -> (usize, usize) {
+    let width = if orientation.rotate % 180 == 90 { 32 } else { 64 };
+    let x = if orientation.mirror { width - 1 - x } else { x };
+    match orientation.rotate {
+        0 => (x, y),
+        90 => (y, 31 - x),
+        180 => (63 - x, 31 - y),
+        _ => (63 - y, x),
+    }
+}
+
+#[test]
+fn every_pixel_lands_where_the_module_wants_it() {
+    for rotate in ["rotate=0", "rotate=90", "rotate=180", "rotate=270"] {
+        for specs in [&[rotate][..], &[rotate, "mirror"], &[rotate, "lsb-first"], &[rotate, "mirror", "lsb-first"]] {
+            let orientation = orientation(specs);
+            let (width, height, stride) = if orientation.rotate % 180 == 90 { (32, 64, 6) } else { (64, 32, 10) };
+            let mut compiler = Compiler::new();
+            compiler.set_framebuffer(0x6000, stride);
+            compiler.set_orientation(orientation);
+            let code = compiler.compile_code(IBM_LOGO).expect("compile");
+            let cpu = golden::emulate(&code, DEFAULT_CLOCK_HZ, 60);
+            let display = &cpu.mem[DISPLAY_BUF as usize..DISPLAY_BUF as usize + 256];
+            let lit = |x: usize, y: usize| display[y * 8 + x / 8] & 0x80 >> (x % 8) != 0;
+            for y in 0..height {
+                for x in 0..width {
+                    let byte = cpu.mem[0x6000 + y * stride as usize + x / 8];
+                    let bit = if orientation.lsb_first { x % 8 } else { 7 - x % 8 };
+                    let (from_x, from_y) = source(orientation, x, y);
+                    assert_eq!(byte >> bit & 1 != 0, lit(from_x, from_y), "{:?} at ({}, {})", specs, x, y);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn layouts_must_fit_and_make_sense() {
+    let build = |stride: u16, specs: &[&str]| {
+        let mut compiler = Compiler::new();
+        compiler.set_framebuffer(0x7000, stride);
+        compiler.set_orientation(orientation(specs));
+        compiler.compile_code(IBM_LOGO).map(|_| ())
+    };
+    // On its side a row is 4 bytes, and there are 64 of them
+    assert!(build(4, &["rotate=90"]).is_ok());
+    assert!(build(4, &["rotate=180"]).unwrap_err().contains("shorter than a row's 8 bytes"));
+    assert!(build(0x80, &["rotate=270"]).unwrap_err().contains("runs to 8F83, into RAM"));
+    let mut compiler = Compiler::new();
+    compiler.set_orientation(orientation(&["mirror"]));
+    assert!(compiler.compile_code(IBM_LOGO).unwrap_err().contains("--orientation lays out --framebuffer"));
+
+    let mut bad = Orientation::default();
+    assert!(bad.apply("rotate=45").unwrap_err().contains("invalid rotate '45' (expected 0, 90, 180 or 270)"));
+    assert!(bad.apply("rotate=sideways").unwrap_err().contains("'sideways'"));
+    assert_eq!(bad, Orientation::default());
+    assert!(bad.apply("upside-down").unwrap_err().contains("invalid orientation"));
+    assert!(bad.apply("lsb-first=on").is_err());
+    assert_eq!(orientation(&["portrait", "mirror"]), orientation(&["rotate=90", "mirror"]));
+    assert_eq!(orientation(&["rotate=270", "mirror", "lsb-first", "mirror=off"]).names(), ["rotate=270", "lsb-first"]);
+    assert!(CompileOptions::from_json(r#"{"orientation": {"rotate": 30}}"#).unwrap_err().contains("0, 90, 180 or 270"));
+}
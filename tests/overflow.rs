@@ -0,0 +1,64 @@
+// Code size limit tests
+// Code that outgrows the room its target has for it must fail the build,
+// saying how much there was of each part, instead of being cut short
+
+use kz80_chip8::codegen::{Compiler, Target};
+
+/// `count` ADD Vx, Vy instructions, each compiled on its own, then a
+/// sprite drawn from the ROM, which has it embedded, and a loop
+fn adds(count: usize) -> Vec<u8> {
+    let mut rom: Vec<u8> = (0..count).flat_map(|i| [0x80 | (i % 15) as u8, ((i + 1) % 15) as u8 * 0x10 + 4]).collect();
+    rom.extend_from_slice(&[0xA2, 0x00, 0xD0, 0x15, 0x12, 0x00]);
+    rom
+}
+
+/// The sizes in an overflow error: total, room, runtime, compiled code and
+/// embedded ROM
+fn sizes(error: &str) -> Vec<usize> {
+    let words = error.split(|c: char| !c.is_ascii_alphanumeric());
+    let numbers: Vec<usize> = words.filter_map(|word| word.parse().ok()).collect();
+    vec![numbers[0], numbers[1], numbers[numbers.len() - 3], numbers[numbers.len() - 2], numbers[numbers.len() - 1]]
+}
+
+#[test]
+fn too_much_code_fails_with_its_parts() {
+    let rom = adds(2500);
+    let error = Compiler::new().compile(&rom).unwrap_err();
+    assert!(error.starts_with("generated "), "{}", error);
+    assert!(error.contains("a retroshield build holds 32768 (from 0000 to RAM at 8000)"), "{}", error);
+    let [total, room, runtime, program, embedded] = sizes(&error)[..] else { unreachable!() };
+    assert!(total > room);
+    assert_eq!(runtime + program + embedded, total);
+    assert_eq!(embedded, rom.len());
+}
+
+#[test]
+fn each_target_has_its_own_room() {
+    let build = |target: Target, rom: &[u8]| {
+        let mut compiler = Compiler::new();
+        compiler.set_target(target);
+        compiler.compile(rom).unwrap_err()
+    };
+    // Coleco and SMS ROMs this long don't fit their 3584 bytes of CHIP-8
+    // RAM, so they end in EXIT rather than the sprite that would embed them
+    let exits = |count: usize| {
+        let mut rom = adds(count);
+        rom.truncate(count * 2);
+        rom.extend_from_slice(&[0x00, 0xFD]);
+        rom
+    };
+    assert!(build(Target::Msx, &adds(1200)).contains("a msx build holds 16384 (from 4000 to the end of the cartridge at 8000)"));
+    // Past FFFF, rather than wrapping round to 0000
+    assert!(build(Target::Coleco, &exits(6000)).contains("a coleco build holds 32768 (from 8000 to the end of the cartridge at 10000)"));
+    assert!(build(Target::Sms, &exits(2500)).contains("to the cartridge header at 7FF0"));
+}
+
+#[test]
+fn code_that_fits_is_all_there() {
+    let mut compiler = Compiler::new();
+    let code = compiler.compile_code(&adds(1500)).expect("compile");
+    assert!(code.code.len() > 0x6000);
+    let image = compiler.compile(&adds(1500)).unwrap();
+    assert_eq!(image.len(), 0x8000);
+    assert_eq!(image[..code.code.len()], code.code[..]);
+}
@@ -0,0 +1,139 @@
+// Output packaging tests
+// Each format must carry the image bytes at the flash base, with valid
+// record checksums and block headers; merged code must run beside a monitor
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::Compiler;
+use kz80_chip8::package::{self, Package, Tape};
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+fn image() -> Vec<u8> {
+    (0..300).map(|i| i as u8).collect()
+}
+
+fn hex_bytes(line: &str) -> Vec<u8> {
+    (0..line.len()).step_by(2).map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap()).collect()
+}
+
+#[test]
+fn bin_is_padded_to_the_flash_base() {
+    let out = Package::Bin.write(&image(), 0x100).unwrap();
+    assert_eq!(out.len(), 0x100 + 300);
+    assert!(out[..0x100].iter().all(|&b| b == 0xFF));
+    assert_eq!(&out[0x100..], &image()[..]);
+}
+
+#[test]
+fn intel_hex_records_rebuild_the_image() {
+    let text = String::from_utf8(Package::Hex.write(&image(), 0x1_FF00).unwrap()).unwrap();
+    let mut upper = 0u32;
+    let mut rebuilt = vec![0u8; 300];
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(*lines.last().unwrap(), ":00000001FF");
+    for line in lines {
+        let bytes = hex_bytes(line.strip_prefix(':').unwrap());
+        assert_eq!(bytes.iter().fold(0u8, |s, b| s.wrapping_add(*b)), 0, "checksum of {}", line);
+        let addr = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0x00 => {
+                let at = ((upper << 16) + addr - 0x1_FF00) as usize;
+                rebuilt[at..at + data.len()].copy_from_slice(data);
+            }
+            0x04 => upper = u16::from_be_bytes([data[0], data[1]]) as u32,
+            _ => {}
+        }
+    }
+    assert_eq!(rebuilt, image());
+}
+
+#[test]
+fn srec_uses_the_narrowest_address_width() {
+    let text = String::from_utf8(Package::Srec.write(&image(), 0x8000).unwrap()).unwrap();
+    assert!(text.lines().skip(1).all(|l| l.starts_with("S1") || l.starts_with("S9")));
+    let text = String::from_utf8(Package::Srec.write(&image(), 0x1_0000).unwrap()).unwrap();
+    let first = text.lines().nth(1).unwrap();
+    assert!(first.starts_with("S2"));
+    let bytes = hex_bytes(&first[2..]);
+    assert_eq!(bytes.iter().fold(0u8, |s, b| s.wrapping_add(*b)), 0xFF, "checksum");
+    assert_eq!(&bytes[1..4], &[0x01, 0x00, 0x00]);
+}
+
+#[test]
+fn uf2_blocks_carry_256_bytes_each() {
+    let out = Package::Uf2.write(&image(), 0x1000_0000).unwrap();
+    assert_eq!(out.len(), 2 * 512);
+    let word = |at: usize| u32::from_le_bytes(out[at..at + 4].try_into().unwrap());
+    for block in 0..2 {
+        let at = block * 512;
+        assert_eq!(word(at), 0x0A32_4655);
+        assert_eq!(word(at + 4), 0x9E5D_5157);
+        assert_eq!(word(at + 12), 0x1000_0000 + 256 * block as u32);
+        assert_eq!(word(at + 20), block as u32);
+        assert_eq!(word(at + 24), 2);
+        assert_eq!(word(at + 508), 0x0AB1_6F30);
+    }
+    assert_eq!(&out[32..32 + 256], &image()[..256]);
+    assert_eq!(&out[512 + 32..512 + 32 + 44], &image()[256..]);
+}
+
+#[test]
+fn wav_tones_carry_the_bytes() {
+    let tape = Tape { baud: 1200, leader_ms: 500 };
+    let out = Package::Wav(tape).write(&image(), 0).unwrap();
+    assert_eq!(&out[..4], b"RIFF");
+    assert_eq!(&out[8..16], b"WAVEfmt ");
+    assert_eq!(u32::from_le_bytes(out[24..28].try_into().unwrap()), 48_000);
+    assert_eq!(&out[36..40], b"data");
+    let samples = &out[44..];
+    // 40 samples a bit; a 0 is one cycle, a 1 two
+    let leader = 48_000 / 2;
+    assert_eq!(samples.len(), leader + 300 * 11 * 40);
+    let bit = |n: usize| {
+        let period = &samples[leader + n * 40..leader + (n + 1) * 40];
+        let falls = period.windows(2).filter(|pair| pair[0] > pair[1]).count();
+        match falls {
+            1 => 0,
+            2 => 1,
+            _ => panic!("bit {} has {} falls", n, falls),
+        }
+    };
+    let rebuilt: Vec<u8> = (0..300)
+        .map(|byte| {
+            assert_eq!((bit(byte * 11), bit(byte * 11 + 9), bit(byte * 11 + 10)), (0, 1, 1), "framing of byte {}", byte);
+            (0..8).map(|n| bit(byte * 11 + 1 + n) << n).sum::<u16>() as u8
+        })
+        .collect();
+    assert_eq!(rebuilt, image());
+    assert!(Package::Wav(Tape { baud: 2400, ..tape }).write(&image(), 0).unwrap_err().contains("2400"));
+    assert!(Package::Wav(tape).write(&image(), 0x100).is_err());
+}
+
+#[test]
+fn merged_game_runs_from_the_monitor() {
+    // A stand-in monitor: jump to the game, then some code of its own
+    let mut monitor = vec![0xC3, 0x00, 0x40];
+    monitor.extend_from_slice(&[0x76; 0x1000]);
+    let mut compiler = Compiler::new();
+    compiler.set_origin(0x4000);
+    let code = compiler.compile_code(include_bytes!("../test/classic/ibm_logo.ch8")).expect("compile");
+    assert_eq!(code.symbols["init"], 0x4100);
+
+    let image = package::merge(&monitor, &code.code, code.origin, 0x8000).unwrap();
+    assert_eq!(image.len(), 0x8000);
+    assert_eq!(&image[..monitor.len()], &monitor[..]);
+    assert_eq!(image[monitor.len()], 0xFF);
+    let mut cpu = Z80::with_rom(&image);
+    assert_eq!(cpu.run(5_000_000, None), StopReason::CycleLimit);
+    assert!(cpu.output().contains("CHIP-8 on Z80"));
+}
+
+#[test]
+fn merge_refuses_to_overwrite_the_base_rom() {
+    let mut base = vec![0xFF; 0x2000];
+    base[0x1234] = 0xC9;
+    let err = package::merge(&base, &[1; 0x100], 0x1200, 0).unwrap_err();
+    assert!(err.contains("1234"), "{}", err);
+    assert_eq!(package::merge(&base, &[1; 0x100], 0x1300, 0).unwrap().len(), 0x2000);
+}
@@ -0,0 +1,92 @@
+// Two-player key map tests
+// Each player's keys must reach the game while the other's are down: both
+// at once on a keyboard, and filed away in turn from a serial console
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+mod common;
+
+use kz80_chip8::codegen::{Compiler, Target};
+use kz80_chip8::diagnostics::Category;
+use kz80_chip8::keymap::KeyMap;
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::Z80;
+
+// 200: LD V0, 1 / SKNP V0 / LD V2, 1 / LD V1, C / SKNP V1 / LD V3, 1 / JP 200
+const BOTH_PLAYERS: &[u8] = &[0x60, 0x01, 0xE0, 0xA1, 0x62, 0x01, 0x61, 0x0C, 0xE1, 0xA1, 0x63, 0x01, 0x12, 0x00];
+
+const ZONES: &str = "player1 = [1, 4, \"7\"]  # left hand\nplayer2 = [\"C\", 0xD, \"f\"]\nw = 1\n";
+
+fn build(target: Target, keymap: Option<&str>) -> Compiler {
+    let mut compiler = Compiler::new();
+    compiler.set_target(target);
+    if let Some(keymap) = keymap {
+        compiler.set_keymap(KeyMap::parse(keymap).unwrap());
+    }
+    compiler
+}
+
+#[test]
+fn zones_read_in_both_formats() {
+    let lines = KeyMap::parse(ZONES).unwrap();
+    let json = KeyMap::parse(r#"{"player2": [15, "c", 13], "player1": [7, 4, 1], "w": 1}"#).unwrap();
+    assert_eq!(lines, json);
+    assert_eq!(lines.players, Some([vec![1, 4, 7], vec![12, 13, 15]]));
+    assert_eq!((lines.player(4), lines.player(0xD), lines.player(2)), (Some(0), Some(1), None));
+    // Kept whole through an options file
+    let options = CompileOptions::builder().keymap(lines.clone()).build().unwrap();
+    let (read, _) = CompileOptions::from_json(&options.to_json()).unwrap();
+    assert_eq!(read.keymap, Some(lines));
+
+    assert!(KeyMap::parse("player1 = [1, 4]").unwrap_err().contains("both player1 and player2"));
+    assert!(KeyMap::parse("player1 = [1, 4]\nplayer2 = [4, 5]").unwrap_err().contains("key 4 is in both"));
+    assert!(KeyMap::parse("player1 = []\nplayer2 = [5]").unwrap_err().contains("player1 has no keys"));
+    assert!(KeyMap::parse("player1 = 1\nplayer2 = [5]").unwrap_err().starts_with("line 1: expected a list"));
+    let err = build(Target::Sms, Some("player1 = [1]\nplayer2 = [C]")).compile_code(BOTH_PLAYERS).unwrap_err();
+    assert!(err.contains("one controller"), "{}", err);
+}
+
+#[test]
+fn both_players_hold_keys_on_a_keyboard() {
+    let held = |keymap: Option<&str>| {
+        let code = build(Target::Spectrum, keymap).compile_code(BOTH_PLAYERS).expect("compile");
+        let mut cpu = common::boot(&code);
+        // Every half-row reads the same here: bit 0 is key 1 (and 4 and 7),
+        // bit 3 is key C (and B)
+        cpu.in_ports[0xFE] = !0x09;
+        cpu.run(1_000_000, None);
+        (cpu.mem[0x8002], cpu.mem[0x8003])
+    };
+    // Alone, the scan stops at the first key down
+    assert_eq!(held(None), (1, 0));
+    assert_eq!(held(Some("player1 = [1, 4]\nplayer2 = [C, D]")), (1, 1));
+}
+
+#[test]
+fn console_keys_stay_down_for_both_players() {
+    let typed = |keymap: Option<&str>| {
+        let code = build(Target::RetroShield, keymap).compile_code(BOTH_PLAYERS).expect("compile");
+        let mut cpu = Z80::with_rom(&code.code);
+        cpu.run(1_000_000, Some(code.symbols["c8_200"]));
+        // Player 2's key, then player 1's: each first read by the other's test,
+        // and still down at its own
+        cpu.send(b"c1");
+        cpu.run(1_000_000, None);
+        (cpu.mem[0x8002], cpu.mem[0x8003])
+    };
+    assert_eq!(typed(None), (1, 1));
+    assert_eq!(typed(Some("player1 = [1, 4]\nplayer2 = [C, D]")), (1, 1));
+}
+
+#[test]
+fn reading_both_players_from_a_console_warns() {
+    let warnings = |target: Target, keymap: &str| {
+        let code = build(target, Some(keymap)).compile_code(BOTH_PLAYERS).expect("compile");
+        code.diagnostics.iter().filter(|d| d.category == Category::TwoPlayer).map(|d| d.to_string()).collect::<Vec<_>>()
+    };
+    let two = warnings(Target::Cpm, "player1 = [1, 4]\nplayer2 = [C, D]");
+    assert_eq!(two.len(), 1);
+    assert!(two[0].contains("(player 1: 1, player 2: C)"), "{}", two[0]);
+    assert!(warnings(Target::Cpm, "player1 = [1, 4]\nplayer2 = [D]").is_empty());
+    assert!(warnings(Target::Spectrum, "player1 = [1, 4]\nplayer2 = [C, D]").is_empty());
+}
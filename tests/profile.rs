@@ -0,0 +1,82 @@
+// Profile-guided build tests
+// A profile must move the busiest blocks to the start of the program,
+// inline the timer tick only in blocks run at least 1/16 as often as the
+// hottest, and carry a V register in A into a hot block when every way in
+// leaves it there
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, Layout};
+use kz80_chip8::profile::Profile;
+use kz80_chip8::z80emu::Z80;
+
+// LD V0, 0 / ADD V0, 1 / SE V0, 10 / JP 202 / ADD V1, 1 / JP 202
+const LOOP: &[u8] = &[0x60, 0x00, 0x70, 0x01, 0x30, 0x0A, 0x12, 0x02, 0x71, 0x01, 0x12, 0x02];
+
+// LD V0, 0 / ADD V0, 1 / SE V0, 10 / JP 202 / JP 208
+const COUNT: &[u8] = &[0x60, 0x00, 0x70, 0x01, 0x30, 0x0A, 0x12, 0x02, 0x12, 0x08];
+
+fn compile(rom: &[u8], counts: &[(u16, u64)]) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    if !counts.is_empty() {
+        compiler.set_layout(Layout::Profile(Profile { counts: counts.iter().copied().collect() }));
+    }
+    compiler.compile_code(rom).expect("compile")
+}
+
+fn build(counts: &[(u16, u64)]) -> CompiledCode {
+    compile(LOOP, counts)
+}
+
+/// Whether the block at a CHIP-8 address starts with the inlined tick
+/// (LD HL,(TICK_COUNT)) rather than LD A,weight / CALL timer_tick
+fn inlined(code: &CompiledCode, addr: u16) -> bool {
+    let at = code.symbols[&format!("c8_{:03X}", addr)] - code.origin;
+    match code.code[at as usize] {
+        0x2A => true,
+        0x3E => false,
+        byte => panic!("block {:03X} starts with {:02X}", addr, byte),
+    }
+}
+
+#[test]
+fn busiest_blocks_come_first() {
+    let source = build(&[]);
+    let symbol = |code: &CompiledCode, addr: u16| code.symbols[&format!("c8_{:03X}", addr)];
+    assert!(symbol(&source, 0x200) < symbol(&source, 0x202) && symbol(&source, 0x202) < symbol(&source, 0x208));
+    let profiled = build(&[(0x200, 1), (0x202, 5120), (0x208, 100)]);
+    let first = profiled.symbols.iter().filter(|(name, _)| name.starts_with("c8_")).map(|(_, &at)| at).min();
+    assert_eq!(first, Some(symbol(&profiled, 0x202)));
+    assert!(symbol(&profiled, 0x202) < symbol(&profiled, 0x208) && symbol(&profiled, 0x208) < symbol(&profiled, 0x200));
+}
+
+#[test]
+fn hot_blocks_inline_the_tick() {
+    // 100 is under 1/16 of 5120, so only the loop is hot
+    let code = build(&[(0x200, 1), (0x202, 5120), (0x208, 100)]);
+    assert!(inlined(&code, 0x202));
+    assert!(!inlined(&code, 0x208));
+    // At 1/10 of the hottest it is hot too; without a profile nothing is
+    let code = build(&[(0x200, 1), (0x202, 1000), (0x208, 100)]);
+    assert!(inlined(&code, 0x202) && inlined(&code, 0x208));
+    let code = build(&[]);
+    assert!(!inlined(&code, 0x202) && !inlined(&code, 0x208));
+}
+
+#[test]
+fn hot_blocks_keep_v_registers_in_a() {
+    // LD V0, 0 and SE V0, 10 both leave V0 in A going into the loop, so
+    // ADD V0, 1 needn't load it (LD A,(8000)); the count comes out the same
+    let load_v0 = [0x3A, 0x00, 0x80];
+    for (counts, loads) in [(&[][..], true), (&[(0x200, 1), (0x202, 1000), (0x208, 1)][..], false)] {
+        let code = compile(COUNT, counts);
+        let block = |addr: u16| (code.symbols[&format!("c8_{:03X}", addr)] - code.origin) as usize;
+        let add = &code.code[block(0x202)..block(0x204)];
+        assert_eq!(add.windows(3).any(|bytes| bytes == load_v0), loads, "{:02X?}", add);
+        let mut cpu = Z80::new();
+        cpu.load(code.origin, &code.code);
+        cpu.pc = code.origin;
+        cpu.run(5_000_000, Some(code.symbols["c8_208"]));
+        assert_eq!(cpu.mem[0x8000], 10);
+    }
+}
@@ -0,0 +1,77 @@
+// AY-3-8910 sound tests
+// --audio ay8910 must set up tone A at startup, then turn its volume up
+// while the sound timer runs and down when it stops, at the ports given
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+mod common;
+
+use kz80_chip8::codegen::{Audio, Compiler, Target};
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::Z80;
+
+// 200: LD V0, 30 / LD ST, V0 / LD V1, 60 / LD DT, V1 /
+// 208: LD V1, DT / SE V1, 0 / JP 208 / JP 200: half a second on, half off
+const BEEPING: &[u8] = &[0x60, 0x1E, 0xF0, 0x18, 0x61, 0x3C, 0xF1, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x08, 0x12, 0x00];
+
+/// Every write to the PSG's registers in `seconds` at 4MHz, as (cycle,
+/// register, value)
+fn psg_writes(compiler: &mut Compiler, register: u8, data: u8, seconds: u64) -> Vec<(u64, u8, u8)> {
+    let code = compiler.compile_code(BEEPING).expect("compile");
+    let mut cpu = common::boot(&code);
+    cpu.run(seconds * 4_000_000, None);
+    let mut selected = 0xFF;
+    let mut writes = Vec::new();
+    for &(cycle, port, value) in &cpu.out_log {
+        if port == register {
+            selected = value;
+        } else if port == data {
+            writes.push((cycle, selected, value));
+        }
+    }
+    writes
+}
+
+#[test]
+fn the_tone_sounds_while_the_timer_runs() {
+    let mut compiler = Compiler::new();
+    compiler.set_audio(Audio::from_name("ay8910").unwrap());
+    let writes = psg_writes(&mut compiler, 0xD8, 0xD0, 3);
+    // Silent, 440Hz on tone A, tone A alone
+    let setup: Vec<(u8, u8)> = writes[..4].iter().map(|&(_, reg, value)| (reg, value)).collect();
+    assert_eq!(setup, [(8, 0), (0, 252), (1, 0), (7, 0xBE)]);
+    // Set each frame: up for each of the 30 frames, down once it runs out
+    let volume: Vec<u8> = writes.iter().filter(|w| w.1 == 8).map(|w| w.2).collect();
+    let sounds: Vec<usize> = volume.split(|&level| level == 0).map(|run| run.len()).filter(|&len| len > 0).collect();
+    assert!(sounds.len() >= 2, "{:?}", volume);
+    assert!(sounds[..sounds.len() - 1].iter().all(|&frames| frames == 30), "{:?}", sounds);
+}
+
+#[test]
+fn the_ports_are_the_boards() {
+    // A PSG decoded elsewhere
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Rc2014);
+    compiler.set_audio(Audio::Ay8910 { register: 0xA0, data: 0xA1 });
+    let writes = psg_writes(&mut compiler, 0xA0, 0xA1, 2);
+    assert!(writes.iter().any(|&(_, reg, value)| (reg, value) == (8, 15)), "never sounded");
+    let info = String::from_utf8_lossy(&compiler.compile_code(BEEPING).unwrap().code).into_owned();
+    assert!(info.contains("audio=ay8910:A0,A1"), "no build info");
+
+    let options = CompileOptions::builder().audio(Audio::Ay8910 { register: 0xA0, data: 0xA1 }).build().unwrap();
+    assert!(options.to_json().contains("\"ay8910\": {\n      \"data\": 161,\n      \"register\": 160\n    }"), "{}", options.to_json());
+    assert_eq!(CompileOptions::from_json(&options.to_json()).unwrap().0, options);
+}
+
+#[test]
+fn a_beeper_can_sound_too() {
+    let mut compiler = Compiler::new();
+    compiler.set_beeper(0x40, 3);
+    compiler.set_audio(Audio::from_name("ay8910").unwrap());
+    let code = compiler.compile_code(BEEPING).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.run(6_000_000, None);
+    let beeps = cpu.out_log.iter().filter(|&&(_, port, value)| port == 0x40 && value == 0x08).count();
+    let tones = cpu.out_log.iter().filter(|&&(_, port, value)| port == 0xD0 && value == 15).count();
+    assert!(beeps > 0 && beeps == tones, "{} beeps, {} tones", beeps, tones);
+}
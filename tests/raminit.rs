@@ -0,0 +1,65 @@
+// RAM fill tests
+// --ram-init must leave CHIP-8 RAM as asked before the game runs, and the
+// analyzer must flag loads of RAM that nothing in the ROM stores
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+use kz80_chip8::analysis;
+use kz80_chip8::codegen::{Compiler, RamInit, Target};
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::Z80;
+
+// LD I, 300 / LD V0..V3, [I] / JP 204
+const LOAD_300: &[u8] = &[0xA3, 0x00, 0xF3, 0x65, 0x12, 0x04];
+
+/// V0-V3 after LOAD_300 runs
+fn loaded(target: Target, ram_init: Option<RamInit>) -> [u8; 4] {
+    let mut compiler = Compiler::new();
+    compiler.set_target(target);
+    if let Some(ram_init) = ram_init {
+        compiler.set_ram_init(ram_init);
+    }
+    let code = compiler.compile_code(LOAD_300).expect("compile");
+    let mut cpu = Z80::new();
+    cpu.mem.fill(0x5A);  // What was there before
+    cpu.load(code.origin, &code.code);
+    cpu.pc = code.origin;
+    cpu.run(5_000_000, Some(code.symbols["c8_204"]));
+    cpu.mem[0x8000..0x8004].try_into().unwrap()
+}
+
+#[test]
+fn ram_is_filled_as_asked() {
+    assert_eq!(loaded(Target::RetroShield, None), [0x5A; 4]);
+    assert_eq!(loaded(Target::RetroShield, Some(RamInit::Zero)), [0; 4]);
+    assert_eq!(loaded(Target::Rc2014, Some(RamInit::Ff)), [0xFF; 4]);
+    let random = loaded(Target::RetroShield, Some(RamInit::Random(7)));
+    assert_ne!(random, loaded(Target::RetroShield, Some(RamInit::Random(8))));
+    assert!(random.iter().any(|&byte| byte != random[0]), "{:02X?}", random);
+}
+
+#[test]
+fn fills_are_named_and_kept() {
+    for name in ["zero", "ff", "random(1234)"] {
+        assert_eq!(RamInit::from_name(name).unwrap().name(), name);
+    }
+    assert_eq!(RamInit::from_name("random(0x10)"), Some(RamInit::Random(16)));
+    assert_eq!(RamInit::from_name("random"), None);
+    assert_eq!(RamInit::from_name("random(70000)"), None);
+    let options = CompileOptions::builder().ram_init(RamInit::Random(9)).build().unwrap();
+    let (read, _) = CompileOptions::from_json(&options.to_json()).unwrap();
+    assert_eq!(read.ram_init, Some(RamInit::Random(9)));
+}
+
+#[test]
+fn loads_of_unwritten_ram_are_flagged() {
+    let map = analysis::classify(LOAD_300);
+    assert_eq!(map.unwritten_reads.len(), 1);
+    assert_eq!((map.unwritten_reads[0].at, map.unwritten_reads[0].source, map.unwritten_reads[0].len), (0x202, 0x300, 4));
+    // LD I, 300 / LD [I], V0..V3 / LD V0..V3, [I] / JP 206: stored first
+    assert!(analysis::classify(&[0xA3, 0x00, 0xF3, 0x55, 0xF3, 0x65, 0x12, 0x06]).unwritten_reads.is_empty());
+    // Only V0 stored, but four loaded
+    assert_eq!(analysis::classify(&[0xA3, 0x00, 0xF0, 0x55, 0xF3, 0x65, 0x12, 0x06]).unwritten_reads.len(), 1);
+    // Anywhere, after an FX55 with I unknown
+    assert!(analysis::classify(&[0xF0, 0x1E, 0xF0, 0x55, 0xA3, 0x00, 0xF3, 0x65, 0x12, 0x08]).unwritten_reads.is_empty());
+}
@@ -0,0 +1,57 @@
+// RC2014 target tests
+// The ROM must set up channel A of the SIO/2 and talk to the terminal
+// through its RR0 status bits rather than the ACIA's
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, Target};
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+fn compile(rom: &[u8]) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Rc2014);
+    compiler.compile_code(rom).expect("compile")
+}
+
+#[test]
+fn game_runs_on_the_sio_console() {
+    let code = compile(include_bytes!("../test/classic/ibm_logo.ch8"));
+    assert_eq!(code.origin, 0x0000);
+    assert!(!code.symbols.contains_key("acia_init"));
+    let mut cpu = Z80::with_rom(&code.code);
+    assert_eq!(cpu.run(6_000_000, None), StopReason::CycleLimit);
+    // Channel reset, then WR4, WR3, WR5 and WR1 through the control port
+    let init: Vec<u8> = cpu.out_log.iter().filter(|&&(_, port, _)| port == 0x80).map(|&(_, _, value)| value).take(9).collect();
+    assert_eq!(init, [0x18, 0x04, 0xC4, 0x03, 0xC1, 0x05, 0xEA, 0x01, 0x00]);
+    let output = cpu.output();
+    assert!(output.contains("CHIP-8 on Z80"));
+    assert!(output.contains("####"));
+}
+
+#[test]
+fn output_waits_for_the_transmit_buffer() {
+    let code = compile(&[0x12, 0x00]);
+    let mut cpu = Z80::with_rom(&code.code);
+    // RR0 from the latch: the ACIA's transmit bit is set, not the SIO's
+    cpu.acia_ctrl = 0x00;
+    cpu.in_ports[0x80] = 0x02;
+    cpu.run(200_000, None);
+    assert!(cpu.tx.is_empty());
+    cpu.in_ports[0x80] = 0x04;
+    cpu.run(2_000_000, None);
+    assert!(cpu.output().contains("CHIP-8 on Z80"));
+}
+
+#[test]
+fn keys_and_build_info_come_from_the_sio() {
+    // FX0A into V0 until it is non-zero, then spin
+    let code = compile(&[0xF0, 0x0A, 0x40, 0x00, 0x12, 0x00, 0x12, 0x06]);
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.run(1_000_000, None);
+    cpu.send(b"?");
+    cpu.run(2_000_000, None);
+    assert!(cpu.output().contains("Options: target=rc2014\r\n"), "{:?}", cpu.output());
+    cpu.send(b"b");
+    cpu.run(1_000_000, None);
+    assert_eq!(cpu.read(0x8000), 0xB);
+}
@@ -0,0 +1,61 @@
+// Reachability tests
+// Only words the program can execute are compiled; these check that the
+// walk from 0x200 leaves data and dead return addresses alone
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::chip8;
+use kz80_chip8::codegen::Compiler;
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+fn rom(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_be_bytes()).collect()
+}
+
+fn addresses(words: &[u16]) -> Vec<u16> {
+    chip8::parse(&rom(words)).iter().map(|inst| inst.addr).collect()
+}
+
+#[test]
+fn data_after_a_jump_is_not_code() {
+    // 200: JP 206; 202-205: sprite bytes; 206: EXIT
+    assert_eq!(addresses(&[0x1206, 0xFF81, 0x81FF, 0x00FD]), [0x200, 0x206]);
+}
+
+#[test]
+fn return_address_of_a_subroutine_that_never_returns_is_dead() {
+    // 200: CALL 204; 202: dead; 204: EXIT
+    assert_eq!(addresses(&[0x2204, 0x6001, 0x00FD]), [0x200, 0x204]);
+    // 200: CALL 206; 202: LD V0,1; 204: EXIT; 206: CALL 20A; 208: RET; 20A: RET
+    assert_eq!(addresses(&[0x2206, 0x6001, 0x00FD, 0x220A, 0x00EE, 0x00EE]), [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A]);
+    // 200: CALL 206; 202: dead; 204: dead; 206: CALL 20A; 208: RET (dead); 20A: EXIT
+    assert_eq!(addresses(&[0x2206, 0x6001, 0x00FD, 0x220A, 0x00EE, 0x00FD]), [0x200, 0x206, 0x20A]);
+}
+
+#[test]
+fn call_without_a_return_address_still_runs() {
+    // 200: CALL 204; 202: dead; 204: LD V1,5; 206: EXIT
+    let code = Compiler::new().compile_code(&rom(&[0x2204, 0x6001, 0x6105, 0x00FD])).expect("compile");
+    assert!(!code.symbols.contains_key("c8_202"));
+    let mut cpu = Z80::with_rom(&code.code);
+    assert_eq!(cpu.run(1_000_000, None), StopReason::Halted);
+    assert_eq!(cpu.read(0x8001), 5);
+}
+
+#[test]
+fn region_hints_override_the_walk() {
+    // 200: LD I,208; 202: DRW; 204: JP 208; 206: code only reached through
+    // a table the walk can't see; 208-20B: data that decodes as EXIT, JP 200
+    let words = rom(&[0xA208, 0xD011, 0x1208, 0x6001, 0x00FD, 0x1200]);
+    let mut regions = chip8::RegionMap::default();
+    regions.code.push(0x206..0x208);
+    regions.data.push(0x208..0x20C);
+    let addrs: Vec<u16> = chip8::parse_with_regions(&words, &regions).iter().map(|inst| inst.addr).collect();
+    assert_eq!(addrs, [0x200, 0x202, 0x204, 0x206]);
+
+    let lines = chip8::disassemble_with_regions(&words, &regions);
+    assert_eq!(lines.len(), 8);
+    assert!(matches!(lines[3], chip8::Line::Code { inst, .. } if inst.addr == 0x206));
+    assert_eq!(lines[4], chip8::Line::Data { addr: 0x208, byte: 0x00 });
+    assert_eq!(lines[7].to_string(), "20B: 00    DB   00      ........");
+}
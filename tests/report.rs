@@ -0,0 +1,59 @@
+// Batch report tests
+// Each ROM gets a numbered status line, the summary adds them all up, and
+// colour and the per-ROM output can be switched off
+
+#![cfg(feature = "cli")]
+
+use kz80_chip8::report::{Entry, Report, Status};
+use std::process::Command;
+
+fn entry(name: &str, status: Status, bytes: usize, warnings: usize) -> Entry {
+    Entry { name: name.to_string(), status, bytes, warnings, note: None }
+}
+
+#[test]
+fn progress_lines_count_up() {
+    let mut report = Report::new(12, false);
+    assert_eq!(report.add(entry("pong.ch8", Status::Ok, 3005, 0)), "[ 1/12] ok      pong.ch8 (3005 bytes)");
+    let mut failed = entry("maze.ch8", Status::Failed, 1229, 2);
+    failed.note = Some("no golden".to_string());
+    assert_eq!(report.add(failed), "[ 2/12] FAILED  maze.ch8 (1229 bytes, 2 warnings): no golden");
+    let mut colored = Report::new(1, true);
+    assert_eq!(colored.add(entry("a.ch8", Status::Blessed, 1, 1)), "[1/1] \x1b[36mblessed\x1b[0m a.ch8 (1 bytes, 1 warning)");
+}
+
+#[test]
+fn summary_totals_the_batch() {
+    let mut report = Report::new(3, false);
+    report.add(entry("test/classic/pong.ch8", Status::Ok, 3005, 0));
+    report.add(entry("maze.ch8", Status::Failed, 1229, 1));
+    report.add(entry("tetris.ch8", Status::Ok, 5462, 2));
+    assert_eq!(report.failed(), 1);
+    let summary = report.summary();
+    let lines: Vec<&str> = summary.lines().collect();
+    assert_eq!(lines[0], "ROM                    Result    Bytes  Warnings");
+    assert_eq!(lines[2], "maze.ch8               FAILED     1229         1");
+    assert_eq!(lines[4], "3 ROMs: 2 passed, 1 failed, 3 warnings, 9696 bytes");
+}
+
+#[test]
+fn quiet_golden_runs_print_only_the_outcome() {
+    let file = std::env::temp_dir().join(format!("kz80_chip8_report_{}.txt", std::process::id()));
+    let file = file.to_str().unwrap();
+    let golden = |extra: &[&str]| {
+        let result = Command::new(env!("CARGO_BIN_EXE_kz80_chip8"))
+            .args(["golden", "test/classic/maze.ch8", "test/hello.ch8", "--frames", "5", "--goldens", file])
+            .args(extra)
+            .output()
+            .expect("run kz80_chip8");
+        assert!(result.status.success());
+        String::from_utf8_lossy(&result.stdout).into_owned()
+    };
+    let blessed = golden(&["--bless"]);
+    assert!(blessed.contains("[2/2] blessed test/hello.ch8 ("), "{}", blessed);
+    assert!(blessed.contains("2 ROMs: 2 passed, 0 failed, 2 warnings"), "{}", blessed);
+    assert!(!blessed.contains('\x1b'));  // Not a terminal
+    let quiet = golden(&["--quiet"]);
+    std::fs::remove_file(file).unwrap();
+    assert_eq!(quiet, "2 displays match their goldens\n");
+}
@@ -0,0 +1,53 @@
+// Embedded ROM data tests
+// The ROM must be embedded for any game that can draw a sprite from it, and
+// left out of one whose I never gets near it
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, DEFAULT_CLOCK_HZ, DISPLAY_BUF};
+use kz80_chip8::golden;
+use kz80_chip8::quirks::Quirks;
+
+const DRAW0: &[u8] = include_bytes!("../test/draw0.ch8");
+const CUSTOM: &[u8] = include_bytes!("../test/custom.ch8");
+
+fn build(rom: &[u8], quirks: Quirks) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_quirks(quirks);
+    compiler.compile_code(rom).expect("compile")
+}
+
+/// The ROM as embedded, empty if it was left out
+fn embedded(code: &CompiledCode) -> &[u8] {
+    &code.code[(code.symbols["chip8_rom_data"] - code.origin) as usize..]
+}
+
+#[test]
+fn font_games_leave_it_out() {
+    let code = build(DRAW0, Quirks::default());
+    assert!(embedded(&code).is_empty());
+    // The digit is still drawn
+    let cpu = golden::emulate(&code, DEFAULT_CLOCK_HZ, 10);
+    assert!(cpu.mem[DISPLAY_BUF as usize..DISPLAY_BUF as usize + 256].iter().any(|&byte| byte != 0), "nothing drawn");
+}
+
+#[test]
+fn rom_sprites_keep_it() {
+    let code = build(CUSTOM, Quirks::default());
+    assert_eq!(embedded(&code), CUSTOM);
+}
+
+#[test]
+fn an_index_that_can_reach_the_rom_keeps_it() {
+    let kept = |rom: &[u8], quirks: Quirks| !embedded(&build(rom, quirks)).is_empty();
+    // LD I, NNN / DRW V0, V1, 5 / JP 204
+    let draw_at = |nnn: u16| vec![0xA0 | (nnn >> 8) as u8, nnn as u8, 0xD0, 0x15, 0x12, 0x04];
+    assert!(!kept(&draw_at(0x1E0), Quirks::default()));
+    assert!(kept(&draw_at(0x1E1), Quirks::default()), "a 16x16 sprite at 1E1 ends in the ROM");
+    // LD I, 100 / ADD I, V0 / DRW V0, V1, 5 / JP 206
+    assert!(kept(&[0xA1, 0x00, 0xF0, 0x1E, 0xD0, 0x15, 0x12, 0x06], Quirks::default()));
+    // LD I, 100 / LD V0..V0, [I] / DRW V0, V1, 5 / JP 202
+    let loads = [0xA1, 0x00, 0xF0, 0x65, 0xD0, 0x15, 0x12, 0x02];
+    assert!(!kept(&loads, Quirks::default()));
+    assert!(kept(&loads, Quirks { load_store_increment: true, ..Quirks::default() }));
+}
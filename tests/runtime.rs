@@ -0,0 +1,70 @@
+// Runtime routine tests
+// The emulator calls the generated routines directly, so that each one is
+// checked against what it claims to do rather than through a whole game
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, CHIP8_RAM, DISPLAY_BUF};
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+const RNG: u16 = 0x8016;
+
+fn compile(rom: &[u8]) -> CompiledCode {
+    Compiler::new().compile_code(rom).expect("compile")
+}
+
+fn cpu(code: &CompiledCode) -> Z80 {
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.sp = 0xF000;
+    cpu
+}
+
+#[test]
+fn draw_sprite_xors_and_reports_collisions() {
+    let code = compile(&[0x12, 0x00]);
+    let mut cpu = cpu(&code);
+    cpu.load(0x9000, &[0xF0, 0x90]);
+    let screen = DISPLAY_BUF + 3 * 8 + 1;  // Row 3, byte 1
+    let draw = |cpu: &mut Z80| {
+        cpu.set_de(screen);
+        cpu.set_hl(0x9000);
+        cpu.b = 2;
+        assert_eq!(cpu.call(code.symbols["draw_sprite"], 10_000), StopReason::Breakpoint);
+        cpu.a
+    };
+    assert_eq!(draw(&mut cpu), 0);
+    assert_eq!([cpu.read(screen), cpu.read(screen + 8)], [0xF0, 0x90]);
+    assert_eq!(draw(&mut cpu), 1);  // Drawn over itself: erased, with a collision
+    assert_eq!([cpu.read(screen), cpu.read(screen + 8)], [0x00, 0x00]);
+    assert!((0..256).all(|i| cpu.read(DISPLAY_BUF + i) == 0));
+}
+
+#[test]
+fn rng_steps_its_shift_register() {
+    let code = compile(&[0x12, 0x00]);
+    let mut cpu = cpu(&code);
+    let mut state: u16 = 0xE1AC;
+    cpu.write16(RNG, state);
+    for _ in 0..100 {
+        // HL doubled, then rotated left through the carry, then L ^= H
+        let doubled = state << 1;
+        let l = (doubled as u8) << 1 | (state >> 15) as u8;
+        let h = ((doubled >> 8) as u8) << 1 | (doubled as u8) >> 7;
+        state = (h as u16) << 8 | (l ^ h) as u16;
+        assert_eq!(cpu.call(code.symbols["rng"], 1_000), StopReason::Breakpoint);
+        assert_eq!(cpu.a, state as u8);
+        assert_eq!(cpu.read16(RNG), state);
+    }
+}
+
+#[test]
+fn bcd_stores_three_digits() {
+    for value in [0u8, 7, 10, 99, 100, 234, 255] {
+        // LD V0, value / LD I, 300 / LD B, V0 / JP 206
+        let code = compile(&[0x60, value, 0xA3, 0x00, 0xF0, 0x33, 0x12, 0x06]);
+        let mut cpu = cpu(&code);
+        cpu.run(2_000_000, None);
+        let digits: Vec<u8> = (0..3).map(|i| cpu.read(CHIP8_RAM + 0x100 + i)).collect();
+        assert_eq!(digits, [value / 100, value / 10 % 10, value % 10], "{}", value);
+    }
+}
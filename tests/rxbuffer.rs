@@ -0,0 +1,83 @@
+// Serial receive buffer tests
+// With --rx-buffer the ACIA's interrupt must take each character as it
+// arrives, even mid-draw, and get_key must read them back in order
+
+#![cfg(all(feature = "emulator", feature = "config"))]
+
+use kz80_chip8::codegen::{Compiler, Target};
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::Z80;
+
+// 200: DRW V0, V1, 15 / JP 200
+const DRAW_FOREVER: &[u8] = &[0xD0, 0x1F, 0x12, 0x00];
+
+// 200: LD V2, 60 / LD DT, V2 / LD V2, DT / SE V2, 0 / JP 204, then
+// 20A: LD V0, K / ADD V1, 1 / JP 20A: a second's wait, then count keys
+const WAIT_THEN_COUNT: &[u8] = &[
+    0x62, 0x3C, 0xF2, 0x15, 0xF2, 0x07, 0x32, 0x00, 0x12, 0x04,
+    0xF0, 0x0A, 0x71, 0x01, 0x12, 0x0A,
+];
+
+fn boot(rom: &[u8], rx_buffer: bool) -> (Z80, kz80_chip8::codegen::CompiledCode) {
+    let mut compiler = Compiler::new();
+    compiler.set_rx_buffer(rx_buffer);
+    compiler.set_key_release(0);
+    let code = compiler.compile_code(rom).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.run(1_000_000, Some(code.symbols["main"]));
+    (cpu, code)
+}
+
+#[test]
+fn characters_are_taken_while_drawing() {
+    let (mut cpu, code) = boot(DRAW_FOREVER, true);
+    cpu.send(b"5a");
+    cpu.run(2_000, None);
+    assert!(cpu.rx.is_empty(), "left at the ACIA");
+    for expected in [b'5', b'a'] {
+        cpu.call(code.symbols["rx_read"], 1_000);
+        assert_eq!(cpu.a, expected);
+        assert_eq!(cpu.f & 0x40, 0, "Z set with a character");
+    }
+    cpu.call(code.symbols["rx_read"], 1_000);
+    assert_ne!(cpu.f & 0x40, 0, "NZ with nothing received");
+
+    // Polling, they wait at the port for get_key
+    let (mut cpu, _) = boot(DRAW_FOREVER, false);
+    cpu.send(b"5a");
+    cpu.run(2_000, None);
+    assert_eq!(cpu.rx.len(), 2);
+}
+
+#[test]
+fn keys_come_out_in_order_until_the_ring_fills() {
+    let typed: Vec<u8> = b"0123456789abcdef".iter().cycle().take(40).copied().collect();
+    let (mut cpu, _) = boot(WAIT_THEN_COUNT, true);
+    cpu.send(&typed);
+    cpu.run(20_000_000, None);
+    // 31 fit in the ring; the rest were dropped
+    assert_eq!((cpu.mem[0x8000], cpu.mem[0x8001]), (0xE, 31));
+    let (mut cpu, _) = boot(WAIT_THEN_COUNT, false);
+    cpu.send(b"123");
+    cpu.run(20_000_000, None);
+    assert_eq!((cpu.mem[0x8000], cpu.mem[0x8001]), (3, 3));
+}
+
+#[test]
+fn only_the_retroshield_at_0000_has_it() {
+    let build = |target: Target, origin: Option<u16>| {
+        let mut compiler = Compiler::new();
+        compiler.set_target(target);
+        if let Some(origin) = origin {
+            compiler.set_origin(origin);
+        }
+        compiler.set_rx_buffer(true);
+        compiler.compile_code(DRAW_FOREVER).map(|code| String::from_utf8_lossy(&code.code).into_owned())
+    };
+    assert!(build(Target::RetroShield, None).unwrap().contains("rx-buffer"), "no build info");
+    assert!(build(Target::Rc2014, None).unwrap_err().contains("a rc2014 build doesn't have"));
+    assert!(build(Target::RetroShield, Some(0x4000)).unwrap_err().contains("needs the code at 0000"));
+    let options = CompileOptions::builder().rx_buffer(true).build().unwrap();
+    let (read, _) = CompileOptions::from_json(&options.to_json()).unwrap();
+    assert!(read.rx_buffer);
+}
@@ -0,0 +1,68 @@
+// Framebuffer scale tests
+// At twice the size every display pixel must fill its 2x2 block of video
+// memory in any orientation, or its top row alone with scanlines
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Compiler, Orientation, Scale, DEFAULT_CLOCK_HZ, DISPLAY_BUF};
+use kz80_chip8::golden;
+
+const IBM_LOGO: &[u8] = include_bytes!("../test/classic/ibm_logo.ch8");
+
+/// Video memory after the logo is drawn, and the display buffer
+fn draw(orientation: Orientation, scale: Scale, stride: u16) -> (Vec<u8>, Vec<u8>) {
+    let mut compiler = Compiler::new();
+    compiler.set_framebuffer(0x5000, stride);
+    compiler.set_orientation(orientation);
+    compiler.set_scale(scale);
+    let code = compiler.compile_code(IBM_LOGO).expect("compile");
+    let cpu = golden::emulate(&code, DEFAULT_CLOCK_HZ, 60);
+    (cpu.mem[0x5000..0x7000].to_vec(), cpu.mem[DISPLAY_BUF as usize..DISPLAY_BUF as usize + 256].to_vec())
+}
+
+#[test]
+fn each_pixel_fills_its_block() {
+    for rotate in [0, 90, 180, 270] {
+        for (mirror, lsb_first) in [(false, false), (true, false), (false, true), (true, true)] {
+            let orientation = Orientation { rotate, mirror, lsb_first };
+            let sideways = rotate % 180 == 90;
+            let (width, height, stride) = if sideways { (64, 128, 9) } else { (128, 64, 20) };
+            let (single, _) = draw(orientation, Scale::Single, stride);
+            let (double, _) = draw(orientation, Scale::Double, stride);
+            let (scanlines, _) = draw(orientation, Scale::Scanlines, stride);
+            let pixel = |memory: &[u8], x: usize, y: usize| {
+                let bit = if lsb_first { x % 8 } else { 7 - x % 8 };
+                memory[y * stride as usize + x / 8] >> bit & 1 != 0
+            };
+            for y in 0..height {
+                for x in 0..width {
+                    let lit = pixel(&single, x / 2, y / 2);
+                    let at = (rotate, mirror, lsb_first, x, y);
+                    assert_eq!(pixel(&double, x, y), lit, "{:?}", at);
+                    assert_eq!(pixel(&scanlines, x, y), lit && y % 2 == 0, "{:?}", at);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn the_larger_display_must_fit() {
+    let build = |stride: u16, rotate: u16, scale: Scale| {
+        let mut compiler = Compiler::new();
+        compiler.set_framebuffer(0x6000, stride);
+        compiler.set_orientation(Orientation { rotate, ..Orientation::default() });
+        compiler.set_scale(scale);
+        compiler.compile_code(IBM_LOGO).map(|_| ())
+    };
+    assert!(build(16, 0, Scale::Double).is_ok());
+    assert!(build(8, 90, Scale::Scanlines).is_ok());
+    assert!(build(8, 0, Scale::Double).unwrap_err().contains("shorter than a row's 16 bytes"));
+    assert!(build(0x100, 0, Scale::Double).unwrap_err().contains("runs to 9F0F, into RAM"));
+    let mut compiler = Compiler::new();
+    compiler.set_scale(Scale::Double);
+    assert!(compiler.compile_code(IBM_LOGO).unwrap_err().contains("--scale 2 draws --framebuffer"));
+    for name in ["1", "2", "scanlines"] {
+        assert_eq!(Scale::from_name(name).unwrap().name(), name);
+    }
+}
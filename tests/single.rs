@@ -0,0 +1,74 @@
+// Single instruction tests
+// compile_single must give the bytes a traceable build compiles an
+// instruction to, and what it gives must run against the build's runtime
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::chip8::Instruction;
+use kz80_chip8::codegen::{CodegenStyle, CompiledCode, Compiler, Context};
+use kz80_chip8::z80emu::Z80;
+
+const PONG: &[u8] = include_bytes!("../test/classic/pong.ch8");
+
+/// A traceable build, whose blocks hold one instruction each
+fn traceable(compiler: &mut Compiler) -> CompiledCode {
+    compiler.set_codegen_style(CodegenStyle::Traceable);
+    compiler.compile_code(PONG).expect("compile")
+}
+
+#[test]
+fn matches_the_traceable_build() {
+    let mut compiler = Compiler::new();
+    let code = traceable(&mut compiler);
+    let mut blocks: Vec<(u16, u16)> = code.symbols.iter()
+        .filter_map(|(name, &at)| Some((at, u16::from_str_radix(name.strip_prefix("c8_")?, 16).ok()?)))
+        .collect();
+    blocks.sort();
+    let mut compared = 0;
+    for pair in blocks.windows(2) {
+        let ((start, addr), (end, _)) = (pair[0], pair[1]);
+        let offset = addr as usize - 0x200;
+        let inst = Instruction::new(u16::from_be_bytes([PONG[offset], PONG[offset + 1]]), addr);
+        if !matches!(inst.opcode >> 12, 0x6 | 0x7 | 0x8 | 0xA | 0xC | 0xD) {
+            continue;  // Jumps and skips may be a JR in the build
+        }
+        // Past the marker (6 bytes) and the timer tick (5)
+        let at = start + 11;
+        let single = compiler.compile_single(&inst, &Context::new(&code, at)).expect("compile single");
+        assert_eq!(single, code.code[(at - code.origin) as usize..(end - code.origin) as usize], "{:03X}", addr);
+        compared += 1;
+    }
+    assert!(compared > 50, "{} compared", compared);
+}
+
+#[test]
+fn jumps_need_their_block() {
+    let mut compiler = Compiler::new();
+    let mut context = Context { at: 0x6000, ..Context::default() };
+    let jump = Instruction::new(0x1234, 0x200);
+    assert!(compiler.compile_single(&jump, &context).unwrap_err().contains("unknown address 234"));
+    context.symbols.insert("c8_234".to_string(), 0x1357);
+    assert_eq!(compiler.compile_single(&jump, &context).unwrap(), [0xC3, 0x57, 0x13]);
+}
+
+#[test]
+fn runs_against_the_runtime() {
+    let mut compiler = Compiler::new();
+    let code = traceable(&mut compiler);
+    let mut cpu = Z80::new();
+    cpu.load(code.origin, &code.code);
+    // VA = 12, VB = 30, VA += VB, then HALT
+    let mut at = 0x6000;
+    for opcode in [0x6A12, 0x6B30, 0x8AB4] {
+        let single = compiler.compile_single(&Instruction::new(opcode, 0x200), &Context::new(&code, at)).unwrap();
+        cpu.load(at, &single);
+        at += single.len() as u16;
+    }
+    cpu.load(at, &[0x76]);
+    cpu.sp = 0x7F00;
+    cpu.pc = 0x6000;
+    cpu.run(1000, None);
+    let v = 0x8000;  // V0
+    assert_eq!(cpu.mem[v + 0xA..v + 0xC], [0x42, 0x30]);
+    assert_eq!(cpu.mem[v + 0xF], 0);
+}
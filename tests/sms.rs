@@ -0,0 +1,98 @@
+// Sega Master System target tests
+// The cartridge must carry a header the BIOS accepts, draw the display
+// as mode 4 tiles through the VDP, and read the pad as CHIP-8 keys
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Compiler, Target, DISPLAY_BUF};
+use kz80_chip8::sms::PadMap;
+use kz80_chip8::z80emu::Z80;
+
+fn compiler() -> Compiler {
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Sms);
+    compiler
+}
+
+/// VRAM after replaying the writes to the VDP's control and data ports
+fn vram(cpu: &Z80) -> Vec<u8> {
+    let mut vram = vec![0u8; 0x4000];
+    let (mut latch, mut addr, mut cram) = (None, 0usize, false);
+    for &(_, port, value) in &cpu.out_log {
+        match port {
+            0xBF => match latch.take() {
+                None => latch = Some(value),
+                Some(low) => {
+                    addr = (low as usize | (value as usize) << 8) & 0x3FFF;
+                    cram = value >> 6 == 3;
+                }
+            },
+            0xBE => {
+                latch = None;
+                if !cram {
+                    vram[addr] = value;
+                }
+                addr = (addr + 1) & 0x3FFF;
+            }
+            _ => {}
+        }
+    }
+    vram
+}
+
+#[test]
+fn cartridge_has_a_valid_header() {
+    let rom = compiler().compile(include_bytes!("../test/classic/ibm_logo.ch8")).expect("compile");
+    assert_eq!(rom.len(), 0x8000);
+    assert_eq!(&rom[0x7FF0..0x7FF8], b"TMR SEGA");
+    let checksum = rom[..0x7FF0].iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+    assert_eq!(u16::from_le_bytes([rom[0x7FFA], rom[0x7FFB]]), checksum);
+    assert_eq!(rom[0x7FFF], 0x4C);
+    assert_eq!(&rom[0x66..0x68], &[0xED, 0x45]);  // RETN for the pause button
+    let mut moved = compiler();
+    moved.set_origin(0x4000);
+    assert!(moved.compile(&[0x12, 0x00]).is_err());
+}
+
+#[test]
+fn name_table_shows_the_display_and_text() {
+    let code = compiler().compile_code(include_bytes!("../test/classic/ibm_logo.ch8")).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.run(6_000_000, None);
+    let vram = vram(&cpu);
+    let pixel = |x: usize, y: usize| cpu.read(DISPLAY_BUF + (y * 8 + x / 8) as u16) & (0x80 >> (x % 8)) != 0;
+    assert!((0..256).any(|i| cpu.read(DISPLAY_BUF + i) != 0));
+    for row in 0..16 {
+        for column in 0..32 {
+            let (x, y) = (column * 2, row * 2);
+            let block = (pixel(x, y) as u8) << 3 | (pixel(x + 1, y) as u8) << 2 | (pixel(x, y + 1) as u8) << 1 | pixel(x + 1, y + 1) as u8;
+            assert_eq!(vram[0x3800 + (row + 2) * 64 + column * 2], block, "cell {},{}", column, row);
+        }
+    }
+    let text: Vec<u8> = (0..13).map(|column| vram[0x3800 + 20 * 64 + column * 2]).collect();
+    assert_eq!(text, b"CHIP-8 ON Z80");
+}
+
+#[test]
+fn pad_buttons_are_chip8_keys() {
+    let pad = PadMap::parse("# Swap the d-pad's right for button 2\nright = C\n2 = none\n").unwrap();
+    assert_eq!(pad.keys, [Some(5), Some(8), Some(7), Some(0xC), Some(6), None]);
+    assert!(PadMap::parse("start = 1").unwrap_err().contains("line 1"));
+    assert!(PadMap::parse("up = 10").is_err());
+    // FX0A into V0 until it is non-zero, then spin
+    let key = |pad: PadMap, pressed: u8| {
+        let mut compiler = compiler();
+        compiler.set_pad(pad);
+        let code = compiler.compile_code(&[0xF0, 0x0A, 0x40, 0x00, 0x12, 0x00, 0x12, 0x06]).expect("compile");
+        let mut cpu = Z80::with_rom(&code.code);
+        cpu.run(1_000_000, None);
+        cpu.in_ports[0xDC] = !pressed;
+        cpu.run(1_000_000, None);
+        cpu.in_ports[0xDC] = 0xFF;  // FX0A waits for the release
+        cpu.run(1_000_000, None);
+        cpu.read(0x8000)
+    };
+    assert_eq!(key(PadMap::default(), 0x08), 0x9);  // Right
+    assert_eq!(key(pad, 0x08), 0xC);
+    assert_eq!(key(pad, 0x20), 0x0);  // Unmapped, so still waiting
+}
@@ -0,0 +1,61 @@
+// CHIP-8 memory image tests
+// The image must hold the font, the ROM with what it stored over it, and
+// the display at 0xF00, whether the game was compiled or interpreted
+
+#![cfg(feature = "cli")]
+
+use kz80_chip8::codegen::{Compiler, Fallback, DEFAULT_CLOCK_HZ};
+use kz80_chip8::golden;
+use kz80_chip8::snapshot::{memory_image, IMAGE_SIZE};
+use std::process::Command;
+
+// 200: LD I, 300 / LD V0, 42 / LD V1, 17 / LD [I], V1
+// 208: LD V0, 0 / LD F, V0 / DRW V0, V0, 5 / JP 20E
+const STORE_AND_DRAW: [u8; 16] = [
+    0xA3, 0x00, 0x60, 0x42, 0x61, 0x17, 0xF1, 0x55,
+    0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x0E,
+];
+
+fn image(fallback: Fallback) -> Vec<u8> {
+    let mut compiler = Compiler::new();
+    compiler.set_fallback(fallback);
+    let code = compiler.compile_code(&STORE_AND_DRAW).expect("compile");
+    memory_image(&golden::emulate(&code, DEFAULT_CLOCK_HZ, 10), &STORE_AND_DRAW)
+}
+
+#[test]
+fn image_is_the_chip8_address_space() {
+    let image = image(Fallback::Trap);
+    assert_eq!(image.len(), IMAGE_SIZE);
+    assert_eq!(&image[..5], &[0xF0, 0x90, 0x90, 0x90, 0xF0]);  // Font 0
+    assert_eq!(&image[0x200..0x210], &STORE_AND_DRAW);
+    assert_eq!(&image[0x300..0x302], &[0x42, 0x17]);
+    // The 0 drawn at the top left, one byte per display row
+    let rows: Vec<u8> = (0..5).map(|row| image[0xF00 + row * 8]).collect();
+    assert_eq!(rows, [0xF0, 0x90, 0x90, 0x90, 0xF0]);
+}
+
+#[test]
+fn interpreter_copy_gives_the_same_image() {
+    assert_eq!(image(Fallback::Interp), image(Fallback::Trap));
+}
+
+#[test]
+fn snapshot_subcommand_writes_the_image() {
+    let file = std::env::temp_dir().join(format!("kz80_chip8_snapshot_{}.mem", std::process::id()));
+    let file = file.to_str().unwrap();
+    let snapshot = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_kz80_chip8"))
+            .args(["snapshot", "test/classic/ibm_logo.ch8", "--frames", "30", "-o", file])
+            .args(extra)
+            .output()
+            .expect("run kz80_chip8")
+    };
+    assert!(snapshot(&[]).status.success());
+    let image = std::fs::read(file).unwrap();
+    std::fs::remove_file(file).unwrap();
+    assert_eq!(image.len(), IMAGE_SIZE);
+    assert_eq!(&image[0x200..0x204], &[0x00, 0xE0, 0xA2, 0x2A]);
+    assert!(image[0xF00..].iter().any(|&b| b != 0));
+    assert!(!snapshot(&["--target", "cpm"]).status.success());
+}
@@ -0,0 +1,58 @@
+// Sound test kit tests
+// Each beep of the kit must start at the first frame tick after LD ST and
+// last ST's frames on the beeper and the PSG alike, and a driver that
+// drops or shortens beeps must show up in the problems
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Audio, Target};
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::soundtest::{self, At, Beep, Output};
+
+#[test]
+fn beeps_last_their_frames_on_each_output() {
+    for target in [Target::RetroShield, Target::Rc2014] {
+        let options = CompileOptions::builder().target(target).beeper(0x40, 3).audio(Audio::from_name("ay8910").unwrap()).build().unwrap();
+        let beeps = soundtest::measure(&options).expect("measure");
+        assert_eq!(beeps.len(), 2 * soundtest::BEEPS.len());
+        assert!(soundtest::problems(&beeps).is_empty(), "{}: {:?}", target.name(), soundtest::problems(&beeps));
+        let lengths: Vec<u32> = beeps.iter().filter(|beep| matches!(beep.output, Output::Psg { .. })).map(|beep| beep.length().unwrap()).collect();
+        assert_eq!(lengths, [1, 2, 3, 6, 15, 30]);
+        // The beeper and the PSG go on and off at the same frame ticks
+        for pair in beeps.chunks(2) {
+            assert_eq!(pair[0].on.map(|at| at.frame), pair[1].on.map(|at| at.frame));
+            assert_eq!(pair[0].off.map(|at| at.frame), pair[1].off.map(|at| at.frame));
+        }
+    }
+}
+
+#[test]
+fn short_late_and_missing_beeps_are_problems() {
+    let output = Output::Beeper { port: 0x40, mask: 0x08 };
+    let at = |frame| Some(At { cycles: frame as u64 * 66_667, frame });
+    let beep = |frames, on, off| Beep { output, frames, set: At { cycles: 0, frame: 0 }, on, off };
+    let beeps = [beep(1, None, None), beep(2, at(1), at(2)), beep(3, at(3), at(6)), beep(6, at(1), None), beep(15, at(1), at(16))];
+    assert_eq!(
+        soundtest::problems(&beeps),
+        [
+            "beeper 40:3 ST=1: never sounded",
+            "beeper 40:3 ST=2: sounded for 1 frames",
+            "beeper 40:3 ST=3: sounded 3 frames after LD ST",
+            "beeper 40:3 ST=6: never stopped",
+            "the kit set ST 5 of 6 times",
+        ]
+    );
+}
+
+#[test]
+fn the_kit_needs_a_sound_output() {
+    let silent = CompileOptions::default();
+    assert!(soundtest::build(&silent).unwrap_err().contains("neither"));
+    let psg = CompileOptions::builder().audio(Audio::Ay8910 { register: 0xA0, data: 0xA1 }).build().unwrap();
+    assert_eq!(Output::of(&psg), [Output::Psg { register: 0xA0, data: 0xA1 }]);
+    assert!(soundtest::build(&psg).unwrap().symbols.contains_key("beep_off"));
+    // Only the boards the emulator models are timed
+    let spectrum = CompileOptions::builder().target(Target::Spectrum).beeper(0xFE, 4).build().unwrap();
+    assert!(soundtest::build(&spectrum).is_ok());
+    assert!(soundtest::measure(&spectrum).unwrap_err().contains("spectrum"));
+}
@@ -0,0 +1,79 @@
+// ZX Spectrum target tests
+// The game must draw into Spectrum screen memory, read the keyboard matrix
+// and come wrapped in a tape image that BASIC can load
+
+#![cfg(feature = "emulator")]
+
+mod common;
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, Target};
+use kz80_chip8::spectrum;
+use kz80_chip8::z80emu::{StopReason, Z80};
+
+fn compile(rom: &[u8]) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_target(Target::Spectrum);
+    compiler.compile_code(rom).expect("compile")
+}
+
+/// Pixel of the 256x192 Spectrum screen, in its interleaved line order
+fn pixel(cpu: &Z80, x: usize, y: usize) -> bool {
+    let addr = 0x4000 | ((y & 0xC0) << 5) | ((y & 7) << 8) | ((y & 0x38) << 2) | (x / 8);
+    cpu.mem[addr] & (0x80 >> (x % 8)) != 0
+}
+
+#[test]
+fn screen_shows_the_display_buffer() {
+    let code = compile(include_bytes!("../test/classic/ibm_logo.ch8"));
+    assert_eq!(code.origin, 0x6000);
+    let mut cpu = common::boot(&code);
+    assert_eq!(cpu.run(20_000_000, None), StopReason::CycleLimit);
+    let mut lit = 0;
+    for y in 0..32 {
+        for x in 0..64 {
+            let chip8 = cpu.mem[0x8200 + y * 8 + x / 8] & (0x80 >> (x % 8)) != 0;
+            for (dx, dy) in [(0, 0), (3, 3)] {
+                assert_eq!(pixel(&cpu, x * 4 + dx, 32 + y * 4 + dy), chip8, "({}, {})", x, y);
+            }
+            lit += chip8 as usize;
+        }
+    }
+    assert!(lit > 100);
+}
+
+#[test]
+fn keypad_is_read_from_the_matrix() {
+    // FX0A into V0, then spin
+    let code = compile(&[0xF0, 0x0A, 0x12, 0x02]);
+    // Every half-row reads the same here, so the first key in the table wins
+    for (bit, key) in [(2, 0x0), (0, 0x1), (3, 0xB)] {
+        let mut cpu = common::boot(&code);
+        cpu.in_ports[0xFE] = !(1 << bit);
+        cpu.run(2_000_000, None);
+        cpu.in_ports[0xFE] = 0xFF;
+        cpu.run(2_000_000, None);
+        assert_eq!(cpu.mem[0x8000], key, "bit {}", bit);
+    }
+}
+
+#[test]
+fn tape_loads_and_starts_the_code() {
+    let code = compile(include_bytes!("../test/classic/ibm_logo.ch8"));
+    let tap = spectrum::tap("ibm_logo", &code.code, code.origin);
+    let mut blocks = Vec::new();
+    let mut rest = &tap[..];
+    while !rest.is_empty() {
+        let len = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+        let block = &rest[2..2 + len];
+        assert_eq!(block.iter().fold(0, |sum, b| sum ^ b), 0);
+        blocks.push(&block[..len - 1]);
+        rest = &rest[2 + len..];
+    }
+    assert_eq!(blocks.len(), 4);
+    assert_eq!(&blocks[0][..12], b"\x00\x00ibm_logo  ");
+    assert!(blocks[1].windows(5).any(|w| w == b"24575"));
+    assert!(blocks[1].windows(5).any(|w| w == b"24576"));
+    assert_eq!(&blocks[2][..2], b"\x00\x03");
+    assert_eq!(u16::from_le_bytes([blocks[2][14], blocks[2][15]]), 0x6000);
+    assert_eq!(&blocks[3][1..], &code.code[..]);
+}
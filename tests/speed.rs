@@ -0,0 +1,74 @@
+// Speed tests
+// A --speed build must run about as many CHIP-8 instructions a second as
+// asked, whatever paces its frames, and leave unpaced builds as they were
+
+#![cfg(feature = "emulator")]
+
+mod common;
+
+use kz80_chip8::codegen::{CompiledCode, Timer};
+use kz80_chip8::options::CompileOptions;
+use kz80_chip8::z80emu::{Ctc, Z80};
+
+const CLOCK: u64 = 4_000_000;
+
+/// Counts V1:V0 up forever, three instructions a count
+const COUNTER: &[u8] = &[
+    0x70, 0x01, 0x30, 0x00,  // 200: ADD V0, 1 / SE V0, 0
+    0x12, 0x00, 0x71, 0x01,  // 204: JP 200 / ADD V1, 1
+    0x12, 0x00,              // 208: JP 200
+];
+
+/// Counts the counter makes in a second of the board's time
+fn counts_per_second(code: &CompiledCode, ctc: Option<u8>) -> u32 {
+    let mut cpu = common::boot(code);
+    cpu.ctc = ctc.map(Ctc::new);
+    let count = |cpu: &Z80| u16::from_le_bytes([cpu.mem[0x8000], cpu.mem[0x8001]]) as u32;
+    cpu.run(CLOCK / 5, None);
+    let start = count(&cpu);
+    cpu.run(CLOCK, None);
+    count(&cpu) - start
+}
+
+fn compile(options: CompileOptions) -> CompiledCode {
+    options.compiler().compile_code(COUNTER).expect("compile")
+}
+
+#[test]
+fn games_run_at_the_speed_asked_for() {
+    // Exactly, with the CTC's frames, whatever a frame's budget comes to
+    let ctc = Timer::Ctc { port: 0x88 };
+    for ips in [20, 90, 700, 1200] {
+        let code = compile(CompileOptions::builder().speed(ips).timer(ctc).build().unwrap());
+        let ran = 3 * counts_per_second(&code, Some(0x88));
+        assert!(ran.abs_diff(ips) <= 6, "{} instructions a second at --speed {}", ran, ips);
+    }
+    // As near as the runtime's estimate of its frames comes otherwise
+    for ips in [600, 1200] {
+        let ran = 3 * counts_per_second(&compile(CompileOptions::builder().speed(ips).build().unwrap()), None);
+        assert!((ips * 85 / 100..=ips).contains(&ran), "{} instructions a second at --speed {}", ran, ips);
+    }
+    assert!(3 * counts_per_second(&compile(CompileOptions::default()), None) > 50_000);
+}
+
+#[test]
+fn unpaced_builds_are_left_alone() {
+    let code = compile(CompileOptions::default());
+    assert!(!code.symbols.contains_key("pace"));
+    let paced = compile(CompileOptions::builder().speed(700).build().unwrap());
+    assert!(paced.symbols.contains_key("pace"));
+    assert!(String::from_utf8_lossy(&paced.code).contains("speed=700"));
+    // LD DE, cost / CALL pace at the loop's merge point: three instructions
+    let call = [&[0xCD][..], &paced.symbols["pace"].to_le_bytes()].concat();
+    let at = paced.code.windows(3).position(|window| window == call).expect("a call to pace");
+    assert_eq!(paced.code[at - 3..at], [0x11, 180, 0]);
+}
+
+#[test]
+fn speeds_out_of_range_are_refused() {
+    for ips in [0, 70_000] {
+        let options = CompileOptions::builder().speed(ips).build().unwrap();
+        let error = options.compiler().compile_code(COUNTER).unwrap_err();
+        assert!(error.contains("--speed"), "{}", error);
+    }
+}
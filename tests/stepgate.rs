@@ -0,0 +1,74 @@
+// Step gate tests
+// A gated build must stop at each block until stepped or let go, show the
+// registers as it stops, and keep keys typed while it runs for the game
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{CompiledCode, Compiler, Target};
+use kz80_chip8::stepgate::{self, Session};
+use kz80_chip8::z80emu::Z80;
+
+// LD V0, 5 / LD I, 300 / ADD V0, 1 / JP 204
+const COUNT: &[u8] = &[0x60, 0x05, 0xA3, 0x00, 0x70, 0x01, 0x12, 0x04];
+
+fn gated(rom: &[u8]) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_debug_stub(true);
+    compiler.set_step_gate(true);
+    compiler.compile_code(rom).expect("compile")
+}
+
+/// Console output after each of `inputs` is sent in turn
+fn steps(rom: &[u8], inputs: &[&[u8]]) -> Vec<String> {
+    let code = gated(rom);
+    let mut cpu = Z80::with_rom(&code.code);
+    let mut outputs = Vec::new();
+    let mut seen = 0;
+    for input in inputs {
+        cpu.send(input);
+        cpu.run(3_000_000, None);
+        let output = cpu.output();
+        outputs.push(output[seen..].to_string());
+        seen = output.len();
+    }
+    outputs
+}
+
+#[test]
+fn blocks_wait_at_the_gate() {
+    let outputs = steps(COUNT, &[b"", b"s", b"s"]);
+    assert!(outputs[0].ends_with("\r\nGate 0200\r\nV 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\r\nI 0000 DT 00 ST 00 SP 00"), "{:?}", outputs[0]);
+    assert_eq!(outputs[1], "\r\n\r\nGate 0204\r\nV 05 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\r\nI 0300 DT 00 ST 00 SP 00");
+    assert!(outputs[2].contains("Gate 0204\r\nV 06 "), "{:?}", outputs[2]);
+}
+
+#[test]
+fn keys_get_through_a_running_game() {
+    // LD V0, 5 / SKP V0 / JP 202 / LD V1, 1 / JP 208
+    let rom = [0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0x61, 0x01, 0x12, 0x08];
+    let outputs = steps(&rom, &[b"", &[stepgate::GO], b"5", &[stepgate::STOP]]);
+    assert!(!outputs[1].contains("Gate") && !outputs[2].contains("Gate"), "{:?}", outputs);
+    assert!(outputs[3].contains("Gate 0208\r\nV 05 01 "), "{:?}", outputs[3]);
+}
+
+#[test]
+fn the_host_notes_what_changed() {
+    let mut session = Session::new();
+    let notes: Vec<String> = steps(COUNT, &[b"", b"s", b"s"]).iter().flat_map(|output| session.feed(output.as_bytes())).collect();
+    assert_eq!(notes, ["stopped at 0200", "stopped at 0204: V0 00->05, I 0000->0300", "stopped at 0204: V0 05->06"]);
+    assert_eq!(session.last().unwrap().v[0], 6);
+    assert_eq!(stepgate::command(""), Ok(Some(vec![stepgate::STEP])));
+    assert_eq!(stepgate::command("k a"), Ok(Some(b"a".to_vec())));
+    assert_eq!(stepgate::command("q"), Ok(None));
+    assert!(stepgate::command("k z").is_err());
+}
+
+#[test]
+fn gates_need_the_stub_and_a_serial_port() {
+    let mut compiler = Compiler::new();
+    compiler.set_step_gate(true);
+    assert!(compiler.compile_code(COUNT).unwrap_err().contains("debug stub"));
+    compiler.set_debug_stub(true);
+    compiler.set_target(Target::Cpm);
+    assert!(compiler.compile_code(COUNT).unwrap_err().contains("serial port"));
+}
@@ -0,0 +1,66 @@
+// Embedded symbol table tests
+// A --symbol-table build must name the CHIP-8 instruction behind any Z80
+// address of compiled code, in its traps and in the debug monitor's stack
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{CompiledCode, Compiler};
+use kz80_chip8::debugmap::DebugMap;
+use kz80_chip8::trap;
+use kz80_chip8::z80emu::Z80;
+
+const PONG: &[u8] = include_bytes!("../test/classic/pong.ch8");
+
+fn build(rom: &[u8], configure: impl Fn(&mut Compiler)) -> CompiledCode {
+    let mut compiler = Compiler::new();
+    compiler.set_symbol_table(true);
+    configure(&mut compiler);
+    compiler.compile_code(rom).expect("compile")
+}
+
+/// Console output of a build after typing `input`
+fn output(code: &CompiledCode, input: &[u8]) -> String {
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.send(input);
+    cpu.run(5_000_000, None);
+    cpu.output()
+}
+
+#[test]
+fn addresses_are_found_as_the_debug_map_finds_them() {
+    let code = build(PONG, |_| {});
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.sp = 0xFF00;
+    let mut lookup = |addr: u16| {
+        cpu.set_hl(addr);
+        cpu.call(code.symbols["symbolicate"], 10_000);
+        (cpu.af() & 0x40 == 0).then_some(cpu.hl())  // NZ when found
+    };
+    let map = DebugMap::new(&code);
+    for span in map.instructions.iter().filter(|span| !span.z80.is_empty()) {
+        assert_eq!(lookup(span.z80.start), Some(span.chip8));
+        assert_eq!(lookup(span.z80.end - 1), Some(span.chip8));
+    }
+    assert_eq!(lookup(code.symbols["cls"]), None);
+    assert_eq!(lookup(code.symbols["halt"]), None);
+}
+
+#[test]
+fn traps_name_the_instruction() {
+    // LD I, 100 / LD V0..V0, [I] / JP 204
+    let rom = [0xA1, 0x00, 0xF0, 0x65, 0x12, 0x04];
+    let out = output(&build(&rom, |_| {}), b"");
+    assert!(out.contains("I out of range: 0100 at 0202\r\n"), "{}", out);
+    let out = output(&build(&rom, |compiler| compiler.set_compact_traps(true)), b"");
+    assert!(out.contains("E03 0100 at 0202\r\n"), "{}", out);
+    assert_eq!(trap::explain("E03 0100 at 0202").unwrap(), "I out of range: 0100 (value of I) at 0202");
+}
+
+#[test]
+fn the_monitor_names_return_points() {
+    // LD V0, 5 / LD I, 300 / LD [I], V0 / CALL 20A / JP 208 / LD V2, K / RET
+    let rom = [0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x22, 0x0A, 0x12, 0x08, 0xF2, 0x0A, 0x00, 0xEE];
+    let code = build(&rom, |compiler| compiler.set_debug_stub(true));
+    let out = output(&code, b"\x02sc");
+    assert!(out.contains(&format!("\r\nS {:04X}=0208", code.symbols["c8_208"])), "{}", out);
+}
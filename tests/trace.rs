@@ -0,0 +1,56 @@
+// Trace build tests
+// A --trace build must print each instruction on the console as it runs,
+// and otherwise run the game exactly as the plain build does
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Compiler, Target, Trace, DEFAULT_CLOCK_HZ, DISPLAY_BUF};
+use kz80_chip8::golden;
+use kz80_chip8::z80emu::Z80;
+
+const IBM_LOGO: &[u8] = include_bytes!("../test/classic/ibm_logo.ch8");
+
+fn traced(trace: Trace, rom: &[u8]) -> Z80 {
+    let mut compiler = Compiler::new();
+    compiler.set_trace(trace);
+    let code = compiler.compile_code(rom).expect("compile");
+    let mut cpu = Z80::with_rom(&code.code);
+    cpu.run(2_000_000, None);
+    cpu
+}
+
+#[test]
+fn instructions_are_printed_as_they_run() {
+    // LD V0, 5 / SE V0, 5 / LD V1, 1 (skipped) / JP 208
+    let rom = [0x60, 0x05, 0x30, 0x05, 0x61, 0x01, 0x12, 0x08, 0x12, 0x08];
+    let output = traced(Trace::Pc, &rom).output();
+    assert!(output.contains("T 0200\r\nT 0202\r\nT 0206\r\nT 0208\r\nT 0208\r\n"), "{}", output);
+    assert!(!output.contains("T 0204"));
+    let output = traced(Trace::Opcode, &rom).output();
+    assert!(output.contains("T 0200 6005\r\nT 0202 3005\r\nT 0206 1208\r\n"), "{}", output);
+}
+
+#[test]
+fn tracing_leaves_the_game_alone() {
+    let plain = Compiler::new().compile_code(IBM_LOGO).expect("compile");
+    let mut compiler = Compiler::new();
+    compiler.set_trace(Trace::Opcode);
+    let code = compiler.compile_code(IBM_LOGO).expect("compile");
+    assert!(code.symbols.contains_key("trace") && !plain.symbols.contains_key("trace"));
+    let display = golden::run(&code, DEFAULT_CLOCK_HZ, 60);
+    assert_eq!(display, golden::run(&plain, DEFAULT_CLOCK_HZ, 60));
+    assert!(display.iter().any(|&b| b != 0));
+    let cpu = traced(Trace::Pc, IBM_LOGO);
+    assert_eq!((0..256).map(|i| cpu.read(DISPLAY_BUF + i)).collect::<Vec<u8>>(), display);
+}
+
+#[test]
+fn only_serial_targets_trace() {
+    for (target, ok) in [(Target::Rc2014, true), (Target::Cpm, true), (Target::Msx, false), (Target::Sms, false)] {
+        let mut compiler = Compiler::new();
+        compiler.set_target(target);
+        compiler.set_trace(Trace::Pc);
+        let result = compiler.compile_code(IBM_LOGO);
+        assert_eq!(result.is_ok(), ok, "{}", target.name());
+    }
+}
@@ -0,0 +1,48 @@
+// Unaligned sprite tests
+// A sprite at any X must be drawn shifted across the two display bytes it
+// spans, colliding in either, and wrap to the start of its row
+
+#![cfg(feature = "emulator")]
+
+use kz80_chip8::codegen::{Compiler, DEFAULT_CLOCK_HZ, DISPLAY_BUF};
+use kz80_chip8::golden;
+use kz80_chip8::z80emu::Z80;
+
+/// Machine after a ROM has run for a few frames
+fn run(rom: &[u8]) -> Z80 {
+    golden::emulate(&Compiler::new().compile_code(rom).expect("compile"), DEFAULT_CLOCK_HZ, 10)
+}
+
+/// The display's first row, eight bytes
+fn first_row(cpu: &Z80) -> &[u8] {
+    &cpu.mem[DISPLAY_BUF as usize..DISPLAY_BUF as usize + 8]
+}
+
+#[test]
+fn sprites_are_split_across_two_bytes() {
+    // LD V0, 3 / LD V1, 0 / LD I, 20A / DRW V0, V1, 1 / JP 208 / 20A: FF
+    let cpu = run(&[0x60, 0x03, 0x61, 0x00, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x08, 0xFF]);
+    assert_eq!(first_row(&cpu), [0x1F, 0xE0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn collisions_count_in_the_second_byte() {
+    // LD I, 212 / LD V0..V1, [I] (5, 0) / LD V2, 12 / LD I, 214
+    // / DRW V2, V1, 1 / LD I, 215 / DRW V0, V1, 1 / JP 20E, then the
+    // table and sprites 80 and FF
+    let rom = [
+        0xA2, 0x12, 0xF1, 0x65, 0x62, 0x0C, 0xA2, 0x14, 0xD2, 0x11, 0xA2, 0x15, 0xD0, 0x11, 0x12, 0x0E,
+        0x00, 0x00, 0x05, 0x00, 0x80, 0xFF,
+    ];
+    let cpu = run(&rom);
+    assert_eq!(first_row(&cpu), [0x07, 0xF0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(cpu.mem[0x800F], 1, "VF");
+}
+
+#[test]
+fn sprites_wrap_to_the_start_of_their_row() {
+    // LD V0, 60 / LD V1, 0 / LD I, 20A / DRW V0, V1, 1 / JP 208 / 20A: FF
+    let cpu = run(&[0x60, 0x3C, 0x61, 0x00, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x08, 0xFF]);
+    assert_eq!(first_row(&cpu), [0xF0, 0, 0, 0, 0, 0, 0, 0x0F]);
+    assert!(cpu.mem[DISPLAY_BUF as usize + 8..DISPLAY_BUF as usize + 16].iter().all(|&byte| byte == 0));
+}
@@ -0,0 +1,57 @@
+// Uninitialized read tests
+// An FX65 or DXYN that may read RAM which neither the font, the ROM nor a
+// store fills must be flagged at that instruction, however I got there
+
+use kz80_chip8::analysis;
+use kz80_chip8::codegen::Compiler;
+use kz80_chip8::diagnostics::{Category, Level};
+use kz80_chip8::quirks::Quirks;
+
+/// First and last byte of each read flagged
+fn flagged(rom: &[u8]) -> Vec<(u16, u16, u16)> {
+    analysis::classify(rom).uninit_reads.iter().map(|read| (read.at, read.source, read.source + read.len - 1)).collect()
+}
+
+#[test]
+fn reads_past_the_rom_are_flagged() {
+    // LD I, 300 / DRW V0, V1, 5 / JP 202
+    assert_eq!(flagged(&[0xA3, 0x00, 0xD0, 0x15, 0x12, 0x02]), [(0x202, 0x300, 0x304)]);
+    // LD I, 206 / DRW V0, V1, 2 / JP 202, the sprite at its end: only its second row is past it
+    assert_eq!(flagged(&[0xA2, 0x06, 0xD0, 0x12, 0x12, 0x02, 0xFF]), [(0x202, 0x207, 0x207)]);
+    // LD I, 300 / LD [I], V0..V3 / LD V0..V3, [I] / JP 206: stored first
+    assert!(flagged(&[0xA3, 0x00, 0xF3, 0x55, 0xF3, 0x65, 0x12, 0x06]).is_empty());
+    // LD F, V0 / DRW V0, V1, 5 / JP 202: a font digit
+    assert!(flagged(&[0xF0, 0x29, 0xD0, 0x15, 0x12, 0x02]).is_empty());
+}
+
+#[test]
+fn i_ranges_follow_the_registers() {
+    // 200: RND V0, 7 / LD I, 20A / ADD I, V0 / DRW V0, V1, 4 / JP 208, then
+    // a table of four bytes: a sprite up to 7 bytes in runs past it
+    let mut rom = vec![0xC0, 0x07, 0xA2, 0x0A, 0xF0, 0x1E, 0xD0, 0x14, 0x12, 0x08, 1, 2, 3, 4];
+    assert_eq!(flagged(&rom), [(0x206, 0x20E, 0x214)]);
+    rom.extend([5, 6, 7, 8, 9, 10, 11]);
+    assert!(flagged(&rom).is_empty());
+    // 200: LD V0, 0 / SE V1, 0 / LD V0, 8 / LD I, 20C / ADD I, V0 / DRW V0, V1, 4, then
+    // 8 bytes: past the skip V0 is 0 or 8, and the sprite at 214 is past the ROM
+    let skipped = [0x60, 0x00, 0x31, 0x00, 0x60, 0x08, 0xA2, 0x0E, 0xF0, 0x1E, 0xD0, 0x14, 0x12, 0x0C, 1, 2, 3, 4, 5, 6, 7, 8];
+    assert_eq!(flagged(&skipped), [(0x20A, 0x216, 0x219)]);
+    // Unknown V0: I could be anywhere, so nothing is said
+    assert!(flagged(&[0xA3, 0x00, 0xF0, 0x1E, 0xD0, 0x15, 0x12, 0x04]).is_empty());
+}
+
+#[test]
+fn reads_warn_at_their_instruction() {
+    let rom = [0xAF, 0x00, 0xD0, 0x15, 0x12, 0x02];  // LD I, F00 / DRW V0, V1, 5 / JP 202
+    let mut compiler = Compiler::new();
+    let code = compiler.compile_code(&rom).expect("compile");
+    let warnings: Vec<String> = code.diagnostics.iter().filter(|d| d.category == Category::UninitRead).map(|d| d.to_string()).collect();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("202: DRW  V0, V1, 5 may read F00-F04"), "{}", warnings[0]);
+    // The display isn't RAM when sprites can be drawn from it
+    compiler.set_quirks(Quirks { display_ram: true, ..Quirks::default() });
+    assert!(compiler.compile_code(&rom).expect("compile").diagnostics.is_empty());
+    let mut compiler = Compiler::new();
+    compiler.set_level(Category::UninitRead, Level::Error);
+    assert!(compiler.compile_code(&rom).unwrap_err().contains("[uninit-read=error]"));
+}